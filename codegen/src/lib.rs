@@ -0,0 +1,414 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Build-time generation of JSON-RPC 2.0 clients from [OpenRPC](https://open-rpc.org) documents,
+//! for APIs with too many methods to list by hand.
+//!
+//! The generator reads an OpenRPC document, in JSON or YAML, and writes a trait with one method
+//! per RPC method, annotated with the `#[jsonrpc_client]` attribute of
+//! [`jsonrpc-client-derive`](../jsonrpc_client_derive/index.html). A plain JSON or YAML array of
+//! OpenRPC method objects is accepted as well, for APIs without a full document.
+//!
+//! Method and parameter names are turned into snake case Rust names, with the original names
+//! kept as `#[rpc(name = "...")]` attributes. Parameter and result types are derived from their
+//! JSON schemas: strings, numbers, booleans and arrays of those map to the corresponding Rust
+//! types, optional parameters to `Option`s and anything else to `serde_json::Value`.
+//!
+//! # Example
+//!
+//! In `build.rs`:
+//!
+//! ```rust,no_run
+//! extern crate jsonrpc_client_codegen;
+//!
+//! use jsonrpc_client_codegen::Generator;
+//! use std::env;
+//! use std::path::Path;
+//!
+//! fn main() {
+//!     let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("eth.rs");
+//!     Generator::new("Eth")
+//!         .generate_file("openrpc.json", out_path)
+//!         .unwrap();
+//! }
+//! ```
+//!
+//! And in the crate, which needs `jsonrpc-client-core`, `jsonrpc-client-derive` and `serde_json`
+//! as dependencies:
+//!
+//! ```rust,ignore
+//! extern crate jsonrpc_client_core;
+//! extern crate jsonrpc_client_derive;
+//! extern crate serde_json;
+//!
+//! include!(concat!(env!("OUT_DIR"), "/eth.rs"));
+//!
+//! fn main() {
+//!     let mut client = EthClient::new(transport);
+//!     let balance = client.eth_get_balance(address, None).call().unwrap();
+//! }
+//! ```
+
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
+extern crate serde_yaml;
+
+use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+use std::error;
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::result;
+
+/// Module for turning names from specs into Rust identifiers.
+mod names;
+
+/// Module for mapping JSON schemas to Rust types.
+mod schema;
+
+
+/// A specialized `Result` type for client generation.
+pub type Result<T> = result::Result<T, Error>;
+
+/// The error type of client generation.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the spec or writing the generated code failed.
+    Io(io::Error),
+    /// The spec could not be parsed or does not describe any methods usable for a client.
+    InvalidSpec(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::InvalidSpec(ref msg) => write!(f, "Invalid spec: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::InvalidSpec(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+
+/// Generates the source code of a client from a spec.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    trait_name: String,
+    client_name: Option<String>,
+}
+
+impl Generator {
+    /// Creates a generator of a trait named `trait_name`, implemented by a client named
+    /// `trait_name` followed by `Client`.
+    pub fn new(trait_name: &str) -> Self {
+        Generator {
+            trait_name: trait_name.to_owned(),
+            client_name: None,
+        }
+    }
+
+    /// Sets the name of the generated client struct.
+    pub fn client_name(mut self, client_name: &str) -> Self {
+        self.client_name = Some(client_name.to_owned());
+        self
+    }
+
+    /// Reads the spec at `spec_path` and writes the generated code to `out_path`. Also tells
+    /// Cargo to run the build script again when the spec changes.
+    pub fn generate_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        spec_path: P,
+        out_path: Q,
+    ) -> Result<()> {
+        println!("cargo:rerun-if-changed={}", spec_path.as_ref().display());
+        let spec = fs::read_to_string(spec_path)?;
+        fs::write(out_path, self.generate(&spec)?)?;
+        Ok(())
+    }
+
+    /// Returns the generated code for the given spec, in JSON or YAML.
+    pub fn generate(&self, spec: &str) -> Result<String> {
+        let spec = parse_spec(spec)?;
+        let methods = match spec {
+            JsonValue::Array(ref methods) => methods,
+            _ => match spec.get("methods") {
+                Some(&JsonValue::Array(ref methods)) => methods,
+                _ => return Err(invalid_spec("No methods array")),
+            },
+        };
+
+        let mut code = String::new();
+        let info = spec.get("info");
+        let title = info.and_then(|info| info.get("title")).and_then(JsonValue::as_str);
+        let version = info.and_then(|info| info.get("version")).and_then(JsonValue::as_str);
+        match (title, version) {
+            (Some(title), Some(version)) => writeln!(
+                code,
+                "// Generated by jsonrpc-client-codegen from {} {}. Do not edit.\n",
+                title,
+                version
+            ),
+            _ => writeln!(code, "// Generated by jsonrpc-client-codegen. Do not edit.\n"),
+        }.unwrap();
+        if let Some(description) = info.and_then(|info| info.get("description")) {
+            write_docs(&mut code, "", description.as_str().unwrap_or_default());
+        }
+        match self.client_name {
+            Some(ref client_name) => writeln!(
+                code,
+                "#[::jsonrpc_client_derive::jsonrpc_client(client = {:?})]",
+                client_name
+            ),
+            None => writeln!(code, "#[::jsonrpc_client_derive::jsonrpc_client]"),
+        }.unwrap();
+        writeln!(code, "pub trait {} {{", self.trait_name).unwrap();
+
+        let mut idents = HashSet::new();
+        for (i, method) in methods.iter().enumerate() {
+            if i > 0 {
+                code.push('\n');
+            }
+            let method = resolve(method, &spec);
+            let ident = write_method(&mut code, method, &spec, version.unwrap_or("unknown"))?;
+            if !idents.insert(ident.clone()) {
+                return Err(invalid_spec(format!("More than one method named {}", ident)));
+            }
+        }
+        code.push_str("}\n");
+        Ok(code)
+    }
+}
+
+/// Writes one method of the trait and returns its Rust name.
+fn write_method(
+    code: &mut String,
+    method: &JsonValue,
+    spec: &JsonValue,
+    spec_version: &str,
+) -> Result<String> {
+    let name = method
+        .get("name")
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| invalid_spec("Method without a name"))?;
+    let ident = names::snake_case(name);
+
+    let summary = method.get("summary").and_then(JsonValue::as_str);
+    let description = method.get("description").and_then(JsonValue::as_str);
+    if let Some(summary) = summary {
+        write_docs(code, "    ", summary);
+    }
+    if let Some(description) = description {
+        if summary.is_some() {
+            code.push_str("    ///\n");
+        }
+        write_docs(code, "    ", description);
+    }
+
+    let mut rpc_attrs = Vec::new();
+    if ident != name {
+        rpc_attrs.push(format!("name = {:?}", name));
+    }
+    if method.get("deprecated").and_then(JsonValue::as_bool) == Some(true) {
+        rpc_attrs.push(format!("deprecated_since = {:?}", spec_version));
+    }
+    if !rpc_attrs.is_empty() {
+        writeln!(code, "    #[rpc({})]", rpc_attrs.join(", ")).unwrap();
+    }
+
+    let params = match method.get("params") {
+        Some(&JsonValue::Array(ref params)) => params.as_slice(),
+        None => &[],
+        Some(_) => return Err(invalid_spec(format!("Params of {} is not an array", name))),
+    };
+    let mut param_idents = HashSet::new();
+    let mut param_lines = String::new();
+    for param in params {
+        let param = resolve(param, spec);
+        let param_name = param
+            .get("name")
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| invalid_spec(format!("Parameter of {} without a name", name)))?;
+        let param_ident = names::snake_case(param_name);
+        if param_ident == "self_" || !param_idents.insert(param_ident.clone()) {
+            return Err(invalid_spec(format!(
+                "Parameter {} of {} can't be used as a Rust name",
+                param_name,
+                name
+            )));
+        }
+        let mut ty = param_type(param, spec);
+        if param.get("required").and_then(JsonValue::as_bool) != Some(true)
+            && !ty.starts_with("Option<")
+        {
+            ty = format!("Option<{}>", ty);
+        }
+        if param_ident != param_name {
+            writeln!(
+                param_lines,
+                "        #[rpc(name = {:?})] {}: {},",
+                param_name,
+                param_ident,
+                ty
+            ).unwrap();
+        } else {
+            writeln!(param_lines, "        {}: {},", param_ident, ty).unwrap();
+        }
+    }
+    let result = match method.get("result") {
+        Some(result) => param_type(resolve(result, spec), spec),
+        None => "::serde_json::Value".to_owned(),
+    };
+    if param_lines.is_empty() {
+        writeln!(code, "    fn {}(&mut self) -> RpcRequest<{}>;", ident, result).unwrap();
+    } else {
+        writeln!(code, "    fn {}(\n        &mut self,", ident).unwrap();
+        code.push_str(&param_lines);
+        writeln!(code, "    ) -> RpcRequest<{}>;", result).unwrap();
+    }
+    Ok(ident)
+}
+
+/// Returns the Rust type of an OpenRPC content descriptor.
+fn param_type(descriptor: &JsonValue, spec: &JsonValue) -> String {
+    match descriptor.get("schema") {
+        Some(schema) => schema::rust_type(schema, spec),
+        None => "::serde_json::Value".to_owned(),
+    }
+}
+
+/// Follows a `$ref` to an object in the spec. Returns the object itself if it is not a
+/// reference, or a reference that can't be resolved.
+fn resolve<'a>(object: &'a JsonValue, spec: &'a JsonValue) -> &'a JsonValue {
+    object
+        .get("$ref")
+        .and_then(JsonValue::as_str)
+        .filter(|reference| reference.starts_with('#'))
+        .and_then(|reference| spec.pointer(&reference[1..]))
+        .unwrap_or(object)
+}
+
+/// Writes text as doc comment lines.
+fn write_docs(code: &mut String, indent: &str, text: &str) {
+    for line in text.trim().lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            writeln!(code, "{}///", indent).unwrap();
+        } else {
+            writeln!(code, "{}/// {}", indent, line).unwrap();
+        }
+    }
+}
+
+/// Parses a spec in JSON, or YAML if it does not look like JSON.
+fn parse_spec(spec: &str) -> Result<JsonValue> {
+    let trimmed = spec.trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        serde_json::from_str(spec).map_err(|e| invalid_spec(format!("Not valid JSON: {}", e)))
+    } else {
+        serde_yaml::from_str(spec).map_err(|e| invalid_spec(format!("Not valid YAML: {}", e)))
+    }
+}
+
+fn invalid_spec<S: Into<String>>(msg: S) -> Error {
+    Error::InvalidSpec(msg.into())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r##"{
+        "openrpc": "1.2.6",
+        "info": {"title": "Test API", "version": "1.0.0", "description": "An API for tests."},
+        "methods": [
+            {
+                "name": "eth_getBalance",
+                "summary": "Returns the balance of an account.",
+                "params": [
+                    {"name": "address", "required": true, "schema": {"type": "string"}},
+                    {"name": "blockNumber", "schema": {"type": "integer", "minimum": 0}}
+                ],
+                "result": {"name": "balance", "schema": {"$ref": "#/components/schemas/Quantity"}}
+            },
+            {"$ref": "#/components/methods/ping"}
+        ],
+        "components": {
+            "schemas": {"Quantity": {"type": "string"}},
+            "methods": {"ping": {"name": "ping", "params": [], "deprecated": true}}
+        }
+    }"##;
+
+    const EXPECTED: &str = r##"// Generated by jsonrpc-client-codegen from Test API 1.0.0. Do not edit.
+
+/// An API for tests.
+#[::jsonrpc_client_derive::jsonrpc_client(client = "TestClient")]
+pub trait Test {
+    /// Returns the balance of an account.
+    #[rpc(name = "eth_getBalance")]
+    fn eth_get_balance(
+        &mut self,
+        address: String,
+        #[rpc(name = "blockNumber")] block_number: Option<u64>,
+    ) -> RpcRequest<String>;
+
+    #[rpc(deprecated_since = "1.0.0")]
+    fn ping(&mut self) -> RpcRequest<::serde_json::Value>;
+}
+"##;
+
+    #[test]
+    fn openrpc_document() {
+        let code = Generator::new("Test")
+            .client_name("TestClient")
+            .generate(SPEC)
+            .unwrap();
+        assert_eq!(EXPECTED, code);
+    }
+
+    #[test]
+    fn yaml_method_list() {
+        let spec = "- name: ping\n  result:\n    name: pong\n    schema:\n      type: boolean\n";
+        let code = Generator::new("Ping").generate(spec).unwrap();
+        assert!(code.contains("#[::jsonrpc_client_derive::jsonrpc_client]\npub trait Ping {"));
+        assert!(code.contains("    fn ping(&mut self) -> RpcRequest<bool>;"));
+    }
+
+    #[test]
+    fn invalid_specs() {
+        let specs = [
+            "{",
+            "{}",
+            r#"[{"params": []}]"#,
+            r#"[{"name": "a_b"}, {"name": "aB"}]"#,
+            r#"[{"name": "a", "params": [{"name": "self"}]}]"#,
+        ];
+        for spec in &specs {
+            match Generator::new("Test").generate(spec) {
+                Err(Error::InvalidSpec(_)) => (),
+                result => panic!("Unexpected result for {}: {:?}", spec, result),
+            }
+        }
+    }
+}
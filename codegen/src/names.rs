@@ -0,0 +1,79 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/// Words that can't be used as identifiers.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Turns a method or parameter name as written in a spec, like `eth_getBalance` or
+/// `rpc.discover`, into a snake case Rust identifier, like `eth_get_balance` or `rpc_discover`.
+pub fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut ident = String::with_capacity(name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_alphanumeric() {
+            if c.is_ascii_uppercase() && i > 0 {
+                let prev = chars[i - 1];
+                let next_is_lower = chars.get(i + 1).map_or(false, |c| c.is_ascii_lowercase());
+                if prev.is_ascii_lowercase() || prev.is_ascii_digit()
+                    || (prev.is_ascii_uppercase() && next_is_lower)
+                {
+                    push_underscore(&mut ident);
+                }
+            }
+            ident.push(c.to_ascii_lowercase());
+        } else {
+            push_underscore(&mut ident);
+        }
+    }
+    while ident.ends_with('_') {
+        ident.pop();
+    }
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if KEYWORDS.contains(&ident.as_str()) {
+        ident.push('_');
+    }
+    ident
+}
+
+/// Pushes an underscore unless the identifier is empty or already ends with one.
+fn push_underscore(ident: &mut String) {
+    if !ident.is_empty() && !ident.ends_with('_') {
+        ident.push('_');
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snake_case_names() {
+        let cases = [
+            ("eth_getBalance", "eth_get_balance"),
+            ("rpc.discover", "rpc_discover"),
+            ("getHTTPStatus", "get_http_status"),
+            ("sha3Uint256", "sha3_uint256"),
+            ("already_snake", "already_snake"),
+            ("type", "type_"),
+            ("2fa", "_2fa"),
+            ("-", "_"),
+        ];
+        for &(name, ident) in &cases {
+            assert_eq!(ident, snake_case(name));
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use serde_json::Value as JsonValue;
+
+/// The type used for values whose schema has no better matching Rust type.
+const ANY: &str = "::serde_json::Value";
+
+/// How many `$ref`s are followed before giving up, to not loop forever on recursive schemas.
+const MAX_REF_DEPTH: usize = 16;
+
+
+/// Returns the Rust type of values matching the JSON schema `schema`. References to
+/// `#/components/schemas` are resolved in `spec`. Schemas without a simple matching type, like
+/// objects and unions, map to `serde_json::Value`.
+pub fn rust_type(schema: &JsonValue, spec: &JsonValue) -> String {
+    rust_type_at_depth(schema, spec, 0)
+}
+
+fn rust_type_at_depth(schema: &JsonValue, spec: &JsonValue, depth: usize) -> String {
+    if let Some(reference) = schema.get("$ref").and_then(JsonValue::as_str) {
+        return match resolve(reference, spec) {
+            Some(schema) if depth < MAX_REF_DEPTH => rust_type_at_depth(schema, spec, depth + 1),
+            _ => ANY.to_owned(),
+        };
+    }
+    match schema.get("type") {
+        Some(&JsonValue::String(ref ty)) => simple_type(ty, schema, spec, depth),
+        Some(&JsonValue::Array(ref types)) => {
+            let non_null: Vec<&str> = types
+                .iter()
+                .filter_map(JsonValue::as_str)
+                .filter(|&ty| ty != "null")
+                .collect();
+            match non_null.as_slice() {
+                [ty] if non_null.len() < types.len() => {
+                    format!("Option<{}>", simple_type(ty, schema, spec, depth))
+                }
+                [ty] => simple_type(ty, schema, spec, depth),
+                _ => ANY.to_owned(),
+            }
+        }
+        _ => ANY.to_owned(),
+    }
+}
+
+fn simple_type(ty: &str, schema: &JsonValue, spec: &JsonValue, depth: usize) -> String {
+    match ty {
+        "string" => "String".to_owned(),
+        "boolean" => "bool".to_owned(),
+        "number" => "f64".to_owned(),
+        "integer" => match schema.get("minimum").and_then(JsonValue::as_f64) {
+            Some(minimum) if minimum >= 0.0 => "u64".to_owned(),
+            _ => "i64".to_owned(),
+        },
+        "null" => "()".to_owned(),
+        "array" => match schema.get("items") {
+            Some(items) if items.is_object() => {
+                format!("Vec<{}>", rust_type_at_depth(items, spec, depth))
+            }
+            _ => format!("Vec<{}>", ANY),
+        },
+        _ => ANY.to_owned(),
+    }
+}
+
+/// Looks up a local reference like `#/components/schemas/Block` in the spec.
+fn resolve<'a>(reference: &str, spec: &'a JsonValue) -> Option<&'a JsonValue> {
+    if reference.starts_with('#') {
+        spec.pointer(&reference[1..])
+    } else {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_types() {
+        let spec = json!({});
+        let cases = [
+            (json!({"type": "string"}), "String"),
+            (json!({"type": "integer"}), "i64"),
+            (json!({"type": "integer", "minimum": 0}), "u64"),
+            (json!({"type": "number"}), "f64"),
+            (json!({"type": "boolean"}), "bool"),
+            (json!({"type": "array", "items": {"type": "string"}}), "Vec<String>"),
+            (json!({"type": "array"}), "Vec<::serde_json::Value>"),
+            (json!({"type": ["string", "null"]}), "Option<String>"),
+            (json!({"type": ["string", "integer"]}), "::serde_json::Value"),
+            (json!({"type": "object"}), "::serde_json::Value"),
+            (json!({"oneOf": []}), "::serde_json::Value"),
+        ];
+        for &(ref schema, ty) in &cases {
+            assert_eq!(ty, rust_type(schema, &spec));
+        }
+    }
+
+    #[test]
+    fn references() {
+        let spec = json!({
+            "components": {
+                "schemas": {
+                    "Address": {"type": "string"},
+                    "Addresses": {"type": "array", "items": {"$ref": "#/components/schemas/Address"}},
+                    "Loop": {"$ref": "#/components/schemas/Loop"},
+                },
+            },
+        });
+        let schema = json!({"$ref": "#/components/schemas/Addresses"});
+        assert_eq!("Vec<String>", rust_type(&schema, &spec));
+        let schema = json!({"$ref": "#/components/schemas/Loop"});
+        assert_eq!("::serde_json::Value", rust_type(&schema, &spec));
+        let schema = json!({"$ref": "https://example.com/schema.json"});
+        assert_eq!("::serde_json::Value", rust_type(&schema, &spec));
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use {ClientConfig, ParamsStyle};
+use serde::ser::{Error, Impossible, Serialize, SerializeMap, SerializeTuple, Serializer};
+use std::slice;
+
+
+/// The arguments of a call from a client generated by the `jsonrpc_client` macro, along with
+/// their declared names. Serializes to an array or an object depending on the `ParamsStyle` of
+/// the client.
+#[doc(hidden)]
+pub struct Arguments<P> {
+    names: &'static [&'static str],
+    values: P,
+    style: ParamsStyle,
+}
+
+impl<P: Serialize> Arguments<P> {
+    /// Creates the arguments of a call. `values` must be a tuple or array with one value for
+    /// every name in `names`.
+    pub fn new(config: &ClientConfig, names: &'static [&'static str], values: P) -> Self {
+        Arguments {
+            names,
+            values,
+            style: config.params_style,
+        }
+    }
+}
+
+impl<P: Serialize> Serialize for Arguments<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.style {
+            ParamsStyle::Positional => self.values.serialize(serializer),
+            ParamsStyle::Named => self.values.serialize(NamedSerializer {
+                inner: serializer,
+                names: self.names,
+            }),
+        }
+    }
+}
+
+
+/// Serializes a tuple as an object, with the values keyed by `names`.
+struct NamedSerializer<S> {
+    inner: S,
+    names: &'static [&'static str],
+}
+
+/// The object the elements of a tuple are serialized into by `NamedSerializer`.
+struct NamedValues<M> {
+    map: M,
+    names: slice::Iter<'static, &'static str>,
+}
+
+impl<M: SerializeMap> SerializeTuple for NamedValues<M> {
+    type Ok = M::Ok;
+    type Error = M::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), M::Error> {
+        match self.names.next() {
+            Some(name) => self.map.serialize_entry(name, value),
+            None => Err(M::Error::custom("More arguments than argument names")),
+        }
+    }
+
+    fn end(self) -> Result<M::Ok, M::Error> {
+        self.map.end()
+    }
+}
+
+/// Implements the `Serializer` methods for everything but tuples, failing with an error.
+macro_rules! reject_non_tuples {
+    ($($method:ident($($arg:ty),*) -> $ok:ty;)*) => {
+        $(
+            fn $method(self $(, _: $arg)*) -> Result<$ok, S::Error> {
+                Err(S::Error::custom("Arguments must be a tuple"))
+            }
+        )*
+    }
+}
+
+impl<S: Serializer> Serializer for NamedSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Impossible<S::Ok, S::Error>;
+    type SerializeTuple = NamedValues<S::SerializeMap>;
+    type SerializeTupleStruct = Impossible<S::Ok, S::Error>;
+    type SerializeTupleVariant = Impossible<S::Ok, S::Error>;
+    type SerializeMap = Impossible<S::Ok, S::Error>;
+    type SerializeStruct = Impossible<S::Ok, S::Error>;
+    type SerializeStructVariant = Impossible<S::Ok, S::Error>;
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        if len != self.names.len() {
+            return Err(S::Error::custom("Number of arguments and argument names differ"));
+        }
+        Ok(NamedValues {
+            map: self.inner.serialize_map(Some(len))?,
+            names: self.names.iter(),
+        })
+    }
+
+    reject_non_tuples! {
+        serialize_bool(bool) -> S::Ok;
+        serialize_i8(i8) -> S::Ok;
+        serialize_i16(i16) -> S::Ok;
+        serialize_i32(i32) -> S::Ok;
+        serialize_i64(i64) -> S::Ok;
+        serialize_u8(u8) -> S::Ok;
+        serialize_u16(u16) -> S::Ok;
+        serialize_u32(u32) -> S::Ok;
+        serialize_u64(u64) -> S::Ok;
+        serialize_f32(f32) -> S::Ok;
+        serialize_f64(f64) -> S::Ok;
+        serialize_char(char) -> S::Ok;
+        serialize_str(&str) -> S::Ok;
+        serialize_bytes(&[u8]) -> S::Ok;
+        serialize_none() -> S::Ok;
+        serialize_unit() -> S::Ok;
+        serialize_unit_struct(&'static str) -> S::Ok;
+        serialize_unit_variant(&'static str, u32, &'static str) -> S::Ok;
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize)
+            -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+        serialize_struct_variant(&'static str, u32, &'static str, usize)
+            -> Self::SerializeStructVariant;
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<S::Ok, S::Error> {
+        Err(S::Error::custom("Arguments must be a tuple"))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        Err(S::Error::custom("Arguments must be a tuple"))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        Err(S::Error::custom("Arguments must be a tuple"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn arguments_json<P>(style: ParamsStyle, names: &'static [&'static str], values: P) -> String
+    where
+        P: Serialize,
+    {
+        let config = ClientConfig::new().params_style(style);
+        serde_json::to_string(&Arguments::new(&config, names, values)).unwrap()
+    }
+
+    #[test]
+    fn positional() {
+        let json = arguments_json(ParamsStyle::Positional, &["a", "b"], (1, "x"));
+        assert_eq!(r#"[1,"x"]"#, json);
+    }
+
+    #[test]
+    fn named() {
+        let json = arguments_json(ParamsStyle::Named, &["a", "b"], (1, "x"));
+        assert_eq!(r#"{"a":1,"b":"x"}"#, json);
+        assert_eq!("{}", arguments_json(ParamsStyle::Named, &[], [(); 0]));
+    }
+
+    #[test]
+    fn wrong_number_of_names() {
+        let config = ClientConfig::new().params_style(ParamsStyle::Named);
+        let arguments = Arguments::new(&config, &["a"], (1, 2));
+        assert!(serde_json::to_string(&arguments).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport spreading calls over several endpoints.
+//!
+//! Public node pools and replicated services expose the same API on many endpoints. A
+//! [`BalancedTransport`] holds one transport per endpoint and sends every call to one of them,
+//! picked by its [`Strategy`]. Calls go to the endpoints in turn by default, or to the endpoint
+//! with the fewest calls in flight, which adapts to endpoints of different speed.
+//!
+//! Calls are not retried on another endpoint when they fail. Ids are taken from a counter of the
+//! balanced transport itself, so they are unique across all endpoints.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::balance::{BalancedTransport, Strategy};
+//!
+//! let transport = HttpTransport::new().standalone()?;
+//! let endpoints = vec![
+//!     transport.handle("https://node1.example.com")?,
+//!     transport.handle("https://node2.example.com")?,
+//! ];
+//! let balanced = BalancedTransport::new(endpoints).strategy(Strategy::LeastLoaded);
+//! let client = FizzBuzzClient::new(balanced);
+//! ```
+//!
+//! [`BalancedTransport`]: struct.BalancedTransport.html
+//! [`Strategy`]: enum.Strategy.html
+
+use Transport;
+use futures::{Async, Future, Poll};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// How a `BalancedTransport` picks the endpoint of a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Sends calls to the endpoints in turn.
+    RoundRobin,
+    /// Sends every call to the endpoint with the fewest calls in flight. Endpoints with equally
+    /// many calls in flight take turns.
+    LeastLoaded,
+}
+
+struct Endpoint<T> {
+    transport: T,
+    in_flight: Arc<AtomicUsize>,
+}
+
+
+/// A transport sending every call to one of several inner transports, as picked by a
+/// `Strategy`.
+///
+/// Clones share the endpoints, the count of calls in flight on them and the id counter.
+pub struct BalancedTransport<T> {
+    endpoints: Arc<Vec<Endpoint<T>>>,
+    strategy: Strategy,
+    next: Arc<AtomicUsize>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl<T: Transport> BalancedTransport<T> {
+    /// Creates a transport spreading calls over `transports`, one per endpoint, with the
+    /// `RoundRobin` strategy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `transports` is empty.
+    pub fn new(transports: Vec<T>) -> Self {
+        assert!(!transports.is_empty(), "No endpoints to balance calls over");
+        let endpoints = transports
+            .into_iter()
+            .map(|transport| Endpoint {
+                transport,
+                in_flight: Arc::new(AtomicUsize::new(0)),
+            })
+            .collect();
+        BalancedTransport {
+            endpoints: Arc::new(endpoints),
+            strategy: Strategy::RoundRobin,
+            next: Arc::new(AtomicUsize::new(0)),
+            next_id: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+
+    /// Sets how the endpoint of each call is picked.
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Returns the number of calls in flight on every endpoint, in the order the transports
+    /// were given.
+    pub fn in_flight(&self) -> Vec<usize> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| endpoint.in_flight.load(Ordering::SeqCst))
+            .collect()
+    }
+
+    fn pick(&self) -> &Endpoint<T> {
+        let count = self.endpoints.len();
+        let start = self.next.fetch_add(1, Ordering::SeqCst) % count;
+        let index = match self.strategy {
+            Strategy::RoundRobin => start,
+            Strategy::LeastLoaded => (0..count)
+                .map(|i| (start + i) % count)
+                .min_by_key(|&i| self.endpoints[i].in_flight.load(Ordering::SeqCst))
+                .unwrap_or(start),
+        };
+        &self.endpoints[index]
+    }
+}
+
+impl<T> Clone for BalancedTransport<T> {
+    fn clone(&self) -> Self {
+        BalancedTransport {
+            endpoints: self.endpoints.clone(),
+            strategy: self.strategy,
+            next: self.next.clone(),
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<T: Transport> Transport for BalancedTransport<T> {
+    type Future = BalancedFuture<T::Future>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    /// Describes all endpoints, separated by commas.
+    fn endpoint(&self) -> Option<String> {
+        let endpoints: Vec<String> = self.endpoints
+            .iter()
+            .filter_map(|endpoint| endpoint.transport.endpoint())
+            .collect();
+        if endpoints.is_empty() {
+            None
+        } else {
+            Some(endpoints.join(", "))
+        }
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let endpoint = self.pick();
+        endpoint.in_flight.fetch_add(1, Ordering::SeqCst);
+        BalancedFuture {
+            future: endpoint.transport.send(json_data),
+            in_flight: Some(InFlight(endpoint.in_flight.clone())),
+        }
+    }
+}
+
+
+/// Counts a call as in flight on its endpoint until dropped.
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+/// The future returned by `BalancedTransport` send operations.
+pub struct BalancedFuture<F> {
+    future: F,
+    in_flight: Option<InFlight>,
+}
+
+impl<F: Future> Future for BalancedFuture<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let result = self.future.poll();
+        if let Ok(Async::NotReady) = result {
+            return result;
+        }
+        self.in_flight = None;
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util::SilentTransport;
+
+    #[test]
+    fn round_robin() {
+        let endpoints = vec![SilentTransport::default(), SilentTransport::default()];
+        let balanced = BalancedTransport::new(endpoints.clone());
+        let calls: Vec<_> = (0..5).map(|_| balanced.send(Vec::new())).collect();
+        assert_eq!((3, 2), (endpoints[0].sent(), endpoints[1].sent()));
+        assert_eq!(vec![3, 2], balanced.in_flight());
+        drop(calls);
+        assert_eq!(vec![0, 0], balanced.in_flight());
+        assert_eq!(1, balanced.get_next_id());
+        assert_eq!(2, balanced.clone().get_next_id());
+    }
+
+    #[test]
+    fn least_loaded() {
+        let endpoints = vec![SilentTransport::default(), SilentTransport::default()];
+        let balanced = BalancedTransport::new(endpoints.clone()).strategy(Strategy::LeastLoaded);
+        let first = balanced.send(Vec::new());
+        let _second = balanced.send(Vec::new());
+        drop(first);
+        assert_eq!(vec![0, 1], balanced.in_flight());
+
+        let _third = balanced.send(Vec::new());
+        assert_eq!((2, 1), (endpoints[0].sent(), endpoints[1].sent()));
+        assert_eq!(vec![1, 1], balanced.in_flight());
+    }
+}
@@ -0,0 +1,656 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper coalescing calls into JSON-RPC batches.
+//!
+//! Every request sent over a high-latency connection pays the full round trip. A
+//! [`BatchingTransport`] collects the calls made within a short window after the first one, or
+//! until a maximum number of calls has been collected, and sends them as one JSON-RPC 2.0 batch.
+//! The server answers with an array of responses, which are handed to the calls by id, so every
+//! caller gets its own result as if the call had been sent alone. A window with a single call
+//! sends it as a plain request.
+//!
+//! The batch is sent when any of its calls is polled after the window has ended, so the calls
+//! have to be driven like any other calls. Notifications in a batch complete when the batch has
+//! been answered, as the server does not respond to them.
+//!
+//! Servers that do not support batches usually answer them with a single error response. All
+//! calls of the batch then fail with [`BatchError::Rejected`]. Errors shared by all calls of a
+//! batch name every call of the batch as a [`BatchedCall`], so callers can tell which calls
+//! failed together.
+//!
+//! A batch is sent with the earliest [deadline] of its calls, so transport wrappers below the
+//! `BatchingTransport`, like a `RetryTransport`, give up in time for every call. A call with a
+//! deadline before the end of the window sends its batch right away instead of waiting. The
+//! [metadata] of the calls does not apply to batches, since they are sent as one request for all
+//! of them. A window with a single call sends it with its metadata.
+//!
+//! # Example
+//!
+//! ```rust
+//! #[macro_use]
+//! extern crate jsonrpc_client_core;
+//! extern crate futures;
+//!
+//! use futures::{future, Future};
+//! use jsonrpc_client_core::batch::BatchingTransport;
+//! use jsonrpc_client_core::jsonrpc_core::Error;
+//! use jsonrpc_client_core::server::LocalTransport;
+//! use std::time::Duration;
+//!
+//! jsonrpc_client!(
+//!     pub struct FizzBuzzClient {
+//!         pub fn fizz_buzz(&self, number: u64) -> RpcRequest<String>;
+//!     }
+//!
+//!     pub trait FizzBuzzServer;
+//! );
+//!
+//! struct MockFizzBuzz;
+//!
+//! impl FizzBuzzServer for MockFizzBuzz {
+//!     fn fizz_buzz(&self, number: u64) -> Result<String, Error> {
+//!         Ok(number.to_string())
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let transport = LocalTransport::new(MockFizzBuzz.into_io_handler());
+//!     let transport = BatchingTransport::new(transport)
+//!         .window(Duration::from_millis(5))
+//!         .max_size(50);
+//!     let client = FizzBuzzClient::new(transport);
+//!     // Sent as one batch of three calls.
+//!     let results = future::join_all((1..4).map(|n| client.fizz_buzz(n))).wait().unwrap();
+//!     assert_eq!(vec!["1", "2", "3"], results);
+//! }
+//! ```
+//!
+//! [`BatchingTransport`]: struct.BatchingTransport.html
+//! [`BatchError::Rejected`]: enum.BatchError.html#variant.Rejected
+//! [`BatchedCall`]: struct.BatchedCall.html
+//! [deadline]: ../deadline/index.html
+//! [metadata]: ../metadata/index.html
+
+use Transport;
+use deadline::{self, Deadline};
+use futures::future::Shared;
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use metadata::Scope;
+use response::id_key;
+use serde_json::{self, Value as JsonValue};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use timer;
+use tokio_timer::Sleep;
+
+
+/// A call of a batch, as named by the errors all calls of the batch fail with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchedCall {
+    /// The method of the call.
+    pub method: String,
+    /// The id of the call, `None` for notifications.
+    pub id: Option<JsonValue>,
+}
+
+/// Error returned by `BatchingTransport` send operations.
+#[derive(Debug)]
+pub enum BatchError<E> {
+    /// The inner transport failed to send the batch.
+    Transport {
+        /// The error of the inner transport, shared by all calls of the batch.
+        error: Arc<E>,
+        /// The calls of the batch, in the order they were made.
+        calls: Arc<Vec<BatchedCall>>,
+    },
+    /// The server did not answer the batch with an array of responses.
+    Rejected {
+        /// The response of the server.
+        response: String,
+        /// The calls of the batch, in the order they were made.
+        calls: Arc<Vec<BatchedCall>>,
+    },
+    /// The server answered the batch, but not this call.
+    MissingResponse,
+}
+
+impl<E: error::Error> fmt::Display for BatchError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BatchError::Transport {
+                ref error,
+                ref calls,
+            } => write!(f, "Transport error sending {}: {}", Described(calls), error),
+            BatchError::Rejected {
+                ref response,
+                ref calls,
+            } => write!(f, "Server rejected {}: {}", Described(calls), response),
+            BatchError::MissingResponse => write!(f, "No response to the call in the batch"),
+        }
+    }
+}
+
+/// Displays the calls of a batch, like `batch of 2 calls (get_balance 1, ping 2)`.
+struct Described<'a>(&'a [BatchedCall]);
+
+impl<'a> fmt::Display for Described<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "batch of {} calls", self.0.len())?;
+        for (i, call) in self.0.iter().enumerate() {
+            let separator = if i == 0 { " (" } else { ", " };
+            write!(f, "{}{}", separator, call.method)?;
+            if let Some(ref id) = call.id {
+                write!(f, " {}", id)?;
+            }
+        }
+        if !self.0.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for BatchError<E> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            BatchError::Transport { ref error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+
+/// The responses to a batch, by request id as returned by `id_key`.
+enum Responses {
+    /// The response to a batch of one call, sent as a plain request.
+    Single(Vec<u8>),
+    Batch(HashMap<String, Vec<u8>>),
+    /// The server did not answer with an array.
+    Rejected(String),
+}
+
+type SentBatch<E> = Shared<Box<Future<Item = Responses, Error = Arc<E>> + Send>>;
+
+/// The calls collected in one window.
+struct Batch<E> {
+    requests: Vec<Vec<u8>>,
+    /// The calls of the batch, named in the errors they all fail with.
+    calls: Arc<Vec<BatchedCall>>,
+    /// When the window of the batch ends.
+    window_end: Instant,
+    /// The earliest deadline of the calls in the batch.
+    deadline: Option<Deadline>,
+    /// The metadata of the first call, applied if it is the only call in the batch.
+    scope: Scope,
+    /// The tasks of the calls waiting for the batch to be sent, by index of the call.
+    waiters: Vec<Option<Task>>,
+    /// The response future, once the batch has been sent.
+    sent: Option<SentBatch<E>>,
+}
+
+struct Inner<T: Transport> {
+    transport: T,
+    window: Duration,
+    max_size: usize,
+    /// The batch collecting calls, if a window is open.
+    open: Mutex<Option<Arc<Mutex<Batch<T::Error>>>>>,
+}
+
+impl<T: Transport> Inner<T> {
+    /// Sends `batch` if it has not been sent yet, and closes its window if it is still open.
+    fn flush(&self, batch: &Arc<Mutex<Batch<T::Error>>>) {
+        {
+            let mut open = self.open.lock().unwrap();
+            if open.as_ref().map_or(false, |open| Arc::ptr_eq(open, batch)) {
+                *open = None;
+            }
+        }
+        let mut batch = batch.lock().unwrap();
+        if batch.sent.is_some() {
+            return;
+        }
+        let requests = mem::replace(&mut batch.requests, Vec::new());
+        trace!("Sending batch of {} requests", requests.len());
+        let transport = &self.transport;
+        let deadline = batch.deadline.clone();
+        let future: Box<Future<Item = Responses, Error = Arc<T::Error>> + Send> =
+            if requests.len() == 1 {
+                let request = requests.into_iter().next().unwrap();
+                let scope = &batch.scope;
+                let future =
+                    deadline::in_scope(deadline, || scope.enter(|| transport.send(request)));
+                Box::new(future.map(Responses::Single).map_err(Arc::new))
+            } else {
+                let future = deadline::in_scope(deadline, || transport.send(batch_body(&requests)));
+                Box::new(future.map(|response| split(&response)).map_err(Arc::new))
+            };
+        batch.sent = Some(future.shared());
+        for waiter in batch.waiters.drain(..).filter_map(|waiter| waiter) {
+            waiter.notify();
+        }
+    }
+}
+
+
+/// A transport collecting the calls made within a short window into one JSON-RPC batch, sent
+/// on an inner transport.
+///
+/// Clones share the inner transport and the batch being collected.
+pub struct BatchingTransport<T: Transport> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Transport + Send + Sync + 'static> BatchingTransport<T> {
+    /// Wraps `transport` so calls are sent in batches. Collects calls for 10 milliseconds after
+    /// the first one, up to 100 calls, by default.
+    pub fn new(transport: T) -> Self {
+        BatchingTransport {
+            inner: Arc::new(Inner {
+                transport,
+                window: Duration::from_millis(10),
+                max_size: 100,
+                open: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Sets how long calls are collected after the first call of a batch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the transport has been cloned.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.inner_mut().window = window;
+        self
+    }
+
+    /// Sets the number of calls after which a batch is sent right away, without waiting for the
+    /// window to end.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the transport has been cloned.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.inner_mut().max_size = max_size.max(1);
+        self
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.inner.transport
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner<T> {
+        Arc::get_mut(&mut self.inner).expect("BatchingTransport configured after being cloned")
+    }
+}
+
+impl<T: Transport> Clone for BatchingTransport<T> {
+    fn clone(&self) -> Self {
+        BatchingTransport {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Transport + Send + Sync + 'static> Transport for BatchingTransport<T> {
+    type Future = BatchFuture<T>;
+    type Error = BatchError<T::Error>;
+
+    fn get_next_id(&self) -> u64 {
+        self.inner.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.inner.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let request = serde_json::from_slice::<JsonValue>(&json_data).ok();
+        let call = BatchedCall {
+            method: request
+                .as_ref()
+                .and_then(|request| request.get("method"))
+                .and_then(JsonValue::as_str)
+                .unwrap_or_default()
+                .to_owned(),
+            id: request.as_ref().and_then(|request| request.get("id")).cloned(),
+        };
+        let id = call.id.as_ref().map(id_key);
+        let window = self.inner.window;
+        let call_deadline = Deadline::current();
+        let (batch, index, window_end, ends_in_window) = {
+            let mut open = self.inner.open.lock().unwrap();
+            let batch = open.get_or_insert_with(|| {
+                Arc::new(Mutex::new(Batch {
+                    requests: Vec::new(),
+                    calls: Arc::new(Vec::new()),
+                    window_end: Instant::now() + window,
+                    deadline: None,
+                    scope: Scope::current(),
+                    waiters: Vec::new(),
+                    sent: None,
+                }))
+            }).clone();
+            let mut collecting = batch.lock().unwrap();
+            collecting.requests.push(json_data);
+            // Only shared once the batch has been sent.
+            Arc::make_mut(&mut collecting.calls).push(call);
+            collecting.waiters.push(None);
+            let ends_in_window = call_deadline
+                .as_ref()
+                .map_or(false, |deadline| deadline.at() <= collecting.window_end);
+            if let Some(call_deadline) = call_deadline {
+                let earlier = collecting
+                    .deadline
+                    .as_ref()
+                    .map_or(true, |deadline| call_deadline.at() < deadline.at());
+                if earlier {
+                    collecting.deadline = Some(call_deadline);
+                }
+            }
+            let index = collecting.requests.len() - 1;
+            let window_end = collecting.window_end;
+            drop(collecting);
+            (batch, index, window_end, ends_in_window)
+        };
+        if index + 1 >= self.inner.max_size || ends_in_window {
+            self.inner.flush(&batch);
+        }
+        let now = Instant::now();
+        let remaining = if window_end > now {
+            window_end - now
+        } else {
+            Duration::new(0, 0)
+        };
+        BatchFuture {
+            inner: self.inner.clone(),
+            batch,
+            index,
+            id,
+            window: timer::sleep(remaining),
+            sent: None,
+        }
+    }
+}
+
+
+/// The future returned by `BatchingTransport` send operations.
+pub struct BatchFuture<T: Transport> {
+    inner: Arc<Inner<T>>,
+    batch: Arc<Mutex<Batch<T::Error>>>,
+    /// The index of the call in its batch.
+    index: usize,
+    /// The id of the request as returned by `id_key`, `None` for notifications.
+    id: Option<String>,
+    window: Sleep,
+    /// The response future of the batch, once it has been sent. Kept between polls, since
+    /// dropping a clone of a shared future stops it from waking this call up.
+    sent: Option<SentBatch<T::Error>>,
+}
+
+impl<T: Transport> Future for BatchFuture<T> {
+    type Item = Vec<u8>;
+    type Error = BatchError<T::Error>;
+
+    fn poll(&mut self) -> Poll<Vec<u8>, BatchError<T::Error>> {
+        loop {
+            if self.sent.is_none() {
+                self.sent = self.batch.lock().unwrap().sent.clone();
+            }
+            let sent = match self.sent {
+                Some(ref mut sent) => sent,
+                None => {
+                    match self.window.poll() {
+                        Ok(Async::NotReady) => {
+                            // Woken when another call sends the batch before the window ends.
+                            let mut batch = self.batch.lock().unwrap();
+                            if batch.sent.is_some() {
+                                continue;
+                            }
+                            batch.waiters[self.index] = Some(task::current());
+                            return Ok(Async::NotReady);
+                        }
+                        Ok(Async::Ready(())) => (),
+                        Err(error) => warn!("Unable to wait for the batch window: {}", error),
+                    }
+                    self.inner.flush(&self.batch);
+                    continue;
+                }
+            };
+            let responses = match sent.poll() {
+                Ok(Async::Ready(responses)) => responses,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(error) => {
+                    return Err(BatchError::Transport {
+                        error: (*error).clone(),
+                        calls: self.batch.lock().unwrap().calls.clone(),
+                    })
+                }
+            };
+            return match (&*responses, self.id.as_ref()) {
+                (&Responses::Single(ref response), _) => Ok(Async::Ready(response.clone())),
+                (_, None) => Ok(Async::Ready(Vec::new())),
+                (&Responses::Batch(ref responses), Some(id)) => match responses.get(id) {
+                    Some(response) => Ok(Async::Ready(response.clone())),
+                    None => Err(BatchError::MissingResponse),
+                },
+                (&Responses::Rejected(ref response), Some(_)) => Err(BatchError::Rejected {
+                    response: response.clone(),
+                    calls: self.batch.lock().unwrap().calls.clone(),
+                }),
+            };
+        }
+    }
+}
+
+
+/// Joins requests into the body of a batch request.
+fn batch_body(requests: &[Vec<u8>]) -> Vec<u8> {
+    let length = requests.iter().map(Vec::len).sum::<usize>() + requests.len() + 1;
+    let mut body = Vec::with_capacity(length);
+    body.push(b'[');
+    for (i, request) in requests.iter().enumerate() {
+        if i > 0 {
+            body.push(b',');
+        }
+        body.extend_from_slice(request);
+    }
+    body.push(b']');
+    body
+}
+
+/// Splits the response to a batch into the responses to its calls.
+fn split(response: &[u8]) -> Responses {
+    match serde_json::from_slice::<JsonValue>(response) {
+        Ok(JsonValue::Array(responses)) => Responses::Batch(
+            responses
+                .into_iter()
+                .filter_map(|response| {
+                    let id = response.get("id").map(id_key)?;
+                    serde_json::to_vec(&response).ok().map(|response| (id, response))
+                })
+                .collect(),
+        ),
+        _ => Responses::Rejected(String::from_utf8_lossy(response).into_owned()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deadline::with_deadline;
+    use futures::future::{self, FutureResult};
+    use metadata::{with_metadata, CallMetadata};
+    use std::io;
+    use test_util::EchoParamsTransport;
+
+    fn request(id: u64, value: u64) -> Vec<u8> {
+        let request = json!({"jsonrpc": "2.0", "method": "echo", "params": [value], "id": id});
+        serde_json::to_vec(&request).unwrap()
+    }
+
+    fn result(response: Vec<u8>) -> JsonValue {
+        serde_json::from_slice::<JsonValue>(&response).unwrap()["result"].clone()
+    }
+
+    #[test]
+    fn calls_in_window_share_a_batch() {
+        let transport = BatchingTransport::new(EchoParamsTransport::default());
+        let calls = (1..4).map(|id| transport.send(request(id, id * 10)));
+        let responses = future::join_all(calls).wait().unwrap();
+        let results: Vec<_> = responses.into_iter().map(result).collect();
+        assert_eq!(vec![json!(10), json!(20), json!(30)], results);
+
+        let notification = br#"{"jsonrpc": "2.0", "method": "log", "params": []}"#.to_vec();
+        let calls = vec![transport.send(request(4, 40)), transport.send(notification)];
+        let responses = future::join_all(calls).wait().unwrap();
+        assert_eq!(json!(40), result(responses[0].clone()));
+        assert!(responses[1].is_empty());
+        assert_eq!(vec![3, 2], *transport.get_ref().sizes.lock().unwrap());
+    }
+
+    #[test]
+    fn full_batch_is_sent_right_away() {
+        let transport = BatchingTransport::new(EchoParamsTransport::default())
+            .window(Duration::from_secs(60))
+            .max_size(2);
+        let first = transport.send(request(1, 1));
+        let second = transport.send(request(2, 2));
+        let single = transport.send(request(3, 3));
+        assert_eq!(json!(2), result(second.wait().unwrap()));
+        assert_eq!(json!(1), result(first.wait().unwrap()));
+        assert_eq!(vec![2], *transport.get_ref().sizes.lock().unwrap());
+        drop(single);
+    }
+
+    #[test]
+    fn sent_with_earliest_deadline() {
+        let transport = BatchingTransport::new(EchoParamsTransport::default());
+        let early = Instant::now() + Duration::from_secs(30);
+        let late = early + Duration::from_secs(30);
+        let calls = vec![
+            with_deadline(late, || transport.send(request(1, 1))),
+            with_deadline(early, || transport.send(request(2, 2))),
+            transport.send(request(3, 3)),
+        ];
+        future::join_all(calls).wait().unwrap();
+        assert_eq!(vec![Some(early)], *transport.get_ref().deadlines.lock().unwrap());
+    }
+
+    #[test]
+    fn sent_before_deadline_in_window() {
+        let transport =
+            BatchingTransport::new(EchoParamsTransport::default()).window(Duration::from_secs(60));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let calls = vec![
+            transport.send(request(1, 1)),
+            with_deadline(deadline, || transport.send(request(2, 2))),
+        ];
+        future::join_all(calls).wait().unwrap();
+        assert!(Instant::now() < deadline);
+        assert_eq!(vec![2], *transport.get_ref().sizes.lock().unwrap());
+    }
+
+    #[test]
+    fn single_call_keeps_metadata() {
+        let transport = BatchingTransport::new(EchoParamsTransport::default());
+        let metadata = CallMetadata::new().trace_id("4bf92f3577b34da6");
+        with_metadata(metadata, || transport.send(request(1, 1))).wait().unwrap();
+        let trace_ids = transport.get_ref().trace_ids.lock().unwrap();
+        assert_eq!(vec![Some("4bf92f3577b34da6".to_owned())], *trace_ids);
+    }
+
+    #[test]
+    fn numeric_ids_as_strings() {
+        let response = br#"[
+            {"jsonrpc": "2.0", "id": "5", "result": 5},
+            {"jsonrpc": "2.0", "id": "five", "result": 5}
+        ]"#;
+        match split(response) {
+            Responses::Batch(responses) => {
+                assert!(responses.contains_key(&id_key(&json!(5))));
+                assert!(responses.contains_key(&id_key(&json!("five"))));
+                assert!(!responses.contains_key(&id_key(&json!(6))));
+            }
+            _ => panic!("Batch response not split"),
+        }
+    }
+
+    #[test]
+    fn transport_error_names_calls() {
+        struct Failing;
+
+        impl Transport for Failing {
+            type Future = FutureResult<Vec<u8>, io::Error>;
+            type Error = io::Error;
+
+            fn get_next_id(&self) -> u64 {
+                1
+            }
+
+            fn send(&self, _json_data: Vec<u8>) -> Self::Future {
+                future::err(io::Error::new(io::ErrorKind::BrokenPipe, "Broken pipe"))
+            }
+        }
+
+        let transport = BatchingTransport::new(Failing);
+        let calls = vec![transport.send(request(1, 1)), transport.send(request(2, 2))];
+        let error = future::join_all(calls).wait().unwrap_err();
+        assert_eq!(
+            "Transport error sending batch of 2 calls (echo 1, echo 2): Broken pipe",
+            error.to_string()
+        );
+        let source = error::Error::source(&error).unwrap();
+        assert_eq!("Broken pipe", source.to_string());
+    }
+
+    #[test]
+    fn rejected_batch() {
+        struct NoBatches;
+
+        impl Transport for NoBatches {
+            type Future = FutureResult<Vec<u8>, io::Error>;
+            type Error = io::Error;
+
+            fn get_next_id(&self) -> u64 {
+                1
+            }
+
+            fn send(&self, _json_data: Vec<u8>) -> Self::Future {
+                let response = json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {"code": -32600, "message": "Invalid Request"},
+                });
+                future::ok(serde_json::to_vec(&response).unwrap())
+            }
+        }
+
+        let transport = BatchingTransport::new(NoBatches);
+        let calls = vec![transport.send(request(1, 1)), transport.send(request(2, 2))];
+        match future::join_all(calls).wait() {
+            Err(BatchError::Rejected {
+                ref response,
+                ref calls,
+            }) => {
+                assert!(response.contains("-32600"));
+                let ids: Vec<_> = calls.iter().map(|call| call.id.clone()).collect();
+                assert_eq!(vec![Some(json!(1)), Some(json!(2))], ids);
+            }
+            other => panic!("Unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Type-erased transports, for choosing the transport at runtime.
+//!
+//! `Transport` has associated future and error types, so it can not be used as a trait object
+//! directly. [`DynTransport`] is an object-safe version of it, implemented for every transport
+//! that is `Send + Sync`. Its futures are boxed and its errors are wrapped in a [`BoxError`].
+//! A [`BoxTransport`] is in turn a `Transport`, so a client on it is one concrete type whatever
+//! transport is behind it, and code using the client does not need to be generic. It is the
+//! default type parameter of generated clients and `RpcClient`, so the type-erased client is
+//! named without any type parameters.
+//!
+//! Errors of the transport behind a `BoxTransport` can still be inspected with
+//! `Error::transport_error`, which looks through the `BoxError`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::boxed;
+//!
+//! fn connect(config: &Config) -> FizzBuzzClient {
+//!     let transport = if config.mock {
+//!         boxed::transport(LocalTransport::new(MockFizzBuzz.into_io_handler()))
+//!     } else {
+//!         boxed::transport(HttpTransport::new().standalone()?.handle(&config.uri)?)
+//!     };
+//!     FizzBuzzClient::new(transport)
+//! }
+//! ```
+//!
+//! [`DynTransport`]: trait.DynTransport.html
+//! [`BoxError`]: struct.BoxError.html
+//! [`BoxTransport`]: type.BoxTransport.html
+
+use Transport;
+use futures::Future;
+use std::error::Error;
+use std::fmt;
+
+/// A transport of any type, behind a box.
+pub type BoxTransport = Box<DynTransport>;
+
+/// The future returned by a `BoxTransport`.
+pub type BoxTransportFuture = Box<Future<Item = Vec<u8>, Error = BoxError> + Send>;
+
+/// Boxes a transport, erasing its type.
+pub fn transport<T>(transport: T) -> BoxTransport
+where
+    T: Transport + Send + Sync + 'static,
+{
+    Box::new(transport)
+}
+
+
+/// An object-safe version of `Transport`, with boxed futures and errors. Implemented for all
+/// transports that can be shared between threads.
+pub trait DynTransport: Send + Sync {
+    /// Returns an id that has not yet been used on this transport, like
+    /// `Transport::get_next_id`.
+    fn get_next_id(&self) -> u64;
+
+    /// Returns a description of where this transport sends its requests, like
+    /// `Transport::endpoint`.
+    fn endpoint(&self) -> Option<String>;
+
+    /// Sends the given data over the transport, like `Transport::send`.
+    fn send_boxed(&self, json_data: Vec<u8>) -> BoxTransportFuture;
+}
+
+impl<T> DynTransport for T
+where
+    T: Transport + Send + Sync,
+{
+    fn get_next_id(&self) -> u64 {
+        Transport::get_next_id(self)
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        Transport::endpoint(self)
+    }
+
+    fn send_boxed(&self, json_data: Vec<u8>) -> BoxTransportFuture {
+        Box::new(Transport::send(self, json_data).map_err(BoxError::new))
+    }
+}
+
+impl Transport for Box<DynTransport> {
+    type Future = BoxTransportFuture;
+    type Error = BoxError;
+
+    fn get_next_id(&self) -> u64 {
+        (**self).get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        (**self).endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        (**self).send_boxed(json_data)
+    }
+}
+
+
+/// The error of a `BoxTransport`, wrapping the error of the transport behind it.
+#[derive(Debug)]
+pub struct BoxError(Box<Error + Send + Sync>);
+
+impl BoxError {
+    /// Wraps the error of a transport. An error that already is a `BoxError` is not wrapped
+    /// again.
+    pub fn new<E: Error + Send + Sync + 'static>(error: E) -> Self {
+        let error: Box<Error + Send + Sync> = Box::new(error);
+        match error.downcast::<BoxError>() {
+            Ok(error) => *error,
+            Err(error) => BoxError(error),
+        }
+    }
+
+    /// Returns the error of the transport.
+    pub fn get_ref(&self) -> &(Error + Send + Sync + 'static) {
+        &*self.0
+    }
+
+    /// Consumes this error and returns the error of the transport.
+    pub fn into_inner(self) -> Box<Error + Send + Sync> {
+        self.0
+    }
+}
+
+impl fmt::Display for BoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for BoxError {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        self.0.source()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ErrorKind;
+    use futures::future::{self, FutureResult};
+    use std::io;
+
+    /// A transport failing every request.
+    struct FailingTransport;
+
+    impl Transport for FailingTransport {
+        type Future = FutureResult<Vec<u8>, io::Error>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            1
+        }
+
+        fn endpoint(&self) -> Option<String> {
+            Some("failing".to_owned())
+        }
+
+        fn send(&self, _json_data: Vec<u8>) -> Self::Future {
+            future::err(io::Error::new(io::ErrorKind::ConnectionRefused, "Refused"))
+        }
+    }
+
+    jsonrpc_client!(pub struct PingClient {
+        pub fn ping(&self) -> RpcRequest<bool>;
+    });
+
+    #[test]
+    fn transport_chosen_at_runtime() {
+        let transports = vec![
+            transport(FailingTransport),
+            transport(::server::LocalTransport::new(Default::default())),
+        ];
+        let clients: Vec<PingClient> =
+            transports.into_iter().map(PingClient::new).collect();
+
+        let error = clients[0].ping().call().unwrap_err();
+        assert_eq!(ErrorKind::TransportError, *error.kind());
+        let io_error = error.transport_error::<io::Error>().unwrap();
+        assert_eq!(io::ErrorKind::ConnectionRefused, io_error.kind());
+        let io_error = error.into_transport_error::<io::Error>().unwrap();
+        assert_eq!(io::ErrorKind::ConnectionRefused, io_error.kind());
+
+        let error = clients[1].ping().call().unwrap_err();
+        assert_ne!(ErrorKind::TransportError, *error.kind());
+    }
+
+    #[test]
+    fn boxing_twice_keeps_one_wrapper() {
+        let twice = transport(transport(FailingTransport));
+        assert_eq!(Some("failing".to_owned()), Transport::endpoint(&twice));
+        let error = twice.send(Vec::new()).wait().unwrap_err();
+        assert!(error.get_ref().downcast_ref::<io::Error>().is_some());
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+
+/// A pool of byte buffers reused across calls, to avoid allocating new buffers for every request
+/// in high frequency polling loops.
+///
+/// Given to a client with [`ClientConfig::buffer_pool`]. Requests are then serialized into
+/// buffers taken from the pool, and the response buffer of each call is returned to the pool
+/// after the response has been parsed. Since transports take ownership of the request and hand
+/// back a response buffer, a steady state is reached where serializing allocates nothing.
+/// Transports that can read responses into a given buffer can share the pool and `take` their
+/// response buffers from it as well.
+///
+/// Clones share the same buffers.
+///
+/// [`ClientConfig::buffer_pool`]: struct.ClientConfig.html#method.buffer_pool
+#[derive(Clone)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+    max_buffer_size: usize,
+}
+
+impl BufferPool {
+    /// Creates an empty pool keeping at most 8 buffers of at most 1 MiB each.
+    pub fn new() -> Self {
+        BufferPool {
+            buffers: Arc::new(Mutex::new(Vec::new())),
+            max_buffers: 8,
+            max_buffer_size: 1024 * 1024,
+        }
+    }
+
+    /// Sets the maximum number of buffers kept in the pool. Should be at least the number of
+    /// calls usually in flight at the same time.
+    pub fn max_buffers(mut self, max_buffers: usize) -> Self {
+        self.max_buffers = max_buffers;
+        self
+    }
+
+    /// Sets the capacity in bytes above which buffers are dropped instead of returned to the
+    /// pool, so a single large response does not stay allocated forever.
+    pub fn max_buffer_size(mut self, max_buffer_size: usize) -> Self {
+        self.max_buffer_size = max_buffer_size;
+        self
+    }
+
+    /// Takes an empty buffer from the pool, or allocates a new one if the pool is empty.
+    pub fn take(&self) -> Vec<u8> {
+        match self.buffers.lock() {
+            Ok(mut buffers) => buffers.pop().unwrap_or_default(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Clears the buffer and returns it to the pool. Dropped instead if the pool is full or the
+    /// buffer is larger than the maximum buffer size.
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > self.max_buffer_size {
+            return;
+        }
+        buffer.clear();
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < self.max_buffers {
+                buffers.push(buffer);
+            }
+        }
+    }
+
+    /// Returns the number of buffers currently in the pool.
+    pub fn len(&self) -> usize {
+        self.buffers.lock().map(|buffers| buffers.len()).unwrap_or(0)
+    }
+
+    /// Returns true if there are no buffers in the pool.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("len", &self.len())
+            .field("max_buffers", &self.max_buffers)
+            .field("max_buffer_size", &self.max_buffer_size)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_buffers() {
+        let pool = BufferPool::new();
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(b"request");
+        let ptr = buffer.as_ptr();
+        pool.put(buffer);
+        assert_eq!(1, pool.len());
+
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        assert_eq!(ptr, buffer.as_ptr());
+        assert_eq!(0, pool.len());
+    }
+
+    #[test]
+    fn limits() {
+        let pool = BufferPool::new().max_buffers(1).max_buffer_size(16);
+        pool.put(Vec::with_capacity(32));
+        assert_eq!(0, pool.len());
+        pool.put(Vec::with_capacity(8));
+        pool.put(Vec::with_capacity(8));
+        assert_eq!(1, pool.len());
+    }
+}
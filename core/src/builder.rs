@@ -0,0 +1,230 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use {ClientConfig, Transport, Validation};
+use boxed::{self, BoxError, BoxTransport};
+use instrument::Instrumentation;
+use intercept::{InterceptedTransport, Interceptor};
+use layer::Layer;
+use retry::{RetryPolicy, RetryTransport};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+
+/// Clients that can be created from a transport and a `ClientConfig`. Implemented by all clients
+/// generated by the `jsonrpc_client` macro and by `RpcClient`.
+pub trait FromTransport<T: Transport> {
+    /// Creates a client backed by `transport` and using `config`.
+    fn from_transport(transport: T, config: ClientConfig) -> Self;
+}
+
+
+/// Builds a client on a transport wrapped in a stack of transport wrappers, with the settings of
+/// a `ClientConfig`. The client is type-erased, on a `BoxTransport`.
+///
+/// Each wrapper added wraps the stack built so far, so the wrapper added last is the outermost
+/// one, handling requests first and responses last.
+///
+/// ```rust,ignore
+/// let client: FizzBuzzClient = ClientBuilder::new(handle)
+///     .retry(RetryPolicy::fixed(Duration::from_millis(100)))
+///     .interceptor(ApiKey("secret".to_owned()))
+///     .timeout(Duration::from_secs(10))
+///     .validation(Validation::Lenient)
+///     .build();
+/// ```
+pub struct ClientBuilder {
+    transport: BoxTransport,
+    config: ClientConfig,
+    id_generator: Option<Arc<Fn() -> u64 + Send + Sync>>,
+}
+
+impl ClientBuilder {
+    /// Creates a builder for clients on `transport`, with all settings at their defaults.
+    pub fn new<T: Transport + Send + Sync + 'static>(transport: T) -> Self {
+        ClientBuilder {
+            transport: boxed::transport(transport),
+            config: ClientConfig::default(),
+            id_generator: None,
+        }
+    }
+
+    /// Replaces the configuration of the client. Settings made on the builder before this call
+    /// are discarded.
+    pub fn config(mut self, config: ClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Gives every call a deadline `timeout` after it is made, see `ClientConfig::timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config = self.config.timeout(timeout);
+        self
+    }
+
+    /// Sets how strictly responses are checked, see `ClientConfig::validation`.
+    pub fn validation(mut self, validation: Validation) -> Self {
+        self.config = self.config.validation(validation);
+        self
+    }
+
+    /// Reports all calls of the client to `instrumentation`, see
+    /// `ClientConfig::instrumentation`.
+    pub fn instrumentation<I: Instrumentation + 'static>(mut self, instrumentation: I) -> Self {
+        self.config = self.config.instrumentation(instrumentation);
+        self
+    }
+
+    /// Takes the ids of requests from `id_generator` instead of from the transport. The ids it
+    /// returns must be unique, also across threads.
+    pub fn id_generator<F>(mut self, id_generator: F) -> Self
+    where
+        F: Fn() -> u64 + Send + Sync + 'static,
+    {
+        self.id_generator = Some(Arc::new(id_generator));
+        self
+    }
+
+    /// Retries failed requests according to `policy`, see the [`retry`](retry/index.html)
+    /// module. The errors given to the predicate of the policy wrap the errors of the stack
+    /// below.
+    pub fn retry(self, policy: RetryPolicy<BoxError>) -> Self {
+        self.layer(|transport| RetryTransport::new(Arc::new(transport), policy))
+    }
+
+    /// Runs `interceptor` on all requests and responses, see the
+    /// [`intercept`](intercept/index.html) module.
+    pub fn interceptor<I: Interceptor + 'static>(self, interceptor: I) -> Self {
+        self.layer(|transport| InterceptedTransport::new(transport).with(interceptor))
+    }
+
+    /// Wraps the stack built so far with any transport wrapper, returned by `layer`.
+    pub fn layer<F, T>(mut self, layer: F) -> Self
+    where
+        F: FnOnce(BoxTransport) -> T,
+        T: Transport + Send + Sync + 'static,
+    {
+        self.transport = boxed::transport(layer(self.transport));
+        self
+    }
+
+    /// Wraps the stack built so far with the transport wrapper of a [`Layer`], see the
+    /// [`layer`](layer/index.html) module. The stack is shared through an `Arc`, so layers
+    /// requiring clonable transports can be used.
+    ///
+    /// [`Layer`]: layer/trait.Layer.html
+    pub fn with_layer<L>(self, layer: L) -> Self
+    where
+        L: Layer<Arc<BoxTransport>>,
+        L::Transport: Send + Sync + 'static,
+    {
+        self.layer(|transport| layer.layer(Arc::new(transport)))
+    }
+
+    /// Builds a client of type `C`.
+    pub fn build<C: FromTransport<BoxTransport>>(self) -> C {
+        let transport = match self.id_generator {
+            Some(id_generator) => boxed::transport(IdGenerated {
+                transport: self.transport,
+                id_generator,
+            }),
+            None => self.transport,
+        };
+        C::from_transport(transport, self.config)
+    }
+}
+
+impl fmt::Debug for ClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientBuilder")
+            .field("config", &self.config)
+            .field("id_generator", &self.id_generator.is_some())
+            .finish()
+    }
+}
+
+
+/// A transport taking the ids of requests from a function.
+struct IdGenerated {
+    transport: BoxTransport,
+    id_generator: Arc<Fn() -> u64 + Send + Sync>,
+}
+
+impl Transport for IdGenerated {
+    type Future = <BoxTransport as Transport>::Future;
+    type Error = BoxError;
+
+    fn get_next_id(&self) -> u64 {
+        (self.id_generator)()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        Transport::endpoint(&self.transport)
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        self.transport.send(json_data)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {ErrorKind, RpcClient};
+    use serde_json::Value as JsonValue;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use test_util::{EchoResultTransport, FlakyTransport};
+
+    struct Stamp;
+
+    impl Interceptor for Stamp {
+        fn on_request(&self, request: &mut JsonValue) {
+            request["stamp"] = JsonValue::from(true);
+        }
+    }
+
+    jsonrpc_client!(pub struct EchoClient {
+        pub fn echo(&self) -> RpcRequest<JsonValue>;
+    });
+
+    #[test]
+    fn build_with_layers() {
+        let next_id = AtomicUsize::new(100);
+        let client: EchoClient = ClientBuilder::new(FlakyTransport::new(2, EchoResultTransport))
+            .retry(RetryPolicy::fixed(Duration::from_millis(1)))
+            .interceptor(Stamp)
+            .id_generator(move || next_id.fetch_add(1, Ordering::SeqCst) as u64)
+            .build();
+
+        let request = client.echo().call().unwrap();
+        assert_eq!(json!(100), request["id"]);
+        assert_eq!(json!(true), request["stamp"]);
+        assert_eq!(json!(101), client.echo().call().unwrap()["id"]);
+    }
+
+    #[test]
+    fn build_with_layer_trait() {
+        let client: EchoClient = ClientBuilder::new(FlakyTransport::new(2, EchoResultTransport))
+            .with_layer(RetryPolicy::fixed(Duration::from_millis(1)))
+            .build();
+        assert!(client.echo().call().is_ok());
+    }
+
+    #[test]
+    fn build_with_config() {
+        let mut client: RpcClient = ClientBuilder::new(FlakyTransport::new(1, EchoResultTransport))
+            .timeout(Duration::from_secs(60))
+            .build();
+        let error = client.call::<_, JsonValue>("echo", ()).call().unwrap_err();
+        assert_eq!(ErrorKind::TransportError, *error.kind());
+        assert!(error.transport_error::<io::Error>().is_some());
+    }
+}
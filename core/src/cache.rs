@@ -0,0 +1,285 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper caching the responses of read-only calls.
+//!
+//! Methods like `getblockhash` or `eth_chainId` return the same result for the same parameters
+//! for a long time, often forever. A [`CachingTransport`] keeps the responses of calls to such
+//! methods for a time to live configured per method, and answers later calls with the same
+//! method and parameters from the cache, without sending them. The id of a cached response is
+//! replaced by the id of the call it answers.
+//!
+//! Methods are only cached when given a time to live, either with [`CachingTransport::ttl`] or
+//! with a `#[rpc(cache_ttl = 60)]` attribute, in seconds, on the methods of a generated client
+//! whose `METHODS` are passed to [`CachingTransport::methods`]. Only responses with a `result`
+//! are cached, errors are not. Batches and notifications are passed through as they are.
+//!
+//! Identical calls made while the first of them is still in flight are all sent.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::cache::CachingTransport;
+//!
+//! let transport = CachingTransport::new(handle)
+//!     .methods(NodeClient::<HttpHandle>::METHODS)
+//!     .ttl("getblockhash", Duration::from_secs(3600));
+//! let client = NodeClient::new(transport);
+//! ```
+//!
+//! [`CachingTransport`]: struct.CachingTransport.html
+//! [`CachingTransport::ttl`]: struct.CachingTransport.html#method.ttl
+//! [`CachingTransport::methods`]: struct.CachingTransport.html#method.methods
+
+use {MethodInfo, Transport};
+use futures::{Async, Future, Poll};
+use serde_json::{self, Value as JsonValue};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+/// The method and the serialized parameters of a call.
+type Key = (String, String);
+
+struct Entry {
+    response: JsonValue,
+    expires: Instant,
+}
+
+type Entries = Arc<Mutex<HashMap<Key, Entry>>>;
+
+
+/// A transport answering repeated calls to cached methods from a cache of earlier responses.
+pub struct CachingTransport<T> {
+    transport: T,
+    ttls: HashMap<String, Duration>,
+    entries: Entries,
+}
+
+impl<T: Transport> CachingTransport<T> {
+    /// Creates a transport sending calls through `transport`. No method is cached until given a
+    /// time to live.
+    pub fn new(transport: T) -> Self {
+        CachingTransport {
+            transport,
+            ttls: HashMap::new(),
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Caches the responses of calls to the method `method` for `ttl`. Overrides the time to live
+    /// of the method taken from `methods`.
+    pub fn ttl(mut self, method: &str, ttl: Duration) -> Self {
+        self.ttls.insert(method.to_owned(), ttl);
+        self
+    }
+
+    /// Caches the responses of calls to the methods among `methods` that have a `cache_ttl`, for
+    /// that many seconds. Usually the `METHODS` of a generated client.
+    pub fn methods(mut self, methods: &'static [MethodInfo]) -> Self {
+        for method in methods {
+            if let Some(secs) = method.cache_ttl_secs {
+                self.ttls.insert(method.name.to_owned(), Duration::from_secs(secs));
+            }
+        }
+        self
+    }
+
+    /// Drops all cached responses.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Returns a reference to the inner transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns the cache key, the id and the time to live of the request in `json_data`, if it
+    /// is a call to a cached method.
+    fn cacheable(&self, json_data: &[u8]) -> Option<(Key, JsonValue, Duration)> {
+        let request: JsonValue = serde_json::from_slice(json_data).ok()?;
+        let method = request.get("method")?.as_str()?;
+        let ttl = *self.ttls.get(method)?;
+        let id = match request.get("id") {
+            None | Some(&JsonValue::Null) => return None,
+            Some(id) => id.clone(),
+        };
+        let params = serde_json::to_string(&request["params"]).ok()?;
+        Some(((method.to_owned(), params), id, ttl))
+    }
+}
+
+impl<T: Transport> Transport for CachingTransport<T> {
+    type Future = CachedFuture<T::Future>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let (key, id, ttl) = match self.cacheable(&json_data) {
+            Some(cacheable) => cacheable,
+            None => return CachedFuture::sending(self.transport.send(json_data), None),
+        };
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let now = Instant::now();
+            match entries.get(&key) {
+                Some(entry) if entry.expires > now => {
+                    trace!("Answering call to {} from the cache", key.0);
+                    let mut response = entry.response.clone();
+                    response["id"] = id;
+                    return CachedFuture {
+                        state: State::Cached(serde_json::to_vec(&response).ok()),
+                    };
+                }
+                Some(_) => {
+                    entries.remove(&key);
+                }
+                None => (),
+            }
+        }
+        let store = Store {
+            entries: self.entries.clone(),
+            key,
+            ttl,
+        };
+        CachedFuture::sending(self.transport.send(json_data), Some(store))
+    }
+}
+
+
+/// Where to put the response of a call to a cached method.
+struct Store {
+    entries: Entries,
+    key: Key,
+    ttl: Duration,
+}
+
+impl Store {
+    fn insert(self, response: &[u8]) {
+        let response: JsonValue = match serde_json::from_slice(response) {
+            Ok(response) => response,
+            Err(_) => return,
+        };
+        if response.get("result").is_none() || response.get("error").is_some() {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.expires > now);
+        entries.insert(
+            self.key,
+            Entry {
+                response,
+                expires: now + self.ttl,
+            },
+        );
+    }
+}
+
+
+enum State<F> {
+    Cached(Option<Vec<u8>>),
+    Sending { future: F, store: Option<Store> },
+}
+
+/// The future returned by `CachingTransport` send operations.
+pub struct CachedFuture<F> {
+    state: State<F>,
+}
+
+impl<F> CachedFuture<F> {
+    fn sending(future: F, store: Option<Store>) -> Self {
+        CachedFuture {
+            state: State::Sending { future, store },
+        }
+    }
+}
+
+impl<F: Future<Item = Vec<u8>>> Future for CachedFuture<F> {
+    type Item = Vec<u8>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Vec<u8>, F::Error> {
+        match self.state {
+            State::Cached(ref mut response) => Ok(Async::Ready(
+                response.take().expect("CachedFuture polled after completion"),
+            )),
+            State::Sending {
+                ref mut future,
+                ref mut store,
+            } => {
+                let response = try_ready!(future.poll());
+                if let Some(store) = store.take() {
+                    store.insert(&response);
+                }
+                Ok(Async::Ready(response))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util::EchoParamsTransport;
+
+    jsonrpc_client!(pub struct NodeClient {
+        #[rpc(idempotent, cache_ttl = 60)]
+        pub fn block_hash(&self, height: u64) -> RpcRequest<u64>;
+        pub fn balance(&self, account: u64) -> RpcRequest<u64>;
+        pub fn fail(&self, value: u64) -> RpcRequest<u64>;
+    });
+
+    #[test]
+    fn caches_methods_with_ttl() {
+        let methods = NodeClient::<EchoParamsTransport>::METHODS;
+        assert_eq!(Some(60), methods[0].cache_ttl_secs);
+        assert_eq!(None, methods[1].cache_ttl_secs);
+
+        let inner = EchoParamsTransport::default();
+        let client = NodeClient::new(CachingTransport::new(inner.clone()).methods(methods));
+        assert_eq!(5, client.block_hash(5).call().unwrap());
+        assert_eq!(5, client.block_hash(5).call().unwrap());
+        assert_eq!(1, inner.sent());
+        assert_eq!(6, client.block_hash(6).call().unwrap());
+        assert_eq!(2, inner.sent());
+
+        assert_eq!(7, client.balance(7).call().unwrap());
+        assert_eq!(7, client.balance(7).call().unwrap());
+        assert_eq!(4, inner.sent());
+    }
+
+    #[test]
+    fn expires_and_skips_errors() {
+        let inner = EchoParamsTransport::default();
+        let transport = CachingTransport::new(inner.clone())
+            .ttl("balance", Duration::from_millis(50))
+            .ttl("fail", Duration::from_secs(60));
+        let client = NodeClient::new(transport);
+        client.balance(1).call().unwrap();
+        client.balance(1).call().unwrap();
+        assert_eq!(1, inner.sent());
+        ::std::thread::sleep(Duration::from_millis(60));
+        client.balance(1).call().unwrap();
+        assert_eq!(2, inner.sent());
+
+        client.fail(1).call().unwrap_err();
+        client.fail(1).call().unwrap_err();
+        assert_eq!(4, inner.sent());
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+pub use self::imp::CallSpan;
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use Error;
+    use jsonrpc_core::types::Id;
+    use tracing::{field, info_span, Span};
+
+    #[derive(Clone)]
+    pub struct CallSpan(Span);
+
+    impl CallSpan {
+        pub fn new<E: FnOnce() -> Option<String>>(method: &str, id: &Id, endpoint: E) -> Self {
+            let span = info_span!(
+                "rpc_call",
+                method = method,
+                id = ?id,
+                endpoint = field::Empty,
+                outcome = field::Empty,
+                error = field::Empty
+            );
+            if let Some(endpoint) = endpoint() {
+                span.record("endpoint", &endpoint.as_str());
+            }
+            CallSpan(span)
+        }
+
+        pub fn in_scope<F: FnOnce() -> R, R>(&self, f: F) -> R {
+            self.0.in_scope(f)
+        }
+
+        pub fn record_success(&self) {
+            self.0.record("outcome", &"success");
+        }
+
+        pub fn record_failure(&self, error: &Error) {
+            self.0.record("outcome", &"failure");
+            self.0.record("error", &field::display(error));
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use Error;
+    use jsonrpc_core::types::Id;
+
+    #[derive(Clone)]
+    pub struct CallSpan;
+
+    impl CallSpan {
+        pub fn new<E: FnOnce() -> Option<String>>(_method: &str, _id: &Id, _endpoint: E) -> Self {
+            CallSpan
+        }
+
+        pub fn in_scope<F: FnOnce() -> R, R>(&self, f: F) -> R {
+            f()
+        }
+
+        pub fn record_success(&self) {}
+
+        pub fn record_failure(&self, _error: &Error) {}
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper that stops sending requests to a transport that keeps failing.
+//!
+//! The [`CircuitBreaker`] starts out closed and forwards all requests. After a configured number
+//! of consecutive transport failures it trips and becomes open. While open, all requests fail
+//! right away with [`CircuitBreakerError::Open`] without touching the inner transport. When the
+//! cool-down period has passed the breaker becomes half-open and lets a single probe request
+//! through. If the probe succeeds the breaker closes again, if it fails the breaker opens for
+//! another cool-down period.
+//!
+//! [`CircuitBreaker`]: struct.CircuitBreaker.html
+//! [`CircuitBreakerError::Open`]: enum.CircuitBreakerError.html#variant.Open
+
+use Transport;
+use futures::{Async, Future, Poll};
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+/// The state of a `CircuitBreaker`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are forwarded to the inner transport.
+    Closed,
+    /// Requests are rejected until the cool-down period is over.
+    Open,
+    /// The cool-down period is over and a probe request decides if the circuit closes again.
+    HalfOpen,
+}
+
+/// Error returned by `CircuitBreaker` send operations.
+#[derive(Debug)]
+pub enum CircuitBreakerError<E> {
+    /// The circuit is open, the request was never sent.
+    Open,
+    /// The inner transport failed.
+    Transport(E),
+}
+
+impl<E: error::Error> fmt::Display for CircuitBreakerError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CircuitBreakerError::Open => write!(f, "Circuit breaker is open, request not sent"),
+            CircuitBreakerError::Transport(ref e) => write!(f, "Transport error: {}", e),
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for CircuitBreakerError<E> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            CircuitBreakerError::Open => None,
+            CircuitBreakerError::Transport(ref e) => Some(e),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+enum Circuit {
+    Closed { consecutive_failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probe_in_flight: bool },
+}
+
+#[derive(Debug)]
+struct Breaker {
+    circuit: Circuit,
+    failure_threshold: u32,
+    cool_down: Duration,
+}
+
+impl Breaker {
+    /// Decides what to do with a new request. Returns `None` if the request should be rejected,
+    /// or `Some(is_probe)` if it should be sent.
+    fn admit(&mut self) -> Option<bool> {
+        match self.circuit {
+            Circuit::Closed { .. } => Some(false),
+            Circuit::Open { until } if Instant::now() >= until => {
+                debug!("Circuit breaker cool-down over, sending probe request");
+                self.circuit = Circuit::HalfOpen {
+                    probe_in_flight: true,
+                };
+                Some(true)
+            }
+            Circuit::HalfOpen {
+                probe_in_flight: false,
+            } => {
+                self.circuit = Circuit::HalfOpen {
+                    probe_in_flight: true,
+                };
+                Some(true)
+            }
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => None,
+        }
+    }
+
+    fn record_success(&mut self) {
+        if let Circuit::HalfOpen { .. } = self.circuit {
+            debug!("Circuit breaker probe succeeded, closing circuit");
+        }
+        self.circuit = Circuit::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    fn record_failure(&mut self, probe: bool) {
+        let trip = match self.circuit {
+            Circuit::Closed {
+                ref mut consecutive_failures,
+            } => {
+                *consecutive_failures += 1;
+                *consecutive_failures >= self.failure_threshold
+            }
+            Circuit::HalfOpen { .. } => probe,
+            Circuit::Open { .. } => false,
+        };
+        if trip {
+            warn!("Circuit breaker tripped, rejecting requests for {:?}", self.cool_down);
+            self.circuit = Circuit::Open {
+                until: Instant::now() + self.cool_down,
+            };
+        }
+    }
+
+    /// Called when a probe request is dropped before completing. Lets the next request probe.
+    fn release_probe(&mut self) {
+        if let Circuit::HalfOpen { .. } = self.circuit {
+            self.circuit = Circuit::HalfOpen {
+                probe_in_flight: false,
+            };
+        }
+    }
+
+    fn state(&self) -> CircuitState {
+        match self.circuit {
+            Circuit::Closed { .. } => CircuitState::Closed,
+            Circuit::Open { until } if Instant::now() >= until => CircuitState::HalfOpen,
+            Circuit::Open { .. } => CircuitState::Open,
+            Circuit::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+
+/// A transport wrapper failing fast while its inner transport is considered dead.
+///
+/// Clones share the same circuit state.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker<T> {
+    transport: T,
+    breaker: Arc<Mutex<Breaker>>,
+}
+
+impl<T: Transport> CircuitBreaker<T> {
+    /// Wraps `transport` in a circuit breaker that trips after `failure_threshold` consecutive
+    /// failures and stays open for `cool_down` before probing the transport again.
+    pub fn new(transport: T, failure_threshold: u32, cool_down: Duration) -> Self {
+        CircuitBreaker {
+            transport,
+            breaker: Arc::new(Mutex::new(Breaker {
+                circuit: Circuit::Closed {
+                    consecutive_failures: 0,
+                },
+                failure_threshold: ::std::cmp::max(failure_threshold, 1),
+                cool_down,
+            })),
+        }
+    }
+
+    /// Returns the current state of the circuit.
+    pub fn state(&self) -> CircuitState {
+        self.breaker.lock().unwrap().state()
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport> Transport for CircuitBreaker<T> {
+    type Future = CircuitBreakerFuture<T::Future>;
+    type Error = CircuitBreakerError<T::Error>;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let admission = self.breaker.lock().unwrap().admit();
+        let inner = admission.map(|probe| InnerFuture {
+            future: self.transport.send(json_data),
+            breaker: self.breaker.clone(),
+            probe,
+            done: false,
+        });
+        CircuitBreakerFuture(inner)
+    }
+}
+
+
+/// The future returned by `CircuitBreaker` send operations.
+pub struct CircuitBreakerFuture<F>(Option<InnerFuture<F>>);
+
+struct InnerFuture<F> {
+    future: F,
+    breaker: Arc<Mutex<Breaker>>,
+    probe: bool,
+    done: bool,
+}
+
+impl<F: Future> Future for CircuitBreakerFuture<F> {
+    type Item = F::Item;
+    type Error = CircuitBreakerError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = match self.0 {
+            Some(ref mut inner) => inner,
+            None => return Err(CircuitBreakerError::Open),
+        };
+        let result = inner.future.poll();
+        match result {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(_)) => inner.breaker.lock().unwrap().record_success(),
+            Err(_) => inner.breaker.lock().unwrap().record_failure(inner.probe),
+        }
+        inner.done = true;
+        result.map_err(CircuitBreakerError::Transport)
+    }
+}
+
+impl<F> Drop for InnerFuture<F> {
+    fn drop(&mut self) {
+        if self.probe && !self.done {
+            if let Ok(mut breaker) = self.breaker.lock() {
+                breaker.release_probe();
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use test_util::SwitchTransport;
+
+    #[test]
+    fn trips_and_recovers() {
+        let transport = SwitchTransport::default();
+        let breaker = CircuitBreaker::new(transport.clone(), 2, Duration::from_millis(20));
+
+        transport.failing.store(true, Ordering::SeqCst);
+        assert!(breaker.send(vec![]).wait().is_err());
+        assert_eq!(CircuitState::Closed, breaker.state());
+        assert!(breaker.send(vec![]).wait().is_err());
+        assert_eq!(CircuitState::Open, breaker.state());
+
+        match breaker.send(vec![]).wait() {
+            Err(CircuitBreakerError::Open) => (),
+            _ => panic!("Request not rejected by open circuit"),
+        }
+        assert_eq!(2, transport.sent());
+
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(CircuitState::HalfOpen, breaker.state());
+        transport.failing.store(false, Ordering::SeqCst);
+        assert!(breaker.send(vec![]).wait().is_ok());
+        assert_eq!(CircuitState::Closed, breaker.state());
+        assert_eq!(3, transport.sent());
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let transport = SwitchTransport::default();
+        transport.failing.store(true, Ordering::SeqCst);
+        let breaker = CircuitBreaker::new(transport.clone(), 1, Duration::from_millis(20));
+
+        assert!(breaker.send(vec![]).wait().is_err());
+        thread::sleep(Duration::from_millis(30));
+        let probe = breaker.send(vec![]);
+        match breaker.send(vec![]).wait() {
+            Err(CircuitBreakerError::Open) => (),
+            _ => panic!("Second request let through while probing"),
+        }
+        assert!(probe.wait().is_err());
+        assert_eq!(CircuitState::Open, breaker.state());
+    }
+
+    #[test]
+    fn dropped_probe_is_released() {
+        let transport = SwitchTransport::default();
+        transport.failing.store(true, Ordering::SeqCst);
+        let breaker = CircuitBreaker::new(transport.clone(), 1, Duration::from_millis(1));
+
+        assert!(breaker.send(vec![]).wait().is_err());
+        thread::sleep(Duration::from_millis(5));
+        drop(breaker.send(vec![]));
+        transport.failing.store(false, Ordering::SeqCst);
+        assert!(breaker.send(vec![]).wait().is_ok());
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use {call_method, take_buffer, ClientConfig, Error, ErrorKind, FromTransport, RpcRequest,
+     Transport};
+use boxed::BoxTransport;
+use error::ResultExt;
+use futures::{Async, Future, Poll};
+use jsonrpc_core::types::Version;
+use pubsub::{self, PubSubTransport, SubscribeRequest};
+use request::Request;
+use serde;
+use std::sync::Arc;
+
+
+/// A ready made client calling methods by name. Useful for scripting and exploring an API before
+/// defining a typed client with the `jsonrpc_client` macro.
+///
+/// ```rust,ignore
+/// let mut client = RpcClient::new(transport);
+/// let sum: u64 = client.call("add", (2, 3)).call()?;
+/// client.notify("log", ("Added numbers",)).wait()?;
+/// ```
+///
+/// Clones share the transport, like the clones of generated clients. Without a type parameter,
+/// `RpcClient` is the type-erased client on a `BoxTransport`.
+#[derive(Debug)]
+pub struct RpcClient<T: Transport = BoxTransport> {
+    transport: Arc<T>,
+    config: ClientConfig,
+}
+
+impl<T: Transport> RpcClient<T> {
+    /// Creates a new RPC client backed by the given transport implementation.
+    pub fn new(transport: T) -> Self {
+        Self::with_config(transport, ClientConfig::default())
+    }
+
+    /// Creates a new RPC client backed by the given transport implementation and using the given
+    /// configuration.
+    pub fn with_config(transport: T, config: ClientConfig) -> Self {
+        RpcClient {
+            transport: Arc::new(transport),
+            config,
+        }
+    }
+
+    /// Calls the RPC method `method` with the given parameters and deserializes the result into
+    /// `R`. Parameters are serialized as described for
+    /// [`call_method`](fn.call_method.html).
+    pub fn call<P, R>(&mut self, method: &str, params: P) -> RpcRequest<R, T::Future>
+    where
+        P: serde::Serialize,
+        R: serde::de::DeserializeOwned + Send + 'static,
+    {
+        call_method(
+            &*self.transport,
+            &self.config,
+            method.to_owned(),
+            params,
+        )
+    }
+
+    /// Sends a notification, a request without id that the server does not reply to, to the
+    /// RPC method `method`. The returned future completes when the transport is done sending it.
+    /// Anything the server responds with is ignored.
+    pub fn notify<P>(&mut self, method: &str, params: P) -> RpcNotification<T::Future>
+    where
+        P: serde::Serialize,
+    {
+        trace!("Serializing notification to method \"{}\"", method);
+        let notification = Request {
+            version: Version::V2,
+            method,
+            params: &params,
+            id: None,
+            empty_params: self.config.empty_params,
+        };
+        let mut notification_raw = take_buffer(&self.config);
+        match notification
+            .serialize_into(&mut notification_raw)
+            .chain_err(|| ErrorKind::SerializeError)
+        {
+            Ok(()) => RpcNotification(Ok(self.transport.send(notification_raw))),
+            Err(e) => {
+                if let Some(ref buffer_pool) = self.config.buffer_pool {
+                    buffer_pool.put(notification_raw);
+                }
+                RpcNotification(Err(Some(e)))
+            }
+        }
+    }
+
+    /// Returns a reference to the transport of this client.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: PubSubTransport> RpcClient<T> {
+    /// Subscribes with the RPC method `method` and resolves to a stream of the notifications of
+    /// the subscription, deserialized into `N`. See the [`pubsub`](pubsub/index.html) module.
+    pub fn subscribe<P, N>(&self, method: &str, params: P) -> SubscribeRequest<N, T::Future>
+    where
+        P: serde::Serialize,
+        N: serde::de::DeserializeOwned,
+    {
+        pubsub::subscribe(&*self.transport, &self.config, method.to_owned(), params)
+    }
+}
+
+impl<T: Transport> Clone for RpcClient<T> {
+    fn clone(&self) -> Self {
+        RpcClient {
+            transport: self.transport.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<T: Transport> FromTransport<T> for RpcClient<T> {
+    fn from_transport(transport: T, config: ClientConfig) -> Self {
+        Self::with_config(transport, config)
+    }
+}
+
+
+/// A lazy RPC notification `Future`. Created by
+/// [`RpcClient::notify`](struct.RpcClient.html#method.notify).
+pub struct RpcNotification<F>(::std::result::Result<F, Option<Error>>);
+
+impl<E, F> Future for RpcNotification<F>
+where
+    E: ::std::error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = E>,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        match self.0 {
+            Ok(ref mut future) => {
+                try_ready!(future.poll().chain_err(|| ErrorKind::TransportError));
+                Ok(Async::Ready(()))
+            }
+            Err(ref mut error_option) => Err(error_option
+                .take()
+                .expect("Cannot call RpcNotification poll twice when in error state")),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{self, FutureResult};
+    use serde_json::{self, Value as JsonValue};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    /// Records all requests and responds with a result of 7.
+    #[derive(Clone, Default)]
+    struct RecordingTransport(Arc<Mutex<Vec<JsonValue>>>);
+
+    impl Transport for RecordingTransport {
+        type Future = FutureResult<Vec<u8>, io::Error>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            1
+        }
+
+        fn send(&self, json_data: Vec<u8>) -> Self::Future {
+            let request = serde_json::from_slice(&json_data).unwrap();
+            self.0.lock().unwrap().push(request);
+            future::ok(br#"{"jsonrpc": "2.0", "id": 1, "result": 7}"#.to_vec())
+        }
+    }
+
+    #[test]
+    fn call() {
+        let transport = RecordingTransport::default();
+        let mut client = RpcClient::new(transport.clone());
+        let result: u64 = client.call("add", (3, 4)).call().unwrap();
+        assert_eq!(7, result);
+        assert_eq!(
+            vec![json!({"jsonrpc": "2.0", "id": 1, "method": "add", "params": [3, 4]})],
+            *transport.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn notify() {
+        let transport = RecordingTransport::default();
+        let mut client = RpcClient::new(transport.clone());
+        client.notify("log", ("Hello",)).wait().unwrap();
+        assert_eq!(
+            vec![json!({"jsonrpc": "2.0", "method": "log", "params": ["Hello"]})],
+            *transport.0.lock().unwrap()
+        );
+    }
+}
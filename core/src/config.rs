@@ -0,0 +1,168 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use BufferPool;
+use instrument::Instrumentation;
+use pubsub::Overflow;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+
+/// Configuration of a client generated by the `jsonrpc_client` macro. Applies to all calls made
+/// through the client, independent of the transport.
+///
+/// Given to the generated `with_config` constructor.
+#[derive(Clone, Default)]
+pub struct ClientConfig {
+    pub(crate) instrumentation: Option<Arc<Instrumentation>>,
+    pub(crate) validation: Validation,
+    pub(crate) buffer_pool: Option<BufferPool>,
+    pub(crate) missing_result_as_null: bool,
+    pub(crate) empty_params: Option<EmptyParams>,
+    pub(crate) params_style: ParamsStyle,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) subscription_buffer: Option<(usize, Overflow)>,
+}
+
+impl ClientConfig {
+    /// Creates a new configuration with all settings at their defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reports all calls made with this configuration to `instrumentation`.
+    pub fn instrumentation<I: Instrumentation + 'static>(mut self, instrumentation: I) -> Self {
+        self.instrumentation = Some(Arc::new(instrumentation));
+        self
+    }
+
+    /// Sets how strictly responses are checked against the JSON-RPC 2.0 specification. Defaults
+    /// to `Validation::Lenient`.
+    pub fn validation(mut self, validation: Validation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Accept responses with neither `result` nor `error` as if the result was `null`, for
+    /// servers that leave out the result of methods returning nothing. Such calls then succeed
+    /// if the method returns `()` or an `Option`. Off by default.
+    pub fn missing_result_as_null(mut self, missing_result_as_null: bool) -> Self {
+        self.missing_result_as_null = missing_result_as_null;
+        self
+    }
+
+    /// Sets whether the methods of generated clients send their arguments as an array or as an
+    /// object keyed by the declared argument names. Defaults to `ParamsStyle::Positional`. Does
+    /// not affect methods called by name.
+    pub fn params_style(mut self, params_style: ParamsStyle) -> Self {
+        self.params_style = params_style;
+        self
+    }
+
+    /// Sets how calls without parameters send their `params`. By default the parameters are sent
+    /// as they serialize, which is `[]` for methods without arguments in generated clients.
+    ///
+    /// Parameters count as empty if they are `null` or an array or object known to be empty when
+    /// serialization starts.
+    pub fn empty_params(mut self, empty_params: EmptyParams) -> Self {
+        self.empty_params = Some(empty_params);
+        self
+    }
+
+    /// Gives every call a deadline `timeout` after it is made. Calls still running at their
+    /// deadline fail with `ErrorKind::DeadlineExceeded`, see the
+    /// [`deadline`](deadline/index.html) module. No timeout by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Buffers at most `capacity` notifications of every subscription that have not been
+    /// consumed yet, handling notifications arriving while the buffer is full as `overflow`
+    /// says, see the [`pubsub`](pubsub/index.html) module. Unbounded by default.
+    pub fn subscription_buffer(mut self, capacity: usize, overflow: Overflow) -> Self {
+        self.subscription_buffer = Some((capacity, overflow));
+        self
+    }
+
+    /// Serializes requests into buffers taken from `buffer_pool` and returns response buffers to
+    /// it, instead of allocating new buffers for every call.
+    pub fn buffer_pool(mut self, buffer_pool: BufferPool) -> Self {
+        self.buffer_pool = Some(buffer_pool);
+        self
+    }
+}
+
+impl fmt::Debug for ClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientConfig")
+            .field("instrumentation", &self.instrumentation.is_some())
+            .field("validation", &self.validation)
+            .field("buffer_pool", &self.buffer_pool)
+            .field("missing_result_as_null", &self.missing_result_as_null)
+            .field("empty_params", &self.empty_params)
+            .field("params_style", &self.params_style)
+            .field("timeout", &self.timeout)
+            .field("subscription_buffer", &self.subscription_buffer)
+            .finish()
+    }
+}
+
+
+/// How strictly responses are checked before the result is deserialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// Responses must follow the JSON-RPC 2.0 specification. They must have a `jsonrpc` member
+    /// equal to `"2.0"`, no members besides `jsonrpc`, `id`, `result` and `error`, and exactly
+    /// one of `result` and `error`.
+    Strict,
+    /// Tolerates responses from servers that don't quite follow the specification. A missing
+    /// `jsonrpc` member, unknown members and an `error` member that is `null` are all accepted.
+    /// This is the default.
+    ///
+    /// Calls to JSON-RPC 1.0 methods are always validated leniently, since 1.0 responses have
+    /// no `jsonrpc` member and an `error` member that is `null` on success.
+    Lenient,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Validation::Lenient
+    }
+}
+
+
+/// How calls without parameters send their `params`. Some servers reject one form or the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyParams {
+    /// Send `"params": []`.
+    Array,
+    /// Send `"params": {}`.
+    Object,
+    /// Leave out the `params` member, which the JSON-RPC 2.0 specification allows.
+    Omit,
+}
+
+
+/// How the methods of generated clients send their arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamsStyle {
+    /// Send the arguments as an array, in the order they are declared. `fn add(a: u64, b: u64)`
+    /// is called with `"params": [1, 2]`.
+    Positional,
+    /// Send the arguments as an object keyed by their declared names. `fn add(a: u64, b: u64)`
+    /// is called with `"params": {"a": 1, "b": 2}`.
+    Named,
+}
+
+impl Default for ParamsStyle {
+    fn default() -> Self {
+        ParamsStyle::Positional
+    }
+}
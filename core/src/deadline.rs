@@ -0,0 +1,201 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Deadlines limiting how long a call may take in total.
+//!
+//! A call gets a deadline from the `timeout` of its [`ClientConfig`], or from an enclosing
+//! [`with_deadline`], whichever is earlier. The deadline covers the call from start to end,
+//! including any retries done by transport wrappers. A call still running at its deadline is
+//! cancelled and fails with `ErrorKind::DeadlineExceeded`.
+//!
+//! The error holds the time that was left of the deadline when the call gave up. It is zero when
+//! the call spent all of its time waiting for the server. It is more than zero when a transport
+//! wrapper gave up early, like a [`RetryTransport`] not waiting for a backoff that would end
+//! after the deadline. Callers can tell a slow server from a budget too small for their retry
+//! policy this way.
+//!
+//! Transport wrappers that wait, retry or resend can read the deadline of the call being sent
+//! with [`Deadline::current`] from within `Transport::send`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::deadline::with_deadline;
+//! use std::time::{Duration, Instant};
+//!
+//! let config = ClientConfig::new().timeout(Duration::from_secs(10));
+//! let mut client = FizzBuzzClient::with_config(transport, config);
+//!
+//! // Sent with a deadline in ten seconds.
+//! client.fizz_buzz(3).call()?;
+//! // Sent with a deadline in one second.
+//! let deadline = Instant::now() + Duration::from_secs(1);
+//! with_deadline(deadline, || client.fizz_buzz(5)).call()?;
+//! ```
+//!
+//! [`ClientConfig`]: ../struct.ClientConfig.html
+//! [`with_deadline`]: fn.with_deadline.html
+//! [`RetryTransport`]: ../retry/struct.RetryTransport.html
+//! [`Deadline::current`]: struct.Deadline.html#method.current
+
+use std::cell::RefCell;
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// The deadline of the call being sent, or of the enclosing `with_deadline`.
+    static CURRENT: RefCell<Option<Deadline>> = RefCell::new(None);
+}
+
+
+/// The deadline of a call. Shared between the call and the transport wrappers handling it, so
+/// they can report why they gave up.
+#[derive(Debug, Clone)]
+pub struct Deadline {
+    at: Instant,
+    gave_up: Arc<Mutex<Option<Duration>>>,
+}
+
+impl Deadline {
+    /// Creates a deadline at the given instant.
+    pub fn new(at: Instant) -> Self {
+        Deadline {
+            at,
+            gave_up: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the deadline of the call currently being sent, when called by a transport from
+    /// within `Transport::send`. Returns `None` for calls without a deadline.
+    pub fn current() -> Option<Deadline> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Returns the instant of the deadline.
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+
+    /// Returns the time left until the deadline, or zero if it has passed.
+    pub fn remaining(&self) -> Duration {
+        let now = Instant::now();
+        if now < self.at {
+            self.at - now
+        } else {
+            Duration::new(0, 0)
+        }
+    }
+
+    /// Returns true if the deadline has passed.
+    pub fn is_exceeded(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    /// Records that a transport wrapper gave up on the call because what it would do next could
+    /// not finish before the deadline. The error the transport fails with is then reported as
+    /// `ErrorKind::DeadlineExceeded`, holding the time that was left.
+    pub fn give_up(&self) {
+        let remaining = self.remaining();
+        *self.gave_up.lock().unwrap() = Some(remaining);
+    }
+
+    /// Returns the time that was left when a transport wrapper gave up, if one did.
+    pub(crate) fn gave_up(&self) -> Option<Duration> {
+        *self.gave_up.lock().unwrap()
+    }
+}
+
+
+/// Runs `f` with `deadline` as the deadline of all calls created within it, unless they have an
+/// earlier deadline of their own. Nested calls keep the earliest deadline.
+pub fn with_deadline<F: FnOnce() -> R, R>(deadline: Instant, f: F) -> R {
+    let deadline = match Deadline::current() {
+        Some(ref current) if current.at <= deadline => current.clone(),
+        _ => Deadline::new(deadline),
+    };
+    in_scope(Some(deadline), f)
+}
+
+/// Returns the deadline of a call starting now, the earliest of the enclosing deadline, if any,
+/// and `timeout` from now.
+pub(crate) fn for_call(timeout: Option<Duration>) -> Option<Deadline> {
+    let from_timeout = timeout.map(|timeout| Instant::now() + timeout);
+    let from_scope = Deadline::current().map(|deadline| deadline.at);
+    let at = match (from_timeout, from_scope) {
+        (Some(a), Some(b)) => Some(cmp::min(a, b)),
+        (a, b) => a.or(b),
+    };
+    at.map(Deadline::new)
+}
+
+/// Runs `f` with `deadline` as the current deadline, restoring the previous one afterwards.
+pub(crate) fn in_scope<F: FnOnce() -> R, R>(deadline: Option<Deadline>, f: F) -> R {
+    struct Restore(Option<Deadline>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let previous = CURRENT.with(|current| current.replace(deadline));
+    let _restore = Restore(previous);
+    f()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {ClientConfig, ErrorKind};
+    use test_util::SilentTransport;
+
+    jsonrpc_client!(pub struct SilentClient {
+        pub fn ping(&mut self) -> RpcRequest<bool>;
+    });
+
+    #[test]
+    fn deadline_exceeded() {
+        let config = ClientConfig::new().timeout(Duration::from_secs(60));
+        let client = SilentClient::with_config(SilentTransport::default(), config);
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let error = with_deadline(deadline, || client.ping()).call().unwrap_err();
+        assert!(Instant::now() >= deadline);
+        assert_eq!(ErrorKind::DeadlineExceeded(Duration::new(0, 0)), *error.kind());
+    }
+
+    #[test]
+    fn earliest_deadline_wins() {
+        let soon = Instant::now() + Duration::from_secs(1);
+        let later = soon + Duration::from_secs(10);
+        assert!(Deadline::current().is_none());
+        with_deadline(soon, || {
+            with_deadline(later, || {
+                assert_eq!(soon, Deadline::current().unwrap().at());
+                let deadline = for_call(Some(Duration::from_secs(60))).unwrap();
+                assert_eq!(soon, deadline.at());
+            });
+            let deadline = for_call(Some(Duration::from_millis(1))).unwrap();
+            assert!(deadline.at() < soon);
+        });
+        assert!(Deadline::current().is_none());
+        assert!(for_call(None).is_none());
+    }
+
+    #[test]
+    fn give_up_records_remaining_time() {
+        let deadline = Deadline::new(Instant::now() + Duration::from_secs(60));
+        assert_eq!(None, deadline.clone().gave_up());
+        deadline.clone().give_up();
+        let remaining = deadline.gave_up().unwrap();
+        assert!(remaining > Duration::from_secs(50));
+        assert!(!deadline.is_exceeded());
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tracking of calls to RPC methods that are deprecated on the server.
+//!
+//! Methods are marked as deprecated with `#[rpc(deprecated_since = "x.y", note = "...")]` in the
+//! `jsonrpc_client` macro. The first call to such a method logs a warning, and every call is
+//! counted. The counters can be read with [`deprecated_calls`] to find out what deprecated APIs
+//! are still in use before they are removed from the server.
+//!
+//! [`deprecated_calls`]: fn.deprecated_calls.html
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref DEPRECATED_CALLS: Mutex<HashMap<&'static str, u64>> = Mutex::new(HashMap::new());
+}
+
+
+/// Records a call to the deprecated RPC method `method`, named as sent to the server. Logs a
+/// warning the first time a method is called.
+///
+/// # Not intended for direct use
+/// This is being called from the client structs generated by the `jsonrpc_client` macro.
+#[doc(hidden)]
+pub fn deprecated_call(method: &'static str, since: &'static str, note: Option<&'static str>) {
+    let mut calls = DEPRECATED_CALLS.lock().unwrap();
+    let count = calls.entry(method).or_insert(0);
+    if *count == 0 {
+        match note {
+            Some(note) => warn!(
+                "Calling RPC method \"{}\", deprecated since {}: {}",
+                method,
+                since,
+                note
+            ),
+            None => warn!(
+                "Calling RPC method \"{}\", deprecated since {}",
+                method,
+                since
+            ),
+        }
+    }
+    *count += 1;
+}
+
+/// Returns how many times the deprecated RPC method `method` has been called in this process.
+/// Methods are named as sent to the server, including the prefix of their client.
+pub fn deprecated_call_count(method: &str) -> u64 {
+    DEPRECATED_CALLS
+        .lock()
+        .unwrap()
+        .get(method)
+        .cloned()
+        .unwrap_or(0)
+}
+
+/// Returns the number of calls to each deprecated RPC method that has been called at least once
+/// in this process.
+pub fn deprecated_calls() -> HashMap<&'static str, u64> {
+    DEPRECATED_CALLS.lock().unwrap().clone()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use golden::RecordingTransport;
+
+    jsonrpc_client!(pub struct DeprecatedClient {
+        /// Deprecated with a note.
+        #[rpc(deprecated_since = "1.2", note = "Use new_add instead")]
+        pub fn old_add(&mut self, a: u64, b: u64) -> RpcRequest<u64>;
+
+        #[rpc(deprecated_since = "0.9")]
+        #[allow(dead_code)]
+        pub fn older_add(&mut self, a: u64, b: u64) -> RpcRequest<u64>;
+
+        pub fn new_add(&mut self, a: u64, b: u64) -> RpcRequest<u64>;
+    });
+
+    jsonrpc_client!(pub struct FirstClient (prefix = "first_") {
+        #[rpc(deprecated_since = "1.0")]
+        pub fn sub(&mut self, a: u64, b: u64) -> RpcRequest<u64>;
+    });
+
+    jsonrpc_client!(pub struct SecondClient (prefix = "second_") {
+        #[rpc(deprecated_since = "1.0")]
+        pub fn sub(&mut self, a: u64, b: u64) -> RpcRequest<u64>;
+    });
+
+    #[test]
+    fn counts_deprecated_calls() {
+        let transport = RecordingTransport::new();
+        let client = DeprecatedClient::new(transport.clone());
+        let _ = client.old_add(1, 2);
+        let _ = client.old_add(3, 4);
+        let _ = client.new_add(5, 6);
+
+        assert_eq!(2, deprecated_call_count("old_add"));
+        assert_eq!(0, deprecated_call_count("older_add"));
+        assert_eq!(0, deprecated_call_count("new_add"));
+        assert_eq!(Some(&2), deprecated_calls().get("old_add"));
+        assert_eq!(3, transport.requests().len());
+    }
+
+    #[test]
+    fn counts_prefixed_methods_apart() {
+        let transport = RecordingTransport::new();
+        let _ = FirstClient::new(transport.clone()).sub(2, 1);
+        let _ = SecondClient::new(transport.clone()).sub(2, 1);
+        let _ = SecondClient::new(transport).sub(4, 1);
+
+        assert_eq!(1, deprecated_call_count("first_sub"));
+        assert_eq!(2, deprecated_call_count("second_sub"));
+        assert_eq!(0, deprecated_call_count("sub"));
+    }
+}
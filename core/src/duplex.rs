@@ -0,0 +1,1060 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport sending many concurrent calls over one persistent connection.
+//!
+//! Calling a method on a generated client does not wait for the response. Every returned
+//! `RpcRequest` completes on its own, so any number of calls can be in flight at once. Transports
+//! doing one request per call, like HTTP, get this for free. Over a single persistent connection,
+//! like a WebSocket, TCP or IPC stream, responses can arrive in any order and must be routed back
+//! to the right call.
+//!
+//! A [`DuplexTransport`] does that routing on top of any connection exposed as a `Sink` of
+//! outgoing messages and a `Stream` of incoming messages, each message being one JSON text. It is
+//! created together with a [`Connection`] future, which writes the requests of all calls and
+//! hands every response to the call with the same id. The connection has to be spawned on an
+//! executor, or otherwise polled, for calls to make progress.
+//!
+//! Responses are matched to calls strictly by id, through a table of the calls in flight, so the
+//! server can answer in any order. The number of calls in flight can be capped with
+//! [`Connection::max_pending`], further calls then wait for a response before they are written.
+//! Responses to calls that have been dropped, and messages without a matching id, are discarded.
+//! Numeric ids also match their decimal string representation, since some servers echo numeric
+//! ids back as strings. A batch, like those sent by a [`BatchingTransport`], is answered with the
+//! array of responses the first of them is found in. Notifications do not wait for a response, they complete as soon as they
+//! are taken to be written. When the connection closes or fails, all calls in flight fail, and so
+//! does every later call.
+//!
+//! Middleboxes like NATs and proxies silently drop connections that have been idle for a while,
+//! leaving calls waiting forever for responses that never come. With
+//! [`Connection::keepalive`], the connection sends a ping message whenever nothing has been
+//! received for a while, and fails with a `TimedOut` error when nothing arrives in response.
+//! Over a WebSocket, the ping can be a call the server answers, or be mapped to a ping frame by the
+//! sink, with pong frames forwarded by the stream.
+//!
+//! `DuplexTransport` is a [`PubSubTransport`]. Notifications from the server carrying the id of a
+//! subscription made with [`pubsub::subscribe`] are routed to its stream, and the connection
+//! stays up while any such stream is alive. Servers sending the events of a subscription as
+//! further responses to the subscribe call, like Tendermint, are supported by setting the
+//! [`SubscriptionRouting`] of the connection.
+//!
+//! Some protocols, like the Language Server Protocol, have the server send requests to the
+//! client as well. Handlers for them are registered by method name with [`Connection::on_request`].
+//! The connection answers every request of the server with the result of its handler, or with a
+//! "Method not found" error if there is no handler for the method. Notifications the server
+//! pushes outside of subscriptions can be handled by method name with
+//! [`Connection::on_notification`].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::duplex::DuplexTransport;
+//!
+//! let (sink, stream) = websocket_messages.split();
+//! let (transport, connection) = DuplexTransport::new(sink, stream);
+//! core.handle().spawn(connection.map_err(|e| error!("Connection failed: {}", e)));
+//!
+//! let mut client = FizzBuzzClient::new(transport);
+//! let (a, b) = core.run(client.fizz_buzz(3).join(client.fizz_buzz(5)))?;
+//! ```
+//!
+//! [`DuplexTransport`]: struct.DuplexTransport.html
+//! [`Connection`]: struct.Connection.html
+//! [`Connection::max_pending`]: struct.Connection.html#method.max_pending
+//! [`Connection::keepalive`]: struct.Connection.html#method.keepalive
+//! [`Connection::on_request`]: struct.Connection.html#method.on_request
+//! [`Connection::on_notification`]: struct.Connection.html#method.on_notification
+//! [`PubSubTransport`]: ../pubsub/trait.PubSubTransport.html
+//! [`pubsub::subscribe`]: ../pubsub/fn.subscribe.html
+//! [`SubscriptionRouting`]: enum.SubscriptionRouting.html
+//! [`BatchingTransport`]: ../batch/struct.BatchingTransport.html
+
+use Transport;
+use pubsub::{NotificationSender, PubSubTransport};
+use response::id_key;
+use futures::future;
+use futures::sync::{mpsc, oneshot};
+use futures::{Async, AsyncSink, Future, IntoFuture, Poll, Sink, Stream};
+use jsonrpc_core;
+use serde;
+use serde::de::{Deserialize, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde_json::{self, Value as JsonValue};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use timer;
+use tokio_timer::Sleep;
+
+
+/// How a `Connection` matches notifications to the subscriptions they belong to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionRouting {
+    /// The subscribe call answers with the id of the subscription. Notifications are calls
+    /// carrying that id in `params.subscription` and the event in `params.result`, as sent by
+    /// Ethereum nodes and most other servers.
+    Params,
+    /// Events are sent as further responses to the subscribe call, with the `result` of each
+    /// being the event. Their id is the id of the subscribe call, or that id followed by
+    /// `#event`, as sent by Tendermint.
+    RequestId,
+}
+
+impl Default for SubscriptionRouting {
+    fn default() -> Self {
+        SubscriptionRouting::Params
+    }
+}
+
+
+/// A request waiting to be written to the connection.
+#[derive(Debug)]
+struct Outgoing {
+    /// The key of the request in the table of calls in flight, see `request_id`. `None` for
+    /// notifications.
+    id: Option<String>,
+    json_data: Vec<u8>,
+    response_tx: oneshot::Sender<Vec<u8>>,
+    /// Where to send the notifications of the subscription, for subscribe calls.
+    subscription: Option<NotificationSender>,
+}
+
+
+/// The future of a request handler, resolving to the result to answer the server with.
+type HandlerFuture = Box<Future<Item = JsonValue, Error = jsonrpc_core::Error> + Send>;
+
+/// Handles requests of the server, given their params.
+type RequestHandler = Box<Fn(JsonValue) -> HandlerFuture + Send>;
+
+/// Handles notifications of the server, given their params.
+type NotificationHandler = Box<Fn(JsonValue) + Send>;
+
+
+/// Keeps an idle connection alive, and detects when it is dead.
+struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    ping: Vec<u8>,
+    last_received: Instant,
+    /// When the last ping was sent, if nothing has been received since.
+    pinged_at: Option<Instant>,
+    /// If a ping is waiting to be written.
+    ping_due: bool,
+    sleep: Sleep,
+}
+
+impl Keepalive {
+    /// Checks the connection when the timer fires. Fails if a ping went unanswered.
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        loop {
+            match self.sleep.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => (),
+                Err(error) => warn!("Unable to wait for the next keepalive check: {}", error),
+            }
+            let now = Instant::now();
+            let next_check = match self.pinged_at {
+                Some(pinged_at) if now >= pinged_at + self.timeout => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "No response to keepalive ping",
+                    ));
+                }
+                Some(pinged_at) => pinged_at + self.timeout,
+                None if now >= self.last_received + self.interval => {
+                    trace!("Connection idle for {:?}, sending ping", self.interval);
+                    self.pinged_at = Some(now);
+                    self.ping_due = true;
+                    now + self.timeout
+                }
+                None => self.last_received + self.interval,
+            };
+            self.sleep = timer::sleep(next_check - now);
+        }
+    }
+
+    fn received(&mut self) {
+        self.last_received = Instant::now();
+        self.pinged_at = None;
+    }
+}
+
+
+/// A transport multiplexing calls over one connection, driven by a [`Connection`].
+///
+/// Clones share the connection and the id counter, so ids are unique among all calls on the
+/// connection.
+///
+/// [`Connection`]: struct.Connection.html
+#[derive(Debug, Clone)]
+pub struct DuplexTransport {
+    requests: mpsc::UnboundedSender<Outgoing>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl DuplexTransport {
+    /// Creates a transport writing requests to `sink` and reading responses from `stream`.
+    /// Returns the transport and the future driving the connection.
+    pub fn new<S, R>(sink: S, stream: R) -> (Self, Connection<S, R>)
+    where
+        S: Sink<SinkItem = Vec<u8>, SinkError = io::Error>,
+        R: Stream<Item = Vec<u8>, Error = io::Error>,
+    {
+        let (requests_tx, requests_rx) = mpsc::unbounded();
+        let transport = DuplexTransport {
+            requests: requests_tx,
+            next_id: Arc::new(AtomicUsize::new(1)),
+        };
+        let connection = Connection {
+            sink,
+            stream,
+            requests: Some(requests_rx),
+            buffered: None,
+            pending: HashMap::new(),
+            subscribing: HashMap::new(),
+            batches: HashMap::new(),
+            subscriptions: HashMap::new(),
+            max_pending: None,
+            keepalive: None,
+            cancel_method: None,
+            subscription_routing: SubscriptionRouting::default(),
+            request_handlers: HashMap::new(),
+            notification_handlers: HashMap::new(),
+            handling: Vec::new(),
+            replies: VecDeque::new(),
+        };
+        (transport, connection)
+    }
+}
+
+impl Transport for DuplexTransport {
+    type Future = Box<Future<Item = Vec<u8>, Error = io::Error> + Send>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        self.send_outgoing(json_data, None)
+    }
+}
+
+impl PubSubTransport for DuplexTransport {
+    fn subscribe(
+        &self,
+        json_data: Vec<u8>,
+        notifications: NotificationSender,
+    ) -> Self::Future {
+        self.send_outgoing(json_data, Some(notifications))
+    }
+}
+
+impl DuplexTransport {
+    fn send_outgoing(
+        &self,
+        json_data: Vec<u8>,
+        subscription: Option<NotificationSender>,
+    ) -> <Self as Transport>::Future {
+        let id = match request_id(&json_data) {
+            Ok(id) => id,
+            Err(e) => return Box::new(::futures::future::err(e)),
+        };
+        let (response_tx, response_rx) = oneshot::channel();
+        let outgoing = Outgoing {
+            id,
+            json_data,
+            response_tx,
+            subscription,
+        };
+        if self.requests.unbounded_send(outgoing).is_err() {
+            return Box::new(::futures::future::err(closed()));
+        }
+        Box::new(response_rx.map_err(|_| closed()))
+    }
+}
+
+
+/// The future driving the connection of a `DuplexTransport`. Writes requests and routes
+/// responses to the calls they belong to.
+///
+/// Completes when the incoming stream ends, or when all transports are dropped, no calls are in
+/// flight, no subscription streams are alive and all requests of the server have been answered.
+/// Fails with the first error of the sink or the stream.
+#[must_use = "futures do nothing unless polled"]
+pub struct Connection<S, R> {
+    sink: S,
+    stream: R,
+    requests: Option<mpsc::UnboundedReceiver<Outgoing>>,
+    buffered: Option<Vec<u8>>,
+    pending: HashMap<String, oneshot::Sender<Vec<u8>>>,
+    /// The notification senders of subscribe calls in flight, by request id.
+    subscribing: HashMap<String, NotificationSender>,
+    /// The keys of the batches in flight, by the ids of their calls.
+    batches: HashMap<String, String>,
+    /// The notification senders of active subscriptions, by subscription id as JSON text.
+    subscriptions: HashMap<String, NotificationSender>,
+    max_pending: Option<usize>,
+    keepalive: Option<Keepalive>,
+    /// The method of the notification telling the server about calls that were dropped.
+    cancel_method: Option<String>,
+    subscription_routing: SubscriptionRouting,
+    request_handlers: HashMap<String, RequestHandler>,
+    notification_handlers: HashMap<String, NotificationHandler>,
+    /// The requests of the server being handled, with their ids.
+    handling: Vec<(JsonValue, HandlerFuture)>,
+    /// Responses to requests of the server and cancellations of calls waiting to be written.
+    replies: VecDeque<Vec<u8>>,
+}
+
+impl<S, R> Connection<S, R> {
+    /// Limits the number of calls in flight, written but not yet answered, to `max_pending`.
+    /// Calls beyond that are queued and written in order as responses arrive. Unlimited by
+    /// default.
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = Some(cmp::max(max_pending, 1));
+        self
+    }
+
+    /// Writes `ping` whenever nothing has been received for `interval`, and fails the connection
+    /// with a `TimedOut` error if nothing is received within `timeout` after that. Every message
+    /// received counts as a sign of life, so `ping` can be any message the server responds to,
+    /// like a call to a method of the server. Its response is discarded. Off by default.
+    pub fn keepalive(mut self, interval: Duration, timeout: Duration, ping: Vec<u8>) -> Self {
+        self.keepalive = Some(Keepalive {
+            interval,
+            timeout,
+            ping,
+            last_received: Instant::now(),
+            pinged_at: None,
+            ping_due: false,
+            sleep: timer::sleep(interval),
+        });
+        self
+    }
+
+    /// Sets how notifications are matched to subscriptions. Defaults to
+    /// `SubscriptionRouting::Params`.
+    pub fn subscription_routing(mut self, subscription_routing: SubscriptionRouting) -> Self {
+        self.subscription_routing = subscription_routing;
+        self
+    }
+
+    /// Sends a notification to the method `method`, with params `{"id": id}`, for every call
+    /// dropped while waiting for its response, so the server can stop working on it. This is
+    /// how calls are cancelled in the Language Server Protocol, with `$/cancelRequest`. Calls
+    /// are dropped silently by default.
+    pub fn cancel_notification(mut self, method: &str) -> Self {
+        self.cancel_method = Some(method.to_owned());
+        self
+    }
+
+    /// Answers requests of the server to the method `method` with `handler`, called with the
+    /// params of the request deserialized into `P`. The server gets the value the handler
+    /// resolves to as the result, or its error. Requests with params not deserializing into `P`
+    /// are answered with an "Invalid params" error.
+    ///
+    /// ```rust,ignore
+    /// let connection = connection.on_request("workspace/configuration", |params: JsonValue| {
+    ///     Ok(JsonValue::Array(vec![settings.clone()]))
+    /// });
+    /// ```
+    pub fn on_request<P, F, U>(mut self, method: &str, handler: F) -> Self
+    where
+        P: serde::de::DeserializeOwned,
+        F: Fn(P) -> U + Send + 'static,
+        U: IntoFuture<Error = jsonrpc_core::Error>,
+        U::Item: serde::Serialize,
+        U::Future: Send + 'static,
+    {
+        let handler = move |params: JsonValue| -> HandlerFuture {
+            let params = match serde_json::from_value(params) {
+                Ok(params) => params,
+                Err(e) => {
+                    return Box::new(future::err(jsonrpc_core::Error::invalid_params(
+                        e.to_string(),
+                    )))
+                }
+            };
+            Box::new(handler(params).into_future().and_then(|result| {
+                serde_json::to_value(result).map_err(|_| jsonrpc_core::Error::internal_error())
+            }))
+        };
+        self.request_handlers
+            .insert(method.to_owned(), Box::new(handler));
+        self
+    }
+
+    /// Calls `handler` with the params of every notification of the server to the method
+    /// `method`, deserialized into `P`, unless it is a notification of a subscription.
+    /// Notifications with params not deserializing into `P` are discarded.
+    ///
+    /// ```rust,ignore
+    /// let connection = connection.on_notification(
+    ///     "blockchain.headers.subscribe",
+    ///     move |(header,): (Header,)| headers_tx.unbounded_send(header).unwrap(),
+    /// );
+    /// ```
+    pub fn on_notification<P, F>(mut self, method: &str, handler: F) -> Self
+    where
+        P: serde::de::DeserializeOwned,
+        F: Fn(P) + Send + 'static,
+    {
+        let name = method.to_owned();
+        let handler = move |params: JsonValue| match serde_json::from_value(params) {
+            Ok(params) => handler(params),
+            Err(e) => warn!("Discarding notification {} with invalid params: {}", name, e),
+        };
+        self.notification_handlers
+            .insert(method.to_owned(), Box::new(handler));
+        self
+    }
+
+    /// Returns true if no more calls may be written before another one is answered.
+    fn is_full(&self) -> bool {
+        self.max_pending
+            .map_or(false, |max_pending| self.pending.len() >= max_pending)
+    }
+}
+
+impl<S, R> Connection<S, R>
+where
+    S: Sink<SinkItem = Vec<u8>, SinkError = io::Error>,
+    R: Stream<Item = Vec<u8>, Error = io::Error>,
+{
+    /// Writes as many requests as the sink accepts.
+    fn poll_outgoing(&mut self) -> Poll<(), io::Error> {
+        loop {
+            if let Some(json_data) = self.buffered.take() {
+                if let AsyncSink::NotReady(json_data) = self.sink.start_send(json_data)? {
+                    self.buffered = Some(json_data);
+                    break;
+                }
+            }
+            if let Some(ref mut keepalive) = self.keepalive {
+                if keepalive.ping_due {
+                    keepalive.ping_due = false;
+                    self.buffered = Some(keepalive.ping.clone());
+                    continue;
+                }
+            }
+            if let Some(reply) = self.replies.pop_front() {
+                self.buffered = Some(reply);
+                continue;
+            }
+            if self.is_full() {
+                break;
+            }
+            let outgoing = match self.requests.as_mut().map(Stream::poll) {
+                Some(Ok(Async::Ready(Some(outgoing)))) => outgoing,
+                Some(Ok(Async::NotReady)) => break,
+                _ => {
+                    self.requests = None;
+                    break;
+                }
+            };
+            match outgoing.id {
+                Some(id) => {
+                    if let Some(notifications) = outgoing.subscription {
+                        self.subscribing.insert(id.clone(), notifications);
+                    }
+                    for call_id in batch_ids(&id) {
+                        self.batches.insert(id_key(&call_id), id.clone());
+                    }
+                    if self.pending.insert(id, outgoing.response_tx).is_some() {
+                        warn!("Request id reused while in flight, dropping the earlier call");
+                    }
+                }
+                None => {
+                    let _ = outgoing.response_tx.send(Vec::new());
+                }
+            }
+            self.buffered = Some(outgoing.json_data);
+        }
+        self.sink.poll_complete()
+    }
+
+    /// Reads responses and hands them to their calls. Ready when the stream has ended.
+    fn poll_incoming(&mut self) -> Poll<(), io::Error> {
+        loop {
+            let json_data = match try_ready!(self.stream.poll()) {
+                Some(json_data) => json_data,
+                None => return Ok(Async::Ready(())),
+            };
+            if let Some(ref mut keepalive) = self.keepalive {
+                keepalive.received();
+            }
+            let message = match serde_json::from_slice::<JsonValue>(&json_data) {
+                Ok(message) => message,
+                Err(_) => {
+                    debug!("Discarding message that is not JSON");
+                    continue;
+                }
+            };
+            if let JsonValue::Array(ref responses) = message {
+                let batch = responses
+                    .iter()
+                    .filter_map(|response| response.get("id"))
+                    .filter_map(|id| self.batches.get(&id_key(id)))
+                    .next()
+                    .cloned();
+                match batch.and_then(|batch| self.pending.remove(&batch)) {
+                    Some(response_tx) => {
+                        let _ = response_tx.send(json_data);
+                    }
+                    None => debug!("Discarding batch response not answering any batch in flight"),
+                }
+                continue;
+            }
+            if message.get("method").is_some() {
+                if message.get("id").is_some() {
+                    self.handle_request(message);
+                } else {
+                    self.route_notification(message);
+                }
+                continue;
+            }
+            let id = message.get("id").map(id_key);
+            match id.and_then(|id| self.pending.remove(&id).map(|tx| (id, tx))) {
+                Some((id, response_tx)) => {
+                    // Registered before the response is handed on, so no notification following
+                    // it can be missed.
+                    if let Some(notifications) = self.subscribing.remove(&id) {
+                        if let Some(subscription) = message.get("result") {
+                            let key = match self.subscription_routing {
+                                SubscriptionRouting::Params => subscription.to_string(),
+                                SubscriptionRouting::RequestId => id.clone(),
+                            };
+                            self.subscriptions.insert(key, notifications);
+                        }
+                    }
+                    let _ = response_tx.send(json_data);
+                }
+                None => self.route_notification(message),
+            }
+        }
+    }
+
+    /// Calls the notification handler of the method of `message`, if any.
+    fn handle_notification(&mut self, mut message: JsonValue) {
+        let handler = message
+            .get("method")
+            .and_then(JsonValue::as_str)
+            .and_then(|method| self.notification_handlers.get(method));
+        match handler {
+            Some(handler) => {
+                let params = message
+                    .get_mut("params")
+                    .map_or(JsonValue::Null, |params| mem::replace(params, JsonValue::Null));
+                handler(params)
+            }
+            None => debug!("Discarding message not answering any call in flight"),
+        }
+    }
+
+    /// Starts handling a request of the server with the handler registered for its method.
+    fn handle_request(&mut self, mut message: JsonValue) {
+        let id = message
+            .get_mut("id")
+            .map_or(JsonValue::Null, |id| mem::replace(id, JsonValue::Null));
+        let params = message
+            .get_mut("params")
+            .map_or(JsonValue::Null, |params| mem::replace(params, JsonValue::Null));
+        let method = message.get("method").and_then(JsonValue::as_str).unwrap_or("");
+        let future = match self.request_handlers.get(method) {
+            Some(handler) => handler(params),
+            None => {
+                debug!("No handler for request {} of the server", method);
+                Box::new(future::err(jsonrpc_core::Error::method_not_found()))
+            }
+        };
+        self.handling.push((id, future));
+    }
+
+    /// Polls the handlers of requests of the server, queueing the responses of those done.
+    fn poll_handling(&mut self) {
+        let mut i = 0;
+        while i < self.handling.len() {
+            let (member, value) = match self.handling[i].1.poll() {
+                Ok(Async::NotReady) => {
+                    i += 1;
+                    continue;
+                }
+                Ok(Async::Ready(result)) => ("result", result),
+                Err(error) => ("error", serde_json::to_value(error).unwrap_or(JsonValue::Null)),
+            };
+            let (id, _) = self.handling.swap_remove(i);
+            let mut response = serde_json::Map::new();
+            response.insert("jsonrpc".to_owned(), JsonValue::String("2.0".to_owned()));
+            response.insert("id".to_owned(), id);
+            response.insert(member.to_owned(), value);
+            match serde_json::to_vec(&response) {
+                Ok(reply) => self.replies.push_back(reply),
+                Err(e) => warn!("Unable to serialize the response to a request: {}", e),
+            }
+        }
+    }
+
+    /// Hands a message not answering any call to the subscription it is a notification of, or
+    /// else to the notification handler of its method.
+    fn route_notification(&mut self, mut message: JsonValue) {
+        let (subscription, pointer) = match self.subscription_routing {
+            SubscriptionRouting::Params => (
+                message.pointer("/params/subscription").map(JsonValue::to_string),
+                "/params/result",
+            ),
+            SubscriptionRouting::RequestId => {
+                (message.get("id").map(event_subscription), "/result")
+            }
+        };
+        let subscription = match subscription {
+            Some(subscription) => subscription,
+            None => return self.handle_notification(message),
+        };
+        let delivered = match self.subscriptions.get(&subscription) {
+            Some(notifications) => {
+                let result = message
+                    .pointer_mut(pointer)
+                    .map_or(JsonValue::Null, |result| mem::replace(result, JsonValue::Null));
+                notifications.send(result)
+            }
+            None => {
+                debug!("Discarding notification of unknown subscription {}", subscription);
+                return;
+            }
+        };
+        if !delivered {
+            self.subscriptions.remove(&subscription);
+        }
+    }
+}
+
+impl<S, R> Future for Connection<S, R>
+where
+    S: Sink<SinkItem = Vec<u8>, SinkError = io::Error>,
+    R: Stream<Item = Vec<u8>, Error = io::Error>,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        if self.poll_incoming()?.is_ready() {
+            debug!("Connection closed with {} calls in flight", self.pending.len());
+            return Ok(Async::Ready(()));
+        }
+        if let Some(ref mut keepalive) = self.keepalive {
+            if let Err(error) = keepalive.poll() {
+                warn!("{}, closing connection", error);
+                return Err(error);
+            }
+        }
+        // Forget calls that were dropped before they got a response, freeing their place in the
+        // table. Polling for cancellation wakes the connection up when more calls are dropped.
+        let cancel_method = &self.cancel_method;
+        let replies = &mut self.replies;
+        self.pending.retain(|id, response_tx| {
+            if response_tx.poll_cancel() == Ok(Async::NotReady) {
+                return true;
+            }
+            if let Some(ref method) = *cancel_method {
+                // The calls of a batch are cancelled one by one.
+                let mut call_ids = batch_ids(id);
+                if call_ids.is_empty() {
+                    call_ids.push(serde_json::from_str(id).unwrap_or(JsonValue::Null));
+                }
+                for call_id in call_ids {
+                    match cancel_notification(method, call_id) {
+                        Ok(notification) => replies.push_back(notification),
+                        Err(e) => warn!("Unable to serialize the cancellation of a call: {}", e),
+                    }
+                }
+            }
+            false
+        });
+        let pending = &self.pending;
+        self.subscribing.retain(|id, _| pending.contains_key(id));
+        self.batches.retain(|_, batch| pending.contains_key(batch));
+        self.subscriptions
+            .retain(|_, notifications| !notifications.is_closed());
+        self.poll_handling();
+        let flushed = self.poll_outgoing()?.is_ready();
+        let idle = self.requests.is_none() && self.buffered.is_none() && self.pending.is_empty()
+            && self.subscriptions.is_empty() && self.handling.is_empty()
+            && self.replies.is_empty();
+        if idle && flushed {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+
+/// Returns the key of a request in the table of calls in flight, or `None` for a notification.
+/// The key of a call is its id as returned by `id_key`. The key of a batch is the array of the
+/// ids of its calls, as JSON text, and `None` if it only holds notifications.
+fn request_id(json_data: &[u8]) -> io::Result<Option<String>> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let is_batch = json_data
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .map_or(false, |&byte| byte == b'[');
+    if !is_batch {
+        let request: RequestId = serde_json::from_slice(json_data).map_err(invalid)?;
+        return Ok(request.0.as_ref().map(id_key));
+    }
+    let requests: Vec<RequestId> = serde_json::from_slice(json_data).map_err(invalid)?;
+    let ids: Vec<_> = requests
+        .iter()
+        .filter_map(|request| request.0.as_ref())
+        .map(id_key)
+        .collect();
+    if ids.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(format!("[{}]", ids.join(","))))
+    }
+}
+
+/// The `id` of a request, deserialized skipping over all other members of the request.
+struct RequestId(Option<JsonValue>);
+
+impl<'de> Deserialize<'de> for RequestId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(RequestIdVisitor)
+    }
+}
+
+struct RequestIdVisitor;
+
+impl<'de> Visitor<'de> for RequestIdVisitor {
+    type Value = RequestId;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON-RPC request object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RequestId, A::Error> {
+        let mut id = None;
+        while let Some(key) = map.next_key::<RequestKey>()? {
+            match key {
+                RequestKey::Id => id = Some(map.next_value()?),
+                RequestKey::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(RequestId(id))
+    }
+}
+
+/// A member name of a request, telling `id` from the others without copying it.
+enum RequestKey {
+    Id,
+    Other,
+}
+
+impl<'de> Deserialize<'de> for RequestKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_identifier(RequestKeyVisitor)
+    }
+}
+
+struct RequestKeyVisitor;
+
+impl<'de> Visitor<'de> for RequestKeyVisitor {
+    type Value = RequestKey;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a member name")
+    }
+
+    fn visit_str<E>(self, key: &str) -> Result<RequestKey, E> {
+        Ok(if key == "id" {
+            RequestKey::Id
+        } else {
+            RequestKey::Other
+        })
+    }
+}
+
+/// Returns the ids of the calls of the batch with the key `key`, or nothing if `key` is the key
+/// of a single call.
+fn batch_ids(key: &str) -> Vec<JsonValue> {
+    if !key.starts_with('[') {
+        return Vec::new();
+    }
+    match serde_json::from_str(key) {
+        Ok(JsonValue::Array(ids)) => ids,
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the id of the subscribe call an event with the id `id` belongs to, as JSON text. The
+/// id of events may have `#event` appended to the id of the call, turning it into a string.
+fn event_subscription(id: &JsonValue) -> String {
+    if let Some(id) = id.as_str() {
+        if id.ends_with("#event") {
+            let call_id = &id[..id.len() - "#event".len()];
+            return id_key(&JsonValue::String(call_id.to_owned()));
+        }
+    }
+    id_key(id)
+}
+
+/// Serializes a notification to `method` cancelling the call with the id `id`, as JSON text.
+fn cancel_notification(method: &str, id: JsonValue) -> serde_json::Result<Vec<u8>> {
+    let mut params = serde_json::Map::new();
+    params.insert("id".to_owned(), id);
+    let mut notification = serde_json::Map::new();
+    notification.insert("jsonrpc".to_owned(), JsonValue::String("2.0".to_owned()));
+    notification.insert("method".to_owned(), JsonValue::String(method.to_owned()));
+    notification.insert("params".to_owned(), JsonValue::Object(params));
+    serde_json::to_vec(&notification)
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "Connection closed")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+
+    jsonrpc_client!(pub struct EchoClient {
+        pub fn echo(&mut self, value: u64) -> RpcRequest<u64>;
+    });
+
+    type TestConnection = Connection<
+        Box<Sink<SinkItem = Vec<u8>, SinkError = io::Error> + Send>,
+        Box<Stream<Item = Vec<u8>, Error = io::Error> + Send>,
+    >;
+
+    /// A transport, the two directions of its socket and the thread driving its connection.
+    type Connected = (
+        DuplexTransport,
+        Receiver<Vec<u8>>,
+        Sender<Vec<u8>>,
+        thread::JoinHandle<io::Result<()>>,
+    );
+
+    /// Connects a transport to channels standing in for the two directions of a socket.
+    fn connect() -> (DuplexTransport, Receiver<Vec<u8>>, Sender<Vec<u8>>) {
+        let (transport, requests, responses, _connection) = connect_with(|connection| connection);
+        (transport, requests, responses)
+    }
+
+    /// Like `connect`, with the connection set up by `configure`. Also returns the thread
+    /// driving the connection.
+    fn connect_with<F>(configure: F) -> Connected
+    where
+        F: FnOnce(TestConnection) -> TestConnection,
+    {
+        let (requests_tx, requests_rx) = channel(16);
+        let (responses_tx, responses_rx) = channel(16);
+        let sink: Box<Sink<SinkItem = _, SinkError = _> + Send> =
+            Box::new(requests_tx.sink_map_err(|_| closed()));
+        let stream: Box<Stream<Item = _, Error = _> + Send> =
+            Box::new(responses_rx.map_err(|()| closed()));
+        let (transport, connection) = DuplexTransport::new(sink, stream);
+        let connection = configure(connection);
+        let connection = thread::spawn(move || connection.wait());
+        (transport, requests_rx, responses_tx, connection)
+    }
+
+    #[test]
+    fn responses_out_of_order() {
+        let (transport, requests, responses) = connect();
+        // Answers two requests at a time, in reverse order.
+        thread::spawn(move || {
+            let mut responses = responses.wait();
+            for batch in requests.chunks(2).wait() {
+                for request in batch.unwrap().into_iter().rev() {
+                    let request: JsonValue = serde_json::from_slice(&request).unwrap();
+                    let response = json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "result": request["params"][0],
+                    });
+                    responses.send(serde_json::to_vec(&response).unwrap()).unwrap();
+                }
+            }
+        });
+
+        let client = EchoClient::new(transport);
+        let first = client.echo(1);
+        let second = client.echo(2);
+        assert_eq!((1, 2), first.join(second).wait().unwrap());
+    }
+
+    #[test]
+    fn max_pending() {
+        let (transport, requests, responses_tx, _connection) =
+            connect_with(|connection| connection.max_pending(1));
+        let respond = |request: Vec<u8>| {
+            let request: JsonValue = serde_json::from_slice(&request).unwrap();
+            let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": 0});
+            serde_json::to_vec(&response).unwrap()
+        };
+
+        let client = EchoClient::new(transport);
+        let calls = client.echo(1).join(client.echo(2));
+        let (first, mut requests) = requests.into_future().wait().ok().unwrap();
+        thread::sleep(::std::time::Duration::from_millis(50));
+        let queued = ::futures::future::lazy(|| requests.poll()).wait().unwrap();
+        assert_eq!(Async::NotReady, queued);
+
+        let responses_tx = responses_tx.send(respond(first.unwrap())).wait().unwrap();
+        let (second, _requests) = requests.into_future().wait().ok().unwrap();
+        let _responses_tx = responses_tx.send(respond(second.unwrap())).wait().unwrap();
+        assert_eq!((0, 0), calls.wait().unwrap());
+    }
+
+    #[test]
+    fn keepalive_detects_dead_connection() {
+        let interval = Duration::from_millis(30);
+        let (transport, requests, responses_tx, connection) = connect_with(|connection| {
+            connection.keepalive(interval, interval, b"ping".to_vec())
+        });
+
+        // The first ping is answered, the second is not.
+        let mut requests = requests.wait();
+        assert_eq!(b"ping".to_vec(), requests.next().unwrap().unwrap());
+        let _responses_tx = responses_tx.send(b"pong".to_vec()).wait().unwrap();
+        assert_eq!(b"ping".to_vec(), requests.next().unwrap().unwrap());
+        let error = connection.join().unwrap().unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, error.kind());
+        assert!(EchoClient::new(transport).echo(1).wait().is_err());
+    }
+
+    #[test]
+    fn answers_requests_of_server() {
+        let (_transport, requests, responses_tx, _connection) = connect_with(|connection| {
+            connection.on_request("add", |(a, b): (u64, u64)| Ok(a + b))
+        });
+
+        let server_requests = vec![
+            json!({"jsonrpc": "2.0", "id": "a", "method": "add", "params": [1, 2]}),
+            json!({"jsonrpc": "2.0", "id": "b", "method": "add", "params": ["x"]}),
+            json!({"jsonrpc": "2.0", "id": "c", "method": "unknown"}),
+        ];
+        let mut responses = responses_tx.wait();
+        let mut requests = requests.wait();
+        let mut reply = |request: &JsonValue| -> JsonValue {
+            responses.send(serde_json::to_vec(request).unwrap()).unwrap();
+            serde_json::from_slice(&requests.next().unwrap().unwrap()).unwrap()
+        };
+        let sum = reply(&server_requests[0]);
+        assert_eq!(json!({"jsonrpc": "2.0", "id": "a", "result": 3}), sum);
+        let invalid = reply(&server_requests[1]);
+        assert_eq!(json!("b"), invalid["id"]);
+        assert_eq!(json!(-32602), invalid["error"]["code"]);
+        let unknown = reply(&server_requests[2]);
+        assert_eq!(json!("c"), unknown["id"]);
+        assert_eq!(json!(-32601), unknown["error"]["code"]);
+    }
+
+    #[test]
+    fn handles_notifications_by_method() {
+        let (headers_tx, headers_rx) = ::std::sync::mpsc::channel();
+        let (_transport, _requests, responses_tx, connection) = connect_with(|connection| {
+            connection.on_notification("headers", move |(height,): (u64,)| {
+                headers_tx.send(height).unwrap();
+            })
+        });
+
+        let notifications = vec![
+            json!({"jsonrpc": "2.0", "method": "headers", "params": [1]}),
+            json!({"jsonrpc": "2.0", "method": "headers", "params": ["invalid"]}),
+            json!({"jsonrpc": "2.0", "method": "other", "params": [2]}),
+            json!({"jsonrpc": "2.0", "method": "headers", "params": [3]}),
+        ];
+        let mut responses = responses_tx.wait();
+        for notification in notifications {
+            responses.send(serde_json::to_vec(&notification).unwrap()).unwrap();
+        }
+        drop(responses);
+        connection.join().unwrap().unwrap();
+        assert_eq!(vec![1, 3], headers_rx.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn numeric_ids_as_strings() {
+        let (transport, requests, responses) = connect();
+        thread::spawn(move || {
+            let mut responses = responses.wait();
+            for request in requests.wait() {
+                let request: JsonValue = serde_json::from_slice(&request.unwrap()).unwrap();
+                let response = json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"].to_string(),
+                    "result": request["params"][0],
+                });
+                responses.send(serde_json::to_vec(&response).unwrap()).unwrap();
+            }
+        });
+
+        let client = EchoClient::new(transport);
+        assert_eq!(7, client.echo(7).wait().unwrap());
+    }
+
+    #[test]
+    fn batches() {
+        let (transport, requests, responses) = connect();
+        // Answers batches with the responses in reverse order, and other requests one by one.
+        thread::spawn(move || {
+            let mut responses = responses.wait();
+            for request in requests.wait() {
+                let request: JsonValue = serde_json::from_slice(&request.unwrap()).unwrap();
+                let answer = |request: &JsonValue| {
+                    json!({"jsonrpc": "2.0", "id": request["id"], "result": request["params"][0]})
+                };
+                let response = match request {
+                    JsonValue::Array(ref requests) => {
+                        JsonValue::Array(requests.iter().rev().map(answer).collect())
+                    }
+                    ref request => answer(request),
+                };
+                responses.send(serde_json::to_vec(&response).unwrap()).unwrap();
+            }
+        });
+
+        let transport = ::batch::BatchingTransport::new(transport);
+        let client = EchoClient::new(transport);
+        let calls = (1..4).map(|value| client.echo(value)).collect::<Vec<_>>();
+        assert_eq!(vec![1, 2, 3], future::join_all(calls).wait().unwrap());
+        assert_eq!(4, client.echo(4).wait().unwrap());
+    }
+
+    #[test]
+    fn request_ids() {
+        let request = br#"{"jsonrpc": "2.0", "method": "echo", "params": [{"id": 9}], "id": 5}"#;
+        assert_eq!(Some("5".to_owned()), request_id(request).unwrap());
+        let batch = br#" [{"method": "a", "id": 1}, {"method": "b"}, {"id": "2"}]"#;
+        assert_eq!(Some("[1,2]".to_owned()), request_id(batch).unwrap());
+        assert_eq!(None, request_id(br#"[{"method": "note"}]"#).unwrap());
+        assert!(request_id(b"[1]").is_err());
+    }
+
+    #[test]
+    fn closed_connection_fails_calls() {
+        let (transport, _requests, responses) = connect();
+        let client = EchoClient::new(transport);
+        let call = client.echo(1);
+        drop(responses);
+        assert!(call.wait().is_err());
+        assert!(client.echo(2).wait().is_err());
+    }
+}
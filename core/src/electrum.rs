@@ -0,0 +1,129 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A preset for talking to Electrum servers.
+//!
+//! Electrum servers speak newline-delimited JSON-RPC over plain TCP or TLS connections. A client
+//! has to start every session by negotiating the protocol version with `server.version`, and
+//! servers close connections that stay idle for a few minutes. [`connect`] frames an established
+//! connection with a [`LineCodec`] and keeps it alive by calling `server.ping` when idle.
+//! [`handshake`] makes the `server.version` call, and should be the first call on the
+//! connection.
+//!
+//! Electrum subscriptions do not carry a subscription id. The subscribe call answers with the
+//! current state, and every later change is pushed as a notification to the method of the
+//! subscribe call itself, like `blockchain.headers.subscribe`. Such notifications are handled
+//! by registering a handler for the method with `Connection::on_notification`.
+//!
+//! TLS is left to the caller, any stream implementing `AsyncRead` and `AsyncWrite` can be given
+//! to `connect`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::electrum;
+//!
+//! let stream = core.run(TcpStream::connect(&address, &core.handle()))?;
+//! let (transport, connection) = electrum::connect(stream);
+//! let connection = connection.on_notification(
+//!     "blockchain.headers.subscribe",
+//!     move |(header,): (Header,)| headers_tx.unbounded_send(header).unwrap(),
+//! );
+//! core.handle().spawn(connection.map_err(|e| error!("Connection failed: {}", e)));
+//!
+//! let (software, version) = core.run(electrum::handshake(&transport, "my-wallet"))?;
+//! let mut client = RpcClient::new(transport);
+//! let tip: Header = core.run(client.call("blockchain.headers.subscribe", ()))?;
+//! ```
+//!
+//! [`connect`]: fn.connect.html
+//! [`handshake`]: fn.handshake.html
+//! [`LineCodec`]: ../framing/struct.LineCodec.html
+
+use {call_method, ClientConfig, RpcRequest, Transport};
+use duplex::DuplexTransport;
+use framing::{self, LineConnection};
+use std::time::Duration;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The version of the Electrum protocol negotiated by `handshake`.
+pub const PROTOCOL_VERSION: &str = "1.4";
+
+/// The `server.ping` call keeping connections alive. Its id is not a number, so it never
+/// collides with the ids of other calls.
+const PING: &[u8] = br#"{"jsonrpc":"2.0","method":"server.ping","params":[],"id":"keepalive"}"#;
+
+
+/// Creates a `DuplexTransport` talking to an Electrum server over `io`, an established TCP or
+/// TLS connection. The connection calls `server.ping` after a minute without messages from the
+/// server, and fails if the server does not answer within 30 seconds.
+pub fn connect<T>(io: T) -> (DuplexTransport, LineConnection<T>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let (transport, connection) = framing::line_delimited(io);
+    let connection =
+        connection.keepalive(Duration::from_secs(60), Duration::from_secs(30), PING.to_vec());
+    (transport, connection)
+}
+
+/// Negotiates the protocol version with the server, identifying the client as `client_name`.
+/// Resolves to the name of the server software and the negotiated protocol version. The server
+/// closes the connection if it does not support `PROTOCOL_VERSION`.
+pub fn handshake<T: Transport>(
+    transport: &T,
+    client_name: &str,
+) -> RpcRequest<(String, String), T::Future> {
+    call_method(
+        transport,
+        &ClientConfig::default(),
+        "server.version".to_owned(),
+        (client_name, PROTOCOL_VERSION),
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use futures::future::{self, FutureResult};
+    use serde_json::{self, Value as JsonValue};
+    use std::io;
+
+    /// A transport answering `server.version` like ElectrumX.
+    struct VersionTransport;
+
+    impl Transport for VersionTransport {
+        type Future = FutureResult<Vec<u8>, io::Error>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            1
+        }
+
+        fn send(&self, json_data: Vec<u8>) -> Self::Future {
+            let request: JsonValue = serde_json::from_slice(&json_data).unwrap();
+            assert_eq!(json!("server.version"), request["method"]);
+            assert_eq!(json!(["wallet", PROTOCOL_VERSION]), request["params"]);
+            let response = json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "result": ["ElectrumX 1.16.0", PROTOCOL_VERSION],
+            });
+            future::ok(serde_json::to_vec(&response).unwrap())
+        }
+    }
+
+    #[test]
+    fn negotiates_version() {
+        let (software, version) = handshake(&VersionTransport, "wallet").wait().unwrap();
+        assert_eq!("ElectrumX 1.16.0", software);
+        assert_eq!(PROTOCOL_VERSION, version);
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use boxed::BoxError;
+use jsonrpc_core;
+use std::error;
+use std::fmt;
+use std::result;
+use std::time::Duration;
+
+
+/// A specialized `Result` type for RPC calls.
+pub type Result<T> = result::Result<T, Error>;
+
+/// The error type of RPC calls. Tells what went wrong through its [`kind`](#method.kind), and
+/// keeps the lower level error that caused it, if any, as its `source`.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    source: Option<Box<error::Error + Send + Sync>>,
+}
+
+/// The different kinds of errors an RPC call can fail with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// Error in the underlying transport layer.
+    TransportError,
+    /// Error while serializing method parameters.
+    SerializeError,
+    /// Error while deserializing or parsing the response data.
+    ResponseError(&'static str),
+    /// The request was replied to, but with a JSON-RPC 2.0 error.
+    JsonRpcError(jsonrpc_core::Error),
+    /// The call did not complete before its deadline. Holds the time that was left of the
+    /// deadline when the call gave up, see the [`deadline`](deadline/index.html) module.
+    DeadlineExceeded(Duration),
+    /// The consumer of a subscription fell behind and the buffer of the subscription overflowed,
+    /// see the [`pubsub`](pubsub/index.html) module.
+    SubscriptionOverflow,
+}
+
+impl Error {
+    /// Creates an error of the given kind, caused by `source`.
+    pub fn with_source<E>(kind: ErrorKind, source: E) -> Self
+    where
+        E: Into<Box<error::Error + Send + Sync>>,
+    {
+        Error {
+            kind,
+            source: Some(source.into()),
+        }
+    }
+
+    /// Returns the kind of this error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Consumes the error and returns its kind.
+    pub fn into_kind(self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the error of the transport if this is a `TransportError` caused by an error of
+    /// type `E`, the `Transport::Error` of the transport the call was made on.
+    /// For calls on a [`BoxTransport`](boxed/type.BoxTransport.html) this is the error of the
+    /// transport behind it.
+    ///
+    /// ```rust,ignore
+    /// match error.transport_error::<jsonrpc_client_http::Error>().map(|e| e.kind()) {
+    ///     Some(&HttpErrorKind::HttpError(StatusCode::ServiceUnavailable)) => retry_later(),
+    ///     _ => give_up(),
+    /// }
+    /// ```
+    pub fn transport_error<E: error::Error + 'static>(&self) -> Option<&E> {
+        match (&self.kind, &self.source) {
+            (&ErrorKind::TransportError, &Some(ref source)) => match source.downcast_ref::<E>() {
+                Some(transport_error) => Some(transport_error),
+                None => source
+                    .downcast_ref::<BoxError>()
+                    .and_then(|boxed| boxed.get_ref().downcast_ref::<E>()),
+            },
+            _ => None,
+        }
+    }
+
+    /// Consumes the error and returns the error of the transport if this is a `TransportError`
+    /// caused by an error of type `E`. Otherwise the error is returned unchanged.
+    pub fn into_transport_error<E: error::Error + 'static>(self) -> result::Result<E, Self> {
+        match (self.kind, self.source) {
+            (ErrorKind::TransportError, Some(source)) => {
+                let source = match source.downcast::<BoxError>() {
+                    Ok(boxed) => boxed.into_inner(),
+                    Err(source) => source,
+                };
+                match source.downcast::<E>() {
+                    Ok(transport_error) => Ok(*transport_error),
+                    Err(source) => Err(Error::with_source(ErrorKind::TransportError, source)),
+                }
+            }
+            (kind, source) => Err(Error { kind, source }),
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error { kind, source: None }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self.source {
+            Some(ref source) => Some(&**source),
+            None => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::TransportError => f.write_str("Unable to send the JSON-RPC 2.0 request"),
+            ErrorKind::SerializeError => f.write_str("Unable to serialize the method parameters"),
+            ErrorKind::ResponseError(msg) => write!(f, "Unable to deserialize the response: {}", msg),
+            ErrorKind::JsonRpcError(ref error) => write!(
+                f,
+                "JSON-RPC 2.0 Error: {} ({})",
+                error.code.description(),
+                error.message
+            ),
+            ErrorKind::DeadlineExceeded(remaining) => {
+                write!(f, "Deadline exceeded with {:?} left", remaining)
+            }
+            ErrorKind::SubscriptionOverflow => {
+                f.write_str("The notification buffer of the subscription overflowed")
+            }
+        }
+    }
+}
+
+
+/// Extension trait for turning the error of a `Result` into the source of an `Error`.
+pub(crate) trait ResultExt<T> {
+    fn chain_err<F: FnOnce() -> ErrorKind>(self, kind: F) -> Result<T>;
+}
+
+impl<T, E> ResultExt<T> for result::Result<T, E>
+where
+    E: error::Error + Send + Sync + 'static,
+{
+    fn chain_err<F: FnOnce() -> ErrorKind>(self, kind: F) -> Result<T> {
+        self.map_err(|e| Error::with_source(kind(), e))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn error_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<Error>();
+    }
+
+    #[test]
+    fn keeps_source() {
+        let io_error = io::Error::new(io::ErrorKind::Other, "Connection reset");
+        let error: Result<()> = Err(io_error).chain_err(|| ErrorKind::TransportError);
+        let error = error.unwrap_err();
+        assert_eq!(ErrorKind::TransportError, *error.kind());
+        assert_eq!("Unable to send the JSON-RPC 2.0 request", error.to_string());
+        let source = error::Error::source(&error).unwrap();
+        assert_eq!("Connection reset", source.to_string());
+    }
+
+    #[test]
+    fn transport_error() {
+        let io_error = io::Error::new(io::ErrorKind::ConnectionReset, "Connection reset");
+        let error = Error::with_source(ErrorKind::TransportError, io_error);
+        let transport_error = error.transport_error::<io::Error>().unwrap();
+        assert_eq!(io::ErrorKind::ConnectionReset, transport_error.kind());
+        assert!(error.transport_error::<::std::fmt::Error>().is_none());
+
+        let error = error.into_transport_error::<::std::fmt::Error>().unwrap_err();
+        let transport_error = error.into_transport_error::<io::Error>().unwrap();
+        assert_eq!(io::ErrorKind::ConnectionReset, transport_error.kind());
+    }
+
+    #[test]
+    fn not_a_transport_error() {
+        let io_error = io::Error::new(io::ErrorKind::InvalidData, "Not json");
+        let error = Error::with_source(ErrorKind::ResponseError("Not valid json"), io_error);
+        assert!(error.transport_error::<io::Error>().is_none());
+        let error = error.into_transport_error::<io::Error>().unwrap_err();
+        assert_eq!(ErrorKind::ResponseError("Not valid json"), *error.kind());
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Typed access to the `data` member of JSON-RPC 2.0 error responses.
+//!
+//! By default the `data` of an error response is only available as an untyped JSON value inside
+//! `ErrorKind::JsonRpcError`. Calling [`RpcRequest::with_error_data`] on a call makes it fail
+//! with a [`CallError`] instead, where JSON-RPC errors are an [`RpcError`] with `data`
+//! deserialized into a type of your choice.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::error_data::CallError;
+//!
+//! #[derive(Debug, Deserialize)]
+//! struct InsufficientFunds {
+//!     balance: u64,
+//!     required: u64,
+//! }
+//!
+//! match client.transfer(to, amount).with_error_data::<InsufficientFunds>().wait() {
+//!     Ok(receipt) => println!("Transferred: {:?}", receipt),
+//!     Err(CallError::Rpc(error)) => println!("Rejected: {} {:?}", error.message, error.data),
+//!     Err(CallError::Other(error)) => println!("Call failed: {}", error),
+//! }
+//! ```
+//!
+//! [`RpcRequest::with_error_data`]: ../struct.RpcRequest.html#method.with_error_data
+//! [`CallError`]: enum.CallError.html
+//! [`RpcError`]: struct.RpcError.html
+
+use {Error, ErrorKind, RpcRequest};
+use futures::{Async, Future, Poll};
+use jsonrpc_core::ErrorCode;
+use serde;
+use serde_json;
+use std::error;
+use std::fmt;
+use std::marker::PhantomData;
+
+
+/// A JSON-RPC 2.0 error response with its `data` member deserialized into `D`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError<D> {
+    /// The error code.
+    pub code: ErrorCode,
+    /// A short description of the error.
+    pub message: String,
+    /// Additional information about the error, if the server sent any.
+    pub data: Option<D>,
+}
+
+impl<D: serde::de::DeserializeOwned> RpcError<D> {
+    /// Converts an untyped JSON-RPC error by deserializing its `data` into `D`.
+    pub fn from_untyped(error: &::jsonrpc_core::Error) -> Result<Self, serde_json::Error> {
+        let data = match error.data {
+            Some(ref data) => Some(serde_json::from_value(data.clone())?),
+            None => None,
+        };
+        Ok(RpcError {
+            code: error.code.clone(),
+            message: error.message.clone(),
+            data,
+        })
+    }
+}
+
+impl<D> fmt::Display for RpcError<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "JSON-RPC 2.0 Error: {} ({})",
+            self.code.description(),
+            self.message
+        )
+    }
+}
+
+impl<D: fmt::Debug> error::Error for RpcError<D> {}
+
+
+/// The error of a call with typed error data.
+#[derive(Debug)]
+pub enum CallError<D> {
+    /// The server replied with a JSON-RPC 2.0 error.
+    Rpc(RpcError<D>),
+    /// The call failed for any other reason. Also used if the server replied with a JSON-RPC 2.0
+    /// error, but with `data` that could not be deserialized into `D`. The untyped error is then
+    /// the cause of an `ErrorKind::ResponseError`.
+    Other(Error),
+}
+
+impl<D: serde::de::DeserializeOwned> From<Error> for CallError<D> {
+    fn from(error: Error) -> Self {
+        let typed = match *error.kind() {
+            ErrorKind::JsonRpcError(ref rpc_error) => RpcError::from_untyped(rpc_error),
+            _ => return CallError::Other(error),
+        };
+        match typed {
+            Ok(rpc_error) => CallError::Rpc(rpc_error),
+            Err(_) => CallError::Other(Error::with_source(
+                ErrorKind::ResponseError("Error data not valid for target type"),
+                error,
+            )),
+        }
+    }
+}
+
+impl<D> fmt::Display for CallError<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CallError::Rpc(ref error) => error.fmt(f),
+            CallError::Other(ref error) => error.fmt(f),
+        }
+    }
+}
+
+impl<D: fmt::Debug> error::Error for CallError<D> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            CallError::Rpc(_) => None,
+            CallError::Other(ref error) => error.source(),
+        }
+    }
+}
+
+
+/// A `RpcRequest` failing with a `CallError` with error data of type `D`. Created by
+/// [`RpcRequest::with_error_data`](../struct.RpcRequest.html#method.with_error_data).
+pub struct ErrorDataRpcRequest<T, F, D> {
+    request: RpcRequest<T, F>,
+    _marker: PhantomData<D>,
+}
+
+impl<T, F, D> ErrorDataRpcRequest<T, F, D> {
+    pub(crate) fn new(request: RpcRequest<T, F>) -> Self {
+        ErrorDataRpcRequest {
+            request,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, E, F, D> Future for ErrorDataRpcRequest<T, F, D>
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+    E: error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = E> + Send + 'static,
+    D: serde::de::DeserializeOwned,
+{
+    type Item = T;
+    type Error = CallError<D>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.request.poll() {
+            Ok(Async::Ready(result)) => Ok(Async::Ready(result)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => Err(CallError::from(e)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Transport;
+    use futures::future::{self, FutureResult};
+    use std::collections::HashMap;
+    use std::io;
+
+    /// Responds to every request with the error response it was created with.
+    #[derive(Clone)]
+    struct ErrorResponseTransport(&'static [u8]);
+
+    impl Transport for ErrorResponseTransport {
+        type Future = FutureResult<Vec<u8>, io::Error>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            1
+        }
+
+        fn send(&self, _json_data: Vec<u8>) -> Self::Future {
+            future::ok(self.0.to_vec())
+        }
+    }
+
+    type Balances = HashMap<String, u64>;
+
+    jsonrpc_client!(pub struct BankClient {
+        pub fn transfer(&mut self, amount: u64) -> RpcRequest<bool>;
+    });
+
+    #[test]
+    fn typed_error_data() {
+        let transport = ErrorResponseTransport(
+            br#"{"jsonrpc": "2.0", "id": 1, "error": {"code": 1, "message": "Insufficient funds",
+                "data": {"balance": 5, "required": 10}}}"#,
+        );
+        let result = BankClient::new(transport)
+            .transfer(10)
+            .with_error_data::<Balances>()
+            .wait();
+        match result {
+            Err(CallError::Rpc(error)) => {
+                assert_eq!(ErrorCode::ServerError(1), error.code);
+                assert_eq!("Insufficient funds", error.message);
+                let data = error.data.unwrap();
+                assert_eq!((5, 10), (data["balance"], data["required"]));
+            }
+            result => panic!("Wrong result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn wrong_error_data() {
+        let transport = ErrorResponseTransport(
+            br#"{"jsonrpc": "2.0", "id": 1, "error": {"code": 1, "message": "Insufficient funds",
+                "data": "Not even close"}}"#,
+        );
+        let result = BankClient::new(transport)
+            .transfer(10)
+            .with_error_data::<Balances>()
+            .wait();
+        match result {
+            Err(CallError::Other(error)) => match *error.kind() {
+                ErrorKind::ResponseError(_) => (),
+                ref kind => panic!("Wrong error kind: {:?}", kind),
+            },
+            result => panic!("Wrong result: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper failing over from a primary endpoint to backups.
+//!
+//! A [`FailoverTransport`] holds one transport per endpoint, in order of preference, and sends
+//! every call to the first healthy one. When a call fails, the endpoint it was sent to is marked
+//! unhealthy for a cool-down period and the call is sent again to the next healthy endpoint.
+//! When all of them fail, the call fails with [`FailoverError::AllFailed`], holding the error of
+//! every endpoint tried.
+//!
+//! Once the cool-down of an endpoint has passed it is healthy again, so calls fail back to the
+//! primary as soon as it has recovered. If it still fails, it cools down once more. When all
+//! endpoints are cooling down, calls are sent to them anyway, in order of preference, and an
+//! endpoint answering is healthy again right away.
+//!
+//! An endpoint may have executed a call before failing, so calls that should not be executed
+//! twice can be limited to one endpoint with [`FailoverTransport::idempotent_only`].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::failover::FailoverTransport;
+//!
+//! let transport = HttpTransport::new().standalone()?;
+//! let endpoints = vec![
+//!     transport.handle("https://primary.example.com")?,
+//!     transport.handle("https://backup.example.com")?,
+//! ];
+//! let failover = FailoverTransport::new(endpoints).cool_down(Duration::from_secs(60));
+//! let client = FizzBuzzClient::new(failover);
+//! ```
+//!
+//! [`FailoverTransport`]: struct.FailoverTransport.html
+//! [`FailoverTransport::idempotent_only`]: struct.FailoverTransport.html#method.idempotent_only
+//! [`FailoverError::AllFailed`]: enum.FailoverError.html#variant.AllFailed
+
+use {MethodInfo, Transport};
+use futures::{Async, Future, Poll};
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+
+/// Error returned by `FailoverTransport` send operations.
+#[derive(Debug)]
+pub enum FailoverError<E> {
+    /// An endpoint failed with an error that is not failed over on.
+    Transport(E),
+    /// Every endpoint tried failed. Holds the index of each of them, in the order the transports
+    /// were given, with its error, in the order they were tried.
+    AllFailed(Vec<(usize, E)>),
+}
+
+impl<E: error::Error> fmt::Display for FailoverError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FailoverError::Transport(ref e) => write!(f, "Transport error: {}", e),
+            FailoverError::AllFailed(ref errors) => {
+                write!(f, "All endpoints failed")?;
+                for (i, &(endpoint, ref e)) in errors.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { ", " };
+                    write!(f, "{}endpoint {} ({})", separator, endpoint, e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for FailoverError<E> {
+    /// The error of the endpoint tried last.
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            FailoverError::Transport(ref e) => Some(e),
+            FailoverError::AllFailed(ref errors) => {
+                errors.last().map(|&(_, ref e)| e as &(error::Error + 'static))
+            }
+        }
+    }
+}
+
+
+struct Endpoint<T> {
+    transport: T,
+    /// Until when the endpoint is cooling down after failing.
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl<T> Endpoint<T> {
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.unhealthy_until
+            .lock()
+            .unwrap()
+            .map_or(true, |until| now >= until)
+    }
+}
+
+
+/// A transport sending calls to the first healthy of several inner transports, failing over to
+/// the next one when a call fails.
+///
+/// Clones share the endpoints, their health and the id counter.
+pub struct FailoverTransport<T: Transport> {
+    endpoints: Arc<Vec<Endpoint<T>>>,
+    cool_down: Duration,
+    predicate: Arc<Fn(&T::Error) -> bool + Send + Sync>,
+    methods: Option<&'static [MethodInfo]>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl<T: Transport> FailoverTransport<T> {
+    /// Creates a transport sending calls to the first healthy transport of `transports`, the
+    /// first one being the primary.
+    ///
+    /// Defaults to failing over on all errors, with a cool-down of 30 seconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `transports` is empty.
+    pub fn new(transports: Vec<T>) -> Self {
+        assert!(!transports.is_empty(), "No endpoints to fail over between");
+        let endpoints = transports
+            .into_iter()
+            .map(|transport| Endpoint {
+                transport,
+                unhealthy_until: Mutex::new(None),
+            })
+            .collect();
+        FailoverTransport {
+            endpoints: Arc::new(endpoints),
+            cool_down: Duration::from_secs(30),
+            predicate: Arc::new(|_| true),
+            methods: None,
+            next_id: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+
+    /// Sets for how long an endpoint is avoided after a call to it failed.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+
+    /// Only fail over on errors for which `predicate` returns true. Other errors are returned
+    /// right away and do not affect the health of the endpoint.
+    pub fn fail_over_if<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&T::Error) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Arc::new(predicate);
+        self
+    }
+
+    /// Only send calls to the methods among `methods` that are marked idempotent to another
+    /// endpoint when they fail, usually the `METHODS` of a generated client. Other calls are only
+    /// sent to the first endpoint, which is still marked unhealthy when they fail.
+    pub fn idempotent_only(mut self, methods: &'static [MethodInfo]) -> Self {
+        self.methods = Some(methods);
+        self
+    }
+
+    /// Returns if every endpoint is healthy, in the order the transports were given.
+    pub fn healthy(&self) -> Vec<bool> {
+        let now = Instant::now();
+        self.endpoints
+            .iter()
+            .map(|endpoint| endpoint.is_healthy(now))
+            .collect()
+    }
+
+    /// Returns the endpoints in the order a call tries them: the healthy ones in order of
+    /// preference, then the ones cooling down.
+    fn order(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let (mut healthy, cooling_down): (Vec<usize>, Vec<usize>) =
+            (0..self.endpoints.len()).partition(|&i| self.endpoints[i].is_healthy(now));
+        healthy.extend(cooling_down);
+        healthy
+    }
+}
+
+impl<T: Transport> Clone for FailoverTransport<T> {
+    fn clone(&self) -> Self {
+        FailoverTransport {
+            endpoints: self.endpoints.clone(),
+            cool_down: self.cool_down,
+            predicate: self.predicate.clone(),
+            methods: self.methods,
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<T: Transport + Send + Sync + 'static> Transport for FailoverTransport<T> {
+    type Future = FailoverFuture<T>;
+    type Error = FailoverError<T::Error>;
+
+    /// Returns ids from a counter of its own, so they stay unique across endpoints.
+    fn get_next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    /// Describes the endpoint new calls are sent to first.
+    fn endpoint(&self) -> Option<String> {
+        self.endpoints[self.order()[0]].transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let mut order = self.order();
+        if let Some(methods) = self.methods {
+            if !MethodInfo::is_idempotent_request(methods, &json_data) {
+                order.truncate(1);
+            }
+        }
+        let first = order.remove(0);
+        let future = self.endpoints[first].transport.send(json_data.clone());
+        order.reverse();
+        FailoverFuture {
+            transport: self.clone(),
+            json_data,
+            current: first,
+            remaining: order,
+            errors: Vec::new(),
+            future,
+        }
+    }
+}
+
+
+/// The future returned by `FailoverTransport` send operations.
+pub struct FailoverFuture<T: Transport> {
+    transport: FailoverTransport<T>,
+    json_data: Vec<u8>,
+    /// The endpoint the call is currently sent to.
+    current: usize,
+    /// The endpoints left to try, the next one last.
+    remaining: Vec<usize>,
+    /// The errors of the endpoints tried so far.
+    errors: Vec<(usize, T::Error)>,
+    future: T::Future,
+}
+
+impl<T: Transport> Future for FailoverFuture<T> {
+    type Item = Vec<u8>;
+    type Error = FailoverError<T::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let error = match self.future.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(response)) => {
+                    let endpoint = &self.transport.endpoints[self.current];
+                    *endpoint.unhealthy_until.lock().unwrap() = None;
+                    return Ok(Async::Ready(response));
+                }
+                Err(error) => error,
+            };
+            if !(self.transport.predicate)(&error) {
+                return Err(FailoverError::Transport(error));
+            }
+            let endpoint = &self.transport.endpoints[self.current];
+            warn!(
+                "Request to endpoint {} failed ({}), avoiding it for {:?}",
+                self.current, error, self.transport.cool_down
+            );
+            *endpoint.unhealthy_until.lock().unwrap() =
+                Some(Instant::now() + self.transport.cool_down);
+            self.errors.push((self.current, error));
+            self.current = match self.remaining.pop() {
+                Some(next) => next,
+                None => {
+                    return Err(FailoverError::AllFailed(self.errors.drain(..).collect()));
+                }
+            };
+            debug!("Failing over to endpoint {}", self.current);
+            let endpoint = &self.transport.endpoints[self.current];
+            self.future = endpoint.transport.send(self.json_data.clone());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use test_util::SwitchTransport;
+
+    jsonrpc_client!(pub struct PingClient {
+        pub fn ping(&self) -> RpcRequest<bool>;
+    });
+
+    #[test]
+    fn fails_over_and_back() {
+        let primary = SwitchTransport::failing();
+        let backup = SwitchTransport::default();
+        let transport = FailoverTransport::new(vec![primary.clone(), backup.clone()])
+            .cool_down(Duration::from_millis(50));
+        let client = PingClient::new(transport.clone());
+
+        assert!(client.ping().call().unwrap());
+        assert_eq!(vec![false, true], transport.healthy());
+        assert!(client.ping().call().unwrap());
+        assert_eq!(1, primary.sent());
+        assert_eq!(2, backup.sent());
+
+        primary.failing.store(false, Ordering::SeqCst);
+        ::std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(vec![true, true], transport.healthy());
+        assert!(client.ping().call().unwrap());
+        assert_eq!(2, primary.sent());
+        assert_eq!(2, backup.sent());
+    }
+
+    #[test]
+    fn all_endpoints_failing() {
+        let endpoints = vec![SwitchTransport::failing(), SwitchTransport::failing()];
+        let transport = FailoverTransport::new(endpoints.clone());
+        let request = br#"{"jsonrpc": "2.0", "method": "ping", "id": 1}"#;
+        let error = transport.send(request.to_vec()).wait().unwrap_err();
+        match error {
+            FailoverError::AllFailed(ref errors) => {
+                let tried: Vec<usize> = errors.iter().map(|&(endpoint, _)| endpoint).collect();
+                assert_eq!(vec![0, 1], tried);
+                let refused = |&(_, ref e): &(usize, io::Error)| {
+                    e.kind() == io::ErrorKind::ConnectionRefused
+                };
+                assert!(errors.iter().all(refused));
+            }
+            ref error => panic!("Unexpected error {}", error),
+        }
+        assert_eq!(vec![false, false], transport.healthy());
+
+        // Endpoints cooling down are tried as a last resort.
+        endpoints[1].failing.store(false, Ordering::SeqCst);
+        assert!(transport.send(request.to_vec()).wait().is_ok());
+        assert_eq!(vec![false, true], transport.healthy());
+    }
+}
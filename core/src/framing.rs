@@ -0,0 +1,986 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Framing of JSON-RPC messages on byte streams, like TCP connections and Unix domain sockets.
+//!
+//! A [`DuplexTransport`] exchanges whole messages. This module turns a byte stream into such
+//! messages with a codec, separating the framing from routing responses to calls. The
+//! [`LineCodec`] frames messages as newline-delimited JSON, which is what most stream based
+//! JSON-RPC servers speak. The [`ContentLengthCodec`] frames messages with HTTP-style headers
+//! giving their length, as the Language Server Protocol does. The [`LengthPrefixCodec`] frames
+//! messages with a 4-byte length prefix. The [`JsonStreamCodec`] frames messages by where their
+//! JSON ends, for servers writing messages back to back or spread over several lines, like
+//! c-lightning. The [`SlipCodec`] frames messages with SLIP, as used on serial lines to
+//! microcontrollers.
+//!
+//! All codecs are a [`Framing`], and so is any `tokio_codec` codec with `Vec<u8>` items, which
+//! is how proprietary framings are plugged in. [`framed`] puts a transport on any stream with
+//! any framing, and [`pipes`] on separate reader and writer halves, like the standard input and
+//! output of a child process. A [`BoxFraming`] holds a framing of any type, for transports that
+//! are configured with one and frame every connection they make with it.
+//!
+//! With the "compression" feature enabled, [`Compressed`] adds a compression stage to any
+//! framing, compressing every message with zlib before framing it, for chatty links where the
+//! bandwidth matters. Compressed messages are binary, so the framing below has to carry any
+//! bytes, like the `LengthPrefixCodec` or the `SlipCodec`. There is no negotiation on streams:
+//! both ends have to be configured to compress.
+//!
+//! Requests are pipelined: all requests queued on the transport are written to the stream, and
+//! flushed together, without waiting for responses to earlier requests. A burst of calls then
+//! costs one round trip instead of one per call.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::framing;
+//!
+//! let stream = core.run(TcpStream::connect(&address, &core.handle()))?;
+//! let (transport, connection) = framing::line_delimited(stream);
+//! core.handle().spawn(connection.map_err(|e| error!("Connection failed: {}", e)));
+//!
+//! let mut client = FizzBuzzClient::new(transport);
+//! let results = core.run(future::join_all((1..100).map(|n| client.fizz_buzz(n))))?;
+//! ```
+//!
+//! [`DuplexTransport`]: ../duplex/struct.DuplexTransport.html
+//! [`LineCodec`]: struct.LineCodec.html
+//! [`ContentLengthCodec`]: struct.ContentLengthCodec.html
+//! [`JsonStreamCodec`]: struct.JsonStreamCodec.html
+//! [`SlipCodec`]: struct.SlipCodec.html
+//! [`LengthPrefixCodec`]: struct.LengthPrefixCodec.html
+//! [`Compressed`]: struct.Compressed.html
+//! [`Framing`]: trait.Framing.html
+//! [`BoxFraming`]: struct.BoxFraming.html
+//! [`framed`]: fn.framed.html
+//! [`pipes`]: fn.pipes.html
+
+use bytes::BytesMut;
+use duplex::{Connection, DuplexTransport};
+use futures::Stream;
+use futures::stream::{SplitSink, SplitStream};
+#[cfg(feature = "compression")]
+use miniz_oxide::deflate::compress_to_vec_zlib;
+#[cfg(feature = "compression")]
+use miniz_oxide::inflate::{decompress_to_vec_zlib_with_limit, TINFLStatus};
+use std::fmt;
+use std::io;
+use tokio_codec::{Decoder, Encoder, Framed, FramedRead, FramedWrite};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The longest message the codecs accept by default, 16 MiB.
+const DEFAULT_MAX_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The compression level of miniz used by default, from 0 to 10.
+#[cfg(feature = "compression")]
+const DEFAULT_LEVEL: u8 = 6;
+
+/// The byte ending SLIP frames.
+const SLIP_END: u8 = 0xc0;
+/// The byte escaping `SLIP_END` and itself in SLIP frames.
+const SLIP_ESC: u8 = 0xdb;
+/// The escaped form of `SLIP_END`, following `SLIP_ESC`.
+const SLIP_ESC_END: u8 = 0xdc;
+/// The escaped form of `SLIP_ESC`, following `SLIP_ESC`.
+const SLIP_ESC_ESC: u8 = 0xdd;
+
+
+/// A codec splitting a byte stream into JSON messages and writing messages to it. Implemented by
+/// all codecs in this module, and by every `tokio_codec` codec encoding and decoding `Vec<u8>`
+/// messages with `io::Error` as error, so custom framings only implement those two traits.
+pub trait Framing:
+    Decoder<Item = Vec<u8>, Error = io::Error> + Encoder<Item = Vec<u8>, Error = io::Error>
+{
+}
+
+impl<F> Framing for F
+where
+    F: Decoder<Item = Vec<u8>, Error = io::Error> + Encoder<Item = Vec<u8>, Error = io::Error>,
+{
+}
+
+
+/// A framing of any type, for transports configured with a framing that connect more than once.
+/// Every connection is framed with a clone of the framing given to `new`. Defaults to a
+/// `LineCodec`.
+pub struct BoxFraming(Box<CloneFraming>);
+
+impl BoxFraming {
+    /// Boxes `framing`.
+    pub fn new<F: Framing + Clone + Send + Sync + 'static>(framing: F) -> Self {
+        BoxFraming(Box::new(framing))
+    }
+}
+
+impl Clone for BoxFraming {
+    fn clone(&self) -> Self {
+        BoxFraming(self.0.clone_box())
+    }
+}
+
+impl Default for BoxFraming {
+    fn default() -> Self {
+        BoxFraming::new(LineCodec::new())
+    }
+}
+
+impl fmt::Debug for BoxFraming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("BoxFraming")
+    }
+}
+
+impl Decoder for BoxFraming {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        self.0.decode(buf)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        self.0.decode_eof(buf)
+    }
+}
+
+impl Encoder for BoxFraming {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Vec<u8>, buf: &mut BytesMut) -> io::Result<()> {
+        self.0.encode(message, buf)
+    }
+}
+
+/// A framing that can be cloned behind a box.
+trait CloneFraming: Framing + Send + Sync {
+    fn clone_box(&self) -> Box<CloneFraming>;
+}
+
+impl<F: Framing + Clone + Send + Sync + 'static> CloneFraming for F {
+    fn clone_box(&self) -> Box<CloneFraming> {
+        Box::new(self.clone())
+    }
+}
+
+
+/// The connection of a `DuplexTransport` on a byte stream framed with `F`.
+pub type FramedConnection<T, F> = Connection<SplitSink<Framed<T, F>>, SplitStream<Framed<T, F>>>;
+
+/// Creates a `DuplexTransport` sending JSON messages over `io`, framed with `framing`. Returns
+/// the transport and the future driving the connection, see the
+/// [`duplex`](../duplex/index.html) module.
+pub fn framed<T, F>(io: T, framing: F) -> (DuplexTransport, FramedConnection<T, F>)
+where
+    T: AsyncRead + AsyncWrite,
+    F: Framing,
+{
+    let (sink, stream) = framing.framed(io).split();
+    DuplexTransport::new(sink, stream)
+}
+
+/// The connection of a `DuplexTransport` on a reader and a writer framed with `F`.
+pub type PipeConnection<R, W, F> = Connection<FramedWrite<W, F>, FramedRead<R, F>>;
+
+/// Creates a `DuplexTransport` reading JSON messages from `reader` and writing them to `writer`,
+/// both framed with `framing`, like on the standard output and input of a child process.
+pub fn pipes<R, W, F>(
+    reader: R,
+    writer: W,
+    framing: F,
+) -> (DuplexTransport, PipeConnection<R, W, F>)
+where
+    R: AsyncRead,
+    W: AsyncWrite,
+    F: Framing + Clone,
+{
+    let sink = FramedWrite::new(writer, framing.clone());
+    let stream = FramedRead::new(reader, framing);
+    DuplexTransport::new(sink, stream)
+}
+
+/// The connection of a `DuplexTransport` on a byte stream framed with a `LineCodec`.
+pub type LineConnection<T> = FramedConnection<T, LineCodec>;
+
+/// Creates a `DuplexTransport` sending newline-delimited JSON messages over `io`. Returns the
+/// transport and the future driving the connection, see the [`duplex`](../duplex/index.html)
+/// module.
+pub fn line_delimited<T>(io: T) -> (DuplexTransport, LineConnection<T>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    framed(io, LineCodec::new())
+}
+
+/// The connection of a `DuplexTransport` on a byte stream framed with a `JsonStreamCodec`.
+pub type JsonStreamConnection<T> = FramedConnection<T, JsonStreamCodec>;
+
+/// Creates a `DuplexTransport` sending JSON messages over `io` and reading messages framed by
+/// where their JSON ends, with a `JsonStreamCodec`.
+pub fn json_stream<T>(io: T) -> (DuplexTransport, JsonStreamConnection<T>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    framed(io, JsonStreamCodec::new())
+}
+
+/// The connection of a `DuplexTransport` on a byte stream framed with a `SlipCodec`.
+pub type SlipConnection<T> = FramedConnection<T, SlipCodec>;
+
+/// Creates a `DuplexTransport` sending JSON messages over `io` in SLIP frames.
+pub fn slip<T>(io: T) -> (DuplexTransport, SlipConnection<T>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    framed(io, SlipCodec::new())
+}
+
+
+/// A codec framing JSON messages by ending each one with a newline. Empty lines between
+/// messages are skipped and a trailing carriage return is removed.
+///
+/// Relies on messages not containing newlines, which holds for the compact JSON this crate
+/// serializes requests as.
+#[derive(Debug, Clone)]
+pub struct LineCodec {
+    max_length: usize,
+    /// How far into the buffer there is known to be no newline.
+    searched: usize,
+}
+
+impl LineCodec {
+    /// Creates a codec accepting messages of up to 16 MiB.
+    pub fn new() -> Self {
+        LineCodec {
+            max_length: DEFAULT_MAX_LENGTH,
+            searched: 0,
+        }
+    }
+
+    /// Sets the longest message accepted, in bytes. Reading a longer message fails the stream.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+impl Default for LineCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for LineCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let newline = match buf[self.searched..].iter().position(|&b| b == b'\n') {
+                Some(offset) => self.searched + offset,
+                None => {
+                    self.searched = buf.len();
+                    if buf.len() > self.max_length {
+                        return Err(too_long());
+                    }
+                    return Ok(None);
+                }
+            };
+            self.searched = 0;
+            let line = buf.split_to(newline + 1);
+            if newline > self.max_length {
+                return Err(too_long());
+            }
+            let message = trim(&line[..newline]);
+            if !message.is_empty() {
+                return Ok(Some(message.to_vec()));
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        match self.decode(buf)? {
+            Some(message) => Ok(Some(message)),
+            None => {
+                // The last message may lack a newline.
+                self.searched = 0;
+                let rest = buf.take();
+                let message = trim(&rest);
+                if message.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(message.to_vec()))
+                }
+            }
+        }
+    }
+}
+
+impl Encoder for LineCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Vec<u8>, buf: &mut BytesMut) -> io::Result<()> {
+        buf.reserve(message.len() + 1);
+        buf.extend_from_slice(&message);
+        buf.extend_from_slice(b"\n");
+        Ok(())
+    }
+}
+
+
+/// A codec framing JSON messages with a header block giving their length, as in
+/// `Content-Length: 42\r\n\r\n{...}`. Headers other than `Content-Length` are ignored when
+/// decoding and not written when encoding.
+#[derive(Debug, Clone)]
+pub struct ContentLengthCodec {
+    max_length: usize,
+    /// The length of the message whose headers have been read, if any.
+    length: Option<usize>,
+}
+
+impl ContentLengthCodec {
+    /// Creates a codec accepting messages of up to 16 MiB.
+    pub fn new() -> Self {
+        ContentLengthCodec {
+            max_length: DEFAULT_MAX_LENGTH,
+            length: None,
+        }
+    }
+
+    /// Sets the length of the longest message accepted, in bytes. Decoding a longer message, or
+    /// headers that long, fails with an `InvalidData` error.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+impl Default for ContentLengthCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ContentLengthCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        let length = match self.length {
+            Some(length) => length,
+            None => {
+                let end = match buf.windows(4).position(|window| window == b"\r\n\r\n") {
+                    Some(end) => end,
+                    None if buf.len() > self.max_length => return Err(too_long()),
+                    None => return Ok(None),
+                };
+                let headers = buf.split_to(end + 4);
+                let length = content_length(&headers[..end])?;
+                if length > self.max_length {
+                    return Err(too_long());
+                }
+                self.length = Some(length);
+                length
+            }
+        };
+        if buf.len() < length {
+            buf.reserve(length - buf.len());
+            return Ok(None);
+        }
+        self.length = None;
+        Ok(Some(buf.split_to(length).to_vec()))
+    }
+}
+
+impl Encoder for ContentLengthCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Vec<u8>, buf: &mut BytesMut) -> io::Result<()> {
+        let headers = format!("Content-Length: {}\r\n\r\n", message.len());
+        buf.reserve(headers.len() + message.len());
+        buf.extend_from_slice(headers.as_bytes());
+        buf.extend_from_slice(&message);
+        Ok(())
+    }
+}
+
+/// A codec framing JSON messages with a 4-byte big-endian length prefix, as in
+/// `\0\0\0\x2a{...}`.
+#[derive(Debug, Clone)]
+pub struct LengthPrefixCodec {
+    max_length: usize,
+}
+
+impl LengthPrefixCodec {
+    /// Creates a codec accepting messages of up to 16 MiB.
+    pub fn new() -> Self {
+        LengthPrefixCodec {
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+
+    /// Sets the length of the longest message accepted, in bytes. Decoding a longer message
+    /// fails with an `InvalidData` error.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+impl Default for LengthPrefixCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for LengthPrefixCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        if buf.len() < 4 {
+            return Ok(None);
+        }
+        let length = buf[..4]
+            .iter()
+            .fold(0, |length, &byte| (length << 8) | byte as usize);
+        if length > self.max_length {
+            return Err(too_long());
+        }
+        if buf.len() < 4 + length {
+            buf.reserve(4 + length - buf.len());
+            return Ok(None);
+        }
+        buf.split_to(4);
+        Ok(Some(buf.split_to(length).to_vec()))
+    }
+}
+
+impl Encoder for LengthPrefixCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Vec<u8>, buf: &mut BytesMut) -> io::Result<()> {
+        if message.len() > u32::max_value() as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Message too long for a 4-byte length prefix",
+            ));
+        }
+        let length = message.len() as u32;
+        buf.reserve(4 + message.len());
+        buf.extend_from_slice(&[
+            (length >> 24) as u8,
+            (length >> 16) as u8,
+            (length >> 8) as u8,
+            length as u8,
+        ]);
+        buf.extend_from_slice(&message);
+        Ok(())
+    }
+}
+
+/// A codec framing JSON messages by where the JSON object or array of each ends, for servers
+/// that do not delimit their messages. Messages may be written back to back, be separated by any
+/// whitespace, contain newlines, and arrive split over any number of reads. Messages are encoded
+/// followed by a newline.
+///
+/// The input is scanned once, keeping track of the nesting depth and of string literals, so
+/// long messages arriving in many small reads are not parsed over and over.
+#[derive(Debug, Clone)]
+pub struct JsonStreamCodec {
+    max_length: usize,
+    /// How far into the buffer the current message has been scanned.
+    scanned: usize,
+    /// The nesting depth of objects and arrays at `scanned`.
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl JsonStreamCodec {
+    /// Creates a codec accepting messages of up to 16 MiB.
+    pub fn new() -> Self {
+        JsonStreamCodec {
+            max_length: DEFAULT_MAX_LENGTH,
+            scanned: 0,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        }
+    }
+
+    /// Sets the longest message accepted, in bytes. Reading a longer message fails the stream.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Scans `buf` from where the last call stopped, returning the length of the message at the
+    /// start of it once its end has been reached.
+    fn scan(&mut self, buf: &[u8]) -> Option<usize> {
+        let start = self.scanned;
+        for (offset, &byte) in buf[start..].iter().enumerate() {
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => (),
+                }
+                continue;
+            }
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        self.scanned = 0;
+                        return Some(start + offset + 1);
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.scanned = buf.len();
+        None
+    }
+}
+
+impl Default for JsonStreamCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for JsonStreamCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        if self.depth == 0 {
+            // Skips the whitespace between messages, leaving the buffer at the start of one.
+            let start = buf.iter().position(|b| !b" \t\r\n".contains(b)).unwrap_or(buf.len());
+            buf.split_to(start);
+            match buf.first() {
+                None => return Ok(None),
+                Some(&b'{') | Some(&b'[') => (),
+                Some(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Message is not a JSON object or array",
+                    ))
+                }
+            }
+        }
+        match self.scan(buf) {
+            Some(length) if length > self.max_length => Err(too_long()),
+            Some(length) => Ok(Some(buf.split_to(length).to_vec())),
+            None if buf.len() > self.max_length => Err(too_long()),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder for JsonStreamCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Vec<u8>, buf: &mut BytesMut) -> io::Result<()> {
+        buf.reserve(message.len() + 1);
+        buf.extend_from_slice(&message);
+        buf.extend_from_slice(b"\n");
+        Ok(())
+    }
+}
+
+
+/// A codec framing JSON messages with SLIP (RFC 1055), ending each message with an `END` byte
+/// and escaping the `END` and `ESC` bytes within it. Messages are also encoded starting with an
+/// `END` byte, ending any line noise the receiver has read since the last message. Empty frames
+/// are skipped.
+#[derive(Debug, Clone)]
+pub struct SlipCodec {
+    max_length: usize,
+}
+
+impl SlipCodec {
+    /// Creates a codec accepting messages of up to 16 MiB.
+    pub fn new() -> Self {
+        SlipCodec {
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+
+    /// Sets the longest message accepted, in bytes, counting escape bytes. Reading a longer
+    /// message fails the stream.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+impl Default for SlipCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for SlipCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let end = match buf.iter().position(|&b| b == SLIP_END) {
+                Some(end) => end,
+                None if buf.len() > self.max_length => return Err(too_long()),
+                None => return Ok(None),
+            };
+            let frame = buf.split_to(end + 1);
+            if end > self.max_length {
+                return Err(too_long());
+            }
+            if end == 0 {
+                continue;
+            }
+            let mut message = Vec::with_capacity(end);
+            let mut escaped = false;
+            for &byte in &frame[..end] {
+                if escaped {
+                    escaped = false;
+                    match byte {
+                        SLIP_ESC_END => message.push(SLIP_END),
+                        SLIP_ESC_ESC => message.push(SLIP_ESC),
+                        _ => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "Invalid escape sequence in SLIP frame",
+                            ))
+                        }
+                    }
+                } else if byte == SLIP_ESC {
+                    escaped = true;
+                } else {
+                    message.push(byte);
+                }
+            }
+            return Ok(Some(message));
+        }
+    }
+}
+
+impl Encoder for SlipCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Vec<u8>, buf: &mut BytesMut) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(message.len() + 2);
+        frame.push(SLIP_END);
+        for byte in message {
+            match byte {
+                SLIP_END => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+                SLIP_ESC => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+                byte => frame.push(byte),
+            }
+        }
+        frame.push(SLIP_END);
+        buf.reserve(frame.len());
+        buf.extend_from_slice(&frame);
+        Ok(())
+    }
+}
+
+/// A framing compressing every message with zlib and framing it with an inner framing, and
+/// decompressing every message the inner framing decodes. The inner framing has to carry any
+/// bytes, like a `LengthPrefixCodec` or a `SlipCodec`.
+#[cfg(feature = "compression")]
+#[derive(Debug, Clone)]
+pub struct Compressed<F> {
+    inner: F,
+    level: u8,
+    max_length: usize,
+}
+
+#[cfg(feature = "compression")]
+impl<F: Framing> Compressed<F> {
+    /// Compresses the messages framed with `inner`, at level 6, accepting messages of up to
+    /// 16 MiB once decompressed.
+    pub fn new(inner: F) -> Self {
+        Compressed {
+            inner,
+            level: DEFAULT_LEVEL,
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+
+    /// Sets the compression level, from 0 for no compression to 10 for the smallest messages.
+    pub fn level(mut self, level: u8) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Sets the length of the longest message accepted once decompressed, in bytes.
+    /// Decompressing a longer message fails with an `InvalidData` error.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    fn decompress(&self, message: Option<Vec<u8>>) -> io::Result<Option<Vec<u8>>> {
+        let message = match message {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+        match decompress_to_vec_zlib_with_limit(&message, self.max_length) {
+            Ok(message) => Ok(Some(message)),
+            Err(ref error) if error.status == TINFLStatus::HasMoreOutput => Err(too_long()),
+            Err(error) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid compressed message: {}", error),
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+impl<F: Framing> Decoder for Compressed<F> {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        let message = self.inner.decode(buf)?;
+        self.decompress(message)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        let message = self.inner.decode_eof(buf)?;
+        self.decompress(message)
+    }
+}
+
+#[cfg(feature = "compression")]
+impl<F: Framing> Encoder for Compressed<F> {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Vec<u8>, buf: &mut BytesMut) -> io::Result<()> {
+        let compressed = compress_to_vec_zlib(&message, self.level);
+        self.inner.encode(compressed, buf)
+    }
+}
+
+
+/// Returns the value of the `Content-Length` header among `headers`.
+fn content_length(headers: &[u8]) -> io::Result<usize> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let headers = ::std::str::from_utf8(headers).map_err(|_| invalid("Headers are not UTF-8"))?;
+    for header in headers.split("\r\n") {
+        let mut parts = header.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            return parts
+                .next()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| invalid("Invalid Content-Length header"));
+        }
+    }
+    Err(invalid("Missing Content-Length header"))
+}
+
+
+/// Removes surrounding whitespace, including the carriage return of `\r\n` line endings.
+fn trim(line: &[u8]) -> &[u8] {
+    let is_space = |b: &u8| b" \t\r".contains(b);
+    let start = line.iter().position(|b| !is_space(b)).unwrap_or(line.len());
+    let end = line.iter().rposition(|b| !is_space(b)).map_or(start, |end| end + 1);
+    &line[start..end]
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Message exceeds the maximum length")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Async, Future};
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn decode_lines() {
+        let mut codec = LineCodec::new();
+        let mut buf = BytesMut::from(&b"{\"id\":1}\r\n\n{\"id\""[..]);
+        assert_eq!(Some(b"{\"id\":1}".to_vec()), codec.decode(&mut buf).unwrap());
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(b":2}");
+        assert_eq!(Some(b"{\"id\":2}".to_vec()), codec.decode_eof(&mut buf).unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed() {
+        let message = br#"{"jsonrpc":"2.0","method":"ping","params":["aaaaaaaaaaaaaaaaaaaaaaaa"]}"#;
+        let mut codec = Compressed::new(LengthPrefixCodec::new());
+        let mut buf = BytesMut::new();
+        codec.encode(message.to_vec(), &mut buf).unwrap();
+        assert!(buf.len() < 4 + message.len());
+        assert_eq!(Some(message.to_vec()), codec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+
+        codec.encode(message.to_vec(), &mut buf).unwrap();
+        let mut codec = Compressed::new(LengthPrefixCodec::new()).max_length(message.len() - 1);
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            codec.decode(&mut buf).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn message_too_long() {
+        let mut codec = LineCodec::new().max_length(4);
+        let mut buf = BytesMut::from(&b"[1,2,3]"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn decode_content_length() {
+        let mut codec = ContentLengthCodec::new();
+        let mut buf = BytesMut::from(&b"Content-Length: 8\r\nContent-Type: x\r\n\r\n{\"id\""[..]);
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(b":1}content-length:2\r\n\r\n[]");
+        assert_eq!(Some(b"{\"id\":1}".to_vec()), codec.decode(&mut buf).unwrap());
+        assert_eq!(Some(b"[]".to_vec()), codec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"Content-Type: x\r\n\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn decode_json_stream() {
+        let mut codec = JsonStreamCodec::new();
+        let mut buf = BytesMut::from(&b"{\"id\":1}{\"id\":2,\n\"result\":\"}\\\"{\""[..]);
+        assert_eq!(Some(b"{\"id\":1}".to_vec()), codec.decode(&mut buf).unwrap());
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(b",\"data\":[{}]}\n\n [1] ");
+        let expected = b"{\"id\":2,\n\"result\":\"}\\\"{\",\"data\":[{}]}".to_vec();
+        assert_eq!(Some(expected), codec.decode(&mut buf).unwrap());
+        assert_eq!(Some(b"[1]".to_vec()), codec.decode(&mut buf).unwrap());
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"null"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn encode_content_length() {
+        let mut buf = BytesMut::new();
+        ContentLengthCodec::new()
+            .encode(b"[1]".to_vec(), &mut buf)
+            .unwrap();
+        assert_eq!(&b"Content-Length: 3\r\n\r\n[1]"[..], &buf[..]);
+    }
+
+    #[test]
+    fn length_prefix_frames() {
+        let mut codec = LengthPrefixCodec::new().max_length(8);
+        let mut buf = BytesMut::new();
+        codec.encode(b"[1]".to_vec(), &mut buf).unwrap();
+        assert_eq!(&b"\0\0\0\x03[1]"[..], &buf[..]);
+        buf.extend_from_slice(b"\0\0\0\x02[");
+        assert_eq!(Some(b"[1]".to_vec()), codec.decode(&mut buf).unwrap());
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(b"]");
+        assert_eq!(Some(b"[]".to_vec()), codec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"\0\0\0\x09"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn slip_frames() {
+        let mut codec = SlipCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(vec![b'[', SLIP_END, SLIP_ESC, b']'], &mut buf).unwrap();
+        let frame = [
+            SLIP_END, b'[', SLIP_ESC, SLIP_ESC_END, SLIP_ESC, SLIP_ESC_ESC, b']', SLIP_END,
+        ];
+        assert_eq!(&frame[..], &buf[..]);
+        buf.extend_from_slice(&[b'[', b'1']);
+        let expected = vec![b'[', SLIP_END, SLIP_ESC, b']'];
+        assert_eq!(Some(expected), codec.decode(&mut buf).unwrap());
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(&[b']', SLIP_END]);
+        assert_eq!(Some(b"[1]".to_vec()), codec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&[SLIP_ESC, b'x', SLIP_END][..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    /// A stream recording every write and never having anything to read.
+    struct RecordingStream {
+        writes: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl Read for RecordingStream {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for RecordingStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.lock().unwrap().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for RecordingStream {}
+
+    impl AsyncWrite for RecordingStream {
+        fn shutdown(&mut self) -> ::futures::Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    jsonrpc_client!(pub struct PingClient {
+        pub fn ping(&mut self) -> RpcRequest<bool>;
+    });
+
+    #[test]
+    fn requests_are_pipelined() {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let stream = RecordingStream {
+            writes: writes.clone(),
+        };
+        let (transport, mut connection) = line_delimited(stream);
+        let client = PingClient::new(transport);
+        let _calls = (client.ping(), client.ping(), client.ping());
+
+        let state = future::lazy(|| connection.poll()).wait().unwrap();
+        assert_eq!(Async::NotReady, state);
+        let writes = writes.lock().unwrap();
+        assert_eq!(1, writes.len());
+        let lines: Vec<&[u8]> = writes[0].split(|&b| b == b'\n').collect();
+        assert_eq!(4, lines.len());
+        assert!(lines[3].is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Helpers for golden-file (snapshot) testing of the requests generated by RPC clients.
+//!
+//! A [`RecordingTransport`] stores every request sent through it. The recorded requests can then
+//! be compared against checked in golden files with [`assert_golden`], catching accidental
+//! changes to the wire format, such as reordered parameters or renamed methods.
+//!
+//! Setting the environment variable `JSONRPC_CLIENT_UPDATE_GOLDEN=1` makes [`assert_golden`]
+//! (re)write the golden files instead of comparing against them.
+//!
+//! This module is only available with the `golden` feature, so it is best enabled in
+//! `[dev-dependencies]` only.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! #[macro_use]
+//! extern crate jsonrpc_client_core;
+//!
+//! use jsonrpc_client_core::golden::{assert_golden, RecordingTransport};
+//!
+//! jsonrpc_client!(pub struct FizzBuzzClient {
+//!     pub fn fizz_buzz(&mut self, number: u64) -> RpcRequest<String>;
+//! });
+//!
+//! fn main() {
+//!     let transport = RecordingTransport::new();
+//!     let mut client = FizzBuzzClient::new(transport.clone());
+//!     let _ = client.fizz_buzz(15);
+//!
+//!     assert_golden("tests/golden/fizz_buzz.json", &transport.last_request().unwrap());
+//! }
+//! ```
+//!
+//! [`RecordingTransport`]: struct.RecordingTransport.html
+//! [`assert_golden`]: fn.assert_golden.html
+
+use Transport;
+use futures::future::{self, FutureResult};
+use serde_json::{self, Value as JsonValue};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The environment variable that, when set to `1`, makes `assert_golden` write golden files.
+pub const UPDATE_ENV_VAR: &str = "JSONRPC_CLIENT_UPDATE_GOLDEN";
+
+
+/// A transport that records all requests sent through it instead of sending them anywhere.
+///
+/// Ids are handed out sequentially starting at 1, so the rendered requests are deterministic.
+/// Clones share the same recording and id counter. All returned futures fail, since there is no
+/// server producing any responses.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingTransport {
+    state: Arc<Mutex<RecordingState>>,
+}
+
+#[derive(Debug, Default)]
+struct RecordingState {
+    next_id: u64,
+    requests: Vec<Vec<u8>>,
+}
+
+impl RecordingTransport {
+    /// Creates a new transport without any recorded requests.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of all requests recorded so far, in the order they were sent.
+    pub fn requests(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Returns the most recently recorded request, if any.
+    pub fn last_request(&self) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().requests.last().cloned()
+    }
+}
+
+impl Transport for RecordingTransport {
+    type Future = FutureResult<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        state.next_id
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        self.state.lock().unwrap().requests.push(json_data);
+        future::err(io::Error::new(
+            io::ErrorKind::Other,
+            "RecordingTransport does not produce responses",
+        ))
+    }
+}
+
+
+/// Renders a serialized request as pretty printed JSON, the format used in golden files.
+///
+/// # Panics
+///
+/// Panics if `request` is not valid JSON.
+pub fn render_request(request: &[u8]) -> String {
+    let json: JsonValue = serde_json::from_slice(request).expect("Request is not valid JSON");
+    let mut rendered = serde_json::to_string_pretty(&json).unwrap();
+    rendered.push('\n');
+    rendered
+}
+
+/// Asserts that `request` is equal to the request stored in the golden file at `path`.
+///
+/// The comparison is done on the JSON level, so formatting and object member order in the golden
+/// file do not matter. Array order, and thus the order of positional parameters, does matter.
+///
+/// If the environment variable `JSONRPC_CLIENT_UPDATE_GOLDEN` is set to `1` the golden file is
+/// written with the rendered request instead.
+///
+/// # Panics
+///
+/// Panics if the golden file can't be read or written, or if the request does not match it.
+pub fn assert_golden<P: AsRef<Path>>(path: P, request: &[u8]) {
+    let path = path.as_ref();
+    let rendered = render_request(request);
+    if env::var(UPDATE_ENV_VAR).map(|value| value == "1").unwrap_or(false) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("Unable to create golden file directory");
+        }
+        fs::write(path, rendered).expect("Unable to write golden file");
+        return;
+    }
+
+    let golden = fs::read(path).unwrap_or_else(|e| {
+        panic!(
+            "Unable to read golden file {}: {}. Run with {}=1 to create it",
+            path.display(),
+            e,
+            UPDATE_ENV_VAR
+        )
+    });
+    let expected: JsonValue = serde_json::from_slice(&golden)
+        .unwrap_or_else(|e| panic!("Golden file {} is not valid JSON: {}", path.display(), e));
+    let actual: JsonValue = serde_json::from_str(&rendered).unwrap();
+    if expected != actual {
+        panic!(
+            "Request does not match golden file {}\nexpected:\n{}\nactual:\n{}\
+             Run with {}=1 to update the golden file",
+            path.display(),
+            String::from_utf8_lossy(&golden),
+            rendered,
+            UPDATE_ENV_VAR
+        );
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    jsonrpc_client!(pub struct GoldenClient {
+        pub fn add(&mut self, a: u64, b: u64) -> RpcRequest<u64>;
+    });
+
+    fn golden_path(name: &str) -> ::std::path::PathBuf {
+        env::temp_dir().join(format!(
+            "jsonrpc-client-core-golden-{}-{}.json",
+            name,
+            ::std::process::id()
+        ))
+    }
+
+    #[test]
+    fn records_requests() {
+        let transport = RecordingTransport::new();
+        let client = GoldenClient::new(transport.clone());
+        let _ = client.add(1, 2);
+        let _ = client.add(3, 4);
+
+        assert_eq!(2, transport.requests().len());
+        let last: JsonValue = serde_json::from_slice(&transport.last_request().unwrap()).unwrap();
+        assert_eq!(
+            json!({"jsonrpc": "2.0", "id": 2, "method": "add", "params": [3, 4]}),
+            last
+        );
+    }
+
+    #[test]
+    fn matches_golden_file() {
+        let path = golden_path("match");
+        fs::write(
+            &path,
+            r#"{"params": [1, 2], "method": "add", "id": 1, "jsonrpc": "2.0"}"#,
+        ).unwrap();
+
+        let transport = RecordingTransport::new();
+        let _ = GoldenClient::new(transport.clone()).add(1, 2);
+        assert_golden(&path, &transport.last_request().unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Removes the golden file when dropped, also when the test panics.
+    struct RemoveOnDrop<'a>(&'a Path);
+
+    impl<'a> Drop for RemoveOnDrop<'a> {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(self.0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "does not match golden file")]
+    fn detects_reordered_params() {
+        let path = golden_path("reordered");
+        let _remove = RemoveOnDrop(&path);
+        let golden = br#"{"jsonrpc":"2.0","id":1,"method":"add","params":[2,1]}"#;
+        fs::write(&path, render_request(golden)).unwrap();
+
+        let transport = RecordingTransport::new();
+        let _ = GoldenClient::new(transport.clone()).add(1, 2);
+        assert_golden(&path, &transport.last_request().unwrap());
+    }
+}
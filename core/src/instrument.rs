@@ -0,0 +1,242 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hooks for collecting metrics about RPC calls.
+//!
+//! Implement [`Instrumentation`] and register it on a client with
+//! [`ClientConfig::instrumentation`] to have it called at the start and end of every call. This
+//! is the place to update Prometheus or statsd counters and latency histograms.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::ClientConfig;
+//! use jsonrpc_client_core::instrument::{CallMetrics, Instrumentation};
+//!
+//! struct Latency;
+//!
+//! impl Instrumentation for Latency {
+//!     fn on_success(&self, method: &str, metrics: &CallMetrics) {
+//!         LATENCY_HISTOGRAM.with_label_values(&[method]).observe(secs(metrics.duration));
+//!     }
+//! }
+//!
+//! let config = ClientConfig::new().instrumentation(Latency);
+//! let mut client = FizzBuzzClient::with_config(transport, config);
+//! ```
+//!
+//! [`Instrumentation`]: trait.Instrumentation.html
+//! [`ClientConfig::instrumentation`]: ../struct.ClientConfig.html#method.instrumentation
+
+use Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+
+/// Hooks called for every call made by a client configured with this instrumentation.
+///
+/// Every call first gets an `on_start` and then exactly one of `on_success`, `on_failure` or
+/// `on_cancel`. All methods have empty default implementations.
+pub trait Instrumentation: Send + Sync {
+    /// A call to `method` is about to be serialized and sent.
+    fn on_start(&self, _method: &str) {}
+
+    /// A call to `method` completed successfully.
+    fn on_success(&self, _method: &str, _metrics: &CallMetrics) {}
+
+    /// A call to `method` failed with `error`. This includes serialization errors, transport
+    /// errors, invalid responses and JSON-RPC error responses.
+    fn on_failure(&self, _method: &str, _metrics: &CallMetrics, _error: &Error) {}
+
+    /// A call to `method` was dropped before it completed.
+    fn on_cancel(&self, _method: &str, _metrics: &CallMetrics) {}
+}
+
+
+/// Measurements of a single call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallMetrics {
+    /// Time from the start of the call until it completed, failed or was dropped.
+    pub duration: Duration,
+    /// The size of the serialized request in bytes. Zero if serialization failed.
+    pub request_size: usize,
+    /// The size of the raw response in bytes, if one was received.
+    pub response_size: Option<usize>,
+}
+
+
+/// Reports the progress of one call to an `Instrumentation`. Reports the call as canceled if
+/// dropped before `success` or `failure` was called.
+pub(crate) struct CallInstrumentation {
+    instrumentation: Arc<Instrumentation>,
+    method: String,
+    started_at: Instant,
+    request_size: usize,
+    response_size: Option<usize>,
+    done: bool,
+}
+
+impl CallInstrumentation {
+    pub(crate) fn start(instrumentation: &Arc<Instrumentation>, method: &str) -> Self {
+        instrumentation.on_start(method);
+        CallInstrumentation {
+            instrumentation: instrumentation.clone(),
+            method: method.to_owned(),
+            started_at: Instant::now(),
+            request_size: 0,
+            response_size: None,
+            done: false,
+        }
+    }
+
+    pub(crate) fn set_request_size(&mut self, request_size: usize) {
+        self.request_size = request_size;
+    }
+
+    pub(crate) fn set_response_size(&mut self, response_size: usize) {
+        self.response_size = Some(response_size);
+    }
+
+    pub(crate) fn success(&mut self) {
+        self.done = true;
+        self.instrumentation
+            .on_success(&self.method, &self.metrics());
+    }
+
+    pub(crate) fn failure(&mut self, error: &Error) {
+        self.done = true;
+        self.instrumentation
+            .on_failure(&self.method, &self.metrics(), error);
+    }
+
+    fn metrics(&self) -> CallMetrics {
+        CallMetrics {
+            duration: self.started_at.elapsed(),
+            request_size: self.request_size,
+            response_size: self.response_size,
+        }
+    }
+}
+
+impl Drop for CallInstrumentation {
+    fn drop(&mut self) {
+        if !self.done {
+            self.instrumentation
+                .on_cancel(&self.method, &self.metrics());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {ClientConfig, ErrorKind, Transport};
+    use futures::Future;
+    use futures::future::{self, Empty, FutureResult};
+    use std::io;
+    use std::sync::Mutex;
+
+    /// Records all hook calls as strings.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Instrumentation for Recorder {
+        fn on_start(&self, method: &str) {
+            self.0.lock().unwrap().push(format!("start {}", method));
+        }
+
+        fn on_success(&self, method: &str, metrics: &CallMetrics) {
+            self.0.lock().unwrap().push(format!(
+                "success {} {} {:?}",
+                method,
+                metrics.request_size,
+                metrics.response_size
+            ));
+        }
+
+        fn on_failure(&self, method: &str, metrics: &CallMetrics, error: &Error) {
+            let kind = match *error.kind() {
+                ErrorKind::TransportError => "transport",
+                _ => "other",
+            };
+            self.0.lock().unwrap().push(format!(
+                "failure {} {} {:?} {}",
+                method,
+                metrics.request_size,
+                metrics.response_size,
+                kind
+            ));
+        }
+
+        fn on_cancel(&self, method: &str, _metrics: &CallMetrics) {
+            self.0.lock().unwrap().push(format!("cancel {}", method));
+        }
+    }
+
+    const RESPONSE: &[u8] = br#"{"jsonrpc": "2.0", "id": 1, "result": 7}"#;
+
+    /// Responds to the method "ok", fails "fail" in the transport and never responds to
+    /// anything else.
+    #[derive(Clone)]
+    struct MethodTransport;
+
+    impl Transport for MethodTransport {
+        type Future = future::Either<FutureResult<Vec<u8>, io::Error>, Empty<Vec<u8>, io::Error>>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            1
+        }
+
+        fn send(&self, json_data: Vec<u8>) -> Self::Future {
+            let request = String::from_utf8(json_data).unwrap();
+            if request.contains("\"ok\"") {
+                future::Either::A(future::ok(RESPONSE.to_vec()))
+            } else if request.contains("\"fail\"") {
+                let error = io::Error::new(io::ErrorKind::Other, "Failed");
+                future::Either::A(future::err(error))
+            } else {
+                future::Either::B(future::empty())
+            }
+        }
+    }
+
+    jsonrpc_client!(pub struct InstrumentedClient {
+        pub fn ok(&mut self) -> RpcRequest<u64>;
+        pub fn fail(&mut self) -> RpcRequest<u64>;
+        pub fn hang(&mut self) -> RpcRequest<u64>;
+    });
+
+    #[test]
+    fn reports_calls() {
+        let recorder = Recorder::default();
+        let config = ClientConfig::new().instrumentation(recorder.clone());
+        let client = InstrumentedClient::with_config(MethodTransport, config);
+
+        assert_eq!(7, client.ok().call().unwrap());
+        assert!(client.fail().call().is_err());
+        drop(client.hang());
+        assert!(client.hang().select2(future::ok::<(), ()>(())).wait().is_ok());
+
+        let request_size = br#"{"jsonrpc":"2.0","method":"ok","params":[],"id":1}"#.len();
+        assert_eq!(
+            vec![
+                "start ok".to_owned(),
+                format!("success ok {} Some({})", request_size, RESPONSE.len()),
+                "start fail".to_owned(),
+                format!("failure fail {} None transport", request_size + 2),
+                "start hang".to_owned(),
+                "cancel hang".to_owned(),
+                "start hang".to_owned(),
+                "cancel hang".to_owned(),
+            ],
+            *recorder.0.lock().unwrap()
+        );
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper running user supplied hooks on all requests and responses.
+//!
+//! An [`Interceptor`] sees, and may modify, every outgoing request as JSON before it is handed
+//! to the transport, and every raw response before it is deserialized. Typical uses are signing
+//! requests, stamping extra fields on them, auditing or fixing up responses from servers that
+//! don't quite follow the specification.
+//!
+//! Interceptors are registered on an [`InterceptedTransport`]. Request hooks run in the order
+//! the interceptors were added, response hooks run in the reverse order. So the first
+//! interceptor added is the outermost one, seeing the request first and the response last.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::intercept::{InterceptedTransport, Interceptor};
+//! use serde_json::Value;
+//!
+//! struct ApiKey(String);
+//!
+//! impl Interceptor for ApiKey {
+//!     fn on_request(&self, request: &mut Value) {
+//!         request["api_key"] = Value::from(self.0.clone());
+//!     }
+//! }
+//!
+//! let handle = HttpTransport::new().standalone()?.handle("http://localhost:8080")?;
+//! let transport = InterceptedTransport::new(handle).with(ApiKey("secret".to_owned()));
+//! let mut client = FizzBuzzClient::new(transport);
+//! ```
+//!
+//! [`Interceptor`]: trait.Interceptor.html
+//! [`InterceptedTransport`]: struct.InterceptedTransport.html
+
+use Transport;
+use futures::{Async, Future, Poll};
+use serde_json::{self, Value as JsonValue};
+use std::fmt;
+use std::sync::Arc;
+
+
+/// Hooks called for every request sent through an `InterceptedTransport`.
+///
+/// All methods have empty default implementations, so only the needed ones have to be
+/// implemented.
+pub trait Interceptor: Send + Sync {
+    /// Called with the JSON of an outgoing request before it is sent. Changes made to `request`
+    /// are what gets sent.
+    fn on_request(&self, _request: &mut JsonValue) {}
+
+    /// Called with the raw response to `request` before it is deserialized. Changes made to
+    /// `response` are what gets deserialized.
+    fn on_response(&self, _request: &JsonValue, _response: &mut Vec<u8>) {}
+}
+
+
+/// A transport running a chain of `Interceptor`s on all requests and responses of an inner
+/// transport.
+#[derive(Clone)]
+pub struct InterceptedTransport<T> {
+    transport: T,
+    interceptors: Vec<Arc<Interceptor>>,
+}
+
+impl<T: Transport> InterceptedTransport<T> {
+    /// Wraps `transport` without any interceptors.
+    pub fn new(transport: T) -> Self {
+        InterceptedTransport {
+            transport,
+            interceptors: Vec::new(),
+        }
+    }
+
+    /// Adds `interceptor` last in the chain.
+    pub fn with<I: Interceptor + 'static>(mut self, interceptor: I) -> Self {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for InterceptedTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InterceptedTransport")
+            .field("transport", &self.transport)
+            .field("interceptors", &self.interceptors.len())
+            .finish()
+    }
+}
+
+impl<T: Transport> Transport for InterceptedTransport<T> {
+    type Future = InterceptedFuture<T::Future>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        if self.interceptors.is_empty() {
+            return InterceptedFuture {
+                future: self.transport.send(json_data),
+                request: JsonValue::Null,
+                interceptors: Vec::new(),
+            };
+        }
+
+        let mut request: JsonValue = match serde_json::from_slice(&json_data) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Not intercepting request that is not valid JSON: {}", e);
+                return InterceptedFuture {
+                    future: self.transport.send(json_data),
+                    request: JsonValue::Null,
+                    interceptors: Vec::new(),
+                };
+            }
+        };
+        for interceptor in &self.interceptors {
+            interceptor.on_request(&mut request);
+        }
+        let json_data = serde_json::to_vec(&request).expect("Unable to serialize JSON value");
+        InterceptedFuture {
+            future: self.transport.send(json_data),
+            request,
+            interceptors: self.interceptors.clone(),
+        }
+    }
+}
+
+
+/// The future returned by `InterceptedTransport` send operations.
+pub struct InterceptedFuture<F> {
+    future: F,
+    request: JsonValue,
+    interceptors: Vec<Arc<Interceptor>>,
+}
+
+impl<F: Future<Item = Vec<u8>>> Future for InterceptedFuture<F> {
+    type Item = Vec<u8>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut response = try_ready!(self.future.poll());
+        for interceptor in self.interceptors.iter().rev() {
+            interceptor.on_response(&self.request, &mut response);
+        }
+        Ok(Async::Ready(response))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use test_util::EchoResultTransport;
+
+    struct Stamp(&'static str);
+
+    impl Interceptor for Stamp {
+        fn on_request(&self, request: &mut JsonValue) {
+            if !request["stamps"].is_array() {
+                request["stamps"] = json!([]);
+            }
+            request["stamps"].as_array_mut().unwrap().push(JsonValue::from(self.0));
+        }
+    }
+
+    /// Records the order responses are seen in.
+    struct Audit(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Interceptor for Audit {
+        fn on_response(&self, request: &JsonValue, _response: &mut Vec<u8>) {
+            assert_eq!("ping", request["method"]);
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    /// Replaces the response with one the client can't misunderstand.
+    struct Normalize;
+
+    impl Interceptor for Normalize {
+        fn on_response(&self, _request: &JsonValue, response: &mut Vec<u8>) {
+            *response = br#"{"jsonrpc": "2.0", "id": 1, "result": {"normalized": true}}"#.to_vec();
+        }
+    }
+
+    jsonrpc_client!(pub struct InterceptClient {
+        pub fn ping(&mut self) -> RpcRequest<JsonValue>;
+    });
+
+    #[test]
+    fn request_hooks_in_order() {
+        let transport = InterceptedTransport::new(EchoResultTransport)
+            .with(Stamp("first"))
+            .with(Stamp("second"));
+        let result = InterceptClient::new(transport).ping().call().unwrap();
+        assert_eq!(json!(["first", "second"]), result["stamps"]);
+    }
+
+    #[test]
+    fn response_hooks_in_reverse_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let transport = InterceptedTransport::new(EchoResultTransport)
+            .with(Audit("outer", seen.clone()))
+            .with(Normalize)
+            .with(Audit("inner", seen.clone()));
+        let result = InterceptClient::new(transport).ping().call().unwrap();
+        assert_eq!(json!({"normalized": true}), result);
+        assert_eq!(vec!["inner", "outer"], *seen.lock().unwrap());
+    }
+}
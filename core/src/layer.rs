@@ -0,0 +1,179 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Composable transport wrappers.
+//!
+//! A [`Layer`] wraps any transport in a transport wrapper, so a stack of wrappers can be
+//! described once and put around different base transports, and new wrappers plug into the
+//! stack the same way as the ones in this crate. The settings of the wrappers in this crate are
+//! layers, like a [`RetryPolicy`], a [`RateLimit`] or [`NetworkConditions`], and so is every
+//! closure taking a transport and returning a transport.
+//!
+//! Layers are stacked with [`Layers`]. Each layer added wraps the stack built so far, so the
+//! layer added last is the outermost one, handling requests first and responses last. This is
+//! the same order as in the [`ClientBuilder`], which takes layers with `with_layer`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::layer::{Layer, Layers};
+//!
+//! let layers = Layers::new()
+//!     .layer(RetryPolicy::fixed(Duration::from_millis(100)))
+//!     .layer(|transport| ThrottledTransport::new(transport, Duration::from_millis(10)))
+//!     .layer(|transport| LoggingTransport::new(transport).redact("password"));
+//! let mainnet = FizzBuzzClient::new(layers.wrap(mainnet_handle));
+//! let testnet = FizzBuzzClient::new(layers.wrap(testnet_handle));
+//! ```
+//!
+//! [`Layer`]: trait.Layer.html
+//! [`RetryPolicy`]: ../retry/struct.RetryPolicy.html
+//! [`RateLimit`]: ../rate_limit/struct.RateLimit.html
+//! [`NetworkConditions`]: ../simulate/struct.NetworkConditions.html
+//! [`Layers`]: struct.Layers.html
+//! [`ClientBuilder`]: ../struct.ClientBuilder.html
+
+use Transport;
+
+
+/// Wraps transports of type `T` in a transport wrapper.
+pub trait Layer<T: Transport> {
+    /// The transport wrapping a `T`.
+    type Transport: Transport;
+
+    /// Wraps `transport`.
+    fn layer(&self, transport: T) -> Self::Transport;
+}
+
+impl<F, T, U> Layer<T> for F
+where
+    F: Fn(T) -> U,
+    T: Transport,
+    U: Transport,
+{
+    type Transport = U;
+
+    fn layer(&self, transport: T) -> U {
+        self(transport)
+    }
+}
+
+
+/// A layer returning transports unchanged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Identity;
+
+impl<T: Transport> Layer<T> for Identity {
+    type Transport = T;
+
+    fn layer(&self, transport: T) -> T {
+        transport
+    }
+}
+
+
+/// Two layers, with `Outer` wrapping the transports returned by `Inner`.
+#[derive(Debug, Clone, Copy)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<Inner, Outer> Stack<Inner, Outer> {
+    /// Stacks `outer` on top of `inner`.
+    pub fn new(inner: Inner, outer: Outer) -> Self {
+        Stack { inner, outer }
+    }
+}
+
+impl<T, Inner, Outer> Layer<T> for Stack<Inner, Outer>
+where
+    T: Transport,
+    Inner: Layer<T>,
+    Outer: Layer<Inner::Transport>,
+{
+    type Transport = Outer::Transport;
+
+    fn layer(&self, transport: T) -> Self::Transport {
+        self.outer.layer(self.inner.layer(transport))
+    }
+}
+
+
+/// Builds a stack of layers. Is itself a layer, wrapping transports in the whole stack.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Layers<L> {
+    layer: L,
+}
+
+impl Layers<Identity> {
+    /// Creates an empty stack, leaving transports unchanged.
+    pub fn new() -> Self {
+        Layers { layer: Identity }
+    }
+}
+
+impl<L> Layers<L> {
+    /// Adds `layer` on top of the stack, wrapping the layers added before it.
+    pub fn layer<N>(self, layer: N) -> Layers<Stack<L, N>> {
+        Layers {
+            layer: Stack::new(self.layer, layer),
+        }
+    }
+
+    /// Wraps `transport` in the whole stack.
+    pub fn wrap<T: Transport>(&self, transport: T) -> L::Transport
+    where
+        L: Layer<T>,
+    {
+        self.layer.layer(transport)
+    }
+}
+
+impl<T: Transport, L: Layer<T>> Layer<T> for Layers<L> {
+    type Transport = L::Transport;
+
+    fn layer(&self, transport: T) -> Self::Transport {
+        self.layer.layer(transport)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use test_util::EchoTransport;
+
+    /// A transport wrapper appending a tag to requests, to see the order of the layers.
+    struct Tag<T>(T, u8);
+
+    impl<T: Transport> Transport for Tag<T> {
+        type Future = T::Future;
+        type Error = T::Error;
+
+        fn get_next_id(&self) -> u64 {
+            self.0.get_next_id()
+        }
+
+        fn send(&self, mut json_data: Vec<u8>) -> Self::Future {
+            json_data.push(self.1);
+            self.0.send(json_data)
+        }
+    }
+
+    #[test]
+    fn last_layer_is_outermost() {
+        let layers = Layers::new()
+            .layer(|transport| Tag(transport, b'1'))
+            .layer(Identity)
+            .layer(|transport| Tag(transport, b'2'));
+        let transport = layers.wrap(EchoTransport);
+        assert_eq!(b"21".to_vec(), transport.send(Vec::new()).wait().unwrap());
+    }
+}
@@ -20,8 +20,17 @@
 //! The `jsonrpc-client-core` crate itself and the structs generated by the `jsonrpc_client` macro
 //! are transport agnostic. They can use any type implementing the `Transport` trait.
 //!
-//! The main (and so far only) transport implementation is the Hyper based HTTP implementation
-//! in the [`jsonrpc-client-http`](../jsonrpc_client_http/index.html) crate.
+//! The main transport implementation is the Hyper based HTTP implementation in the
+//! [`jsonrpc-client-http`](../jsonrpc_client_http/index.html) crate. For clients running in a
+//! browser, the [`jsonrpc-client-wasm`](../jsonrpc_client_wasm/index.html) crate provides a transport
+//! based on the `fetch` API.
+//!
+//! # Tracing
+//!
+//! With the "tracing" feature enabled every call is wrapped in a `rpc_call` span from the
+//! [`tracing`](https://docs.rs/tracing) crate, with the method, id and endpoint of the call and
+//! its outcome as fields. The request is handed to the transport within the span, so a transport
+//! can pick up the current trace context and propagate it to the server.
 //!
 //! # Example
 //!
@@ -55,21 +64,39 @@
 
 #![deny(missing_docs)]
 
-#[macro_use]
-extern crate error_chain;
+extern crate bytes;
 #[macro_use]
 extern crate futures;
-extern crate jsonrpc_core;
+/// The JSON-RPC server implementation used by the [`server`](server/index.html) module.
+pub extern crate jsonrpc_core;
+#[macro_use]
+extern crate lazy_static;
 #[macro_use]
 extern crate log;
-extern crate serde;
+#[cfg(feature = "compression")]
+extern crate miniz_oxide;
+extern crate rand;
+// Public for the code generated by the macros of this crate.
+#[doc(hidden)]
+pub extern crate serde;
 #[cfg_attr(test, macro_use)]
 extern crate serde_json;
+extern crate tokio_codec;
+extern crate tokio_io;
+extern crate tokio_timer;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
 use futures::Async;
+use call_span::CallSpan;
+use deadline::Deadline;
 use futures::future::Future;
-use jsonrpc_core::types::{Id, MethodCall, Params, Version};
-use serde_json::Value as JsonValue;
+use instrument::CallInstrumentation;
+use jsonrpc_core::types::{Id, Version};
+use metadata::{ResponseMetadata, ResponseSlot};
+use request::Request;
+use std::time::{Duration, Instant};
+use tokio_timer::Sleep;
 
 /// Contains the main macro of this crate, `jsonrpc_client`.
 #[macro_use]
@@ -77,62 +104,269 @@ mod macros;
 
 /// Module for functions parsing the response to a RPC method call.
 mod response;
+pub use response::ResponseEnvelope;
+use response::ParseOptions;
+
+/// Module with the arguments of calls from generated clients.
+mod arguments;
+#[doc(hidden)]
+pub use arguments::Arguments;
+
+/// Module with descriptions of the methods of generated clients.
+mod method_info;
+pub use method_info::MethodInfo;
+
+/// Module with a pool of buffers reused across calls.
+mod buffer;
+pub use buffer::BufferPool;
+
+/// Module with the serialized form of requests and notifications.
+mod request;
+
+/// Module with the tracing span of a call, a no-op unless the "tracing" feature is enabled.
+mod call_span;
+
+/// Module with the error type of RPC calls.
+mod error;
+pub use error::{Error, ErrorKind, Result};
+use error::ResultExt;
+
+/// Module with a builder for clients on a stack of transport wrappers.
+mod builder;
+pub use builder::{ClientBuilder, FromTransport};
+
+/// Module containing a client calling methods by name, without a generated struct.
+mod client;
+pub use client::{RpcClient, RpcNotification};
+
+/// Module with the configuration of generated clients.
+mod config;
+pub use config::{ClientConfig, EmptyParams, ParamsStyle, Validation};
+
+/// Module containing a transport spreading calls over several endpoints.
+pub mod balance;
+
+/// Module containing a transport wrapper coalescing calls into batches.
+pub mod batch;
+
+/// Module containing type-erased transports, for choosing the transport at runtime.
+pub mod boxed;
+
+/// Module containing a transport wrapper caching the responses of read-only calls.
+pub mod cache;
+
+/// Module containing a transport wrapper failing fast while the inner transport is down.
+pub mod circuit_breaker;
+
+/// Module containing deadlines limiting the total time of calls.
+pub mod deadline;
+
+/// Module keeping track of calls to RPC methods marked as deprecated.
+pub mod deprecation;
+
+/// Module containing a transport multiplexing concurrent calls over one connection.
+pub mod duplex;
+
+/// Module containing a preset for talking to Electrum servers.
+pub mod electrum;
+
+/// Module with typed access to the data of JSON-RPC 2.0 error responses.
+pub mod error_data;
 
 /// Module containing an example client. To show in the docs what a generated struct look like.
 pub mod example;
 
-error_chain! {
-    errors {
-        /// Error in the underlying transport layer.
-        TransportError {
-            description("Unable to send the JSON-RPC 2.0 request")
-        }
-        /// Error while serializing method parameters.
-        SerializeError {
-            description("Unable to serialize the method parameters")
-        }
-        /// Error while deserializing or parsing the response data.
-        ResponseError(msg: &'static str) {
-            description("Unable to deserialize the response into the desired type")
-            display("Unable to deserialize the response: {}", msg)
-        }
-        /// The request was replied to, but with a JSON-RPC 2.0 error.
-        JsonRpcError(error: jsonrpc_core::Error) {
-            description("Method call returned JSON-RPC 2.0 error")
-            display("JSON-RPC 2.0 Error: {} ({})", error.code.description(), error.message)
-        }
-    }
-}
+/// Module containing a transport wrapper failing over from a primary endpoint to backups.
+pub mod failover;
+
+/// Module framing JSON-RPC messages on byte streams.
+pub mod framing;
+
+/// Module with helpers for golden-file testing of generated requests.
+#[cfg(any(test, feature = "golden"))]
+pub mod golden;
+
+/// Module with hooks for collecting metrics about calls.
+pub mod instrument;
+
+/// Module containing a transport wrapper running hooks on all requests and responses.
+pub mod intercept;
+
+/// Module with layers, composing transport wrappers around any transport.
+pub mod layer;
+
+/// Module with a preset for c-lightning nodes, talking to their `lightning-rpc` socket.
+pub mod lightning;
+
+/// Module containing a transport wrapper logging all requests and responses.
+pub mod logging;
 
+/// Module containing a preset for driving language servers.
+pub mod lsp;
+
+/// Module with metadata of single calls and their responses, passed between caller and transport.
+pub mod metadata;
+
+/// Module containing a transport calling methods through an MQTT broker.
+pub mod mqtt;
+
+/// Module containing a transport calling methods by NATS request/reply.
+pub mod nats;
+
+/// Module turning methods returning a page of items at a time into streams of all items.
+pub mod pagination;
+
+/// Module watching the result of a method by calling it repeatedly.
+pub mod polling;
+
+/// Module containing subscriptions to notification streams on persistent connections.
+pub mod pubsub;
+
+/// Module containing a transport sending every call to several endpoints at once.
+pub mod quorum;
+
+/// Module containing a transport wrapper limiting the request rate.
+pub mod rate_limit;
+
+/// Module containing a transport wrapper whose inner transport can be replaced at runtime.
+pub mod reconfigure;
+
+/// Module containing a transport receiving notifications from Redis pub/sub channels.
+pub mod redis;
+
+/// Module containing a transport wrapper reconnecting persistent transports.
+pub mod reconnect;
+
+/// Module containing a transport wrapper that retries failed requests.
+pub mod retry;
+
+/// Module with in-process servers for testing generated clients.
+pub mod server;
+
+/// Module containing a transport wrapper simulating network latency, jitter and bandwidth.
+pub mod simulate;
+
+/// Module containing a transport receiving notifications as server-sent events.
+pub mod sse;
+
+/// Module parsing large responses incrementally as they arrive.
+pub mod streaming;
+
+/// Module with a preset for subscribing to events of Tendermint and Cosmos nodes.
+pub mod tendermint;
+
+/// Module containing a transport wrapper keeping a minimum interval between requests.
+pub mod throttle;
+
+/// Module with the timer used for all delays in this crate.
+mod timer;
+
+/// Module with mock transports for the tests of this crate.
+#[cfg(test)]
+mod test_util;
+
+/// Module containing a transport calling methods on ZeroMQ sockets.
+pub mod zmq;
+
+
+/// A boxed RPC call `Future`, independent of the transport the call is made on. Returned by the
+/// methods of traits declared with the `jsonrpc_client_trait` macro.
+pub type RpcFuture<T, E = Error> = Box<Future<Item = T, Error = E> + Send>;
 
 /// A lazy RPC call `Future`. The actual call has not been sent when an instance of this type
 /// is returned from a client generated by the macro in this crate. This is a `Future` that, when
 /// executed, performs the RPC call.
-pub struct RpcRequest<T, F>(::std::result::Result<InnerRpcRequest<T, F>, Option<Error>>);
+///
+/// The call fails with an error of type `E`, this crate's `Error` unless the client method was
+/// declared with another error type. See [`err_into`](#method.err_into).
+///
+/// Dropping a `RpcRequest` before it has completed cancels the call. The transport future is
+/// dropped with it, and the transport is expected to abort the request and ignore any response
+/// arriving later. The client and transport stay usable for new calls, so timeouts can be
+/// composed around calls from the outside.
+pub struct RpcRequest<T, F, E = Error>(
+    ::std::result::Result<InnerRpcRequest<T, F>, Option<Error>>,
+    ::std::marker::PhantomData<fn() -> E>,
+);
 
-impl<T, E, F> RpcRequest<T, F>
+impl<T, TE, F, E> RpcRequest<T, F, E>
 where
     T: serde::de::DeserializeOwned + Send + 'static,
-    E: ::std::error::Error + Send + 'static,
-    F: Future<Item = Vec<u8>, Error = E> + Send + 'static,
+    TE: ::std::error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = TE> + Send + 'static,
+    E: From<Error>,
 {
     /// Consume this RPC request and run it synchronously. This blocks until the RPC call is done,
     /// then the result of the call is returned.
-    pub fn call(self) -> Result<T> {
+    pub fn call(self) -> ::std::result::Result<T, E> {
         self.wait()
     }
 }
 
-impl<T, E, F> Future for RpcRequest<T, F>
+impl<T, TE, F, E> Future for RpcRequest<T, F, E>
 where
     T: serde::de::DeserializeOwned + Send + 'static,
-    E: ::std::error::Error + Send + 'static,
-    F: Future<Item = Vec<u8>, Error = E> + Send + 'static,
+    TE: ::std::error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = TE> + Send + 'static,
+    E: From<Error>,
 {
     type Item = T;
-    type Error = Error;
+    type Error = E;
 
     fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let output = try_ready!(self.poll_output().map_err(E::from));
+        Ok(Async::Ready(output.result))
+    }
+}
+
+impl<T, TE, F, E> RpcRequest<T, F, E>
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+    TE: ::std::error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = TE> + Send + 'static,
+{
+    /// Turns this request into one failing with an application specific error type, converted
+    /// from this crate's `Error`. Client methods declared as returning `RpcRequest<T, MyError>`
+    /// do this for all their calls.
+    pub fn err_into<E2: From<Error>>(self) -> RpcRequest<T, F, E2> {
+        RpcRequest(self.0, ::std::marker::PhantomData)
+    }
+
+    /// Turns this request into a future that also yields how long the different phases of the
+    /// call took. Useful to find out if the latency of a call comes from the network or from
+    /// handling the JSON.
+    pub fn timed(self) -> TimedRpcRequest<T, F, E> {
+        TimedRpcRequest(self)
+    }
+
+    /// Turns this request into a future that also yields the envelope of the response, all its
+    /// members except `result`. Useful for debugging servers and for reading nonstandard members
+    /// some servers add to their responses.
+    pub fn with_envelope(mut self) -> EnvelopeRpcRequest<T, F, E> {
+        if let Ok(ref mut inner) = self.0 {
+            inner.parse_options.keep_envelope = true;
+        }
+        EnvelopeRpcRequest(self)
+    }
+
+    /// Turns this request into a future that also yields the `ResponseMetadata` the transport
+    /// stored for the response, like its HTTP status and headers. See the
+    /// [`metadata`](metadata/index.html) module.
+    pub fn with_response_metadata(self) -> ResponseMetadataRpcRequest<T, F, E> {
+        ResponseMetadataRpcRequest(self)
+    }
+
+    /// Turns this request into a future failing with a `CallError`, where JSON-RPC 2.0 error
+    /// responses have their `data` member deserialized into `D`. See the
+    /// [`error_data`](error_data/index.html) module.
+    pub fn with_error_data<D>(self) -> error_data::ErrorDataRpcRequest<T, F, D>
+    where
+        D: serde::de::DeserializeOwned,
+    {
+        error_data::ErrorDataRpcRequest::new(RpcRequest(self.0, ::std::marker::PhantomData))
+    }
+
+    fn poll_output(&mut self) -> futures::Poll<CallOutput<T>, Error> {
         match self.0 {
             Ok(ref mut inner) => inner.poll(),
             Err(ref mut error_option) => Err(error_option
@@ -142,17 +376,131 @@ where
     }
 }
 
+/// A `RpcRequest` that yields the `CallTimings` of the call together with the result. Created by
+/// [`RpcRequest::timed`](struct.RpcRequest.html#method.timed).
+pub struct TimedRpcRequest<T, F, E = Error>(RpcRequest<T, F, E>);
+
+impl<T, TE, F, E> Future for TimedRpcRequest<T, F, E>
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+    TE: ::std::error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = TE> + Send + 'static,
+    E: From<Error>,
+{
+    type Item = (T, CallTimings);
+    type Error = E;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let output = try_ready!(self.0.poll_output().map_err(E::from));
+        Ok(Async::Ready((output.result, output.timings)))
+    }
+}
+
+/// A `RpcRequest` that yields the `ResponseEnvelope` of the response together with the result.
+/// Created by [`RpcRequest::with_envelope`](struct.RpcRequest.html#method.with_envelope).
+pub struct EnvelopeRpcRequest<T, F, E = Error>(RpcRequest<T, F, E>);
+
+impl<T, TE, F, E> Future for EnvelopeRpcRequest<T, F, E>
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+    TE: ::std::error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = TE> + Send + 'static,
+    E: From<Error>,
+{
+    type Item = (T, ResponseEnvelope);
+    type Error = E;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let output = try_ready!(self.0.poll_output().map_err(E::from));
+        let envelope = output
+            .envelope
+            .expect("Envelope not kept for EnvelopeRpcRequest");
+        Ok(Async::Ready((output.result, envelope)))
+    }
+}
+
+/// A `RpcRequest` that yields the `ResponseMetadata` of the response together with the result.
+/// Created by
+/// [`RpcRequest::with_response_metadata`](struct.RpcRequest.html#method.with_response_metadata).
+pub struct ResponseMetadataRpcRequest<T, F, E = Error>(RpcRequest<T, F, E>);
+
+impl<T, TE, F, E> Future for ResponseMetadataRpcRequest<T, F, E>
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+    TE: ::std::error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = TE> + Send + 'static,
+    E: From<Error>,
+{
+    type Item = (T, ResponseMetadata);
+    type Error = E;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let output = try_ready!(self.0.poll_output().map_err(E::from));
+        Ok(Async::Ready((output.result, output.response_metadata)))
+    }
+}
+
+/// The time spent in the different phases of a RPC call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallTimings {
+    /// Time spent serializing the request.
+    pub serialize: Duration,
+    /// Time from handing the request to the transport until the transport returned the response.
+    pub transport: Duration,
+    /// Time spent parsing the response and deserializing the result.
+    pub parse: Duration,
+}
+
+/// Everything a successful call produces.
+struct CallOutput<T> {
+    result: T,
+    timings: CallTimings,
+    envelope: Option<ResponseEnvelope>,
+    response_metadata: ResponseMetadata,
+}
+
 struct InnerRpcRequest<T, F> {
     transport_future: F,
     id: Id,
+    parse_options: ParseOptions,
+    serialize_time: Duration,
+    sent_at: Instant,
+    instrumentation: Option<CallInstrumentation>,
+    span: CallSpan,
+    buffer_pool: Option<BufferPool>,
+    deadline: Option<Deadline>,
+    deadline_sleep: Option<Sleep>,
+    response_slot: ResponseSlot,
     _marker: ::std::marker::PhantomData<T>,
 }
 
 impl<T, F> InnerRpcRequest<T, F> {
-    fn new(transport_future: F, id: Id) -> Self {
+    fn new(
+        transport_future: F,
+        id: Id,
+        parse_options: ParseOptions,
+        serialize_time: Duration,
+        instrumentation: Option<CallInstrumentation>,
+        span: CallSpan,
+        buffer_pool: Option<BufferPool>,
+        deadline: Option<Deadline>,
+        response_slot: ResponseSlot,
+    ) -> Self {
+        let deadline_sleep = deadline
+            .as_ref()
+            .map(|deadline| timer::sleep(deadline.remaining()));
         Self {
             transport_future,
             id,
+            parse_options,
+            serialize_time,
+            sent_at: Instant::now(),
+            instrumentation,
+            span,
+            buffer_pool,
+            deadline,
+            deadline_sleep,
+            response_slot,
             _marker: ::std::marker::PhantomData,
         }
     }
@@ -161,24 +509,115 @@ impl<T, F> InnerRpcRequest<T, F> {
 impl<T, E, F> Future for InnerRpcRequest<T, F>
 where
     T: serde::de::DeserializeOwned + Send + 'static,
-    E: ::std::error::Error + Send + 'static,
+    E: ::std::error::Error + Send + Sync + 'static,
     F: Future<Item = Vec<u8>, Error = E> + Send + 'static,
 {
-    type Item = T;
+    type Item = CallOutput<T>;
     type Error = Error;
 
     fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
-        let response_raw = try_ready!(
-            self.transport_future
-                .poll()
-                .chain_err(|| ErrorKind::TransportError)
-        );
+        let span = self.span.clone();
+        let result = span.in_scope(|| self.poll_response());
+        match result {
+            Ok(Async::NotReady) => (),
+            Ok(Async::Ready(_)) => {
+                span.record_success();
+                if let Some(ref mut instrumentation) = self.instrumentation {
+                    instrumentation.success();
+                }
+            }
+            Err(ref e) => {
+                span.record_failure(e);
+                if let Some(ref mut instrumentation) = self.instrumentation {
+                    instrumentation.failure(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl<T, E, F> InnerRpcRequest<T, F>
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+    E: ::std::error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = E> + Send + 'static,
+{
+    fn poll_response(&mut self) -> futures::Poll<CallOutput<T>, Error> {
+        let response_raw = match self.transport_future.poll() {
+            Ok(Async::Ready(response_raw)) => response_raw,
+            Ok(Async::NotReady) => {
+                self.poll_deadline()?;
+                return Ok(Async::NotReady);
+            }
+            Err(e) => {
+                // A transport wrapper giving up because of the deadline reports it.
+                let kind = match self.deadline.as_ref().and_then(Deadline::gave_up) {
+                    Some(remaining) => ErrorKind::DeadlineExceeded(remaining),
+                    None => ErrorKind::TransportError,
+                };
+                return Err(Error::with_source(kind, e));
+            }
+        };
+        if let Some(ref mut instrumentation) = self.instrumentation {
+            instrumentation.set_response_size(response_raw.len());
+        }
+        let transport_time = self.sent_at.elapsed();
         trace!(
             "Deserializing {} byte response to request with id {:?}",
             response_raw.len(),
             self.id
         );
-        response::parse(&response_raw, &self.id).map(|t| Async::Ready(t))
+        let parse_start = Instant::now();
+        let parsed = response::parse(&response_raw, &self.id, self.parse_options);
+        if let Some(ref buffer_pool) = self.buffer_pool {
+            buffer_pool.put(response_raw);
+        }
+        let (result, envelope) = parsed?;
+        let timings = CallTimings {
+            serialize: self.serialize_time,
+            transport: transport_time,
+            parse: parse_start.elapsed(),
+        };
+        debug!(
+            "Call with id {:?} spent {:?} serializing, {:?} in transport and {:?} parsing",
+            self.id,
+            timings.serialize,
+            timings.transport,
+            timings.parse
+        );
+        Ok(Async::Ready(CallOutput {
+            result,
+            timings,
+            envelope,
+            response_metadata: self.response_slot.take(),
+        }))
+    }
+
+    /// Fails if the deadline of the call has passed.
+    fn poll_deadline(&mut self) -> ::std::result::Result<(), Error> {
+        loop {
+            let fired = match self.deadline_sleep {
+                Some(ref mut sleep) => match sleep.poll() {
+                    Ok(Async::Ready(())) => true,
+                    Ok(Async::NotReady) => return Ok(()),
+                    Err(e) => {
+                        warn!("Unable to wait for the deadline of call {:?}: {}", self.id, e);
+                        false
+                    }
+                },
+                None => return Ok(()),
+            };
+            let deadline = self.deadline.as_ref().expect("Deadline sleep without deadline");
+            self.deadline_sleep = if !fired {
+                None
+            } else if deadline.is_exceeded() {
+                return Err(ErrorKind::DeadlineExceeded(Duration::new(0, 0)).into());
+            } else {
+                // The timer can't wait arbitrarily long, keep waiting for far deadlines.
+                Some(timer::sleep(deadline.remaining()))
+            };
+        }
     }
 }
 
@@ -190,27 +629,88 @@ pub trait Transport {
     type Future: Future<Item = Vec<u8>, Error = Self::Error> + Send + 'static;
 
     /// The type of error that this transport emits if it fails.
-    type Error: ::std::error::Error + Send + 'static;
+    type Error: ::std::error::Error + Send + Sync + 'static;
 
     /// Returns an id that has not yet been used on this transport. Used by the RPC clients
     /// to fill in the "id" field of a request.
-    fn get_next_id(&mut self) -> u64;
+    ///
+    /// Clones of a client share one transport and may call this concurrently, so the ids must be
+    /// unique across threads, for example by taking them from an atomic counter.
+    fn get_next_id(&self) -> u64;
+
+    /// Returns a description of where this transport sends its requests, such as a URI. Used to
+    /// annotate the tracing span of each call. Returns `None` by default.
+    fn endpoint(&self) -> Option<String> {
+        None
+    }
 
     /// Sends the given data over the transport and returns a future that will complete with the
     /// response to the request, or the transport specific error if something went wrong.
+    ///
+    /// Dropping the returned future before it completes must cancel the request as far as the
+    /// transport is able to, and must leave the transport usable. A transport multiplexing many
+    /// requests over one connection should forget the request id and discard a late response.
     fn send(&self, json_data: Vec<u8>) -> Self::Future;
 }
 
+/// Lets a client borrow a transport.
+impl<'a, T: Transport + ?Sized> Transport for &'a mut T {
+    type Future = T::Future;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        (**self).get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        (**self).endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        (**self).send(json_data)
+    }
+}
+
+/// Lets transport wrappers requiring a `Clone` transport share one that is not.
+impl<T: Transport + ?Sized> Transport for ::std::sync::Arc<T> {
+    type Future = T::Future;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        (**self).get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        (**self).endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        (**self).send(json_data)
+    }
+}
+
 
 /// Prepares a lazy `RpcRequest` with a given transport, method and parameters.
 /// The call is not sent to the transport until the returned `RpcRequest` is actually executed,
 /// either as a `Future` or by calling `RpcRequest::call()`.
 ///
-/// # Not intended for direct use
-/// This is being called from the client structs generated by the `jsonrpc_client` macro. This
-/// function is not intended to be used directly, only the generated structs should call this.
+/// This is what the client structs generated by the `jsonrpc_client` macro call for every method.
+/// It can also be used directly to call methods by name, for APIs too large or changing too often
+/// to list all methods in a client struct. Clients generated by the macro offer the same through
+/// their `call_raw` method, and so does the generic [`RpcClient`](struct.RpcClient.html).
+///
+/// The parameters are serialized to JSON. An array, object or `null` is sent as the `params` of
+/// the request and any other value is sent as the only positional parameter.
+/// The result is deserialized into `R`, use `serde_json::Value` to get it untyped.
+///
+/// ```rust,ignore
+/// let config = ClientConfig::default();
+/// let balance: serde_json::Value =
+///     call_method(&transport, &config, "getbalance".to_owned(), ("alice", 6)).call()?;
+/// ```
 pub fn call_method<T, P, R>(
-    transport: &mut T,
+    transport: &T,
+    config: &ClientConfig,
     method: String,
     params: P,
 ) -> RpcRequest<R, T::Future>
@@ -219,21 +719,24 @@ where
     P: serde::Serialize,
     R: serde::de::DeserializeOwned + Send + 'static,
 {
-    let id = Id::Num(transport.get_next_id());
-    trace!("Serializing call to method \"{}\" with id {:?}", method, id);
-    let request_serialization_result = serialize_request(id.clone(), method, params)
-        .chain_err(|| ErrorKind::SerializeError);
-    match request_serialization_result {
-        Err(e) => RpcRequest(Err(Some(e))),
-        Ok(request_raw) => {
-            let transport_future = transport.send(request_raw);
-            RpcRequest(Ok(InnerRpcRequest::new(transport_future, id)))
-        }
-    }
+    call_method_with(
+        transport,
+        config,
+        config.validation,
+        Version::V2,
+        method,
+        params,
+        |json_data| transport.send(json_data),
+    )
 }
 
+/// Like [`call_method`](fn.call_method.html), but sends a JSON-RPC 1.0 request. Called by the
+/// client structs generated by the `jsonrpc_client_v1` macro.
+///
+/// Responses to JSON-RPC 1.0 requests are always validated leniently, see `Validation`.
 pub fn call_method_v1<T, P, R>(
-    transport: &mut T,
+    transport: &T,
+    config: &ClientConfig,
     method: String,
     params: P,
 ) -> RpcRequest<R, T::Future>
@@ -242,94 +745,131 @@ where
     P: serde::Serialize,
     R: serde::de::DeserializeOwned + Send + 'static,
 {
-    let id = Id::Num(transport.get_next_id());
-    trace!("Serializing call to method \"{}\" with id {:?}", method, id);
-    let request_serialization_result = serialize_request_v1(id.clone(), method, params)
-        .chain_err(|| ErrorKind::SerializeError);
-    match request_serialization_result {
-        Err(e) => RpcRequest(Err(Some(e))),
-        Ok(request_raw) => {
-            let transport_future = transport.send(request_raw);
-            RpcRequest(Ok(InnerRpcRequest::new(transport_future, id)))
-        }
-    }
-}
-
-/// Creates a JSON-RPC 2.0 request to the given method with the given parameters.
-fn serialize_request<P>(
-    id: Id,
-    method: String,
-    params: P,
-) -> ::std::result::Result<Vec<u8>, serde_json::error::Error>
-where
-    P: serde::Serialize,
-{
-    let serialized_params = match serde_json::to_value(params)? {
-        JsonValue::Null => None,
-        JsonValue::Array(vec) => Some(Params::Array(vec)),
-        JsonValue::Object(obj) => Some(Params::Map(obj)),
-        value => Some(Params::Array(vec![value])),
-    };
-    let method_call = MethodCall {
-        jsonrpc: Some(Version::V2),
+    // JSON-RPC 1.0 responses never pass strict 2.0 validation.
+    call_method_with(
+        transport,
+        config,
+        Validation::Lenient,
+        Version::V1,
         method,
-        params: serialized_params,
-        id,
-    };
-    serde_json::to_vec(&method_call)
+        params,
+        |json_data| transport.send(json_data),
+    )
 }
 
-fn serialize_request_v1<P>(
-    id: Id,
+/// Prepares a call like `call_method`, handing the serialized request to `send` instead of
+/// `Transport::send`.
+pub(crate) fn call_method_with<T, P, R, F, S>(
+    transport: &T,
+    config: &ClientConfig,
+    validation: Validation,
+    version: Version,
     method: String,
     params: P,
-) -> ::std::result::Result<Vec<u8>, serde_json::error::Error>
+    send: S,
+) -> RpcRequest<R, F>
 where
+    T: Transport,
     P: serde::Serialize,
+    R: serde::de::DeserializeOwned + Send + 'static,
+    S: FnOnce(Vec<u8>) -> F,
 {
-    let serialized_params = match serde_json::to_value(params)? {
-        JsonValue::Null => None,
-        JsonValue::Array(vec) => Some(Params::Array(vec)),
-        JsonValue::Object(obj) => Some(Params::Map(obj)),
-        value => Some(Params::Array(vec![value])),
-    };
-    let method_call = MethodCall {
-        jsonrpc: Some(Version::V1),
-        method,
-        params: serialized_params,
-        id,
+    let mut instrumentation = config
+        .instrumentation
+        .as_ref()
+        .map(|instrumentation| CallInstrumentation::start(instrumentation, &method));
+    let deadline = deadline::for_call(config.timeout);
+    let id = Id::Num(transport.get_next_id());
+    let span = CallSpan::new(&method, &id, || transport.endpoint());
+    trace!("Serializing call to method \"{}\" with id {:?}", method, id);
+    let serialize_start = Instant::now();
+    let request = Request {
+        version,
+        method: &method,
+        params: &params,
+        id: Some(&id),
+        empty_params: config.empty_params,
     };
-    serde_json::to_vec(&method_call)
+    let mut request_raw = take_buffer(config);
+    match request.serialize_into(&mut request_raw).chain_err(|| ErrorKind::SerializeError) {
+        Err(e) => {
+            if let Some(ref buffer_pool) = config.buffer_pool {
+                buffer_pool.put(request_raw);
+            }
+            span.record_failure(&e);
+            if let Some(ref mut instrumentation) = instrumentation {
+                instrumentation.failure(&e);
+            }
+            RpcRequest(Err(Some(e)), ::std::marker::PhantomData)
+        }
+        Ok(()) => {
+            let serialize_time = serialize_start.elapsed();
+            if let Some(ref mut instrumentation) = instrumentation {
+                instrumentation.set_request_size(request_raw.len());
+            }
+            // Sent within the span, with the deadline and the response slot, so transports can
+            // propagate the trace context, respect the deadline and report on the response.
+            let response_slot = ResponseSlot::default();
+            let transport_future = span.in_scope(|| {
+                deadline::in_scope(deadline.clone(), || {
+                    metadata::respond_in(Some(response_slot.clone()), || send(request_raw))
+                })
+            });
+            let parse_options = ParseOptions {
+                validation,
+                missing_result_as_null: config.missing_result_as_null,
+                keep_envelope: false,
+            };
+            let inner = InnerRpcRequest::new(
+                transport_future,
+                id,
+                parse_options,
+                serialize_time,
+                instrumentation,
+                span,
+                config.buffer_pool.clone(),
+                deadline,
+                response_slot,
+            );
+            RpcRequest(Ok(inner), ::std::marker::PhantomData)
+        }
+    }
 }
 
+/// Takes a buffer to serialize a request into from the buffer pool of the configuration, if any.
+fn take_buffer(config: &ClientConfig) -> Vec<u8> {
+    match config.buffer_pool {
+        Some(ref buffer_pool) => buffer_pool.take(),
+        None => Vec::new(),
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::Value as JsonValue;
     use std::io;
+    use std::sync::{Arc, Mutex};
+    use test_util::EchoResultTransport;
 
     pub type BoxFuture<T, E> = Box<Future<Item = T, Error = E> + Send>;
 
-    /// A test transport that just echoes back a response containing the entire request as the
-    /// result.
-    #[derive(Clone)]
-    struct EchoTransport;
+    /// Echoes requests like `EchoResultTransport` and records the address of every request
+    /// buffer.
+    #[derive(Clone, Default)]
+    struct AddressRecordingTransport(Arc<Mutex<Vec<usize>>>);
 
-    impl Transport for EchoTransport {
+    impl Transport for AddressRecordingTransport {
         type Future = BoxFuture<Vec<u8>, io::Error>;
         type Error = io::Error;
 
-        fn get_next_id(&mut self) -> u64 {
+        fn get_next_id(&self) -> u64 {
             1
         }
 
         fn send(&self, json_data: Vec<u8>) -> Self::Future {
-            let json = json!({
-                "jsonrpc": "2.0",
-                "id": 1,
-                "result": serde_json::from_slice::<JsonValue>(&json_data).unwrap(),
-            });
-            Box::new(futures::future::ok(serde_json::to_vec(&json).unwrap()))
+            self.0.lock().unwrap().push(json_data.as_ptr() as usize);
+            Box::new(EchoResultTransport.send(json_data))
         }
     }
 
@@ -341,7 +881,7 @@ mod tests {
         type Future = BoxFuture<Vec<u8>, io::Error>;
         type Error = io::Error;
 
-        fn get_next_id(&mut self) -> u64 {
+        fn get_next_id(&self) -> u64 {
             1
         }
 
@@ -367,7 +907,7 @@ mod tests {
         type Future = BoxFuture<Vec<u8>, io::Error>;
         type Error = io::Error;
 
-        fn get_next_id(&mut self) -> u64 {
+        fn get_next_id(&self) -> u64 {
             1
         }
 
@@ -385,7 +925,7 @@ mod tests {
 
     #[test]
     fn echo() {
-        let mut client = TestRpcClient::new(EchoTransport);
+        let client = TestRpcClient::new(EchoResultTransport);
         let result = client.ping("Hello").call().unwrap();
         if let JsonValue::Object(map) = result {
             assert_eq!(Some(&JsonValue::from("2.0")), map.get("jsonrpc"));
@@ -398,9 +938,227 @@ mod tests {
         }
     }
 
+    jsonrpc_client!(pub struct PrefixedClient (prefix = "test_") {
+        pub fn ping(&mut self, arg0: &str) -> RpcRequest<JsonValue>;
+    });
+
+    #[test]
+    fn method_prefix() {
+        let client = PrefixedClient::new(EchoResultTransport);
+        let result = client.ping("Hello").call().unwrap();
+        assert_eq!(Some(&JsonValue::from("test_ping")), result.get("method"));
+        let result: JsonValue = client.call_raw("other", ()).call().unwrap();
+        assert_eq!(Some(&JsonValue::from("other")), result.get("method"));
+    }
+
+    #[test]
+    fn method_infos() {
+        let methods = ServedClient::<EchoResultTransport>::METHODS;
+        assert_eq!(2, methods.len());
+        assert_eq!("test_greet", methods[0].name);
+        assert_eq!(&["name", "times"], methods[0].params);
+        assert_eq!(2, methods[0].arity());
+        assert!(methods[0].idempotent);
+        let fail = MethodInfo::find(methods, "test_fail").unwrap();
+        assert_eq!(0, fail.arity());
+        assert!(!fail.idempotent);
+        assert_eq!(None, MethodInfo::find(methods, "fail"));
+    }
+
+    jsonrpc_client!(pub struct ParentClient {
+        pub mod test: PrefixedClient;
+        pub fn ping(&mut self, arg0: &str) -> RpcRequest<JsonValue>;
+    });
+
+    #[test]
+    fn namespace() {
+        let client = ParentClient::new(EchoResultTransport);
+        let result = client.test().ping("Hello").call().unwrap();
+        assert_eq!(Some(&JsonValue::from("test_ping")), result.get("method"));
+        let result = client.ping("Hello").call().unwrap();
+        assert_eq!(Some(&JsonValue::from("ping")), result.get("method"));
+    }
+
+    /// Echoes requests like `EchoResultTransport`, with ids from a counter.
+    struct CountingTransport(::std::sync::atomic::AtomicUsize);
+
+    impl Transport for CountingTransport {
+        type Future = BoxFuture<Vec<u8>, io::Error>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            self.0.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst) as u64
+        }
+
+        fn send(&self, json_data: Vec<u8>) -> Self::Future {
+            Box::new(EchoResultTransport.send(json_data))
+        }
+    }
+
+    #[test]
+    fn clones_share_transport() {
+        let client = TestRpcClient::new(CountingTransport(Default::default()));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let client = client.clone();
+                ::std::thread::spawn(move || {
+                    (0..10)
+                        .map(|_| client.ping("Hello").call().unwrap()["id"].as_u64().unwrap())
+                        .collect::<Vec<u64>>()
+                })
+            })
+            .collect();
+        let mut ids: Vec<u64> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(40, ids.len());
+    }
+
+    jsonrpc_client!(pub struct SharedClient {
+        pub fn ping(&self, arg0: &str) -> RpcRequest<JsonValue>;
+    });
+
+    #[test]
+    fn call_through_arc() {
+        let client = ::std::sync::Arc::new(SharedClient::new(EchoResultTransport));
+        let shared: &SharedClient<EchoResultTransport> = &client;
+        let result = shared.ping("Hello").call().unwrap();
+        assert_eq!(Some(&JsonValue::from("ping")), result.get("method"));
+        let result = client.call_raw::<_, JsonValue>("pong", ()).call().unwrap();
+        assert_eq!(Some(&JsonValue::from("pong")), result.get("method"));
+    }
+
+    jsonrpc_client!(
+        pub struct ServedClient (prefix = "test_") {
+            #[rpc(deprecated_since = "1.0", idempotent)]
+            pub fn greet(&mut self, name: &str, times: u64) -> RpcRequest<String>;
+            pub fn fail(&mut self) -> RpcRequest<bool>;
+        }
+
+        pub trait TestServer;
+    );
+
+    struct MockServer;
+
+    type ServerResult<T> = ::std::result::Result<T, jsonrpc_core::Error>;
+
+    impl TestServer for MockServer {
+        fn greet(&self, name: &str, times: u64) -> ServerResult<String> {
+            Ok(format!("Hello {}", name).repeat(times as usize))
+        }
+
+        fn fail(&self) -> ServerResult<bool> {
+            Err(jsonrpc_core::Error::invalid_request())
+        }
+    }
+
+    #[test]
+    fn server_trait() {
+        let transport = server::LocalTransport::new(MockServer.into_io_handler());
+        let client = ServedClient::new(transport.clone());
+        assert_eq!("Hello xHello x", client.greet("x", 2).call().unwrap());
+        match client.fail().call().unwrap_err().kind() {
+            ErrorKind::JsonRpcError(e) => {
+                assert_eq!(jsonrpc_core::ErrorCode::InvalidRequest, e.code)
+            }
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+
+        let config = ClientConfig::new().params_style(ParamsStyle::Named);
+        let client = ServedClient::with_config(transport, config);
+        assert_eq!("Hello y", client.greet("y", 1).call().unwrap());
+    }
+
+    jsonrpc_client_trait!(
+        pub trait Ping {
+            /// Pings the server.
+            #[rpc(deprecated_since = "1.0")]
+            fn ping(&mut self, arg0: &str) -> RpcRequest<JsonValue>;
+        }
+
+        pub struct PingClient;
+    );
+
+    /// A mock implementation of the same trait as `PingClient`.
+    struct MockPing;
+
+    impl Ping for MockPing {
+        fn ping(&mut self, arg0: &str) -> RpcFuture<JsonValue> {
+            Box::new(futures::future::ok(json!({"method": "mock", "params": [arg0]})))
+        }
+    }
+
+    fn ping_method<P: Ping>(client: &mut P) -> JsonValue {
+        client.ping("Hello").wait().unwrap()["method"].clone()
+    }
+
+    #[test]
+    fn trait_client() {
+        let mut client = PingClient::new(EchoResultTransport);
+        assert_eq!(JsonValue::from("ping"), ping_method(&mut client));
+        assert_eq!(JsonValue::from("mock"), ping_method(&mut MockPing));
+    }
+
+    #[test]
+    fn named_params() {
+        let config = ClientConfig::new().params_style(ParamsStyle::Named);
+        let client = TestRpcClient::with_config(EchoResultTransport, config);
+        let result = client.ping("Hello").call().unwrap();
+        assert_eq!(Some(&json!({"arg0": "Hello"})), result.get("params"));
+    }
+
+    #[test]
+    fn with_envelope() {
+        let client = TestRpcClient::new(EchoResultTransport);
+        let (result, envelope) = client.ping("Hello").with_envelope().wait().unwrap();
+        assert_eq!(Some(&JsonValue::from("ping")), result.get("method"));
+        assert_eq!(Some(JsonValue::from("2.0")), envelope.jsonrpc);
+        assert_eq!(JsonValue::from(1), envelope.id);
+        assert!(envelope.extra.is_empty());
+    }
+
+    #[test]
+    fn buffer_pool() {
+        let pool = BufferPool::new();
+        let buffer = Vec::with_capacity(256);
+        let address = buffer.as_ptr() as usize;
+        pool.put(buffer);
+
+        let transport = AddressRecordingTransport::default();
+        let config = ClientConfig::new().buffer_pool(pool.clone());
+        let client = TestRpcClient::with_config(transport.clone(), config);
+        client.ping("Hello").call().unwrap();
+        assert_eq!(vec![address], *transport.0.lock().unwrap());
+        // The response buffer was returned to the pool.
+        assert_eq!(1, pool.len());
+    }
+
+    #[test]
+    fn call_raw() {
+        let client = TestRpcClient::new(EchoResultTransport);
+        let result: JsonValue = client.call_raw("unlisted", ("Hello", 2)).call().unwrap();
+        assert_eq!(Some(&JsonValue::from("unlisted")), result.get("method"));
+        assert_eq!(Some(&json!(["Hello", 2])), result.get("params"));
+    }
+
+    #[test]
+    fn call_method_by_name() {
+        let config = ClientConfig::default();
+        let params = json!({"name": "Hello"});
+        let result: JsonValue =
+            call_method(&mut EchoResultTransport, &config, "named".to_owned(), params.clone())
+                .call()
+                .unwrap();
+        assert_eq!(Some(&JsonValue::from("named")), result.get("method"));
+        assert_eq!(Some(&params), result.get("params"));
+    }
+
     #[test]
     fn invalid_request() {
-        let mut client = TestRpcClient::new(InvalidRequestTransport);
+        let client = TestRpcClient::new(InvalidRequestTransport);
         let error = client.ping("").call().unwrap_err();
         if let &ErrorKind::JsonRpcError(ref json_error) = error.kind() {
             use jsonrpc_core::ErrorCode;
@@ -412,9 +1170,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn timed() {
+        let client = TestRpcClient::new(EchoResultTransport);
+        let (result, timings) = client.ping("Hello").timed().wait().unwrap();
+        assert_eq!(Some(&JsonValue::from("ping")), result.get("method"));
+        assert!(timings.serialize + timings.parse < Duration::from_secs(1));
+    }
+
+    /// An application specific error type.
+    #[derive(Debug, PartialEq)]
+    enum PingError {
+        Rejected(String),
+        Failed,
+    }
+
+    impl From<Error> for PingError {
+        fn from(error: Error) -> Self {
+            match *error.kind() {
+                ErrorKind::JsonRpcError(ref error) => PingError::Rejected(error.message.clone()),
+                _ => PingError::Failed,
+            }
+        }
+    }
+
+    jsonrpc_client!(pub struct CustomErrorClient {
+        pub fn ping(&mut self, arg0: &str) -> RpcRequest<JsonValue, PingError>;
+    });
+
+    #[test]
+    fn custom_error_type() {
+        let client = CustomErrorClient::new(InvalidRequestTransport);
+        let error = client.ping("").call().unwrap_err();
+        assert_eq!(PingError::Rejected("This was an invalid request".to_owned()), error);
+
+        let client = CustomErrorClient::new(ErrorTransport);
+        assert_eq!(PingError::Failed, client.ping("").wait().unwrap_err());
+    }
+
     #[test]
     fn transport_error() {
-        let mut client = TestRpcClient::new(ErrorTransport);
+        let client = TestRpcClient::new(ErrorTransport);
         match client.ping("").call().unwrap_err().kind() {
             &ErrorKind::TransportError => (),
             _ => panic!("Wrong error kind"),
@@ -0,0 +1,183 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A preset for talking to c-lightning (Core Lightning) nodes over their `lightning-rpc` socket.
+//!
+//! `lightningd` serves JSON-RPC on a Unix domain socket in its network directory, by default
+//! `~/.lightning/bitcoin/lightning-rpc`. It does not delimit its messages: responses end with
+//! two newlines, but the JSON of large responses is flushed in pieces, and older versions spread
+//! it over several lines. [`connect`] therefore frames the socket with a [`JsonStreamCodec`],
+//! which finds the end of every message by scanning its JSON.
+//!
+//! Unless started with `allow-deprecated-apis=true`, `lightningd` fails calls to deprecated
+//! commands, and calls using deprecated parameters, with an error rather than by leaving them
+//! out. [`deprecated_api`] tells such errors apart from the node not having the command at all.
+//! Other errors carry command specific fields in their `data`, which can be deserialized with
+//! [`RpcRequest::with_error_data`].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::lightning;
+//!
+//! let path = lightning::rpc_file(home_dir.join(".lightning"), "bitcoin");
+//! let stream = UnixStream::connect(path, &core.handle())?;
+//! let (transport, connection) = lightning::connect(stream);
+//! core.handle().spawn(connection.map_err(|e| error!("lightningd connection failed: {}", e)));
+//!
+//! let mut client = LightningClient::new(transport);
+//! let info = core.run(client.getinfo())?;
+//! ```
+//!
+//! [`connect`]: fn.connect.html
+//! [`deprecated_api`]: fn.deprecated_api.html
+//! [`JsonStreamCodec`]: ../framing/struct.JsonStreamCodec.html
+//! [`RpcRequest::with_error_data`]: ../struct.RpcRequest.html#method.with_error_data
+
+use {Error, ErrorKind};
+use duplex::DuplexTransport;
+use framing::{self, JsonStreamConnection};
+use jsonrpc_core::ErrorCode;
+use std::path::{Path, PathBuf};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The name of the socket file in the network directory of `lightningd`.
+pub const RPC_FILE: &str = "lightning-rpc";
+
+
+/// Returns the path of the `lightning-rpc` socket of a node with the lightning directory
+/// `lightning_dir` running on `network`, like `bitcoin` or `regtest`.
+pub fn rpc_file<P: AsRef<Path>>(lightning_dir: P, network: &str) -> PathBuf {
+    lightning_dir.as_ref().join(network).join(RPC_FILE)
+}
+
+/// Creates a `DuplexTransport` talking to `lightningd` over `io`, a connection to its
+/// `lightning-rpc` socket. Returns the transport and the future driving the connection.
+pub fn connect<T>(io: T) -> (DuplexTransport, JsonStreamConnection<T>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    framing::json_stream(io)
+}
+
+/// Returns the message of `error` if it is `lightningd` refusing a deprecated command or
+/// parameter, which it does unless started with `allow-deprecated-apis=true`.
+pub fn deprecated_api(error: &Error) -> Option<&str> {
+    match *error.kind() {
+        ErrorKind::JsonRpcError(ref error) => match error.code {
+            ErrorCode::MethodNotFound | ErrorCode::InvalidParams
+                if error.message.to_lowercase().contains("deprecated") =>
+            {
+                Some(&error.message)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Async, Future};
+    use jsonrpc_core;
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use RpcClient;
+
+    /// A socket reading the given chunks once a request has been written to it, and discarding
+    /// everything written.
+    struct ChunkedSocket {
+        chunks: VecDeque<&'static [u8]>,
+        written: bool,
+    }
+
+    impl Read for ChunkedSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.written {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            match self.chunks.pop_front() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                }
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    impl Write for ChunkedSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written = true;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for ChunkedSocket {}
+
+    impl AsyncWrite for ChunkedSocket {
+        fn shutdown(&mut self) -> ::futures::Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn response_in_pieces() {
+        let chunks = vec![
+            &b"{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\n  \"alias\":"[..],
+            &b" \"NODE\",\n  \"blockheight\": 800000\n}}\n\n"[..],
+        ];
+        let socket = ChunkedSocket {
+            chunks: chunks.into_iter().collect(),
+            written: false,
+        };
+        let (transport, mut connection) = connect(socket);
+        let mut client = RpcClient::new(transport);
+        let mut call = client.call::<_, ::serde_json::Value>("getinfo", ());
+
+        // Writes the request, and then reads the response.
+        future::lazy(|| connection.poll()).wait().unwrap();
+        future::lazy(|| connection.poll()).wait().unwrap();
+        let info = match future::lazy(|| call.poll()).wait().unwrap() {
+            Async::Ready(info) => info,
+            Async::NotReady => panic!("Response not routed to the call"),
+        };
+        assert_eq!(json!({"alias": "NODE", "blockheight": 800000}), info);
+    }
+
+    #[test]
+    fn deprecated_api_errors() {
+        let error = |code, message: &str| {
+            Error::from(ErrorKind::JsonRpcError(jsonrpc_core::Error {
+                code,
+                message: message.to_owned(),
+                data: None,
+            }))
+        };
+        let deprecated = error(ErrorCode::MethodNotFound, "Command \"listpays\" is deprecated");
+        assert_eq!(
+            Some("Command \"listpays\" is deprecated"),
+            deprecated_api(&deprecated)
+        );
+        let unknown = error(ErrorCode::MethodNotFound, "Unknown command 'listpays'");
+        assert_eq!(None, deprecated_api(&unknown));
+        assert_eq!(None, deprecated_api(&Error::from(ErrorKind::TransportError)));
+    }
+
+    #[test]
+    fn rpc_file_path() {
+        let path = rpc_file("/home/user/.lightning", "regtest");
+        assert_eq!(Path::new("/home/user/.lightning/regtest/lightning-rpc"), path);
+    }
+}
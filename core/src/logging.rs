@@ -0,0 +1,265 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper logging all traffic of a transport as pretty-printed JSON.
+//!
+//! [`LoggingTransport`] logs every request together with its response, or the error it failed
+//! with, and the time the inner transport took to answer. Values of object fields with redacted
+//! names, like passwords and API keys, are replaced before logging, at any depth of the JSON.
+//! Long bodies can be truncated to keep the log readable. Nothing is formatted unless the
+//! configured log level is enabled for this module.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::logging::LoggingTransport;
+//!
+//! let handle = HttpTransport::new().standalone()?.handle("http://localhost:8332")?;
+//! let transport = LoggingTransport::new(handle)
+//!     .max_body_length(4096)
+//!     .redact("passphrase");
+//! let mut client = WalletClient::new(transport);
+//! ```
+//!
+//! [`LoggingTransport`]: struct.LoggingTransport.html
+
+use Transport;
+use futures::{Async, Future, Poll};
+use log::Level;
+use serde_json::{self, Value as JsonValue};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// What redacted values are replaced with.
+const REDACTED: &str = "[redacted]";
+
+
+/// How a `LoggingTransport` formats the bodies it logs.
+#[derive(Debug, Clone, Default)]
+struct BodyFormat {
+    max_length: Option<usize>,
+    redacted: Vec<String>,
+}
+
+impl BodyFormat {
+    /// Pretty-prints `body` with redacted values replaced, and truncates the result. Bodies that
+    /// are not JSON are logged as text.
+    fn format(&self, body: &[u8]) -> String {
+        let mut formatted = match serde_json::from_slice::<JsonValue>(body) {
+            Ok(mut json) => {
+                self.redact(&mut json);
+                serde_json::to_string_pretty(&json).expect("Unable to serialize JSON value")
+            }
+            Err(_) => String::from_utf8_lossy(body).into_owned(),
+        };
+        if let Some(max_length) = self.max_length {
+            if formatted.len() > max_length {
+                let mut end = max_length;
+                while !formatted.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let truncated = formatted.len() - end;
+                formatted.truncate(end);
+                formatted.push_str(&format!("... ({} bytes truncated)", truncated));
+            }
+        }
+        formatted
+    }
+
+    fn redact(&self, json: &mut JsonValue) {
+        match *json {
+            JsonValue::Object(ref mut object) => for (name, value) in object.iter_mut() {
+                if self.redacted.iter().any(|redacted| redacted == name) {
+                    *value = JsonValue::from(REDACTED);
+                } else {
+                    self.redact(value);
+                }
+            },
+            JsonValue::Array(ref mut array) => for value in array {
+                self.redact(value);
+            },
+            _ => (),
+        }
+    }
+}
+
+
+/// A transport logging all requests and responses of an inner transport.
+///
+/// Logs at debug level by default. No bodies are truncated or redacted by default.
+#[derive(Debug, Clone)]
+pub struct LoggingTransport<T> {
+    transport: T,
+    level: Level,
+    format: Arc<BodyFormat>,
+}
+
+impl<T: Transport> LoggingTransport<T> {
+    /// Wraps `transport`, logging its traffic at debug level.
+    pub fn new(transport: T) -> Self {
+        LoggingTransport {
+            transport,
+            level: Level::Debug,
+            format: Arc::new(BodyFormat::default()),
+        }
+    }
+
+    /// Sets the level to log at.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Truncates the formatted requests and responses to at most `max_length` bytes each.
+    pub fn max_body_length(mut self, max_length: usize) -> Self {
+        Arc::make_mut(&mut self.format).max_length = Some(max_length);
+        self
+    }
+
+    /// Replaces the values of all object fields named `name` before logging.
+    pub fn redact<S: Into<String>>(mut self, name: S) -> Self {
+        Arc::make_mut(&mut self.format).redacted.push(name.into());
+        self
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport> Transport for LoggingTransport<T> {
+    type Future = LoggingFuture<T::Future>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let pending = if log_enabled!(self.level) {
+            Some(PendingLog {
+                level: self.level,
+                format: self.format.clone(),
+                endpoint: self.transport.endpoint(),
+                request: self.format.format(&json_data),
+                sent_at: Instant::now(),
+            })
+        } else {
+            None
+        };
+        LoggingFuture {
+            future: self.transport.send(json_data),
+            pending,
+        }
+    }
+}
+
+
+/// A request waiting for its response to be logged together with it.
+struct PendingLog {
+    level: Level,
+    format: Arc<BodyFormat>,
+    endpoint: Option<String>,
+    request: String,
+    sent_at: Instant,
+}
+
+impl PendingLog {
+    fn header(&self) -> String {
+        match self.endpoint {
+            Some(ref endpoint) => format!("RPC request to {}", endpoint),
+            None => "RPC request".to_owned(),
+        }
+    }
+}
+
+
+/// The future returned by `LoggingTransport` send operations.
+pub struct LoggingFuture<F> {
+    future: F,
+    pending: Option<PendingLog>,
+}
+
+impl<F> Future for LoggingFuture<F>
+where
+    F: Future<Item = Vec<u8>>,
+    F::Error: ::std::fmt::Display,
+{
+    type Item = Vec<u8>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = self.future.poll();
+        if let Some(pending) = self.pending.take() {
+            match result {
+                Ok(Async::NotReady) => self.pending = Some(pending),
+                Ok(Async::Ready(ref response)) => log!(
+                    pending.level,
+                    "{}:\n{}\nResponse after {:?}:\n{}",
+                    pending.header(),
+                    pending.request,
+                    pending.sent_at.elapsed(),
+                    pending.format.format(response)
+                ),
+                Err(ref error) => log!(
+                    pending.level,
+                    "{}:\n{}\nFailed after {:?}: {}",
+                    pending.header(),
+                    pending.request,
+                    pending.sent_at.elapsed(),
+                    error
+                ),
+            }
+        }
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pretty_prints_json() {
+        let format = BodyFormat::default();
+        let formatted = format.format(br#"{"id":1,"params":[2]}"#);
+        assert_eq!("{\n  \"id\": 1,\n  \"params\": [\n    2\n  ]\n}", formatted);
+        assert_eq!("not json", format.format(b"not json"));
+    }
+
+    #[test]
+    fn redacts_nested_fields() {
+        let format = BodyFormat {
+            max_length: None,
+            redacted: vec!["password".to_owned()],
+        };
+        let body = br#"{"params":[{"user":"bob","password":"hunter2"}],"password":{"a":1}}"#;
+        let formatted: JsonValue = serde_json::from_str(&format.format(body)).unwrap();
+        let expected = json!({
+            "params": [{"user": "bob", "password": "[redacted]"}],
+            "password": "[redacted]",
+        });
+        assert_eq!(expected, formatted);
+    }
+
+    #[test]
+    fn truncates_at_char_boundary() {
+        let format = BodyFormat {
+            max_length: Some(4),
+            redacted: Vec::new(),
+        };
+        assert_eq!("abc... (5 bytes truncated)", format.format("abcé€".as_bytes()));
+        assert_eq!("abcd", format.format(b"abcd"));
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A preset for driving language servers over the Language Server Protocol.
+//!
+//! Language servers usually run as a child process of the editor, speaking JSON-RPC 2.0 over its
+//! standard input and output. Every message is framed with a `Content-Length` header, and both
+//! sides send requests and notifications. [`connect`] puts these pieces together: it frames the
+//! two pipes with a [`ContentLengthCodec`] and returns a [`DuplexTransport`] and its
+//! [`Connection`], which sends `$/cancelRequest` for every call dropped before its response.
+//!
+//! Requests and notifications of the server are handled by registering handlers on the
+//! connection with `on_request` and `on_notification`. Requests of the server without a handler
+//! are answered with a "Method not found" error.
+//!
+//! LSP methods take a single object as their params, rather than a list of arguments, so they
+//! are best called by name through an [`RpcClient`], with a params struct serializing to that
+//! object.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::lsp;
+//!
+//! let mut server = Command::new("rust-analyzer").spawn_async(&core.handle())?;
+//! let stdout = server.stdout().take().unwrap();
+//! let stdin = server.stdin().take().unwrap();
+//! let (transport, connection) = lsp::connect(stdout, stdin);
+//! let connection = connection
+//!     .on_request("workspace/configuration", |params: ConfigurationParams| {
+//!         Ok(settings_for(&params.items))
+//!     })
+//!     .on_notification("textDocument/publishDiagnostics", |params: PublishDiagnostics| {
+//!         show_diagnostics(params)
+//!     });
+//! core.handle().spawn(connection.map_err(|e| error!("Language server failed: {}", e)));
+//!
+//! let mut client = RpcClient::new(transport);
+//! let capabilities: JsonValue = core.run(client.call("initialize", initialize_params))?;
+//! ```
+//!
+//! [`connect`]: fn.connect.html
+//! [`ContentLengthCodec`]: ../framing/struct.ContentLengthCodec.html
+//! [`DuplexTransport`]: ../duplex/struct.DuplexTransport.html
+//! [`Connection`]: ../duplex/struct.Connection.html
+//! [`RpcClient`]: ../struct.RpcClient.html
+
+use duplex::DuplexTransport;
+use framing::{self, ContentLengthCodec, PipeConnection};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The method of the notification cancelling a request.
+pub const CANCEL_REQUEST: &str = "$/cancelRequest";
+
+/// The error code of requests cancelled with `$/cancelRequest`.
+pub const REQUEST_CANCELLED: i64 = -32800;
+
+/// The error code of requests whose result is outdated because the document changed.
+pub const CONTENT_MODIFIED: i64 = -32801;
+
+
+/// The connection of a `DuplexTransport` to a language server.
+pub type LspConnection<R, W> = PipeConnection<R, W, ContentLengthCodec>;
+
+/// Creates a `DuplexTransport` talking to a language server, reading its messages from `reader`,
+/// usually the standard output of the server, and writing to `writer`, usually its standard
+/// input. Returns the transport and the future driving the connection.
+pub fn connect<R, W>(reader: R, writer: W) -> (DuplexTransport, LspConnection<R, W>)
+where
+    R: AsyncRead,
+    W: AsyncWrite,
+{
+    let (transport, connection) = framing::pipes(reader, writer, ContentLengthCodec::new());
+    (transport, connection.cancel_notification(CANCEL_REQUEST))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Async, Future};
+    use std::io::{self, Read, Write};
+    use std::sync::{Arc, Mutex};
+    use RpcClient;
+
+    /// A pipe never having anything to read.
+    struct SilentPipe;
+
+    impl Read for SilentPipe {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl AsyncRead for SilentPipe {}
+
+    /// A pipe collecting everything written to it.
+    #[derive(Clone, Default)]
+    struct RecordingPipe(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for RecordingPipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncWrite for RecordingPipe {
+        fn shutdown(&mut self) -> ::futures::Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn cancels_dropped_calls() {
+        let written = RecordingPipe::default();
+        let (transport, mut connection) = connect(SilentPipe, written.clone());
+        let mut client = RpcClient::new(transport);
+        let call = client.call::<_, bool>("shutdown", ());
+
+        let mut poll = || future::lazy(|| connection.poll()).wait().unwrap();
+        assert_eq!(Async::NotReady, poll());
+        drop(call);
+        assert_eq!(Async::NotReady, poll());
+
+        let written = String::from_utf8(written.0.lock().unwrap().clone()).unwrap();
+        let request = r#"{"jsonrpc":"2.0","method":"shutdown","params":null,"id":1}"#;
+        let cancel = r#"{"jsonrpc":"2.0","method":"$/cancelRequest","params":{"id":1}}"#;
+        let expected = format!(
+            "Content-Length: {}\r\n\r\n{}Content-Length: {}\r\n\r\n{}",
+            request.len(),
+            request,
+            cancel.len(),
+            cancel
+        );
+        assert_eq!(expected, written);
+    }
+}
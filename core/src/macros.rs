@@ -8,74 +8,886 @@
 
 /// The main macro of this crate. Generates JSON-RPC 2.0 client structs with automatic serialization
 /// and deserialization. Method calls get correct types automatically.
+///
+/// Methods can be configured with an `rpc` attribute. The following settings are supported:
+///
+/// * `deprecated_since = "x.y"` and optionally `note = "..."` - Marks the RPC method as
+///   deprecated on the server. The first call to the method logs a warning and every call is
+///   counted, see the [`deprecation`](deprecation/index.html) module.
+/// * `idempotent` - Marks the RPC method as safe to call more than once, like methods only
+///   reading state. A [`RetryPolicy`](retry/struct.RetryPolicy.html) restricted with
+///   `idempotent_only` never resends calls to methods without this setting.
+/// * `cache_ttl = secs` - Lets a [`CachingTransport`](cache/struct.CachingTransport.html)
+///   configured with the `METHODS` of the client cache responses of the method for `secs`
+///   seconds.
+///
+/// ```rust,ignore
+/// jsonrpc_client!(pub struct FizzBuzzClient {
+///     #[rpc(deprecated_since = "1.2", note = "Use fizz_buzz_v2 instead")]
+///     pub fn fizz_buzz(&mut self, number: u64) -> RpcRequest<String>;
+/// });
+/// ```
+///
+/// Calls fail with this crate's `Error` by default. A method can instead fail with an application
+/// specific error type by giving it as a second type parameter, `RpcRequest<T, MyError>`. The
+/// error type must implement `From<jsonrpc_client_core::Error>`.
+///
+/// ```rust,ignore
+/// jsonrpc_client!(pub struct FizzBuzzClient {
+///     pub fn fizz_buzz(&mut self, number: u64) -> RpcRequest<String, FizzBuzzError>;
+/// });
+/// ```
+///
+/// APIs where all method names share a namespace prefix can give it once after the struct name.
+/// The prefix is put in front of the names of all methods, but not of methods called by name
+/// through `call_raw`. Method names are otherwise sent as written, so the client below calls
+/// `eth_blockNumber`.
+///
+/// ```rust,ignore
+/// jsonrpc_client!(pub struct EthClient (prefix = "eth_") {
+///     #[allow(non_snake_case)]
+///     pub fn blockNumber(&mut self) -> RpcRequest<String>;
+/// });
+/// ```
+///
+/// Large APIs can be split into namespaces, each a client of its own. A client lists the clients
+/// of its namespaces with `pub mod` items before its methods, and gets a method returning each
+/// of them. The namespace clients share the transport of the parent client.
+///
+/// ```rust,ignore
+/// jsonrpc_client!(pub struct WalletClient (prefix = "wallet.") {
+///     pub fn get_balance(&mut self) -> RpcRequest<u64>;
+/// });
+///
+/// jsonrpc_client!(pub struct NodeClient {
+///     pub mod wallet: WalletClient;
+///     pub fn version(&mut self) -> RpcRequest<String>;
+/// });
+///
+/// let balance = node_client.wallet().get_balance().call()?;
+/// ```
+///
+/// Clients are `Clone`, with all clones sharing one transport, so a client can be handed to
+/// many threads or tasks. The transport is required to hand out unique ids across threads.
+///
+/// Methods can be declared taking either `&self` or `&mut self`. The generated methods always
+/// take `&self`, so a client can be called through a shared reference or from inside an `Arc`
+/// without locking.
+///
+/// Clients are generic over their transport, with the type of the transport as their only type
+/// parameter. It defaults to `BoxTransport`, so code choosing the transport at runtime can name
+/// the type-erased client as just `FizzBuzzClient`, see the [`boxed`](boxed/index.html) module.
+///
+/// A server trait matching the client can be generated as well, by giving its name after the
+/// client. Implementations of it can serve the client in tests, see the
+/// [`server`](server/index.html) module.
+///
+/// ```rust,ignore
+/// jsonrpc_client!(
+///     pub struct FizzBuzzClient {
+///         pub fn fizz_buzz(&mut self, number: u64) -> RpcRequest<String>;
+///     }
+///
+///     pub trait FizzBuzzServer;
+/// );
+/// ```
 #[macro_export]
 macro_rules! jsonrpc_client {
     (
         $(#[$struct_attr:meta])*
-        pub struct $struct_name:ident {$(
-            $(#[$attr:meta])*
-            pub fn $method:ident(&mut $selff:ident $(, $arg_name:ident: $arg_ty:ty)*)
-                -> RpcRequest<$return_ty:ty>;
+        pub struct $struct_name:ident {
+        $(pub mod $namespace:ident: $namespace_client:ident;)*
+        $(
+            $(#[$($attr:tt)*])*
+            pub fn $method:ident(&$(mut)? self $(, $arg_name:ident: $arg_ty:ty)*)
+                -> RpcRequest<$return_ty:ty $(, $error_ty:ty)?>;
         )*}
     ) => (
-        $(#[$struct_attr])*
-        pub struct $struct_name<T: $crate::Transport> {
-            transport: T,
+        jsonrpc_client!(
+            $(#[$struct_attr])*
+            pub struct $struct_name (prefix = "") {
+            $(pub mod $namespace: $namespace_client;)*
+            $(
+                $(#[$($attr)*])*
+                pub fn $method(&self $(, $arg_name: $arg_ty)*)
+                    -> RpcRequest<$return_ty $(, $error_ty)?>;
+            )*}
+        );
+    );
+    (
+        $(#[$struct_attr:meta])*
+        pub struct $struct_name:ident (prefix = $prefix:tt) {
+        $(pub mod $namespace:ident: $namespace_client:ident;)*
+        $(
+            $(#[$($attr:tt)*])*
+            pub fn $method:ident(&$(mut)? self $(, $arg_name:ident: $arg_ty:ty)*)
+                -> RpcRequest<$return_ty:ty $(, $error_ty:ty)?>;
+        )*}
+    ) => (
+        jsonrpc_client_struct!($(#[$struct_attr])* $struct_name);
+
+        impl<T: $crate::Transport> $struct_name<T> {
+            /// Calls the RPC method `method` with the given parameters. For calling methods not
+            /// listed in this client, see
+            /// [`call_method`](../jsonrpc_client_core/fn.call_method.html).
+            #[allow(dead_code)]
+            pub fn call_raw<P, R>(&self, method: &str, params: P)
+                -> $crate::RpcRequest<R, T::Future>
+            where
+                P: $crate::serde::Serialize,
+                R: $crate::serde::de::DeserializeOwned + Send + 'static,
+            {
+                $crate::call_method(&*self.transport, &self.config, method.to_owned(), params)
+            }
+
+            $(
+                /// Returns a client for the methods of a namespace, sending its requests through
+                /// the transport of this client.
+                pub fn $namespace(&self) -> $namespace_client<T> {
+                    $namespace_client::from_shared(self.transport.clone(), self.config.clone())
+                }
+            )*
+
+            /// Descriptions of the RPC methods of this client.
+            #[allow(dead_code)]
+            pub const METHODS: &'static [$crate::MethodInfo] = &[$(
+                $crate::MethodInfo {
+                    name: concat!($prefix, stringify!($method)),
+                    params: &[$(stringify!($arg_name)),*],
+                    idempotent: jsonrpc_client_idempotent!($(#[$($attr)*])*),
+                    cache_ttl_secs: jsonrpc_client_cache_ttl!($(#[$($attr)*])*),
+                },
+            )*];
+
+            $(
+                jsonrpc_client_method!(
+                    call_method $prefix [] []
+                    $(#[$($attr)*])*
+                    pub fn $method(&self $(, $arg_name: $arg_ty)*)
+                        -> RpcRequest<$return_ty $(, $error_ty)?>;
+                );
+            )*
+        }
+    );
+    (
+        $(#[$struct_attr:meta])*
+        pub struct $struct_name:ident {
+        $(pub mod $namespace:ident: $namespace_client:ident;)*
+        $(
+            $(#[$($attr:tt)*])*
+            pub fn $method:ident(&$(mut)? self $(, $arg_name:ident: $arg_ty:ty)*)
+                -> RpcRequest<$return_ty:ty $(, $error_ty:ty)?>;
+        )*}
+
+        $(#[$server_attr:meta])*
+        pub trait $server_name:ident;
+    ) => (
+        jsonrpc_client!(
+            $(#[$struct_attr])*
+            pub struct $struct_name (prefix = "") {
+            $(pub mod $namespace: $namespace_client;)*
+            $(
+                $(#[$($attr)*])*
+                pub fn $method(&self $(, $arg_name: $arg_ty)*)
+                    -> RpcRequest<$return_ty $(, $error_ty)?>;
+            )*}
+
+            $(#[$server_attr])*
+            pub trait $server_name;
+        );
+    );
+    (
+        $(#[$struct_attr:meta])*
+        pub struct $struct_name:ident (prefix = $prefix:tt) {
+        $(pub mod $namespace:ident: $namespace_client:ident;)*
+        $(
+            $(#[$($attr:tt)*])*
+            pub fn $method:ident(&$(mut)? self $(, $arg_name:ident: $arg_ty:ty)*)
+                -> RpcRequest<$return_ty:ty $(, $error_ty:ty)?>;
+        )*}
+
+        $(#[$server_attr:meta])*
+        pub trait $server_name:ident;
+    ) => (
+        jsonrpc_client!(
+            $(#[$struct_attr])*
+            pub struct $struct_name (prefix = $prefix) {
+            $(pub mod $namespace: $namespace_client;)*
+            $(
+                $(#[$($attr)*])*
+                pub fn $method(&self $(, $arg_name: $arg_ty)*)
+                    -> RpcRequest<$return_ty $(, $error_ty)?>;
+            )*}
+        );
+
+        $(#[$server_attr])*
+        pub trait $server_name: Send + Sync + 'static {
+            $(
+                jsonrpc_server_method!(
+                    []
+                    $(#[$($attr)*])*
+                    fn $method($(, $arg_name: $arg_ty)*) -> $return_ty;
+                );
+            )*
+
+            /// Returns a handler answering JSON-RPC requests by calling the methods of this
+            /// server.
+            fn into_io_handler(self) -> $crate::jsonrpc_core::IoHandler
+            where
+                Self: Sized,
+            {
+                let server = ::std::sync::Arc::new(self);
+                let mut io = $crate::jsonrpc_core::IoHandler::new();
+                $(
+                    let method_server = server.clone();
+                    io.add_method(
+                        concat!($prefix, stringify!($method)),
+                        move |params: $crate::jsonrpc_core::Params| {
+                            let values = $crate::server::positional_params(
+                                params,
+                                &[$(stringify!($arg_name)),*],
+                            );
+                            jsonrpc_server_params!(values; $($arg_name: $arg_ty),*);
+                            let result = method_server.$method($($arg_name),*)?;
+                            $crate::server::serialize_result(result)
+                        },
+                    );
+                )*
+                io
+            }
         }
+    );
+}
+
+/// Like [`jsonrpc_client`](macro.jsonrpc_client.html), but generates clients sending JSON-RPC 1.0
+/// requests.
+#[macro_export]
+macro_rules! jsonrpc_client_v1 {
+    (
+        $(#[$struct_attr:meta])*
+        pub struct $struct_name:ident {
+        $(pub mod $namespace:ident: $namespace_client:ident;)*
+        $(
+            $(#[$($attr:tt)*])*
+            pub fn $method:ident(&$(mut)? self $(, $arg_name:ident: $arg_ty:ty)*)
+                -> RpcRequest<$return_ty:ty $(, $error_ty:ty)?>;
+        )*}
+    ) => (
+        jsonrpc_client_v1!(
+            $(#[$struct_attr])*
+            pub struct $struct_name (prefix = "") {
+            $(pub mod $namespace: $namespace_client;)*
+            $(
+                $(#[$($attr)*])*
+                pub fn $method(&self $(, $arg_name: $arg_ty)*)
+                    -> RpcRequest<$return_ty $(, $error_ty)?>;
+            )*}
+        );
+    );
+    (
+        $(#[$struct_attr:meta])*
+        pub struct $struct_name:ident (prefix = $prefix:tt) {
+        $(pub mod $namespace:ident: $namespace_client:ident;)*
+        $(
+            $(#[$($attr:tt)*])*
+            pub fn $method:ident(&$(mut)? self $(, $arg_name:ident: $arg_ty:ty)*)
+                -> RpcRequest<$return_ty:ty $(, $error_ty:ty)?>;
+        )*}
+    ) => (
+        jsonrpc_client_struct!($(#[$struct_attr])* $struct_name);
 
         impl<T: $crate::Transport> $struct_name<T> {
-            /// Creates a new RPC client backed by the given transport implementation.
-            pub fn new(transport: T) -> Self {
-                $struct_name { transport }
+            /// Calls the RPC method `method` with the given parameters. For calling methods not
+            /// listed in this client, see
+            /// [`call_method_v1`](../jsonrpc_client_core/fn.call_method_v1.html).
+            #[allow(dead_code)]
+            pub fn call_raw<P, R>(&self, method: &str, params: P)
+                -> $crate::RpcRequest<R, T::Future>
+            where
+                P: $crate::serde::Serialize,
+                R: $crate::serde::de::DeserializeOwned + Send + 'static,
+            {
+                $crate::call_method_v1(&*self.transport, &self.config, method.to_owned(), params)
             }
 
             $(
-                $(#[$attr])*
-                pub fn $method(&mut $selff $(, $arg_name: $arg_ty)*)
-                    -> $crate::RpcRequest<$return_ty, T::Future>
-                {
-                    let method = String::from(stringify!($method));
-                    let params = expand_params!($($arg_name,)*);
-                    $crate::call_method(&mut $selff.transport, method, params)
+                /// Returns a client for the methods of a namespace, sending its requests through
+                /// the transport of this client.
+                pub fn $namespace(&self) -> $namespace_client<T> {
+                    $namespace_client::from_shared(self.transport.clone(), self.config.clone())
                 }
             )*
+
+            /// Descriptions of the RPC methods of this client.
+            #[allow(dead_code)]
+            pub const METHODS: &'static [$crate::MethodInfo] = &[$(
+                $crate::MethodInfo {
+                    name: concat!($prefix, stringify!($method)),
+                    params: &[$(stringify!($arg_name)),*],
+                    idempotent: jsonrpc_client_idempotent!($(#[$($attr)*])*),
+                    cache_ttl_secs: jsonrpc_client_cache_ttl!($(#[$($attr)*])*),
+                },
+            )*];
+
+            $(
+                jsonrpc_client_method!(
+                    call_method_v1 $prefix [] []
+                    $(#[$($attr)*])*
+                    pub fn $method(&self $(, $arg_name: $arg_ty)*)
+                        -> RpcRequest<$return_ty $(, $error_ty)?>;
+                );
+            )*
         }
     )
 }
 
+/// Declares a trait with the methods of a JSON-RPC API and generates a client implementing it.
+/// The same trait can then be implemented by mocks of the client in tests, or by servers.
+///
+/// The methods are declared like in [`jsonrpc_client`](macro.jsonrpc_client.html), but without
+/// `pub`. In the trait they return a boxed [`RpcFuture`](type.RpcFuture.html) instead of a
+/// `RpcRequest`. The generated client struct also has all the methods of the trait as inherent
+/// methods returning unboxed `RpcRequest`s, just like a client from `jsonrpc_client`.
+///
+/// ```rust,ignore
+/// jsonrpc_client_trait!(
+///     pub trait FizzBuzz {
+///         fn fizz_buzz(&mut self, number: u64) -> RpcRequest<String>;
+///     }
+///
+///     pub struct FizzBuzzClient;
+/// );
+///
+/// fn fizz_buzz_all<C: FizzBuzz>(client: &mut C) -> Vec<String> {
+///     (1..16).map(|n| client.fizz_buzz(n).wait().unwrap()).collect()
+/// }
+/// ```
 #[macro_export]
-macro_rules! jsonrpc_client_v1 {
+macro_rules! jsonrpc_client_trait {
     (
+        $(#[$trait_attr:meta])*
+        pub trait $trait_name:ident {$(
+            $(#[$($attr:tt)*])*
+            fn $method:ident(&mut $selff:ident $(, $arg_name:ident: $arg_ty:ty)*)
+                -> RpcRequest<$return_ty:ty $(, $error_ty:ty)?>;
+        )*}
+
         $(#[$struct_attr:meta])*
-        pub struct $struct_name:ident {$(
-            $(#[$attr:meta])*
-            pub fn $method:ident(&mut $selff:ident $(, $arg_name:ident: $arg_ty:ty)*)
-                -> RpcRequest<$return_ty:ty>;
+        pub struct $struct_name:ident;
+    ) => (
+        $(#[$trait_attr])*
+        pub trait $trait_name {$(
+            jsonrpc_client_trait_method!(
+                []
+                $(#[$($attr)*])*
+                fn $method(&mut $selff $(, $arg_name: $arg_ty)*)
+                    -> RpcRequest<$return_ty $(, $error_ty)?>;
+            );
         )*}
+
+        jsonrpc_client!(
+            $(#[$struct_attr])*
+            pub struct $struct_name {$(
+                $(#[$($attr)*])*
+                pub fn $method(&mut $selff $(, $arg_name: $arg_ty)*)
+                    -> RpcRequest<$return_ty $(, $error_ty)?>;
+            )*}
+        );
+
+        impl<T: $crate::Transport> $trait_name for $struct_name<T> {$(
+            fn $method(&mut $selff $(, $arg_name: $arg_ty)*)
+                -> $crate::RpcFuture<$return_ty $(, $error_ty)?>
+            {
+                // Inherent methods take precedence, so this is the method generated above.
+                Box::new($struct_name::$method($selff $(, $arg_name)*))
+            }
+        )*}
+    )
+}
+
+/// Generates a client for an API declared with the `build_rpc_trait` macro of the
+/// `jsonrpc-macros` crate, used by servers built on `jsonrpc-core`. Projects owning both the
+/// client and the server can then keep the declaration in one place, so the two can't drift
+/// apart.
+///
+/// The macro takes the name of the client struct followed by the trait exactly as given to
+/// `build_rpc_trait`. The client gets one method per RPC method, calling it by the name in its
+/// `rpc` attribute and with the same argument types as the server. A last `Trailing<T>` argument
+/// becomes an `Option<T>`, left out of the request when `None`. `Self::Metadata` arguments are
+/// left out and subscriptions are skipped.
+/// The methods return `RpcRequest<T>`, where `T` is the value in the `Result` or future returned
+/// by the server method.
+///
+/// `build_rpc_trait` accepts no settings but the method name, so methods are not idempotent by
+/// default. The client marks methods idempotent by listing them after its name, like
+/// `pub struct FizzBuzzClient (idempotent = [fizz_buzz]);`, for retries restricted to idempotent
+/// methods. Listing a method the trait does not have fails to compile.
+///
+/// Having the declaration in a macro of its own lets it be given to both macros:
+///
+/// ```rust,ignore
+/// macro_rules! fizz_buzz_api {
+///     ($macro:ident $($client:tt)*) => {
+///         $macro! {
+///             $($client)*
+///
+///             pub trait FizzBuzzApi {
+///                 #[rpc(name = "fizzBuzz")]
+///                 fn fizz_buzz(&self, u64) -> Result<String, Error>;
+///             }
+///         }
+///     };
+/// }
+///
+/// // In the server.
+/// fizz_buzz_api!(build_rpc_trait);
+/// // In the client.
+/// fizz_buzz_api!(jsonrpc_client_from_rpc_trait pub struct FizzBuzzClient;);
+/// ```
+#[macro_export]
+macro_rules! jsonrpc_client_from_rpc_trait {
+    (
+        @client $idempotent:tt
+        $(#[$struct_attr:meta])*
+        pub struct $struct_name:ident;
+
+        $(#[$trait_attr:meta])*
+        pub trait $trait_name:ident {
+            $(type Metadata;)*
+
+            $(
+                $(#[doc = $doc:expr])*
+                #[rpc($($rpc:tt)*)]
+                fn $method:ident($($param:tt)*) -> $result:tt<$out:ty $(, $error:ty)*>;
+            )*
+
+            $(
+                #[pubsub($($pubsub:tt)*)] {$($subscription:tt)*}
+            )*
+        }
     ) => (
+        jsonrpc_client_struct!($(#[$struct_attr])* $struct_name);
+
+        impl<T: $crate::Transport> $struct_name<T> {
+            /// Calls the RPC method `method` with the given parameters. For calling methods not
+            /// listed in this client, see
+            /// [`call_method`](../jsonrpc_client_core/fn.call_method.html).
+            #[allow(dead_code)]
+            pub fn call_raw<P, R>(&self, method: &str, params: P)
+                -> $crate::RpcRequest<R, T::Future>
+            where
+                P: $crate::serde::Serialize,
+                R: $crate::serde::de::DeserializeOwned + Send + 'static,
+            {
+                $crate::call_method(&*self.transport, &self.config, method.to_owned(), params)
+            }
+
+            /// Descriptions of the RPC methods of this client.
+            #[allow(dead_code)]
+            pub const METHODS: &'static [$crate::MethodInfo] = &[$(
+                jsonrpc_client_rpc_trait_method!(
+                    info $idempotent ($($rpc)*) $method [$out]
+                    [arg0 arg1 arg2 arg3 arg4 arg5 arg6 arg7 arg8 arg9 arg10 arg11 arg12 arg13]
+                    [] []
+                    $($param)*
+                ),
+            )*];
+
+            $(
+                jsonrpc_client_rpc_trait_method!(
+                    method [$(#[doc = $doc])*] ($($rpc)*) $method [$out]
+                    [arg0 arg1 arg2 arg3 arg4 arg5 arg6 arg7 arg8 arg9 arg10 arg11 arg12 arg13]
+                    [] []
+                    $($param)*
+                );
+            )*
+
+            jsonrpc_client_rpc_trait_method!(check $idempotent);
+        }
+    );
+    (
+        $(#[$struct_attr:meta])*
+        pub struct $struct_name:ident $((idempotent = [$($idempotent:ident),* $(,)?]))?;
+
+        $($api:tt)*
+    ) => (
+        jsonrpc_client_from_rpc_trait!(
+            @client [$($($idempotent)*)?]
+            $(#[$struct_attr])*
+            pub struct $struct_name;
+
+            $($api)*
+        );
+    );
+}
+
+/// Generates one method of a client from `jsonrpc_client_from_rpc_trait`. Munches the
+/// parameters of the server method one by one, naming them from the list of unused names. A
+/// `Trailing` parameter is kept apart from the others.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_client_rpc_trait_method {
+    (
+        $mode:ident $attrs:tt $rpc:tt $method:ident $out:tt $names:tt $args:tt $trailing:tt
+        &self $($params:tt)*
+    ) => (
+        jsonrpc_client_rpc_trait_method! {
+            $mode $attrs $rpc $method $out $names $args $trailing $($params)*
+        }
+    );
+    (
+        $mode:ident $attrs:tt $rpc:tt $method:ident $out:tt $names:tt $args:tt $trailing:tt
+        , Self::Metadata $($params:tt)*
+    ) => (
+        jsonrpc_client_rpc_trait_method! {
+            $mode $attrs $rpc $method $out $names $args $trailing $($params)*
+        }
+    );
+    (
+        $mode:ident $attrs:tt $rpc:tt $method:ident $out:tt
+        [$name:ident $($names:ident)*] $args:tt []
+        , Trailing<$arg_ty:ty>
+    ) => (
+        jsonrpc_client_rpc_trait_method! {
+            $mode $attrs $rpc $method $out [$($names)*] $args [$name: $arg_ty]
+        }
+    );
+    (
+        $mode:ident $attrs:tt $rpc:tt $method:ident $out:tt
+        [$name:ident $($names:ident)*] [$($args:tt)*] []
+        , $arg_ty:ty , $($params:tt)*
+    ) => (
+        jsonrpc_client_rpc_trait_method! {
+            $mode $attrs $rpc $method $out [$($names)*] [$($args)* $name: $arg_ty,] [] , $($params)*
+        }
+    );
+    (
+        $mode:ident $attrs:tt $rpc:tt $method:ident $out:tt
+        [$name:ident $($names:ident)*] [$($args:tt)*] []
+        , $arg_ty:ty
+    ) => (
+        jsonrpc_client_rpc_trait_method! {
+            $mode $attrs $rpc $method $out [$($names)*] [$($args)* $name: $arg_ty,] []
+        }
+    );
+    (
+        method [$($attrs:tt)*] ($($rpc:tt)*) $method:ident [$out:ty] $names:tt
+        [$($arg_name:ident: $arg_ty:ty,)*] []
+    ) => (
+        $($attrs)*
+        pub fn $method(&self $(, $arg_name: $arg_ty)*) -> $crate::RpcRequest<$out, T::Future> {
+            let method = String::from(jsonrpc_client_rpc_trait_name!($($rpc)*));
+            let params = $crate::Arguments::new(
+                &self.config,
+                &[$(stringify!($arg_name)),*],
+                expand_params!($($arg_name,)*),
+            );
+            $crate::call_method(&*self.transport, &self.config, method, params)
+        }
+    );
+    (
+        method [$($attrs:tt)*] ($($rpc:tt)*) $method:ident [$out:ty] $names:tt
+        [$($arg_name:ident: $arg_ty:ty,)*] [$trailing_name:ident: $trailing_ty:ty]
+    ) => (
+        $($attrs)*
+        pub fn $method(
+            &self
+            $(, $arg_name: $arg_ty)*,
+            $trailing_name: Option<$trailing_ty>
+        ) -> $crate::RpcRequest<$out, T::Future> {
+            let method = String::from(jsonrpc_client_rpc_trait_name!($($rpc)*));
+            // The server fails on a `null` trailing parameter, so it's left out instead.
+            match $trailing_name {
+                Some($trailing_name) => {
+                    let params = $crate::Arguments::new(
+                        &self.config,
+                        &[$(stringify!($arg_name),)* stringify!($trailing_name)],
+                        ($($arg_name,)* $trailing_name,),
+                    );
+                    $crate::call_method(&*self.transport, &self.config, method, params)
+                }
+                None => {
+                    let params = $crate::Arguments::new(
+                        &self.config,
+                        &[$(stringify!($arg_name)),*],
+                        expand_params!($($arg_name,)*),
+                    );
+                    $crate::call_method(&*self.transport, &self.config, method, params)
+                }
+            }
+        }
+    );
+    (
+        info [$($idempotent:ident)*] ($($rpc:tt)*) $method:ident $out:tt $names:tt
+        [$($arg_name:ident: $arg_ty:ty,)*] [$($trailing_name:ident: $trailing_ty:ty)*]
+    ) => (
+        $crate::MethodInfo {
+            name: jsonrpc_client_rpc_trait_name!($($rpc)*),
+            params: &[$(stringify!($arg_name),)* $(stringify!($trailing_name))*],
+            idempotent: $crate::MethodInfo::is_listed(
+                stringify!($method),
+                &[$(stringify!($idempotent)),*],
+            ),
+            cache_ttl_secs: None,
+        }
+    );
+    (check [$($idempotent:ident)*]) => (
+        /// Fails to compile if a method listed as idempotent does not exist.
+        #[doc(hidden)]
+        #[allow(dead_code)]
+        fn __check_idempotent_methods() {
+            $(let _ = Self::$idempotent;)*
+        }
+    );
+    (
+        $mode:ident $attrs:tt $rpc:tt $method:ident $out:tt $names:tt $args:tt $trailing:tt
+        $($params:tt)+
+    ) => (
+        compile_error!(concat!("Unsupported parameters of ", stringify!($method)));
+    );
+}
+
+/// Expands to the method name given in the `rpc` attribute of a `build_rpc_trait` method.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_client_rpc_trait_name {
+    (name = $name:tt $($rest:tt)*) => ($name);
+    ($other:tt $($rest:tt)*) => (jsonrpc_client_rpc_trait_name!($($rest)*));
+    () => (compile_error!("Missing name in rpc attribute"));
+}
+
+/// Declares one method of a trait generated by `jsonrpc_client_trait`. Munches the method
+/// attributes one by one, dropping `rpc` attributes, which only apply to the generated client.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_client_trait_method {
+    (
+        [$($attrs:tt)*]
+        #[rpc($($args:tt)*)]
+        $($rest:tt)*
+    ) => (
+        jsonrpc_client_trait_method!([$($attrs)*] $($rest)*);
+    );
+    (
+        [$($attrs:tt)*]
+        #[$($attr:tt)*]
+        $($rest:tt)*
+    ) => (
+        jsonrpc_client_trait_method!([$($attrs)* #[$($attr)*]] $($rest)*);
+    );
+    (
+        [$($attrs:tt)*]
+        fn $method:ident(&mut $selff:ident $(, $arg_name:ident: $arg_ty:ty)*)
+            -> RpcRequest<$return_ty:ty $(, $error_ty:ty)?>;
+    ) => (
+        $($attrs)*
+        fn $method(&mut $selff $(, $arg_name: $arg_ty)*)
+            -> $crate::RpcFuture<$return_ty $(, $error_ty)?>;
+    );
+}
+
+/// Declares one method of a server trait generated by `jsonrpc_client`. Munches the method
+/// attributes one by one, dropping `rpc` attributes, which only apply to the generated client.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_server_method {
+    (
+        [$($attrs:tt)*]
+        #[rpc($($args:tt)*)]
+        $($rest:tt)*
+    ) => (
+        jsonrpc_server_method!([$($attrs)*] $($rest)*);
+    );
+    (
+        [$($attrs:tt)*]
+        #[$($attr:tt)*]
+        $($rest:tt)*
+    ) => (
+        jsonrpc_server_method!([$($attrs)* #[$($attr)*]] $($rest)*);
+    );
+    (
+        [$($attrs:tt)*]
+        fn $method:ident($(, $arg_name:ident: $arg_ty:ty)*) -> $return_ty:ty;
+    ) => (
+        $($attrs)*
+        fn $method(&self $(, $arg_name: $arg_ty)*)
+            -> ::std::result::Result<$return_ty, $crate::jsonrpc_core::Error>;
+    );
+}
+
+/// Deserializes the arguments of a call to a server method into variables named like the
+/// arguments. Nullary methods don't parse anything, since `()` can't be deserialized from `[]`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_server_params {
+    ($values:ident;) => (
+        let _ = $values;
+    );
+    ($values:ident; $($arg_name:ident: $arg_ty:ty),+) => (
+        let ($($arg_name,)+): ($($arg_ty,)+) = $crate::server::parse_params(&$values)?;
+    );
+}
+
+/// Declares the struct of a client, holding its transport and configuration. Clones of the
+/// client share the transport.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_client_struct {
+    ($(#[$struct_attr:meta])* $struct_name:ident) => (
         $(#[$struct_attr])*
-        pub struct $struct_name<T: $crate::Transport> {
-            transport: T,
+        pub struct $struct_name<T: $crate::Transport = $crate::boxed::BoxTransport> {
+            transport: ::std::sync::Arc<T>,
+            config: $crate::ClientConfig,
         }
 
         impl<T: $crate::Transport> $struct_name<T> {
             /// Creates a new RPC client backed by the given transport implementation.
             pub fn new(transport: T) -> Self {
-                $struct_name { transport }
+                Self::with_config(transport, $crate::ClientConfig::default())
             }
 
-            $(
-                $(#[$attr])*
-                pub fn $method(&mut $selff $(, $arg_name: $arg_ty)*)
-                    -> $crate::RpcRequest<$return_ty, T::Future>
-                {
-                    let method = String::from(stringify!($method));
-                    let params = expand_params!($($arg_name,)*);
-                    $crate::call_method_v1(&mut $selff.transport, method, params)
+            /// Creates a new RPC client backed by the given transport implementation and using
+            /// the given configuration.
+            pub fn with_config(transport: T, config: $crate::ClientConfig) -> Self {
+                Self::from_shared(::std::sync::Arc::new(transport), config)
+            }
+
+            /// Creates a client sharing the transport of another client.
+            #[doc(hidden)]
+            pub fn from_shared(transport: ::std::sync::Arc<T>, config: $crate::ClientConfig)
+                -> Self
+            {
+                $struct_name { transport, config }
+            }
+        }
+
+        /// Clones share the transport, and so the ids of their requests never collide.
+        impl<T: $crate::Transport> Clone for $struct_name<T> {
+            fn clone(&self) -> Self {
+                $struct_name {
+                    transport: self.transport.clone(),
+                    config: self.config.clone(),
                 }
-            )*
+            }
         }
-    )
+
+        impl<T: $crate::Transport> $crate::FromTransport<T> for $struct_name<T> {
+            fn from_transport(transport: T, config: $crate::ClientConfig) -> Self {
+                Self::with_config(transport, config)
+            }
+        }
+    );
+}
+
+/// Generates one method of a client struct. Munches the method attributes one by one, keeping
+/// `rpc` attributes apart from the ones that should be put on the generated method.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_client_method {
+    (
+        $call:ident $prefix:tt [$($attrs:tt)*] [$($rpc:tt)*]
+        #[rpc($($args:tt)*)]
+        $($rest:tt)*
+    ) => (
+        jsonrpc_client_method!($call $prefix [$($attrs)*] [$($rpc)* $($args)*,] $($rest)*);
+    );
+    (
+        $call:ident $prefix:tt [$($attrs:tt)*] [$($rpc:tt)*]
+        #[$($attr:tt)*]
+        $($rest:tt)*
+    ) => (
+        jsonrpc_client_method!($call $prefix [$($attrs)* #[$($attr)*]] [$($rpc)*] $($rest)*);
+    );
+    (
+        $call:ident $prefix:tt [$($attrs:tt)*] [$($rpc:tt)*]
+        pub fn $method:ident(&$(mut)? self $(, $arg_name:ident: $arg_ty:ty)*)
+            -> RpcRequest<$return_ty:ty $(, $error_ty:ty)?>;
+    ) => (
+        $($attrs)*
+        pub fn $method(&self $(, $arg_name: $arg_ty)*)
+            -> $crate::RpcRequest<$return_ty, T::Future $(, $error_ty)?>
+        {
+            jsonrpc_client_rpc_attr!($prefix $method; $($rpc)*);
+            let method = String::from(concat!($prefix, stringify!($method)));
+            let params = $crate::Arguments::new(
+                &self.config,
+                &[$(stringify!($arg_name)),*],
+                expand_params!($($arg_name,)*),
+            );
+            $crate::$call(&*self.transport, &self.config, method, params).err_into()
+        }
+    );
+}
+
+/// Expands the settings given in `rpc` attributes into statements run on every call.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_client_rpc_attr {
+    ($prefix:tt $method:ident;) => ();
+    ($prefix:tt $method:ident; , $($rest:tt)*) => (
+        jsonrpc_client_rpc_attr!($prefix $method; $($rest)*);
+    );
+    (
+        $prefix:tt $method:ident;
+        deprecated_since = $since:expr, note = $note:expr, $($rest:tt)*
+    ) => (
+        $crate::deprecation::deprecated_call(
+            concat!($prefix, stringify!($method)),
+            $since,
+            Some($note),
+        );
+        jsonrpc_client_rpc_attr!($prefix $method; $($rest)*);
+    );
+    (
+        $prefix:tt $method:ident;
+        note = $note:expr, deprecated_since = $since:expr, $($rest:tt)*
+    ) => (
+        $crate::deprecation::deprecated_call(
+            concat!($prefix, stringify!($method)),
+            $since,
+            Some($note),
+        );
+        jsonrpc_client_rpc_attr!($prefix $method; $($rest)*);
+    );
+    ($prefix:tt $method:ident; deprecated_since = $since:expr, $($rest:tt)*) => (
+        $crate::deprecation::deprecated_call(concat!($prefix, stringify!($method)), $since, None);
+        jsonrpc_client_rpc_attr!($prefix $method; $($rest)*);
+    );
+    ($prefix:tt $method:ident; idempotent, $($rest:tt)*) => (
+        jsonrpc_client_rpc_attr!($prefix $method; $($rest)*);
+    );
+    ($prefix:tt $method:ident; cache_ttl = $secs:expr, $($rest:tt)*) => (
+        jsonrpc_client_rpc_attr!($prefix $method; $($rest)*);
+    );
+    ($prefix:tt $method:ident; $($unknown:tt)*) => (
+        compile_error!(concat!("Invalid rpc attribute: ", stringify!($($unknown)*)));
+    );
+}
+
+/// Expands to `true` if any of the given method attributes is an `rpc` attribute containing
+/// `idempotent`, and to `false` otherwise.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_client_idempotent {
+    () => (false);
+    (#[rpc(idempotent $($args:tt)*)] $($rest:tt)*) => (true);
+    (#[rpc($other:tt $($args:tt)*)] $($rest:tt)*) => (
+        jsonrpc_client_idempotent!(#[rpc($($args)*)] $($rest)*)
+    );
+    (#[$($attr:tt)*] $($rest:tt)*) => (jsonrpc_client_idempotent!($($rest)*));
+}
+
+/// Expands to `Some(secs)` if any of the given method attributes is an `rpc` attribute containing
+/// `cache_ttl = secs`, and to `None` otherwise.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! jsonrpc_client_cache_ttl {
+    () => (None);
+    (#[rpc(cache_ttl = $secs:tt $($args:tt)*)] $($rest:tt)*) => (Some($secs));
+    (#[rpc($other:tt $($args:tt)*)] $($rest:tt)*) => (
+        jsonrpc_client_cache_ttl!(#[rpc($($args)*)] $($rest)*)
+    );
+    (#[$($attr:tt)*] $($rest:tt)*) => (jsonrpc_client_cache_ttl!($($rest)*));
 }
 
 /// Expands a variable list of parameters into its serializable form. Is needed to make the params
@@ -0,0 +1,348 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Metadata attached to single calls, passed down to the transport, and metadata of their
+//! responses, passed back up from the transport.
+//!
+//! Configuration like the headers of an HTTP transport applies to all calls made through it.
+//! Some things differ from call to call though, like the tenant a call is made on behalf of or
+//! the trace it belongs to. Calls created within [`with_metadata`] carry a [`CallMetadata`] with
+//! extra headers, a trace id and a priority. Transports read it with [`CallMetadata::current`]
+//! from within `Transport::send`, and decide what to make of it. The HTTP transport sends the
+//! headers and the trace id as HTTP headers.
+//!
+//! Nested `with_metadata` add their headers to the enclosing ones, and replace the trace id and
+//! priority if they set them.
+//!
+//! In the other direction, transports store what they know about the response to a call, like
+//! its HTTP status and headers, as a [`ResponseMetadata`] in the [`ResponseSlot`] of the call.
+//! Callers get it together with the result from
+//! [`RpcRequest::with_response_metadata`], for example to slow down as the rate limit counters in
+//! the response headers of a server run out.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::metadata::{with_metadata, CallMetadata, Priority};
+//!
+//! let metadata = CallMetadata::new()
+//!     .header("X-Tenant", "acme")
+//!     .trace_id("4bf92f3577b34da6")
+//!     .priority(Priority::High);
+//! with_metadata(metadata, || client.fizz_buzz(3)).call()?;
+//!
+//! let (result, response) = client.fizz_buzz(5).with_response_metadata().wait()?;
+//! if response.header("X-RateLimit-Remaining") == Some("0") {
+//!     // Back off.
+//! }
+//! ```
+//!
+//! [`with_metadata`]: fn.with_metadata.html
+//! [`CallMetadata`]: struct.CallMetadata.html
+//! [`CallMetadata::current`]: struct.CallMetadata.html#method.current
+//! [`ResponseMetadata`]: struct.ResponseMetadata.html
+//! [`ResponseSlot`]: struct.ResponseSlot.html
+//! [`RpcRequest::with_response_metadata`]:
+//! ../struct.RpcRequest.html#method.with_response_metadata
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    /// The metadata of the call being sent, or of the enclosing `with_metadata`.
+    static CURRENT: RefCell<Option<CallMetadata>> = RefCell::new(None);
+    /// Where the metadata of the response to the call being sent goes.
+    static CURRENT_RESPONSE: RefCell<Option<ResponseSlot>> = RefCell::new(None);
+}
+
+
+/// How urgent a call is. Transports and transport wrappers may use it to order or route calls,
+/// the HTTP transport ignores it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Calls that may wait for others, like background synchronization.
+    Low,
+    /// The priority of calls not given any.
+    Normal,
+    /// Calls someone is waiting for.
+    High,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+
+/// Metadata of a call, for the transport sending it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallMetadata {
+    headers: Vec<(String, String)>,
+    trace_id: Option<String>,
+    priority: Option<Priority>,
+}
+
+impl CallMetadata {
+    /// Creates empty metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the metadata of the call currently being sent, when called by a transport from
+    /// within `Transport::send`. Returns `None` for calls made outside of `with_metadata`.
+    pub fn current() -> Option<CallMetadata> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Adds a header to send with the call. Adding several headers with the same name sends all
+    /// of them.
+    pub fn header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the id of the trace the call belongs to.
+    pub fn trace_id<S: Into<String>>(mut self, trace_id: S) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+
+    /// Sets the priority of the call. Defaults to `Priority::Normal`.
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Returns the headers to send with the call, as names and values, in the order they were
+    /// added.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Returns the id of the trace the call belongs to, if any.
+    pub fn get_trace_id(&self) -> Option<&str> {
+        self.trace_id.as_ref().map(String::as_str)
+    }
+
+    /// Returns the priority of the call.
+    pub fn get_priority(&self) -> Priority {
+        self.priority.unwrap_or_default()
+    }
+
+    /// Returns this metadata extended with `inner`, the metadata of a nested scope.
+    fn extend(mut self, inner: CallMetadata) -> Self {
+        self.headers.extend(inner.headers);
+        self.trace_id = inner.trace_id.or(self.trace_id);
+        self.priority = inner.priority.or(self.priority);
+        self
+    }
+}
+
+
+/// What a transport knows about the response to a call. Fields the transport does not know are
+/// left empty.
+///
+/// The address of the peer a response came from is not included. The HTTP transport sends calls
+/// over pooled connections, and hyper does not tell which connection a response came over.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseMetadata {
+    /// The HTTP status code of the response.
+    pub status: Option<u16>,
+    /// The headers of the response, as names and values.
+    pub headers: Vec<(String, String)>,
+}
+
+impl ResponseMetadata {
+    /// Returns the value of the first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref header, _)| header.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+
+/// Where a transport stores the `ResponseMetadata` of a call. Every call has one, shared between
+/// the call and the transport sending it.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseSlot(Arc<Mutex<Option<ResponseMetadata>>>);
+
+impl ResponseSlot {
+    /// Returns the slot of the call currently being sent, when called by a transport from within
+    /// `Transport::send`. Transports handing the response over later keep the slot until then.
+    pub fn current() -> Option<ResponseSlot> {
+        CURRENT_RESPONSE.with(|current| current.borrow().clone())
+    }
+
+    /// Stores `metadata` as the metadata of the response, replacing any stored before. A retried
+    /// call ends up with the metadata of the last attempt.
+    pub fn set(&self, metadata: ResponseMetadata) {
+        *self.0.lock().unwrap() = Some(metadata);
+    }
+
+    /// Returns a copy of the stored metadata, if any. For transports inspecting the responses to
+    /// the requests they send.
+    pub fn get(&self) -> Option<ResponseMetadata> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Takes the stored metadata, or empty metadata if the transport stored none.
+    pub(crate) fn take(&self) -> ResponseMetadata {
+        self.0.lock().unwrap().take().unwrap_or_default()
+    }
+}
+
+
+/// Runs `f` with `metadata` attached to all calls created within it. Metadata of an enclosing
+/// `with_metadata` is extended, see the [module documentation](index.html).
+pub fn with_metadata<F: FnOnce() -> R, R>(metadata: CallMetadata, f: F) -> R {
+    let metadata = match CallMetadata::current() {
+        Some(current) => current.extend(metadata),
+        None => metadata,
+    };
+    in_scope(Some(metadata), f)
+}
+
+/// Runs `f` with `metadata` as the current metadata, restoring the previous one afterwards.
+fn in_scope<F: FnOnce() -> R, R>(metadata: Option<CallMetadata>, f: F) -> R {
+    struct Restore(Option<CallMetadata>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let previous = CURRENT.with(|current| current.replace(metadata));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// Runs `f` with `slot` as the current response slot, restoring the previous one afterwards.
+pub(crate) fn respond_in<F: FnOnce() -> R, R>(slot: Option<ResponseSlot>, f: F) -> R {
+    struct Restore(Option<ResponseSlot>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT_RESPONSE.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let previous = CURRENT_RESPONSE.with(|current| current.replace(slot));
+    let _restore = Restore(previous);
+    f()
+}
+
+
+/// The metadata of the call being sent and the slot for the metadata of its response. Kept by
+/// transport wrappers sending requests again after `Transport::send` returned.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Scope {
+    metadata: Option<CallMetadata>,
+    response: Option<ResponseSlot>,
+}
+
+impl Scope {
+    /// Returns the scope of the call currently being sent.
+    pub fn current() -> Self {
+        Scope {
+            metadata: CallMetadata::current(),
+            response: ResponseSlot::current(),
+        }
+    }
+
+    /// Runs `f` within this scope.
+    pub fn enter<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        in_scope(self.metadata.clone(), || respond_in(self.response.clone(), f))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Transport;
+    use futures::Future;
+    use futures::future::{self, FutureResult};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    /// A transport recording the metadata of the calls it sends, responding with status 200.
+    #[derive(Clone, Default)]
+    struct RecordingTransport(Arc<Mutex<Vec<Option<CallMetadata>>>>);
+
+    impl Transport for RecordingTransport {
+        type Future = FutureResult<Vec<u8>, io::Error>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            1
+        }
+
+        fn send(&self, _json_data: Vec<u8>) -> Self::Future {
+            self.0.lock().unwrap().push(CallMetadata::current());
+            if let Some(slot) = ResponseSlot::current() {
+                slot.set(ResponseMetadata {
+                    status: Some(200),
+                    headers: vec![("X-RateLimit-Remaining".to_owned(), "41".to_owned())],
+                });
+            }
+            future::ok(br#"{"jsonrpc":"2.0","id":1,"result":true}"#.to_vec())
+        }
+    }
+
+    jsonrpc_client!(pub struct PingClient {
+        pub fn ping(&mut self) -> RpcRequest<bool>;
+    });
+
+    #[test]
+    fn metadata_reaches_transport() {
+        let transport = RecordingTransport::default();
+        let client = PingClient::new(transport.clone());
+        let tenant = CallMetadata::new().header("X-Tenant", "acme");
+        assert!(with_metadata(tenant.clone(), || client.ping()).call().unwrap());
+        assert!(client.ping().call().unwrap());
+        assert_eq!(vec![Some(tenant), None], *transport.0.lock().unwrap());
+    }
+
+    #[test]
+    fn nested_metadata_extends() {
+        let outer = CallMetadata::new()
+            .header("X-Tenant", "acme")
+            .trace_id("outer")
+            .priority(Priority::Low);
+        let inner = CallMetadata::new().header("X-Shard", "7").trace_id("inner");
+        let metadata = with_metadata(outer, || with_metadata(inner, CallMetadata::current));
+        let metadata = metadata.unwrap();
+        assert_eq!(
+            &[
+                ("X-Tenant".to_owned(), "acme".to_owned()),
+                ("X-Shard".to_owned(), "7".to_owned()),
+            ],
+            metadata.headers()
+        );
+        assert_eq!(Some("inner"), metadata.get_trace_id());
+        assert_eq!(Priority::Low, metadata.get_priority());
+        assert!(CallMetadata::current().is_none());
+    }
+
+    #[test]
+    fn response_metadata_reaches_caller() {
+        let client = PingClient::new(RecordingTransport::default());
+        let (result, response) = client.ping().with_response_metadata().wait().unwrap();
+        assert!(result);
+        assert_eq!(Some(200), response.status);
+        assert_eq!(Some("41"), response.header("x-ratelimit-remaining"));
+        assert_eq!(None, response.header("Retry-After"));
+        assert!(ResponseSlot::current().is_none());
+    }
+}
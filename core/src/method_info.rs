@@ -0,0 +1,92 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use serde_json::{self, Value as JsonValue};
+
+/// Describes one RPC method of a generated client. Every client has a `METHODS` constant listing
+/// its methods, for enumerating what the client supports at runtime.
+///
+/// ```rust,ignore
+/// for method in FizzBuzzClient::<HttpHandle>::METHODS {
+///     println!("{}({})", method.name, method.params.join(", "));
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MethodInfo {
+    /// The name of the method as sent to the server, including any prefix.
+    pub name: &'static str,
+    /// The names of the parameters, in order.
+    pub params: &'static [&'static str],
+    /// If the method is marked `#[rpc(idempotent)]`, meaning calling it more than once has the
+    /// same effect as calling it once. Only idempotent methods are retried by a
+    /// [`RetryPolicy`](retry/struct.RetryPolicy.html) restricted with `idempotent_only`.
+    pub idempotent: bool,
+    /// For how many seconds responses of the method may be cached, if it is marked
+    /// `#[rpc(cache_ttl = ...)]`. Used by a
+    /// [`CachingTransport`](cache/struct.CachingTransport.html) configured with `methods`.
+    pub cache_ttl_secs: Option<u64>,
+}
+
+impl MethodInfo {
+    /// Returns the number of parameters of the method.
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Returns the method with the given name among `methods`, if any.
+    pub fn find(methods: &'static [MethodInfo], name: &str) -> Option<&'static MethodInfo> {
+        methods.iter().find(|method| method.name == name)
+    }
+
+    /// Returns true if `name` is among `names`. Marks the methods listed as idempotent in
+    /// clients generated by `jsonrpc_client_from_rpc_trait`.
+    #[doc(hidden)]
+    pub const fn is_listed(name: &str, names: &[&str]) -> bool {
+        let mut i = 0;
+        while i < names.len() {
+            if str_eq(name, names[i]) {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+
+    /// Returns true if the request in `json_data` only calls methods among `methods` that are
+    /// idempotent. A batch has to consist of idempotent calls only.
+    pub(crate) fn is_idempotent_request(methods: &'static [MethodInfo], json_data: &[u8]) -> bool {
+        let is_idempotent = |request: &JsonValue| {
+            request
+                .get("method")
+                .and_then(JsonValue::as_str)
+                .and_then(|name| MethodInfo::find(methods, name))
+                .map_or(false, |method| method.idempotent)
+        };
+        match serde_json::from_slice(json_data) {
+            Ok(JsonValue::Array(ref requests)) => requests.iter().all(is_idempotent),
+            Ok(ref request) => is_idempotent(request),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Compares two strings, in constant expressions.
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
@@ -0,0 +1,571 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport calling methods over MQTT, through a broker.
+//!
+//! Devices and services that are managed over MQTT commonly take JSON-RPC requests published on
+//! a request topic, and publish the responses on a topic of the calling client. [`connect`]
+//! speaks MQTT 3.1.1 over a connection to the broker: it connects as the client of the given
+//! [`MqttOptions`], subscribes to the response topic, and publishes every request on the request
+//! topic. Responses are matched to calls by their id, like on any `DuplexTransport`, so many
+//! calls can be in flight at once. How the server learns the response topic of a client is up
+//! to the API, usually the topic is derived from the client id.
+//!
+//! Messages are published and received with QoS 0, at most once, so calls to a server that is
+//! offline are lost rather than queued by the broker. Use a timeout or a deadline on calls.
+//! The connection is made without a keep alive interval and with a clean session.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::mqtt::{self, MqttOptions};
+//!
+//! let stream = core.run(TcpStream::connect(&broker_address, &core.handle()))?;
+//! let options = MqttOptions::new("admin-7", "devices/sensor-17/rpc", "clients/admin-7/rpc")
+//!     .credentials("admin", "secret");
+//! let (transport, connection) = mqtt::connect(stream, options);
+//! core.handle().spawn(connection.map_err(|e| error!("MQTT connection failed: {}", e)));
+//!
+//! let mut client = DeviceClient::new(transport);
+//! let firmware = core.run(client.firmware_version())?;
+//! ```
+//!
+//! [`connect`]: fn.connect.html
+//! [`MqttOptions`]: struct.MqttOptions.html
+
+use bytes::BytesMut;
+use duplex::{Connection, DuplexTransport};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::stream::{SplitSink, SplitStream};
+use std::collections::VecDeque;
+use std::io;
+use tokio_codec::{Decoder, Encoder, Framed};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The longest packet accepted from the broker, 16 MiB.
+const MAX_PACKET_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The packet identifier of the only subscribe packet sent.
+const SUBSCRIBE_PACKET_ID: u16 = 1;
+
+/// The return code of a subscribe acknowledgement refusing the subscription.
+const SUBSCRIPTION_FAILURE: u8 = 0x80;
+
+
+/// The client id, topics and credentials of an MQTT transport.
+#[derive(Debug, Clone)]
+pub struct MqttOptions {
+    client_id: String,
+    request_topic: String,
+    response_topic: String,
+    username: Option<String>,
+    password: Option<Vec<u8>>,
+}
+
+impl MqttOptions {
+    /// Creates options for connecting as `client_id`, publishing requests on `request_topic` and
+    /// receiving responses on `response_topic`. The response topic may be a topic filter with
+    /// wildcards.
+    pub fn new(client_id: &str, request_topic: &str, response_topic: &str) -> Self {
+        MqttOptions {
+            client_id: client_id.to_owned(),
+            request_topic: request_topic.to_owned(),
+            response_topic: response_topic.to_owned(),
+            username: None,
+            password: None,
+        }
+    }
+
+    /// Sets the user name and password to connect to the broker with.
+    pub fn credentials<P: Into<Vec<u8>>>(mut self, username: &str, password: P) -> Self {
+        self.username = Some(username.to_owned());
+        self.password = Some(password.into());
+        self
+    }
+}
+
+/// The connection of a `DuplexTransport` talking to an MQTT broker.
+pub type MqttConnection<T> = Connection<MqttSink<T>, MqttStream<T>>;
+
+/// Creates a `DuplexTransport` calling methods over `io`, a connection to an MQTT broker, as
+/// described by `options`. Returns the transport and the future driving the connection.
+///
+/// The connect and subscribe packets are written when the connection is first polled, without
+/// waiting for the broker to acknowledge them. The connection fails if the broker refuses the
+/// connection or the subscription.
+pub fn connect<T>(io: T, options: MqttOptions) -> (DuplexTransport, MqttConnection<T>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let (framed_sink, framed_stream) = MqttCodec.framed(io).split();
+    let handshake = vec![
+        Packet::Connect {
+            client_id: options.client_id,
+            username: options.username,
+            password: options.password,
+        },
+        Packet::Subscribe {
+            packet_id: SUBSCRIBE_PACKET_ID,
+            topic_filter: options.response_topic,
+        },
+    ];
+    let sink = MqttSink {
+        framed: framed_sink,
+        request_topic: options.request_topic,
+        handshake: handshake.into_iter().collect(),
+    };
+    let stream = MqttStream {
+        framed: framed_stream,
+    };
+    DuplexTransport::new(sink, stream)
+}
+
+
+/// Publishes the requests of an MQTT transport on its request topic.
+pub struct MqttSink<T> {
+    framed: SplitSink<Framed<T, MqttCodec>>,
+    request_topic: String,
+    /// The connect and subscribe packets not yet handed to the framed sink.
+    handshake: VecDeque<Packet>,
+}
+
+impl<T: AsyncRead + AsyncWrite> MqttSink<T> {
+    /// Hands the connect and subscribe packets to the framed sink, ahead of any request.
+    fn poll_handshake(&mut self) -> Poll<(), io::Error> {
+        while let Some(packet) = self.handshake.pop_front() {
+            if let AsyncSink::NotReady(packet) = self.framed.start_send(packet)? {
+                self.handshake.push_front(packet);
+                return Ok(Async::NotReady);
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Sink for MqttSink<T> {
+    type SinkItem = Vec<u8>;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, json_data: Vec<u8>) -> StartSend<Vec<u8>, io::Error> {
+        if self.poll_handshake()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(json_data));
+        }
+        let publish = Packet::Publish {
+            topic: self.request_topic.clone(),
+            payload: json_data,
+        };
+        match self.framed.start_send(publish)? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(Packet::Publish { payload, .. }) => {
+                Ok(AsyncSink::NotReady(payload))
+            }
+            AsyncSink::NotReady(_) => unreachable!("Framed sink returned another packet"),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        try_ready!(self.poll_handshake());
+        self.framed.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), io::Error> {
+        try_ready!(self.poll_handshake());
+        self.framed.close()
+    }
+}
+
+/// Reads the messages published on the response topic of an MQTT transport.
+pub struct MqttStream<T> {
+    framed: SplitStream<Framed<T, MqttCodec>>,
+}
+
+impl<T: AsyncRead + AsyncWrite> Stream for MqttStream<T> {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, io::Error> {
+        loop {
+            match try_ready!(self.framed.poll()) {
+                Some(Packet::Publish { topic, payload }) => {
+                    trace!("Received {} bytes on {}", payload.len(), topic);
+                    return Ok(Async::Ready(Some(payload)));
+                }
+                Some(Packet::ConnAck { return_code: 0 }) => debug!("Connected to MQTT broker"),
+                Some(Packet::ConnAck { return_code }) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionRefused,
+                        format!("MQTT broker refused the connection: {}", refusal(return_code)),
+                    ))
+                }
+                Some(Packet::SubAck { return_code, .. }) if return_code == SUBSCRIPTION_FAILURE => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "MQTT broker refused the subscription to the response topic",
+                    ))
+                }
+                Some(packet) => trace!("Ignoring MQTT packet {:?}", packet),
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+
+/// The MQTT control packets used by the transport.
+#[derive(Debug, Clone, PartialEq)]
+enum Packet {
+    Connect {
+        client_id: String,
+        username: Option<String>,
+        password: Option<Vec<u8>>,
+    },
+    ConnAck { return_code: u8 },
+    Publish { topic: String, payload: Vec<u8> },
+    Subscribe { packet_id: u16, topic_filter: String },
+    SubAck { packet_id: u16, return_code: u8 },
+    /// A packet of a type the transport does not use, with its packet type.
+    Other(u8),
+}
+
+/// A codec framing MQTT 3.1.1 control packets.
+#[derive(Debug)]
+struct MqttCodec;
+
+impl Decoder for MqttCodec {
+    type Item = Packet;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Packet>> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let (length, length_bytes) = match remaining_length(&buf[1..])? {
+            Some(remaining_length) => remaining_length,
+            None => return Ok(None),
+        };
+        if length > MAX_PACKET_LENGTH {
+            return Err(invalid("MQTT packet exceeds the maximum length"));
+        }
+        let header_length = 1 + length_bytes;
+        if buf.len() < header_length + length {
+            buf.reserve(header_length + length - buf.len());
+            return Ok(None);
+        }
+        let packet = buf.split_to(header_length + length);
+        parse_packet(packet[0], &packet[header_length..]).map(Some)
+    }
+}
+
+impl Encoder for MqttCodec {
+    type Item = Packet;
+    type Error = io::Error;
+
+    fn encode(&mut self, packet: Packet, buf: &mut BytesMut) -> io::Result<()> {
+        let mut body = Vec::new();
+        let first_byte = match packet {
+            Packet::Connect {
+                client_id,
+                username,
+                password,
+            } => {
+                let mut flags = 0x02; // Clean session.
+                if username.is_some() {
+                    flags |= 0x80;
+                }
+                if password.is_some() {
+                    flags |= 0x40;
+                }
+                put_bytes(&mut body, b"MQTT")?;
+                // Protocol level 4 is MQTT 3.1.1, and a keep alive of zero disables it.
+                body.extend_from_slice(&[4, flags, 0, 0]);
+                put_bytes(&mut body, client_id.as_bytes())?;
+                if let Some(username) = username {
+                    put_bytes(&mut body, username.as_bytes())?;
+                }
+                if let Some(password) = password {
+                    put_bytes(&mut body, &password)?;
+                }
+                0x10
+            }
+            Packet::Publish { topic, payload } => {
+                put_bytes(&mut body, topic.as_bytes())?;
+                body.extend_from_slice(&payload);
+                0x30
+            }
+            Packet::Subscribe {
+                packet_id,
+                topic_filter,
+            } => {
+                body.extend_from_slice(&[(packet_id >> 8) as u8, packet_id as u8]);
+                put_bytes(&mut body, topic_filter.as_bytes())?;
+                // The highest QoS of messages delivered on the subscription.
+                body.push(0);
+                0x82
+            }
+            packet => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Sending {:?} packets is not supported", packet),
+                ))
+            }
+        };
+        if body.len() > MAX_PACKET_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "MQTT packet exceeds the maximum length",
+            ));
+        }
+        buf.reserve(5 + body.len());
+        buf.extend_from_slice(&[first_byte]);
+        let mut length = body.len();
+        loop {
+            let mut byte = (length % 128) as u8;
+            length /= 128;
+            if length > 0 {
+                byte |= 0x80;
+            }
+            buf.extend_from_slice(&[byte]);
+            if length == 0 {
+                break;
+            }
+        }
+        buf.extend_from_slice(&body);
+        Ok(())
+    }
+}
+
+
+/// Decodes the remaining length of a packet from the bytes after its first byte. Returns the
+/// length and how many bytes it took up, or `None` if more bytes are needed.
+fn remaining_length(bytes: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let mut length = 0;
+    for (i, &byte) in bytes.iter().take(4).enumerate() {
+        length += ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((length, i + 1)));
+        }
+    }
+    if bytes.len() >= 4 {
+        Err(invalid("Malformed MQTT remaining length"))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parses the packet with the first byte `first_byte` and the variable header and payload
+/// `body`.
+fn parse_packet(first_byte: u8, body: &[u8]) -> io::Result<Packet> {
+    let truncated = || invalid("Truncated MQTT packet");
+    match first_byte >> 4 {
+        2 => {
+            let return_code = *body.get(1).ok_or_else(truncated)?;
+            Ok(Packet::ConnAck { return_code })
+        }
+        3 => {
+            let (topic, rest) = take_bytes(body).ok_or_else(truncated)?;
+            let topic = String::from_utf8(topic.to_vec())
+                .map_err(|_| invalid("MQTT topic name is not UTF-8"))?;
+            // Messages published with a QoS above zero carry a packet identifier.
+            let qos = (first_byte >> 1) & 0x03;
+            let payload = if qos > 0 {
+                rest.get(2..).ok_or_else(truncated)?
+            } else {
+                rest
+            };
+            Ok(Packet::Publish {
+                topic,
+                payload: payload.to_vec(),
+            })
+        }
+        9 => {
+            if body.len() < 3 {
+                return Err(truncated());
+            }
+            Ok(Packet::SubAck {
+                packet_id: (body[0] as u16) << 8 | body[1] as u16,
+                return_code: body[2],
+            })
+        }
+        packet_type => Ok(Packet::Other(packet_type)),
+    }
+}
+
+/// Writes `bytes` prefixed with their length, as MQTT encodes strings and binary data.
+fn put_bytes(body: &mut Vec<u8>, bytes: &[u8]) -> io::Result<()> {
+    if bytes.len() > 0xffff {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "MQTT string exceeds 65535 bytes",
+        ));
+    }
+    body.extend_from_slice(&[(bytes.len() >> 8) as u8, bytes.len() as u8]);
+    body.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Reads length-prefixed bytes from the start of `body`, returning them and the rest of `body`.
+fn take_bytes(body: &[u8]) -> Option<(&[u8], &[u8])> {
+    if body.len() < 2 {
+        return None;
+    }
+    let length = (body[0] as usize) << 8 | body[1] as usize;
+    if body.len() < 2 + length {
+        return None;
+    }
+    Some((&body[2..2 + length], &body[2 + length..]))
+}
+
+/// Describes the return code of a connect acknowledgement refusing the connection.
+fn refusal(return_code: u8) -> String {
+    match return_code {
+        1 => "unacceptable protocol version".to_owned(),
+        2 => "client identifier rejected".to_owned(),
+        3 => "server unavailable".to_owned(),
+        4 => "bad user name or password".to_owned(),
+        5 => "not authorized".to_owned(),
+        code => format!("return code {}", code),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Future};
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+    use RpcClient;
+
+    #[test]
+    fn encode_connect() {
+        let mut buf = BytesMut::new();
+        let connect = Packet::Connect {
+            client_id: "c1".to_owned(),
+            username: Some("u".to_owned()),
+            password: Some(b"pw".to_vec()),
+        };
+        MqttCodec.encode(connect, &mut buf).unwrap();
+        let expected = b"\x10\x15\x00\x04MQTT\x04\xc2\x00\x00\x00\x02c1\x00\x01u\x00\x02pw";
+        assert_eq!(&expected[..], &buf[..]);
+    }
+
+    #[test]
+    fn decode_packets() {
+        let mut buf = BytesMut::from(&b"\x20\x02\x00\x00\x90\x03\x00\x01\x00\x32\x8a"[..]);
+        assert_eq!(
+            Some(Packet::ConnAck { return_code: 0 }),
+            MqttCodec.decode(&mut buf).unwrap()
+        );
+        let suback = Packet::SubAck {
+            packet_id: 1,
+            return_code: 0,
+        };
+        assert_eq!(Some(suback), MqttCodec.decode(&mut buf).unwrap());
+        assert_eq!(None, MqttCodec.decode(&mut buf).unwrap());
+
+        // A QoS 1 publish with a payload long enough for a two byte remaining length.
+        let payload = vec![b'x'; 133];
+        buf.extend_from_slice(b"\x01\x00\x01t\x00\x07");
+        assert_eq!(None, MqttCodec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(&payload);
+        let publish = Packet::Publish {
+            topic: "t".to_owned(),
+            payload,
+        };
+        assert_eq!(Some(publish), MqttCodec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+    }
+
+    /// A broker connection recording every write, and reading the given bytes once something
+    /// has been written to it.
+    struct BrokerSocket {
+        writes: Arc<Mutex<Vec<u8>>>,
+        reply: Option<Vec<u8>>,
+    }
+
+    impl Read for BrokerSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.writes.lock().unwrap().is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            match self.reply.take() {
+                Some(reply) => {
+                    buf[..reply.len()].copy_from_slice(&reply);
+                    Ok(reply.len())
+                }
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    impl Write for BrokerSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for BrokerSocket {}
+
+    impl AsyncWrite for BrokerSocket {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn broker(reply: &[u8]) -> (BrokerSocket, Arc<Mutex<Vec<u8>>>) {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let socket = BrokerSocket {
+            writes: writes.clone(),
+            reply: Some(reply.to_vec()),
+        };
+        (socket, writes)
+    }
+
+    #[test]
+    fn call_over_broker() {
+        let response = br#"{"jsonrpc":"2.0","id":1,"result":true}"#;
+        let mut reply = b"\x20\x02\x00\x00\x90\x03\x00\x01\x00".to_vec();
+        reply.extend_from_slice(&[0x30, 2 + 5 + response.len() as u8, 0, 5]);
+        reply.extend_from_slice(b"c1/rx");
+        reply.extend_from_slice(response);
+        let (socket, writes) = broker(&reply);
+        let (transport, mut connection) = connect(socket, MqttOptions::new("c1", "rpc", "c1/rx"));
+        let mut client = RpcClient::new(transport);
+        let mut call = client.call::<_, bool>("ping", ());
+
+        // Writes the handshake and the request, and then reads the replies of the broker.
+        future::lazy(|| connection.poll()).wait().unwrap();
+        future::lazy(|| connection.poll()).wait().unwrap();
+        assert_eq!(Async::Ready(true), future::lazy(|| call.poll()).wait().unwrap());
+
+        let writes = writes.lock().unwrap();
+        let connect = b"\x10\x0e\x00\x04MQTT\x04\x02\x00\x00\x00\x02c1";
+        let subscribe = b"\x82\x0a\x00\x01\x00\x05c1/rx\x00";
+        assert!(writes.starts_with(&[&connect[..], &subscribe[..]].concat()));
+        let publish = &writes[connect.len() + subscribe.len()..];
+        assert_eq!(&b"\x00\x03rpc{"[..], &publish[2..8]);
+    }
+
+    #[test]
+    fn connection_refused() {
+        let (socket, _writes) = broker(b"\x20\x02\x00\x05");
+        let (_transport, mut connection) = connect(socket, MqttOptions::new("c1", "rpc", "c1/rx"));
+        future::lazy(|| connection.poll()).wait().unwrap();
+        let error = future::lazy(|| connection.poll()).wait().unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionRefused, error.kind());
+    }
+}
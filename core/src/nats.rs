@@ -0,0 +1,557 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport calling methods by NATS request/reply.
+//!
+//! Services in NATS based setups listen on a subject, and reply to every message on the reply
+//! subject the message was published with. [`connect`] speaks the NATS client protocol over a
+//! connection to a NATS server: it subscribes to an inbox subject unique to the connection, and
+//! publishes every request on the subject of the service with the inbox as reply subject. The
+//! replies are matched to calls by their id, so many calls can be in flight at once, also to
+//! services with several instances in a queue group.
+//!
+//! NATS does not tell a requester that nobody is listening on a subject, a request just goes
+//! unanswered. Like the request functions of NATS clients, the [`NatsTransport`] therefore fails
+//! calls not answered within a timeout, with a `TimedOut` IO error as the transport error of the
+//! call.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::nats::{self, NatsOptions};
+//!
+//! let stream = core.run(TcpStream::connect(&nats_address, &core.handle()))?;
+//! let options = NatsOptions::new("accounts.rpc").token(&token).timeout(Duration::from_secs(2));
+//! let (transport, connection) = nats::connect(stream, options);
+//! core.handle().spawn(connection.map_err(|e| error!("NATS connection failed: {}", e)));
+//!
+//! let mut client = AccountsClient::new(transport);
+//! let balance = core.run(client.balance(account_id))?;
+//! ```
+//!
+//! [`connect`]: fn.connect.html
+//! [`NatsTransport`]: struct.NatsTransport.html
+
+use Transport;
+use bytes::BytesMut;
+use duplex::{Connection, DuplexTransport};
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use futures::future::Either;
+use futures::stream::{SplitSink, SplitStream};
+use rand;
+use serde_json::{self, Value as JsonValue};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use timer;
+use tokio_codec::{Decoder, Encoder, Framed};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The longest message payload accepted from the server, 16 MiB.
+const MAX_PAYLOAD_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The longest protocol line accepted from the server.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// The subscription id of the inbox subscription, the only one made.
+const INBOX_SID: &str = "1";
+
+
+/// The subject, credentials and timeout of a NATS transport.
+#[derive(Debug, Clone)]
+pub struct NatsOptions {
+    subject: String,
+    name: Option<String>,
+    user: Option<(String, String)>,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+impl NatsOptions {
+    /// Creates options for calling methods on the service listening on `subject`, with a
+    /// timeout of five seconds.
+    pub fn new(subject: &str) -> Self {
+        NatsOptions {
+            subject: subject.to_owned(),
+            name: None,
+            user: None,
+            token: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Sets the name of the connection shown by the server in its monitoring data.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// Sets the user name and password to connect to the server with.
+    pub fn credentials(mut self, user: &str, password: &str) -> Self {
+        self.user = Some((user.to_owned(), password.to_owned()));
+        self
+    }
+
+    /// Sets the token to connect to the server with.
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_owned());
+        self
+    }
+
+    /// Sets how long to wait for the reply to a call before failing it.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Serializes the `CONNECT` message of these options.
+    fn connect_message(&self) -> String {
+        let mut message = serde_json::Map::new();
+        message.insert("verbose".to_owned(), JsonValue::Bool(false));
+        message.insert("pedantic".to_owned(), JsonValue::Bool(false));
+        message.insert("lang".to_owned(), JsonValue::String("rust".to_owned()));
+        let version = env!("CARGO_PKG_VERSION").to_owned();
+        message.insert("version".to_owned(), JsonValue::String(version));
+        if let Some(ref name) = self.name {
+            message.insert("name".to_owned(), JsonValue::String(name.clone()));
+        }
+        if let Some((ref user, ref password)) = self.user {
+            message.insert("user".to_owned(), JsonValue::String(user.clone()));
+            message.insert("pass".to_owned(), JsonValue::String(password.clone()));
+        }
+        if let Some(ref token) = self.token {
+            message.insert("auth_token".to_owned(), JsonValue::String(token.clone()));
+        }
+        JsonValue::Object(message).to_string()
+    }
+}
+
+/// The connection of a `NatsTransport` to a NATS server.
+pub type NatsConnection<T> = Connection<NatsSink<T>, NatsStream<T>>;
+
+/// Creates a `NatsTransport` calling methods over `io`, a connection to a NATS server, as
+/// described by `options`. Returns the transport and the future driving the connection.
+///
+/// The connect and subscribe messages are written when the connection is first polled, without
+/// waiting for the server. The connection fails if the server reports an error other than a
+/// permissions violation, which is logged and leaves the call to time out.
+pub fn connect<T>(io: T, options: NatsOptions) -> (NatsTransport, NatsConnection<T>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let inbox = format!("_INBOX.{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+    let (framed_sink, framed_stream) = NatsCodec::new().framed(io).split();
+    let pongs_owed = Arc::new(AtomicUsize::new(0));
+    let handshake = vec![
+        Op::Connect(options.connect_message()),
+        Op::Sub {
+            subject: inbox.clone(),
+            sid: INBOX_SID,
+        },
+    ];
+    let sink = NatsSink {
+        framed: framed_sink,
+        handshake: handshake.into_iter().collect(),
+        subject: options.subject,
+        inbox,
+        pongs_owed: pongs_owed.clone(),
+    };
+    let stream = NatsStream {
+        framed: framed_stream,
+        pongs_owed,
+    };
+    let (inner, connection) = DuplexTransport::new(sink, stream);
+    let transport = NatsTransport {
+        inner,
+        timeout: options.timeout,
+    };
+    (transport, connection)
+}
+
+
+/// A transport calling methods by NATS request/reply, failing calls not answered in time.
+/// Created with [`connect`](fn.connect.html).
+#[derive(Debug, Clone)]
+pub struct NatsTransport {
+    inner: DuplexTransport,
+    timeout: Duration,
+}
+
+impl Transport for NatsTransport {
+    type Future = Box<Future<Item = Vec<u8>, Error = io::Error> + Send>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.inner.get_next_id()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let timeout = self.timeout;
+        let reply = self.inner.send(json_data);
+        Box::new(
+            reply
+                .select2(timer::sleep(timeout))
+                .then(move |result| match result {
+                    Ok(Either::A((reply, _))) => Ok(reply),
+                    Err(Either::A((error, _))) => Err(error),
+                    Ok(Either::B(_)) | Err(Either::B(_)) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("No reply from NATS subscriber within {:?}", timeout),
+                    )),
+                }),
+        )
+    }
+}
+
+
+/// Publishes the requests of a NATS transport, and answers the pings of the server.
+pub struct NatsSink<T> {
+    framed: SplitSink<Framed<T, NatsCodec>>,
+    /// The connect and subscribe messages not yet handed to the framed sink.
+    handshake: VecDeque<Op>,
+    subject: String,
+    inbox: String,
+    /// The number of pings received from the server and not yet answered.
+    pongs_owed: Arc<AtomicUsize>,
+}
+
+impl<T: AsyncRead + AsyncWrite> NatsSink<T> {
+    /// Hands the handshake and pongs owed to the framed sink, ahead of any request.
+    fn poll_control(&mut self) -> Poll<(), io::Error> {
+        while let Some(op) = self.handshake.pop_front() {
+            if let AsyncSink::NotReady(op) = self.framed.start_send(op)? {
+                self.handshake.push_front(op);
+                return Ok(Async::NotReady);
+            }
+        }
+        while self.pongs_owed.load(Ordering::SeqCst) > 0 {
+            if self.framed.start_send(Op::Pong)?.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            self.pongs_owed.fetch_sub(1, Ordering::SeqCst);
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Sink for NatsSink<T> {
+    type SinkItem = Vec<u8>;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, json_data: Vec<u8>) -> StartSend<Vec<u8>, io::Error> {
+        if self.poll_control()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(json_data));
+        }
+        let publish = Op::Pub {
+            subject: self.subject.clone(),
+            reply_to: self.inbox.clone(),
+            payload: json_data,
+        };
+        match self.framed.start_send(publish)? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(Op::Pub { payload, .. }) => Ok(AsyncSink::NotReady(payload)),
+            AsyncSink::NotReady(_) => unreachable!("Framed sink returned another message"),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        try_ready!(self.poll_control());
+        self.framed.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), io::Error> {
+        try_ready!(self.poll_control());
+        self.framed.close()
+    }
+}
+
+/// Reads the replies delivered to the inbox of a NATS transport.
+pub struct NatsStream<T> {
+    framed: SplitStream<Framed<T, NatsCodec>>,
+    pongs_owed: Arc<AtomicUsize>,
+}
+
+impl<T: AsyncRead + AsyncWrite> Stream for NatsStream<T> {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, io::Error> {
+        loop {
+            match try_ready!(self.framed.poll()) {
+                Some(Op::Msg { payload }) => return Ok(Async::Ready(Some(payload))),
+                // Answered by the sink, which the connection polls right after the stream.
+                Some(Op::Ping) => {
+                    self.pongs_owed.fetch_add(1, Ordering::SeqCst);
+                }
+                Some(Op::Err(ref message)) if message.contains("Permissions Violation") => {
+                    warn!("NATS server refused an operation: {}", message);
+                }
+                Some(Op::Err(message)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("NATS server failed the connection: {}", message),
+                    ))
+                }
+                Some(op) => trace!("Ignoring NATS message {:?}", op),
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+
+/// The messages of the NATS client protocol used by the transport.
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    Info(String),
+    Connect(String),
+    Sub { subject: String, sid: &'static str },
+    Pub {
+        subject: String,
+        reply_to: String,
+        payload: Vec<u8>,
+    },
+    Msg { payload: Vec<u8> },
+    Ping,
+    Pong,
+    Ok,
+    Err(String),
+}
+
+/// A codec framing the messages of the NATS client protocol.
+#[derive(Debug)]
+struct NatsCodec {
+    /// The length of the payload of the `MSG` whose line has been read, if any.
+    payload_length: Option<usize>,
+}
+
+impl NatsCodec {
+    fn new() -> Self {
+        NatsCodec {
+            payload_length: None,
+        }
+    }
+}
+
+impl Decoder for NatsCodec {
+    type Item = Op;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Op>> {
+        loop {
+            if let Some(length) = self.payload_length {
+                // The payload is followed by `\r\n`.
+                if buf.len() < length + 2 {
+                    buf.reserve(length + 2 - buf.len());
+                    return Ok(None);
+                }
+                self.payload_length = None;
+                let payload = buf.split_to(length).to_vec();
+                buf.split_to(2);
+                return Ok(Some(Op::Msg { payload }));
+            }
+            let end = match buf.iter().position(|&b| b == b'\n') {
+                Some(end) => end,
+                None if buf.len() > MAX_LINE_LENGTH => {
+                    return Err(invalid("NATS protocol line too long".to_owned()))
+                }
+                None => return Ok(None),
+            };
+            let line = buf.split_to(end + 1);
+            let line = String::from_utf8_lossy(&line[..end]);
+            let line = line.trim();
+            let (name, arguments) = match line.find(' ') {
+                Some(space) => (&line[..space], line[space..].trim()),
+                None => (line, ""),
+            };
+            let op = match &*name.to_uppercase() {
+                "" => continue,
+                "MSG" => {
+                    // The arguments are the subject, the subscription id, an optional reply
+                    // subject and the length of the payload.
+                    let length = arguments
+                        .split_whitespace()
+                        .last()
+                        .and_then(|length| length.parse().ok())
+                        .ok_or_else(|| invalid(format!("Invalid NATS message: {}", line)))?;
+                    if length > MAX_PAYLOAD_LENGTH {
+                        return Err(invalid("NATS message exceeds the maximum length".to_owned()));
+                    }
+                    self.payload_length = Some(length);
+                    continue;
+                }
+                "INFO" => Op::Info(arguments.to_owned()),
+                "PING" => Op::Ping,
+                "PONG" => Op::Pong,
+                "+OK" => Op::Ok,
+                "-ERR" => Op::Err(arguments.trim_matches('\'').to_owned()),
+                _ => return Err(invalid(format!("Unknown NATS message: {}", line))),
+            };
+            return Ok(Some(op));
+        }
+    }
+}
+
+impl Encoder for NatsCodec {
+    type Item = Op;
+    type Error = io::Error;
+
+    fn encode(&mut self, op: Op, buf: &mut BytesMut) -> io::Result<()> {
+        let line = match op {
+            Op::Connect(options) => format!("CONNECT {}\r\n", options),
+            Op::Sub { subject, sid } => format!("SUB {} {}\r\n", subject, sid),
+            Op::Pub {
+                subject,
+                reply_to,
+                payload,
+            } => {
+                let line = format!("PUB {} {} {}\r\n", subject, reply_to, payload.len());
+                buf.reserve(line.len() + payload.len() + 2);
+                buf.extend_from_slice(line.as_bytes());
+                buf.extend_from_slice(&payload);
+                buf.extend_from_slice(b"\r\n");
+                return Ok(());
+            }
+            Op::Ping => "PING\r\n".to_owned(),
+            Op::Pong => "PONG\r\n".to_owned(),
+            op => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Sending {:?} is not supported", op),
+                ))
+            }
+        };
+        buf.reserve(line.len());
+        buf.extend_from_slice(line.as_bytes());
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::io::{Read, Write};
+    use std::sync::Mutex;
+    use {ErrorKind, RpcClient};
+
+    #[test]
+    fn decode_messages() {
+        let mut codec = NatsCodec::new();
+        let mut buf = BytesMut::from(&b"INFO {\"port\":4222}\r\nPING\r\nMSG _INBOX.a 1 "[..]);
+        let info = Op::Info("{\"port\":4222}".to_owned());
+        assert_eq!(Some(info), codec.decode(&mut buf).unwrap());
+        assert_eq!(Some(Op::Ping), codec.decode(&mut buf).unwrap());
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(b"6\r\n[1,\r\n]");
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(b"\r\n-ERR 'Authorization Violation'\r\n");
+        let msg = Op::Msg {
+            payload: b"[1,\r\n]".to_vec(),
+        };
+        assert_eq!(Some(msg), codec.decode(&mut buf).unwrap());
+        let error = Op::Err("Authorization Violation".to_owned());
+        assert_eq!(Some(error), codec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+    }
+
+    /// A connection to a NATS server recording every write, and reading the given bytes once
+    /// something has been written to it.
+    struct ServerSocket {
+        writes: Arc<Mutex<Vec<u8>>>,
+        reply: Option<Vec<u8>>,
+    }
+
+    impl Read for ServerSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.writes.lock().unwrap().is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            match self.reply.take() {
+                Some(reply) => {
+                    buf[..reply.len()].copy_from_slice(&reply);
+                    Ok(reply.len())
+                }
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    impl Write for ServerSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for ServerSocket {}
+
+    impl AsyncWrite for ServerSocket {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn request_reply() {
+        let response = br#"{"jsonrpc":"2.0","id":1,"result":true}"#;
+        let mut reply = format!("INFO {{}}\r\nPING\r\nMSG _INBOX.x 1 {}\r\n", response.len())
+            .into_bytes();
+        reply.extend_from_slice(response);
+        reply.extend_from_slice(b"\r\n");
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let socket = ServerSocket {
+            writes: writes.clone(),
+            reply: Some(reply),
+        };
+        let (transport, mut connection) = connect(socket, NatsOptions::new("svc.rpc").name("t"));
+        let mut client = RpcClient::new(transport);
+        let mut call = client.call::<_, bool>("ping", ());
+
+        // Writes the handshake and the request, and then reads the replies of the server.
+        future::lazy(|| connection.poll()).wait().unwrap();
+        future::lazy(|| connection.poll()).wait().unwrap();
+        assert_eq!(Async::Ready(true), future::lazy(|| call.poll()).wait().unwrap());
+
+        let writes = writes.lock().unwrap();
+        let writes = String::from_utf8_lossy(&writes);
+        let lines: Vec<&str> = writes.split("\r\n").collect();
+        assert!(lines[0].starts_with("CONNECT {"));
+        assert!(lines[0].contains("\"name\":\"t\""));
+        let inbox = lines[1].split(' ').nth(1).unwrap();
+        assert_eq!(format!("SUB {} 1", inbox), lines[1]);
+        assert!(lines[2].starts_with(&format!("PUB svc.rpc {} ", inbox)));
+        assert_eq!("PONG", lines[4]);
+    }
+
+    #[test]
+    fn unanswered_call_times_out() {
+        let socket = ServerSocket {
+            writes: Arc::new(Mutex::new(Vec::new())),
+            reply: None,
+        };
+        let options = NatsOptions::new("svc.rpc").timeout(Duration::from_millis(20));
+        let (transport, _connection) = connect(socket, options);
+        let mut client = RpcClient::new(transport);
+        let error = client.call::<_, bool>("ping", ()).wait().unwrap_err();
+        assert_eq!(ErrorKind::TransportError, *error.kind());
+        let io_error = error.transport_error::<io::Error>().unwrap();
+        assert_eq!(io::ErrorKind::TimedOut, io_error.kind());
+    }
+}
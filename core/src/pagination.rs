@@ -0,0 +1,163 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Iterating over all items of a method returning them one page at a time.
+//!
+//! Methods listing many items often return a page of them along with a cursor to pass to the
+//! next call, to get the following page. [`paginate`] turns such a method into a stream of all
+//! items, calling the method again with the cursor from the previous page whenever the items of
+//! a page run out, until a page comes without a cursor. Call `wait` on the stream for a blocking
+//! iterator over the items.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::pagination::paginate;
+//!
+//! // A method taking an optional cursor and returning `(Vec<Transaction>, Option<String>)`.
+//! let transactions = paginate(|cursor| client.list_transactions(100, cursor));
+//! for transaction in transactions.wait() {
+//!     println!("{:?}", transaction?);
+//! }
+//! ```
+//!
+//! [`paginate`]: fn.paginate.html
+
+use Error;
+use futures::{Async, Future, IntoFuture, Poll, Stream};
+use std::fmt;
+use std::vec;
+
+
+/// Returns a stream of the items of all pages fetched with `fetch`. `fetch` is called with
+/// `None` for the first page, and with the cursor returned along with the previous page for the
+/// following ones. Pages are fetched one at a time, when the items of the previous page have
+/// been taken from the stream. The stream ends after the first page returned without a cursor,
+/// and fails with the error of a failing call.
+pub fn paginate<C, T, F, R>(fetch: F) -> Paginated<C, T, F, R::Future>
+where
+    F: FnMut(Option<C>) -> R,
+    R: IntoFuture<Item = (Vec<T>, Option<C>), Error = Error>,
+{
+    Paginated {
+        fetch,
+        page: Some(Page::Cursor(None)),
+        items: Vec::new().into_iter(),
+    }
+}
+
+
+/// The stream of all items of all pages. Returned by [`paginate`](fn.paginate.html).
+pub struct Paginated<C, T, F, R> {
+    fetch: F,
+    /// The next page, `None` after the last one.
+    page: Option<Page<C, R>>,
+    /// The items of the current page not yet taken from the stream.
+    items: vec::IntoIter<T>,
+}
+
+/// The next page to be returned by a `Paginated` stream.
+enum Page<C, R> {
+    /// A page not yet requested, fetched with the given cursor.
+    Cursor(Option<C>),
+    /// A page being fetched.
+    Fetching(R),
+}
+
+impl<C, T, F, R, IR> Stream for Paginated<C, T, F, R>
+where
+    F: FnMut(Option<C>) -> IR,
+    IR: IntoFuture<Future = R, Item = (Vec<T>, Option<C>), Error = Error>,
+    R: Future<Item = (Vec<T>, Option<C>), Error = Error>,
+{
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<T>, Error> {
+        loop {
+            if let Some(item) = self.items.next() {
+                return Ok(Async::Ready(Some(item)));
+            }
+            let mut future = match self.page.take() {
+                None => return Ok(Async::Ready(None)),
+                Some(Page::Cursor(cursor)) => (self.fetch)(cursor).into_future(),
+                Some(Page::Fetching(future)) => future,
+            };
+            // The stream ends if fetching the page fails.
+            let (items, cursor) = match future.poll()? {
+                Async::Ready(page) => page,
+                Async::NotReady => {
+                    self.page = Some(Page::Fetching(future));
+                    return Ok(Async::NotReady);
+                }
+            };
+            trace!("Fetched page of {} items", items.len());
+            self.items = items.into_iter();
+            self.page = cursor.map(|cursor| Page::Cursor(Some(cursor)));
+        }
+    }
+}
+
+impl<C: fmt::Debug, T, F, R> fmt::Debug for Paginated<C, T, F, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let page = match self.page {
+            Some(Page::Cursor(ref cursor)) => format!("{:?}", cursor),
+            Some(Page::Fetching(_)) => "fetching".to_owned(),
+            None => "done".to_owned(),
+        };
+        f.debug_struct("Paginated")
+            .field("page", &page)
+            .field("items", &self.items.len())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ErrorKind;
+    use futures::future;
+
+    #[test]
+    fn fetches_all_pages() {
+        let mut cursors = Vec::new();
+        let items: Vec<u32> = {
+            let pages = paginate(|cursor: Option<u32>| {
+                cursors.push(cursor);
+                let start = cursor.unwrap_or(0);
+                let next = if start < 6 { Some(start + 3) } else { None };
+                future::ok((vec![start, start + 1, start + 2], next))
+            });
+            pages.wait().map(|item| item.unwrap()).collect()
+        };
+        assert_eq!((0..9).collect::<Vec<_>>(), items);
+        assert_eq!(vec![None, Some(3), Some(6)], cursors);
+    }
+
+    #[test]
+    fn empty_pages() {
+        let mut pages = paginate(|cursor: Option<u32>| match cursor {
+            None => Ok((vec![], Some(1))),
+            Some(_) => Ok((vec!["last"], None)),
+        }).wait();
+        assert_eq!("last", pages.next().unwrap().unwrap());
+        assert!(pages.next().is_none());
+    }
+
+    #[test]
+    fn stops_at_error() {
+        let mut pages = paginate(|cursor: Option<u32>| match cursor {
+            None => Ok((vec![1], Some(1))),
+            Some(_) => Err(ErrorKind::TransportError.into()),
+        }).wait();
+        assert_eq!(1, pages.next().unwrap().unwrap());
+        assert!(pages.next().unwrap().is_err());
+        assert!(pages.next().is_none());
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Watching the result of a method by calling it repeatedly.
+//!
+//! Servers without subscriptions can only be watched by polling. A [`Polling`] stream calls a
+//! method over and over at a fixed interval and yields the results, or with
+//! [`changes_only`](struct.Polling.html#method.changes_only) only the results differing from the
+//! previous one. Failing calls end the stream, unless it is told to keep polling after failures
+//! with a backoff.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::polling::Polling;
+//! use jsonrpc_client_core::retry::Backoff;
+//!
+//! let block_counts = Polling::new(Duration::from_secs(5), || client.get_block_count())
+//!     .changes_only()
+//!     .retry_failures(Backoff::Exponential {
+//!         initial: Duration::from_secs(1),
+//!         max: Duration::from_secs(60),
+//!     });
+//! for block_count in block_counts.wait() {
+//!     println!("New block: {}", block_count?);
+//! }
+//! ```
+//!
+//! [`Polling`]: struct.Polling.html
+
+use Error;
+use futures::{Async, Future, IntoFuture, Poll, Stream};
+use retry::Backoff;
+use std::fmt;
+use std::time::Duration;
+use timer;
+use tokio_timer::Sleep;
+
+
+/// A stream of the results of calling a method repeatedly.
+pub struct Polling<T, F, R> {
+    fetch: F,
+    interval: Duration,
+    backoff: Option<Backoff>,
+    max_failures: Option<u32>,
+    failures: u32,
+    filter: Option<Box<FnMut(&T) -> bool + Send>>,
+    state: PollingState<R>,
+}
+
+enum PollingState<R> {
+    /// The next call is to be made right away.
+    Due,
+    Fetching(R),
+    Waiting(Sleep),
+    Done,
+}
+
+impl<T, F, R> Polling<T, F, R> {
+    /// Creates a stream calling `fetch` right away, and again `interval` after each result.
+    ///
+    /// Defaults to yielding every result and ending with the error of the first failing call.
+    pub fn new<IR>(interval: Duration, fetch: F) -> Self
+    where
+        F: FnMut() -> IR,
+        IR: IntoFuture<Future = R, Item = T, Error = Error>,
+    {
+        Polling {
+            fetch,
+            interval,
+            backoff: None,
+            max_failures: None,
+            failures: 0,
+            filter: None,
+            state: PollingState::Due,
+        }
+    }
+
+    /// Keeps polling after failing calls instead of ending the stream, waiting as `backoff`
+    /// tells before calling again. Failures are not yielded, only logged.
+    pub fn retry_failures(mut self, backoff: Backoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// Ends the stream with the error of the call after `max_failures` calls in a row have
+    /// failed, when retrying failures.
+    pub fn max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = Some(max_failures.max(1));
+        self
+    }
+
+    /// Only yields results differing from the previously yielded one.
+    pub fn changes_only(mut self) -> Self
+    where
+        T: PartialEq + Clone + Send + 'static,
+    {
+        let mut previous: Option<T> = None;
+        self.filter = Some(Box::new(move |result: &T| {
+            if previous.as_ref() == Some(result) {
+                return false;
+            }
+            previous = Some(result.clone());
+            true
+        }));
+        self
+    }
+}
+
+impl<T, F, R, IR> Stream for Polling<T, F, R>
+where
+    F: FnMut() -> IR,
+    IR: IntoFuture<Future = R, Item = T, Error = Error>,
+    R: Future<Item = T, Error = Error>,
+{
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<T>, Error> {
+        loop {
+            let next_state = match self.state {
+                PollingState::Due => PollingState::Fetching((self.fetch)().into_future()),
+                PollingState::Fetching(ref mut future) => match future.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(result)) => {
+                        self.failures = 0;
+                        self.state = PollingState::Waiting(timer::sleep(self.interval));
+                        let changed = self.filter.as_mut().map_or(true, |filter| filter(&result));
+                        if changed {
+                            return Ok(Async::Ready(Some(result)));
+                        }
+                        continue;
+                    }
+                    Err(error) => {
+                        self.failures += 1;
+                        let failures = self.failures;
+                        let gives_up = self.max_failures.map_or(false, |max| failures >= max);
+                        match self.backoff {
+                            Some(ref backoff) if !gives_up => {
+                                let delay = backoff.delay(failures - 1);
+                                debug!("Polled call failed ({}), retrying in {:?}", error, delay);
+                                PollingState::Waiting(timer::sleep(delay))
+                            }
+                            _ => {
+                                self.state = PollingState::Done;
+                                return Err(error);
+                            }
+                        }
+                    }
+                },
+                PollingState::Waiting(ref mut sleep) => match sleep.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => PollingState::Due,
+                    Err(error) => {
+                        warn!("Unable to wait before polling again: {}", error);
+                        PollingState::Due
+                    }
+                },
+                PollingState::Done => return Ok(Async::Ready(None)),
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+impl<T, F, R> fmt::Debug for Polling<T, F, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Polling")
+            .field("interval", &self.interval)
+            .field("backoff", &self.backoff)
+            .field("max_failures", &self.max_failures)
+            .field("failures", &self.failures)
+            .field("changes_only", &self.filter.is_some())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ErrorKind;
+
+    #[test]
+    fn yields_changes() {
+        let mut results = vec![1, 1, 2, 2, 2, 3].into_iter();
+        let polling = Polling::new(Duration::from_millis(1), || {
+            results.next().ok_or_else(|| Error::from(ErrorKind::TransportError))
+        }).changes_only();
+        let mut changes = polling.wait();
+        for expected in 1..4 {
+            assert_eq!(expected, changes.next().unwrap().unwrap());
+        }
+        assert!(changes.next().unwrap().is_err());
+        assert!(changes.next().is_none());
+    }
+
+    #[test]
+    fn retries_failures() {
+        let mut calls = 0;
+        let polling = Polling::new(Duration::from_millis(1), || {
+            calls += 1;
+            match calls {
+                2 | 4 | 5 => Err(ErrorKind::TransportError.into()),
+                _ => Ok(calls),
+            }
+        }).retry_failures(Backoff::Fixed(Duration::from_millis(1)))
+            .max_failures(2);
+        let mut results = polling.wait();
+        assert_eq!(1, results.next().unwrap().unwrap());
+        assert_eq!(3, results.next().unwrap().unwrap());
+        assert!(results.next().unwrap().is_err());
+        assert!(results.next().is_none());
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Subscriptions to streams of notifications pushed by the server.
+//!
+//! Many servers on persistent connections let clients subscribe to events. The client calls a
+//! subscribe method, the server answers with a subscription id and then sends a notification for
+//! every event, carrying the id and the event in its `params`:
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "method": "newHeads", "params": {"subscription": "0x9c", "result": {}}}
+//! ```
+//!
+//! Transports able to route such notifications implement [`PubSubTransport`], like
+//! [`DuplexTransport`]. [`subscribe`] makes the subscribe call and resolves to a
+//! [`Subscription`], a `Stream` of the `result` of every notification, deserialized into the
+//! item type. The stream ends when the connection closes.
+//!
+//! A [`ReconnectingTransport`] over a `PubSubTransport` remembers the active subscriptions and
+//! issues their subscribe calls again on every new connection. Notifications of the new
+//! subscription flow into the same `Subscription` stream, so consumers keep receiving events
+//! after the connection drops. The server may hand out a new subscription id when subscribing
+//! again, `Subscription::id` stays the id of the first subscription. Events sent while there was
+//! no connection are lost.
+//!
+//! Notifications are buffered until the `Subscription` is polled, without limit by default. A
+//! consumer falling behind a high-volume subscription would let the buffer grow without bound,
+//! so a limit can be set with [`ClientConfig::subscription_buffer`], along with an [`Overflow`]
+//! policy for notifications arriving while the buffer is full.
+//!
+//! Dropping a `Subscription` stops routing its notifications and re-subscribing it, but does not
+//! tell the server. Call the unsubscribe method of the server for that.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::pubsub;
+//!
+//! let config = ClientConfig::default();
+//! let heads = pubsub::subscribe::<_, _, Header>(
+//!     &transport,
+//!     &config,
+//!     "eth_subscribe".to_owned(),
+//!     ("newHeads",),
+//! );
+//! core.run(heads.and_then(|heads| heads.for_each(|head| handle_head(head))))?;
+//! ```
+//!
+//! [`PubSubTransport`]: trait.PubSubTransport.html
+//! [`DuplexTransport`]: ../duplex/struct.DuplexTransport.html
+//! [`ReconnectingTransport`]: ../reconnect/struct.ReconnectingTransport.html
+//! [`subscribe`]: fn.subscribe.html
+//! [`Subscription`]: struct.Subscription.html
+//! [`ClientConfig::subscription_buffer`]: ../struct.ClientConfig.html#method.subscription_buffer
+//! [`Overflow`]: enum.Overflow.html
+
+use {call_method_with, ClientConfig, Error, ErrorKind, RpcRequest, Transport};
+use error::ResultExt;
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll, Stream};
+use jsonrpc_core::types::Version;
+use serde;
+use serde_json::{self, Value as JsonValue};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+
+/// A transport that can route notifications of subscriptions back to the subscriber.
+pub trait PubSubTransport: Transport {
+    /// Sends the subscribe call in `json_data`, like `Transport::send`. If the response has a
+    /// `result`, it is the id of the subscription, and the `result` in the `params` of every
+    /// later notification with that `subscription` id is sent to `notifications`.
+    fn subscribe(
+        &self,
+        json_data: Vec<u8>,
+        notifications: NotificationSender,
+    ) -> Self::Future;
+}
+
+
+/// What happens to a notification arriving while the buffer of its subscription is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drops the oldest buffered notification to make room for the new one.
+    DropOldest,
+    /// Drops the new notification.
+    DropNewest,
+    /// Ends the subscription. The stream yields the buffered notifications, then fails with
+    /// `ErrorKind::SubscriptionOverflow`.
+    Error,
+}
+
+
+#[derive(Debug)]
+struct Buffer {
+    notifications: VecDeque<JsonValue>,
+    limit: Option<(usize, Overflow)>,
+    /// The number of notifications dropped because the buffer was full.
+    dropped: u64,
+    /// If the buffer overflowed with `Overflow::Error`.
+    overflowed: bool,
+    senders: usize,
+    receiver_alive: bool,
+    receiver: Option<Task>,
+}
+
+/// Hands the notifications of a subscription to its `Subscription` stream, buffering them until
+/// the stream is polled. The stream ends when all senders are dropped.
+#[derive(Debug)]
+pub struct NotificationSender {
+    buffer: Arc<Mutex<Buffer>>,
+}
+
+impl NotificationSender {
+    /// Buffers `notification` for the subscription. Returns false if the subscription is gone,
+    /// because the `Subscription` was dropped or ended after its buffer overflowed.
+    pub fn send(&self, notification: JsonValue) -> bool {
+        let mut buffer = self.buffer.lock().unwrap();
+        if !buffer.receiver_alive || buffer.overflowed {
+            return false;
+        }
+        match buffer.limit {
+            Some((capacity, overflow)) if buffer.notifications.len() >= capacity => {
+                buffer.dropped += 1;
+                match overflow {
+                    Overflow::DropOldest => {
+                        warn!("Subscription buffer full, dropping the oldest notification");
+                        buffer.notifications.pop_front();
+                        buffer.notifications.push_back(notification);
+                    }
+                    Overflow::DropNewest => {
+                        warn!("Subscription buffer full, dropping the newest notification");
+                    }
+                    Overflow::Error => {
+                        warn!("Subscription buffer full, ending the subscription");
+                        buffer.overflowed = true;
+                    }
+                }
+            }
+            _ => buffer.notifications.push_back(notification),
+        }
+        if let Some(receiver) = buffer.receiver.take() {
+            receiver.notify();
+        }
+        !buffer.overflowed
+    }
+
+    /// Returns true if notifications can no longer be delivered, see `send`.
+    pub fn is_closed(&self) -> bool {
+        let buffer = self.buffer.lock().unwrap();
+        !buffer.receiver_alive || buffer.overflowed
+    }
+}
+
+impl Clone for NotificationSender {
+    fn clone(&self) -> Self {
+        self.buffer.lock().unwrap().senders += 1;
+        NotificationSender {
+            buffer: self.buffer.clone(),
+        }
+    }
+}
+
+impl Drop for NotificationSender {
+    fn drop(&mut self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.senders -= 1;
+        if buffer.senders == 0 {
+            if let Some(receiver) = buffer.receiver.take() {
+                receiver.notify();
+            }
+        }
+    }
+}
+
+struct NotificationReceiver {
+    buffer: Arc<Mutex<Buffer>>,
+    /// If the overflow error has been returned.
+    failed: bool,
+}
+
+impl Stream for NotificationReceiver {
+    type Item = JsonValue;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<JsonValue>, Error> {
+        let mut buffer = self.buffer.lock().unwrap();
+        if let Some(notification) = buffer.notifications.pop_front() {
+            return Ok(Async::Ready(Some(notification)));
+        }
+        if buffer.overflowed && !self.failed {
+            self.failed = true;
+            return Err(ErrorKind::SubscriptionOverflow.into());
+        }
+        if buffer.overflowed || buffer.senders == 0 {
+            return Ok(Async::Ready(None));
+        }
+        buffer.receiver = Some(task::current());
+        Ok(Async::NotReady)
+    }
+}
+
+impl Drop for NotificationReceiver {
+    fn drop(&mut self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.receiver_alive = false;
+        buffer.notifications.clear();
+    }
+}
+
+/// Creates the buffer of a subscription, holding at most `limit.0` notifications if given.
+fn notification_channel(
+    limit: Option<(usize, Overflow)>,
+) -> (NotificationSender, NotificationReceiver) {
+    let buffer = Arc::new(Mutex::new(Buffer {
+        notifications: VecDeque::new(),
+        limit,
+        dropped: 0,
+        overflowed: false,
+        senders: 1,
+        receiver_alive: true,
+        receiver: None,
+    }));
+    let sender = NotificationSender {
+        buffer: buffer.clone(),
+    };
+    (
+        sender,
+        NotificationReceiver {
+            buffer,
+            failed: false,
+        },
+    )
+}
+
+
+/// Prepares a lazy call to the subscribe method `method`, with parameters serialized like for
+/// [`call_method`](../fn.call_method.html). Resolves to a stream of the notifications of the
+/// subscription, with items deserialized into `N`.
+pub fn subscribe<T, P, N>(
+    transport: &T,
+    config: &ClientConfig,
+    method: String,
+    params: P,
+) -> SubscribeRequest<N, T::Future>
+where
+    T: PubSubTransport,
+    P: serde::Serialize,
+    N: serde::de::DeserializeOwned,
+{
+    let (notifications_tx, notifications_rx) = notification_channel(config.subscription_buffer);
+    let request = call_method_with(
+        transport,
+        config,
+        config.validation,
+        Version::V2,
+        method,
+        params,
+        |json_data| transport.subscribe(json_data, notifications_tx),
+    );
+    SubscribeRequest {
+        request,
+        notifications: Some(notifications_rx),
+        _marker: PhantomData,
+    }
+}
+
+
+/// A lazy subscribe call `Future`, resolving to the `Subscription`. Created by
+/// [`subscribe`](fn.subscribe.html).
+pub struct SubscribeRequest<N, F> {
+    request: RpcRequest<JsonValue, F>,
+    notifications: Option<NotificationReceiver>,
+    _marker: PhantomData<fn() -> N>,
+}
+
+impl<N, E, F> Future for SubscribeRequest<N, F>
+where
+    N: serde::de::DeserializeOwned,
+    E: ::std::error::Error + Send + Sync + 'static,
+    F: Future<Item = Vec<u8>, Error = E> + Send + 'static,
+{
+    type Item = Subscription<N>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Subscription<N>, Error> {
+        let id = try_ready!(self.request.poll());
+        let notifications = self.notifications
+            .take()
+            .expect("Cannot poll SubscribeRequest twice after it is done");
+        Ok(Async::Ready(Subscription {
+            id,
+            notifications,
+            _marker: PhantomData,
+        }))
+    }
+}
+
+
+/// A `Stream` of the notifications of a subscription, deserialized into `N`.
+///
+/// Ends when the connection closes, or, over a `ReconnectingTransport`, when it is closed. Fails
+/// with `ErrorKind::SubscriptionOverflow` if its buffer overflowed with `Overflow::Error`.
+pub struct Subscription<N> {
+    id: JsonValue,
+    notifications: NotificationReceiver,
+    _marker: PhantomData<fn() -> N>,
+}
+
+impl<N> Subscription<N> {
+    /// Returns the id the server gave the subscription when it was made.
+    pub fn id(&self) -> &JsonValue {
+        &self.id
+    }
+
+    /// Returns the number of notifications dropped because the buffer of the subscription was
+    /// full.
+    pub fn dropped(&self) -> u64 {
+        self.notifications.buffer.lock().unwrap().dropped
+    }
+}
+
+impl<N: serde::de::DeserializeOwned> Stream for Subscription<N> {
+    type Item = N;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<N>, Error> {
+        match try_ready!(self.notifications.poll()) {
+            Some(notification) => serde_json::from_value(notification)
+                .chain_err(|| ErrorKind::ResponseError("Not a valid notification"))
+                .map(|notification| Async::Ready(Some(notification))),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use duplex::DuplexTransport;
+    use futures::sync::mpsc::channel;
+    use futures::Sink;
+    use std::io;
+    use std::thread;
+
+    fn closed<E>(_: E) -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, "Channel closed")
+    }
+
+    #[test]
+    fn notifications_of_subscription() {
+        let (requests_tx, requests_rx) = channel(16);
+        let (responses_tx, responses_rx) = channel(16);
+        let (transport, connection) =
+            DuplexTransport::new(requests_tx.sink_map_err(closed), responses_rx.map_err(closed));
+        thread::spawn(move || connection.wait());
+        // Answers the subscribe call and sends notifications of this and another subscription.
+        thread::spawn(move || {
+            let mut responses = responses_tx.wait();
+            let request = requests_rx.wait().next().unwrap().unwrap();
+            let request: JsonValue = serde_json::from_slice(&request).unwrap();
+            assert_eq!(json!("subscribe"), request["method"]);
+            let messages = vec![
+                json!({"jsonrpc": "2.0", "id": request["id"], "result": "sub"}),
+                json!({"method": "n", "params": {"subscription": "sub", "result": 1}}),
+                json!({"method": "n", "params": {"subscription": "other", "result": 2}}),
+                json!({"method": "n", "params": {"subscription": "sub", "result": 3}}),
+            ];
+            for message in messages {
+                responses.send(serde_json::to_vec(&message).unwrap()).unwrap();
+            }
+        });
+
+        let config = ClientConfig::default();
+        let subscription = subscribe::<_, _, u64>(&transport, &config, "subscribe".to_owned(), ())
+            .wait()
+            .unwrap();
+        assert_eq!(json!("sub"), *subscription.id());
+        assert_eq!(vec![1, 3], subscription.collect().wait().unwrap());
+    }
+
+    #[test]
+    fn buffer_overflow() {
+        let overflowing = |overflow| {
+            let (sender, receiver) = notification_channel(Some((2, overflow)));
+            for i in 1..4 {
+                sender.send(json!(i));
+            }
+            (sender, receiver)
+        };
+        let (_, receiver) = overflowing(Overflow::DropOldest);
+        assert_eq!(vec![json!(2), json!(3)], receiver.collect().wait().unwrap());
+        let (_, receiver) = overflowing(Overflow::DropNewest);
+        assert_eq!(1, receiver.buffer.lock().unwrap().dropped);
+        assert_eq!(vec![json!(1), json!(2)], receiver.collect().wait().unwrap());
+
+        let (sender, receiver) = overflowing(Overflow::Error);
+        assert!(sender.is_closed());
+        let mut notifications = receiver.wait();
+        assert_eq!(json!(1), notifications.next().unwrap().unwrap());
+        assert_eq!(json!(2), notifications.next().unwrap().unwrap());
+        let error = notifications.next().unwrap().unwrap_err();
+        assert_eq!(ErrorKind::SubscriptionOverflow, *error.kind());
+        assert!(notifications.next().is_none());
+    }
+}
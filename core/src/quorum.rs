@@ -0,0 +1,418 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport sending every call to several endpoints at once.
+//!
+//! Wallets and other software talking to servers they do not fully trust can ask several of
+//! them and only accept an answer enough of them agree on. A [`QuorumTransport`] sends every call
+//! to all of its endpoints in parallel and completes as soon as its [`Agreement`] is reached:
+//! with the first successful response, or with a response that a majority, or a given number, of
+//! the endpoints agree on. Responses agree when their `result` and `error` members are equal as
+//! JSON, whatever their formatting. The calls still in flight are then dropped.
+//!
+//! When the agreement can no longer be reached, the call fails with
+//! [`QuorumError::NoAgreement`], or with [`QuorumError::Transport`] if every endpoint failed.
+//! Both hold what every endpoint responded or failed with, to tell why.
+//! Fanning out only makes sense for calls that read, so calls to other methods can be sent to a
+//! single endpoint with [`QuorumTransport::idempotent_only`].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::quorum::{Agreement, QuorumTransport};
+//!
+//! let servers = vec![electrum1, electrum2, electrum3];
+//! let transport = QuorumTransport::new(servers)
+//!     .agreement(Agreement::Majority)
+//!     .idempotent_only(ElectrumClient::<BoxTransport>::METHODS);
+//! let client = ElectrumClient::new(transport);
+//! ```
+//!
+//! [`QuorumTransport`]: struct.QuorumTransport.html
+//! [`Agreement`]: enum.Agreement.html
+//! [`QuorumError::NoAgreement`]: enum.QuorumError.html#variant.NoAgreement
+//! [`QuorumError::Transport`]: enum.QuorumError.html#variant.Transport
+//! [`QuorumTransport::idempotent_only`]: struct.QuorumTransport.html#method.idempotent_only
+
+use {MethodInfo, Transport};
+use futures::{Async, Future, Poll};
+use serde_json::{self, Value as JsonValue};
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// How many endpoints of a `QuorumTransport` must agree on a response before it is accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Agreement {
+    /// Accept the first successful response.
+    First,
+    /// Accept a response once more than half of the endpoints agree on it.
+    Majority,
+    /// Accept a response once the given number of endpoints agree on it, or all of them if there
+    /// are fewer.
+    AtLeast(usize),
+}
+
+impl Agreement {
+    /// Returns the number of agreeing responses needed out of `endpoints`.
+    fn needed(&self, endpoints: usize) -> usize {
+        match *self {
+            Agreement::First => 1,
+            Agreement::Majority => endpoints / 2 + 1,
+            Agreement::AtLeast(needed) => cmp::min(cmp::max(needed, 1), endpoints),
+        }
+    }
+}
+
+/// Error returned by `QuorumTransport` send operations.
+#[derive(Debug)]
+pub enum QuorumError<E> {
+    /// Not enough endpoints agreed on a response.
+    NoAgreement {
+        /// The distinct responses, each with the indices of the endpoints that responded with
+        /// it.
+        responses: Vec<(Vec<usize>, Vec<u8>)>,
+        /// The errors of the endpoints that failed, with their indices.
+        errors: Vec<(usize, E)>,
+    },
+    /// All endpoints failed, holds the error of each, with its index.
+    Transport(Vec<(usize, E)>),
+}
+
+impl<E: error::Error> fmt::Display for QuorumError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QuorumError::NoAgreement {
+                ref responses,
+                ref errors,
+            } => {
+                let responded: usize = responses.iter().map(|&(ref from, _)| from.len()).sum();
+                write!(
+                    f,
+                    "Not enough endpoints agreed on a response, {} responded with {} different \
+                     responses and {} failed",
+                    responded,
+                    responses.len(),
+                    errors.len()
+                )
+            }
+            QuorumError::Transport(ref errors) => {
+                write!(f, "All endpoints failed")?;
+                for (i, &(endpoint, ref e)) in errors.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { ", " };
+                    write!(f, "{}endpoint {} ({})", separator, endpoint, e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for QuorumError<E> {
+    /// The error of the endpoint that failed last, if any.
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        let errors = match *self {
+            QuorumError::NoAgreement { ref errors, .. } | QuorumError::Transport(ref errors) => {
+                errors
+            }
+        };
+        errors.last().map(|&(_, ref e)| e as &(error::Error + 'static))
+    }
+}
+
+
+/// A transport sending every call to all of several inner transports, completing when enough of
+/// them agree on the response.
+///
+/// Clones share the endpoints and the id counter.
+pub struct QuorumTransport<T> {
+    endpoints: Arc<Vec<T>>,
+    agreement: Agreement,
+    methods: Option<&'static [MethodInfo]>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl<T: Transport> QuorumTransport<T> {
+    /// Creates a transport sending calls to all of `transports`, one per endpoint, and accepting
+    /// the first successful response.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `transports` is empty.
+    pub fn new(transports: Vec<T>) -> Self {
+        assert!(!transports.is_empty(), "No endpoints to send calls to");
+        QuorumTransport {
+            endpoints: Arc::new(transports),
+            agreement: Agreement::First,
+            methods: None,
+            next_id: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+
+    /// Sets how many endpoints must agree on a response.
+    pub fn agreement(mut self, agreement: Agreement) -> Self {
+        self.agreement = agreement;
+        self
+    }
+
+    /// Only send calls to the methods among `methods` that are marked idempotent to all
+    /// endpoints, usually the `METHODS` of a generated client. Other calls are sent to the first
+    /// endpoint only, and its response is accepted as is.
+    pub fn idempotent_only(mut self, methods: &'static [MethodInfo]) -> Self {
+        self.methods = Some(methods);
+        self
+    }
+}
+
+impl<T> Clone for QuorumTransport<T> {
+    fn clone(&self) -> Self {
+        QuorumTransport {
+            endpoints: self.endpoints.clone(),
+            agreement: self.agreement,
+            methods: self.methods,
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<T: Transport> Transport for QuorumTransport<T> {
+    type Future = QuorumFuture<T::Future>;
+    type Error = QuorumError<T::Error>;
+
+    /// Returns ids from a counter of its own, so they are unique on all endpoints.
+    fn get_next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    /// Describes all endpoints, separated by commas.
+    fn endpoint(&self) -> Option<String> {
+        let endpoints: Vec<String> = self.endpoints
+            .iter()
+            .filter_map(Transport::endpoint)
+            .collect();
+        if endpoints.is_empty() {
+            None
+        } else {
+            Some(endpoints.join(", "))
+        }
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let fan_out = self.methods
+            .map_or(true, |methods| MethodInfo::is_idempotent_request(methods, &json_data));
+        let endpoints = if fan_out { &self.endpoints[..] } else { &self.endpoints[..1] };
+        let futures = endpoints
+            .iter()
+            .map(|endpoint| Some(endpoint.send(json_data.clone())))
+            .collect();
+        QuorumFuture {
+            futures,
+            needed: if fan_out { self.agreement.needed(endpoints.len()) } else { 1 },
+            responses: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+
+/// The future returned by `QuorumTransport` send operations.
+pub struct QuorumFuture<F: Future> {
+    /// The calls to all endpoints, `None` for those that have completed.
+    futures: Vec<Option<F>>,
+    needed: usize,
+    /// The distinct responses so far, with what they are compared by and the endpoints that
+    /// responded with them.
+    responses: Vec<(Option<JsonValue>, Vec<u8>, Vec<usize>)>,
+    /// The errors of the endpoints that failed so far.
+    errors: Vec<(usize, F::Error)>,
+}
+
+impl<F: Future<Item = Vec<u8>>> Future for QuorumFuture<F> {
+    type Item = Vec<u8>;
+    type Error = QuorumError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Vec<u8>, QuorumError<F::Error>> {
+        for (endpoint, slot) in self.futures.iter_mut().enumerate() {
+            let result = match slot.as_mut().map(Future::poll) {
+                Some(Ok(Async::NotReady)) | None => continue,
+                Some(result) => result,
+            };
+            *slot = None;
+            match result {
+                Ok(Async::Ready(response)) => {
+                    let verdict = verdict(&response);
+                    let same = self.responses.iter().position(|&(ref other, _, _)| {
+                        verdict.is_some() && *other == verdict
+                    });
+                    let index = match same {
+                        Some(index) => {
+                            self.responses[index].2.push(endpoint);
+                            index
+                        }
+                        None => {
+                            self.responses.push((verdict, response, vec![endpoint]));
+                            self.responses.len() - 1
+                        }
+                    };
+                    if self.responses[index].2.len() >= self.needed {
+                        return Ok(Async::Ready(self.responses.swap_remove(index).1));
+                    }
+                }
+                Err(error) => self.errors.push((endpoint, error)),
+                Ok(Async::NotReady) => unreachable!(),
+            }
+        }
+
+        let in_flight = self.futures.iter().filter(|slot| slot.is_some()).count();
+        let most_agreeing = self.responses
+            .iter()
+            .map(|&(_, _, ref from)| from.len())
+            .max()
+            .unwrap_or(0);
+        if most_agreeing + in_flight >= self.needed {
+            return Ok(Async::NotReady);
+        }
+        let errors = self.errors.drain(..).collect();
+        if self.responses.is_empty() {
+            return Err(QuorumError::Transport(errors));
+        }
+        debug!("No agreement among the {} different responses", self.responses.len());
+        let responses = self.responses
+            .drain(..)
+            .map(|(_, response, from)| (from, response))
+            .collect();
+        Err(QuorumError::NoAgreement { responses, errors })
+    }
+}
+
+/// Returns what a response is compared with others by, its `result` and `error` members, or
+/// those of every response in a batch. `None` if it is not valid JSON.
+fn verdict(response: &[u8]) -> Option<JsonValue> {
+    fn outcome(response: &JsonValue) -> JsonValue {
+        let member = |name| response.get(name).cloned().unwrap_or(JsonValue::Null);
+        JsonValue::Array(vec![member("result"), member("error")])
+    }
+    match serde_json::from_slice(response).ok()? {
+        JsonValue::Array(responses) => Some(responses.iter().map(outcome).collect()),
+        response => Some(outcome(&response)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{self, FutureResult};
+    use std::io;
+
+    /// A transport answering every call with `result`, or failing if it is `None`.
+    struct FixedTransport {
+        result: Option<JsonValue>,
+    }
+
+    fn fixed(results: &[Option<u64>]) -> Vec<FixedTransport> {
+        results
+            .iter()
+            .map(|result| FixedTransport {
+                result: result.map(JsonValue::from),
+            })
+            .collect()
+    }
+
+    impl Transport for FixedTransport {
+        type Future = FutureResult<Vec<u8>, io::Error>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            1
+        }
+
+        fn send(&self, json_data: Vec<u8>) -> Self::Future {
+            let request: JsonValue = serde_json::from_slice(&json_data).unwrap();
+            match self.result {
+                Some(ref result) => {
+                    let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+                    future::ok(serde_json::to_vec(&response).unwrap())
+                }
+                None => future::err(io::Error::new(io::ErrorKind::ConnectionRefused, "Refused")),
+            }
+        }
+    }
+
+    jsonrpc_client!(pub struct HeightClient {
+        #[rpc(idempotent)]
+        pub fn height(&self) -> RpcRequest<u64>;
+        pub fn broadcast(&self) -> RpcRequest<u64>;
+    });
+
+    fn call<T: Transport>(transport: QuorumTransport<T>) -> ::Result<u64> {
+        HeightClient::new(transport).height().call()
+    }
+
+    #[test]
+    fn first_successful_response() {
+        let transport = QuorumTransport::new(fixed(&[None, Some(2), Some(3)]));
+        assert_eq!(2, call(transport).unwrap());
+    }
+
+    #[test]
+    fn majority() {
+        let endpoints = fixed(&[Some(2), None, Some(1), Some(2), Some(2)]);
+        let transport = QuorumTransport::new(endpoints).agreement(Agreement::Majority);
+        assert_eq!(2, call(transport).unwrap());
+
+        let endpoints = fixed(&[Some(1), None, Some(2), Some(3)]);
+        let transport = QuorumTransport::new(endpoints).agreement(Agreement::Majority);
+        let error = call(transport).unwrap_err();
+        let error = error.transport_error::<QuorumError<io::Error>>().unwrap();
+        match *error {
+            QuorumError::NoAgreement {
+                ref responses,
+                ref errors,
+            } => {
+                let from: Vec<Vec<usize>> = responses.iter().map(|r| r.0.clone()).collect();
+                assert_eq!(vec![vec![0], vec![2], vec![3]], from);
+                let result: JsonValue = serde_json::from_slice(&responses[1].1).unwrap();
+                assert_eq!(json!(2), result["result"]);
+                assert_eq!(vec![1], errors.iter().map(|e| e.0).collect::<Vec<_>>());
+            }
+            ref error => panic!("Unexpected error {}", error),
+        }
+    }
+
+    #[test]
+    fn all_endpoints_failing() {
+        let transport = QuorumTransport::new(fixed(&[None, None])).agreement(Agreement::AtLeast(2));
+        let error = call(transport).unwrap_err();
+        let error = error.transport_error::<QuorumError<io::Error>>().unwrap();
+        match *error {
+            QuorumError::Transport(ref errors) => {
+                assert_eq!(2, errors.len());
+                let refused = |&(_, ref e): &(usize, io::Error)| {
+                    e.kind() == io::ErrorKind::ConnectionRefused
+                };
+                assert!(errors.iter().all(refused));
+            }
+            ref error => panic!("Unexpected error {}", error),
+        }
+    }
+
+    #[test]
+    fn only_reads_fan_out() {
+        let endpoints = fixed(&[Some(1), Some(2), Some(2)]);
+        let transport = QuorumTransport::new(endpoints)
+            .agreement(Agreement::Majority)
+            .idempotent_only(HeightClient::<QuorumTransport<FixedTransport>>::METHODS);
+        let client = HeightClient::new(transport);
+        assert_eq!(2, client.height().call().unwrap());
+        assert_eq!(1, client.broadcast().call().unwrap());
+    }
+}
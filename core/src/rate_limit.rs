@@ -0,0 +1,234 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper throttling the rate of outgoing requests.
+//!
+//! [`RateLimitedTransport`] implements a token bucket. The bucket holds up to `burst` tokens and
+//! is refilled at the configured number of tokens per second. Every request takes one token.
+//! When the bucket is empty, requests are delayed until a token becomes available, in the order
+//! they were sent. Requests are never rejected.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::rate_limit::{RateLimit, RateLimitedTransport};
+//!
+//! let handle = HttpTransport::new().standalone()?.handle("https://gateway.example.com/rpc")?;
+//! let limit = RateLimit::per_second(10.0).burst(20);
+//! let mut client = FizzBuzzClient::new(RateLimitedTransport::new(handle, limit));
+//! ```
+//!
+//! [`RateLimitedTransport`]: struct.RateLimitedTransport.html
+
+use Transport;
+use futures::{Async, Future, Poll};
+use layer::Layer;
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use timer;
+use tokio_timer::Sleep;
+
+
+/// The rate a `RateLimitedTransport` sends requests at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    per_second: f64,
+    burst: u32,
+}
+
+impl RateLimit {
+    /// Allows `requests` requests per second on average. Non-positive rates are treated as the
+    /// smallest possible positive rate.
+    ///
+    /// Defaults to a burst of one request, meaning requests are evenly spaced out.
+    pub fn per_second(requests: f64) -> Self {
+        RateLimit {
+            per_second: requests.max(::std::f64::MIN_POSITIVE),
+            burst: 1,
+        }
+    }
+
+    /// Sets how many requests can be sent back to back after the transport has been idle.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = cmp::max(burst, 1);
+        self
+    }
+}
+
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Bucket {
+            limit,
+            tokens: limit.burst as f64,
+            updated_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token from the bucket and returns how long the caller must wait before it may
+    /// send its request. The token count goes negative while requests are queued, so later
+    /// requests wait for the earlier ones.
+    fn acquire(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated_at);
+        let elapsed_secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        self.tokens = (self.tokens + elapsed_secs * self.limit.per_second)
+            .min(self.limit.burst as f64);
+        self.updated_at = now;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            let wait_secs = -self.tokens / self.limit.per_second;
+            Duration::new(wait_secs as u64, (wait_secs.fract() * 1e9) as u32)
+        }
+    }
+}
+
+
+/// A transport sending requests on an inner transport no faster than a given `RateLimit`.
+///
+/// Clones share the same token bucket, so the limit applies to all of them together.
+#[derive(Debug, Clone)]
+pub struct RateLimitedTransport<T> {
+    transport: T,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl<T: Transport + Clone + Send + 'static> RateLimitedTransport<T> {
+    /// Wraps `transport` so requests are sent at most at the rate given by `limit`.
+    pub fn new(transport: T, limit: RateLimit) -> Self {
+        RateLimitedTransport {
+            transport,
+            bucket: Arc::new(Mutex::new(Bucket::new(limit))),
+        }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport + Clone + Send + 'static> Transport for RateLimitedTransport<T> {
+    type Future = RateLimitedFuture<T>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let delay = self.bucket.lock().unwrap().acquire();
+        let state = if delay == Duration::from_secs(0) {
+            RateLimitState::Sending(self.transport.send(json_data))
+        } else {
+            trace!("Rate limit reached, delaying request {:?}", delay);
+            RateLimitState::Waiting(timer::sleep(delay), Some(json_data))
+        };
+        RateLimitedFuture {
+            transport: self.transport.clone(),
+            state,
+        }
+    }
+}
+
+impl<T: Transport + Clone + Send + 'static> Layer<T> for RateLimit {
+    type Transport = RateLimitedTransport<T>;
+
+    /// Wraps `transport` in a `RateLimitedTransport` with its own token bucket.
+    fn layer(&self, transport: T) -> RateLimitedTransport<T> {
+        RateLimitedTransport::new(transport, *self)
+    }
+}
+
+
+/// The future returned by `RateLimitedTransport` send operations.
+pub struct RateLimitedFuture<T: Transport> {
+    transport: T,
+    state: RateLimitState<T::Future>,
+}
+
+enum RateLimitState<F> {
+    Waiting(Sleep, Option<Vec<u8>>),
+    Sending(F),
+}
+
+impl<T: Transport> Future for RateLimitedFuture<T> {
+    type Item = Vec<u8>;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let json_data = match self.state {
+            RateLimitState::Sending(ref mut future) => return future.poll(),
+            RateLimitState::Waiting(ref mut sleep, ref mut json_data) => {
+                match sleep.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => (),
+                    Err(timer_error) => {
+                        warn!("Unable to delay rate limited request: {}", timer_error)
+                    }
+                }
+                json_data
+                    .take()
+                    .expect("Rate limited future polled after completion")
+            }
+        };
+        self.state = RateLimitState::Sending(self.transport.send(json_data));
+        self.poll()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use test_util::OkTransport;
+
+    #[test]
+    fn spaces_out_requests() {
+        let transport = RateLimitedTransport::new(OkTransport, RateLimit::per_second(50.0));
+        let start = Instant::now();
+        let requests: Vec<_> = (0..3).map(|_| transport.send(vec![])).collect();
+        future::join_all(requests).wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn allows_bursts() {
+        let limit = RateLimit::per_second(1.0).burst(3);
+        let transport = RateLimitedTransport::new(OkTransport, limit);
+        let start = Instant::now();
+        for _ in 0..3 {
+            transport.send(vec![]).wait().unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn queued_requests_wait_in_order() {
+        let mut bucket = Bucket::new(RateLimit::per_second(10.0));
+        assert_eq!(Duration::from_secs(0), bucket.acquire());
+        assert!(bucket.acquire() > Duration::from_millis(90));
+        assert!(bucket.acquire() > Duration::from_millis(190));
+    }
+}
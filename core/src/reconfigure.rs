@@ -0,0 +1,238 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper that allows replacing the inner transport at runtime.
+//!
+//! A [`ReconfigurableTransport`] is given to an RPC client like any other transport. A clone of
+//! it can then be used to switch to a new transport, for example one pointing to another
+//! endpoint or going through another proxy, with [`reconfigure`]. Calls that are already in
+//! flight are drained: they finish on the old transport while all new calls use the new one.
+//! The old transport is dropped when its last call has completed, and the [`Drained`] future
+//! returned from [`reconfigure`] resolves at that point.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::reconfigure::ReconfigurableTransport;
+//!
+//! let handle = HttpTransport::new().standalone()?.handle("http://primary.example.com/rpc")?;
+//! let transport = ReconfigurableTransport::new(handle);
+//! let mut client = FizzBuzzClient::new(transport.clone());
+//!
+//! // Later, when the configuration changes:
+//! let new_handle = HttpTransport::new().standalone()?.handle("http://backup.example.com/rpc")?;
+//! transport.reconfigure(new_handle).wait().unwrap();
+//! ```
+//!
+//! [`ReconfigurableTransport`]: struct.ReconfigurableTransport.html
+//! [`reconfigure`]: struct.ReconfigurableTransport.html#method.reconfigure
+//! [`Drained`]: struct.Drained.html
+
+use Transport;
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
+use std::sync::{Arc, Mutex};
+
+
+/// One configuration of a `ReconfigurableTransport`. Shared by the transport and all calls in
+/// flight on it, so it is dropped when it is both replaced and drained.
+struct Generation<T> {
+    transport: Mutex<T>,
+    drained_tx: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl<T> Generation<T> {
+    fn new(transport: T) -> Arc<Self> {
+        Arc::new(Generation {
+            transport: Mutex::new(transport),
+            drained_tx: Mutex::new(None),
+        })
+    }
+}
+
+impl<T> Drop for Generation<T> {
+    fn drop(&mut self) {
+        if let Ok(mut drained_tx) = self.drained_tx.lock() {
+            if let Some(drained_tx) = drained_tx.take() {
+                let _ = drained_tx.send(());
+            }
+        }
+    }
+}
+
+
+/// A transport forwarding all calls to an inner transport that can be replaced at any time.
+///
+/// Clones share the same inner transport, so reconfiguring one of them reconfigures them all.
+pub struct ReconfigurableTransport<T> {
+    current: Arc<Mutex<Arc<Generation<T>>>>,
+}
+
+impl<T: Transport + Send + 'static> ReconfigurableTransport<T> {
+    /// Creates a new reconfigurable transport initially forwarding calls to `transport`.
+    pub fn new(transport: T) -> Self {
+        ReconfigurableTransport {
+            current: Arc::new(Mutex::new(Generation::new(transport))),
+        }
+    }
+
+    /// Replaces the inner transport with `transport`. All calls made after this use the new
+    /// transport, while calls already in flight complete on the old one.
+    ///
+    /// Returns a future that resolves once all calls on the old transport have completed and the
+    /// old transport has been dropped.
+    pub fn reconfigure(&self, transport: T) -> Drained {
+        let (drained_tx, drained_rx) = oneshot::channel();
+        let old = {
+            let mut current = self.current.lock().unwrap();
+            ::std::mem::replace(&mut *current, Generation::new(transport))
+        };
+        *old.drained_tx.lock().unwrap() = Some(drained_tx);
+        debug!("Transport reconfigured, draining calls in flight on the old transport");
+        Drained(drained_rx)
+    }
+
+    fn current(&self) -> Arc<Generation<T>> {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+impl<T> Clone for ReconfigurableTransport<T> {
+    fn clone(&self) -> Self {
+        ReconfigurableTransport {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<T: Transport + Send + 'static> Transport for ReconfigurableTransport<T> {
+    type Future = ReconfigurableFuture<T>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.current().transport.lock().unwrap().get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.current().transport.lock().unwrap().endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let generation = self.current();
+        let future = generation.transport.lock().unwrap().send(json_data);
+        ReconfigurableFuture {
+            future,
+            generation: Some(generation),
+        }
+    }
+}
+
+
+/// The future returned by `ReconfigurableTransport` send operations. Keeps the transport it was
+/// sent on alive until it completes.
+pub struct ReconfigurableFuture<T: Transport> {
+    future: T::Future,
+    generation: Option<Arc<Generation<T>>>,
+}
+
+impl<T: Transport> Future for ReconfigurableFuture<T> {
+    type Item = Vec<u8>;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = self.future.poll();
+        if let Ok(Async::NotReady) = result {
+            return result;
+        }
+        self.generation = None;
+        result
+    }
+}
+
+
+/// Future returned from `ReconfigurableTransport::reconfigure`. Resolves when all calls on the
+/// replaced transport have completed.
+pub struct Drained(oneshot::Receiver<()>);
+
+impl Future for Drained {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        match self.0.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            _ => Ok(Async::Ready(())),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::io;
+
+    type ResponseTx = oneshot::Sender<Vec<u8>>;
+
+    /// A transport whose responses are sent manually by the test through the stored senders.
+    #[derive(Clone, Default)]
+    struct ManualTransport {
+        pending: Arc<Mutex<Vec<ResponseTx>>>,
+    }
+
+    impl Transport for ManualTransport {
+        type Future = Box<Future<Item = Vec<u8>, Error = io::Error> + Send>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            1
+        }
+
+        fn send(&self, _json_data: Vec<u8>) -> Self::Future {
+            let (tx, rx) = oneshot::channel();
+            self.pending.lock().unwrap().push(tx);
+            Box::new(rx.map_err(|_| io::Error::new(io::ErrorKind::Other, "Response dropped")))
+        }
+    }
+
+    fn is_ready<F: Future>(future: &mut F) -> bool {
+        future::poll_fn(|| Ok::<_, ()>(Async::Ready(future.poll())))
+            .wait()
+            .unwrap()
+            .map(|async| async.is_ready())
+            .unwrap_or(true)
+    }
+
+    #[test]
+    fn drains_old_transport() {
+        let old = ManualTransport::default();
+        let new = ManualTransport::default();
+        let transport = ReconfigurableTransport::new(old.clone());
+
+        let in_flight = transport.send(vec![]);
+        let mut drained = transport.reconfigure(new.clone());
+        let mut after = transport.send(vec![]);
+        assert_eq!(1, old.pending.lock().unwrap().len());
+        assert_eq!(1, new.pending.lock().unwrap().len());
+        assert!(!is_ready(&mut drained));
+
+        old.pending.lock().unwrap().pop().unwrap().send(b"old".to_vec()).unwrap();
+        assert_eq!(b"old".to_vec(), in_flight.wait().unwrap());
+        assert!(is_ready(&mut drained));
+
+        new.pending.lock().unwrap().pop().unwrap().send(b"new".to_vec()).unwrap();
+        assert!(is_ready(&mut after));
+    }
+
+    #[test]
+    fn idle_transport_drains_immediately() {
+        let transport = ReconfigurableTransport::new(ManualTransport::default());
+        transport.reconfigure(ManualTransport::default()).wait().unwrap();
+    }
+}
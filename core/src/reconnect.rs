@@ -0,0 +1,1049 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper reconnecting persistent transports when their connection drops.
+//!
+//! Transports on a persistent connection, like a [`DuplexTransport`], come with a future driving
+//! the connection, and fail every call once the connection is lost. A
+//! [`ReconnectingTransport`] instead creates its inner transports with a connect function, and a
+//! [`Reconnector`] future drives the connection and connects again whenever it ends. Failed
+//! connection attempts are retried with the backoff of a [`ReconnectPolicy`].
+//!
+//! Calls made while there is no connection wait for the next one. Calls that were in flight
+//! when the connection dropped, and so failed with a `BrokenPipe`, `ConnectionReset`,
+//! `ConnectionAborted`, `NotConnected` or `UnexpectedEof` error, are failed or sent again on the
+//! next connection as the [`Replay`] setting of the policy decides. The server may have executed
+//! such a call before the connection dropped, so replaying is off by default.
+//!
+//! Over a [`PubSubTransport`], subscriptions made with [`pubsub::subscribe`] survive the
+//! connection dropping. The subscribe call of every subscription whose stream is still alive is
+//! made again on each new connection, and its notifications flow into the existing stream. If
+//! subscribing again fails, the stream ends.
+//!
+//! Calls with a [deadline] give up waiting for a connection when the next connection attempt
+//! would start after their deadline. They then fail with `ErrorKind::DeadlineExceeded`, holding
+//! the time that was left. Calls to replay on the next connection give up the same way.
+//!
+//! The current state of the connection can be queried with
+//! [`ReconnectingTransport::state`]. Callbacks registered with
+//! [`ReconnectingTransport::on_event`] are told about every change as a [`ConnectionEvent`], for
+//! flipping health indicators, pausing work or logging outages without polling.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::framing;
+//! use jsonrpc_client_core::reconnect::{ReconnectingTransport, ReconnectPolicy, Replay};
+//!
+//! let handle = core.handle();
+//! let policy = ReconnectPolicy::exponential(Duration::from_millis(100), Duration::from_secs(30))
+//!     .replay(Replay::Idempotent(FizzBuzzClient::<DuplexTransport>::METHODS));
+//! let (transport, reconnector) = ReconnectingTransport::new(
+//!     move || TcpStream::connect(&address, &handle).map(framing::line_delimited),
+//!     policy,
+//! );
+//! core.handle().spawn(reconnector.map_err(|e| error!("Gave up reconnecting: {}", e)));
+//!
+//! let client = FizzBuzzClient::new(transport);
+//! ```
+//!
+//! [`DuplexTransport`]: ../duplex/struct.DuplexTransport.html
+//! [deadline]: ../deadline/index.html
+//! [`ReconnectingTransport`]: struct.ReconnectingTransport.html
+//! [`ReconnectingTransport::state`]: struct.ReconnectingTransport.html#method.state
+//! [`ReconnectingTransport::on_event`]: struct.ReconnectingTransport.html#method.on_event
+//! [`ConnectionEvent`]: enum.ConnectionEvent.html
+//! [`Reconnector`]: struct.Reconnector.html
+//! [`ReconnectPolicy`]: struct.ReconnectPolicy.html
+//! [`Replay`]: enum.Replay.html
+//! [`PubSubTransport`]: ../pubsub/trait.PubSubTransport.html
+//! [`pubsub::subscribe`]: ../pubsub/fn.subscribe.html
+
+use {MethodInfo, Transport};
+use deadline::Deadline;
+use futures::sync::oneshot;
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use pubsub::{NotificationSender, PubSubTransport};
+use retry::Backoff;
+use serde_json::{self, Value as JsonValue};
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use timer;
+use tokio_timer::Sleep;
+
+
+/// What to do with calls that were in flight when the connection dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Replay {
+    /// Fail them with the error of the inner transport.
+    Never,
+    /// Send calls to methods marked `#[rpc(idempotent)]` among the given methods again, usually
+    /// the `METHODS` of a generated client. Fail all other calls.
+    Idempotent(&'static [MethodInfo]),
+    /// Send all of them again.
+    Always,
+}
+
+/// Decides how a `ReconnectingTransport` reconnects, and what happens to calls in flight when
+/// the connection drops.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    backoff: Backoff,
+    max_attempts: Option<u32>,
+    replay: Replay,
+}
+
+impl ReconnectPolicy {
+    /// Creates a policy waiting `delay` between failed connection attempts.
+    ///
+    /// Defaults to trying to connect forever and not replaying any calls.
+    pub fn fixed(delay: Duration) -> Self {
+        Self::new(Backoff::Fixed(delay))
+    }
+
+    /// Creates a policy with exponential backoff between failed connection attempts, starting
+    /// at `initial` and doubling the delay for every attempt up to `max`.
+    ///
+    /// Defaults to trying to connect forever and not replaying any calls.
+    pub fn exponential(initial: Duration, max: Duration) -> Self {
+        Self::new(Backoff::Exponential { initial, max })
+    }
+
+    fn new(backoff: Backoff) -> Self {
+        ReconnectPolicy {
+            backoff,
+            max_attempts: None,
+            replay: Replay::Never,
+        }
+    }
+
+    /// Gives up after `max_attempts` connection attempts in a row have failed. The transport is
+    /// then closed and fails all calls.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts.max(1));
+        self
+    }
+
+    /// Sets what happens to calls in flight when the connection drops.
+    pub fn replay(mut self, replay: Replay) -> Self {
+        self.replay = replay;
+        self
+    }
+
+    /// Returns true if the request in `json_data` may be sent again on a new connection.
+    fn may_replay(&self, json_data: &[u8]) -> bool {
+        match self.replay {
+            Replay::Never => false,
+            Replay::Idempotent(methods) => MethodInfo::is_idempotent_request(methods, json_data),
+            Replay::Always => true,
+        }
+    }
+}
+
+
+/// The state of the connection of a `ReconnectingTransport`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// A connection attempt is in progress. Calls wait for it.
+    Connecting,
+    /// Connected. Calls are sent right away.
+    Connected,
+    /// Waiting before the next connection attempt, after `failed_attempts` attempts in a row
+    /// have failed. Calls wait for the next connection.
+    Waiting {
+        /// The number of connection attempts that failed since the last connection.
+        failed_attempts: u32,
+    },
+    /// Gave up connecting, or the `Reconnector` was dropped. Calls fail right away.
+    Closed,
+}
+
+/// A change of the connection of a `ReconnectingTransport`, reported to the callbacks registered
+/// with `ReconnectingTransport::on_event`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// A connection was established.
+    Connected,
+    /// The connection ended.
+    Disconnected {
+        /// The error the connection failed with, if it did not end cleanly.
+        error: Option<String>,
+    },
+    /// A connection attempt is starting after the connection ended or an attempt failed.
+    Reconnecting {
+        /// The number of the attempt since the last connection, counted from one.
+        attempt: u32,
+    },
+    /// Gave up connecting, or the `Reconnector` was dropped. No more events follow.
+    Closed,
+}
+
+/// The inner transport of the current connection, with a number telling connections apart.
+type Connected<T> = (T, u64);
+
+/// A call waiting for the next connection.
+struct Waiter<T> {
+    sender: oneshot::Sender<Connected<T>>,
+    deadline: Option<Deadline>,
+}
+
+impl<T> Waiter<T> {
+    /// Returns true, after giving up on the deadline, if the call has a deadline no later than
+    /// `next_attempt`.
+    fn gives_up_before(&self, next_attempt: Instant) -> bool {
+        match self.deadline {
+            Some(ref deadline) if deadline.at() <= next_attempt => {
+                debug!("No connection before the deadline of the call, giving up");
+                deadline.give_up();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+type Callback = Arc<Fn(&ConnectionEvent) + Send + Sync>;
+
+/// `PubSubTransport::subscribe` of the inner transport.
+type Subscribe<T> =
+    fn(&T, Vec<u8>, NotificationSender) -> <T as Transport>::Future;
+
+/// A subscription to make again on every new connection.
+struct ActiveSubscription<T: Transport> {
+    key: u64,
+    json_data: Vec<u8>,
+    notifications: NotificationSender,
+    subscribe: Subscribe<T>,
+}
+
+impl<T: Transport> ActiveSubscription<T> {
+    /// Makes the subscribe call again on `transport`.
+    fn resubscribe(&self, transport: &T) -> Resubscribing<T::Future> {
+        Resubscribing {
+            key: self.key,
+            future: (self.subscribe)(
+                transport,
+                self.json_data.clone(),
+                self.notifications.clone(),
+            ),
+        }
+    }
+}
+
+/// A subscribe call made again on a new connection.
+struct Resubscribing<F> {
+    key: u64,
+    future: F,
+}
+
+struct State<T: Transport> {
+    connection_state: ConnectionState,
+    current: Option<Connected<T>>,
+    generation: u64,
+    /// Calls waiting for the next connection.
+    waiters: Vec<Waiter<T>>,
+    /// When the next connection attempt starts, while waiting after a failed attempt.
+    next_attempt: Option<Instant>,
+    subscriptions: Vec<ActiveSubscription<T>>,
+    next_subscription_key: u64,
+    /// The subscribe calls made again on the current connection, not yet answered. Polled by
+    /// the `Reconnector`.
+    resubscribing: Vec<Resubscribing<T::Future>>,
+    /// The task of the `Reconnector`, woken when a subscribe call is made again.
+    reconnector: Option<Task>,
+}
+
+struct Shared<T: Transport> {
+    state: Mutex<State<T>>,
+    policy: ReconnectPolicy,
+    next_id: AtomicUsize,
+    callbacks: Mutex<Vec<Callback>>,
+}
+
+impl<T: Transport> Shared<T> {
+    /// Calls all registered callbacks with `event`, without holding any lock.
+    fn emit(&self, event: ConnectionEvent) {
+        let callbacks = self.callbacks.lock().unwrap().clone();
+        for callback in callbacks {
+            callback(&event);
+        }
+    }
+}
+
+impl<T: Transport + Clone> Shared<T> {
+    /// Returns a receiver of the first connection newer than `after`, which may be the current
+    /// one. The receiver fails if the transport is closed, or if the next connection attempt
+    /// starts after `deadline`.
+    fn connection_after(
+        &self,
+        after: Option<u64>,
+        deadline: Option<Deadline>,
+    ) -> oneshot::Receiver<Connected<T>> {
+        let (sender, rx) = oneshot::channel();
+        let waiter = Waiter { sender, deadline };
+        let mut state = self.state.lock().unwrap();
+        match state.current {
+            Some(ref current) if after.map_or(true, |after| current.1 > after) => {
+                let _ = waiter.sender.send(current.clone());
+            }
+            _ if state.connection_state == ConnectionState::Closed => (),
+            _ => {
+                let gave_up = state
+                    .next_attempt
+                    .map_or(false, |next_attempt| waiter.gives_up_before(next_attempt));
+                if !gave_up {
+                    state.waiters.push(waiter);
+                }
+            }
+        }
+        rx
+    }
+
+    /// Remembers that the next connection attempt starts at `next_attempt`, and fails the
+    /// waiting calls with a deadline before then.
+    fn next_attempt_at(&self, next_attempt: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.next_attempt = Some(next_attempt);
+        state
+            .waiters
+            .retain(|waiter| !waiter.gives_up_before(next_attempt));
+    }
+
+    /// Makes `transport` the current connection, makes the subscribe calls of the active
+    /// subscriptions again on it and hands it to the waiting calls.
+    fn connected(&self, transport: T) {
+        let (current, waiters) = {
+            let mut state = self.state.lock().unwrap();
+            state.generation += 1;
+            let current = (transport, state.generation);
+            state.current = Some(current.clone());
+            state.connection_state = ConnectionState::Connected;
+            state
+                .subscriptions
+                .retain(|subscription| !subscription.notifications.is_closed());
+            let resubscribing = state
+                .subscriptions
+                .iter()
+                .map(|subscription| subscription.resubscribe(&current.0))
+                .collect();
+            state.resubscribing = resubscribing;
+            (current, mem::replace(&mut state.waiters, Vec::new()))
+        };
+        // Callbacks hear about the connection before any call waiting for it is sent.
+        self.emit(ConnectionEvent::Connected);
+        for waiter in waiters {
+            let _ = waiter.sender.send(current.clone());
+        }
+    }
+
+    /// Remembers a subscription made on the connection `generation` with the subscribe call in
+    /// `json_data`, to make it again on every new connection.
+    fn subscribed(
+        &self,
+        json_data: Vec<u8>,
+        notifications: NotificationSender,
+        subscribe: Subscribe<T>,
+        generation: u64,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        if state.connection_state == ConnectionState::Closed {
+            return;
+        }
+        let key = state.next_subscription_key;
+        state.next_subscription_key += 1;
+        let subscription = ActiveSubscription {
+            key,
+            json_data,
+            notifications,
+            subscribe,
+        };
+        // The connection may have been replaced before the response was handled, and then the
+        // subscription was not made again on the new one.
+        let resubscribe = match state.current {
+            Some((ref transport, current)) if current != generation => {
+                Some(subscription.resubscribe(transport))
+            }
+            _ => None,
+        };
+        if let Some(resubscribing) = resubscribe {
+            state.resubscribing.push(resubscribing);
+            if let Some(ref task) = state.reconnector {
+                task.notify();
+            }
+        }
+        state.subscriptions.push(subscription);
+    }
+
+    fn set_connection_state(&self, connection_state: ConnectionState) {
+        let mut state = self.state.lock().unwrap();
+        state.connection_state = connection_state;
+        state.next_attempt = None;
+        if connection_state != ConnectionState::Connected {
+            state.current = None;
+            // Made again on the next connection.
+            state.resubscribing.clear();
+        }
+        if connection_state == ConnectionState::Closed {
+            state.waiters.clear();
+            state.subscriptions.clear();
+        }
+    }
+}
+
+
+/// A transport sending calls over the current connection of a persistent inner transport,
+/// connecting again when the connection drops. Driven by a [`Reconnector`].
+///
+/// Clones share the connection.
+///
+/// [`Reconnector`]: struct.Reconnector.html
+pub struct ReconnectingTransport<T: Transport> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> ReconnectingTransport<T>
+where
+    T: Transport<Error = io::Error> + Clone + Send + 'static,
+{
+    /// Creates a transport connecting with `connect`. It returns a future resolving to an inner
+    /// transport and the future driving its connection, like the return value of
+    /// `DuplexTransport::new`. The connection is lost when the driving future completes or fails.
+    ///
+    /// Returns the transport and the `Reconnector`, which has to be spawned on an executor, or
+    /// otherwise polled, for calls to make progress.
+    pub fn new<C, F, D>(connect: C, policy: ReconnectPolicy) -> (Self, Reconnector<T, C, F, D>)
+    where
+        C: FnMut() -> F,
+        F: Future<Item = (T, D), Error = io::Error>,
+        D: Future<Item = (), Error = io::Error>,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                connection_state: ConnectionState::Connecting,
+                current: None,
+                generation: 0,
+                waiters: Vec::new(),
+                next_attempt: None,
+                subscriptions: Vec::new(),
+                next_subscription_key: 0,
+                resubscribing: Vec::new(),
+                reconnector: None,
+            }),
+            policy,
+            next_id: AtomicUsize::new(1),
+            callbacks: Mutex::new(Vec::new()),
+        });
+        let transport = ReconnectingTransport {
+            shared: shared.clone(),
+        };
+        let reconnector = Reconnector {
+            shared,
+            connect,
+            state: ReconnectorState::Connecting(None),
+            failed_attempts: 0,
+            started: false,
+        };
+        (transport, reconnector)
+    }
+
+    /// Returns the current state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        self.shared.state.lock().unwrap().connection_state
+    }
+
+    /// Returns true if there is a connection to send calls on.
+    pub fn is_connected(&self) -> bool {
+        self.state() == ConnectionState::Connected
+    }
+
+    /// Registers `callback` to be called with every later change of the connection. Callbacks
+    /// run on the task polling the `Reconnector` and should return quickly.
+    pub fn on_event<F>(&self, callback: F)
+    where
+        F: Fn(&ConnectionEvent) + Send + Sync + 'static,
+    {
+        self.shared.callbacks.lock().unwrap().push(Arc::new(callback));
+    }
+}
+
+impl<T: Transport> Clone for ReconnectingTransport<T> {
+    fn clone(&self) -> Self {
+        ReconnectingTransport {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Transport for ReconnectingTransport<T>
+where
+    T: Transport<Error = io::Error> + Clone + Send + 'static,
+{
+    type Future = ReconnectingFuture<T>;
+    type Error = io::Error;
+
+    /// Returns ids from a counter of its own, so they stay unique across connections.
+    fn get_next_id(&self) -> u64 {
+        self.shared.next_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .current
+            .as_ref()
+            .and_then(|&(ref transport, _)| transport.endpoint())
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        self.send_on_connection(json_data, None)
+    }
+}
+
+/// Makes subscriptions on the current connection, and again on every later connection.
+impl<T> PubSubTransport for ReconnectingTransport<T>
+where
+    T: PubSubTransport<Error = io::Error> + Clone + Send + 'static,
+{
+    fn subscribe(
+        &self,
+        json_data: Vec<u8>,
+        notifications: NotificationSender,
+    ) -> Self::Future {
+        self.send_on_connection(json_data, Some((notifications, T::subscribe)))
+    }
+}
+
+impl<T> ReconnectingTransport<T>
+where
+    T: Transport<Error = io::Error> + Clone + Send + 'static,
+{
+    fn send_on_connection(
+        &self,
+        json_data: Vec<u8>,
+        subscription: Option<(NotificationSender, Subscribe<T>)>,
+    ) -> ReconnectingFuture<T> {
+        let may_replay = self.shared.policy.may_replay(&json_data);
+        let deadline = Deadline::current();
+        ReconnectingFuture {
+            state: RequestState::Waiting(self.shared.connection_after(None, deadline.clone())),
+            shared: self.shared.clone(),
+            json_data,
+            may_replay,
+            subscription,
+            deadline,
+        }
+    }
+}
+
+
+/// The future returned by `ReconnectingTransport` send operations.
+pub struct ReconnectingFuture<T: Transport> {
+    shared: Arc<Shared<T>>,
+    json_data: Vec<u8>,
+    may_replay: bool,
+    /// The notification sender and the subscribe function of subscribe calls.
+    subscription: Option<(NotificationSender, Subscribe<T>)>,
+    deadline: Option<Deadline>,
+    state: RequestState<T>,
+}
+
+enum RequestState<T: Transport> {
+    Waiting(oneshot::Receiver<Connected<T>>),
+    Sending(T::Future, u64),
+}
+
+impl<T> Future for ReconnectingFuture<T>
+where
+    T: Transport<Error = io::Error> + Clone,
+{
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Vec<u8>, io::Error> {
+        loop {
+            let next_state = match self.state {
+                RequestState::Waiting(ref mut connection) => match connection.poll() {
+                    Ok(Async::Ready((transport, generation))) => {
+                        // Subscribe calls are kept to be made again on later connections.
+                        let json_data = if self.may_replay || self.subscription.is_some() {
+                            self.json_data.clone()
+                        } else {
+                            mem::replace(&mut self.json_data, Vec::new())
+                        };
+                        let future = match self.subscription {
+                            Some((ref notifications, subscribe)) => {
+                                subscribe(&transport, json_data, notifications.clone())
+                            }
+                            None => transport.send(json_data),
+                        };
+                        RequestState::Sending(future, generation)
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(oneshot::Canceled) => {
+                        let gave_up = self.deadline
+                            .as_ref()
+                            .map_or(false, |deadline| deadline.gave_up().is_some());
+                        return Err(if gave_up {
+                            io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "No connection before the deadline",
+                            )
+                        } else {
+                            io::Error::new(io::ErrorKind::NotConnected, "Transport closed")
+                        });
+                    }
+                },
+                RequestState::Sending(ref mut future, generation) => match future.poll() {
+                    Err(ref error) if self.may_replay && is_connection_lost(error) => {
+                        debug!("Connection lost ({}), replaying request", error);
+                        RequestState::Waiting(
+                            self.shared
+                                .connection_after(Some(generation), self.deadline.clone()),
+                        )
+                    }
+                    Ok(Async::Ready(response)) => {
+                        if let Some((notifications, subscribe)) = self.subscription.take() {
+                            if has_result(&response) {
+                                let json_data = mem::replace(&mut self.json_data, Vec::new());
+                                self.shared.subscribed(
+                                    json_data,
+                                    notifications,
+                                    subscribe,
+                                    generation,
+                                );
+                            }
+                        }
+                        return Ok(Async::Ready(response));
+                    }
+                    result => return result,
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+/// Returns true if `response` is a response with a `result`, and not an error response.
+fn has_result(response: &[u8]) -> bool {
+    serde_json::from_slice::<JsonValue>(response)
+        .ok()
+        .map_or(false, |response| response.get("result").is_some())
+}
+
+/// Returns true if the error is one of those a call fails with when its connection drops.
+fn is_connection_lost(error: &io::Error) -> bool {
+    match error.kind() {
+        io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::NotConnected
+        | io::ErrorKind::UnexpectedEof => true,
+        _ => false,
+    }
+}
+
+
+/// The future driving the connections of a `ReconnectingTransport`. Connects, drives the
+/// connection and connects again when it ends.
+///
+/// Fails with the error of the last connection attempt when the policy gives up. Completes when
+/// a connection ends after all transports have been dropped. Dropping it closes the transport.
+#[must_use = "futures do nothing unless polled"]
+pub struct Reconnector<T: Transport, C, F, D> {
+    shared: Arc<Shared<T>>,
+    connect: C,
+    state: ReconnectorState<F, D>,
+    failed_attempts: u32,
+    /// If the first connection attempt has been made.
+    started: bool,
+}
+
+enum ReconnectorState<F, D> {
+    /// Connecting, with `None` before the attempt has started.
+    Connecting(Option<F>),
+    Connected(D),
+    Waiting(Sleep),
+}
+
+impl<T, C, F, D> Future for Reconnector<T, C, F, D>
+where
+    T: Transport<Error = io::Error> + Clone,
+    C: FnMut() -> F,
+    F: Future<Item = (T, D), Error = io::Error>,
+    D: Future<Item = (), Error = io::Error>,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        loop {
+            let next_state = match self.state {
+                ReconnectorState::Connecting(ref mut attempt) => {
+                    if attempt.is_none() {
+                        if Arc::strong_count(&self.shared) == 1 {
+                            debug!("All reconnecting transports dropped, not reconnecting");
+                            return Ok(Async::Ready(()));
+                        }
+                        self.shared
+                            .set_connection_state(ConnectionState::Connecting);
+                        if self.started {
+                            self.shared.emit(ConnectionEvent::Reconnecting {
+                                attempt: self.failed_attempts + 1,
+                            });
+                        }
+                        self.started = true;
+                        *attempt = Some((self.connect)());
+                    }
+                    match attempt.as_mut().map(Future::poll) {
+                        Some(Ok(Async::Ready((transport, connection)))) => {
+                            debug!("Connected after {} failed attempts", self.failed_attempts);
+                            self.failed_attempts = 0;
+                            self.shared.connected(transport);
+                            ReconnectorState::Connected(connection)
+                        }
+                        Some(Err(error)) => {
+                            self.failed_attempts += 1;
+                            let policy = &self.shared.policy;
+                            let failed_attempts = self.failed_attempts;
+                            if policy.max_attempts.map_or(false, |max| failed_attempts >= max) {
+                                warn!("Unable to connect ({}), giving up", error);
+                                self.shared.set_connection_state(ConnectionState::Closed);
+                                self.shared.emit(ConnectionEvent::Closed);
+                                return Err(error);
+                            }
+                            let delay = policy.backoff.delay(self.failed_attempts - 1);
+                            debug!("Unable to connect ({}), retrying in {:?}", error, delay);
+                            self.shared.set_connection_state(ConnectionState::Waiting {
+                                failed_attempts: self.failed_attempts,
+                            });
+                            self.shared.next_attempt_at(Instant::now() + delay);
+                            ReconnectorState::Waiting(timer::sleep(delay))
+                        }
+                        _ => return Ok(Async::NotReady),
+                    }
+                }
+                ReconnectorState::Connected(ref mut connection) => match connection.poll() {
+                    Ok(Async::NotReady) => {
+                        self.poll_resubscribing();
+                        return Ok(Async::NotReady);
+                    }
+                    result => {
+                        let error = match result {
+                            Err(error) => {
+                                warn!("Connection failed ({}), reconnecting", error);
+                                Some(error.to_string())
+                            }
+                            _ => {
+                                debug!("Connection closed, reconnecting");
+                                None
+                            }
+                        };
+                        self.shared
+                            .set_connection_state(ConnectionState::Connecting);
+                        self.shared.emit(ConnectionEvent::Disconnected { error });
+                        ReconnectorState::Connecting(None)
+                    }
+                },
+                ReconnectorState::Waiting(ref mut sleep) => match sleep.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => ReconnectorState::Connecting(None),
+                    Err(error) => {
+                        warn!("Unable to wait before reconnecting: {}", error);
+                        ReconnectorState::Connecting(None)
+                    }
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+impl<T, C, F, D> Reconnector<T, C, F, D>
+where
+    T: Transport<Error = io::Error> + Clone,
+{
+    /// Polls the subscribe calls made again on the current connection. Subscriptions that could
+    /// not be made again are forgotten, which ends their streams.
+    fn poll_resubscribing(&mut self) {
+        let mut resubscribing = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.reconnector = Some(task::current());
+            mem::replace(&mut state.resubscribing, Vec::new())
+        };
+        let mut failed = Vec::new();
+        let mut i = 0;
+        while i < resubscribing.len() {
+            let succeeded = match resubscribing[i].future.poll() {
+                Ok(Async::NotReady) => {
+                    i += 1;
+                    continue;
+                }
+                Ok(Async::Ready(ref response)) if has_result(response) => true,
+                Ok(Async::Ready(_)) => {
+                    warn!("Server refused to subscribe again, ending subscription");
+                    false
+                }
+                // Made again on the next connection.
+                Err(ref error) if is_connection_lost(error) => true,
+                Err(error) => {
+                    warn!("Unable to subscribe again ({}), ending subscription", error);
+                    false
+                }
+            };
+            let done = resubscribing.swap_remove(i);
+            if !succeeded {
+                failed.push(done.key);
+            }
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        state
+            .subscriptions
+            .retain(|subscription| !failed.contains(&subscription.key));
+        state.resubscribing.extend(resubscribing);
+    }
+}
+
+impl<T: Transport, C, F, D> Drop for Reconnector<T, C, F, D> {
+    fn drop(&mut self) {
+        let was_closed = match self.shared.state.lock() {
+            Ok(mut state) => {
+                let was_closed = state.connection_state == ConnectionState::Closed;
+                state.connection_state = ConnectionState::Closed;
+                state.current = None;
+                state.waiters.clear();
+                state.subscriptions.clear();
+                state.resubscribing.clear();
+                was_closed
+            }
+            Err(_) => return,
+        };
+        if !was_closed {
+            self.shared.emit(ConnectionEvent::Closed);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {ClientConfig, ErrorKind, RpcClient};
+    use duplex::{Connection, DuplexTransport};
+    use futures::future::{self, FutureResult};
+    use futures::stream::{MapErr as StreamMapErr, Stream};
+    use futures::sink::{Sink, SinkMapErr};
+    use futures::sync::mpsc::{channel, Receiver, SendError, Sender};
+    use serde_json::{self, Value as JsonValue};
+    use std::sync::mpsc as std_mpsc;
+    use std::thread;
+
+    type TestConnection = Connection<
+        SinkMapErr<Sender<Vec<u8>>, fn(SendError<Vec<u8>>) -> io::Error>,
+        StreamMapErr<Receiver<Vec<u8>>, fn(()) -> io::Error>,
+    >;
+
+    /// The server side of a connection, receiving requests and sending responses.
+    type Server = (Receiver<Vec<u8>>, Sender<Vec<u8>>);
+
+    fn broken_pipe<E>(_: E) -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, "Channel closed")
+    }
+
+    /// Returns a connect function creating connections over channels, handing the server side
+    /// of each connection to the returned receiver.
+    fn connector() -> (
+        impl FnMut() -> FutureResult<(DuplexTransport, TestConnection), io::Error>,
+        std_mpsc::Receiver<Server>,
+    ) {
+        let (servers_tx, servers_rx) = std_mpsc::channel();
+        let connect = move || {
+            let (requests_tx, requests_rx) = channel(16);
+            let (responses_tx, responses_rx) = channel(16);
+            let _ = servers_tx.send((requests_rx, responses_tx));
+            let sink = requests_tx.sink_map_err(broken_pipe as fn(_) -> _);
+            let stream = responses_rx.map_err(broken_pipe as fn(_) -> _);
+            future::ok(DuplexTransport::new(sink, stream))
+        };
+        (connect, servers_rx)
+    }
+
+    /// Answers requests on a connection with their first parameter, dropping the connection
+    /// instead of answering the request number `drop_at`, if given.
+    fn serve((requests, responses): Server, drop_at: Option<usize>) {
+        let mut responses = responses.wait();
+        for (i, request) in requests.wait().enumerate() {
+            if Some(i) == drop_at {
+                return;
+            }
+            let request: JsonValue = serde_json::from_slice(&request.unwrap()).unwrap();
+            let response = json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "result": request["params"][0],
+            });
+            responses.send(serde_json::to_vec(&response).unwrap()).unwrap();
+        }
+    }
+
+    /// Answers the subscribe call on a connection with the subscription id `id`, then sends a
+    /// notification of it with `value`. Returns the server side of the connection.
+    fn serve_subscription((requests, responses): Server, id: &str, value: u64) -> Server {
+        let (request, requests) = requests.into_future().wait().ok().unwrap();
+        let request: JsonValue = serde_json::from_slice(&request.unwrap()).unwrap();
+        assert_eq!(json!("subscribe"), request["method"]);
+        let messages = vec![
+            json!({"jsonrpc": "2.0", "id": request["id"], "result": id}),
+            json!({"method": "n", "params": {"subscription": id, "result": value}}),
+        ];
+        let mut responses = responses;
+        for message in messages {
+            responses = responses.send(serde_json::to_vec(&message).unwrap()).wait().unwrap();
+        }
+        (requests, responses)
+    }
+
+    fn record_events<T>(transport: &ReconnectingTransport<T>) -> Arc<Mutex<Vec<ConnectionEvent>>>
+    where
+        T: Transport<Error = io::Error> + Clone + Send + 'static,
+    {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        transport.on_event(move |event| recorded.lock().unwrap().push(event.clone()));
+        events
+    }
+
+    jsonrpc_client!(pub struct EchoClient {
+        #[rpc(idempotent)]
+        pub fn echo(&self, value: u64) -> RpcRequest<u64>;
+        pub fn store(&self, value: u64) -> RpcRequest<u64>;
+    });
+
+    #[test]
+    fn replays_calls_after_reconnecting() {
+        let (connect, servers) = connector();
+        let policy = ReconnectPolicy::fixed(Duration::from_millis(10))
+            .replay(Replay::Idempotent(EchoClient::<DuplexTransport>::METHODS));
+        let (transport, reconnector) = ReconnectingTransport::new(connect, policy);
+        let events = record_events(&transport);
+        let client = EchoClient::new(transport.clone());
+        thread::spawn(move || reconnector.wait());
+        thread::spawn(move || {
+            serve(servers.recv().unwrap(), Some(0));
+            serve(servers.recv().unwrap(), Some(1));
+        });
+
+        assert_eq!(7, client.echo(7).call().unwrap());
+        assert!(transport.is_connected());
+        assert_eq!(
+            vec![
+                ConnectionEvent::Connected,
+                ConnectionEvent::Disconnected { error: None },
+                ConnectionEvent::Reconnecting { attempt: 1 },
+                ConnectionEvent::Connected,
+            ],
+            events.lock().unwrap()[..4].to_vec()
+        );
+        let error = client.store(8).call().unwrap_err();
+        assert_eq!(ErrorKind::TransportError, *error.kind());
+        assert_eq!(
+            io::ErrorKind::BrokenPipe,
+            error.transport_error::<io::Error>().unwrap().kind()
+        );
+    }
+
+    #[test]
+    fn resubscribes_after_reconnecting() {
+        let (connect, servers) = connector();
+        let policy = ReconnectPolicy::fixed(Duration::from_millis(10));
+        let (transport, reconnector) = ReconnectingTransport::new(connect, policy);
+        thread::spawn(move || reconnector.wait());
+        let (done_tx, done_rx) = std_mpsc::channel::<()>();
+        thread::spawn(move || {
+            drop(serve_subscription(servers.recv().unwrap(), "first", 1));
+            let _server = serve_subscription(servers.recv().unwrap(), "second", 2);
+            let _ = done_rx.recv();
+        });
+
+        let client = RpcClient::new(transport);
+        let subscription = client.subscribe::<_, u64>("subscribe", ()).wait().unwrap();
+        assert_eq!(json!("first"), *subscription.id());
+        let notifications = subscription.take(2).collect().wait().unwrap();
+        assert_eq!(vec![1, 2], notifications);
+        drop(done_tx);
+    }
+
+    #[test]
+    fn gives_up_connecting() {
+        let policy =
+            ReconnectPolicy::exponential(Duration::from_millis(1), Duration::from_millis(5))
+                .max_attempts(3);
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let connect = move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            future::err::<(DuplexTransport, TestConnection), _>(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "Refused",
+            ))
+        };
+        let (transport, reconnector) = ReconnectingTransport::new(connect, policy);
+        let events = record_events(&transport);
+        let client = EchoClient::new(transport.clone());
+        let call = client.echo(1);
+        assert_eq!(ConnectionState::Connecting, transport.state());
+
+        let error = reconnector.wait().unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionRefused, error.kind());
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+        assert_eq!(ConnectionState::Closed, transport.state());
+        assert_eq!(
+            vec![
+                ConnectionEvent::Reconnecting { attempt: 2 },
+                ConnectionEvent::Reconnecting { attempt: 3 },
+                ConnectionEvent::Closed,
+            ],
+            *events.lock().unwrap()
+        );
+        let error = call.call().unwrap_err();
+        assert_eq!(
+            io::ErrorKind::NotConnected,
+            error.transport_error::<io::Error>().unwrap().kind()
+        );
+    }
+
+    #[test]
+    fn gives_up_waiting_after_deadline() {
+        let policy = ReconnectPolicy::fixed(Duration::from_secs(10));
+        let connect = || {
+            future::err::<(DuplexTransport, TestConnection), _>(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "Refused",
+            ))
+        };
+        let (transport, reconnector) = ReconnectingTransport::new(connect, policy);
+        let config = ClientConfig::new().timeout(Duration::from_secs(5));
+        let client = EchoClient::with_config(transport.clone(), config);
+        let call = client.echo(1);
+        thread::spawn(move || reconnector.wait());
+
+        match call.call().unwrap_err().into_kind() {
+            ErrorKind::DeadlineExceeded(remaining) => assert!(remaining > Duration::from_secs(4)),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        // Calls made while waiting for the next attempt give up right away.
+        assert_eq!(ConnectionState::Waiting { failed_attempts: 1 }, transport.state());
+        match client.echo(2).call().unwrap_err().into_kind() {
+            ErrorKind::DeadlineExceeded(remaining) => assert!(remaining > Duration::from_secs(4)),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+    }
+}
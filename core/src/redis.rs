@@ -0,0 +1,488 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport sending calls over one channel and receiving notifications from Redis pub/sub.
+//!
+//! Some backends take calls over HTTP or a socket, but fan the notifications of subscriptions
+//! out over Redis channels. A [`RedisTransport`] pairs a transport for the calls with a
+//! connection to such a Redis server. It is created together with a [`RedisConnection`] future,
+//! which subscribes to the channels and patterns of the given [`RedisOptions`], and routes the
+//! messages published on them to the subscriptions made with [`pubsub::subscribe`]. The
+//! connection has to be spawned on an executor, or otherwise polled, for notifications to
+//! arrive.
+//!
+//! A message carrying a JSON-RPC notification with the subscription id in
+//! `params.subscription` is routed like on any other transport, with `params.result` as the
+//! notification. Any other JSON message is routed as a whole to the subscription whose id is the
+//! name of the channel it was published on, for backends returning the channel to listen on as
+//! the subscription id. As with the [`sse`] module, notifications arriving before the response
+//! to their subscribe call are kept for a while.
+//!
+//! When the connection to Redis closes or fails, all subscription streams end.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::redis::{RedisOptions, RedisTransport};
+//!
+//! let redis = core.run(TcpStream::connect(&redis_address, &core.handle()))?;
+//! let options = RedisOptions::new().pattern("ticker:*").password(&redis_password);
+//! let (transport, connection) = RedisTransport::new(http_handle, redis, options);
+//! core.handle().spawn(connection.map_err(|e| error!("Redis connection failed: {}", e)));
+//!
+//! let ticker = pubsub::subscribe::<_, _, Ticker>(&transport, &config, method, ("BTC-EUR",));
+//! ```
+//!
+//! [`RedisTransport`]: struct.RedisTransport.html
+//! [`RedisConnection`]: struct.RedisConnection.html
+//! [`RedisOptions`]: struct.RedisOptions.html
+//! [`pubsub::subscribe`]: ../pubsub/fn.subscribe.html
+//! [`sse`]: ../sse/index.html
+
+use Transport;
+use bytes::BytesMut;
+use futures::{Async, AsyncSink, Future, Poll, Sink, Stream};
+use pubsub::{NotificationSender, PubSubTransport};
+use serde_json::{self, Value as JsonValue};
+use sse::{self, Routes};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio_codec::{Decoder, Encoder, Framed};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The longest reply accepted from Redis, 16 MiB.
+const MAX_REPLY_LENGTH: usize = 16 * 1024 * 1024;
+
+
+/// The channels and patterns a `RedisTransport` receives notifications from, and the password
+/// of the Redis server.
+#[derive(Debug, Clone, Default)]
+pub struct RedisOptions {
+    channels: Vec<String>,
+    patterns: Vec<String>,
+    password: Option<String>,
+}
+
+impl RedisOptions {
+    /// Creates options subscribing to nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a channel to receive the messages of.
+    pub fn channel(mut self, channel: &str) -> Self {
+        self.channels.push(channel.to_owned());
+        self
+    }
+
+    /// Adds a glob-style pattern, like `ticker:*`, to receive the messages of all channels
+    /// matching it.
+    pub fn pattern(mut self, pattern: &str) -> Self {
+        self.patterns.push(pattern.to_owned());
+        self
+    }
+
+    /// Sets the password to authenticate to the Redis server with.
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_owned());
+        self
+    }
+
+    /// Returns the commands subscribing to the channels and patterns, after authenticating.
+    fn commands(self) -> VecDeque<Vec<Vec<u8>>> {
+        let mut commands = VecDeque::new();
+        if let Some(password) = self.password {
+            commands.push_back(vec![b"AUTH".to_vec(), password.into_bytes()]);
+        }
+        let command = |name: &[u8], arguments: Vec<String>| {
+            let mut command = vec![name.to_vec()];
+            command.extend(arguments.into_iter().map(String::into_bytes));
+            command
+        };
+        if !self.channels.is_empty() {
+            commands.push_back(command(b"SUBSCRIBE", self.channels));
+        }
+        if !self.patterns.is_empty() {
+            commands.push_back(command(b"PSUBSCRIBE", self.patterns));
+        }
+        commands
+    }
+}
+
+
+/// A transport sending calls over an inner transport, and receiving the notifications of
+/// subscriptions from Redis channels. Clones share the subscriptions.
+#[derive(Clone)]
+pub struct RedisTransport<T> {
+    transport: T,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl<T: Transport> RedisTransport<T> {
+    /// Creates a transport sending calls over `transport` and receiving notifications over
+    /// `io`, a connection to a Redis server, from the channels and patterns of `options`.
+    /// Returns the connection routing the notifications along with the transport.
+    pub fn new<S>(transport: T, io: S, options: RedisOptions) -> (Self, RedisConnection<S>)
+    where
+        S: AsyncRead + AsyncWrite,
+    {
+        let routes = Arc::new(Mutex::new(Routes::default()));
+        let connection = RedisConnection {
+            framed: RespCodec.framed(io),
+            commands: options.commands(),
+            routes: routes.clone(),
+        };
+        (RedisTransport { transport, routes }, connection)
+    }
+
+    /// Returns a reference to the transport the calls are sent over.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport> Transport for RedisTransport<T> {
+    type Future = Box<Future<Item = Vec<u8>, Error = T::Error> + Send>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        Box::new(self.transport.send(json_data))
+    }
+}
+
+impl<T: Transport> PubSubTransport for RedisTransport<T> {
+    fn subscribe(
+        &self,
+        json_data: Vec<u8>,
+        notifications: NotificationSender,
+    ) -> Self::Future {
+        sse::subscribe(&self.transport, &self.routes, json_data, notifications)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RedisTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let routes = self.routes.lock().unwrap();
+        f.debug_struct("RedisTransport")
+            .field("transport", &self.transport)
+            .field("subscriptions", &routes.subscriptions.len())
+            .field("closed", &routes.closed)
+            .finish()
+    }
+}
+
+
+/// The future driving the Redis connection of a `RedisTransport`, subscribing to the channels
+/// and routing the messages published on them to subscriptions. Completes when Redis closes the
+/// connection, and fails with the errors of the connection and the errors Redis replies with.
+/// All subscriptions end with it.
+#[must_use = "futures do nothing unless polled"]
+pub struct RedisConnection<S> {
+    framed: Framed<S, RespCodec>,
+    /// The commands not yet handed to the framed connection.
+    commands: VecDeque<Vec<Vec<u8>>>,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl<S: AsyncRead + AsyncWrite> RedisConnection<S> {
+    fn poll_connection(&mut self) -> Poll<(), io::Error> {
+        while let Some(command) = self.commands.pop_front() {
+            if let AsyncSink::NotReady(command) = self.framed.start_send(command)? {
+                self.commands.push_front(command);
+                break;
+            }
+        }
+        self.framed.poll_complete()?;
+        loop {
+            let reply = match try_ready!(self.framed.poll()) {
+                Some(Resp::Array(Some(reply))) => reply,
+                Some(Resp::Error(message)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("Redis replied with an error: {}", message),
+                    ))
+                }
+                Some(reply) => {
+                    trace!("Ignoring Redis reply {:?}", reply);
+                    continue;
+                }
+                None => return Ok(Async::Ready(())),
+            };
+            // Messages of patterns carry the pattern before the channel.
+            let (channel, message) = match (reply.first(), reply.len()) {
+                (Some(&Resp::Bulk(Some(ref kind))), 3) if kind == b"message" => {
+                    (&reply[1], &reply[2])
+                }
+                (Some(&Resp::Bulk(Some(ref kind))), 4) if kind == b"pmessage" => {
+                    (&reply[2], &reply[3])
+                }
+                _ => {
+                    trace!("Ignoring Redis reply {:?}", reply);
+                    continue;
+                }
+            };
+            match (channel, message) {
+                (&Resp::Bulk(Some(ref channel)), &Resp::Bulk(Some(ref message))) => {
+                    self.route(channel, message)
+                }
+                _ => debug!("Discarding malformed Redis message"),
+            }
+        }
+    }
+
+    /// Routes a message published on `channel`.
+    fn route(&self, channel: &[u8], message: &[u8]) {
+        let message = match serde_json::from_slice::<JsonValue>(message) {
+            Ok(message) => message,
+            Err(_) => {
+                debug!("Discarding Redis message that is not JSON");
+                return;
+            }
+        };
+        if let Some(message) = sse::route(&self.routes, message) {
+            let channel = JsonValue::String(String::from_utf8_lossy(channel).into_owned());
+            self.routes.lock().unwrap().notify(channel.to_string(), message);
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Future for RedisConnection<S> {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        let result = self.poll_connection();
+        match result {
+            Ok(Async::NotReady) => (),
+            _ => {
+                debug!("Redis connection closed, ending all subscriptions");
+                self.routes.lock().unwrap().close();
+            }
+        }
+        result
+    }
+}
+
+impl<S> fmt::Debug for RedisConnection<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RedisConnection")
+            .field("commands", &self.commands.len())
+            .finish()
+    }
+}
+
+
+/// A reply of Redis, in the RESP2 protocol.
+#[derive(Debug, Clone, PartialEq)]
+enum Resp {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Resp>>),
+}
+
+/// A codec decoding the replies of Redis and encoding commands as arrays of bulk strings.
+#[derive(Debug)]
+struct RespCodec;
+
+impl Decoder for RespCodec {
+    type Item = Resp;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Resp>> {
+        match parse_reply(buf)? {
+            Some((reply, length)) => {
+                buf.split_to(length);
+                Ok(Some(reply))
+            }
+            None if buf.len() > MAX_REPLY_LENGTH => {
+                Err(invalid("Redis reply exceeds the maximum length"))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder for RespCodec {
+    type Item = Vec<Vec<u8>>;
+    type Error = io::Error;
+
+    fn encode(&mut self, command: Vec<Vec<u8>>, buf: &mut BytesMut) -> io::Result<()> {
+        let mut encoded = format!("*{}\r\n", command.len()).into_bytes();
+        for argument in command {
+            encoded.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+            encoded.extend_from_slice(&argument);
+            encoded.extend_from_slice(b"\r\n");
+        }
+        buf.reserve(encoded.len());
+        buf.extend_from_slice(&encoded);
+        Ok(())
+    }
+}
+
+
+/// Parses the reply at the start of `buf`. Returns the reply and its length, or `None` if it is
+/// not complete.
+fn parse_reply(buf: &[u8]) -> io::Result<Option<(Resp, usize)>> {
+    let end = match buf.windows(2).position(|window| window == b"\r\n") {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    let line = String::from_utf8_lossy(&buf[1..end]).into_owned();
+    let rest = end + 2;
+    let length = || {
+        line.parse::<i64>()
+            .map_err(|_| invalid("Invalid length in Redis reply"))
+    };
+    let reply = match buf[0] {
+        b'+' => (Resp::Simple(line.clone()), rest),
+        b'-' => (Resp::Error(line.clone()), rest),
+        b':' => (Resp::Integer(length()?), rest),
+        b'$' => match length()? {
+            length if length < 0 => (Resp::Bulk(None), rest),
+            length => {
+                let length = length as usize;
+                if buf.len() < rest + length + 2 {
+                    return Ok(None);
+                }
+                (Resp::Bulk(Some(buf[rest..rest + length].to_vec())), rest + length + 2)
+            }
+        },
+        b'*' => match length()? {
+            count if count < 0 => (Resp::Array(None), rest),
+            count => {
+                let mut elements = Vec::new();
+                let mut offset = rest;
+                for _ in 0..count {
+                    match parse_reply(&buf[offset..])? {
+                        Some((element, length)) => {
+                            elements.push(element);
+                            offset += length;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                (Resp::Array(Some(elements)), offset)
+            }
+        },
+        _ => return Err(invalid("Invalid Redis reply type")),
+    };
+    Ok(Some(reply))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ClientConfig;
+    use pubsub;
+    use std::io::{Read, Write};
+    use test_util::SubscribingTransport;
+
+    #[test]
+    fn decode_replies() {
+        let mut buf = BytesMut::from(&b"*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n"[..]);
+        buf.extend_from_slice(b"*3\r\n$7\r\nm");
+        let subscribed = Resp::Array(Some(vec![
+            Resp::Bulk(Some(b"subscribe".to_vec())),
+            Resp::Bulk(Some(b"a".to_vec())),
+            Resp::Integer(1),
+        ]));
+        assert_eq!(Some(subscribed), RespCodec.decode(&mut buf).unwrap());
+        assert_eq!(None, RespCodec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(b"essage\r\n$1\r\na\r\n$4\r\n\r\n{}\r\n");
+        buf.extend_from_slice(b"-NOAUTH Authentication required.\r\n");
+        let message = Resp::Array(Some(vec![
+            Resp::Bulk(Some(b"message".to_vec())),
+            Resp::Bulk(Some(b"a".to_vec())),
+            Resp::Bulk(Some(b"\r\n{}".to_vec())),
+        ]));
+        assert_eq!(Some(message), RespCodec.decode(&mut buf).unwrap());
+        let error = Resp::Error("NOAUTH Authentication required.".to_owned());
+        assert_eq!(Some(error), RespCodec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+    }
+
+    /// A Redis connection reading the given bytes and then the end of the stream, discarding
+    /// everything written.
+    struct RedisSocket(io::Cursor<Vec<u8>>);
+
+    impl Read for RedisSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for RedisSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for RedisSocket {}
+
+    impl AsyncWrite for RedisSocket {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn message(channel: &str, message: &str) -> String {
+        format!(
+            "*3\r\n$7\r\nmessage\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
+            channel.len(),
+            channel,
+            message.len(),
+            message
+        )
+    }
+
+    #[test]
+    fn routes_messages() {
+        let notification =
+            r#"{"jsonrpc":"2.0","method":"s","params":{"subscription":"trades","result":2}}"#;
+        let replies = [
+            "*3\r\n$9\r\nsubscribe\r\n$6\r\ntrades\r\n:1\r\n".to_owned(),
+            message("trades", "1"),
+            message("other", "9"),
+            message("trades", notification),
+        ].concat();
+        let socket = RedisSocket(io::Cursor::new(replies.into_bytes()));
+        let options = RedisOptions::new().channel("trades").channel("other");
+        let (transport, connection) =
+            RedisTransport::new(SubscribingTransport("trades"), socket, options);
+        let config = ClientConfig::default();
+        let subscription =
+            pubsub::subscribe::<_, _, u64>(&transport, &config, "subscribe".to_owned(), ())
+                .wait()
+                .unwrap();
+        // Reads the messages until Redis closes the connection, which ends the subscription.
+        connection.wait().unwrap();
+        let notifications: Vec<u64> = subscription.wait().map(Result::unwrap).collect();
+        assert_eq!(vec![1, 2], notifications);
+    }
+}
@@ -0,0 +1,359 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use EmptyParams;
+use jsonrpc_core::types::{Id, Version};
+use serde::ser::{self, Impossible, Serialize, SerializeStruct, Serializer};
+use serde_json;
+use std::error;
+use std::fmt;
+
+/// A JSON-RPC request, or a notification if it has no id. Serialized straight to bytes, without
+/// building a `serde_json::Value` of it or its parameters first.
+pub struct Request<'a, P: 'a> {
+    pub version: Version,
+    pub method: &'a str,
+    pub params: &'a P,
+    pub id: Option<&'a Id>,
+    /// How to send params that are an empty array or object, or `null`. Sent as they are if
+    /// `None`.
+    pub empty_params: Option<EmptyParams>,
+}
+
+impl<'a, P: Serialize> Request<'a, P> {
+    /// Serializes the request into JSON, appended to `buffer`.
+    pub fn serialize_into(&self, buffer: &mut Vec<u8>) -> serde_json::Result<()> {
+        serde_json::to_writer(buffer, self)
+    }
+}
+
+impl<'a, P: Serialize> Serialize for Request<'a, P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let shape = Shape::of(self.params);
+        let omit_params = shape == Shape::Empty && self.empty_params == Some(EmptyParams::Omit);
+        let len = 2 + !omit_params as usize + self.id.is_some() as usize;
+        let mut request = serializer.serialize_struct("Request", len)?;
+        request.serialize_field("jsonrpc", &self.version)?;
+        request.serialize_field("method", self.method)?;
+        match (shape, self.empty_params) {
+            // Arrays, objects and `null` are used as they are, any other value is sent as the
+            // only positional parameter.
+            (Shape::Scalar, _) => request.serialize_field("params", &(self.params,))?,
+            (Shape::Empty, Some(EmptyParams::Array)) => {
+                request.serialize_field("params", &[(); 0])?
+            }
+            (Shape::Empty, Some(EmptyParams::Object)) => {
+                request.serialize_field("params", &serde_json::Map::new())?
+            }
+            (Shape::Empty, Some(EmptyParams::Omit)) => (),
+            _ => request.serialize_field("params", self.params)?,
+        }
+        if let Some(id) = self.id {
+            request.serialize_field("id", id)?;
+        }
+        request.end()
+    }
+}
+
+
+/// What JSON value method parameters serialize to, as far as sending them is concerned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    /// A string, number or boolean.
+    Scalar,
+    /// An array or object known to be empty, or `null`.
+    Empty,
+    /// Any other array or object.
+    Compound,
+}
+
+impl Shape {
+    fn of<P: Serialize>(params: &P) -> Shape {
+        match params.serialize(ShapeProbe) {
+            Ok(shape) | Err(ProbeDone(shape)) => shape,
+        }
+    }
+}
+
+/// A serializer finding the `Shape` of a value. Stops at the start of arrays and objects, so it
+/// never visits their contents.
+struct ShapeProbe;
+
+/// Returned as the error of `ShapeProbe` to stop serializing arrays and objects early.
+#[derive(Debug)]
+struct ProbeDone(Shape);
+
+impl ProbeDone {
+    fn with_len(len: usize) -> Self {
+        ProbeDone(if len == 0 {
+            Shape::Empty
+        } else {
+            Shape::Compound
+        })
+    }
+}
+
+impl fmt::Display for ProbeDone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Probed value shape: {:?}", self.0)
+    }
+}
+
+impl error::Error for ProbeDone {}
+
+impl ser::Error for ProbeDone {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        // Serializing the params for real will fail with the actual error.
+        ProbeDone(Shape::Compound)
+    }
+}
+
+impl Serializer for ShapeProbe {
+    type Ok = Shape;
+    type Error = ProbeDone;
+    type SerializeSeq = Impossible<Shape, ProbeDone>;
+    type SerializeTuple = Impossible<Shape, ProbeDone>;
+    type SerializeTupleStruct = Impossible<Shape, ProbeDone>;
+    type SerializeTupleVariant = Impossible<Shape, ProbeDone>;
+    type SerializeMap = Impossible<Shape, ProbeDone>;
+    type SerializeStruct = Impossible<Shape, ProbeDone>;
+    type SerializeStructVariant = Impossible<Shape, ProbeDone>;
+
+    fn serialize_bool(self, _v: bool) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_i128(self, _v: i128) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_u128(self, _v: u128) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Shape, ProbeDone> {
+        Err(ProbeDone::with_len(v.len()))
+    }
+
+    fn serialize_none(self) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Empty)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Shape, ProbeDone> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Empty)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Empty)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Scalar)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Shape, ProbeDone> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Shape, ProbeDone> {
+        Ok(Shape::Compound)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, ProbeDone> {
+        Err(ProbeDone::with_len(len.unwrap_or(1)))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, ProbeDone> {
+        Err(ProbeDone::with_len(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, ProbeDone> {
+        Err(ProbeDone::with_len(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, ProbeDone> {
+        Err(ProbeDone(Shape::Compound))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, ProbeDone> {
+        Err(ProbeDone::with_len(len.unwrap_or(1)))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, ProbeDone> {
+        Err(ProbeDone::with_len(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, ProbeDone> {
+        Err(ProbeDone(Shape::Compound))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn request_json<P: Serialize>(
+        params: &P,
+        id: Option<&Id>,
+        empty_params: Option<EmptyParams>,
+    ) -> String {
+        let request = Request {
+            version: Version::V2,
+            method: "ping",
+            params,
+            id,
+            empty_params,
+        };
+        let mut buffer = Vec::new();
+        request.serialize_into(&mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn params() {
+        let id = Id::Num(3);
+        let mut map = BTreeMap::new();
+        map.insert("a", 1);
+        let cases = [
+            (request_json(&(1, "x"), Some(&id), None), r#"[1,"x"]"#),
+            (request_json(&map, Some(&id), None), r#"{"a":1}"#),
+            (request_json(&(), Some(&id), None), r#"null"#),
+            (request_json(&Some(5), Some(&id), None), r#"[5]"#),
+            (request_json(&"x", Some(&id), None), r#"["x"]"#),
+        ];
+        for &(ref json, params) in &cases {
+            let expected = format!(
+                r#"{{"jsonrpc":"2.0","method":"ping","params":{},"id":3}}"#,
+                params
+            );
+            assert_eq!(expected, *json);
+        }
+    }
+
+    #[test]
+    fn notification() {
+        assert_eq!(
+            r#"{"jsonrpc":"2.0","method":"ping","params":[]}"#,
+            request_json(&[0u8; 0], None, None)
+        );
+    }
+
+    #[test]
+    fn empty_params() {
+        let empty_map = BTreeMap::<String, u64>::new();
+        let as_object = [
+            request_json(&[0u8; 0], None, Some(EmptyParams::Object)),
+            request_json(&empty_map, None, Some(EmptyParams::Object)),
+            request_json(&(), None, Some(EmptyParams::Object)),
+        ];
+        for json in &as_object {
+            assert_eq!(r#"{"jsonrpc":"2.0","method":"ping","params":{}}"#, *json);
+        }
+        assert_eq!(
+            r#"{"jsonrpc":"2.0","method":"ping","params":[]}"#,
+            request_json(&None::<u64>, None, Some(EmptyParams::Array))
+        );
+        assert_eq!(
+            r#"{"jsonrpc":"2.0","method":"ping","id":3}"#,
+            request_json(&[0u8; 0], Some(&Id::Num(3)), Some(EmptyParams::Omit))
+        );
+        assert_eq!(
+            r#"{"jsonrpc":"2.0","method":"ping","params":[0]}"#,
+            request_json(&[0u8], None, Some(EmptyParams::Omit))
+        );
+    }
+}
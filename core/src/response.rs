@@ -6,29 +6,398 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use {ErrorKind, Result, ResultExt};
-use jsonrpc_core::types::{Id, Output, Version};
+use {Error, ErrorKind, Result, Validation};
+use error::ResultExt;
+use jsonrpc_core::types::Id;
 use serde;
-use serde_json;
+use serde::de::{DeserializeSeed, Deserializer, Error as DeError, MapAccess, Visitor};
+use serde_json::{self, Map, Value as JsonValue};
+use serde_json::value::RawValue;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
 
-/// Parses a binary response into json, extracts the "result" field and tries to deserialize that
-/// to the desired type.
-pub fn parse<R>(response_raw: &[u8], expected_id: &Id) -> Result<R>
+/// The members of a response besides its result.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResponseEnvelope {
+    /// The `jsonrpc` member, if present.
+    pub jsonrpc: Option<JsonValue>,
+    /// The `id` member. `Null` if missing.
+    pub id: JsonValue,
+    /// All members not defined by the JSON-RPC 2.0 specification.
+    pub extra: Map<String, JsonValue>,
+}
+
+/// A response object where the `result` member is deserialized into `T`. Parsing a buffered
+/// response keeps the result as a slice of the raw response, so it can be deserialized straight
+/// into the desired type without building a `JsonValue` tree first. Parsing a response from a
+/// reader deserializes the result into the desired type right away.
+struct RawResponse<T> {
+    jsonrpc: Option<JsonValue>,
+    id: JsonValue,
+    result: Option<T>,
+    /// `Some(JsonValue::Null)` if the member is present but `null`.
+    error: Option<JsonValue>,
+    extra: Map<String, JsonValue>,
+}
+
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for RawResponse<T> {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(RawResponseVisitor(PhantomData))
+    }
+}
+
+/// Deserializes a response object, deserializing its result with the seed `S`.
+struct RawResponseVisitor<S>(S);
+
+impl<'de, S: DeserializeSeed<'de>> Visitor<'de> for RawResponseVisitor<S> {
+    type Value = RawResponse<S::Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON-RPC response object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> ::std::result::Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut response = RawResponse {
+            jsonrpc: None,
+            id: JsonValue::Null,
+            result: None,
+            error: None,
+            extra: Map::new(),
+        };
+        let mut result_seed = Some(self.0);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "jsonrpc" => response.jsonrpc = Some(map.next_value()?),
+                "id" => response.id = map.next_value()?,
+                "result" => {
+                    let result_seed = result_seed
+                        .take()
+                        .ok_or_else(|| A::Error::duplicate_field("result"))?;
+                    response.result = Some(map.next_value_seed(result_seed)?);
+                }
+                "error" => response.error = Some(map.next_value()?),
+                _ => {
+                    let value = map.next_value()?;
+                    response.extra.insert(key, value);
+                }
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Settings for parsing the response to a call.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    /// How strictly the response is checked against the specification.
+    pub validation: Validation,
+    /// Parse a response with neither `result` nor `error` as if the result was `null`.
+    pub missing_result_as_null: bool,
+    /// Return the envelope of the response along with the result.
+    pub keep_envelope: bool,
+}
+
+/// Parses a binary response and deserializes its "result" member directly from the response bytes
+/// into the desired type. Also returns the envelope of the response if `keep_envelope` is set.
+pub fn parse<R>(
+    response_raw: &[u8],
+    expected_id: &Id,
+    options: ParseOptions,
+) -> Result<(R, Option<ResponseEnvelope>)>
 where
     R: serde::de::DeserializeOwned,
 {
-    let response: Output = serde_json::from_slice(response_raw)
-        .chain_err(|| ErrorKind::ResponseError("Not valid json"))?;
-    ensure!(
-        response.id() == expected_id,
-        ErrorKind::ResponseError("Response id not equal to request id")
-    );
-    match response {
-        Output::Success(success) => {
-            trace!("Received json result: {}", success.result);
-            serde_json::from_value(success.result)
-                .chain_err(|| ErrorKind::ResponseError("Not valid for target type"))
-        }
-        Output::Failure(failure) => bail!(ErrorKind::JsonRpcError(failure.error)),
+    let response: RawResponse<&RawValue> =
+        serde_json::from_slice(response_raw).map_err(parse_error)?;
+    check(&response, expected_id, options)?;
+    let raw_result = match response.result {
+        Some(raw_result) => raw_result.get(),
+        None if options.missing_result_as_null => "null",
+        None => return Err(ErrorKind::ResponseError("Response has no result").into()),
+    };
+    trace!("Received json result: {}", raw_result);
+    let result = serde_json::from_str(raw_result)
+        .chain_err(|| ErrorKind::ResponseError("Not valid for target type"))?;
+    let envelope = if options.keep_envelope {
+        Some(ResponseEnvelope {
+            jsonrpc: response.jsonrpc,
+            id: response.id,
+            extra: response.extra,
+        })
+    } else {
+        None
+    };
+    Ok((result, envelope))
+}
+
+/// Parses a response read from `reader`, deserializing its "result" member into the desired type
+/// as it is read. The response is never buffered whole.
+pub fn parse_reader<R, Rd>(reader: Rd, expected_id: &Id, options: ParseOptions) -> Result<R>
+where
+    R: serde::de::DeserializeOwned,
+    Rd: io::Read,
+{
+    match parse_reader_seed(reader, PhantomData, expected_id, options)? {
+        Some(result) => Ok(result),
+        None => serde_json::from_str("null")
+            .chain_err(|| ErrorKind::ResponseError("Not valid for target type")),
+    }
+}
+
+/// Parses a response read from `reader` like `parse_reader`, deserializing its "result" member
+/// with `seed`. Returns `None` if the result is missing and `missing_result_as_null` is set.
+pub fn parse_reader_seed<S, V, Rd>(
+    reader: Rd,
+    seed: S,
+    expected_id: &Id,
+    options: ParseOptions,
+) -> Result<Option<V>>
+where
+    S: for<'de> DeserializeSeed<'de, Value = V>,
+    Rd: io::Read,
+{
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let response = deserializer
+        .deserialize_map(RawResponseVisitor(seed))
+        .and_then(|response| deserializer.end().map(|_| response))
+        .map_err(|e| {
+            if e.is_io() {
+                Error::with_source(ErrorKind::TransportError, e)
+            } else {
+                parse_error(e)
+            }
+        })?;
+    check(&response, expected_id, options)?;
+    match response.result {
+        Some(result) => Ok(Some(result)),
+        None if options.missing_result_as_null => Ok(None),
+        None => Err(ErrorKind::ResponseError("Response has no result").into()),
+    }
+}
+
+/// Returns the error of a response that could not be parsed.
+fn parse_error(error: serde_json::Error) -> Error {
+    let msg = if error.is_data() {
+        "Not a valid JSON-RPC response"
+    } else {
+        "Not valid json"
+    };
+    Error::with_source(ErrorKind::ResponseError(msg), error)
+}
+
+/// Checks everything about a response except its result: that it follows the specification as
+/// strictly as told by `options`, that it has the id of the request, and that it is not an error.
+fn check<T>(response: &RawResponse<T>, expected_id: &Id, options: ParseOptions) -> Result<()> {
+    if options.validation == Validation::Strict {
+        validate_strict(response, options.missing_result_as_null)?;
+    }
+    let id_matches = serde_json::to_value(expected_id)
+        .map(|expected_id| id_key(&response.id) == id_key(&expected_id))
+        .unwrap_or(false);
+    if !id_matches {
+        return Err(ErrorKind::ResponseError("Response id not equal to request id").into());
+    }
+    match response.error {
+        Some(JsonValue::Null) | None => Ok(()),
+        Some(ref error) => {
+            let error = serde_json::from_value(error.clone())
+                .chain_err(|| ErrorKind::ResponseError("Not a valid JSON-RPC error"))?;
+            Err(ErrorKind::JsonRpcError(error).into())
+        }
+    }
+}
+
+/// Checks that a response follows the JSON-RPC 2.0 specification. The more tolerant checks done
+/// for all responses are done after this. A response with neither `result` nor `error` is
+/// accepted if `missing_result_as_null` is set.
+fn validate_strict<T>(response: &RawResponse<T>, missing_result_as_null: bool) -> Result<()> {
+    let (has_result, has_error) = (response.result.is_some(), response.error.is_some());
+    let error = if response.jsonrpc != Some(JsonValue::from("2.0")) {
+        "Missing or wrong jsonrpc version"
+    } else if !response.extra.is_empty() {
+        "Unknown member in response"
+    } else if has_result && has_error || !has_result && !has_error && !missing_result_as_null {
+        "Response must have exactly one of result and error"
+    } else {
+        return Ok(());
+    };
+    Err(ErrorKind::ResponseError(error).into())
+}
+
+/// Returns the key a response with the id `id` is matched to its request by, the id as JSON text.
+/// Numeric ids and their decimal string representation have the same key, since some servers
+/// echo numeric ids back as strings.
+pub(crate) fn id_key(id: &JsonValue) -> String {
+    if let Some(string) = id.as_str() {
+        if let Ok(number) = string.parse::<u64>() {
+            if number.to_string() == string {
+                return number.to_string();
+            }
+        }
+    }
+    id.to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_bool(response: &[u8], id: u64, validation: Validation) -> Result<bool> {
+        let options = ParseOptions {
+            validation,
+            ..ParseOptions::default()
+        };
+        parse(response, &Id::Num(id), options).map(|(result, _)| result)
+    }
+
+    #[test]
+    fn numeric_id_as_string() {
+        let response = br#"{"jsonrpc": "2.0", "id": "5", "result": true}"#;
+        assert!(parse_bool(response, 5, Validation::Strict).unwrap());
+    }
+
+    #[test]
+    fn wrong_id() {
+        for id in &[r#"6"#, r#""6""#, r#""5 ""#, r#""five""#, r#"null"#] {
+            let response = format!(r#"{{"jsonrpc": "2.0", "id": {}, "result": true}}"#, id);
+            match *parse_bool(response.as_bytes(), 5, Validation::Strict).unwrap_err().kind() {
+                ErrorKind::ResponseError(_) => (),
+                ref kind => panic!("Wrong error kind for id {}: {:?}", id, kind),
+            }
+        }
+    }
+
+    const NOT_QUITE_COMPLIANT: &[&str] = &[
+        r#"{"id": 1, "result": true}"#,
+        r#"{"jsonrpc": "2.0", "id": 1, "result": true, "server_time": 1234}"#,
+        r#"{"jsonrpc": "2.0", "id": 1, "result": true, "error": null}"#,
+    ];
+
+    #[test]
+    fn strict_rejects_not_quite_compliant() {
+        for response in NOT_QUITE_COMPLIANT {
+            let result = parse_bool(response.as_bytes(), 1, Validation::Strict);
+            match *result.unwrap_err().kind() {
+                ErrorKind::ResponseError(_) => (),
+                ref kind => panic!("Wrong error kind for {}: {:?}", response, kind),
+            }
+        }
+    }
+
+    #[test]
+    fn lenient_accepts_not_quite_compliant() {
+        for response in NOT_QUITE_COMPLIANT {
+            let result = parse_bool(response.as_bytes(), 1, Validation::Lenient);
+            assert!(result.unwrap(), "Not accepted: {}", response);
+        }
+    }
+
+    #[test]
+    fn default_accepts_not_quite_compliant() {
+        for response in NOT_QUITE_COMPLIANT {
+            let result = parse_bool(response.as_bytes(), 1, Validation::default());
+            assert!(result.unwrap(), "Not accepted: {}", response);
+        }
+    }
+
+    #[test]
+    fn lenient_error_with_null_result() {
+        let response = br#"{"id": 1, "result": null, "error": {"code": -32601, "message": "No"}}"#;
+        match *parse_bool(response, 1, Validation::Lenient).unwrap_err().kind() {
+            ErrorKind::JsonRpcError(ref error) => assert_eq!("No", error.message),
+            ref kind => panic!("Wrong error kind: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn result_from_raw_response() {
+        let response = br#"{"jsonrpc": "2.0", "result": {"a\"b": [1, {"c": "\u00e9"}]}, "id": 1}"#;
+        let options = ParseOptions::default();
+        let (result, _) = parse::<JsonValue>(response, &Id::Num(1), options).unwrap();
+        assert_eq!(json!({"a\"b": [1, {"c": "\u{e9}"}]}), result);
+    }
+
+    #[test]
+    fn not_a_response_object() {
+        for response in &[r#"[true]"#, r#"{"jsonrpc": "2.0", "id": 1, "result": tru}"#] {
+            match *parse_bool(response.as_bytes(), 1, Validation::Lenient).unwrap_err().kind() {
+                ErrorKind::ResponseError(_) => (),
+                ref kind => panic!("Wrong error kind for {}: {:?}", response, kind),
+            }
+        }
+    }
+
+    #[test]
+    fn null_result() {
+        let response = br#"{"jsonrpc": "2.0", "id": 1, "result": null}"#;
+        let options = ParseOptions::default();
+        let (result, _) = parse::<Option<u64>>(response, &Id::Num(1), options).unwrap();
+        assert_eq!(None, result);
+        parse::<()>(response, &Id::Num(1), options).unwrap();
+    }
+
+    #[test]
+    fn missing_result() {
+        for &validation in &[Validation::Strict, Validation::Lenient] {
+            let response = br#"{"jsonrpc": "2.0", "id": 1}"#;
+            let mut options = ParseOptions {
+                validation,
+                ..ParseOptions::default()
+            };
+            match *parse::<()>(response, &Id::Num(1), options).unwrap_err().kind() {
+                ErrorKind::ResponseError(_) => (),
+                ref kind => panic!("Wrong error kind: {:?}", kind),
+            }
+
+            options.missing_result_as_null = true;
+            parse::<()>(response, &Id::Num(1), options).unwrap();
+            let (result, _) = parse::<Option<u64>>(response, &Id::Num(1), options).unwrap();
+            assert_eq!(None, result);
+        }
+    }
+
+    #[test]
+    fn parse_from_reader() {
+        let response = br#"{"jsonrpc": "2.0", "id": 1, "result": [1, 2, 3]}"#;
+        let options = ParseOptions::default();
+        let result: Vec<u64> = parse_reader(&response[..], &Id::Num(1), options).unwrap();
+        assert_eq!(vec![1, 2, 3], result);
+
+        let response = br#"{"jsonrpc": "2.0", "id": 1, "error": {"code": -1, "message": "No"}}"#;
+        match *parse_reader::<(), _>(&response[..], &Id::Num(1), options).unwrap_err().kind() {
+            ErrorKind::JsonRpcError(ref error) => assert_eq!("No", error.message),
+            ref kind => panic!("Wrong error kind: {:?}", kind),
+        }
+        let response = br#"{"jsonrpc": "2.0", "id": 1, "result": [1, 2"#;
+        let result = parse_reader::<Vec<u64>, _>(&response[..], &Id::Num(1), options);
+        match *result.unwrap_err().kind() {
+            ErrorKind::ResponseError(_) => (),
+            ref kind => panic!("Wrong error kind: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn envelope() {
+        let response = br#"{"jsonrpc": "2.0", "id": 1, "result": true, "server_time": 1234}"#;
+        let options = ParseOptions {
+            validation: Validation::Lenient,
+            keep_envelope: true,
+            ..ParseOptions::default()
+        };
+        let (result, envelope) = parse::<bool>(response, &Id::Num(1), options).unwrap();
+        assert!(result);
+        let envelope = envelope.unwrap();
+        assert_eq!(Some(json!("2.0")), envelope.jsonrpc);
+        assert_eq!(json!(1), envelope.id);
+        assert_eq!(Some(&json!(1234)), envelope.extra.get("server_time"));
+        assert_eq!(1, envelope.extra.len());
     }
 }
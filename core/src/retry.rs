@@ -0,0 +1,473 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper retrying failed requests.
+//!
+//! [`RetryTransport`] wraps any transport and resends a request when the inner transport fails
+//! to deliver it. How many times, how long to wait in between and which errors to retry on is
+//! decided by a [`RetryPolicy`].
+//!
+//! Only errors from the transport itself are retried. A JSON-RPC error response from the server
+//! is a successful transport operation and is returned to the caller right away.
+//!
+//! A failed request might still have reached the server, so a retry can execute a call twice.
+//! Policies restricted with [`idempotent_only`] only retry calls to methods marked
+//! `#[rpc(idempotent)]`, and send all other calls at most once.
+//!
+//! Errors telling how long to wait, like a server throttling requests with a `Retry-After`
+//! header, can override the backoff through [`retry_after`].
+//!
+//! Calls with a [deadline] are not retried when the backoff would end after the deadline. The
+//! call then fails with `ErrorKind::DeadlineExceeded`, holding the time that was left. Retries
+//! carry the [metadata] of the call like the first attempt, and the response metadata of a call
+//! is that of its last attempt.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::retry::{RetryPolicy, RetryTransport};
+//! use jsonrpc_client_http::{ErrorKind, HttpTransport};
+//! use std::time::Duration;
+//!
+//! let handle = HttpTransport::new().standalone()?.handle("http://localhost:8080")?;
+//! let policy = RetryPolicy::exponential(Duration::from_millis(100), Duration::from_secs(5))
+//!     .max_attempts(5)
+//!     .jitter(0.5)
+//!     .idempotent_only(FizzBuzzClient::<HttpHandle>::METHODS)
+//!     .retry_if(|e: &jsonrpc_client_http::Error| match *e.kind() {
+//!         ErrorKind::HttpError(_) => false,
+//!         _ => true,
+//!     });
+//! let mut client = FizzBuzzClient::new(RetryTransport::new(handle, policy));
+//! ```
+//!
+//! [`RetryTransport`]: struct.RetryTransport.html
+//! [`RetryPolicy`]: struct.RetryPolicy.html
+//! [`idempotent_only`]: struct.RetryPolicy.html#method.idempotent_only
+//! [`retry_after`]: struct.RetryPolicy.html#method.retry_after
+//! [deadline]: ../deadline/index.html
+//! [metadata]: ../metadata/index.html
+
+use {MethodInfo, Transport};
+use deadline::Deadline;
+use layer::Layer;
+use futures::{Async, Future, Poll};
+use metadata::Scope;
+use rand;
+use std::cmp;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use timer;
+use tokio_timer::Sleep;
+
+
+/// How long to wait between attempts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+    /// Wait the same amount of time before every retry.
+    Fixed(Duration),
+    /// Start with `initial` and double the delay for every retry, never waiting more than `max`.
+    Exponential {
+        /// The delay before the first retry.
+        initial: Duration,
+        /// The upper bound of the delay.
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Returns the delay before retry number `retry`, counted from zero.
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        match *self {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => {
+                let factor = 1u32.checked_shl(retry).unwrap_or(u32::max_value());
+                initial
+                    .checked_mul(factor)
+                    .map(|delay| cmp::min(delay, max))
+                    .unwrap_or(max)
+            }
+        }
+    }
+}
+
+
+/// Decides if, and when, a failed request should be sent again.
+pub struct RetryPolicy<E> {
+    backoff: Backoff,
+    max_attempts: u32,
+    jitter: f64,
+    predicate: Arc<Fn(&E) -> bool + Send + Sync>,
+    retry_after: Option<Arc<Fn(&E) -> Option<Duration> + Send + Sync>>,
+    methods: Option<&'static [MethodInfo]>,
+}
+
+impl<E> RetryPolicy<E> {
+    /// Creates a policy waiting `delay` between each attempt.
+    ///
+    /// Defaults to at most 3 attempts in total, no jitter and retrying on all errors.
+    pub fn fixed(delay: Duration) -> Self {
+        Self::new(Backoff::Fixed(delay))
+    }
+
+    /// Creates a policy with exponential backoff, starting at `initial` and doubling the delay
+    /// for every retry up to `max`.
+    ///
+    /// Defaults to at most 3 attempts in total, no jitter and retrying on all errors.
+    pub fn exponential(initial: Duration, max: Duration) -> Self {
+        Self::new(Backoff::Exponential { initial, max })
+    }
+
+    fn new(backoff: Backoff) -> Self {
+        RetryPolicy {
+            backoff,
+            max_attempts: 3,
+            jitter: 0.0,
+            predicate: Arc::new(|_: &E| true),
+            retry_after: None,
+            methods: None,
+        }
+    }
+
+    /// Sets the maximum number of attempts, including the first one. A value of 1 disables
+    /// retries.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = cmp::max(max_attempts, 1);
+        self
+    }
+
+    /// Randomizes the delays. Each delay is multiplied with a random factor between
+    /// `1 - jitter` and `1`. The jitter is clamped to the range `[0, 1]`.
+    ///
+    /// Spreads out the retries of many clients that failed at the same time.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.max(0.0).min(1.0);
+        self
+    }
+
+    /// Only retry requests that failed with an error for which `predicate` returns true.
+    pub fn retry_if<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&E) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Arc::new(predicate);
+        self
+    }
+
+    /// Wait as long as `retry_after` returns before retrying requests that failed with an error
+    /// telling how long to wait, like a server throttling requests. The backoff and jitter only
+    /// apply to errors for which `retry_after` returns `None`.
+    ///
+    /// ```rust,ignore
+    /// let policy = RetryPolicy::exponential(Duration::from_millis(100), Duration::from_secs(5))
+    ///     .retry_after(jsonrpc_client_http::Error::retry_after);
+    /// ```
+    pub fn retry_after<F>(mut self, retry_after: F) -> Self
+    where
+        F: Fn(&E) -> Option<Duration> + Send + Sync + 'static,
+    {
+        self.retry_after = Some(Arc::new(retry_after));
+        self
+    }
+
+    /// Only retry calls to the methods among `methods` that are marked idempotent, usually the
+    /// `METHODS` of a generated client. Calls to other methods, including methods called by
+    /// name, are never sent more than once. A batch is only retried if all of its calls are
+    /// idempotent.
+    pub fn idempotent_only(mut self, methods: &'static [MethodInfo]) -> Self {
+        self.methods = Some(methods);
+        self
+    }
+
+    /// Returns true if the request in `json_data` may be sent again after a failure.
+    fn may_resend(&self, json_data: &[u8]) -> bool {
+        match self.methods {
+            Some(methods) => MethodInfo::is_idempotent_request(methods, json_data),
+            None => true,
+        }
+    }
+
+    /// Returns the delay before the given retry if the given error should be retried.
+    /// `retry` is zero for the first retry, i.e. the second attempt.
+    fn retry_delay(&self, retry: u32, error: &E) -> Option<Duration> {
+        if retry + 1 >= self.max_attempts || !(self.predicate)(error) {
+            return None;
+        }
+        let requested = self.retry_after
+            .as_ref()
+            .and_then(|retry_after| retry_after(error));
+        if let Some(delay) = requested {
+            return Some(delay);
+        }
+        let delay = self.backoff.delay(retry);
+        if self.jitter == 0.0 {
+            Some(delay)
+        } else {
+            let factor = 1.0 - self.jitter * rand::random::<f64>();
+            Some(scale(delay, factor))
+        }
+    }
+}
+
+impl<E> Clone for RetryPolicy<E> {
+    fn clone(&self) -> Self {
+        RetryPolicy {
+            backoff: self.backoff,
+            max_attempts: self.max_attempts,
+            jitter: self.jitter,
+            predicate: self.predicate.clone(),
+            retry_after: self.retry_after.clone(),
+            methods: self.methods,
+        }
+    }
+}
+
+impl<E> fmt::Debug for RetryPolicy<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("backoff", &self.backoff)
+            .field("max_attempts", &self.max_attempts)
+            .field("jitter", &self.jitter)
+            .field("retry_after", &self.retry_after.is_some())
+            .field("methods", &self.methods)
+            .finish()
+    }
+}
+
+/// Multiplies a duration with a factor.
+fn scale(duration: Duration, factor: f64) -> Duration {
+    let nanos = (duration.as_secs() as f64 * 1e9 + duration.subsec_nanos() as f64) * factor;
+    Duration::new((nanos / 1e9) as u64, (nanos % 1e9) as u32)
+}
+
+
+/// A transport retrying failed requests on an inner transport according to a `RetryPolicy`.
+#[derive(Debug, Clone)]
+pub struct RetryTransport<T: Transport> {
+    transport: T,
+    policy: RetryPolicy<T::Error>,
+}
+
+impl<T: Transport + Clone + Send + 'static> RetryTransport<T> {
+    /// Wraps `transport` so failed requests are retried according to `policy`.
+    pub fn new(transport: T, policy: RetryPolicy<T::Error>) -> Self {
+        RetryTransport { transport, policy }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport + Clone + Send + 'static> Transport for RetryTransport<T> {
+    type Future = RetryFuture<T>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let future = self.transport.send(json_data.clone());
+        RetryFuture {
+            transport: self.transport.clone(),
+            policy: self.policy.clone(),
+            may_resend: self.policy.may_resend(&json_data),
+            deadline: Deadline::current(),
+            scope: Scope::current(),
+            json_data,
+            retry: 0,
+            state: RetryState::Sending(future),
+        }
+    }
+}
+
+impl<T: Transport + Clone + Send + 'static> Layer<T> for RetryPolicy<T::Error> {
+    type Transport = RetryTransport<T>;
+
+    fn layer(&self, transport: T) -> RetryTransport<T> {
+        RetryTransport::new(transport, self.clone())
+    }
+}
+
+
+/// The future returned by `RetryTransport` send operations.
+pub struct RetryFuture<T: Transport> {
+    transport: T,
+    policy: RetryPolicy<T::Error>,
+    may_resend: bool,
+    deadline: Option<Deadline>,
+    scope: Scope,
+    json_data: Vec<u8>,
+    retry: u32,
+    state: RetryState<T::Future, T::Error>,
+}
+
+enum RetryState<F, E> {
+    Sending(F),
+    Waiting(Sleep, Option<E>),
+}
+
+impl<T: Transport> Future for RetryFuture<T> {
+    type Item = Vec<u8>;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                RetryState::Sending(ref mut future) => match future.poll() {
+                    Err(error) => match self.policy.retry_delay(self.retry, &error) {
+                        Some(delay) if self.may_resend => {
+                            if let Some(ref deadline) = self.deadline {
+                                if delay >= deadline.remaining() {
+                                    debug!("Request failed ({}), no time left to retry", error);
+                                    deadline.give_up();
+                                    return Err(error);
+                                }
+                            }
+                            debug!("Request failed ({}), retrying in {:?}", error, delay);
+                            RetryState::Waiting(timer::sleep(delay), Some(error))
+                        }
+                        _ => return Err(error),
+                    },
+                    result => return result,
+                },
+                RetryState::Waiting(ref mut sleep, ref mut error) => match sleep.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => {
+                        self.retry += 1;
+                        let (transport, json_data) = (&self.transport, &self.json_data);
+                        let future = self.scope.enter(|| transport.send(json_data.clone()));
+                        RetryState::Sending(future)
+                    }
+                    Err(timer_error) => {
+                        warn!("Unable to wait before retrying request: {}", timer_error);
+                        return Err(error.take().expect("Retry future polled after completion"));
+                    }
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {ClientConfig, ErrorKind};
+    use std::io;
+    use std::sync::atomic::Ordering;
+    use test_util::{FlakyTransport, OkTransport};
+
+    jsonrpc_client!(pub struct RetryClient {
+        pub fn ping(&mut self) -> RpcRequest<bool>;
+        #[rpc(idempotent)]
+        pub fn get_balance(&mut self) -> RpcRequest<bool>;
+    });
+
+    #[test]
+    fn retries_until_success() {
+        let transport = FlakyTransport::new(2, OkTransport);
+        let policy = RetryPolicy::fixed(Duration::from_millis(1));
+        let client = RetryClient::new(RetryTransport::new(transport.clone(), policy));
+
+        assert!(client.ping().call().unwrap());
+        assert_eq!(3, transport.attempts.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let transport = FlakyTransport::new(10, OkTransport);
+        let policy = RetryPolicy::exponential(Duration::from_millis(1), Duration::from_millis(5))
+            .max_attempts(4)
+            .jitter(1.0);
+        let client = RetryClient::new(RetryTransport::new(transport.clone(), policy));
+
+        assert!(client.ping().call().is_err());
+        assert_eq!(4, transport.attempts.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn predicate_stops_retries() {
+        let transport = FlakyTransport::new(1, OkTransport);
+        let policy = RetryPolicy::fixed(Duration::from_millis(1))
+            .retry_if(|e: &io::Error| e.kind() != io::ErrorKind::ConnectionRefused);
+        let client = RetryClient::new(RetryTransport::new(transport.clone(), policy));
+
+        assert!(client.ping().call().is_err());
+        assert_eq!(1, transport.attempts.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn only_idempotent_calls_are_retried() {
+        let transport = FlakyTransport::new(1, OkTransport);
+        let policy = RetryPolicy::fixed(Duration::from_millis(1))
+            .idempotent_only(RetryClient::<FlakyTransport<OkTransport>>::METHODS);
+        let client = RetryClient::new(RetryTransport::new(transport.clone(), policy));
+
+        assert!(client.ping().call().is_err());
+        assert_eq!(1, transport.attempts.load(Ordering::SeqCst));
+
+        let transport = FlakyTransport::new(1, OkTransport);
+        let policy = RetryPolicy::fixed(Duration::from_millis(1))
+            .idempotent_only(RetryClient::<FlakyTransport<OkTransport>>::METHODS);
+        let client = RetryClient::new(RetryTransport::new(transport.clone(), policy));
+
+        assert!(client.get_balance().call().unwrap());
+        assert_eq!(2, transport.attempts.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn no_retry_after_deadline() {
+        let transport = FlakyTransport::new(1, OkTransport);
+        let policy = RetryPolicy::fixed(Duration::from_secs(10));
+        let config = ClientConfig::new().timeout(Duration::from_secs(5));
+        let client =
+            RetryClient::with_config(RetryTransport::new(transport.clone(), policy), config);
+
+        match client.ping().call().unwrap_err().into_kind() {
+            ErrorKind::DeadlineExceeded(remaining) => assert!(remaining > Duration::from_secs(4)),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+        assert_eq!(1, transport.attempts.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn waits_as_requested() {
+        let policy = RetryPolicy::fixed(Duration::from_millis(100))
+            .jitter(1.0)
+            .retry_after(|e: &io::Error| match e.kind() {
+                io::ErrorKind::WouldBlock => Some(Duration::from_secs(3)),
+                _ => None,
+            });
+        let throttled = io::Error::new(io::ErrorKind::WouldBlock, "Too many requests");
+        assert_eq!(Some(Duration::from_secs(3)), policy.retry_delay(0, &throttled));
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "Flaky");
+        assert!(policy.retry_delay(0, &refused).unwrap() <= Duration::from_millis(100));
+        assert_eq!(None, policy.retry_delay(2, &throttled));
+    }
+
+    #[test]
+    fn exponential_backoff_is_capped() {
+        let backoff = Backoff::Exponential {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+        };
+        assert_eq!(Duration::from_millis(100), backoff.delay(0));
+        assert_eq!(Duration::from_millis(400), backoff.delay(2));
+        assert_eq!(Duration::from_secs(1), backoff.delay(4));
+        assert_eq!(Duration::from_secs(1), backoff.delay(100));
+    }
+}
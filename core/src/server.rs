@@ -0,0 +1,162 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! In-process servers for testing generated clients.
+//!
+//! A client generated by `jsonrpc_client` can also generate a matching server trait, by giving a
+//! trait name after the client. The server trait has the same methods as the client, taking
+//! `&self` and returning the result directly. Its `into_io_handler` method returns a
+//! `jsonrpc_core::IoHandler` calling the methods of the implementing type, which can be put
+//! behind a [`LocalTransport`] to serve the client without any network.
+//!
+//! Argument and return types have to implement `Deserialize` and `Serialize` respectively, in
+//! addition to what the client requires. Server methods are registered under the same names as
+//! the client sends, including any prefix, and accept arguments by position or by name.
+//!
+//! # Example
+//!
+//! ```rust
+//! #[macro_use]
+//! extern crate jsonrpc_client_core;
+//!
+//! use jsonrpc_client_core::jsonrpc_core::Error;
+//! use jsonrpc_client_core::server::LocalTransport;
+//!
+//! jsonrpc_client!(
+//!     pub struct FizzBuzzClient {
+//!         pub fn fizz_buzz(&mut self, number: u64) -> RpcRequest<String>;
+//!     }
+//!
+//!     pub trait FizzBuzzServer;
+//! );
+//!
+//! struct MockFizzBuzz;
+//!
+//! impl FizzBuzzServer for MockFizzBuzz {
+//!     fn fizz_buzz(&self, number: u64) -> Result<String, Error> {
+//!         Ok(number.to_string())
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let transport = LocalTransport::new(MockFizzBuzz.into_io_handler());
+//!     let mut client = FizzBuzzClient::new(transport);
+//!     assert_eq!("4", client.fizz_buzz(4).call().unwrap());
+//! }
+//! ```
+//!
+//! [`LocalTransport`]: struct.LocalTransport.html
+
+use Transport;
+use futures::Future;
+use jsonrpc_core::{Error, IoHandler, Params};
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value as JsonValue};
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// A transport answering requests in-process with a `jsonrpc_core::IoHandler`, without any
+/// serialization to a network. Notifications are answered with an empty response.
+///
+/// Clones share the same handler and id counter.
+#[derive(Debug, Clone)]
+pub struct LocalTransport {
+    handler: Arc<IoHandler>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl LocalTransport {
+    /// Creates a transport sending all requests to the given handler.
+    pub fn new(handler: IoHandler) -> Self {
+        LocalTransport {
+            handler: Arc::new(handler),
+            next_id: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+}
+
+impl Transport for LocalTransport {
+    type Future = Box<Future<Item = Vec<u8>, Error = io::Error> + Send>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let request = match String::from_utf8(json_data) {
+            Ok(request) => request,
+            Err(e) => {
+                return Box::new(::futures::future::err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    e,
+                )))
+            }
+        };
+        let response = self.handler
+            .handle_request(&request)
+            .map(|response| response.map(String::into_bytes).unwrap_or_default())
+            .map_err(|()| io::Error::new(io::ErrorKind::Other, "IoHandler failed"));
+        Box::new(response)
+    }
+}
+
+
+/// Returns the arguments of a call as an array, in the order of `names`. Arguments given by
+/// name are looked up in the map, with missing arguments set to `null`.
+#[doc(hidden)]
+pub fn positional_params(params: Params, names: &[&str]) -> JsonValue {
+    match params {
+        Params::Array(values) => JsonValue::Array(values),
+        Params::Map(mut map) => JsonValue::Array(
+            names
+                .iter()
+                .map(|name| map.remove(*name).unwrap_or(JsonValue::Null))
+                .collect(),
+        ),
+        Params::None => JsonValue::Array(Vec::new()),
+    }
+}
+
+/// Deserializes the arguments of a call from an array, borrowing strings from it.
+#[doc(hidden)]
+pub fn parse_params<'a, D: Deserialize<'a>>(values: &'a JsonValue) -> Result<D, Error> {
+    D::deserialize(values).map_err(|e| Error::invalid_params(e.to_string()))
+}
+
+/// Serializes the value returned by a server method as the result of the call.
+#[doc(hidden)]
+pub fn serialize_result<R: Serialize>(result: R) -> Result<JsonValue, Error> {
+    serde_json::to_value(result).map_err(|_| Error::internal_error())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_params_in_order() {
+        let mut map = serde_json::Map::new();
+        map.insert("b".to_owned(), json!(2));
+        map.insert("a".to_owned(), json!(1));
+        let values = positional_params(Params::Map(map), &["a", "b", "c"]);
+        assert_eq!(json!([1, 2, null]), values);
+        let (a, b, c): (u64, u64, Option<u64>) = parse_params(&values).unwrap();
+        assert_eq!((1, 2, None), (a, b, c));
+    }
+
+    #[test]
+    fn invalid_params() {
+        let values = json!(["x"]);
+        let error = parse_params::<(u64,)>(&values).unwrap_err();
+        assert_eq!(::jsonrpc_core::ErrorCode::InvalidParams, error.code);
+    }
+}
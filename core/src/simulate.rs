@@ -0,0 +1,330 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper simulating a slow network, for testing timeouts and retries.
+//!
+//! [`SimulatedTransport`] delays every request before passing it to the inner transport, and
+//! every response before returning it, as configured by [`NetworkConditions`]:
+//!
+//! * The latency is added once in each direction, so it adds twice its length to every call.
+//! * The jitter randomly lengthens or shortens the latency of each direction by up to its length.
+//! * The bandwidth limits how fast requests and responses are transferred. Like on a real link,
+//!   the bandwidth is shared by all calls, so concurrent calls queue up behind each other.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::simulate::{NetworkConditions, SimulatedTransport};
+//!
+//! // A congested mobile connection.
+//! let conditions = NetworkConditions::new()
+//!     .latency(Duration::from_millis(150))
+//!     .jitter(Duration::from_millis(50))
+//!     .bandwidth(32 * 1024);
+//! let handle = HttpTransport::new().standalone()?.handle("http://localhost:8545")?;
+//! let mut client = FizzBuzzClient::new(SimulatedTransport::new(handle, conditions));
+//! ```
+//!
+//! [`SimulatedTransport`]: struct.SimulatedTransport.html
+//! [`NetworkConditions`]: struct.NetworkConditions.html
+
+use Transport;
+use futures::{Async, Future, Poll};
+use layer::Layer;
+use rand;
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use timer;
+use tokio_timer::Sleep;
+
+
+/// The latency, jitter and bandwidth a `SimulatedTransport` simulates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkConditions {
+    latency: Duration,
+    jitter: Duration,
+    upload_bandwidth: Option<u64>,
+    download_bandwidth: Option<u64>,
+}
+
+impl NetworkConditions {
+    /// Creates conditions without latency, jitter or bandwidth limits, adding no delays.
+    pub fn new() -> Self {
+        NetworkConditions {
+            latency: Duration::from_secs(0),
+            jitter: Duration::from_secs(0),
+            upload_bandwidth: None,
+            download_bandwidth: None,
+        }
+    }
+
+    /// Sets the one-way latency, added both before sending a request and before returning its
+    /// response.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Sets the jitter. The latency of each direction of each call is changed by a random
+    /// amount between minus and plus the jitter, but never below zero.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Limits the transfer of both requests and responses to `bytes_per_second`.
+    pub fn bandwidth(self, bytes_per_second: u64) -> Self {
+        self.upload_bandwidth(bytes_per_second)
+            .download_bandwidth(bytes_per_second)
+    }
+
+    /// Limits the transfer of requests to `bytes_per_second`. Zero is treated as one.
+    pub fn upload_bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.upload_bandwidth = Some(cmp::max(bytes_per_second, 1));
+        self
+    }
+
+    /// Limits the transfer of responses to `bytes_per_second`. Zero is treated as one.
+    pub fn download_bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.download_bandwidth = Some(cmp::max(bytes_per_second, 1));
+        self
+    }
+
+    /// Returns the latency of one direction of a call, with the jitter applied.
+    fn one_way_latency(&self) -> Duration {
+        let latency = nanos(self.latency) as f64;
+        let jitter = nanos(self.jitter) as f64 * (2.0 * rand::random::<f64>() - 1.0);
+        from_nanos((latency + jitter).max(0.0) as u64)
+    }
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        NetworkConditions::new()
+    }
+}
+
+
+/// One direction of the simulated network, transferring one message at a time.
+#[derive(Debug)]
+struct Link {
+    bytes_per_second: Option<u64>,
+    busy_until: Instant,
+}
+
+impl Link {
+    fn new(bytes_per_second: Option<u64>) -> Self {
+        Link {
+            bytes_per_second,
+            busy_until: Instant::now(),
+        }
+    }
+
+    /// Queues a message of `length` bytes on the link and returns how long it takes until it has
+    /// been transferred, including the time spent waiting for earlier messages.
+    fn transfer(&mut self, length: usize) -> Duration {
+        let bytes_per_second = match self.bytes_per_second {
+            Some(bytes_per_second) => bytes_per_second,
+            None => return Duration::from_secs(0),
+        };
+        let now = Instant::now();
+        let start = cmp::max(now, self.busy_until);
+        let transfer_nanos = length as u64 * 1_000_000_000 / bytes_per_second;
+        self.busy_until = start + from_nanos(transfer_nanos);
+        self.busy_until.duration_since(now)
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos())
+}
+
+fn from_nanos(nanos: u64) -> Duration {
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+
+/// A transport delaying requests and responses of an inner transport to simulate the given
+/// `NetworkConditions`.
+///
+/// Clones share the same simulated links, so the bandwidth is shared by all of them.
+#[derive(Debug, Clone)]
+pub struct SimulatedTransport<T> {
+    transport: T,
+    conditions: NetworkConditions,
+    upload: Arc<Mutex<Link>>,
+    download: Arc<Mutex<Link>>,
+}
+
+impl<T: Transport + Clone + Send + 'static> SimulatedTransport<T> {
+    /// Wraps `transport` so its calls are delayed as if sent over a network with `conditions`.
+    pub fn new(transport: T, conditions: NetworkConditions) -> Self {
+        SimulatedTransport {
+            transport,
+            conditions,
+            upload: Arc::new(Mutex::new(Link::new(conditions.upload_bandwidth))),
+            download: Arc::new(Mutex::new(Link::new(conditions.download_bandwidth))),
+        }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport + Clone + Send + 'static> Transport for SimulatedTransport<T> {
+    type Future = SimulatedFuture<T>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let transfer = self.upload.lock().unwrap().transfer(json_data.len());
+        let delay = transfer + self.conditions.one_way_latency();
+        trace!("Simulating network, delaying request {:?}", delay);
+        SimulatedFuture {
+            transport: self.transport.clone(),
+            conditions: self.conditions,
+            download: self.download.clone(),
+            state: SimulatedState::Uploading(timer::sleep(delay), Some(json_data)),
+        }
+    }
+}
+
+impl<T: Transport + Clone + Send + 'static> Layer<T> for NetworkConditions {
+    type Transport = SimulatedTransport<T>;
+
+    /// Wraps `transport` in a `SimulatedTransport` with its own simulated links.
+    fn layer(&self, transport: T) -> SimulatedTransport<T> {
+        SimulatedTransport::new(transport, *self)
+    }
+}
+
+
+/// The future returned by `SimulatedTransport` send operations.
+pub struct SimulatedFuture<T: Transport> {
+    transport: T,
+    conditions: NetworkConditions,
+    download: Arc<Mutex<Link>>,
+    state: SimulatedState<T::Future>,
+}
+
+enum SimulatedState<F> {
+    Uploading(Sleep, Option<Vec<u8>>),
+    Sending(F),
+    Downloading(Sleep, Option<Vec<u8>>),
+}
+
+impl<T: Transport> Future for SimulatedFuture<T> {
+    type Item = Vec<u8>;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let next_state = match self.state {
+            SimulatedState::Uploading(ref mut sleep, ref mut json_data) => {
+                if !sleep_done(sleep) {
+                    return Ok(Async::NotReady);
+                }
+                let json_data = json_data
+                    .take()
+                    .expect("Simulated future polled after completion");
+                SimulatedState::Sending(self.transport.send(json_data))
+            }
+            SimulatedState::Sending(ref mut future) => {
+                let response = try_ready!(future.poll());
+                let transfer = self.download.lock().unwrap().transfer(response.len());
+                let delay = transfer + self.conditions.one_way_latency();
+                trace!("Simulating network, delaying response {:?}", delay);
+                SimulatedState::Downloading(timer::sleep(delay), Some(response))
+            }
+            SimulatedState::Downloading(ref mut sleep, ref mut response) => {
+                if !sleep_done(sleep) {
+                    return Ok(Async::NotReady);
+                }
+                let response = response
+                    .take()
+                    .expect("Simulated future polled after completion");
+                return Ok(Async::Ready(response));
+            }
+        };
+        self.state = next_state;
+        self.poll()
+    }
+}
+
+/// Polls `sleep`, returning whether it has completed. Timer errors are logged and end the delay.
+fn sleep_done(sleep: &mut Sleep) -> bool {
+    match sleep.poll() {
+        Ok(Async::NotReady) => false,
+        Ok(Async::Ready(())) => true,
+        Err(timer_error) => {
+            warn!("Unable to delay simulated network traffic: {}", timer_error);
+            true
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util::EchoTransport;
+
+    #[test]
+    fn adds_latency_in_both_directions() {
+        let conditions = NetworkConditions::new().latency(Duration::from_millis(50));
+        let transport = SimulatedTransport::new(EchoTransport, conditions);
+        let start = Instant::now();
+        assert_eq!(b"ping".to_vec(), transport.send(b"ping".to_vec()).wait().unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let conditions = NetworkConditions::new()
+            .latency(Duration::from_millis(100))
+            .jitter(Duration::from_millis(30));
+        for _ in 0..100 {
+            let latency = conditions.one_way_latency();
+            assert!(latency >= Duration::from_millis(70));
+            assert!(latency <= Duration::from_millis(130));
+        }
+        let conditions = NetworkConditions::new().jitter(Duration::from_millis(30));
+        for _ in 0..100 {
+            assert!(conditions.one_way_latency() <= Duration::from_millis(30));
+        }
+    }
+
+    #[test]
+    fn concurrent_transfers_share_bandwidth() {
+        let mut link = Link::new(Some(1000));
+        assert!(link.transfer(100) > Duration::from_millis(90));
+        assert!(link.transfer(100) > Duration::from_millis(190));
+        assert_eq!(Duration::from_secs(0), Link::new(None).transfer(100));
+    }
+
+    #[test]
+    fn limits_bandwidth() {
+        let conditions = NetworkConditions::new()
+            .upload_bandwidth(1000)
+            .download_bandwidth(2000);
+        let transport = SimulatedTransport::new(EchoTransport, conditions);
+        let start = Instant::now();
+        transport.send(vec![0; 50]).wait().unwrap();
+        // 50 ms to upload the request, 25 ms to download the echoed response.
+        assert!(start.elapsed() >= Duration::from_millis(65));
+    }
+}
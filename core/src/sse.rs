@@ -0,0 +1,389 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport sending calls over one channel and receiving notifications as server-sent events.
+//!
+//! Some gateways take calls as HTTP POST requests, but deliver the notifications of
+//! subscriptions over a separate [server-sent events] stream. An [`SseTransport`] pairs a
+//! transport for the calls with such an event stream, given as a stream of the bytes of the
+//! response body. It is created together with an [`SseConnection`] future, which parses the
+//! events and routes the notifications they carry to the subscriptions made with
+//! [`pubsub::subscribe`]. The connection has to be spawned on an executor, or otherwise polled,
+//! for notifications to arrive.
+//!
+//! The data of every event is expected to be a JSON-RPC notification carrying the subscription
+//! id in `params.subscription` and the event in `params.result`. The type and id of events are
+//! ignored. A notification may arrive before the response to the subscribe call it belongs to,
+//! so the latest notifications of unknown subscriptions are kept and handed to subscriptions
+//! made later.
+//!
+//! When the event stream ends or fails, all subscription streams end. The stream is not opened
+//! again.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::sse::SseTransport;
+//!
+//! let transport = HttpTransport::new().standalone()?;
+//! let events = transport.handle("https://gateway.example.com/events")?.event_stream();
+//! let rpc = transport.handle("https://gateway.example.com/rpc")?;
+//! let (transport, connection) = SseTransport::new(rpc, events);
+//! core.handle().spawn(connection.map_err(|e| error!("Event stream failed: {}", e)));
+//!
+//! let heads = pubsub::subscribe::<_, _, Header>(&transport, &config, method, ("newHeads",));
+//! ```
+//!
+//! [server-sent events]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+//! [`SseTransport`]: struct.SseTransport.html
+//! [`SseConnection`]: struct.SseConnection.html
+//! [`pubsub::subscribe`]: ../pubsub/fn.subscribe.html
+
+use Transport;
+use futures::{Async, Future, Poll, Stream};
+use pubsub::{NotificationSender, PubSubTransport};
+use serde_json::{self, Value as JsonValue};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+/// How many notifications of unknown subscriptions are kept for subscriptions made later.
+const MAX_UNMATCHED: usize = 64;
+
+
+/// The subscriptions of an `SseTransport`, shared with its connection. Also routes the
+/// notifications of a `RedisTransport`.
+#[derive(Debug, Default)]
+pub(crate) struct Routes {
+    /// The notification senders of active subscriptions, by subscription id as JSON text.
+    pub(crate) subscriptions: HashMap<String, NotificationSender>,
+    /// The latest notifications of unknown subscriptions, with their subscription ids.
+    unmatched: VecDeque<(String, JsonValue)>,
+    pub(crate) closed: bool,
+}
+
+impl Routes {
+    /// Routes the notifications of the subscription `subscription` to `notifications`, handing
+    /// it the notifications that arrived before.
+    pub(crate) fn subscribe(&mut self, subscription: String, notifications: NotificationSender) {
+        if self.closed {
+            return;
+        }
+        let unmatched = mem::replace(&mut self.unmatched, VecDeque::new());
+        for (id, result) in unmatched {
+            if id == subscription {
+                notifications.send(result);
+            } else {
+                self.unmatched.push_back((id, result));
+            }
+        }
+        self.subscriptions.insert(subscription, notifications);
+    }
+
+    /// Hands the `result` of a notification to the subscription `subscription`.
+    pub(crate) fn notify(&mut self, subscription: String, result: JsonValue) {
+        let delivered = match self.subscriptions.get(&subscription) {
+            Some(notifications) => notifications.send(result),
+            None => {
+                debug!("Keeping notification of unknown subscription {}", subscription);
+                if self.unmatched.len() >= MAX_UNMATCHED {
+                    self.unmatched.pop_front();
+                }
+                self.unmatched.push_back((subscription, result));
+                return;
+            }
+        };
+        if !delivered {
+            self.subscriptions.remove(&subscription);
+        }
+    }
+
+    /// Ends all subscriptions.
+    pub(crate) fn close(&mut self) {
+        self.closed = true;
+        self.subscriptions.clear();
+        self.unmatched.clear();
+    }
+}
+
+
+/// A transport sending calls over an inner transport, and receiving the notifications of
+/// subscriptions as server-sent events. Clones share the subscriptions.
+#[derive(Clone)]
+pub struct SseTransport<T> {
+    transport: T,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl<T: Transport> SseTransport<T> {
+    /// Creates a transport sending calls over `transport` and receiving notifications from
+    /// `events`, the body of a server-sent events response. Returns the connection routing the
+    /// notifications along with the transport.
+    pub fn new<S>(transport: T, events: S) -> (Self, SseConnection<S>)
+    where
+        S: Stream,
+        S::Item: AsRef<[u8]>,
+    {
+        let routes = Arc::new(Mutex::new(Routes::default()));
+        let connection = SseConnection {
+            events,
+            parser: EventParser::default(),
+            routes: routes.clone(),
+        };
+        (SseTransport { transport, routes }, connection)
+    }
+
+    /// Returns a reference to the transport the calls are sent over.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport> Transport for SseTransport<T> {
+    type Future = Box<Future<Item = Vec<u8>, Error = T::Error> + Send>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        Box::new(self.transport.send(json_data))
+    }
+}
+
+impl<T: Transport> PubSubTransport for SseTransport<T> {
+    fn subscribe(
+        &self,
+        json_data: Vec<u8>,
+        notifications: NotificationSender,
+    ) -> Self::Future {
+        subscribe(&self.transport, &self.routes, json_data, notifications)
+    }
+}
+
+/// Sends the subscribe call `json_data` over `transport`, and routes the notifications of the
+/// subscription to `notifications` once the call has returned the subscription id.
+pub(crate) fn subscribe<T: Transport>(
+    transport: &T,
+    routes: &Arc<Mutex<Routes>>,
+    json_data: Vec<u8>,
+    notifications: NotificationSender,
+) -> Box<Future<Item = Vec<u8>, Error = T::Error> + Send> {
+    let routes = routes.clone();
+    Box::new(transport.send(json_data).map(move |response| {
+        let subscription = serde_json::from_slice::<JsonValue>(&response)
+            .ok()
+            .and_then(|response| response.get("result").map(JsonValue::to_string));
+        if let Some(subscription) = subscription {
+            routes.lock().unwrap().subscribe(subscription, notifications);
+        }
+        response
+    }))
+}
+
+/// Routes `notification` to its subscription if it is a notification of a subscription,
+/// carrying the subscription id in `params.subscription`. Returns it otherwise.
+pub(crate) fn route(routes: &Mutex<Routes>, mut notification: JsonValue) -> Option<JsonValue> {
+    let subscription = match notification.pointer("/params/subscription") {
+        Some(subscription) => subscription.to_string(),
+        None => return Some(notification),
+    };
+    let result = notification
+        .pointer_mut("/params/result")
+        .map_or(JsonValue::Null, |result| mem::replace(result, JsonValue::Null));
+    routes.lock().unwrap().notify(subscription, result);
+    None
+}
+
+impl<T: fmt::Debug> fmt::Debug for SseTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let routes = self.routes.lock().unwrap();
+        f.debug_struct("SseTransport")
+            .field("transport", &self.transport)
+            .field("subscriptions", &routes.subscriptions.len())
+            .field("closed", &routes.closed)
+            .finish()
+    }
+}
+
+
+/// The future reading the event stream of an `SseTransport`, routing notifications to their
+/// subscriptions. Completes when the event stream ends, and fails with the error of the stream.
+/// All subscriptions end with it.
+#[must_use = "futures do nothing unless polled"]
+pub struct SseConnection<S> {
+    events: S,
+    parser: EventParser,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl<S> SseConnection<S> {
+    /// Routes the notification in the data of an event.
+    fn route(&self, data: &str) {
+        let notification = match serde_json::from_str::<JsonValue>(data) {
+            Ok(notification) => notification,
+            Err(_) => {
+                debug!("Discarding event that is not JSON");
+                return;
+            }
+        };
+        if route(&self.routes, notification).is_some() {
+            debug!("Discarding event that is not a notification of a subscription");
+        }
+    }
+}
+
+impl<S> Future for SseConnection<S>
+where
+    S: Stream,
+    S::Item: AsRef<[u8]>,
+{
+    type Item = ();
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<(), S::Error> {
+        loop {
+            match self.events.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(Some(chunk))) => {
+                    for data in self.parser.feed(chunk.as_ref()) {
+                        self.route(&data);
+                    }
+                }
+                result => {
+                    debug!("Event stream ended, ending all subscriptions");
+                    self.routes.lock().unwrap().close();
+                    return result.map(|_| Async::Ready(()));
+                }
+            }
+        }
+    }
+}
+
+impl<S> fmt::Debug for SseConnection<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SseConnection")
+            .field("parser", &self.parser)
+            .finish()
+    }
+}
+
+
+/// Parses a server-sent events stream into the data of its events.
+#[derive(Debug, Default)]
+struct EventParser {
+    /// The incomplete line at the end of the data fed so far.
+    line: Vec<u8>,
+    /// The data of the event being parsed, a line for each data field.
+    data: String,
+    /// If the last byte fed was a carriage return, so a following line feed ends no line.
+    after_cr: bool,
+}
+
+impl EventParser {
+    /// Parses `bytes`, the next part of the stream. Returns the data of the events completed
+    /// by it.
+    fn feed(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut events = Vec::new();
+        for &byte in bytes {
+            let after_cr = mem::replace(&mut self.after_cr, byte == b'\r');
+            match byte {
+                b'\n' if after_cr => (),
+                b'\n' | b'\r' => {
+                    let line = mem::replace(&mut self.line, Vec::new());
+                    if let Some(data) = self.parse_line(&String::from_utf8_lossy(&line)) {
+                        events.push(data);
+                    }
+                }
+                _ => self.line.push(byte),
+            }
+        }
+        events
+    }
+
+    /// Parses a complete line. Returns the data of the event if the line ends it.
+    fn parse_line(&mut self, line: &str) -> Option<String> {
+        if line.is_empty() {
+            if self.data.is_empty() {
+                return None;
+            }
+            let mut data = mem::replace(&mut self.data, String::new());
+            data.pop();
+            return Some(data);
+        }
+        let (field, value) = match line.find(':') {
+            Some(0) => return None,
+            Some(colon) => {
+                let value = &line[colon + 1..];
+                // Only a single leading space is left out of the value.
+                let value = if value.starts_with(' ') { &value[1..] } else { value };
+                (&line[..colon], value)
+            }
+            None => (line, ""),
+        };
+        if field == "data" {
+            self.data.push_str(value);
+            self.data.push('\n');
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ClientConfig;
+    use futures::sync::mpsc;
+    use pubsub;
+    use std::thread;
+    use test_util::SubscribingTransport;
+
+    #[test]
+    fn parses_events() {
+        let mut parser = EventParser::default();
+        assert!(parser.feed(b": keepalive\r\n\r\nevent: message\r\nda").is_empty());
+        let events = parser.feed(b"ta: {\"a\":\r\ndata:1}\r\n\r\ndata\n\ndata: x\r\r");
+        assert_eq!(vec!["{\"a\":\n1}", "", "x"], events);
+    }
+
+    fn notification(subscription: &str, result: u64) -> Vec<u8> {
+        let notification = format!(
+            r#"{{"jsonrpc":"2.0","method":"s","params":{{"subscription":"{}","result":{}}}}}"#,
+            subscription, result
+        );
+        format!("data: {}\n\n", notification).into_bytes()
+    }
+
+    #[test]
+    fn routes_notifications() {
+        let (event_tx, event_rx) = mpsc::unbounded();
+        let (transport, connection) = SseTransport::new(SubscribingTransport("0x9c"), event_rx);
+        let connection = thread::spawn(move || connection.wait());
+        // Arrives before or after the subscribe call completes, and is routed either way.
+        event_tx.unbounded_send(notification("0x9c", 1)).unwrap();
+        event_tx.unbounded_send(notification("0x0", 0)).unwrap();
+        let config = ClientConfig::default();
+        let subscription =
+            pubsub::subscribe::<_, _, u64>(&transport, &config, "subscribe".to_owned(), ())
+                .wait()
+                .unwrap();
+        event_tx.unbounded_send(notification("0x9c", 2)).unwrap();
+        drop(event_tx);
+        connection.join().unwrap().unwrap();
+        // The subscription ends with the event stream.
+        let notifications: Vec<u64> = subscription.wait().map(Result::unwrap).collect();
+        assert_eq!(vec![1, 2], notifications);
+    }
+}
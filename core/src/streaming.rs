@@ -0,0 +1,396 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Incremental parsing of large responses, without buffering them whole first.
+//!
+//! A call made with [`call_method`] reads the response body from a [`StreamingTransport`] chunk
+//! by chunk and deserializes the result as the chunks arrive. Only the deserialized result and
+//! the chunks not parsed yet are held in memory, never the whole response, which bounds the
+//! peak memory use of calls returning results of hundreds of megabytes.
+//!
+//! Methods returning large arrays, like dumps of logs or transactions, can be called with
+//! [`call_method_elements`] instead. It yields the elements of the result one at a time as they are
+//! parsed, so they can be processed with constant memory instead of being collected into a `Vec`.
+//!
+//! The result is parsed on a thread of its own, since `serde_json` can only parse incrementally
+//! from a blocking reader.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::streaming::call_method;
+//!
+//! let config = ClientConfig::default();
+//! let block: Block = call_method(&transport, &config, "getblock".to_owned(), (hash, 2)).wait()?;
+//! ```
+//!
+//! [`call_method`]: fn.call_method.html
+//! [`call_method_elements`]: fn.call_method_elements.html
+//! [`StreamingTransport`]: trait.StreamingTransport.html
+
+use {Error, ErrorKind, RpcFuture, Transport};
+use ClientConfig;
+use Result;
+use error::ResultExt;
+use futures::{future, Async, Future, Poll, Sink, Stream};
+use futures::stream::Wait;
+use futures::sync::{mpsc, oneshot};
+use jsonrpc_core::types::{Id, Version};
+use request::Request;
+use response::{self, ParseOptions};
+use serde;
+use serde::de::{DeserializeSeed, Deserializer, Error as DeError, SeqAccess, Visitor};
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::io;
+use std::thread;
+
+/// How many elements are parsed ahead of the consumer of an `ElementStream`.
+const PARSE_AHEAD: usize = 16;
+
+
+/// A transport able to hand over the body of a response as a stream of chunks, as they arrive.
+pub trait StreamingTransport: Transport {
+    /// The chunks of a response body.
+    type Chunk: AsRef<[u8]> + Send + 'static;
+
+    /// The body of a response.
+    type Body: Stream<Item = Self::Chunk, Error = Self::Error> + Send + 'static;
+
+    /// The future resolving to the body of a response once it starts arriving.
+    type BodyFuture: Future<Item = Self::Body, Error = Self::Error> + Send + 'static;
+
+    /// Sends the given data over the transport and returns a future of the body of the response,
+    /// like `Transport::send`.
+    fn send_streaming(&self, json_data: Vec<u8>) -> Self::BodyFuture;
+}
+
+
+/// Calls `method` with `params` on `transport`, parsing the response incrementally as it arrives.
+/// Takes the validation, missing result and empty params settings from `config`, see
+/// [`call_method`](../fn.call_method.html) for the rest. The timeout and instrumentation of
+/// `config` do not apply to streaming calls.
+pub fn call_method<T, P, R>(
+    transport: &T,
+    config: &ClientConfig,
+    method: String,
+    params: P,
+) -> RpcFuture<R>
+where
+    T: StreamingTransport,
+    P: serde::Serialize,
+    R: serde::de::DeserializeOwned + Send + 'static,
+{
+    let id = Id::Num(transport.get_next_id());
+    let json_data = match serialize_request(&id, config, &method, &params) {
+        Ok(json_data) => json_data,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let options = parse_options(config);
+    let future = transport
+        .send_streaming(json_data)
+        .map_err(|e| Error::with_source(ErrorKind::TransportError, e))
+        .and_then(move |body| {
+            let (result_tx, result_rx) = oneshot::channel();
+            thread::spawn(move || {
+                let result = response::parse_reader(BodyReader::new(body), &id, options);
+                // The caller is gone if this fails, and with it the interest in the result.
+                let _ = result_tx.send(result);
+            });
+            result_rx
+                .map_err(|e| Error::with_source(ErrorKind::ResponseError("Parser died"), e))
+                .and_then(future::result)
+        });
+    Box::new(future)
+}
+
+/// Calls `method` with `params` on `transport` like [`call_method`](fn.call_method.html), for
+/// methods returning an array. Returns a stream of the elements of the array, deserialized one
+/// at a time as they arrive. A `null` or missing result is treated as an empty array.
+///
+/// The response is only checked for being an error, or having the wrong id, once it has been
+/// read to the end. Elements preceding these members in the response are yielded before the
+/// stream fails with the error.
+pub fn call_method_elements<T, P, E>(
+    transport: &T,
+    config: &ClientConfig,
+    method: String,
+    params: P,
+) -> ElementStream<E>
+where
+    T: StreamingTransport,
+    P: serde::Serialize,
+    E: serde::de::DeserializeOwned + Send + 'static,
+{
+    let (element_tx, element_rx) = mpsc::channel(PARSE_AHEAD);
+    let id = Id::Num(transport.get_next_id());
+    let body_future = serialize_request(&id, config, &method, &params).map(|json_data| {
+        transport
+            .send_streaming(json_data)
+            .map_err(|e| Error::with_source(ErrorKind::TransportError, e))
+    });
+    let options = parse_options(config);
+    thread::spawn(move || {
+        let seed = ElementSeed {
+            elements: element_tx.clone(),
+        };
+        let result = body_future
+            .and_then(|body_future| body_future.wait())
+            .and_then(|body| {
+                response::parse_reader_seed(BodyReader::new(body), seed, &id, options)
+            });
+        if let Err(e) = result {
+            // The caller is gone if this fails, and with it the interest in the error.
+            let _ = element_tx.send(Err(e)).wait();
+        }
+    });
+    ElementStream {
+        elements: element_rx,
+    }
+}
+
+/// Returns the serialized request calling `method` with `params`.
+fn serialize_request<P: serde::Serialize>(
+    id: &Id,
+    config: &ClientConfig,
+    method: &str,
+    params: &P,
+) -> Result<Vec<u8>> {
+    trace!("Serializing streaming call to method \"{}\" with id {:?}", method, id);
+    let request = Request {
+        version: Version::V2,
+        method,
+        params,
+        id: Some(id),
+        empty_params: config.empty_params,
+    };
+    let mut json_data = Vec::new();
+    request
+        .serialize_into(&mut json_data)
+        .chain_err(|| ErrorKind::SerializeError)?;
+    Ok(json_data)
+}
+
+/// Returns the settings from `config` for parsing responses.
+fn parse_options(config: &ClientConfig) -> ParseOptions {
+    ParseOptions {
+        validation: config.validation,
+        missing_result_as_null: config.missing_result_as_null,
+        keep_envelope: false,
+    }
+}
+
+
+/// The elements of an array result, deserialized one at a time as they arrive. Returned by
+/// [`call_method_elements`](fn.call_method_elements.html). Dropping it aborts the call.
+#[derive(Debug)]
+pub struct ElementStream<E> {
+    elements: mpsc::Receiver<Result<E>>,
+}
+
+impl<E> Stream for ElementStream<E> {
+    type Item = E;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<E>, Error> {
+        match self.elements.poll() {
+            Ok(Async::Ready(Some(Ok(element)))) => Ok(Async::Ready(Some(element))),
+            Ok(Async::Ready(Some(Err(e)))) => Err(e),
+            Ok(Async::Ready(None)) | Err(()) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Deserializes an array, sending its elements to `elements` one at a time instead of
+/// collecting them.
+struct ElementSeed<E> {
+    elements: mpsc::Sender<Result<E>>,
+}
+
+impl<'de, E: serde::Deserialize<'de>> DeserializeSeed<'de> for ElementSeed<E> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> ::std::result::Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ElementVisitor(self.elements))
+    }
+}
+
+struct ElementVisitor<E>(mpsc::Sender<Result<E>>);
+
+impl<'de, E: serde::Deserialize<'de>> Visitor<'de> for ElementVisitor<E> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array")
+    }
+
+    fn visit_unit<A: DeError>(self) -> ::std::result::Result<(), A> {
+        Ok(())
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> ::std::result::Result<(), A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut elements = self.0;
+        while let Some(element) = seq.next_element()? {
+            elements = elements
+                .send(Ok(element))
+                .wait()
+                .map_err(|_| A::Error::custom("Element stream dropped"))?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Reads a stream of chunks, blocking while waiting for the next chunk.
+struct BodyReader<S: Stream> {
+    chunks: Wait<S>,
+    chunk: Option<S::Item>,
+    position: usize,
+}
+
+impl<S: Stream> BodyReader<S> {
+    fn new(body: S) -> Self {
+        BodyReader {
+            chunks: body.wait(),
+            chunk: None,
+            position: 0,
+        }
+    }
+}
+
+impl<S> io::Read for BodyReader<S>
+where
+    S: Stream,
+    S::Item: AsRef<[u8]>,
+    S::Error: error::Error + Send + Sync + 'static,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(ref chunk) = self.chunk {
+                let remaining = &chunk.as_ref()[self.position..];
+                if !remaining.is_empty() {
+                    let len = cmp::min(remaining.len(), buf.len());
+                    buf[..len].copy_from_slice(&remaining[..len]);
+                    self.position += len;
+                    return Ok(len);
+                }
+            }
+            match self.chunks.next() {
+                Some(Ok(chunk)) => {
+                    self.chunk = Some(chunk);
+                    self.position = 0;
+                }
+                Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream::{self, IterResult};
+    use futures::future::FutureResult;
+    use std::vec::IntoIter;
+
+    /// A transport sending back the given response, in chunks of a few bytes.
+    struct ChunkedTransport(String);
+
+    impl ChunkedTransport {
+        /// Responds with a long array of numbers, and the given members after the result.
+        fn numbers(members: &str) -> Self {
+            let numbers: Vec<String> = (0..1000).map(|n| n.to_string()).collect();
+            ChunkedTransport(format!(
+                r#"{{"jsonrpc":"2.0","result":[{}]{}}}"#,
+                numbers.join(","),
+                members
+            ))
+        }
+    }
+
+    impl Transport for ChunkedTransport {
+        type Future = FutureResult<Vec<u8>, io::Error>;
+        type Error = io::Error;
+
+        fn get_next_id(&self) -> u64 {
+            1
+        }
+
+        fn send(&self, _json_data: Vec<u8>) -> Self::Future {
+            unimplemented!()
+        }
+    }
+
+    impl StreamingTransport for ChunkedTransport {
+        type Chunk = Vec<u8>;
+        type Body = IterResult<IntoIter<io::Result<Vec<u8>>>>;
+        type BodyFuture = FutureResult<Self::Body, io::Error>;
+
+        fn send_streaming(&self, _json_data: Vec<u8>) -> Self::BodyFuture {
+            let chunks: Vec<_> = self.0
+                .as_bytes()
+                .chunks(7)
+                .map(|chunk| Ok(chunk.to_vec()))
+                .collect();
+            future::ok(stream::iter_result(chunks))
+        }
+    }
+
+    #[test]
+    fn parses_chunked_response() {
+        let config = ClientConfig::default();
+        let transport = ChunkedTransport::numbers(r#","id":1"#);
+        let result: Vec<u32> = call_method(&transport, &config, "dump".to_owned(), ())
+            .wait()
+            .unwrap();
+        assert_eq!((0..1000).collect::<Vec<_>>(), result);
+    }
+
+    #[test]
+    fn yields_elements() {
+        let config = ClientConfig::default();
+        let transport = ChunkedTransport::numbers(r#","id":1"#);
+        let elements: ElementStream<u32> =
+            call_method_elements(&transport, &config, "dump".to_owned(), ());
+        let mut expected = 0;
+        for element in elements.wait() {
+            assert_eq!(expected, element.unwrap());
+            expected += 1;
+        }
+        assert_eq!(1000, expected);
+
+        let transport = ChunkedTransport(r#"{"jsonrpc":"2.0","id":1,"result":null}"#.to_owned());
+        let elements: ElementStream<u32> =
+            call_method_elements(&transport, &config, "dump".to_owned(), ());
+        assert_eq!(0, elements.wait().count());
+    }
+
+    #[test]
+    fn fails_after_elements() {
+        let config = ClientConfig::default();
+        let transport = ChunkedTransport::numbers(r#","id":2"#);
+        let elements: Vec<Result<u32>> =
+            call_method_elements(&transport, &config, "dump".to_owned(), ()).wait().collect();
+        assert_eq!(1001, elements.len());
+        assert!(elements[..1000].iter().all(|element| element.is_ok()));
+        match *elements[1000].as_ref().unwrap_err().kind() {
+            ErrorKind::ResponseError(_) => (),
+            ref kind => panic!("Wrong error kind: {:?}", kind),
+        }
+    }
+}
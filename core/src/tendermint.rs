@@ -0,0 +1,190 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A preset for subscribing to events of Tendermint and Cosmos nodes over WebSocket.
+//!
+//! The Tendermint RPC subscribes to events matching a query, like `tm.event='NewBlock'`, with
+//! `subscribe` and `unsubscribe` calls taking the query as their only named param. Unlike most
+//! servers, it does not hand out subscription ids. Events are sent as further responses to the
+//! subscribe call, with the id of the call followed by `#event`, and with an envelope holding
+//! the query, the typed event data and the indexed event attributes as their `result`.
+//!
+//! [`connect`] creates a [`DuplexTransport`] routing such events to their subscription, over the
+//! messages of a WebSocket connection. [`subscribe`] makes the subscribe call and resolves to a
+//! stream of [`Event`]s, with the event data deserialized into the given type.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::tendermint;
+//!
+//! let (sink, stream) = websocket_messages.split();
+//! let (transport, connection) = tendermint::connect(sink, stream);
+//! core.handle().spawn(connection.map_err(|e| error!("Connection failed: {}", e)));
+//!
+//! let config = ClientConfig::default();
+//! let blocks = tendermint::subscribe::<_, NewBlock>(&transport, &config, "tm.event='NewBlock'");
+//! core.run(blocks.and_then(|blocks| blocks.for_each(|event| handle_block(event.value))))?;
+//! ```
+//!
+//! [`connect`]: fn.connect.html
+//! [`subscribe`]: fn.subscribe.html
+//! [`Event`]: struct.Event.html
+//! [`DuplexTransport`]: ../duplex/struct.DuplexTransport.html
+
+use {call_method, ClientConfig, RpcRequest, Transport};
+use duplex::{Connection, DuplexTransport, SubscriptionRouting};
+use futures::{Sink, Stream};
+use pubsub::{self, PubSubTransport, SubscribeRequest};
+use serde::de::{self, Deserialize, DeserializeOwned, Deserializer};
+use serde_json::{self, Map, Value as JsonValue};
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+
+
+/// An event of a Tendermint subscription.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event<D> {
+    /// The query of the subscription the event matched.
+    pub query: String,
+    /// The type of the event data, like `tendermint/event/NewBlock`.
+    pub event_type: String,
+    /// The event data.
+    pub value: D,
+    /// The indexed attributes of the event, like `tm.event` or `tx.hash`, with their values.
+    pub events: HashMap<String, Vec<String>>,
+}
+
+impl<'de, D: DeserializeOwned> Deserialize<'de> for Event<D> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let mut envelope = JsonValue::deserialize(deserializer)?;
+        Ok(Event {
+            query: take(&mut envelope, "/query")?,
+            event_type: take(&mut envelope, "/data/type")?,
+            value: take(&mut envelope, "/data/value")?,
+            events: take::<Option<_>, _>(&mut envelope, "/events")?.unwrap_or_default(),
+        })
+    }
+}
+
+/// Takes the member at `pointer` out of `envelope` and deserializes it into `T`.
+fn take<T, E>(envelope: &mut JsonValue, pointer: &str) -> Result<T, E>
+where
+    T: DeserializeOwned,
+    E: de::Error,
+{
+    let value = envelope
+        .pointer_mut(pointer)
+        .map_or(JsonValue::Null, |value| mem::replace(value, JsonValue::Null));
+    serde_json::from_value(value)
+        .map_err(|e| E::custom(format!("Invalid event {}: {}", pointer, e)))
+}
+
+
+/// Creates a `DuplexTransport` talking to a Tendermint node over a WebSocket connection exposed
+/// as a `Sink` and a `Stream` of messages. The connection routes events to subscriptions made
+/// with `subscribe`.
+pub fn connect<S, R>(sink: S, stream: R) -> (DuplexTransport, Connection<S, R>)
+where
+    S: Sink<SinkItem = Vec<u8>, SinkError = io::Error>,
+    R: Stream<Item = Vec<u8>, Error = io::Error>,
+{
+    let (transport, connection) = DuplexTransport::new(sink, stream);
+    (
+        transport,
+        connection.subscription_routing(SubscriptionRouting::RequestId),
+    )
+}
+
+/// Subscribes to the events matching `query`, and resolves to a stream of them with their data
+/// deserialized into `D`.
+pub fn subscribe<T, D>(
+    transport: &T,
+    config: &ClientConfig,
+    query: &str,
+) -> SubscribeRequest<Event<D>, T::Future>
+where
+    T: PubSubTransport,
+    D: DeserializeOwned,
+{
+    pubsub::subscribe(transport, config, "subscribe".to_owned(), query_params(query))
+}
+
+/// Tells the node to stop sending the events matching `query`.
+pub fn unsubscribe<T: Transport>(
+    transport: &T,
+    config: &ClientConfig,
+    query: &str,
+) -> RpcRequest<JsonValue, T::Future> {
+    call_method(transport, config, "unsubscribe".to_owned(), query_params(query))
+}
+
+fn query_params(query: &str) -> JsonValue {
+    let mut params = Map::new();
+    params.insert("query".to_owned(), JsonValue::String(query.to_owned()));
+    JsonValue::Object(params)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::sync::mpsc::channel;
+    use futures::Future;
+    use std::thread;
+
+    fn closed<E>(_: E) -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, "Channel closed")
+    }
+
+    fn event(id: JsonValue, height: u64) -> JsonValue {
+        json!({"jsonrpc": "2.0", "id": id, "result": {
+            "query": "tm.event='NewBlock'",
+            "data": {"type": "tendermint/event/NewBlock", "value": height},
+            "events": {"tm.event": ["NewBlock"]},
+        }})
+    }
+
+    #[test]
+    fn events_of_subscription() {
+        let (requests_tx, requests_rx) = channel(16);
+        let (responses_tx, responses_rx) = channel(16);
+        let (transport, connection) =
+            connect(requests_tx.sink_map_err(closed), responses_rx.map_err(closed));
+        thread::spawn(move || connection.wait());
+        // Answers the subscribe call and sends events in the styles of different versions.
+        thread::spawn(move || {
+            let mut responses = responses_tx.wait();
+            let request = requests_rx.wait().next().unwrap().unwrap();
+            let request: JsonValue = serde_json::from_slice(&request).unwrap();
+            assert_eq!(json!({"query": "tm.event='NewBlock'"}), request["params"]);
+            let id = request["id"].clone();
+            let messages = vec![
+                json!({"jsonrpc": "2.0", "id": id, "result": {}}),
+                event(json!(format!("{}#event", id)), 1),
+                event(json!("other#event"), 2),
+                event(id, 3),
+            ];
+            for message in messages {
+                responses.send(serde_json::to_vec(&message).unwrap()).unwrap();
+            }
+        });
+
+        let config = ClientConfig::default();
+        let events = subscribe::<_, u64>(&transport, &config, "tm.event='NewBlock'")
+            .wait()
+            .unwrap()
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(vec![1, 3], events.iter().map(|e| e.value).collect::<Vec<_>>());
+        assert_eq!("tendermint/event/NewBlock", events[0].event_type);
+        assert_eq!(vec!["NewBlock".to_owned()], events[0].events["tm.event"]);
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Mock transports shared by the tests of the transport wrappers.
+
+use deadline::Deadline;
+use futures::future::{self, Empty, FutureResult};
+use metadata::CallMetadata;
+use serde_json::{self, Value as JsonValue};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use Transport;
+
+/// A transport responding with the received request.
+#[derive(Debug, Clone)]
+pub struct EchoTransport;
+
+impl Transport for EchoTransport {
+    type Future = FutureResult<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        1
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        future::ok(json_data)
+    }
+}
+
+/// Returns the id of the request in `json_data`, or 1 if it is not a request.
+fn request_id(json_data: &[u8]) -> JsonValue {
+    serde_json::from_slice::<JsonValue>(json_data)
+        .ok()
+        .and_then(|request| request.get("id").cloned())
+        .unwrap_or_else(|| JsonValue::from(1))
+}
+
+/// A transport responding with the received request as the result.
+#[derive(Debug, Clone)]
+pub struct EchoResultTransport;
+
+impl Transport for EchoResultTransport {
+    type Future = FutureResult<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        1
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let request: JsonValue = serde_json::from_slice(&json_data).unwrap();
+        let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": request});
+        future::ok(serde_json::to_vec(&response).unwrap())
+    }
+}
+
+/// A transport responding to every request with `true` as the result.
+#[derive(Debug, Clone)]
+pub struct OkTransport;
+
+impl Transport for OkTransport {
+    type Future = FutureResult<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        1
+    }
+
+    fn send(&self, _json_data: Vec<u8>) -> Self::Future {
+        future::ok(br#"{"jsonrpc": "2.0", "id": 1, "result": true}"#.to_vec())
+    }
+}
+
+/// A transport answering every call with its first parameter, or with an error for calls to
+/// `fail`, and batches with the responses to their calls in reverse order. Records the number
+/// of calls, the deadline and the trace id of every request. Clones share the records.
+#[derive(Debug, Clone, Default)]
+pub struct EchoParamsTransport {
+    next_id: Arc<AtomicUsize>,
+    /// The number of calls in every request, one for requests that are not batches.
+    pub sizes: Arc<Mutex<Vec<usize>>>,
+    pub deadlines: Arc<Mutex<Vec<Option<Instant>>>>,
+    pub trace_ids: Arc<Mutex<Vec<Option<String>>>>,
+}
+
+impl EchoParamsTransport {
+    /// Returns the number of requests sent.
+    pub fn sent(&self) -> usize {
+        self.sizes.lock().unwrap().len()
+    }
+
+    fn answer(request: &JsonValue) -> JsonValue {
+        if request["method"] == "fail" {
+            json!({"jsonrpc": "2.0", "id": request["id"], "error": {
+                "code": -32000,
+                "message": "Failed",
+            }})
+        } else {
+            json!({"jsonrpc": "2.0", "id": request["id"], "result": request["params"][0]})
+        }
+    }
+}
+
+impl Transport for EchoParamsTransport {
+    type Future = FutureResult<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let deadline = Deadline::current().map(|deadline| deadline.at());
+        self.deadlines.lock().unwrap().push(deadline);
+        let trace_id = CallMetadata::current()
+            .and_then(|metadata| metadata.get_trace_id().map(str::to_owned));
+        self.trace_ids.lock().unwrap().push(trace_id);
+        let request: JsonValue = serde_json::from_slice(&json_data).unwrap();
+        let response = match request {
+            JsonValue::Array(requests) => {
+                self.sizes.lock().unwrap().push(requests.len());
+                let responses: Vec<_> = requests
+                    .iter()
+                    .rev()
+                    .filter(|request| request.get("id").is_some())
+                    .map(Self::answer)
+                    .collect();
+                JsonValue::Array(responses)
+            }
+            request => {
+                self.sizes.lock().unwrap().push(1);
+                Self::answer(&request)
+            }
+        };
+        future::ok(serde_json::to_vec(&response).unwrap())
+    }
+}
+
+/// A transport failing the first `failures` requests with `ConnectionRefused`, and handing the
+/// rest to `inner`. Clones share the count of attempts.
+#[derive(Debug, Clone)]
+pub struct FlakyTransport<T> {
+    inner: T,
+    failures: usize,
+    pub attempts: Arc<AtomicUsize>,
+}
+
+impl<T> FlakyTransport<T> {
+    pub fn new(failures: usize, inner: T) -> Self {
+        FlakyTransport {
+            inner,
+            failures,
+            attempts: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<T> Transport for FlakyTransport<T>
+where
+    T: Transport<Future = FutureResult<Vec<u8>, io::Error>, Error = io::Error>,
+{
+    type Future = FutureResult<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.inner.get_next_id()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+            future::err(io::Error::new(io::ErrorKind::ConnectionRefused, "Flaky"))
+        } else {
+            self.inner.send(json_data)
+        }
+    }
+}
+
+/// A transport answering `true` unless told to fail, counting the requests sent on it. Clones
+/// share the switch and the count.
+#[derive(Debug, Clone, Default)]
+pub struct SwitchTransport {
+    pub failing: Arc<AtomicBool>,
+    sent: Arc<AtomicUsize>,
+}
+
+impl SwitchTransport {
+    pub fn failing() -> Self {
+        let transport = Self::default();
+        transport.failing.store(true, Ordering::SeqCst);
+        transport
+    }
+
+    pub fn sent(&self) -> usize {
+        self.sent.load(Ordering::SeqCst)
+    }
+}
+
+impl Transport for SwitchTransport {
+    type Future = FutureResult<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        1
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+        if self.failing.load(Ordering::SeqCst) {
+            return future::err(io::Error::new(io::ErrorKind::ConnectionRefused, "Refused"));
+        }
+        let response = json!({"jsonrpc": "2.0", "id": request_id(&json_data), "result": true});
+        future::ok(serde_json::to_vec(&response).unwrap())
+    }
+}
+
+/// A transport never responding, counting the requests sent on it. Clones share the count.
+#[derive(Debug, Clone, Default)]
+pub struct SilentTransport {
+    sent: Arc<AtomicUsize>,
+}
+
+impl SilentTransport {
+    pub fn sent(&self) -> usize {
+        self.sent.load(Ordering::SeqCst)
+    }
+}
+
+impl Transport for SilentTransport {
+    type Future = Empty<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        1
+    }
+
+    fn send(&self, _json_data: Vec<u8>) -> Self::Future {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+        future::empty()
+    }
+}
+
+/// A transport answering every call, like a subscribe call, with the given subscription id.
+#[derive(Debug, Clone)]
+pub struct SubscribingTransport(pub &'static str);
+
+impl Transport for SubscribingTransport {
+    type Future = FutureResult<Vec<u8>, io::Error>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        1
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let response = json!({"jsonrpc": "2.0", "id": request_id(&json_data), "result": self.0});
+        future::ok(serde_json::to_vec(&response).unwrap())
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport wrapper keeping a minimum interval between requests.
+//!
+//! [`ThrottledTransport`] protects a connection shared by several clients, like a serial port or
+//! a daemon that drops requests arriving too close together. All clones of the transport share
+//! one schedule, so wrapping the shared transport once throttles every client using it. Unlike a
+//! [`RateLimitedTransport`] there are no bursts: two requests are never sent closer together than
+//! the interval, however long the transport has been idle. Requests are delayed in the order they
+//! were sent and never rejected.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::throttle::ThrottledTransport;
+//!
+//! let transport = ThrottledTransport::new(serial_transport, Duration::from_millis(20));
+//! let mut sensors = SensorClient::new(transport.clone());
+//! let mut motors = MotorClient::new(transport);
+//! ```
+//!
+//! [`ThrottledTransport`]: struct.ThrottledTransport.html
+//! [`RateLimitedTransport`]: ../rate_limit/struct.RateLimitedTransport.html
+
+use Transport;
+use futures::{Async, Future, Poll};
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use timer;
+use tokio_timer::Sleep;
+
+
+/// When requests may be sent, shared by all clones of a `ThrottledTransport`.
+#[derive(Debug)]
+struct Schedule {
+    interval: Duration,
+    next_send: Option<Instant>,
+}
+
+impl Schedule {
+    /// Reserves the next free slot and returns how long the caller must wait until it.
+    fn reserve(&mut self) -> Duration {
+        let now = Instant::now();
+        let send_at = self.next_send.map_or(now, |next_send| cmp::max(now, next_send));
+        self.next_send = Some(send_at + self.interval);
+        send_at.duration_since(now)
+    }
+}
+
+
+/// A transport sending requests on an inner transport with at least a given interval between
+/// them.
+///
+/// Clones share the same schedule, so the interval applies to all of them together.
+#[derive(Debug, Clone)]
+pub struct ThrottledTransport<T> {
+    transport: T,
+    schedule: Arc<Mutex<Schedule>>,
+}
+
+impl<T: Transport + Clone + Send + 'static> ThrottledTransport<T> {
+    /// Wraps `transport` so requests are sent at least `interval` apart.
+    pub fn new(transport: T, interval: Duration) -> Self {
+        ThrottledTransport {
+            transport,
+            schedule: Arc::new(Mutex::new(Schedule {
+                interval,
+                next_send: None,
+            })),
+        }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport + Clone + Send + 'static> Transport for ThrottledTransport<T> {
+    type Future = ThrottledFuture<T>;
+    type Error = T::Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.transport.get_next_id()
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.transport.endpoint()
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let delay = self.schedule.lock().unwrap().reserve();
+        let state = if delay == Duration::from_secs(0) {
+            ThrottleState::Sending(self.transport.send(json_data))
+        } else {
+            trace!("Throttling request {:?}", delay);
+            ThrottleState::Waiting(timer::sleep(delay), Some(json_data))
+        };
+        ThrottledFuture {
+            transport: self.transport.clone(),
+            state,
+        }
+    }
+}
+
+
+/// The future returned by `ThrottledTransport` send operations.
+pub struct ThrottledFuture<T: Transport> {
+    transport: T,
+    state: ThrottleState<T::Future>,
+}
+
+enum ThrottleState<F> {
+    Waiting(Sleep, Option<Vec<u8>>),
+    Sending(F),
+}
+
+impl<T: Transport> Future for ThrottledFuture<T> {
+    type Item = Vec<u8>;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let json_data = match self.state {
+            ThrottleState::Sending(ref mut future) => return future.poll(),
+            ThrottleState::Waiting(ref mut sleep, ref mut json_data) => {
+                match sleep.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => (),
+                    Err(timer_error) => warn!("Unable to delay throttled request: {}", timer_error),
+                }
+                json_data
+                    .take()
+                    .expect("Throttled future polled after completion")
+            }
+        };
+        self.state = ThrottleState::Sending(self.transport.send(json_data));
+        self.poll()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use test_util::OkTransport;
+
+    #[test]
+    fn clones_share_interval() {
+        let transport = ThrottledTransport::new(OkTransport, Duration::from_millis(30));
+        let other = transport.clone();
+        let start = Instant::now();
+        let requests = vec![transport.send(vec![]), other.send(vec![]), transport.send(vec![])];
+        future::join_all(requests).wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn no_burst_after_idle() {
+        let mut schedule = Schedule {
+            interval: Duration::from_millis(100),
+            next_send: Some(Instant::now() - Duration::from_secs(10)),
+        };
+        assert_eq!(Duration::from_secs(0), schedule.reserve());
+        assert!(schedule.reserve() > Duration::from_millis(90));
+        assert!(schedule.reserve() > Duration::from_millis(190));
+    }
+}
@@ -0,0 +1,29 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::time::Duration;
+use tokio_timer::{self, Sleep, Timer};
+
+/// The longest delay the timer can be asked for. Longer delays are shortened to this.
+const MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    /// Timer shared by all parts of this crate that need to delay something. Runs on its own
+    /// thread, making the delays independent of any event loop.
+    static ref TIMER: Timer = tokio_timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .max_timeout(MAX_DELAY)
+        .thread_name("jsonrpc-client-timer")
+        .build();
+}
+
+/// Returns a future that completes after `duration` has passed.
+pub fn sleep(duration: Duration) -> Sleep {
+    TIMER.sleep(cmp::min(duration, MAX_DELAY))
+}
@@ -0,0 +1,501 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A transport calling methods on ZeroMQ sockets.
+//!
+//! [`connect`] speaks ZMTP 3.0, the wire protocol of ZeroMQ, over a connection to a `REP` or
+//! `ROUTER` socket, without linking to libzmq. It takes the part of a `REQ` or a `DEALER`
+//! socket, as chosen with [`SocketType`]. Only the `NULL` security mechanism is supported, so
+//! the peer must not use `PLAIN` or `CURVE`.
+//!
+//! Every request is sent as a multipart message of an empty delimiter frame followed by the
+//! JSON, the envelope `REP` sockets expect. The frames of a reply after the delimiter are joined
+//! into the response. A `REQ` socket has one call in flight at a time, further calls waiting for
+//! the reply to the previous one, while a `DEALER` socket sends calls as they are made and
+//! matches replies to calls by their id, which needs a server answering with a `ROUTER` socket
+//! or one worker per request.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use jsonrpc_client_core::zmq::{self, SocketType};
+//!
+//! let stream = core.run(TcpStream::connect(&address, &core.handle()))?;
+//! let (transport, connection) = zmq::connect(stream, SocketType::Dealer);
+//! core.handle().spawn(connection.map_err(|e| error!("ZMTP connection failed: {}", e)));
+//!
+//! let mut client = OrderBookClient::new(transport);
+//! let depth = core.run(client.depth("BTC-EUR"))?;
+//! ```
+//!
+//! [`connect`]: fn.connect.html
+//! [`SocketType`]: enum.SocketType.html
+
+use bytes::BytesMut;
+use duplex::{Connection, DuplexTransport};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::stream::{SplitSink, SplitStream};
+use std::collections::VecDeque;
+use std::io;
+use tokio_codec::{Decoder, Encoder, Framed};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The longest frame accepted from the peer, 16 MiB.
+const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The length of the greeting starting a ZMTP 3.0 connection.
+const GREETING_LENGTH: usize = 64;
+
+/// The flag of frames followed by more frames of the same message.
+const MORE: u8 = 0x01;
+/// The flag of frames with an eight byte size.
+const LONG: u8 = 0x02;
+/// The flag of command frames.
+const COMMAND: u8 = 0x04;
+
+
+/// The type of ZeroMQ socket the transport acts as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketType {
+    /// A `REQ` socket, sending one request at a time and waiting for its reply.
+    Req,
+    /// A `DEALER` socket, sending requests without waiting for the replies to earlier ones.
+    Dealer,
+}
+
+impl SocketType {
+    fn name(&self) -> &'static [u8] {
+        match *self {
+            SocketType::Req => b"REQ",
+            SocketType::Dealer => b"DEALER",
+        }
+    }
+}
+
+/// The connection of a `DuplexTransport` talking to a ZeroMQ socket.
+pub type ZmqConnection<T> = Connection<ZmqSink<T>, ZmqStream<T>>;
+
+/// Creates a `DuplexTransport` calling methods over `io`, a connection to a ZeroMQ socket,
+/// acting as a socket of type `socket_type`. Returns the transport and the future driving the
+/// connection.
+///
+/// The greeting and handshake are written when the connection is first polled, followed by the
+/// requests without waiting for the handshake of the peer. The connection fails if the peer
+/// speaks an older ZMTP version, asks for another security mechanism or sends an `ERROR`
+/// command.
+pub fn connect<T>(io: T, socket_type: SocketType) -> (DuplexTransport, ZmqConnection<T>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let (framed_sink, framed_stream) = ZmtpCodec::new().framed(io).split();
+    let sink = ZmqSink {
+        framed: framed_sink,
+        queued: vec![Frame::Greeting, ready_command(socket_type)]
+            .into_iter()
+            .collect(),
+    };
+    let stream = ZmqStream {
+        framed: framed_stream,
+        parts: Vec::new(),
+    };
+    let (transport, connection) = DuplexTransport::new(sink, stream);
+    let connection = match socket_type {
+        SocketType::Req => connection.max_pending(1),
+        SocketType::Dealer => connection,
+    };
+    (transport, connection)
+}
+
+
+/// Sends the requests of a ZeroMQ transport as multipart messages.
+pub struct ZmqSink<T> {
+    framed: SplitSink<Framed<T, ZmtpCodec>>,
+    /// Frames accepted by the sink but not yet handed to the framed sink.
+    queued: VecDeque<Frame>,
+}
+
+impl<T: AsyncRead + AsyncWrite> ZmqSink<T> {
+    fn poll_queued(&mut self) -> Poll<(), io::Error> {
+        while let Some(frame) = self.queued.pop_front() {
+            if let AsyncSink::NotReady(frame) = self.framed.start_send(frame)? {
+                self.queued.push_front(frame);
+                return Ok(Async::NotReady);
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Sink for ZmqSink<T> {
+    type SinkItem = Vec<u8>;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, json_data: Vec<u8>) -> StartSend<Vec<u8>, io::Error> {
+        if self.poll_queued()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(json_data));
+        }
+        self.queued.push_back(Frame::Message {
+            more: true,
+            body: Vec::new(),
+        });
+        self.queued.push_back(Frame::Message {
+            more: false,
+            body: json_data,
+        });
+        self.poll_queued()?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        try_ready!(self.poll_queued());
+        self.framed.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), io::Error> {
+        try_ready!(self.poll_queued());
+        self.framed.close()
+    }
+}
+
+/// Reads the replies to the requests of a ZeroMQ transport.
+pub struct ZmqStream<T> {
+    framed: SplitStream<Framed<T, ZmtpCodec>>,
+    /// The frames received so far of the current message.
+    parts: Vec<Vec<u8>>,
+}
+
+impl<T: AsyncRead + AsyncWrite> Stream for ZmqStream<T> {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, io::Error> {
+        loop {
+            match try_ready!(self.framed.poll()) {
+                Some(Frame::Message { more: true, body }) => self.parts.push(body),
+                Some(Frame::Message { more: false, body }) => {
+                    self.parts.push(body);
+                    // Skips the envelope, ending with an empty delimiter frame.
+                    let parts = ::std::mem::replace(&mut self.parts, Vec::new());
+                    let start = parts.iter().position(Vec::is_empty).map_or(0, |i| i + 1);
+                    return Ok(Async::Ready(Some(parts[start..].concat())));
+                }
+                Some(Frame::Command(command)) => command_received(&command)?,
+                Some(Frame::Greeting) => trace!("Received ZMTP greeting"),
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+
+/// The frames of a ZMTP connection.
+#[derive(Debug, Clone, PartialEq)]
+enum Frame {
+    /// The greeting starting the connection, announcing the `NULL` mechanism.
+    Greeting,
+    /// A command frame, with the command name and its data.
+    Command(Vec<u8>),
+    /// A frame of a message, followed by more frames of the message if `more` is set.
+    Message { more: bool, body: Vec<u8> },
+}
+
+/// A codec framing ZMTP 3.0 greetings and frames.
+#[derive(Debug)]
+struct ZmtpCodec {
+    /// If the greeting of the peer has been decoded.
+    greeted: bool,
+}
+
+impl ZmtpCodec {
+    fn new() -> Self {
+        ZmtpCodec { greeted: false }
+    }
+}
+
+impl Decoder for ZmtpCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Frame>> {
+        if !self.greeted {
+            if buf.len() < GREETING_LENGTH {
+                return Ok(None);
+            }
+            let greeting = buf.split_to(GREETING_LENGTH);
+            check_greeting(&greeting)?;
+            self.greeted = true;
+            return Ok(Some(Frame::Greeting));
+        }
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let flags = buf[0];
+        let (length, header_length) = if flags & LONG != 0 {
+            if buf.len() < 9 {
+                return Ok(None);
+            }
+            let length = buf[1..9].iter().fold(0u64, |length, &b| length << 8 | b as u64);
+            (length, 9)
+        } else {
+            if buf.len() < 2 {
+                return Ok(None);
+            }
+            (buf[1] as u64, 2)
+        };
+        if length > MAX_FRAME_LENGTH as u64 {
+            return Err(invalid("ZMTP frame exceeds the maximum length"));
+        }
+        let length = length as usize;
+        if buf.len() < header_length + length {
+            buf.reserve(header_length + length - buf.len());
+            return Ok(None);
+        }
+        buf.split_to(header_length);
+        let body = buf.split_to(length).to_vec();
+        if flags & COMMAND != 0 {
+            Ok(Some(Frame::Command(body)))
+        } else {
+            Ok(Some(Frame::Message {
+                more: flags & MORE != 0,
+                body,
+            }))
+        }
+    }
+}
+
+impl Encoder for ZmtpCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, buf: &mut BytesMut) -> io::Result<()> {
+        let (flags, body) = match frame {
+            Frame::Greeting => {
+                let mut greeting = [0; GREETING_LENGTH];
+                greeting[0] = 0xff;
+                greeting[9] = 0x7f;
+                greeting[10] = 3;
+                greeting[12..16].copy_from_slice(b"NULL");
+                buf.extend_from_slice(&greeting);
+                return Ok(());
+            }
+            Frame::Command(body) => (COMMAND, body),
+            Frame::Message { more: true, body } => (MORE, body),
+            Frame::Message { more: false, body } => (0, body),
+        };
+        buf.reserve(9 + body.len());
+        if body.len() > 0xff {
+            let length = body.len() as u64;
+            buf.extend_from_slice(&[flags | LONG]);
+            for shift in (0..8).rev() {
+                buf.extend_from_slice(&[(length >> (8 * shift)) as u8]);
+            }
+        } else {
+            buf.extend_from_slice(&[flags, body.len() as u8]);
+        }
+        buf.extend_from_slice(&body);
+        Ok(())
+    }
+}
+
+
+/// Checks that the greeting of the peer is one of ZMTP 3 with the `NULL` mechanism.
+fn check_greeting(greeting: &[u8]) -> io::Result<()> {
+    if greeting[0] != 0xff || greeting[9] & 0x01 == 0 {
+        return Err(invalid("Peer does not speak ZMTP"));
+    }
+    if greeting[10] < 3 {
+        return Err(invalid("Peer speaks a ZMTP version older than 3.0"));
+    }
+    let mechanism = &greeting[12..32];
+    let end = mechanism.iter().position(|&b| b == 0).unwrap_or(mechanism.len());
+    if &mechanism[..end] != b"NULL" {
+        let message = format!(
+            "Peer asks for the {} security mechanism, only NULL is supported",
+            String::from_utf8_lossy(&mechanism[..end])
+        );
+        return Err(invalid(&message));
+    }
+    Ok(())
+}
+
+/// Returns the `READY` command of the `NULL` mechanism, announcing the socket type.
+fn ready_command(socket_type: SocketType) -> Frame {
+    let name = socket_type.name();
+    let mut command = b"\x05READY\x0bSocket-Type".to_vec();
+    command.extend_from_slice(&[0, 0, 0, name.len() as u8]);
+    command.extend_from_slice(name);
+    Frame::Command(command)
+}
+
+/// Handles a command of the peer, failing on `ERROR` commands.
+fn command_received(command: &[u8]) -> io::Result<()> {
+    let name_length = command.first().map_or(0, |&length| length as usize);
+    let name = command.get(1..1 + name_length).unwrap_or(b"");
+    match name {
+        b"READY" => debug!("ZMTP handshake completed"),
+        b"ERROR" => {
+            // The reason is prefixed with its length, a single byte.
+            let reason = command.get(2 + name_length..).unwrap_or(b"");
+            let message = format!("ZMTP peer failed: {}", String::from_utf8_lossy(reason));
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, message));
+        }
+        name => trace!("Ignoring ZMTP command {}", String::from_utf8_lossy(name)),
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Future};
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+    use RpcClient;
+
+    fn greeting() -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        ZmtpCodec::new().encode(Frame::Greeting, &mut buf).unwrap();
+        buf.to_vec()
+    }
+
+    #[test]
+    fn encode_frames() {
+        let mut codec = ZmtpCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(ready_command(SocketType::Req), &mut buf).unwrap();
+        assert_eq!(&b"\x04\x19\x05READY\x0bSocket-Type\x00\x00\x00\x03REQ"[..], &buf[..]);
+
+        let mut buf = BytesMut::new();
+        let body = vec![b'x'; 300];
+        let frame = Frame::Message {
+            more: true,
+            body: body.clone(),
+        };
+        codec.encode(frame, &mut buf).unwrap();
+        assert_eq!(&b"\x03\x00\x00\x00\x00\x00\x00\x01\x2c"[..], &buf[..9]);
+        assert_eq!(&body[..], &buf[9..]);
+    }
+
+    #[test]
+    fn decode_frames() {
+        let mut codec = ZmtpCodec::new();
+        let mut buf = BytesMut::from(&greeting()[..20]);
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+        buf.extend_from_slice(&greeting()[20..]);
+        buf.extend_from_slice(b"\x01\x00\x00\x02{}");
+        assert_eq!(Some(Frame::Greeting), codec.decode(&mut buf).unwrap());
+        let delimiter = Frame::Message {
+            more: true,
+            body: vec![],
+        };
+        assert_eq!(Some(delimiter), codec.decode(&mut buf).unwrap());
+        let body = Frame::Message {
+            more: false,
+            body: b"{}".to_vec(),
+        };
+        assert_eq!(Some(body), codec.decode(&mut buf).unwrap());
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+
+        let mut curve = greeting();
+        curve[12..17].copy_from_slice(b"CURVE");
+        let mut buf = BytesMut::from(curve);
+        assert!(ZmtpCodec::new().decode(&mut buf).is_err());
+    }
+
+    /// A connection to a socket recording every write, and reading the given bytes once
+    /// something has been written to it.
+    struct PeerSocket {
+        writes: Arc<Mutex<Vec<u8>>>,
+        reply: Option<Vec<u8>>,
+    }
+
+    impl Read for PeerSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.writes.lock().unwrap().is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            match self.reply.take() {
+                Some(reply) => {
+                    buf[..reply.len()].copy_from_slice(&reply);
+                    Ok(reply.len())
+                }
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    impl Write for PeerSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for PeerSocket {}
+
+    impl AsyncWrite for PeerSocket {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn peer(reply: &[u8]) -> (PeerSocket, Arc<Mutex<Vec<u8>>>) {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let socket = PeerSocket {
+            writes: writes.clone(),
+            reply: Some([&greeting()[..], reply].concat()),
+        };
+        (socket, writes)
+    }
+
+    #[test]
+    fn calls_are_sent_one_at_a_time() {
+        let response = br#"{"jsonrpc":"2.0","id":1,"result":true}"#;
+        let mut reply = b"\x04\x19\x05READY\x0bSocket-Type\x00\x00\x00\x03REP".to_vec();
+        reply.extend_from_slice(&[MORE, 0, 0, response.len() as u8]);
+        reply.extend_from_slice(response);
+        let (socket, writes) = peer(&reply);
+        let (transport, mut connection) = connect(socket, SocketType::Req);
+        let mut client = RpcClient::new(transport);
+        let mut first = client.call::<_, bool>("ping", ());
+        let _second = client.call::<_, bool>("ping", ());
+
+        // Writes the handshake and the first request, and then reads the reply.
+        future::lazy(|| connection.poll()).wait().unwrap();
+        let written = writes.lock().unwrap().len();
+        future::lazy(|| connection.poll()).wait().unwrap();
+        assert_eq!(Async::Ready(true), future::lazy(|| first.poll()).wait().unwrap());
+
+        let writes = writes.lock().unwrap();
+        assert_eq!(&greeting()[..], &writes[..GREETING_LENGTH]);
+        let ready = b"\x04\x19\x05READY\x0bSocket-Type\x00\x00\x00\x03REQ";
+        let request = &writes[GREETING_LENGTH + ready.len()..written];
+        assert_eq!(&b"\x01\x00\x00"[..], &request[..3]);
+        assert_eq!(request.len(), 4 + request[3] as usize);
+        // The second request is only written once the first has been answered.
+        assert!(writes.len() > written);
+    }
+
+    #[test]
+    fn error_command() {
+        let (socket, _writes) = peer(b"\x04\x0f\x05ERROR\x08Too busy");
+        let (_transport, mut connection) = connect(socket, SocketType::Dealer);
+        future::lazy(|| connection.poll()).wait().unwrap();
+        let error = future::lazy(|| connection.poll()).wait().unwrap_err();
+        assert_eq!("ZMTP peer failed: Too busy", error.to_string());
+    }
+}
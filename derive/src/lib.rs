@@ -0,0 +1,559 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A procedural macro alternative to the `jsonrpc_client` macro of
+//! [`jsonrpc-client-core`](../jsonrpc_client_core/index.html).
+//!
+//! The `#[jsonrpc_client]` attribute goes on a trait listing the methods of an API. It generates
+//! the same things as the `jsonrpc_client_trait` macro of `jsonrpc-client-core`: the trait with
+//! methods returning a boxed `RpcFuture`, and a client struct implementing it. The client also has
+//! all the methods of the trait as inherent methods returning `RpcRequest`s. Trait methods take
+//! `&self` or `&mut self`, as declared, while the inherent methods always take `&self`.
+//!
+//! Compared to the `macro_rules` macros it allows more configuration, and mistakes in the trait
+//! are reported with errors pointing at the offending code.
+//!
+//! # Attribute arguments
+//!
+//! * `client = "Name"` - The name of the generated client struct. Defaults to the name of the
+//!   trait followed by `Client`.
+//! * `prefix = "ns_"` - Put in front of the names of all methods when sent to the server.
+//! * `version = "1.0"` - Send JSON-RPC 1.0 requests instead of 2.0.
+//! * `openrpc = "function_name"` - Also generate a function with the given name, returning an
+//!   [OpenRPC](https://open-rpc.org) document describing the methods as JSON. Parameter and
+//!   result schemas are derived from the Rust types, with types like structs accepting any value.
+//!
+//! # Method and argument attributes
+//!
+//! * `#[rpc(name = "methodName")]` on a method - The method name sent to the server, instead of
+//!   the name of the Rust method.
+//! * `#[rpc(deprecated_since = "x.y", note = "...")]` on a method - Marks the RPC method as
+//!   deprecated, see the `deprecation` module of `jsonrpc-client-core`.
+//! * `#[rpc(idempotent)]` on a method - Marks the RPC method as safe to call more than once, so
+//!   retry policies restricted with `idempotent_only` may resend it.
+//! * `#[rpc(cache_ttl = 60)]` on a method - Lets a `CachingTransport` configured with the
+//!   `METHODS` of the client cache responses of the method for that many seconds.
+//! * `#[rpc(name = "argName")]` on an argument - The name of the argument when the client is
+//!   configured to send arguments keyed by name, with `ParamsStyle::Named`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! extern crate jsonrpc_client_core;
+//! extern crate jsonrpc_client_derive;
+//!
+//! use jsonrpc_client_derive::jsonrpc_client;
+//!
+//! #[jsonrpc_client(client = "FizzBuzzClient")]
+//! pub trait FizzBuzz {
+//!     /// Returns the fizz-buzz string for the given number.
+//!     #[rpc(name = "fizzBuzz")]
+//!     fn fizz_buzz(&mut self, number: u64) -> RpcRequest<String>;
+//! }
+//!
+//! fn main() {
+//!     let mut client = FizzBuzzClient::new(transport);
+//!     println!("{}", client.fizz_buzz(3).call().unwrap());
+//! }
+//! ```
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::spanned::Spanned;
+use syn::{AttributeArgs, Attribute, FnArg, GenericArgument, Ident, ItemTrait, Lit, LitStr, Meta,
+          NestedMeta, Pat, PathArguments, ReturnType, TraitItem, TraitItemMethod, Type};
+
+/// Module for describing clients as OpenRPC documents.
+mod openrpc;
+
+
+/// Generates a JSON-RPC client implementing the trait this attribute is put on. See the
+/// [crate documentation](index.html) for details.
+#[proc_macro_attribute]
+pub fn jsonrpc_client(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(input as ItemTrait);
+    match expand(args, item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+
+/// The settings given as arguments to the attribute.
+struct ClientOptions {
+    client: Ident,
+    prefix: String,
+    json_rpc_v1: bool,
+    openrpc: Option<Ident>,
+}
+
+/// A method of the trait.
+struct Method {
+    attrs: Vec<Attribute>,
+    name: Ident,
+    wire_name: String,
+    deprecated_since: Option<LitStr>,
+    note: Option<LitStr>,
+    idempotent: bool,
+    cache_ttl_secs: Option<u64>,
+    /// Whether the trait method takes `&mut self` rather than `&self`.
+    mut_receiver: bool,
+    args: Vec<Argument>,
+    return_ty: Type,
+    error_ty: Option<Type>,
+}
+
+/// An argument of a method.
+struct Argument {
+    name: Ident,
+    ty: Type,
+    wire_name: String,
+}
+
+
+fn expand(args: AttributeArgs, item: ItemTrait) -> syn::Result<TokenStream2> {
+    let options = parse_options(args, &item.ident)?;
+    if !item.generics.params.is_empty() || item.generics.where_clause.is_some() {
+        return Err(syn::Error::new(
+            item.generics.span(),
+            "generic traits are not supported",
+        ));
+    }
+    if let Some(supertrait) = item.supertraits.first() {
+        return Err(syn::Error::new(
+            supertrait.span(),
+            "supertraits are not supported",
+        ));
+    }
+    let methods = item.items
+        .iter()
+        .map(|item| match *item {
+            TraitItem::Method(ref method) => parse_method(method, &options),
+            ref item => Err(syn::Error::new(item.span(), "only methods are supported")),
+        })
+        .collect::<syn::Result<Vec<Method>>>()?;
+
+    let vis = &item.vis;
+    let trait_attrs = &item.attrs;
+    let trait_name = &item.ident;
+    let client = &options.client;
+    let call = if options.json_rpc_v1 {
+        quote!(call_method_v1)
+    } else {
+        quote!(call_method)
+    };
+    let client_doc = format!(
+        "A JSON-RPC client implementing [`{0}`](trait.{0}.html).",
+        trait_name
+    );
+
+    let trait_methods = methods.iter().map(|method| {
+        let attrs = &method.attrs;
+        let signature = signature(method, false);
+        quote! {
+            #(#attrs)*
+            #signature;
+        }
+    });
+    let client_methods = methods.iter().map(|method| {
+        let attrs = &method.attrs;
+        let signature = signature(method, true);
+        let wire_name = &method.wire_name;
+        let deprecation = method.deprecated_since.as_ref().map(|since| {
+            let note = match method.note {
+                Some(ref note) => quote!(Some(#note)),
+                None => quote!(None),
+            };
+            quote!(::jsonrpc_client_core::deprecation::deprecated_call(#wire_name, #since, #note);)
+        });
+        let arg_names = method.args.iter().map(|arg| &arg.name);
+        let arg_wire_names = method.args.iter().map(|arg| &arg.wire_name);
+        // A nullary method must send `[]`, not the `null` that `()` serializes to.
+        let values = if method.args.is_empty() {
+            quote!([] as [(); 0])
+        } else {
+            quote!((#(#arg_names,)*))
+        };
+        quote! {
+            #(#attrs)*
+            pub #signature {
+                #deprecation
+                let params = ::jsonrpc_client_core::Arguments::new(
+                    &self.config,
+                    &[#(#arg_wire_names),*],
+                    #values,
+                );
+                ::jsonrpc_client_core::#call(
+                    &*self.transport,
+                    &self.config,
+                    String::from(#wire_name),
+                    params,
+                ).err_into()
+            }
+        }
+    });
+    let method_infos = methods.iter().map(|method| {
+        let wire_name = &method.wire_name;
+        let arg_wire_names = method.args.iter().map(|arg| &arg.wire_name);
+        let idempotent = method.idempotent;
+        let cache_ttl_secs = match method.cache_ttl_secs {
+            Some(secs) => quote!(Some(#secs)),
+            None => quote!(None),
+        };
+        quote! {
+            ::jsonrpc_client_core::MethodInfo {
+                name: #wire_name,
+                params: &[#(#arg_wire_names),*],
+                idempotent: #idempotent,
+                cache_ttl_secs: #cache_ttl_secs,
+            }
+        }
+    });
+    let impl_methods = methods.iter().map(|method| {
+        let signature = signature(method, false);
+        let name = &method.name;
+        let arg_names = method.args.iter().map(|arg| &arg.name);
+        quote! {
+            #signature {
+                Box::new(#client::#name(self, #(#arg_names),*))
+            }
+        }
+    });
+    let openrpc = options.openrpc.as_ref().map(|function| {
+        let document = openrpc::document(
+            &trait_name.to_string(),
+            openrpc::docs(trait_attrs),
+            &methods,
+        );
+        let doc = format!(
+            "Returns an OpenRPC document describing the methods of [`{0}`](trait.{0}.html), as \
+             JSON.",
+            trait_name
+        );
+        quote! {
+            #[doc = #doc]
+            #vis fn #function() -> &'static str {
+                #document
+            }
+        }
+    });
+
+    Ok(quote! {
+        #(#trait_attrs)*
+        #vis trait #trait_name {
+            #(#trait_methods)*
+        }
+
+        #[doc = #client_doc]
+        #vis struct #client<
+            T: ::jsonrpc_client_core::Transport = ::jsonrpc_client_core::boxed::BoxTransport,
+        > {
+            transport: ::std::sync::Arc<T>,
+            config: ::jsonrpc_client_core::ClientConfig,
+        }
+
+        /// Clones share the transport, and so the ids of their requests never collide.
+        impl<T: ::jsonrpc_client_core::Transport> Clone for #client<T> {
+            fn clone(&self) -> Self {
+                #client {
+                    transport: self.transport.clone(),
+                    config: self.config.clone(),
+                }
+            }
+        }
+
+        impl<T: ::jsonrpc_client_core::Transport> ::jsonrpc_client_core::FromTransport<T>
+            for #client<T>
+        {
+            fn from_transport(transport: T, config: ::jsonrpc_client_core::ClientConfig) -> Self {
+                Self::with_config(transport, config)
+            }
+        }
+
+        impl<T: ::jsonrpc_client_core::Transport> #client<T> {
+            /// Creates a new RPC client backed by the given transport implementation.
+            pub fn new(transport: T) -> Self {
+                Self::with_config(transport, ::jsonrpc_client_core::ClientConfig::default())
+            }
+
+            /// Creates a new RPC client backed by the given transport implementation and using
+            /// the given configuration.
+            pub fn with_config(transport: T, config: ::jsonrpc_client_core::ClientConfig) -> Self {
+                #client {
+                    transport: ::std::sync::Arc::new(transport),
+                    config,
+                }
+            }
+
+            /// Calls the RPC method `method` with the given parameters. The method name is sent
+            /// as it is, without any prefix.
+            pub fn call_raw<P, R>(&self, method: &str, params: P)
+                -> ::jsonrpc_client_core::RpcRequest<R, T::Future>
+            where
+                P: ::jsonrpc_client_core::serde::Serialize,
+                R: ::jsonrpc_client_core::serde::de::DeserializeOwned + Send + 'static,
+            {
+                ::jsonrpc_client_core::#call(
+                    &*self.transport,
+                    &self.config,
+                    method.to_owned(),
+                    params,
+                )
+            }
+
+            /// Descriptions of the RPC methods of this client.
+            pub const METHODS: &'static [::jsonrpc_client_core::MethodInfo] = &[
+                #(#method_infos,)*
+            ];
+
+            #(#client_methods)*
+        }
+
+        impl<T: ::jsonrpc_client_core::Transport> #trait_name for #client<T> {
+            #(#impl_methods)*
+        }
+
+        #openrpc
+    })
+}
+
+/// Generates the signature of a method, returning `RpcFuture<T, E>` in the trait and
+/// `RpcRequest<T, T::Future, E>` in the inherent methods of the client. Inherent methods always
+/// take `&self`, trait methods take the receiver they were declared with.
+fn signature(method: &Method, inherent: bool) -> TokenStream2 {
+    let name = &method.name;
+    let args = method.args.iter().map(|arg| {
+        let (name, ty) = (&arg.name, &arg.ty);
+        quote!(#name: #ty)
+    });
+    let return_ty = &method.return_ty;
+    let error_ty = match method.error_ty {
+        Some(ref error_ty) => quote!(#error_ty),
+        None => quote!(::jsonrpc_client_core::Error),
+    };
+    let output = if inherent {
+        quote!(::jsonrpc_client_core::RpcRequest<#return_ty, T::Future, #error_ty>)
+    } else {
+        quote!(::jsonrpc_client_core::RpcFuture<#return_ty, #error_ty>)
+    };
+    let receiver = if method.mut_receiver && !inherent {
+        quote!(&mut self)
+    } else {
+        quote!(&self)
+    };
+    quote! {
+        fn #name(#receiver, #(#args),*) -> #output
+    }
+}
+
+fn parse_options(args: AttributeArgs, trait_name: &Ident) -> syn::Result<ClientOptions> {
+    let mut options = ClientOptions {
+        client: Ident::new(&format!("{}Client", trait_name), trait_name.span()),
+        prefix: String::new(),
+        json_rpc_v1: false,
+        openrpc: None,
+    };
+    for arg in args {
+        let (key, value) = name_value(&arg)?;
+        match key.as_str() {
+            "client" => options.client = value.parse()?,
+            "prefix" => options.prefix = value.value(),
+            "openrpc" => options.openrpc = Some(value.parse()?),
+            "version" => match value.value().as_str() {
+                "1.0" => options.json_rpc_v1 = true,
+                "2.0" => options.json_rpc_v1 = false,
+                _ => return Err(syn::Error::new(value.span(), "expected \"1.0\" or \"2.0\"")),
+            },
+            _ => return Err(syn::Error::new(arg.span(), "unknown argument")),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_method(method: &TraitItemMethod, options: &ClientOptions) -> syn::Result<Method> {
+    let sig = &method.sig;
+    if let Some(ref default) = method.default {
+        return Err(syn::Error::new(
+            default.span(),
+            "methods can not have a default implementation",
+        ));
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "generic methods are not supported",
+        ));
+    }
+    if sig.constness.is_some() || sig.asyncness.is_some() || sig.unsafety.is_some()
+        || sig.abi.is_some() || sig.variadic.is_some()
+    {
+        return Err(syn::Error::new(sig.span(), "expected a plain method"));
+    }
+
+    let mut inputs = sig.inputs.iter();
+    let mut_receiver = match inputs.next() {
+        Some(&FnArg::Receiver(ref receiver)) if receiver.reference.is_some() => {
+            receiver.mutability.is_some()
+        }
+        _ => {
+            return Err(syn::Error::new(
+                sig.inputs.span(),
+                "methods must take `&self` or `&mut self`",
+            ))
+        }
+    };
+    let args = inputs.map(parse_argument).collect::<syn::Result<Vec<_>>>()?;
+    let (return_ty, error_ty) = parse_return_type(&sig.output)?;
+
+    let mut parsed = Method {
+        attrs: Vec::new(),
+        name: sig.ident.clone(),
+        wire_name: sig.ident.to_string(),
+        deprecated_since: None,
+        note: None,
+        idempotent: false,
+        cache_ttl_secs: None,
+        mut_receiver,
+        args,
+        return_ty,
+        error_ty,
+    };
+    for attr in &method.attrs {
+        if !attr.path.is_ident("rpc") {
+            parsed.attrs.push(attr.clone());
+            continue;
+        }
+        for nested in rpc_attribute_args(attr)? {
+            if let NestedMeta::Meta(Meta::Path(ref path)) = nested {
+                if path.is_ident("idempotent") {
+                    parsed.idempotent = true;
+                    continue;
+                }
+            }
+            if let NestedMeta::Meta(Meta::NameValue(ref name_value)) = nested {
+                if let (true, &Lit::Int(ref secs)) =
+                    (name_value.path.is_ident("cache_ttl"), &name_value.lit)
+                {
+                    parsed.cache_ttl_secs = Some(secs.base10_parse()?);
+                    continue;
+                }
+            }
+            let (key, value) = name_value(&nested)?;
+            match key.as_str() {
+                "name" => parsed.wire_name = value.value(),
+                "deprecated_since" => parsed.deprecated_since = Some(value),
+                "note" => parsed.note = Some(value),
+                _ => return Err(syn::Error::new(nested.span(), "unknown rpc attribute")),
+            }
+        }
+    }
+    if let (&Some(ref note), &None) = (&parsed.note, &parsed.deprecated_since) {
+        return Err(syn::Error::new(
+            note.span(),
+            "`note` requires `deprecated_since`",
+        ));
+    }
+    parsed.wire_name = format!("{}{}", options.prefix, parsed.wire_name);
+    Ok(parsed)
+}
+
+fn parse_argument(arg: &FnArg) -> syn::Result<Argument> {
+    let pat_type = match *arg {
+        FnArg::Typed(ref pat_type) => pat_type,
+        FnArg::Receiver(ref receiver) => {
+            return Err(syn::Error::new(receiver.span(), "unexpected receiver"))
+        }
+    };
+    let name = match *pat_type.pat {
+        Pat::Ident(ref pat) if pat.by_ref.is_none() && pat.subpat.is_none() => pat.ident.clone(),
+        ref pat => return Err(syn::Error::new(pat.span(), "expected an argument name")),
+    };
+    let mut wire_name = name.to_string();
+    for attr in &pat_type.attrs {
+        if !attr.path.is_ident("rpc") {
+            return Err(syn::Error::new(
+                attr.span(),
+                "only rpc attributes are supported on arguments",
+            ));
+        }
+        for nested in rpc_attribute_args(attr)? {
+            match name_value(&nested)? {
+                (ref key, ref value) if key == "name" => wire_name = value.value(),
+                _ => return Err(syn::Error::new(nested.span(), "unknown rpc attribute")),
+            }
+        }
+    }
+    Ok(Argument {
+        name,
+        ty: (*pat_type.ty).clone(),
+        wire_name,
+    })
+}
+
+/// Parses `RpcRequest<T>` or `RpcRequest<T, E>` into `T` and `E`.
+fn parse_return_type(output: &ReturnType) -> syn::Result<(Type, Option<Type>)> {
+    let error = || {
+        syn::Error::new(
+            output.span(),
+            "methods must return `RpcRequest<T>` or `RpcRequest<T, E>`",
+        )
+    };
+    let segment = match *output {
+        ReturnType::Type(_, ref ty) => match **ty {
+            Type::Path(ref path) if path.qself.is_none() => path.path.segments.last(),
+            _ => None,
+        },
+        ReturnType::Default => None,
+    };
+    let arguments = match segment {
+        Some(segment) if segment.ident == "RpcRequest" => match segment.arguments {
+            PathArguments::AngleBracketed(ref arguments) => &arguments.args,
+            _ => return Err(error()),
+        },
+        _ => return Err(error()),
+    };
+    let mut types = arguments.iter().map(|argument| match *argument {
+        GenericArgument::Type(ref ty) => Ok(ty.clone()),
+        _ => Err(error()),
+    });
+    match (types.next(), types.next(), types.next()) {
+        (Some(return_ty), None, None) => Ok((return_ty?, None)),
+        (Some(return_ty), Some(error_ty), None) => Ok((return_ty?, Some(error_ty?))),
+        _ => Err(error()),
+    }
+}
+
+/// Returns the settings inside `#[rpc(...)]`.
+fn rpc_attribute_args(attr: &Attribute) -> syn::Result<Vec<NestedMeta>> {
+    match attr.parse_meta()? {
+        Meta::List(list) => Ok(list.nested.into_iter().collect()),
+        meta => Err(syn::Error::new(meta.span(), "expected `rpc(...)`")),
+    }
+}
+
+/// Parses a `key = "value"` setting.
+fn name_value(nested: &NestedMeta) -> syn::Result<(String, LitStr)> {
+    if let NestedMeta::Meta(Meta::NameValue(ref name_value)) = *nested {
+        if let (Some(key), &Lit::Str(ref value)) =
+            (name_value.path.get_ident(), &name_value.lit)
+        {
+            return Ok((key.to_string(), value.clone()));
+        }
+    }
+    Err(syn::Error::new(nested.span(), "expected `name = \"value\"`"))
+}
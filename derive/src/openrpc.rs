@@ -0,0 +1,207 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use serde_json::{Map, Value as JsonValue};
+use std::env;
+use syn::{Attribute, GenericArgument, Lit, Meta, PathArguments, Type};
+use Method;
+
+/// The OpenRPC version of the generated documents.
+const OPENRPC_VERSION: &str = "1.2.6";
+
+
+/// Returns an OpenRPC document describing the methods, as JSON. The version of the API is the
+/// version of the crate the client is defined in.
+pub fn document(title: &str, description: Option<String>, methods: &[Method]) -> String {
+    let mut info = Map::new();
+    info.insert("title".to_owned(), title.into());
+    info.insert(
+        "version".to_owned(),
+        env::var("CARGO_PKG_VERSION")
+            .unwrap_or_else(|_| "0.0.0".to_owned())
+            .into(),
+    );
+    if let Some(description) = description {
+        info.insert("description".to_owned(), description.into());
+    }
+    let methods: Vec<JsonValue> = methods.iter().map(method).collect();
+    json!({
+        "openrpc": OPENRPC_VERSION,
+        "info": info,
+        "methods": methods,
+    }).to_string()
+}
+
+fn method(method: &Method) -> JsonValue {
+    let params: Vec<JsonValue> = method
+        .args
+        .iter()
+        .map(|arg| {
+            let (ty, required) = match option_inner(&arg.ty) {
+                Some(ty) => (ty, false),
+                None => (&arg.ty, true),
+            };
+            json!({
+                "name": arg.wire_name,
+                "required": required,
+                "schema": schema(ty),
+            })
+        })
+        .collect();
+    let mut object = Map::new();
+    object.insert("name".to_owned(), method.wire_name.clone().into());
+    if let Some(description) = docs(&method.attrs) {
+        object.insert("description".to_owned(), description.into());
+    }
+    object.insert("params".to_owned(), params.into());
+    object.insert(
+        "result".to_owned(),
+        json!({
+            "name": "result",
+            "schema": schema(&method.return_ty),
+        }),
+    );
+    if method.deprecated_since.is_some() {
+        object.insert("deprecated".to_owned(), true.into());
+    }
+    object.into()
+}
+
+/// Returns the JSON schema of values of a Rust type. Types without a known schema, like structs,
+/// get the schema accepting anything.
+fn schema(ty: &Type) -> JsonValue {
+    match *ty {
+        Type::Reference(ref reference) => schema(&reference.elem),
+        Type::Paren(ref paren) => schema(&paren.elem),
+        Type::Group(ref group) => schema(&group.elem),
+        Type::Slice(ref slice) => json!({"type": "array", "items": schema(&slice.elem)}),
+        Type::Array(ref array) => json!({"type": "array", "items": schema(&array.elem)}),
+        Type::Tuple(ref tuple) if tuple.elems.is_empty() => json!({"type": "null"}),
+        Type::Path(ref path) if path.qself.is_none() => {
+            let segment = match path.path.segments.last() {
+                Some(segment) => segment,
+                None => return json!({}),
+            };
+            match segment.ident.to_string().as_str() {
+                "String" | "str" | "char" => json!({"type": "string"}),
+                "bool" => json!({"type": "boolean"}),
+                "f32" | "f64" => json!({"type": "number"}),
+                "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => {
+                    json!({"type": "integer", "minimum": 0})
+                }
+                "i8" | "i16" | "i32" | "i64" | "i128" | "isize" => json!({"type": "integer"}),
+                "Vec" | "VecDeque" | "HashSet" | "BTreeSet" => match type_argument(ty) {
+                    Some(item) => json!({"type": "array", "items": schema(item)}),
+                    None => json!({"type": "array"}),
+                },
+                "Option" => match type_argument(ty).map(schema) {
+                    Some(JsonValue::Object(mut object)) => {
+                        let nullable = match object.get("type") {
+                            Some(&JsonValue::String(ref ty)) => Some(json!([ty, "null"])),
+                            _ => None,
+                        };
+                        match nullable {
+                            Some(types) => {
+                                object.insert("type".to_owned(), types);
+                                object.into()
+                            }
+                            None => json!({}),
+                        }
+                    }
+                    _ => json!({}),
+                },
+                _ => json!({}),
+            }
+        }
+        _ => json!({}),
+    }
+}
+
+/// Returns `T` if the type is `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    match *ty {
+        Type::Path(ref path) if path.qself.is_none() => {
+            let segment = path.path.segments.last()?;
+            if segment.ident == "Option" {
+                type_argument(ty)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the first type argument of a generic type, like `T` of `Vec<T>`.
+fn type_argument(ty: &Type) -> Option<&Type> {
+    let segment = match *ty {
+        Type::Path(ref path) => path.path.segments.last()?,
+        _ => return None,
+    };
+    match segment.arguments {
+        PathArguments::AngleBracketed(ref arguments) => {
+            arguments.args.iter().filter_map(|argument| match *argument {
+                GenericArgument::Type(ref ty) => Some(ty),
+                _ => None,
+            }).next()
+        }
+        _ => None,
+    }
+}
+
+/// Returns the text of the doc comments among the attributes, if any.
+pub fn docs(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(ref meta)) => match meta.lit {
+                Lit::Str(ref text) => Some(text.value().trim().to_owned()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn;
+
+    fn schema_of(ty: &str) -> JsonValue {
+        schema(&syn::parse_str(ty).unwrap())
+    }
+
+    #[test]
+    fn schemas() {
+        let cases = [
+            ("String", json!({"type": "string"})),
+            ("&str", json!({"type": "string"})),
+            ("u64", json!({"type": "integer", "minimum": 0})),
+            ("i32", json!({"type": "integer"})),
+            ("f64", json!({"type": "number"})),
+            ("bool", json!({"type": "boolean"})),
+            ("()", json!({"type": "null"})),
+            ("Vec<bool>", json!({"type": "array", "items": {"type": "boolean"}})),
+            ("&[u8]", json!({"type": "array", "items": {"type": "integer", "minimum": 0}})),
+            ("Option<String>", json!({"type": ["string", "null"]})),
+            ("Option<Block>", json!({})),
+            ("serde_json::Value", json!({})),
+        ];
+        for &(ty, ref expected) in &cases {
+            assert_eq!(*expected, schema_of(ty));
+        }
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate futures;
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_derive;
+#[macro_use]
+extern crate serde_json;
+
+use futures::Future;
+use jsonrpc_client_core::{ClientConfig, ParamsStyle, RpcFuture, Transport};
+use jsonrpc_client_derive::jsonrpc_client;
+use serde_json::Value as JsonValue;
+use std::io;
+
+
+/// A transport responding with the request it was sent as the result.
+struct EchoTransport;
+
+impl Transport for EchoTransport {
+    type Future = Box<Future<Item = Vec<u8>, Error = io::Error> + Send>;
+    type Error = io::Error;
+
+    fn get_next_id(&self) -> u64 {
+        1
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let json = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": serde_json::from_slice::<JsonValue>(&json_data).unwrap(),
+        });
+        Box::new(futures::future::ok(serde_json::to_vec(&json).unwrap()))
+    }
+}
+
+/// An API echoing requests.
+#[jsonrpc_client(prefix = "test_", openrpc = "echo_openrpc")]
+pub trait Echo {
+    /// Echoes the request.
+    fn ping(&mut self, value: u64) -> RpcRequest<JsonValue>;
+
+    #[rpc(name = "lookUp", idempotent, cache_ttl = 60)]
+    fn look_up(&mut self, #[rpc(name = "userId")] user_id: &str, limit: u64)
+        -> RpcRequest<JsonValue>;
+
+    fn nothing(&self) -> RpcRequest<JsonValue>;
+}
+
+#[jsonrpc_client(client = "LegacyClient", version = "1.0")]
+trait Legacy {
+    #[rpc(deprecated_since = "2.0", note = "Use ping")]
+    fn old_ping(&mut self) -> RpcRequest<JsonValue>;
+}
+
+
+#[test]
+fn prefix_and_rename() {
+    let client = EchoClient::new(EchoTransport);
+    let request = client.ping(3).call().unwrap();
+    assert_eq!("test_ping", request["method"]);
+    assert_eq!(json!([3]), request["params"]);
+
+    let request = client.look_up("a", 2).call().unwrap();
+    assert_eq!("test_lookUp", request["method"]);
+    assert_eq!(json!(["a", 2]), request["params"]);
+
+    assert_eq!(json!([]), client.nothing().call().unwrap()["params"]);
+}
+
+#[test]
+fn type_erased_client() {
+    let client: EchoClient = EchoClient::new(jsonrpc_client_core::boxed::transport(EchoTransport));
+    assert_eq!("test_ping", client.ping(3).call().unwrap()["method"]);
+}
+
+#[test]
+fn named_arguments() {
+    let config = ClientConfig::new().params_style(ParamsStyle::Named);
+    let client = EchoClient::with_config(EchoTransport, config);
+    let request = client.look_up("a", 2).call().unwrap();
+    assert_eq!(json!({"userId": "a", "limit": 2}), request["params"]);
+    assert_eq!(json!({}), client.nothing().call().unwrap()["params"]);
+}
+
+#[test]
+fn method_infos() {
+    let methods = EchoClient::<EchoTransport>::METHODS;
+    assert_eq!(3, methods.len());
+    assert_eq!("test_lookUp", methods[1].name);
+    assert_eq!(&["userId", "limit"], methods[1].params);
+    assert!(methods[1].idempotent);
+    assert!(!methods[0].idempotent);
+    assert_eq!(Some(60), methods[1].cache_ttl_secs);
+    assert_eq!(None, methods[0].cache_ttl_secs);
+}
+
+#[test]
+fn json_rpc_v1() {
+    let client = LegacyClient::new(EchoTransport);
+    let request = client.old_ping().call().unwrap();
+    assert_eq!("old_ping", request["method"]);
+    assert_eq!("1.0", request["jsonrpc"]);
+    assert_eq!(
+        1,
+        jsonrpc_client_core::deprecation::deprecated_call_count("old_ping")
+    );
+}
+
+#[test]
+fn openrpc_document() {
+    let document: JsonValue = serde_json::from_str(echo_openrpc()).unwrap();
+    assert_eq!("Echo", document["info"]["title"]);
+    assert_eq!("An API echoing requests.", document["info"]["description"]);
+    let look_up = &document["methods"][1];
+    assert_eq!("test_lookUp", look_up["name"]);
+    assert_eq!(
+        json!([
+            {"name": "userId", "required": true, "schema": {"type": "string"}},
+            {"name": "limit", "required": true, "schema": {"type": "integer", "minimum": 0}},
+        ]),
+        look_up["params"]
+    );
+    assert_eq!(json!({}), look_up["result"]["schema"]);
+    assert_eq!("Echoes the request.", document["methods"][0]["description"]);
+}
+
+#[test]
+fn trait_object() {
+    struct MockEcho;
+
+    impl Echo for MockEcho {
+        fn ping(&mut self, value: u64) -> RpcFuture<JsonValue> {
+            Box::new(futures::future::ok(json!(value)))
+        }
+
+        fn look_up(&mut self, _user_id: &str, _limit: u64) -> RpcFuture<JsonValue> {
+            unimplemented!();
+        }
+
+        fn nothing(&self) -> RpcFuture<JsonValue> {
+            unimplemented!();
+        }
+    }
+
+    let mut clients: Vec<Box<Echo>> = vec![
+        Box::new(EchoClient::new(EchoTransport)),
+        Box::new(MockEcho),
+    ];
+    assert_eq!("test_ping", clients[0].ping(3).wait().unwrap()["method"]);
+    assert_eq!(json!(3), clients[1].ping(3).wait().unwrap());
+}
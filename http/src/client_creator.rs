@@ -9,7 +9,7 @@ pub trait ClientCreator: Send + 'static {
     type Connect: Connect;
 
     /// The error emitted by this type in case creating the `Client` failed.
-    type Error: ::std::error::Error + Send;
+    type Error: ::std::error::Error + Send + Sync + 'static;
 
     /// Tries to create a Hyper `Client` based on the given Tokio `Handle`.
     fn create(&self, handle: &Handle) -> Result<Client<Self::Connect, Body>, Self::Error>;
@@ -32,7 +32,7 @@ impl ClientCreator for DefaultClient {
 impl<C, E, F> ClientCreator for F
 where
     C: Connect,
-    E: ::std::error::Error + Send,
+    E: ::std::error::Error + Send + Sync + 'static,
     F: Fn(&Handle) -> Result<Client<C, Body>, E>,
     F: Send + 'static,
 {
@@ -0,0 +1,170 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Gzip compression of request bodies.
+//!
+//! Requests like `sendrawtransaction` with a large hex payload, or big batches, shrink a lot
+//! when compressed. A [`RequestCompression`] gzips request bodies above a minimum size and sends
+//! them with `Content-Encoding: gzip`. Since servers not supporting compressed requests reject
+//! them, it can wait for the server to advertise support with an `Accept-Encoding` header
+//! listing gzip in a response before compressing anything.
+//!
+//! [`RequestCompression`]: struct.RequestCompression.html
+
+use hyper::header::{ContentEncoding, Encoding, Headers};
+use jsonrpc_client_core::metadata::ResponseMetadata;
+use miniz_oxide::deflate::compress_to_vec;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The size of the smallest body compressed by default, in bytes.
+const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// The compression level of miniz, from 0 to 10.
+const LEVEL: u8 = 6;
+
+
+/// Gzip compression of request bodies. Given to
+/// [`HttpHandle::set_request_compression`](struct.HttpHandle.html#method.set_request_compression).
+/// Clones share what is known about the support of the server.
+#[derive(Debug, Clone)]
+pub struct RequestCompression {
+    min_size: usize,
+    when_advertised: bool,
+    advertised: Arc<AtomicBool>,
+}
+
+impl RequestCompression {
+    /// Compresses all request bodies of at least 1 KiB with gzip.
+    pub fn gzip() -> Self {
+        RequestCompression {
+            min_size: DEFAULT_MIN_SIZE,
+            when_advertised: false,
+            advertised: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Sets the size in bytes of the smallest body that is compressed. Defaults to 1 KiB.
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Only compresses requests once the server has advertised support for gzip compressed
+    /// requests, by sending an `Accept-Encoding` header listing gzip in a response.
+    pub fn when_advertised(mut self) -> Self {
+        self.when_advertised = true;
+        self
+    }
+
+    /// Returns true if the response metadata of a request is needed to tell if the server
+    /// supports compression.
+    pub(crate) fn needs_response(&self) -> bool {
+        self.when_advertised && !self.advertised.load(Ordering::SeqCst)
+    }
+
+    /// Learns from `response` if the server supports compressed requests.
+    pub(crate) fn observe(&self, response: &ResponseMetadata) {
+        let advertised = response.header("Accept-Encoding").map_or(false, |encodings| {
+            encodings
+                .split(',')
+                .map(|encoding| encoding.split(';').next().unwrap_or("").trim())
+                .any(|encoding| encoding.eq_ignore_ascii_case("gzip"))
+        });
+        if advertised && !self.advertised.swap(true, Ordering::SeqCst) {
+            debug!("Server supports gzip compressed requests");
+        }
+    }
+
+    /// Compresses `body` if it is large enough and the server supports it, setting the
+    /// `Content-Encoding` header. Returns the body to send.
+    pub(crate) fn compress(&self, body: Vec<u8>, headers: &mut Headers) -> Vec<u8> {
+        if body.len() < self.min_size
+            || (self.when_advertised && !self.advertised.load(Ordering::SeqCst))
+        {
+            return body;
+        }
+        let compressed = gzip(&body);
+        trace!("Compressed {} byte request to {} bytes", body.len(), compressed.len());
+        headers.set(ContentEncoding(vec![Encoding::Gzip]));
+        compressed
+    }
+}
+
+
+/// Compresses `data` into the gzip format.
+fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic number, deflate, no flags, no modification time, default level, unknown OS.
+    let mut output = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    output.extend(compress_to_vec(data, LEVEL));
+    output.extend_from_slice(&to_le_bytes(crc32(data)));
+    output.extend_from_slice(&to_le_bytes(data.len() as u32));
+    output
+}
+
+fn to_le_bytes(value: u32) -> [u8; 4] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+/// Computes the CRC-32 checksum of `data`, as used by gzip.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::inflate::decompress_to_vec;
+
+    #[test]
+    fn gzip_format() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+        let data = b"0200000001".repeat(200);
+        let compressed = gzip(&data);
+        assert_eq!(&[0x1f, 0x8b, 8], &compressed[..3]);
+        let trailer = &compressed[compressed.len() - 8..];
+        assert_eq!(&to_le_bytes(crc32(&data)), &trailer[..4]);
+        assert_eq!(&to_le_bytes(2000), &trailer[4..]);
+        let deflated = &compressed[10..compressed.len() - 8];
+        assert_eq!(data, decompress_to_vec(deflated).unwrap());
+    }
+
+    #[test]
+    fn compresses_when_advertised() {
+        let compression = RequestCompression::gzip().min_size(10).when_advertised();
+        let mut headers = Headers::new();
+        assert_eq!(b"short".to_vec(), compression.compress(b"short".to_vec(), &mut headers));
+        let body = b"[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1]".to_vec();
+        assert_eq!(body, compression.compress(body.clone(), &mut headers));
+        assert!(headers.get::<ContentEncoding>().is_none());
+
+        assert!(compression.needs_response());
+        compression.observe(&ResponseMetadata {
+            status: Some(200),
+            headers: vec![("accept-encoding".to_owned(), "deflate, GZIP;q=0.5".to_owned())],
+        });
+        assert!(!compression.needs_response());
+        assert_ne!(body, compression.compress(body.clone(), &mut headers));
+        assert_eq!(
+            Some(&ContentEncoding(vec![Encoding::Gzip])),
+            headers.get::<ContentEncoding>()
+        );
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Basic authentication with credentials read from a cookie file, as written by Bitcoin Core.
+//!
+//! Unless configured with a fixed password, `bitcoind` writes random credentials to a `.cookie`
+//! file in its data directory on every start, as `__cookie__:<password>`. The file is checked
+//! for changes before every request and read again when it has been modified, so requests keep
+//! being authorized after the daemon restarts.
+
+use hyper::header::{Authorization, Basic, Headers};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+
+/// The Bitcoin network a daemon runs on, deciding the subdirectory of its data directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitcoinNetwork {
+    /// The main network, with the cookie directly in the data directory.
+    Mainnet,
+    /// The `testnet3` test network.
+    Testnet,
+    /// The `testnet4` test network.
+    Testnet4,
+    /// The default signet.
+    Signet,
+    /// A local regression test network.
+    Regtest,
+}
+
+impl BitcoinNetwork {
+    /// Returns the subdirectory of the data directory used for the network.
+    fn subdirectory(&self) -> &'static str {
+        match *self {
+            BitcoinNetwork::Mainnet => "",
+            BitcoinNetwork::Testnet => "testnet3",
+            BitcoinNetwork::Testnet4 => "testnet4",
+            BitcoinNetwork::Signet => "signet",
+            BitcoinNetwork::Regtest => "regtest",
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct Cookie {
+    modified: SystemTime,
+    username: String,
+    password: String,
+}
+
+/// Credentials read from a cookie file, for authenticating requests with Basic authentication.
+/// Given to [`HttpHandle::set_cookie_auth`](struct.HttpHandle.html#method.set_cookie_auth).
+#[derive(Debug)]
+pub struct CookieAuth {
+    path: PathBuf,
+    cookie: Mutex<Option<Cookie>>,
+}
+
+impl CookieAuth {
+    /// Reads the credentials from the file at `path`, holding `username:password`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        CookieAuth {
+            path: path.into(),
+            cookie: Mutex::new(None),
+        }
+    }
+
+    /// Reads the credentials from the cookie file of a Bitcoin Core daemon on `network` with the
+    /// data directory `datadir`.
+    pub fn bitcoin<P: AsRef<Path>>(datadir: P, network: BitcoinNetwork) -> Self {
+        Self::new(datadir.as_ref().join(network.subdirectory()).join(".cookie"))
+    }
+
+    /// Reads the credentials from the cookie file of a Bitcoin Core daemon on `network` with the
+    /// default data directory of the platform: `~/.bitcoin` on Linux,
+    /// `~/Library/Application Support/Bitcoin` on macOS and `%APPDATA%\Bitcoin` on Windows.
+    /// Returns `None` if the home directory is unknown.
+    pub fn bitcoin_default(network: BitcoinNetwork) -> Option<Self> {
+        default_bitcoin_datadir().map(|datadir| Self::bitcoin(datadir, network))
+    }
+
+    /// Returns the path of the cookie file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the username and password in the cookie file, reading it again if it has been
+    /// modified since it was last read.
+    pub fn credentials(&self) -> io::Result<(String, String)> {
+        let modified = fs::metadata(&self.path)?.modified()?;
+        let mut cookie = self.cookie.lock().unwrap();
+        let outdated = cookie
+            .as_ref()
+            .map_or(true, |cookie| cookie.modified != modified);
+        if outdated {
+            debug!("Reading cookie file {}", self.path.display());
+            let contents = fs::read_to_string(&self.path)?;
+            let mut parts = contents.trim().splitn(2, ':');
+            let (username, password) = match (parts.next(), parts.next()) {
+                (Some(username), Some(password)) => (username.to_owned(), password.to_owned()),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Cookie file not in the form username:password",
+                    ))
+                }
+            };
+            *cookie = Some(Cookie {
+                modified,
+                username,
+                password,
+            });
+        }
+        let cookie = cookie.as_ref().expect("Cookie read above");
+        Ok((cookie.username.clone(), cookie.password.clone()))
+    }
+
+    /// Sets the `Authorization` header of a request to the credentials in the cookie file. The
+    /// request is sent without credentials if the file cannot be read.
+    pub(crate) fn authorize(&self, headers: &mut Headers) {
+        match self.credentials() {
+            Ok((username, password)) => headers.set(Authorization(Basic {
+                username,
+                password: Some(password),
+            })),
+            Err(e) => warn!("Unable to read cookie file {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn default_bitcoin_datadir() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join("Library")
+            .join("Application Support")
+            .join("Bitcoin")
+    })
+}
+
+#[cfg(windows)]
+fn default_bitcoin_datadir() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(|appdata| PathBuf::from(appdata).join("Bitcoin"))
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn default_bitcoin_datadir() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".bitcoin"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn bitcoin_cookie_paths() {
+        let mainnet = CookieAuth::bitcoin("/data", BitcoinNetwork::Mainnet);
+        assert_eq!(Path::new("/data/.cookie"), mainnet.path());
+        let regtest = CookieAuth::bitcoin("/data", BitcoinNetwork::Regtest);
+        assert_eq!(Path::new("/data/regtest/.cookie"), regtest.path());
+    }
+
+    #[test]
+    fn reads_rotated_cookie() {
+        let path = env::temp_dir().join(format!("jsonrpc-client-cookie-{}", ::std::process::id()));
+        fs::write(&path, "__cookie__:first\n").unwrap();
+        let auth = CookieAuth::new(&path);
+        let expected = ("__cookie__".to_owned(), "first".to_owned());
+        assert_eq!(expected, auth.credentials().unwrap());
+
+        // Makes sure the modification time differs on file systems with coarse timestamps.
+        thread::sleep(Duration::from_millis(1100));
+        fs::write(&path, "__cookie__:second").unwrap();
+        let mut headers = Headers::new();
+        auth.authorize(&mut headers);
+        let Authorization(ref basic) = *headers.get::<Authorization<Basic>>().unwrap();
+        assert_eq!(Some("second"), basic.password.as_ref().map(String::as_str));
+
+        fs::remove_file(&path).unwrap();
+        assert!(auth.credentials().is_err());
+    }
+}
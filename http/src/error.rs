@@ -0,0 +1,151 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use hyper::{self, StatusCode};
+use std::error;
+use std::fmt;
+use std::result;
+use std::time::Duration;
+
+
+/// A specialized `Result` type for the HTTP transport.
+pub type Result<T> = result::Result<T, Error>;
+
+/// The error type of the HTTP transport. Tells what went wrong through its
+/// [`kind`](#method.kind), and keeps the lower level error that caused it, if any, as its
+/// `source`.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    source: Option<Box<error::Error + Send + Sync>>,
+}
+
+/// The different kinds of errors the HTTP transport can fail with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// When there was an error creating the Hyper `Client` from the given creator.
+    ClientCreatorError,
+    /// When the http status code of the response is not 200 OK
+    HttpError(StatusCode),
+    /// When the server is throttling requests, responding with 429 Too Many Requests, or 503
+    /// Service Unavailable with a `Retry-After` header. Holds the status and how long the server
+    /// asked to wait before retrying, if it did.
+    Throttled(StatusCode, Option<Duration>),
+    /// When the request times out.
+    RequestTimeout,
+    /// When there was an error in the Tokio Core.
+    TokioCoreError(&'static str),
+    /// An error occured in Hyper.
+    Hyper,
+    /// The string given was not a valid URI.
+    Uri,
+    /// No token to authenticate the request with could be obtained.
+    AuthError,
+}
+
+impl Error {
+    /// Creates an error of the given kind, caused by `source`.
+    pub fn with_source<E>(kind: ErrorKind, source: E) -> Self
+    where
+        E: Into<Box<error::Error + Send + Sync>>,
+    {
+        Error {
+            kind,
+            source: Some(source.into()),
+        }
+    }
+
+    /// Returns the kind of this error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Returns how long the server asked to wait before retrying, if this error is the server
+    /// throttling requests. Can be given to `RetryPolicy::retry_after` of
+    /// `jsonrpc_client_core::retry`.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self.kind {
+            ErrorKind::Throttled(_, retry_after) => retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error { kind, source: None }
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(error: hyper::Error) -> Self {
+        Error::with_source(ErrorKind::Hyper, error)
+    }
+}
+
+impl From<hyper::error::UriError> for Error {
+    fn from(error: hyper::error::UriError) -> Self {
+        Error::with_source(ErrorKind::Uri, error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::ClientCreatorError => f.write_str("Failed to create the Hyper Client"),
+            ErrorKind::HttpError(http_code) => write!(f, "Http error. Status code {}", http_code),
+            ErrorKind::Throttled(http_code, Some(retry_after)) => write!(
+                f,
+                "Server is throttling requests. Status code {}, retry after {:?}",
+                http_code,
+                retry_after
+            ),
+            ErrorKind::Throttled(http_code, None) => write!(
+                f,
+                "Server is throttling requests. Status code {}",
+                http_code
+            ),
+            ErrorKind::RequestTimeout => f.write_str("Timeout while waiting for a request"),
+            ErrorKind::TokioCoreError(msg) => write!(f, "Error with the Tokio Core: {}", msg),
+            ErrorKind::AuthError => f.write_str("Unable to obtain an authentication token"),
+            // Hyper and URI errors are only wrappers, the source tells what actually went wrong.
+            ErrorKind::Hyper => match self.source {
+                Some(ref source) => source.fmt(f),
+                None => f.write_str("An error occured in Hyper"),
+            },
+            ErrorKind::Uri => match self.source {
+                Some(ref source) => source.fmt(f),
+                None => f.write_str("The string given was not a valid URI"),
+            },
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self.source {
+            Some(ref source) => Some(&**source),
+            None => None,
+        }
+    }
+}
+
+
+/// Extension trait for turning the error of a `Result` into the source of an `Error`.
+pub(crate) trait ResultExt<T> {
+    fn chain_err<F: FnOnce() -> ErrorKind>(self, kind: F) -> Result<T>;
+}
+
+impl<T, E> ResultExt<T> for result::Result<T, E>
+where
+    E: error::Error + Send + Sync + 'static,
+{
+    fn chain_err<F: FnOnce() -> ErrorKind>(self, kind: F) -> Result<T> {
+        self.map_err(|e| Error::with_source(kind(), e))
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Byte streams on raw file descriptors, for devices and sockets the standard library and
+//! tokio-core have no types for.
+
+use libc;
+use mio::{Evented, Poll, PollOpt, Ready, Token};
+use mio::unix::EventedFd;
+use std::io::{self, Read, Write};
+use std::os::unix::io::RawFd;
+
+
+/// A byte stream on a file descriptor it owns, closing it when dropped. Can be registered with
+/// an event loop when the descriptor is in non-blocking mode.
+#[derive(Debug)]
+pub(crate) struct FdStream {
+    fd: RawFd,
+}
+
+impl FdStream {
+    /// Takes ownership of `fd`, failing with the last OS error if it is negative, as returned by
+    /// a failing `open` or `socket`.
+    pub fn new(fd: RawFd) -> io::Result<Self> {
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(FdStream { fd })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Puts the descriptor in non-blocking mode.
+    pub fn set_nonblocking(&self) -> io::Result<()> {
+        unsafe {
+            let flags = check(libc::fcntl(self.fd, libc::F_GETFL))?;
+            check(libc::fcntl(self.fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for FdStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let buf_ptr = buf.as_mut_ptr() as *mut libc::c_void;
+        let read = unsafe { libc::read(self.fd, buf_ptr, buf.len()) };
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(read as usize)
+    }
+}
+
+impl Write for FdStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let buf_ptr = buf.as_ptr() as *const libc::c_void;
+        let written = unsafe { libc::write(self.fd, buf_ptr, buf.len()) };
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Evented for FdStream {
+    fn register(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.fd).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.fd).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        EventedFd(&self.fd).deregister(poll)
+    }
+}
+
+impl Drop for FdStream {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+
+/// Turns the result of a libc call returning -1 on errors into an `io::Result`.
+pub(crate) fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
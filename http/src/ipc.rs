@@ -0,0 +1,112 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Transports for the IPC endpoints of local Ethereum nodes, like geth and OpenEthereum.
+//!
+//! The nodes speak JSON-RPC without HTTP on a Unix domain socket, usually `geth.ipc` in the
+//! data directory, with one JSON message per line. Many calls can be in flight on the socket at
+//! once, so one connection is shared by all calls and responses are routed back by id.
+
+use futures::Future;
+use jsonrpc_client_core::duplex::DuplexTransport;
+use jsonrpc_client_core::framing::{self, Framing, LineCodec};
+use std::io;
+use std::path::Path;
+use tokio_core::reactor::Handle;
+use tokio_uds::UnixStream;
+
+/// The URI scheme of IPC endpoints, as in `ipc:///home/user/.ethereum/geth.ipc`.
+pub const IPC_SCHEME: &str = "ipc";
+
+
+/// Connects to the IPC endpoint `endpoint`, a socket path optionally prefixed with `ipc://`, and
+/// returns a transport multiplexing calls over the connection, with one JSON message per line.
+/// The connection is driven by a task spawned on `handle`, which logs an error and fails all
+/// calls in flight if the connection fails.
+///
+/// Only Unix domain sockets are supported, not Windows named pipes.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate jsonrpc_client_http;
+/// # extern crate tokio_core;
+/// # use jsonrpc_client_http::ipc_transport;
+/// # fn main() {
+/// let core = tokio_core::reactor::Core::new().unwrap();
+/// let transport = ipc_transport("ipc:///home/user/.ethereum/geth.ipc", &core.handle()).unwrap();
+/// # }
+/// ```
+pub fn ipc_transport(endpoint: &str, handle: &Handle) -> io::Result<DuplexTransport> {
+    ipc_transport_framed(endpoint, LineCodec::new(), handle)
+}
+
+/// Connects to the IPC endpoint `endpoint` and returns a transport like the one of
+/// [`ipc_transport`](fn.ipc_transport.html), with messages framed with `framing`.
+pub fn ipc_transport_framed<F>(
+    endpoint: &str,
+    framing: F,
+    handle: &Handle,
+) -> io::Result<DuplexTransport>
+where
+    F: Framing + 'static,
+{
+    let prefix = format!("{}://", IPC_SCHEME);
+    let path = if endpoint.starts_with(&prefix) {
+        &endpoint[prefix.len()..]
+    } else {
+        endpoint
+    };
+    debug!("Connecting to IPC endpoint {}", path);
+    let stream = UnixStream::connect(Path::new(path), handle)?;
+    let (transport, connection) = framing::framed(stream, framing);
+    let path = path.to_owned();
+    handle.spawn(connection.map_err(move |e| error!("IPC connection to {} failed: {}", path, e)));
+    Ok(transport)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_client_core::RpcClient;
+    use std::env;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn calls_over_ipc() {
+        let path = env::temp_dir().join(format!("jsonrpc-client-ipc-{}", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        // Answers every request with its id as the result.
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                let id_start = line.find("\"id\":").unwrap() + 5;
+                let id = line[id_start..].trim_end_matches('}');
+                let response = format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{}}}\n", id, id);
+                writer.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let mut core = Core::new().unwrap();
+        let endpoint = format!("ipc://{}", path.display());
+        let transport = ipc_transport(&endpoint, &core.handle()).unwrap();
+        let mut client = RpcClient::new(transport);
+        let first = client.call::<_, u64>("eth_blockNumber", ());
+        let second = client.call::<_, u64>("eth_chainId", ());
+        assert_eq!((1, 2), core.run(first.join(second)).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
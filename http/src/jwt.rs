@@ -0,0 +1,220 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Bearer authentication with JSON Web Tokens that are refreshed when they expire.
+//!
+//! A [`JwtAuth`] obtains tokens from a refresh function, or by calling a refresh RPC method, and
+//! keeps the current one. A new token is obtained when the `exp` claim of the current one is
+//! about to pass, and when the server rejects it, in which case the rejected call is sent again
+//! with the new token. Concurrent calls needing a new token share one refresh.
+//!
+//! [`JwtAuth`]: struct.JwtAuth.html
+
+use Result;
+use futures::IntoFuture;
+use hyper::header::{Authorization, Bearer, Headers};
+use jsonrpc_client_core::{call_method, ClientConfig, Transport};
+use jsonrpc_client_core::serde::Serialize;
+use serde_json::{self, Value as JsonValue};
+use std::error;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use token::{self, RequestAuth, TokenCache, TokenFuture};
+
+/// How long before its expiry a token is refreshed by default.
+const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+
+/// Bearer authentication with JSON Web Tokens. Given to
+/// [`HttpHandle::set_jwt_auth`](struct.HttpHandle.html#method.set_jwt_auth). Clones share the
+/// current token.
+#[derive(Clone)]
+pub struct JwtAuth {
+    tokens: TokenCache,
+    auth_error_code: Option<i64>,
+}
+
+impl JwtAuth {
+    /// Obtains tokens by calling `refresh`.
+    pub fn new<F, R>(refresh: F) -> Self
+    where
+        F: Fn() -> R + Send + Sync + 'static,
+        R: IntoFuture<Item = String>,
+        R::Future: Send + 'static,
+        R::Error: Into<Box<error::Error + Send + Sync>> + 'static,
+    {
+        let mut tokens = TokenCache::new(refresh, expiry);
+        tokens.refresh_margin = DEFAULT_REFRESH_MARGIN;
+        JwtAuth {
+            tokens,
+            auth_error_code: None,
+        }
+    }
+
+    /// Obtains tokens by calling the RPC method `method` with `params` on `transport`, usually a
+    /// handle to an authentication endpoint without JWT authentication itself. The method must
+    /// return the token as a string.
+    pub fn refresh_method<T, P>(transport: T, method: &str, params: P) -> Self
+    where
+        T: Transport + Send + Sync + 'static,
+        T::Future: Send,
+        P: Serialize + Clone + Send + Sync + 'static,
+    {
+        let method = method.to_owned();
+        Self::new(move || {
+            call_method(&transport, &ClientConfig::default(), method.clone(), params.clone())
+        })
+    }
+
+    /// Sets how long before the expiry of a token a new one is obtained. Defaults to 30 seconds.
+    /// Only tokens with an `exp` claim expire, others are used until the server rejects them.
+    pub fn refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.tokens.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Treats JSON-RPC error responses with the error code `code` as the server rejecting the
+    /// token, in addition to HTTP 401 Unauthorized responses.
+    pub fn auth_error_code(mut self, code: i64) -> Self {
+        self.auth_error_code = Some(code);
+        self
+    }
+}
+
+impl RequestAuth for JwtAuth {
+    fn token(&self) -> TokenFuture {
+        self.tokens.token()
+    }
+
+    fn authorize(&self, token: &str, body: Vec<u8>, headers: &mut Headers) -> Vec<u8> {
+        headers.set(Authorization(Bearer {
+            token: token.to_owned(),
+        }));
+        body
+    }
+
+    fn is_rejection(&self, result: &Result<Vec<u8>>) -> bool {
+        token::is_rejection(result, self.auth_error_code)
+    }
+
+    fn invalidate(&self, token: &str) {
+        self.tokens.invalidate(token)
+    }
+}
+
+impl fmt::Debug for JwtAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Leaves out the token.
+        f.debug_struct("JwtAuth")
+            .field("refresh_margin", &self.tokens.refresh_margin)
+            .field("auth_error_code", &self.auth_error_code)
+            .finish()
+    }
+}
+
+/// Returns when `jwt` expires, according to its `exp` claim.
+fn expiry(jwt: &str) -> Option<SystemTime> {
+    let payload = decode_base64url(jwt.split('.').nth(1)?)?;
+    let claims: JsonValue = serde_json::from_slice(&payload).ok()?;
+    let exp = claims.get("exp")?.as_u64()?;
+    Some(UNIX_EPOCH + Duration::from_secs(exp))
+}
+
+/// Decodes unpadded base64 with the URL safe alphabet, as used in JSON Web Tokens.
+fn decode_base64url(input: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in input.bytes().filter(|&byte| byte != b'=') {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6 | u32::from(value)) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use Error;
+
+    /// Returns an unsigned JWT with the given `exp` claim.
+    fn jwt(exp: u64) -> String {
+        let encode = |input: &[u8]| -> String {
+            const ALPHABET: &[u8] =
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+            let mut output = String::new();
+            for chunk in input.chunks(3) {
+                let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+                let n = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+                for i in 0..chunk.len() + 1 {
+                    output.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+                }
+            }
+            output
+        };
+        format!(
+            "{}.{}.",
+            encode(br#"{"alg":"none"}"#),
+            encode(format!(r#"{{"sub":"user","exp":{}}}"#, exp).as_bytes())
+        )
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn reads_expiry() {
+        assert_eq!(Some(UNIX_EPOCH + Duration::from_secs(1516239022)), expiry(&jwt(1516239022)));
+        assert_eq!(None, expiry("opaque-token"));
+    }
+
+    #[test]
+    fn refreshes_expiring_tokens() {
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let auth = {
+            let refreshes = refreshes.clone();
+            JwtAuth::new(move || {
+                // The first token expires within the refresh margin.
+                let exp = match refreshes.fetch_add(1, Ordering::SeqCst) {
+                    0 => now() + 10,
+                    _ => now() + 3600,
+                };
+                Ok(jwt(exp)) as ::std::result::Result<_, Error>
+            })
+        };
+        let first = auth.token().wait().unwrap();
+        let second = auth.token().wait().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(second, auth.token().wait().unwrap());
+        assert_eq!(2, refreshes.load(Ordering::SeqCst));
+
+        auth.invalidate(&second);
+        auth.token().wait().unwrap();
+        assert_eq!(3, refreshes.load(Ordering::SeqCst));
+    }
+}
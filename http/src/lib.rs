@@ -33,6 +33,131 @@
 //! [`HttpTransport`]: struct.HttpTransport.html
 //! [`HttpTransport::with_tls`]: struct.HttpTransport.html#method.with_tls
 //!
+//! JSON-RPC servers without HTTP are reached over TCP with [`tcp_transport`]. If the
+//! "rustls-tls" feature is enabled, [`tls_transport`] reaches them over TLS with rustls,
+//! optionally trusting self-signed certificates by their pinned fingerprint.
+//!
+//! [`tcp_transport`]: fn.tcp_transport.html
+//! [`tls_transport`]: fn.tls_transport.html
+//!
+//! # Resolving hosts
+//!
+//! A [`TcpConnector`] resolves host names with any [`Resolve`] implementation, like a
+//! [`StaticResolver`] pinning hosts to fixed addresses. HTTP requests are sent through such a
+//! connector by building the `HttpTransport` with a [`TcpClient`]. Both also tune their sockets
+//! with [`SocketOptions`], like disabling Nagle's algorithm or selecting the local address.
+//! Hosts with both IPv6 and IPv4 addresses are connected to with Happy Eyeballs, so a broken
+//! route of one family does not stall connecting.
+//!
+//! [`TcpConnector`]: struct.TcpConnector.html
+//! [`Resolve`]: trait.Resolve.html
+//! [`StaticResolver`]: struct.StaticResolver.html
+//! [`TcpClient`]: struct.TcpClient.html
+//! [`SocketOptions`]: struct.SocketOptions.html
+//!
+//! # Endpoint URLs
+//!
+//! [`transport_from_url`] creates a transport from a URL like
+//! `tcp://node.example.com:50001?timeout=5s`, choosing the transport by the scheme. With
+//! [`Endpoint::from_env`] the URL is read from the `JSONRPC_ENDPOINT` environment variable, so
+//! deployments can switch endpoints and protocols without code changes.
+//!
+//! [`transport_from_url`]: fn.transport_from_url.html
+//! [`Endpoint::from_env`]: struct.Endpoint.html#method.from_env
+//!
+//! # Unix domain sockets
+//!
+//! On Unix platforms the builder returned from [`HttpTransport::unix`] produces a transport that
+//! sends its HTTP requests over Unix domain sockets instead of TCP. This is how many local daemons
+//! expose their APIs (Docker-API style). The socket path is encoded in the URI given to
+//! [`HttpTransport::handle`], use [`unix_uri`] to create such URIs:
+//!
+//! ```rust,no_run
+//! # extern crate jsonrpc_client_http;
+//! # use jsonrpc_client_http::{unix_uri, HttpTransport};
+//! # fn main() {
+//! let transport = HttpTransport::unix().standalone().unwrap();
+//! let handle = transport.handle(&unix_uri("/var/run/daemon.sock", "/rpc")).unwrap();
+//! # }
+//! ```
+//!
+//! [`HttpTransport::unix`]: struct.HttpTransport.html#method.unix
+//! [`HttpTransport::handle`]: struct.HttpTransport.html#method.handle
+//! [`unix_uri`]: fn.unix_uri.html
+//!
+//! # IPC endpoints
+//!
+//! Local Ethereum nodes like geth serve JSON-RPC on a Unix domain socket without HTTP.
+//! [`ipc_transport`] connects to such an `ipc://` endpoint and returns a transport multiplexing
+//! all calls over the one connection.
+//!
+//! [`ipc_transport`]: fn.ipc_transport.html
+//!
+//! # Cookie authentication
+//!
+//! Bitcoin Core and similar daemons write random credentials to a cookie file on every start.
+//! [`HttpHandle::set_cookie_auth`] authenticates requests with the credentials in such a file,
+//! reading it again when the daemon restarts and rotates them:
+//!
+//! ```rust,no_run
+//! # extern crate jsonrpc_client_http;
+//! # use jsonrpc_client_http::{BitcoinNetwork, CookieAuth, HttpTransport};
+//! # fn main() {
+//! let transport = HttpTransport::new().standalone().unwrap();
+//! let mut handle = transport.handle("http://127.0.0.1:18443").unwrap();
+//! handle.set_cookie_auth(CookieAuth::bitcoin_default(BitcoinNetwork::Regtest).unwrap());
+//! # }
+//! ```
+//!
+//! [`HttpHandle::set_cookie_auth`]: struct.HttpHandle.html#method.set_cookie_auth
+//!
+//! # JWT authentication
+//!
+//! [`HttpHandle::set_jwt_auth`] sends a JSON Web Token as bearer token with every request. The
+//! [`JwtAuth`] given to it obtains tokens from a closure, or by calling a refresh method, and
+//! replaces them shortly before they expire. A call rejected because of its token is sent once
+//! more with a new one, so callers never see an expired token fail their call.
+//!
+//! [`HttpHandle::set_jwt_auth`]: struct.HttpHandle.html#method.set_jwt_auth
+//! [`JwtAuth`]: struct.JwtAuth.html
+//!
+//! # Session login
+//!
+//! APIs like those of Zabbix or aria2 instead hand out a session token from a login method, to be
+//! sent in a header, in the params or next to the method of every later request.
+//! [`HttpHandle::set_session_auth`] logs in before the first call with a [`SessionAuth`], adds
+//! the token where its [`TokenPlacement`] tells, and logs in again when the session has ended.
+//!
+//! [`HttpHandle::set_session_auth`]: struct.HttpHandle.html#method.set_session_auth
+//! [`SessionAuth`]: struct.SessionAuth.html
+//! [`TokenPlacement`]: enum.TokenPlacement.html
+//!
+//! # Request signing
+//!
+//! Many exchange-style APIs require every request to be signed with an HMAC of its content,
+//! often together with a timestamp and a nonce. With the "signing" feature enabled,
+//! [`HttpHandle::set_request_signer`] signs all requests with a [`RequestSigner`], putting the
+//! signature either in headers or in the params of the request.
+//!
+//! [`HttpHandle::set_request_signer`]: struct.HttpHandle.html#method.set_request_signer
+//! [`RequestSigner`]: struct.RequestSigner.html
+//!
+//! # Request compression
+//!
+//! With the "compression" feature enabled, [`HttpHandle::set_request_compression`] gzips large
+//! request bodies with a [`RequestCompression`], either always or once the server has
+//! advertised support for compressed requests.
+//!
+//! [`HttpHandle::set_request_compression`]: struct.HttpHandle.html#method.set_request_compression
+//! [`RequestCompression`]: struct.RequestCompression.html
+//!
+//! # Cancellation
+//!
+//! Dropping the future returned from a send operation, or the `RpcRequest` wrapping it, before it
+//! completes aborts the HTTP request. The connection it was using is closed and the transport
+//! moves on to the next request. This makes it safe to put timeouts or other cancellation logic
+//! around RPC calls from the outside.
+//!
 //! # Examples
 //!
 //! See the integration test in `tests/localhost.rs` for code that creates an actual HTTP server
@@ -66,67 +191,156 @@
 
 #![deny(missing_docs)]
 
-#[macro_use]
-extern crate error_chain;
 extern crate futures;
 extern crate hyper;
 extern crate jsonrpc_client_core;
 #[macro_use]
 extern crate log;
+extern crate net2;
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
 extern crate tokio_core;
+extern crate tokio_io;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(unix)]
+extern crate mio;
+#[cfg(unix)]
+extern crate tokio_uds;
 
 #[cfg(feature = "tls")]
 extern crate hyper_tls;
 #[cfg(feature = "tls")]
 extern crate native_tls;
 
+#[cfg(feature = "rustls-tls")]
+extern crate ring;
+#[cfg(feature = "rustls-tls")]
+extern crate rustls;
+#[cfg(feature = "rustls-tls")]
+extern crate webpki;
+#[cfg(feature = "rustls-tls")]
+extern crate webpki_roots;
+
+#[cfg(feature = "signing")]
+extern crate hmac;
+#[cfg(feature = "signing")]
+extern crate sha2;
+
+#[cfg(feature = "compression")]
+extern crate miniz_oxide;
+
 use futures::{Async, Future, Poll, Stream};
 use futures::future::{self, Either, Select2};
 use futures::sync::{mpsc, oneshot};
-use hyper::{Client, Request, StatusCode, Uri};
+use hyper::{Client, Request, Uri};
 pub use hyper::header;
 use jsonrpc_client_core::Transport;
+use jsonrpc_client_core::streaming::StreamingTransport;
+use jsonrpc_client_core::metadata::{CallMetadata, ResponseMetadata, ResponseSlot};
+use std::fmt;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio_core::reactor::{Core, Timeout};
 pub use tokio_core::reactor::Handle;
 
 mod client_creator;
 pub use client_creator::*;
 
-error_chain! {
-    errors {
-        /// When there was an error creating the Hyper `Client` from the given creator.
-        ClientCreatorError {
-            description("Failed to create the Hyper Client")
-        }
-        /// When the http status code of the response is not 200 OK
-        HttpError(http_code: StatusCode) {
-            description("Http error. Server did not return 200 OK")
-            display("Http error. Status code {}", http_code)
-        }
-        /// When the request times out.
-        RequestTimeout {
-            description("Timeout while waiting for a request")
-        }
-        /// When there was an error in the Tokio Core.
-        TokioCoreError(msg: &'static str) {
-            description("Error with the Tokio Core")
-            display("Error with the Tokio Core: {}", msg)
-        }
-    }
-    foreign_links {
-        Hyper(hyper::Error) #[doc = "An error occured in Hyper."];
-        Uri(hyper::error::UriError) #[doc = "The string given was not a valid URI."];
-    }
-}
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use unix::*;
+
+#[cfg(unix)]
+mod ipc;
+#[cfg(unix)]
+pub use ipc::*;
+
+#[cfg(unix)]
+mod fd;
+
+#[cfg(unix)]
+mod serial;
+#[cfg(unix)]
+pub use serial::*;
+
+#[cfg(unix)]
+mod ssh;
+#[cfg(unix)]
+pub use ssh::*;
+
+#[cfg(unix)]
+mod vsock;
+#[cfg(unix)]
+pub use vsock::*;
+
+mod resolve;
+pub use resolve::*;
+
+mod socket;
+pub use socket::{Keepalive, SocketOptions};
+
+mod tcp;
+pub use tcp::*;
+
+#[cfg(feature = "rustls-tls")]
+mod tls;
+#[cfg(feature = "rustls-tls")]
+pub use tls::*;
+
+mod endpoint;
+pub use endpoint::{transport_from_url, Endpoint, EndpointFuture, EndpointTransport,
+                   ENDPOINT_VAR};
 
+mod cookie;
+pub use cookie::{BitcoinNetwork, CookieAuth};
 
-type CoreSender = mpsc::UnboundedSender<(Request, oneshot::Sender<Result<Vec<u8>>>)>;
-type CoreReceiver = mpsc::UnboundedReceiver<(Request, oneshot::Sender<Result<Vec<u8>>>)>;
+mod jwt;
+pub use jwt::JwtAuth;
+
+mod session;
+pub use session::{SessionAuth, TokenPlacement};
+
+mod token;
+pub use token::RefreshFuture;
+use token::RequestAuth;
+
+#[cfg(feature = "signing")]
+mod signing;
+#[cfg(feature = "signing")]
+pub use signing::{HmacAlgorithm, RequestSigner, SignaturePlacement};
+
+#[cfg(feature = "compression")]
+mod compression;
+#[cfg(feature = "compression")]
+pub use compression::RequestCompression;
+
+mod streaming;
+pub use streaming::{EventStream, ResponseBody};
+
+mod error;
+pub use error::{Error, ErrorKind, Result};
+use error::ResultExt;
+
+
+/// A request for the event loop, with the slot to store the metadata of its response in and where
+/// to send the response.
+type CoreMessage = (Request, Option<ResponseSlot>, Responder);
+type CoreSender = mpsc::UnboundedSender<CoreMessage>;
+type CoreReceiver = mpsc::UnboundedReceiver<CoreMessage>;
+
+/// Where the event loop sends the response to a request.
+#[derive(Debug)]
+enum Responder {
+    /// The channel to send the whole body back on, once it has arrived.
+    Buffered(oneshot::Sender<Result<Vec<u8>>>),
+    /// The channel to send the body back on as soon as it starts arriving.
+    Streaming(oneshot::Sender<Result<ResponseBody>>),
+}
 
 
 /// The main struct of the HTTP transport implementation for
@@ -169,6 +383,16 @@ impl HttpTransport {
         HttpTransportBuilder::with_client(DefaultTlsClient)
     }
 
+    /// Returns a builder to create a `HttpTransport` sending its requests over Unix domain
+    /// sockets.
+    ///
+    /// Handles created from this transport must be given `http+unix` URIs, as produced by
+    /// [`unix_uri`](fn.unix_uri.html).
+    #[cfg(unix)]
+    pub fn unix() -> HttpTransportBuilder<UnixClient> {
+        HttpTransportBuilder::with_client(UnixClient)
+    }
+
     /// Returns a handle to this `HttpTransport` valid for a given URI.
     ///
     /// Used to create instances implementing `jsonrpc_client_core::Transport` for use with RPC
@@ -180,6 +404,14 @@ impl HttpTransport {
             uri,
             id: self.id.clone(),
             headers: header::Headers::new(),
+            header_hook: None,
+            cookie_auth: None,
+            auth: None,
+            #[cfg(feature = "signing")]
+            request_signer: None,
+            #[cfg(feature = "compression")]
+            request_compression: None,
+            trace_id_header: DEFAULT_TRACE_ID_HEADER.to_owned(),
         })
     }
 }
@@ -193,6 +425,7 @@ impl HttpTransport {
 pub struct HttpTransportBuilder<C: ClientCreator> {
     client_creator: C,
     timeout: Option<Duration>,
+    error_bodies: bool,
 }
 
 impl<C: ClientCreator> HttpTransportBuilder<C> {
@@ -217,6 +450,7 @@ impl<C: ClientCreator> HttpTransportBuilder<C> {
         HttpTransportBuilder {
             client_creator,
             timeout: None,
+            error_bodies: false,
         }
     }
 
@@ -226,13 +460,27 @@ impl<C: ClientCreator> HttpTransportBuilder<C> {
         self
     }
 
+    /// Hand the body of responses with a status other than 200 OK back to the client when it is
+    /// a JSON-RPC error response, instead of failing with `ErrorKind::HttpError`. The call then
+    /// fails with the JSON-RPC error the server sent, for servers answering errors with statuses
+    /// like 500 Internal Server Error. Responses with other bodies, and all 401 Unauthorized
+    /// responses, still fail with `ErrorKind::HttpError`. Off by default.
+    pub fn error_bodies(mut self, error_bodies: bool) -> Self {
+        self.error_bodies = error_bodies;
+        self
+    }
+
     /// Creates the final `HttpTransport` backed by its own Tokio `Core` running in a separate
     /// thread that is exclusive to this transport instance. To make the transport run on an
     /// existing event loop, use the [`shared`](#method.shared) method instead.
     pub fn standalone(self) -> Result<HttpTransport> {
         let (tx, rx) = ::std::sync::mpsc::channel();
         thread::spawn(
-            move || match create_standalone_core(self.client_creator, self.timeout) {
+            move || match create_standalone_core(
+                self.client_creator,
+                self.timeout,
+                self.error_bodies,
+            ) {
                 Err(e) => {
                     tx.send(Err(e)).unwrap();
                 }
@@ -260,6 +508,7 @@ impl<C: ClientCreator> HttpTransportBuilder<C> {
             request_rx,
             client,
             self.timeout,
+            self.error_bodies,
             handle.clone(),
         ));
         Ok(Self::build(request_tx))
@@ -327,6 +576,7 @@ impl<F: Future<Error = Error>> Future for TimeLimited<F> {
 fn create_standalone_core<C: ClientCreator>(
     client_creator: C,
     timeout: Option<Duration>,
+    error_bodies: bool,
 ) -> Result<(Core, CoreSender, Box<Future<Item = (), Error = ()>>)> {
     let core = Core::new().chain_err(|| ErrorKind::TokioCoreError("Unable to create"))?;
     let handle = core.handle();
@@ -334,7 +584,8 @@ fn create_standalone_core<C: ClientCreator>(
         .create(&handle)
         .chain_err(|| ErrorKind::ClientCreatorError)?;
     let (request_tx, request_rx) = mpsc::unbounded();
-    let future = create_request_processing_future(request_rx, client, timeout, handle);
+    let future =
+        create_request_processing_future(request_rx, client, timeout, error_bodies, handle);
     Ok((core, request_tx, future))
 }
 
@@ -344,41 +595,165 @@ fn create_request_processing_future<CC: hyper::client::Connect>(
     request_rx: CoreReceiver,
     client: Client<CC, hyper::Body>,
     timeout: Option<Duration>,
+    error_bodies: bool,
     handle: Handle,
 ) -> Box<Future<Item = (), Error = ()>> {
-    let f = request_rx.for_each(move |(request, response_tx)| {
+    let f = request_rx.for_each(move |(request, response_slot, responder)| {
         trace!("Sending request to {}", request.uri());
         let request = client.request(request).from_err();
 
-        TimeLimited::new(request, timeout, &handle)
-            .and_then(|response: hyper::Response| {
-                if response.status() == hyper::StatusCode::Ok {
-                    future::ok(response)
-                } else {
-                    future::err(ErrorKind::HttpError(response.status()).into())
+        let body_future = TimeLimited::new(request, timeout, &handle)
+            .and_then(move |response: hyper::Response| {
+                if let Some(response_slot) = response_slot {
+                    response_slot.set(response_metadata(&response));
                 }
-            })
-            .and_then(|response: hyper::Response| response.body().concat2().from_err())
-            .map(|response_chunk| response_chunk.to_vec())
-            .then(move |response_result| {
-                if let Err(_) = response_tx.send(response_result) {
-                    warn!("Unable to send response back to caller");
-                }
-                Ok(())
-            })
+                accept_response(response, error_bodies)
+            });
+        match responder {
+            Responder::Buffered(response_tx) => Either::A(RespondOrCancel {
+                future: body_future.and_then(|body| {
+                    body.concat2().from_err().map(|response_chunk| response_chunk.to_vec())
+                }),
+                response_tx: Some(response_tx),
+            }),
+            Responder::Streaming(response_tx) => {
+                let handle = handle.clone();
+                Either::B(RespondOrCancel {
+                    future: body_future.map(move |body| ResponseBody::forward(body, &handle)),
+                    response_tx: Some(response_tx),
+                })
+            }
+        }
     });
     Box::new(f) as Box<Future<Item = (), Error = ()>>
 }
 
+/// Returns the body of `response` if it is successful, or if it is a JSON-RPC error response
+/// and `error_bodies` is set. Returns the error for the response otherwise.
+fn accept_response(
+    response: hyper::Response,
+    error_bodies: bool,
+) -> Box<Future<Item = hyper::Body, Error = Error>> {
+    let status = response.status();
+    if let Some(error) = throttling(&response) {
+        return Box::new(future::err(error.into()));
+    }
+    if status == hyper::StatusCode::Ok {
+        return Box::new(future::ok(response.body()));
+    }
+    // Unauthorized responses stay errors, the token authentication retries on them.
+    if !error_bodies || status == hyper::StatusCode::Unauthorized {
+        return Box::new(future::err(ErrorKind::HttpError(status).into()));
+    }
+    Box::new(response.body().concat2().from_err().and_then(move |response_chunk| {
+        if is_error_response(&response_chunk) {
+            Ok(hyper::Body::from(response_chunk))
+        } else {
+            Err(ErrorKind::HttpError(status).into())
+        }
+    }))
+}
+
+/// Returns the metadata of `response` for the caller.
+fn response_metadata(response: &hyper::Response) -> ResponseMetadata {
+    let headers = response
+        .headers()
+        .iter()
+        .map(|header| (header.name().to_owned(), header.value_string()))
+        .collect();
+    ResponseMetadata {
+        status: Some(response.status().as_u16()),
+        headers,
+    }
+}
+
+/// Returns the error for `response` if it tells that the server is throttling requests.
+fn throttling(response: &hyper::Response) -> Option<ErrorKind> {
+    let retry_after = response
+        .headers()
+        .get::<header::RetryAfter>()
+        .map(|retry_after| match *retry_after {
+            header::RetryAfter::Delay(delay) => delay,
+            header::RetryAfter::DateTime(date) => SystemTime::from(date)
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::new(0, 0)),
+        });
+    match response.status() {
+        hyper::StatusCode::TooManyRequests => (),
+        hyper::StatusCode::ServiceUnavailable if retry_after.is_some() => (),
+        _ => return None,
+    }
+    Some(ErrorKind::Throttled(response.status(), retry_after))
+}
+
+/// Returns true if `body` is a JSON-RPC error response, or a batch of them.
+fn is_error_response(body: &[u8]) -> bool {
+    fn has_error(response: &serde_json::Value) -> bool {
+        response.get("error").map_or(false, |error| error.is_object())
+    }
+
+    match serde_json::from_slice(body) {
+        Ok(serde_json::Value::Array(ref batch)) => !batch.is_empty() && batch.iter().all(has_error),
+        Ok(ref response) => has_error(response),
+        Err(_) => false,
+    }
+}
+
+/// Drives a request and sends the result back to the caller. Drops the request, thus aborting it,
+/// if the caller stops waiting for the response.
+struct RespondOrCancel<F: Future> {
+    future: F,
+    response_tx: Option<oneshot::Sender<Result<F::Item>>>,
+}
+
+impl<F: Future<Error = Error>> Future for RespondOrCancel<F> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        {
+            let response_tx = self.response_tx
+                .as_mut()
+                .expect("RespondOrCancel polled after completion");
+            if let Ok(Async::Ready(())) = response_tx.poll_cancel() {
+                debug!("Caller dropped the request, aborting it");
+                return Ok(Async::Ready(()));
+            }
+        }
+        let response_result = match self.future.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(response)) => Ok(response),
+            Err(e) => Err(e),
+        };
+        if let Err(_) = self.response_tx.take().unwrap().send(response_result) {
+            warn!("Unable to send response back to caller");
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+type HeaderHook = Arc<Fn(&mut header::Headers) + Send + Sync>;
+
+/// The header the trace id of a call is sent in by default.
+const DEFAULT_TRACE_ID_HEADER: &str = "X-Trace-Id";
+
 /// A handle to a [`HttpTransport`](struct.HttpTransport.html). This implements
 /// `jsonrpc_client_core::Transport` and can be used as the transport for a RPC client generated
 /// by the `jsonrpc_client!` macro.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct HttpHandle {
     request_tx: CoreSender,
     uri: Uri,
     id: Arc<AtomicUsize>,
     headers: header::Headers,
+    header_hook: Option<HeaderHook>,
+    cookie_auth: Option<Arc<CookieAuth>>,
+    auth: Option<Arc<RequestAuth>>,
+    #[cfg(feature = "signing")]
+    request_signer: Option<Arc<RequestSigner>>,
+    #[cfg(feature = "compression")]
+    request_compression: Option<RequestCompression>,
+    trace_id_header: String,
 }
 
 impl HttpHandle {
@@ -391,40 +766,201 @@ impl HttpHandle {
         self
     }
 
-    /// Creates a Hyper POST request with JSON content type and the given body data.
-    fn create_request(&self, body: Vec<u8>) -> Request {
+    /// Configure a function that is called with the headers of every request sent through this
+    /// transport, right before it is sent. Runs after the headers given to
+    /// [`set_header`](#method.set_header) have been applied.
+    ///
+    /// The function is called in the context of the call, so it can be used to propagate the
+    /// current trace context, for example by injecting the W3C `traceparent` header.
+    pub fn set_header_hook<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&mut header::Headers) + Send + Sync + 'static,
+    {
+        self.header_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets the header the trace id of calls made with
+    /// [`CallMetadata`](../jsonrpc_client_core/metadata/struct.CallMetadata.html) is sent in.
+    /// Defaults to `X-Trace-Id`.
+    pub fn set_trace_id_header<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.trace_id_header = name.into();
+        self
+    }
+
+    /// Authenticate all requests sent through this transport with the credentials in a cookie
+    /// file, like the one Bitcoin Core writes on every start. The file is read again whenever it
+    /// changes. The `Authorization` header is set before the header hook runs.
+    pub fn set_cookie_auth(&mut self, cookie_auth: CookieAuth) -> &mut Self {
+        self.cookie_auth = Some(Arc::new(cookie_auth));
+        self
+    }
+
+    /// Authenticate all requests sent through this transport with a JSON Web Token in the
+    /// `Authorization` header. A new token is obtained from `jwt_auth` when the current one is
+    /// about to expire, and when the server rejects it, in which case the request is sent once
+    /// more with the new token. The header is set before the header hook runs.
+    ///
+    /// Replaces any session authentication set with
+    /// [`set_session_auth`](#method.set_session_auth).
+    pub fn set_jwt_auth(&mut self, jwt_auth: JwtAuth) -> &mut Self {
+        self.auth = Some(Arc::new(jwt_auth));
+        self
+    }
+
+    /// Authenticate all requests sent through this transport with a session token, obtained by
+    /// logging in before the first request. When the server rejects the token, `session_auth`
+    /// logs in again and the request is sent once more with the new token. The token is added
+    /// before requests are signed.
+    ///
+    /// Replaces any JWT authentication set with [`set_jwt_auth`](#method.set_jwt_auth).
+    pub fn set_session_auth(&mut self, session_auth: SessionAuth) -> &mut Self {
+        self.auth = Some(Arc::new(session_auth));
+        self
+    }
+
+    /// Sign all requests sent through this transport with an HMAC of their content. Requires the
+    /// "signing" feature. The signature headers are set before any other header, so the header
+    /// hook sees them.
+    #[cfg(feature = "signing")]
+    pub fn set_request_signer(&mut self, request_signer: RequestSigner) -> &mut Self {
+        self.request_signer = Some(Arc::new(request_signer));
+        self
+    }
+
+    /// Compress the bodies of requests sent through this transport as `request_compression`
+    /// says. Requires the "compression" feature. Bodies are compressed after they are signed.
+    #[cfg(feature = "compression")]
+    pub fn set_request_compression(&mut self, compression: RequestCompression) -> &mut Self {
+        self.request_compression = Some(compression);
+        self
+    }
+
+    /// Creates a Hyper POST request with JSON content type and the given body data, bearing the
+    /// authentication token `token` and the headers of the call `metadata` if given.
+    fn create_request(
+        &self,
+        body: Vec<u8>,
+        token: Option<&str>,
+        metadata: Option<&CallMetadata>,
+    ) -> Request {
         let mut request = hyper::Request::new(hyper::Method::Post, self.uri.clone());
-        {
+        let body = {
             let headers = request.headers_mut();
+            let body = match (&self.auth, token) {
+                (&Some(ref auth), Some(token)) => auth.authorize(token, body, headers),
+                _ => body,
+            };
+            #[cfg(feature = "signing")]
+            let body = match self.request_signer {
+                Some(ref request_signer) => request_signer.sign(body, headers),
+                None => body,
+            };
+            #[cfg(feature = "compression")]
+            let body = match self.request_compression {
+                Some(ref request_compression) => request_compression.compress(body, headers),
+                None => body,
+            };
             headers.set(hyper::header::ContentType::json());
             headers.set(hyper::header::ContentLength(body.len() as u64));
-            headers.extend(self.headers.iter());
-        }
+            self.set_handle_headers(headers, metadata);
+            body
+        };
         request.set_body(body);
         request
     }
-}
 
-impl Transport for HttpHandle {
-    type Future = Box<Future<Item = Vec<u8>, Error = Self::Error> + Send>;
-    type Error = Error;
+    /// Sets the custom headers, cookie authentication and headers of the call `metadata` if
+    /// given, then runs the header hook.
+    fn set_handle_headers(&self, headers: &mut header::Headers, metadata: Option<&CallMetadata>) {
+        headers.extend(self.headers.iter());
+        if let Some(ref cookie_auth) = self.cookie_auth {
+            cookie_auth.authorize(headers);
+        }
+        if let Some(metadata) = metadata {
+            self.set_metadata_headers(metadata, headers);
+        }
+        if let Some(ref header_hook) = self.header_hook {
+            header_hook(headers);
+        }
+    }
 
-    fn get_next_id(&mut self) -> u64 {
-        self.id.fetch_add(1, Ordering::SeqCst) as u64
+    /// Opens a server-sent events stream with a GET request to the URI of this handle, for
+    /// receiving notifications with an
+    /// [`SseTransport`](../jsonrpc_client_core/sse/struct.SseTransport.html). The request is sent
+    /// when the stream is first polled, with the custom headers, cookie authentication and header
+    /// hook of this handle. The timeout of the transport only applies until the response arrives,
+    /// not to the stream.
+    pub fn event_stream(&self) -> EventStream {
+        let mut request = hyper::Request::new(hyper::Method::Get, self.uri.clone());
+        request
+            .headers_mut()
+            .set_raw("Accept", "text/event-stream");
+        self.set_handle_headers(request.headers_mut(), CallMetadata::current().as_ref());
+        let handle = self.clone();
+        let body = future::lazy(move || handle.send_to_core(request, None, Responder::Streaming));
+        Box::new(body.flatten_stream())
     }
 
-    fn send(&self, json_data: Vec<u8>) -> Self::Future {
-        let request = self.create_request(json_data);
+    /// Sets the headers and trace id of `metadata`, replacing headers of the same names.
+    fn set_metadata_headers(&self, metadata: &CallMetadata, headers: &mut header::Headers) {
+        for &(ref name, _) in metadata.headers() {
+            headers.remove_raw(name);
+        }
+        for &(ref name, ref value) in metadata.headers() {
+            headers.append_raw(name.clone(), value.clone());
+        }
+        if let Some(trace_id) = metadata.get_trace_id() {
+            headers.set_raw(self.trace_id_header.clone(), trace_id.to_owned());
+        }
+    }
+
+    /// Sends `request` to the event loop and returns a future of its response, storing the
+    /// metadata of the response in `response_slot` if given.
+    fn send_request(
+        &self,
+        request: Request,
+        response_slot: Option<ResponseSlot>,
+    ) -> Box<Future<Item = Vec<u8>, Error = Error> + Send> {
+        #[cfg(feature = "compression")]
+        let response_slot = match self.request_compression {
+            // Looks for the server advertising support for compressed requests.
+            Some(ref request_compression) if request_compression.needs_response() => {
+                let response_slot = response_slot.unwrap_or_default();
+                let future =
+                    self.send_to_core(request, Some(response_slot.clone()), Responder::Buffered);
+                let request_compression = request_compression.clone();
+                return Box::new(future.then(move |result| {
+                    if let Some(response) = response_slot.get() {
+                        request_compression.observe(&response);
+                    }
+                    result
+                }));
+            }
+            _ => response_slot,
+        };
+        self.send_to_core(request, response_slot, Responder::Buffered)
+    }
+
+    /// Hands `request` to the event loop and returns a future of its response, sent back as
+    /// `responder` tells.
+    fn send_to_core<T: Send + 'static>(
+        &self,
+        request: Request,
+        response_slot: Option<ResponseSlot>,
+        responder: fn(oneshot::Sender<Result<T>>) -> Responder,
+    ) -> Box<Future<Item = T, Error = Error> + Send> {
         let (response_tx, response_rx) = oneshot::channel();
-        let future = future::result(self.request_tx.unbounded_send((request, response_tx)))
-            .map_err(|e| {
-                Error::with_chain(e, ErrorKind::TokioCoreError("Not listening for requests"))
-            })
+        let message = (request, response_slot, responder(response_tx));
+        let future = future::result(self.request_tx.unbounded_send(message))
+            // The error only carries the unsent request, which is not `Sync` and thus can't be
+            // kept as the source.
+            .map_err(|_| Error::from(ErrorKind::TokioCoreError("Not listening for requests")))
             .and_then(move |_| {
                 response_rx.map_err(|e| {
-                    Error::with_chain(
-                        e,
+                    Error::with_source(
                         ErrorKind::TokioCoreError("Died without returning response"),
+                        e,
                     )
                 })
             })
@@ -433,6 +969,87 @@ impl Transport for HttpHandle {
     }
 }
 
+impl fmt::Debug for HttpHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpHandle")
+            .field("request_tx", &self.request_tx)
+            .field("uri", &self.uri)
+            .field("id", &self.id)
+            .field("headers", &self.headers)
+            .field("header_hook", &self.header_hook.is_some())
+            .field("cookie_auth", &self.cookie_auth.as_ref().map(|auth| auth.path()))
+            .field("auth", &self.auth.is_some())
+            .field("trace_id_header", &self.trace_id_header)
+            .finish()
+    }
+}
+
+impl Transport for HttpHandle {
+    type Future = Box<Future<Item = Vec<u8>, Error = Self::Error> + Send>;
+    type Error = Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        Some(self.uri.to_string())
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let metadata = CallMetadata::current();
+        let response_slot = ResponseSlot::current();
+        let auth = match self.auth {
+            Some(ref auth) => auth.clone(),
+            None => {
+                let request = self.create_request(json_data, None, metadata.as_ref());
+                return self.send_request(request, response_slot);
+            }
+        };
+        let handle = self.clone();
+        let future = auth.token().and_then(move |token| {
+            let request = handle.create_request(json_data.clone(), Some(&token), metadata.as_ref());
+            let result = handle.send_request(request, response_slot.clone());
+            result.then(move |result| -> Self::Future {
+                if !auth.is_rejection(&result) {
+                    return Box::new(future::result(result));
+                }
+                // Sends the request once more with a new token.
+                auth.invalidate(&token);
+                Box::new(auth.token().and_then(move |token| {
+                    let request = handle.create_request(json_data, Some(&token), metadata.as_ref());
+                    handle.send_request(request, response_slot)
+                }))
+            })
+        });
+        Box::new(future)
+    }
+}
+
+impl StreamingTransport for HttpHandle {
+    type Chunk = hyper::Chunk;
+    type Body = ResponseBody;
+    type BodyFuture = Box<Future<Item = ResponseBody, Error = Self::Error> + Send>;
+
+    /// Sends the request like `send`, but returns the body of the response as it arrives. Requests
+    /// rejected because of their authentication token are not sent again.
+    fn send_streaming(&self, json_data: Vec<u8>) -> Self::BodyFuture {
+        let metadata = CallMetadata::current();
+        let response_slot = ResponseSlot::current();
+        let token = match self.auth {
+            Some(ref auth) => Either::A(auth.token().map(Some)),
+            None => Either::B(future::ok(None)),
+        };
+        let handle = self.clone();
+        let future = token.and_then(move |token: Option<String>| {
+            let token = token.as_ref().map(String::as_str);
+            let request = handle.create_request(json_data, token, metadata.as_ref());
+            handle.send_to_core(request, response_slot, Responder::Streaming)
+        });
+        Box::new(future)
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
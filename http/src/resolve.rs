@@ -0,0 +1,178 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Resolving host names to the addresses network transports connect to.
+//!
+//! The [`SystemResolver`] asks the operating system, like the standard library does. Hosts can
+//! instead be pinned to fixed addresses with a [`StaticResolver`], for nodes fronted by load
+//! balancers only reachable by IP, or for names that only exist at the other end of a tunnel.
+//! Any other lookup, like DNS over HTTPS, can be plugged in by implementing [`Resolve`].
+//!
+//! [`SystemResolver`]: struct.SystemResolver.html
+//! [`StaticResolver`]: struct.StaticResolver.html
+//! [`Resolve`]: trait.Resolve.html
+
+use futures::Future;
+use futures::future;
+use futures::sync::oneshot;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+
+/// A future resolving to the addresses of a host.
+pub type ResolveFuture = Box<Future<Item = Vec<SocketAddr>, Error = io::Error> + Send>;
+
+
+/// Trait for types resolving host names to socket addresses.
+pub trait Resolve: Send + Sync {
+    /// Returns a future resolving to the addresses of `host`, with `port` as their port. The
+    /// addresses are connected to in the order they are returned.
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture;
+}
+
+impl<R: Resolve + ?Sized> Resolve for Arc<R> {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        (**self).resolve(host, port)
+    }
+}
+
+
+/// Resolves hosts with the resolver of the operating system. Since lookups block, each one runs
+/// on a thread of its own.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        let host = host.to_owned();
+        let (tx, rx) = oneshot::channel();
+        let spawned = thread::Builder::new()
+            .name("jsonrpc-dns".to_owned())
+            .spawn(move || {
+                let addrs = (&host[..], port).to_socket_addrs();
+                let _ = tx.send(addrs.map(|addrs| addrs.collect()));
+            });
+        if let Err(e) = spawned {
+            return Box::new(future::err(e));
+        }
+        Box::new(rx.then(|result| match result {
+            Ok(addrs) => addrs,
+            Err(oneshot::Canceled) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Host lookup thread panicked",
+            )),
+        }))
+    }
+}
+
+
+/// Resolves hosts to fixed addresses, and other hosts with an optional fallback resolver.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate jsonrpc_client_http;
+/// # use jsonrpc_client_http::{StaticResolver, SystemResolver};
+/// # fn main() {
+/// let resolver = StaticResolver::new()
+///     .host("node.example.com", "203.0.113.7".parse().unwrap())
+///     .fallback(SystemResolver);
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    fallback: Option<Arc<Resolve>>,
+}
+
+impl StaticResolver {
+    /// Creates a resolver without any hosts, failing all lookups.
+    pub fn new() -> Self {
+        StaticResolver::default()
+    }
+
+    /// Resolves `host` to `addr`. Adding the same host more than once resolves it to all the
+    /// added addresses, in the order they were added. Host names are not case sensitive.
+    pub fn host<S: AsRef<str>>(mut self, host: S, addr: IpAddr) -> Self {
+        self.hosts
+            .entry(host.as_ref().to_lowercase())
+            .or_default()
+            .push(addr);
+        self
+    }
+
+    /// Resolves hosts that have not been added with `resolver`, instead of failing.
+    pub fn fallback<R: Resolve + 'static>(mut self, resolver: R) -> Self {
+        self.fallback = Some(Arc::new(resolver));
+        self
+    }
+}
+
+impl Resolve for StaticResolver {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        if let Some(addrs) = self.hosts.get(&host.to_lowercase()) {
+            let addrs = addrs.iter().map(|&ip| SocketAddr::new(ip, port)).collect();
+            return Box::new(future::ok(addrs));
+        }
+        match self.fallback {
+            Some(ref fallback) => fallback.resolve(host, port),
+            None => Box::new(future::err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No address for host {}", host),
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for StaticResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticResolver")
+            .field("hosts", &self.hosts)
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_hosts() {
+        let v4 = "203.0.113.7".parse().unwrap();
+        let v6 = "2001:db8::7".parse().unwrap();
+        let resolver = StaticResolver::new()
+            .host("Node.example.com", v4)
+            .host("node.example.com", v6);
+        let addrs = resolver.resolve("NODE.example.com", 8332).wait().unwrap();
+        assert_eq!(vec![SocketAddr::new(v4, 8332), SocketAddr::new(v6, 8332)], addrs);
+
+        let error = resolver.resolve("other.example.com", 8332).wait().unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, error.kind());
+    }
+
+    #[test]
+    fn fallback() {
+        let fallback = StaticResolver::new().host("localhost", "127.0.0.2".parse().unwrap());
+        let resolver = StaticResolver::new()
+            .host("node.example.com", "203.0.113.7".parse().unwrap())
+            .fallback(fallback);
+        let addrs = resolver.resolve("localhost", 80).wait().unwrap();
+        assert_eq!(vec!["127.0.0.2:80".parse::<SocketAddr>().unwrap()], addrs);
+    }
+
+    #[test]
+    fn system_resolver() {
+        let addrs = SystemResolver.resolve("127.0.0.1", 80).wait().unwrap();
+        assert_eq!(vec!["127.0.0.1:80".parse::<SocketAddr>().unwrap()], addrs);
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Transports for devices exposing JSON-RPC on a serial port, like microcontrollers on a UART
+//! or a USB-CDC link.
+//!
+//! The port is put in raw mode, with 8 data bits, no parity, one stop bit and no flow control,
+//! at the baud rate of the [`SerialSettings`]. Messages are framed either one per line, or with
+//! SLIP for devices that need a framing robust against line noise. Many calls can be in flight
+//! at once if the device supports it, responses are routed back by id. Devices with other
+//! framings are reached by opening a [`SerialPort`] and framing it with any
+//! [`Framing`](../jsonrpc_client_core/framing/trait.Framing.html).
+//!
+//! [`SerialSettings`]: struct.SerialSettings.html
+//! [`SerialPort`]: struct.SerialPort.html
+
+use fd::{check, FdStream};
+use futures::{Future, Poll};
+use jsonrpc_client_core::duplex::DuplexTransport;
+use jsonrpc_client_core::framing::{self, LineCodec, SlipCodec};
+use libc;
+use std::ffi::CString;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use tokio_core::reactor::{Handle, PollEvented};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+
+/// How messages are framed on a serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialFraming {
+    /// One JSON message per line, ended by a newline.
+    Lines,
+    /// JSON messages in SLIP frames, see
+    /// [`SlipCodec`](../jsonrpc_client_core/framing/struct.SlipCodec.html).
+    Slip,
+}
+
+/// The baud rate and framing of a serial port.
+#[derive(Debug, Clone)]
+pub struct SerialSettings {
+    baud_rate: u32,
+    framing: SerialFraming,
+}
+
+impl SerialSettings {
+    /// Creates settings for a port running at `baud_rate`, with one message per line.
+    pub fn new(baud_rate: u32) -> Self {
+        SerialSettings {
+            baud_rate,
+            framing: SerialFraming::Lines,
+        }
+    }
+
+    /// Sets how messages are framed on the port.
+    pub fn framing(mut self, framing: SerialFraming) -> Self {
+        self.framing = framing;
+        self
+    }
+}
+
+
+/// Opens the serial port at `path`, like `/dev/ttyUSB0` or `/dev/ttyACM0`, configures it with
+/// `settings` and returns a transport multiplexing calls over it. The port is driven by a task
+/// spawned on `handle`, which logs an error and fails all calls in flight if reading or writing
+/// the port fails.
+///
+/// Fails with an `InvalidInput` error for baud rates the platform does not support.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate jsonrpc_client_http;
+/// # extern crate tokio_core;
+/// # use jsonrpc_client_http::{serial_transport, SerialFraming, SerialSettings};
+/// # fn main() {
+/// let core = tokio_core::reactor::Core::new().unwrap();
+/// let settings = SerialSettings::new(115_200).framing(SerialFraming::Slip);
+/// let transport = serial_transport("/dev/ttyACM0", &settings, &core.handle()).unwrap();
+/// # }
+/// ```
+pub fn serial_transport<P: AsRef<Path>>(
+    path: P,
+    settings: &SerialSettings,
+    handle: &Handle,
+) -> io::Result<DuplexTransport> {
+    let path = path.as_ref();
+    let port = SerialPort::open(path, settings, handle)?;
+    let path = path.display().to_string();
+    let log_error = move |e| error!("Serial port {} failed: {}", path, e);
+    let transport = match settings.framing {
+        SerialFraming::Lines => {
+            let (transport, connection) = framing::framed(port, LineCodec::new());
+            handle.spawn(connection.map_err(log_error));
+            transport
+        }
+        SerialFraming::Slip => {
+            let (transport, connection) = framing::framed(port, SlipCodec::new());
+            handle.spawn(connection.map_err(log_error));
+            transport
+        }
+    };
+    Ok(transport)
+}
+
+
+/// An open serial port, registered with an event loop. Closed when dropped.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let port = SerialPort::open("/dev/ttyUSB0", &SerialSettings::new(9600), &handle)?;
+/// let (transport, connection) = framing::framed(port, LengthPrefixCodec::new());
+/// handle.spawn(connection.map_err(|e| error!("Serial port failed: {}", e)));
+/// ```
+#[derive(Debug)]
+pub struct SerialPort {
+    io: PollEvented<FdStream>,
+}
+
+impl SerialPort {
+    /// Opens the serial port at `path` and configures it with the baud rate of `settings`. The
+    /// framing of `settings` is not used.
+    ///
+    /// Fails with an `InvalidInput` error for baud rates the platform does not support.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        settings: &SerialSettings,
+        handle: &Handle,
+    ) -> io::Result<SerialPort> {
+        let path = path.as_ref();
+        debug!("Opening serial port {} at {} baud", path.display(), settings.baud_rate);
+        let port = open_port(path, settings.baud_rate)?;
+        Ok(SerialPort {
+            io: PollEvented::new(port, handle)?,
+        })
+    }
+}
+
+impl Read for SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl Write for SerialPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl AsyncRead for SerialPort {}
+
+impl AsyncWrite for SerialPort {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+
+/// Opens the serial port at `path` in non-blocking mode, and puts it in raw mode at `baud_rate`.
+fn open_port(path: &Path, baud_rate: u32) -> io::Result<FdStream> {
+    let speed = speed(baud_rate)?;
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK | libc::O_CLOEXEC;
+    // Owning the descriptor closes it if configuring the port fails.
+    let port = FdStream::new(unsafe { libc::open(path.as_ptr(), flags) })?;
+    let fd = port.fd();
+    unsafe {
+        let mut termios: libc::termios = mem::zeroed();
+        check(libc::tcgetattr(fd, &mut termios))?;
+        libc::cfmakeraw(&mut termios);
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cflag &= !(libc::CSTOPB | libc::CRTSCTS);
+        check(libc::cfsetispeed(&mut termios, speed))?;
+        check(libc::cfsetospeed(&mut termios, speed))?;
+        check(libc::tcsetattr(fd, libc::TCSANOW, &termios))?;
+        check(libc::tcflush(fd, libc::TCIOFLUSH))?;
+    }
+    Ok(port)
+}
+
+/// Returns the termios speed of `baud_rate`.
+fn speed(baud_rate: u32) -> io::Result<libc::speed_t> {
+    let speed = match baud_rate {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19_200 => libc::B19200,
+        38_400 => libc::B38400,
+        57_600 => libc::B57600,
+        115_200 => libc::B115200,
+        230_400 => libc::B230400,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        460_800 => libc::B460800,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        921_600 => libc::B921600,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported baud rate {}", baud_rate),
+            ))
+        }
+    };
+    Ok(speed)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_client_core::RpcClient;
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::io::FromRawFd;
+    use std::thread;
+    use tokio_core::reactor::Core;
+
+    /// Opens a pseudo terminal, returning its master side and the path of its slave side, which
+    /// behaves like a serial port.
+    fn pseudo_terminal() -> (File, String) {
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0);
+            assert_eq!(0, libc::grantpt(master));
+            assert_eq!(0, libc::unlockpt(master));
+            let path = CStr::from_ptr(libc::ptsname(master)).to_str().unwrap().to_owned();
+            (File::from_raw_fd(master), path)
+        }
+    }
+
+    #[test]
+    fn calls_over_serial_port() {
+        let (master, path) = pseudo_terminal();
+        let mut core = Core::new().unwrap();
+        let settings = SerialSettings::new(115_200);
+        let transport = serial_transport(&path, &settings, &core.handle()).unwrap();
+        // Answers a request with its id as the result, like a device would.
+        thread::spawn(move || {
+            let mut writer = master.try_clone().unwrap();
+            let mut reader = BufReader::new(master);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+            let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": request["id"]});
+            writeln!(writer, "{}", response).unwrap();
+            // Closing the master side would discard the response before it has been read.
+            let _ = reader.read_line(&mut line);
+        });
+        let mut client = RpcClient::new(transport);
+        let id: u64 = core.run(client.call("ping", ())).unwrap();
+        assert_eq!(1, id);
+    }
+
+    #[test]
+    fn unsupported_baud_rate() {
+        let (_master, path) = pseudo_terminal();
+        let core = Core::new().unwrap();
+        let settings = SerialSettings::new(12_345);
+        let error = serial_transport(&path, &settings, &core.handle()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+    }
+}
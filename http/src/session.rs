@@ -0,0 +1,218 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Authentication with a session token obtained by logging in, logging in again when the
+//! session ends.
+//!
+//! Many JSON-RPC APIs, like those of Zabbix, Odoo or aria2, have a login method returning a
+//! session token that has to be sent with every later call. A [`SessionAuth`] logs in before
+//! the first call, adds the token to every request as told by its [`TokenPlacement`], and logs
+//! in again when the server rejects the token, sending the rejected call once more.
+//!
+//! [`SessionAuth`]: struct.SessionAuth.html
+//! [`TokenPlacement`]: enum.TokenPlacement.html
+
+use Result;
+use futures::IntoFuture;
+use hyper::header::Headers;
+use jsonrpc_client_core::{call_method, ClientConfig, Transport};
+use jsonrpc_client_core::serde::Serialize;
+use serde_json::{self, Map, Value as JsonValue};
+use std::error;
+use std::fmt;
+use token::{self, RequestAuth, TokenCache, TokenFuture};
+
+
+/// Where a `SessionAuth` puts the session token in requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenPlacement {
+    /// In the HTTP header with the given name.
+    Header(String),
+    /// As the member with the given name of the params object. Requests with params given by
+    /// position get the token as their first param instead, like aria2 expects.
+    Param(String),
+    /// As the member with the given name of the request itself, next to `method` and `params`,
+    /// like the `auth` member of the Zabbix API.
+    Member(String),
+}
+
+impl TokenPlacement {
+    /// Adds `token` to `request`, a single request object.
+    fn place(&self, token: &str, request: &mut JsonValue) {
+        let token = JsonValue::String(token.to_owned());
+        let request = match request.as_object_mut() {
+            Some(request) => request,
+            None => return,
+        };
+        match *self {
+            TokenPlacement::Header(_) => (),
+            TokenPlacement::Param(ref name) => {
+                let params = request
+                    .entry("params")
+                    .or_insert_with(|| JsonValue::Object(Map::new()));
+                if params.is_null() {
+                    *params = JsonValue::Object(Map::new());
+                }
+                match *params {
+                    JsonValue::Object(ref mut params) => {
+                        params.insert(name.clone(), token);
+                    }
+                    JsonValue::Array(ref mut params) => params.insert(0, token),
+                    _ => warn!("Unable to add session token to params that are not structured"),
+                }
+            }
+            TokenPlacement::Member(ref name) => {
+                request.insert(name.clone(), token);
+            }
+        }
+    }
+}
+
+
+/// Authentication with a session token obtained by logging in. Given to
+/// [`HttpHandle::set_session_auth`](struct.HttpHandle.html#method.set_session_auth). Clones
+/// share the session.
+#[derive(Clone)]
+pub struct SessionAuth {
+    tokens: TokenCache,
+    placement: TokenPlacement,
+    auth_error_code: Option<i64>,
+}
+
+impl SessionAuth {
+    /// Obtains session tokens by calling `login`, and puts them in requests as told by
+    /// `placement`.
+    pub fn new<F, R>(placement: TokenPlacement, login: F) -> Self
+    where
+        F: Fn() -> R + Send + Sync + 'static,
+        R: IntoFuture<Item = String>,
+        R::Future: Send + 'static,
+        R::Error: Into<Box<error::Error + Send + Sync>> + 'static,
+    {
+        SessionAuth {
+            tokens: TokenCache::new(login, |_| None),
+            placement,
+            auth_error_code: None,
+        }
+    }
+
+    /// Obtains session tokens by calling the login method `method` with `params` on
+    /// `transport`, and puts them in requests as told by `placement`. The method must return
+    /// the token as a string. `transport` must not be authenticated with this `SessionAuth`
+    /// itself, create a separate handle for it.
+    pub fn login_method<T, P>(
+        placement: TokenPlacement,
+        transport: T,
+        method: &str,
+        params: P,
+    ) -> Self
+    where
+        T: Transport + Send + Sync + 'static,
+        T::Future: Send,
+        P: Serialize + Clone + Send + Sync + 'static,
+    {
+        let method = method.to_owned();
+        Self::new(placement, move || {
+            call_method(&transport, &ClientConfig::default(), method.clone(), params.clone())
+        })
+    }
+
+    /// Treats JSON-RPC error responses with the error code `code` as the session having ended,
+    /// in addition to HTTP 401 Unauthorized responses.
+    pub fn auth_error_code(mut self, code: i64) -> Self {
+        self.auth_error_code = Some(code);
+        self
+    }
+}
+
+impl RequestAuth for SessionAuth {
+    fn token(&self) -> TokenFuture {
+        self.tokens.token()
+    }
+
+    fn authorize(&self, token: &str, body: Vec<u8>, headers: &mut Headers) -> Vec<u8> {
+        if let TokenPlacement::Header(ref name) = self.placement {
+            headers.set_raw(name.clone(), token.to_owned());
+            return body;
+        }
+        let mut request: JsonValue = match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Unable to add session token to request that is not valid JSON: {}", e);
+                return body;
+            }
+        };
+        match request {
+            JsonValue::Array(ref mut batch) => {
+                for request in batch {
+                    self.placement.place(token, request);
+                }
+            }
+            ref mut request => self.placement.place(token, request),
+        }
+        serde_json::to_vec(&request).expect("JSON is serializable")
+    }
+
+    fn is_rejection(&self, result: &Result<Vec<u8>>) -> bool {
+        token::is_rejection(result, self.auth_error_code)
+    }
+
+    fn invalidate(&self, token: &str) {
+        self.tokens.invalidate(token)
+    }
+}
+
+impl fmt::Debug for SessionAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Leaves out the token.
+        f.debug_struct("SessionAuth")
+            .field("placement", &self.placement)
+            .field("auth_error_code", &self.auth_error_code)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Error;
+
+    type LoginResult = ::std::result::Result<String, Error>;
+
+    fn authorize(placement: TokenPlacement, request: &str) -> JsonValue {
+        let auth = SessionAuth::new(placement, || Ok("s3cr3t".to_owned()) as LoginResult);
+        let body = auth.authorize("s3cr3t", request.as_bytes().to_vec(), &mut Headers::new());
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn places_token() {
+        let request = r#"{"jsonrpc":"2.0","method":"host.get","params":{"limit":1},"id":1}"#;
+        let zabbix = authorize(TokenPlacement::Member("auth".to_owned()), request);
+        assert_eq!("s3cr3t", zabbix["auth"]);
+        let named = authorize(TokenPlacement::Param("token".to_owned()), request);
+        assert_eq!(json!({"limit": 1, "token": "s3cr3t"}), named["params"]);
+
+        let request = r#"[{"method":"aria2.tellActive","params":[["gid"]],"id":1},
+                          {"method":"aria2.getVersion","params":null,"id":2}]"#;
+        let aria2 = authorize(TokenPlacement::Param("token".to_owned()), request);
+        assert_eq!(json!(["s3cr3t", ["gid"]]), aria2[0]["params"]);
+        assert_eq!(json!({"token": "s3cr3t"}), aria2[1]["params"]);
+    }
+
+    #[test]
+    fn token_in_header() {
+        let auth = SessionAuth::new(TokenPlacement::Header("X-Session".to_owned()), || {
+            Ok("s3cr3t".to_owned()) as LoginResult
+        });
+        let mut headers = Headers::new();
+        assert_eq!(b"{}".to_vec(), auth.authorize("s3cr3t", b"{}".to_vec(), &mut headers));
+        assert_eq!(&b"s3cr3t"[..], headers.get_raw("X-Session").unwrap().one().unwrap());
+    }
+}
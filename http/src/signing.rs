@@ -0,0 +1,288 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! HMAC signing of requests, as required by many exchange-style JSON-RPC APIs.
+//!
+//! A [`RequestSigner`] computes an HMAC of every request with a shared secret, optionally
+//! together with a timestamp and a nonce, and sends the hex encoded signature either in HTTP
+//! headers or as fields of the params object of the request.
+//!
+//! [`RequestSigner`]: struct.RequestSigner.html
+
+use hmac::{Hmac, Mac};
+use hyper::header::Headers;
+use serde_json::{self, Value as JsonValue};
+use sha2::{Sha256, Sha512};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+/// The hash function an HMAC is computed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HmacAlgorithm {
+    /// HMAC-SHA256.
+    Sha256,
+    /// HMAC-SHA512.
+    Sha512,
+}
+
+impl HmacAlgorithm {
+    /// Computes the HMAC of `message` with `key`.
+    fn sign(&self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        match *self {
+            HmacAlgorithm::Sha256 => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(key).expect("Any key length is valid");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            HmacAlgorithm::Sha512 => {
+                let mut mac =
+                    Hmac::<Sha512>::new_from_slice(key).expect("Any key length is valid");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+}
+
+/// Where a `RequestSigner` puts the signature, timestamp and nonce of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignaturePlacement {
+    /// In HTTP headers. The signed message is the timestamp, the nonce and the request body,
+    /// concatenated in that order.
+    Headers,
+    /// As fields of the params object of the request. The timestamp and nonce fields are added
+    /// first, and the signed message is the request serialized with them.
+    Params,
+}
+
+
+/// Signs requests with an HMAC of their content. Given to
+/// [`HttpHandle::set_request_signer`](struct.HttpHandle.html#method.set_request_signer).
+pub struct RequestSigner {
+    algorithm: HmacAlgorithm,
+    key: Vec<u8>,
+    placement: SignaturePlacement,
+    signature_name: String,
+    timestamp_name: Option<String>,
+    nonce_name: Option<String>,
+    last_nonce: AtomicU64,
+}
+
+impl RequestSigner {
+    /// Creates a signer sending the signature in the `X-Signature` header, without a timestamp
+    /// or nonce.
+    pub fn headers<K: Into<Vec<u8>>>(algorithm: HmacAlgorithm, key: K) -> Self {
+        Self::new(algorithm, key.into(), SignaturePlacement::Headers, "X-Signature")
+    }
+
+    /// Creates a signer adding the signature as the `signature` field of the params object,
+    /// without a timestamp or nonce.
+    pub fn params<K: Into<Vec<u8>>>(algorithm: HmacAlgorithm, key: K) -> Self {
+        Self::new(algorithm, key.into(), SignaturePlacement::Params, "signature")
+    }
+
+    fn new(
+        algorithm: HmacAlgorithm,
+        key: Vec<u8>,
+        placement: SignaturePlacement,
+        signature_name: &str,
+    ) -> Self {
+        RequestSigner {
+            algorithm,
+            key,
+            placement,
+            signature_name: signature_name.to_owned(),
+            timestamp_name: None,
+            nonce_name: None,
+            last_nonce: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the name of the header or field carrying the signature.
+    pub fn signature(mut self, name: &str) -> Self {
+        self.signature_name = name.to_owned();
+        self
+    }
+
+    /// Signs a timestamp, in milliseconds since the Unix epoch, along with every request and
+    /// sends it in the header or field `name`.
+    pub fn timestamp(mut self, name: &str) -> Self {
+        self.timestamp_name = Some(name.to_owned());
+        self
+    }
+
+    /// Signs a nonce along with every request and sends it in the header or field `name`. Nonces
+    /// increase with every request, starting from the current time in microseconds since the
+    /// Unix epoch, as exchanges rejecting replayed requests expect.
+    pub fn nonce(mut self, name: &str) -> Self {
+        self.nonce_name = Some(name.to_owned());
+        self
+    }
+
+    /// Returns where the signature is sent.
+    pub fn placement(&self) -> SignaturePlacement {
+        self.placement
+    }
+
+    /// Signs the request `body`, setting the headers of the signature on `headers` or adding its
+    /// fields to the params of the request. Returns the body to send. Requests whose params
+    /// are not an object are sent unsigned when signing into the params.
+    pub(crate) fn sign(&self, body: Vec<u8>, headers: &mut Headers) -> Vec<u8> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time before the Unix epoch");
+        let millis = now.as_secs() * 1000 + u64::from(now.subsec_millis());
+        let micros = now.as_secs() * 1_000_000 + u64::from(now.subsec_micros());
+        let timestamp = self.timestamp_name
+            .as_ref()
+            .map(|name| (name, millis.to_string()));
+        let nonce = self.nonce_name
+            .as_ref()
+            .map(|name| (name, self.next_nonce(micros)));
+        match self.placement {
+            SignaturePlacement::Headers => {
+                let mut message = Vec::new();
+                for &(name, ref value) in timestamp.iter().chain(nonce.iter()) {
+                    message.extend_from_slice(value.as_bytes());
+                    headers.set_raw(name.clone(), value.clone());
+                }
+                message.extend_from_slice(&body);
+                headers.set_raw(self.signature_name.clone(), self.hex_signature(&message));
+                body
+            }
+            SignaturePlacement::Params => {
+                let mut request: JsonValue = match serde_json::from_slice(&body) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        warn!("Unable to sign request that is not valid JSON: {}", e);
+                        return body;
+                    }
+                };
+                if request["params"].is_null() {
+                    request["params"] = JsonValue::Object(serde_json::Map::new());
+                }
+                let signature = {
+                    let params = match request["params"].as_object_mut() {
+                        Some(params) => params,
+                        None => {
+                            warn!("Unable to sign request without a params object");
+                            return body;
+                        }
+                    };
+                    for (name, value) in timestamp.into_iter().chain(nonce) {
+                        params.insert(name.clone(), JsonValue::String(value));
+                    }
+                    let message = serde_json::to_vec(&request).expect("JSON is serializable");
+                    self.hex_signature(&message)
+                };
+                request["params"][&self.signature_name] = JsonValue::String(signature);
+                serde_json::to_vec(&request).expect("JSON is serializable")
+            }
+        }
+    }
+
+    /// Returns the next nonce, at least `now` and greater than all nonces returned before.
+    fn next_nonce(&self, now: u64) -> String {
+        let mut last = self.last_nonce.load(Ordering::SeqCst);
+        loop {
+            let next = ::std::cmp::max(now, last + 1);
+            let swapped =
+                self.last_nonce
+                    .compare_exchange(last, next, Ordering::SeqCst, Ordering::SeqCst);
+            match swapped {
+                Ok(_) => return next.to_string(),
+                Err(actual) => last = actual,
+            }
+        }
+    }
+
+    fn hex_signature(&self, message: &[u8]) -> String {
+        self.algorithm
+            .sign(&self.key, message)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+impl fmt::Debug for RequestSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Leaves out the key.
+        f.debug_struct("RequestSigner")
+            .field("algorithm", &self.algorithm)
+            .field("placement", &self.placement)
+            .field("signature_name", &self.signature_name)
+            .field("timestamp_name", &self.timestamp_name)
+            .field("nonce_name", &self.nonce_name)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test case 2 of RFC 4231.
+    const MESSAGE: &str = "what do ya want for nothing?";
+    const SHA256_SIGNATURE: &str =
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+
+    fn header(headers: &Headers, name: &str) -> String {
+        String::from_utf8(headers.get_raw(name).unwrap().one().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn signs_into_headers() {
+        let signer = RequestSigner::headers(HmacAlgorithm::Sha256, "Jefe");
+        let mut headers = Headers::new();
+        let body = signer.sign(MESSAGE.as_bytes().to_vec(), &mut headers);
+        assert_eq!(MESSAGE.as_bytes(), &body[..]);
+        assert_eq!(SHA256_SIGNATURE, header(&headers, "X-Signature"));
+    }
+
+    #[test]
+    fn signs_timestamp_and_nonce() {
+        let signer = RequestSigner::headers(HmacAlgorithm::Sha512, "key")
+            .timestamp("X-Timestamp")
+            .nonce("X-Nonce");
+        let mut headers = Headers::new();
+        signer.sign(b"{}".to_vec(), &mut headers);
+        let first_nonce: u64 = header(&headers, "X-Nonce").parse().unwrap();
+        let message = format!("{}{}{{}}", header(&headers, "X-Timestamp"), first_nonce);
+        assert_eq!(signer.hex_signature(message.as_bytes()), header(&headers, "X-Signature"));
+
+        signer.sign(b"{}".to_vec(), &mut headers);
+        let second_nonce: u64 = header(&headers, "X-Nonce").parse().unwrap();
+        assert!(second_nonce > first_nonce);
+    }
+
+    #[test]
+    fn signs_into_params() {
+        let signer = RequestSigner::params(HmacAlgorithm::Sha256, "key").nonce("nonce");
+        let request = br#"{"jsonrpc":"2.0","method":"balance","params":{"asset":"BTC"},"id":1}"#;
+        let body = signer.sign(request.to_vec(), &mut Headers::new());
+        let mut request: JsonValue = serde_json::from_slice(&body).unwrap();
+        assert_eq!("BTC", request["params"]["asset"]);
+        assert!(request["params"]["nonce"].is_string());
+
+        let signature = request["params"]
+            .as_object_mut()
+            .unwrap()
+            .remove("signature")
+            .unwrap();
+        let unsigned = serde_json::to_vec(&request).unwrap();
+        assert_eq!(signer.hex_signature(&unsigned), signature);
+
+        let positional = br#"{"jsonrpc":"2.0","method":"balance","params":["BTC"],"id":1}"#;
+        assert_eq!(&positional[..], &signer.sign(positional.to_vec(), &mut Headers::new())[..]);
+    }
+}
@@ -0,0 +1,283 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tuning of the TCP sockets network transports connect with.
+//!
+//! [`SocketOptions`] are set on the socket before it connects, so they also apply to the
+//! handshake. They are given to a [`TcpConnector`], or to a [`TcpClient`] for HTTP transports.
+//!
+//! [`SocketOptions`]: struct.SocketOptions.html
+//! [`TcpConnector`]: struct.TcpConnector.html
+//! [`TcpClient`]: struct.TcpClient.html
+
+use futures::future;
+use net2::{TcpBuilder, TcpStreamExt};
+use std::io;
+use std::net::{self, IpAddr, SocketAddr};
+use std::time::Duration;
+use tcp::TcpConnectFuture;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+
+
+/// How idle connections are probed to detect peers that have gone away, and to keep NAT and
+/// firewall state alive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    idle: Duration,
+    interval: Option<Duration>,
+    retries: Option<u32>,
+}
+
+impl Keepalive {
+    /// Starts sending keepalive probes once the connection has been idle for `idle`. The time
+    /// between probes and their number default to those of the operating system.
+    pub fn new(idle: Duration) -> Self {
+        Keepalive {
+            idle,
+            interval: None,
+            retries: None,
+        }
+    }
+
+    /// Sets the time between probes the peer does not answer.
+    ///
+    /// Only supported on Linux, Android, macOS, iOS and FreeBSD. Connecting fails elsewhere.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Sets how many unanswered probes close the connection.
+    ///
+    /// Only supported on Linux, Android, macOS, iOS and FreeBSD. Connecting fails elsewhere.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+}
+
+
+/// Options for the TCP sockets of a transport. Options that are not set keep the defaults of
+/// the operating system.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate jsonrpc_client_http;
+/// # use jsonrpc_client_http::{Keepalive, SocketOptions};
+/// # use std::time::Duration;
+/// # fn main() {
+/// let options = SocketOptions::new()
+///     .nodelay(true)
+///     .keepalive(Keepalive::new(Duration::from_secs(30)).interval(Duration::from_secs(5)))
+///     .local_address("192.0.2.10".parse().unwrap());
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<Keepalive>,
+    local_address: Option<IpAddr>,
+    interface: Option<String>,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    /// Creates options keeping all defaults of the operating system.
+    pub fn new() -> Self {
+        SocketOptions::default()
+    }
+
+    /// Sets `TCP_NODELAY`. Disabling Nagle's algorithm sends small requests right away instead
+    /// of waiting to coalesce them, lowering latency.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enables `SO_KEEPALIVE`, probing idle connections as configured by `keepalive`.
+    pub fn keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Connects from `address`, selecting the source address on hosts with more than one.
+    /// Servers with addresses of the other IP version can then not be connected to.
+    pub fn local_address(mut self, address: IpAddr) -> Self {
+        self.local_address = Some(address);
+        self
+    }
+
+    /// Connects through the network interface named `interface`, like `eth1`, with
+    /// `SO_BINDTODEVICE`.
+    ///
+    /// Only supported on Linux and Android, where older kernels also require the
+    /// `CAP_NET_RAW` capability. Connecting fails elsewhere.
+    pub fn interface<S: Into<String>>(mut self, interface: S) -> Self {
+        self.interface = Some(interface.into());
+        self
+    }
+
+    /// Sets the size of the receive buffer of the socket, `SO_RCVBUF`, in bytes.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the size of the send buffer of the socket, `SO_SNDBUF`, in bytes.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Returns a future resolving to a stream connected to `addr` from a socket with these
+    /// options.
+    pub(crate) fn connect(&self, addr: &SocketAddr, handle: &Handle) -> TcpConnectFuture {
+        match self.socket(addr) {
+            Ok(socket) => TcpStream::connect_stream(socket, addr, handle),
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+
+    /// Creates a socket with these options, ready to connect to `addr`.
+    fn socket(&self, addr: &SocketAddr) -> io::Result<net::TcpStream> {
+        let builder = match *addr {
+            SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+            SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+        };
+        if let Some(ref interface) = self.interface {
+            bind_to_interface(&builder, interface)?;
+        }
+        if let Some(address) = self.local_address {
+            builder.bind(SocketAddr::new(address, 0))?;
+        }
+        let socket = builder.to_tcp_stream()?;
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            TcpStreamExt::set_recv_buffer_size(&socket, size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            TcpStreamExt::set_send_buffer_size(&socket, size)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            TcpStreamExt::set_keepalive(&socket, Some(keepalive.idle))?;
+            set_keepalive_probes(&socket, &keepalive)?;
+        }
+        Ok(socket)
+    }
+}
+
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_to_interface(builder: &TcpBuilder, interface: &str) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    set_socket_option(
+        builder.as_raw_fd(),
+        ::libc::SOL_SOCKET,
+        ::libc::SO_BINDTODEVICE,
+        interface.as_bytes(),
+    )
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_to_interface(_builder: &TcpBuilder, _interface: &str) -> io::Result<()> {
+    Err(unsupported("Binding to an interface"))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios",
+          target_os = "freebsd"))]
+fn set_keepalive_probes(socket: &net::TcpStream, keepalive: &Keepalive) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    use std::{cmp, mem, slice};
+    let mut options = Vec::new();
+    if let Some(interval) = keepalive.interval {
+        let secs = cmp::min(cmp::max(interval.as_secs(), 1), ::libc::c_int::max_value() as u64);
+        options.push((::libc::TCP_KEEPINTVL, secs as ::libc::c_int));
+    }
+    if let Some(retries) = keepalive.retries {
+        let retries = cmp::min(retries, ::libc::c_int::max_value() as u32);
+        options.push((::libc::TCP_KEEPCNT, retries as ::libc::c_int));
+    }
+    for (name, value) in options {
+        let bytes = unsafe {
+            slice::from_raw_parts(&value as *const _ as *const u8, mem::size_of_val(&value))
+        };
+        set_socket_option(socket.as_raw_fd(), ::libc::IPPROTO_TCP, name, bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos",
+              target_os = "ios", target_os = "freebsd")))]
+fn set_keepalive_probes(_socket: &net::TcpStream, keepalive: &Keepalive) -> io::Result<()> {
+    if keepalive.interval.is_some() || keepalive.retries.is_some() {
+        return Err(unsupported("Setting keepalive intervals and retries"));
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios",
+          target_os = "freebsd"))]
+fn set_socket_option(
+    fd: ::std::os::unix::io::RawFd,
+    level: ::libc::c_int,
+    name: ::libc::c_int,
+    value: &[u8],
+) -> io::Result<()> {
+    let value_ptr = value.as_ptr() as *const ::libc::c_void;
+    let value_len = value.len() as ::libc::socklen_t;
+    ::fd::check(unsafe { ::libc::setsockopt(fd, level, name, value_ptr, value_len) })?;
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("{} is not supported on this platform", what),
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn applies_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut core = Core::new().unwrap();
+        let options = SocketOptions::new()
+            .nodelay(true)
+            .keepalive(Keepalive::new(Duration::from_secs(60)).retries(4))
+            .local_address("127.0.0.1".parse().unwrap())
+            .recv_buffer_size(1 << 16);
+        let stream = core.run(options.connect(&addr, &core.handle())).unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert_eq!(Some(Duration::from_secs(60)), stream.keepalive().unwrap());
+        assert_eq!(addr.ip(), stream.local_addr().unwrap().ip());
+        let (accepted, _) = listener.accept().unwrap();
+        assert_eq!(stream.local_addr().unwrap(), accepted.peer_addr().unwrap());
+    }
+
+    #[test]
+    fn local_address_of_other_ip_version() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut core = Core::new().unwrap();
+        let options = SocketOptions::new().local_address("::1".parse().unwrap());
+        assert!(core.run(options.connect(&addr, &core.handle())).is_err());
+    }
+}
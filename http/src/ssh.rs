@@ -0,0 +1,278 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Transports tunneled through SSH, for daemons that only listen on localhost on a remote
+//! machine.
+//!
+//! The tunnel is the OpenSSH client run with `-W`, forwarding its standard input and output to a
+//! host and port as seen from the SSH server. Authentication is left to the client, so keys,
+//! agents, `~/.ssh/config` and known hosts work like they do on the command line. The client
+//! runs in batch mode and fails rather than prompt for passwords or passphrases. Messages are
+//! sent one per line unless the tunnel is given another framing, and many calls can be in
+//! flight at once.
+
+use fd::FdStream;
+use futures::{Future, Poll};
+use jsonrpc_client_core::duplex::DuplexTransport;
+use jsonrpc_client_core::framing::{self, BoxFraming, Framing};
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::os::unix::io::IntoRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use tokio_core::reactor::{Handle, PollEvented};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+
+/// Where to connect with SSH, how to authenticate and what to forward to.
+#[derive(Debug, Clone)]
+pub struct SshTunnel {
+    destination: String,
+    remote_host: String,
+    remote_port: u16,
+    port: Option<u16>,
+    identity_file: Option<PathBuf>,
+    options: Vec<String>,
+    program: PathBuf,
+    framing: BoxFraming,
+}
+
+impl SshTunnel {
+    /// Creates a tunnel to `remote_port` on localhost of the SSH server `destination`, given as
+    /// `host` or `user@host`, or a host alias from the SSH config. Authenticates with the keys
+    /// of the SSH agent or the default identity files.
+    pub fn new<S: Into<String>>(destination: S, remote_port: u16) -> Self {
+        SshTunnel {
+            destination: destination.into(),
+            remote_host: "localhost".to_owned(),
+            remote_port,
+            port: None,
+            identity_file: None,
+            options: Vec::new(),
+            program: PathBuf::from("ssh"),
+            framing: BoxFraming::default(),
+        }
+    }
+
+    /// Sets the host to forward to, as resolved by the SSH server. Defaults to `localhost`.
+    pub fn remote_host<S: Into<String>>(mut self, remote_host: S) -> Self {
+        self.remote_host = remote_host.into();
+        self
+    }
+
+    /// Sets the port the SSH server listens on, instead of the default or configured one.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Authenticates with the private key in `identity_file` only, instead of the keys of the
+    /// agent and the default identity files.
+    pub fn identity_file<P: Into<PathBuf>>(mut self, identity_file: P) -> Self {
+        self.identity_file = Some(identity_file.into());
+        self
+    }
+
+    /// Adds an option in the format of the SSH config, like `StrictHostKeyChecking=yes`.
+    pub fn option<S: Into<String>>(mut self, option: S) -> Self {
+        self.options.push(option.into());
+        self
+    }
+
+    /// Sets the SSH client to run, instead of the `ssh` found in `PATH`.
+    pub fn program<P: Into<PathBuf>>(mut self, program: P) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Frames the messages sent through the tunnel with `framing`, instead of one JSON message
+    /// per line.
+    pub fn framing<F: Framing + Clone + Send + Sync + 'static>(mut self, framing: F) -> Self {
+        self.framing = BoxFraming::new(framing);
+        self
+    }
+
+    /// Returns the arguments of the SSH client.
+    fn args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["-o".into(), "BatchMode=yes".into()];
+        if let Some(port) = self.port {
+            args.push("-p".into());
+            args.push(port.to_string().into());
+        }
+        if let Some(ref identity_file) = self.identity_file {
+            args.push("-i".into());
+            args.push(identity_file.clone().into());
+            args.push("-o".into());
+            args.push("IdentitiesOnly=yes".into());
+        }
+        for option in &self.options {
+            args.push("-o".into());
+            args.push(option.into());
+        }
+        args.push("-W".into());
+        args.push(format!("{}:{}", self.remote_host, self.remote_port).into());
+        args.push("--".into());
+        args.push(self.destination.clone().into());
+        args
+    }
+}
+
+
+/// Starts the SSH client for `tunnel` and returns a transport multiplexing calls over the
+/// forwarded connection. The connection is driven by a task spawned on `handle`, which logs an
+/// error and fails all calls in flight if the connection fails. The SSH client is killed when
+/// the connection ends.
+///
+/// Fails if the SSH client can not be started. Failing to connect or authenticate is only
+/// noticed when the client exits, failing the calls in flight, with the reason printed by the
+/// client on the standard error.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate jsonrpc_client_http;
+/// # extern crate tokio_core;
+/// # use jsonrpc_client_http::{ssh_transport, SshTunnel};
+/// # fn main() {
+/// let core = tokio_core::reactor::Core::new().unwrap();
+/// let tunnel = SshTunnel::new("admin@node.example.com", 8332)
+///     .identity_file("/etc/rpc/id_ed25519");
+/// let transport = ssh_transport(&tunnel, &core.handle()).unwrap();
+/// # }
+/// ```
+pub fn ssh_transport(tunnel: &SshTunnel, handle: &Handle) -> io::Result<DuplexTransport> {
+    debug!(
+        "Tunneling to {}:{} through SSH to {}",
+        tunnel.remote_host,
+        tunnel.remote_port,
+        tunnel.destination
+    );
+    let stream = SshStream::spawn(Path::new(&tunnel.program), &tunnel.args(), handle)?;
+    let (transport, connection) = framing::framed(stream, tunnel.framing.clone());
+    let destination = tunnel.destination.clone();
+    handle.spawn(connection.map_err(move |e| {
+        error!("SSH tunnel through {} failed: {}", destination, e)
+    }));
+    Ok(transport)
+}
+
+
+/// The standard input and output of an SSH client, as one stream. Kills the client when
+/// dropped.
+struct SshStream {
+    child: Child,
+    stdin: PollEvented<FdStream>,
+    stdout: PollEvented<FdStream>,
+}
+
+impl SshStream {
+    fn spawn(program: &Path, args: &[OsString], handle: &Handle) -> io::Result<Self> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().map(IntoRawFd::into_raw_fd);
+        let stdout = child.stdout.take().map(IntoRawFd::into_raw_fd);
+        let pipes = (|| {
+            let stdin = FdStream::new(stdin.unwrap_or(-1))?;
+            let stdout = FdStream::new(stdout.unwrap_or(-1))?;
+            stdin.set_nonblocking()?;
+            stdout.set_nonblocking()?;
+            Ok((PollEvented::new(stdin, handle)?, PollEvented::new(stdout, handle)?))
+        })();
+        match pipes {
+            Ok((stdin, stdout)) => Ok(SshStream {
+                child,
+                stdin,
+                stdout,
+            }),
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Read for SshStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Write for SshStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl AsyncRead for SshStream {}
+
+impl AsyncWrite for SshStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.stdin.shutdown()
+    }
+}
+
+impl Drop for SshStream {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_client_core::RpcClient;
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn args() {
+        let tunnel = SshTunnel::new("admin@node", 8332)
+            .port(2222)
+            .identity_file("/keys/id_ed25519")
+            .option("StrictHostKeyChecking=yes");
+        let args: Vec<_> = tunnel
+            .args()
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect();
+        let expected = "-o BatchMode=yes -p 2222 -i /keys/id_ed25519 -o IdentitiesOnly=yes \
+                        -o StrictHostKeyChecking=yes -W localhost:8332 -- admin@node";
+        assert_eq!(expected, args.join(" "));
+    }
+
+    #[test]
+    fn calls_through_ssh() {
+        // Stands in for the SSH client, answering the first request with a fixed response.
+        let name = format!("jsonrpc-client-ssh-{}", ::std::process::id());
+        let program = env::temp_dir().join(name);
+        let script = "#!/bin/sh\nread request\necho '{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":5}'\n";
+        fs::write(&program, script).unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut core = Core::new().unwrap();
+        let tunnel = SshTunnel::new("node", 8332).program(&program);
+        let transport = ssh_transport(&tunnel, &core.handle()).unwrap();
+        let mut client = RpcClient::new(transport);
+        let result: u64 = core.run(client.call("getblockcount", ())).unwrap();
+        assert_eq!(5, result);
+        fs::remove_file(&program).unwrap();
+    }
+}
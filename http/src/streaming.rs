@@ -0,0 +1,57 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Response bodies handed over to the caller while they arrive.
+
+use {Error, Result};
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::sync::mpsc;
+use hyper::{self, Chunk};
+use tokio_core::reactor::Handle;
+
+/// How many chunks of a body the event loop reads ahead of the caller.
+const READ_AHEAD: usize = 4;
+
+/// The body of a server-sent events response. Returned by
+/// [`HttpHandle::event_stream`](struct.HttpHandle.html#method.event_stream).
+pub type EventStream = Box<Stream<Item = Chunk, Error = Error> + Send>;
+
+
+/// The body of a response, as a stream of chunks arriving from the event loop. Returned by
+/// [`HttpHandle`](struct.HttpHandle.html) as a
+/// [`StreamingTransport`](../jsonrpc_client_core/streaming/trait.StreamingTransport.html).
+/// Dropping it aborts the response.
+#[derive(Debug)]
+pub struct ResponseBody {
+    chunks: mpsc::Receiver<Result<Chunk>>,
+}
+
+impl ResponseBody {
+    /// Passes the chunks of `body` on from the event loop of `handle` as they arrive, only
+    /// reading a few chunks ahead of the returned stream.
+    pub(crate) fn forward(body: hyper::Body, handle: &Handle) -> Self {
+        let (chunk_tx, chunk_rx) = mpsc::channel(READ_AHEAD);
+        let chunks = body.then(|result| Ok::<_, mpsc::SendError<_>>(result.map_err(Error::from)));
+        handle.spawn(chunk_tx.send_all(chunks).then(|_| Ok(())));
+        ResponseBody { chunks: chunk_rx }
+    }
+}
+
+impl Stream for ResponseBody {
+    type Item = Chunk;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
+        match self.chunks.poll() {
+            Ok(Async::Ready(Some(Ok(chunk)))) => Ok(Async::Ready(Some(chunk))),
+            Ok(Async::Ready(Some(Err(e)))) => Err(e),
+            Ok(Async::Ready(None)) | Err(()) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+        }
+    }
+}
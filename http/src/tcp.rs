@@ -0,0 +1,531 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Transports for JSON-RPC servers speaking JSON without HTTP over TCP, like Electrum servers
+//! and many daemons, with one JSON message per line or any other `Framing`. Many calls can be in
+//! flight on a connection at once, and responses are routed back by id.
+//!
+//! Servers given by host name are reached with a [`TcpConnector`], which resolves the name with
+//! any [`Resolve`] implementation and tunes its sockets with [`SocketOptions`]. The same
+//! connector carries HTTP requests of an `HttpTransport` when created through a [`TcpClient`].
+//!
+//! [`TcpConnector`]: struct.TcpConnector.html
+//! [`Resolve`]: trait.Resolve.html
+//! [`SocketOptions`]: struct.SocketOptions.html
+//! [`TcpClient`]: struct.TcpClient.html
+
+use client_creator::ClientCreator;
+use futures::{Async, Future, Poll};
+use futures::future;
+use hyper::{Body, Client, Uri};
+use hyper::client::Service;
+use jsonrpc_client_core::duplex::DuplexTransport;
+use jsonrpc_client_core::framing::{self, BoxFraming, Framing, LineCodec};
+use resolve::{Resolve, SystemResolver};
+use socket::SocketOptions;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use std::vec;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Timeout};
+
+/// A future resolving to a transport once its connection has been established.
+pub type ConnectFuture = Box<Future<Item = DuplexTransport, Error = io::Error>>;
+
+/// A future resolving to a connected TCP stream.
+pub type TcpConnectFuture = Box<Future<Item = TcpStream, Error = io::Error>>;
+
+/// The Happy Eyeballs connection attempt delay recommended by RFC 8305.
+const DEFAULT_ATTEMPT_DELAY_MS: u64 = 250;
+
+
+/// Connects to `addr` and returns a future resolving to a transport multiplexing calls over the
+/// connection, with one JSON message per line. The connection is driven by a task spawned on
+/// `handle`, which logs an error and fails all calls in flight if the connection fails.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate jsonrpc_client_http;
+/// # extern crate tokio_core;
+/// # use jsonrpc_client_http::tcp_transport;
+/// # fn main() {
+/// let mut core = tokio_core::reactor::Core::new().unwrap();
+/// let addr = "127.0.0.1:50001".parse().unwrap();
+/// let transport = core.run(tcp_transport(&addr, &core.handle())).unwrap();
+/// # }
+/// ```
+pub fn tcp_transport(addr: &SocketAddr, handle: &Handle) -> ConnectFuture {
+    tcp_transport_framed(addr, LineCodec::new(), handle)
+}
+
+/// Connects to `addr` and returns a future resolving to a transport like the one of
+/// [`tcp_transport`](fn.tcp_transport.html), with messages framed with `framing`.
+pub fn tcp_transport_framed<F>(addr: &SocketAddr, framing: F, handle: &Handle) -> ConnectFuture
+where
+    F: Framing + 'static,
+{
+    debug!("Connecting to {}", addr);
+    let addr = *addr;
+    let handle = handle.clone();
+    Box::new(TcpStream::connect(&addr, &handle).map(move |stream| {
+        let (transport, connection) = framing::framed(stream, framing);
+        handle.spawn(connection.map_err(move |e| error!("Connection to {} failed: {}", addr, e)));
+        transport
+    }))
+}
+
+
+/// Connects to servers given by host name and port, resolving the host with a configurable
+/// resolver. Host names that are IP addresses are connected to directly.
+///
+/// Hosts with both IPv6 and IPv4 addresses are connected to with Happy Eyeballs (RFC 8305).
+/// The addresses are tried alternating between the two families, starting with the family of
+/// the first address the resolver returned. A new attempt starts whenever the previous one
+/// fails, or has not succeeded within the attempt delay, while earlier attempts continue. The
+/// first connection to succeed is used, so a broken route of one family only delays connecting
+/// by the attempt delay.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate jsonrpc_client_http;
+/// # extern crate tokio_core;
+/// # use jsonrpc_client_http::{StaticResolver, TcpConnector};
+/// # fn main() {
+/// let mut core = tokio_core::reactor::Core::new().unwrap();
+/// let resolver = StaticResolver::new().host("electrum.example.com", "10.8.0.1".parse().unwrap());
+/// let connector = TcpConnector::new(&core.handle()).resolver(resolver);
+/// let transport = core.run(connector.transport("electrum.example.com", 50001)).unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct TcpConnector {
+    handle: Handle,
+    resolver: Arc<Resolve>,
+    socket_options: SocketOptions,
+    attempt_delay: Duration,
+    pub(crate) framing: BoxFraming,
+}
+
+impl TcpConnector {
+    /// Creates a connector registering its connections on `handle`, resolving hosts with the
+    /// `SystemResolver` and keeping the default socket options.
+    pub fn new(handle: &Handle) -> Self {
+        TcpConnector {
+            handle: handle.clone(),
+            resolver: Arc::new(SystemResolver),
+            socket_options: SocketOptions::new(),
+            attempt_delay: Duration::from_millis(DEFAULT_ATTEMPT_DELAY_MS),
+            framing: BoxFraming::default(),
+        }
+    }
+
+    /// Resolves hosts with `resolver`.
+    pub fn resolver<R: Resolve + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Connects from sockets with the given options.
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
+
+    /// Sets how long a connection attempt may take before the next address is tried in
+    /// parallel. Defaults to 250 milliseconds, as recommended by RFC 8305.
+    pub fn attempt_delay(mut self, attempt_delay: Duration) -> Self {
+        self.attempt_delay = attempt_delay;
+        self
+    }
+
+    /// Frames the messages of the transports it creates with `framing`, instead of one JSON
+    /// message per line.
+    pub fn framing<F: Framing + Clone + Send + Sync + 'static>(mut self, framing: F) -> Self {
+        self.framing = BoxFraming::new(framing);
+        self
+    }
+
+    /// Returns the handle connections are registered on.
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    /// Returns a future resolving to a TCP stream connected to `port` on `host`.
+    pub fn connect(&self, host: &str, port: u16) -> TcpConnectFuture {
+        // Hosts of URIs keep the brackets around IPv6 addresses.
+        let literal = host.trim_matches(|c| c == '[' || c == ']');
+        let addrs = match literal.parse::<IpAddr>() {
+            Ok(ip) => Box::new(future::ok(vec![SocketAddr::new(ip, port)])),
+            Err(_) => self.resolver.resolve(host, port),
+        };
+        let handle = self.handle.clone();
+        let socket_options = self.socket_options.clone();
+        let attempt_delay = self.attempt_delay;
+        let host = host.to_owned();
+        Box::new(addrs.and_then(move |addrs| {
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No address for host {}", host),
+                ));
+            }
+            let addrs = interleave(addrs);
+            debug!("Connecting to {} at {:?}", host, addrs);
+            Ok(HappyEyeballs {
+                host,
+                addrs: addrs.into_iter(),
+                attempts: Vec::new(),
+                delay: None,
+                attempt_delay,
+                last_error: None,
+                socket_options,
+                handle,
+            })
+        }).flatten())
+    }
+
+    /// Connects to `port` on `host` and returns a future resolving to a transport multiplexing
+    /// calls over the connection, framed with the framing of the connector. The connection is
+    /// driven by a task spawned on the handle of the connector, like with [`tcp_transport`].
+    ///
+    /// [`tcp_transport`]: fn.tcp_transport.html
+    pub fn transport(&self, host: &str, port: u16) -> ConnectFuture {
+        let handle = self.handle.clone();
+        let endpoint = format!("{}:{}", host, port);
+        let framing = self.framing.clone();
+        Box::new(self.connect(host, port).map(move |stream| {
+            let (transport, connection) = framing::framed(stream, framing);
+            handle.spawn(connection.map_err(move |e| {
+                error!("Connection to {} failed: {}", endpoint, e)
+            }));
+            transport
+        }))
+    }
+}
+
+impl Service for TcpConnector {
+    type Request = Uri;
+    type Response = TcpStream;
+    type Error = io::Error;
+    type Future = TcpConnectFuture;
+
+    fn call(&self, uri: Uri) -> Self::Future {
+        trace!("Connecting over TCP for {}", uri);
+        let host = match uri.host() {
+            Some(host) => host,
+            None => {
+                return Box::new(future::err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Missing host in URI",
+                )))
+            }
+        };
+        let default_port = if uri.scheme() == Some("https") { 443 } else { 80 };
+        self.connect(host, uri.port().unwrap_or(default_port))
+    }
+}
+
+impl fmt::Debug for TcpConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TcpConnector")
+            .field("socket_options", &self.socket_options)
+            .finish()
+    }
+}
+
+
+/// `Client` creator for Hyper `Client`s connecting with a [`TcpConnector`], to send the requests
+/// of an `HttpTransport` to hosts resolved by a custom resolver.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate jsonrpc_client_http;
+/// # use jsonrpc_client_http::{HttpTransportBuilder, StaticResolver, TcpClient};
+/// # fn main() {
+/// let resolver = StaticResolver::new().host("node.example.com", "203.0.113.7".parse().unwrap());
+/// let transport = HttpTransportBuilder::with_client(TcpClient::new().resolver(resolver))
+///     .standalone()
+///     .unwrap();
+/// let handle = transport.handle("http://node.example.com:8545").unwrap();
+/// # }
+/// ```
+///
+/// [`TcpConnector`]: struct.TcpConnector.html
+#[derive(Clone)]
+pub struct TcpClient {
+    resolver: Arc<Resolve>,
+    socket_options: SocketOptions,
+    attempt_delay: Duration,
+}
+
+impl TcpClient {
+    /// Creates a client creator resolving hosts with the `SystemResolver` and keeping the
+    /// default socket options.
+    pub fn new() -> Self {
+        TcpClient {
+            resolver: Arc::new(SystemResolver),
+            socket_options: SocketOptions::new(),
+            attempt_delay: Duration::from_millis(DEFAULT_ATTEMPT_DELAY_MS),
+        }
+    }
+
+    /// Resolves hosts with `resolver`.
+    pub fn resolver<R: Resolve + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Connects from sockets with the given options.
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
+
+    /// Sets the Happy Eyeballs attempt delay, see
+    /// [`TcpConnector::attempt_delay`](struct.TcpConnector.html#method.attempt_delay).
+    pub fn attempt_delay(mut self, attempt_delay: Duration) -> Self {
+        self.attempt_delay = attempt_delay;
+        self
+    }
+}
+
+impl Default for TcpClient {
+    fn default() -> Self {
+        TcpClient::new()
+    }
+}
+
+impl ClientCreator for TcpClient {
+    type Connect = TcpConnector;
+    type Error = io::Error;
+
+    fn create(&self, handle: &Handle) -> Result<Client<TcpConnector, Body>, io::Error> {
+        let connector = TcpConnector {
+            handle: handle.clone(),
+            resolver: self.resolver.clone(),
+            socket_options: self.socket_options.clone(),
+            attempt_delay: self.attempt_delay,
+            framing: BoxFraming::default(),
+        };
+        Ok(Client::configure().connector(connector).build(handle))
+    }
+}
+
+impl fmt::Debug for TcpClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TcpClient")
+            .field("socket_options", &self.socket_options)
+            .finish()
+    }
+}
+
+
+/// Orders `addrs` alternating between IPv6 and IPv4, starting with the family of the first
+/// address and otherwise keeping the order of the resolver.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_v6 = addrs.iter().take(1).any(SocketAddr::is_ipv6);
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_v6);
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return interleaved,
+            (first, second) => interleaved.extend(first.into_iter().chain(second)),
+        }
+    }
+}
+
+
+/// Connects to addresses with Happy Eyeballs, running staggered attempts in parallel until one
+/// succeeds. Fails with the error of the last attempt if all of them fail.
+struct HappyEyeballs {
+    host: String,
+    addrs: vec::IntoIter<SocketAddr>,
+    attempts: Vec<(SocketAddr, TcpConnectFuture)>,
+    /// Fires when the next attempt is due, even if the running ones have not failed.
+    delay: Option<Timeout>,
+    attempt_delay: Duration,
+    last_error: Option<io::Error>,
+    socket_options: SocketOptions,
+    handle: Handle,
+}
+
+impl Future for HappyEyeballs {
+    type Item = TcpStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<TcpStream, io::Error> {
+        loop {
+            let mut failed = false;
+            let mut i = 0;
+            while i < self.attempts.len() {
+                match self.attempts[i].1.poll() {
+                    Ok(Async::NotReady) => i += 1,
+                    Ok(Async::Ready(stream)) => {
+                        debug!("Connected to {} at {}", self.host, self.attempts[i].0);
+                        return Ok(Async::Ready(stream));
+                    }
+                    Err(e) => {
+                        let (addr, _) = self.attempts.remove(i);
+                        debug!("Connecting to {} at {} failed: {}", self.host, addr, e);
+                        self.last_error = Some(e);
+                        failed = true;
+                    }
+                }
+            }
+            let delay_elapsed = match self.delay {
+                Some(ref mut delay) => delay.poll()?.is_ready(),
+                None => true,
+            };
+            if !failed && !delay_elapsed && !self.attempts.is_empty() {
+                return Ok(Async::NotReady);
+            }
+            match self.addrs.next() {
+                Some(addr) => {
+                    let attempt = self.socket_options.connect(&addr, &self.handle);
+                    self.attempts.push((addr, attempt));
+                    self.delay = Some(Timeout::new(self.attempt_delay, &self.handle)?);
+                }
+                None if self.attempts.is_empty() => {
+                    return Err(self.last_error
+                        .take()
+                        .expect("HappyEyeballs polled after completion"))
+                }
+                None => {
+                    self.delay = None;
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_client_core::RpcClient;
+    use jsonrpc_client_core::framing::LengthPrefixCodec;
+    use resolve::StaticResolver;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn calls_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Answers every request with its id as the result.
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                let request: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+                let id = &request["id"];
+                let response = json!({"jsonrpc": "2.0", "id": id, "result": id});
+                writeln!(writer, "{}", response).unwrap();
+            }
+        });
+
+        let mut core = Core::new().unwrap();
+        let transport = core.run(tcp_transport(&addr, &core.handle())).unwrap();
+        let mut client = RpcClient::new(transport);
+        let first = client.call::<_, u64>("server.ping", ());
+        let second = client.call::<_, u64>("server.ping", ());
+        assert_eq!((1, 2), core.run(first.join(second)).unwrap());
+    }
+
+    #[test]
+    fn connects_to_resolved_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            writeln!(writer, r#"{{"jsonrpc": "2.0", "id": 1, "result": "pong"}}"#).unwrap();
+        });
+
+        let mut core = Core::new().unwrap();
+        // The first address refuses connections, so the second one is tried.
+        let resolver = StaticResolver::new()
+            .host("node.test", "127.0.0.2".parse().unwrap())
+            .host("node.test", "127.0.0.1".parse().unwrap());
+        let connector = TcpConnector::new(&core.handle()).resolver(resolver);
+        let transport = core.run(connector.transport("node.test", port)).unwrap();
+        let result: String = core.run(RpcClient::new(transport).call("ping", ())).unwrap();
+        assert_eq!("pong", result);
+    }
+
+    #[test]
+    fn custom_framing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Answers a request framed with a 4-byte length prefix.
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut length = [0; 4];
+            stream.read_exact(&mut length).unwrap();
+            let mut request = vec![0; u32::from_be_bytes(length) as usize];
+            stream.read_exact(&mut request).unwrap();
+            let response = br#"{"jsonrpc": "2.0", "id": 1, "result": "pong"}"#;
+            stream.write_all(&(response.len() as u32).to_be_bytes()).unwrap();
+            stream.write_all(response).unwrap();
+        });
+
+        let mut core = Core::new().unwrap();
+        let connector = TcpConnector::new(&core.handle()).framing(LengthPrefixCodec::new());
+        let transport = core.run(connector.transport("127.0.0.1", port)).unwrap();
+        let result: String = core.run(RpcClient::new(transport).call("ping", ())).unwrap();
+        assert_eq!("pong", result);
+    }
+
+    #[test]
+    fn interleaves_families() {
+        let addrs: Vec<SocketAddr> = ["[2001:db8::1]:80", "[2001:db8::2]:80", "[2001:db8::3]:80",
+                                      "192.0.2.1:80", "192.0.2.2:80"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let expected = vec![addrs[0], addrs[3], addrs[1], addrs[4], addrs[2]];
+        assert_eq!(expected, interleave(addrs.clone()));
+
+        let v4_first = vec![addrs[3], addrs[0], addrs[4]];
+        assert_eq!(vec![addrs[3], addrs[0], addrs[4]], interleave(v4_first));
+    }
+
+    #[test]
+    fn unreachable_address_does_not_stall() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut core = Core::new().unwrap();
+        // Connections to the documentation address either hang or fail, depending on the routes
+        // of the host. Both must not stall connecting to the next address.
+        let resolver = StaticResolver::new()
+            .host("node.test", "192.0.2.1".parse().unwrap())
+            .host("node.test", "127.0.0.1".parse().unwrap());
+        let connector = TcpConnector::new(&core.handle())
+            .resolver(resolver)
+            .attempt_delay(Duration::from_millis(50));
+        let start = ::std::time::Instant::now();
+        let stream = core.run(connector.connect("node.test", port)).unwrap();
+        assert_eq!(port, stream.peer_addr().unwrap().port());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,430 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! TLS for transports on raw byte streams, using rustls.
+//!
+//! Servers are verified against the Mozilla root certificates by default, extended with the
+//! roots added to the [`TlsConfig`]. Servers with self-signed certificates, like most Electrum
+//! servers, are instead trusted by pinning the SHA-256 fingerprint of their certificate. Pinned
+//! certificates are trusted as they are, without checking their chain, validity or names.
+//!
+//! [`tls_transport`] connects to a server over TCP and TLS, with one JSON message per line or
+//! any other framing with `tls_transport_framed`, and [`TcpConnector::tls_transport`] does the
+//! same for a server given by host name, with the framing of the connector.
+//! [`tls_connect`] runs TLS on any other stream, which can then be framed with the `framing`
+//! module of the core crate. When connecting through a tunnel, or to an address that is not the
+//! name of the server, [`TlsConfig::server_name`] sets the name to ask for and verify.
+//!
+//! [`TlsConfig`]: struct.TlsConfig.html
+//! [`TlsConfig::server_name`]: struct.TlsConfig.html#method.server_name
+//! [`tls_transport`]: fn.tls_transport.html
+//! [`TcpConnector::tls_transport`]: struct.TcpConnector.html#method.tls_transport
+//! [`tls_connect`]: fn.tls_connect.html
+
+use futures::{Async, Future, Poll};
+use jsonrpc_client_core::framing::{self, Framing, LineCodec};
+use ring::digest;
+use rustls::{self, ClientConfig, ClientSession, RootCertStore, ServerCertVerified,
+             ServerCertVerifier, Session, TLSError};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tcp::{ConnectFuture, TcpConnector};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use webpki::DNSNameRef;
+use webpki_roots;
+
+
+/// How servers are verified when connecting with TLS.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    root_certificates: Vec<Vec<u8>>,
+    pins: Vec<String>,
+    server_name: Option<String>,
+}
+
+impl TlsConfig {
+    /// Creates a configuration trusting servers with certificates issued for their name by the
+    /// Mozilla root certificates.
+    pub fn new() -> Self {
+        TlsConfig::default()
+    }
+
+    /// Also trusts certificates issued by the DER encoded root certificate `der`, like the one
+    /// of a private certificate authority.
+    pub fn root_certificate(mut self, der: Vec<u8>) -> Self {
+        self.root_certificates.push(der);
+        self
+    }
+
+    /// Trusts only servers presenting a certificate whose SHA-256 fingerprint is
+    /// `fingerprint`, or any of the other pinned fingerprints. The fingerprint is written in hex,
+    /// optionally with colons between the bytes, as printed by
+    /// `openssl x509 -noout -fingerprint -sha256`.
+    pub fn pin_sha256<S: Into<String>>(mut self, fingerprint: S) -> Self {
+        self.pins.push(fingerprint.into());
+        self
+    }
+
+    /// Sends `name` as the server name (SNI) and verifies the certificate of the server against
+    /// it, instead of the name given when connecting. Needed when connecting through a tunnel,
+    /// or to a server fronted by a load balancer that is only reachable by IP.
+    pub fn server_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Builds the rustls configuration. Fails with an `InvalidInput` error if a root certificate
+    /// or a pin is invalid.
+    fn client_config(&self) -> io::Result<Arc<ClientConfig>> {
+        let mut config = ClientConfig::new();
+        config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        for der in &self.root_certificates {
+            config
+                .root_store
+                .add(&rustls::Certificate(der.clone()))
+                .map_err(|e| invalid_input(format!("Invalid root certificate: {:?}", e)))?;
+        }
+        if !self.pins.is_empty() {
+            let fingerprints = self.pins
+                .iter()
+                .map(|pin| parse_fingerprint(pin))
+                .collect::<io::Result<_>>()?;
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(PinnedCertificates { fingerprints }));
+        }
+        Ok(Arc::new(config))
+    }
+}
+
+/// Parses a SHA-256 fingerprint written in hex, with or without colons.
+fn parse_fingerprint(pin: &str) -> io::Result<Vec<u8>> {
+    let hex: Vec<u8> = pin.bytes().filter(|&b| b != b':').collect();
+    let invalid = || invalid_input(format!("Invalid SHA-256 fingerprint {}", pin));
+    if hex.len() != 2 * digest::SHA256_OUTPUT_LEN {
+        return Err(invalid());
+    }
+    hex.chunks(2)
+        .map(|pair| {
+            let pair = ::std::str::from_utf8(pair).map_err(|_| invalid())?;
+            u8::from_str_radix(pair, 16).map_err(|_| invalid())
+        })
+        .collect()
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+
+/// Trusts servers presenting a certificate with one of the pinned fingerprints.
+struct PinnedCertificates {
+    fingerprints: Vec<Vec<u8>>,
+}
+
+impl ServerCertVerifier for PinnedCertificates {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let certificate = presented_certs
+            .first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        let fingerprint = digest::digest(&digest::SHA256, &certificate.0);
+        if self.fingerprints
+            .iter()
+            .any(|pinned| &pinned[..] == fingerprint.as_ref())
+        {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TLSError::General(
+                "Server certificate does not match any pinned fingerprint".to_owned(),
+            ))
+        }
+    }
+}
+
+
+/// Connects to `addr` over TCP and TLS, verifying the server as `server_name` according to
+/// `config`, or as the server name set in `config`, and returns a future resolving to a
+/// transport multiplexing calls over the connection, with one JSON message per line. The
+/// connection is driven by a task spawned on `handle`, which logs an error and fails all calls
+/// in flight if the connection fails.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate jsonrpc_client_http;
+/// # extern crate tokio_core;
+/// # use jsonrpc_client_http::{tls_transport, TlsConfig};
+/// # fn main() {
+/// let mut core = tokio_core::reactor::Core::new().unwrap();
+/// let addr = "203.0.113.7:50002".parse().unwrap();
+/// let config = TlsConfig::new()
+///     .pin_sha256("90ffb0d296b9e2ef02fd8114c3716e27629a3f8872b0f66f8788097ebb9725e8");
+/// let future = tls_transport(&addr, "electrum.example.com", &config, &core.handle());
+/// let transport = core.run(future).unwrap();
+/// # }
+/// ```
+pub fn tls_transport(
+    addr: &SocketAddr,
+    server_name: &str,
+    config: &TlsConfig,
+    handle: &Handle,
+) -> ConnectFuture {
+    tls_transport_framed(addr, server_name, config, LineCodec::new(), handle)
+}
+
+/// Connects to `addr` over TCP and TLS and returns a future resolving to a transport like the
+/// one of [`tls_transport`](fn.tls_transport.html), with messages framed with `framing`.
+pub fn tls_transport_framed<F>(
+    addr: &SocketAddr,
+    server_name: &str,
+    config: &TlsConfig,
+    framing: F,
+    handle: &Handle,
+) -> ConnectFuture
+where
+    F: Framing + 'static,
+{
+    debug!("Connecting to {} with TLS as {}", addr, server_name);
+    let addr = *addr;
+    let server_name = server_name.to_owned();
+    let config = config.clone();
+    let handle = handle.clone();
+    let future = TcpStream::connect(&addr, &handle)
+        .and_then(move |stream| tls_connect(stream, &server_name, &config))
+        .map(move |stream| {
+            let (transport, connection) = framing::framed(stream, framing);
+            handle.spawn(connection.map_err(move |e| {
+                error!("TLS connection to {} failed: {}", addr, e)
+            }));
+            transport
+        });
+    Box::new(future)
+}
+
+impl TcpConnector {
+    /// Connects to `port` on `host` over TCP and TLS, verifying the server as `host` according
+    /// to `config`, or as the server name set in `config`. Returns a future resolving to a
+    /// transport like the one of [`tls_transport`](fn.tls_transport.html), framed with the
+    /// framing of the connector.
+    pub fn tls_transport(&self, host: &str, port: u16, config: &TlsConfig) -> ConnectFuture {
+        let handle = self.handle().clone();
+        let server_name = host.to_owned();
+        let endpoint = format!("{}:{}", host, port);
+        let config = config.clone();
+        let framing = self.framing.clone();
+        let future = self.connect(host, port)
+            .and_then(move |stream| tls_connect(stream, &server_name, &config))
+            .map(move |stream| {
+                let (transport, connection) = framing::framed(stream, framing);
+                handle.spawn(connection.map_err(move |e| {
+                    error!("TLS connection to {} failed: {}", endpoint, e)
+                }));
+                transport
+            });
+        Box::new(future)
+    }
+}
+
+/// Runs a TLS handshake on `io`, verifying the server as `server_name` according to `config`,
+/// or as the server name set in `config`. Returns a future resolving to the encrypted stream.
+///
+/// The future fails with an `InvalidInput` error if `config` or `server_name` is invalid, and
+/// with an `InvalidData` error if the server can not be verified.
+pub fn tls_connect<S>(io: S, server_name: &str, config: &TlsConfig) -> TlsConnect<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let server_name = config.server_name.as_ref().map_or(server_name, |name| &name[..]);
+    let session = config.client_config().and_then(|client_config| {
+        let dns_name = DNSNameRef::try_from_ascii_str(server_name)
+            .map_err(|()| invalid_input(format!("Invalid TLS server name {}", server_name)))?;
+        Ok(ClientSession::new(&client_config, dns_name))
+    });
+    let state = match session {
+        Ok(session) => ConnectState::Handshaking(Box::new(TlsStream {
+            io,
+            session,
+            eof: false,
+        })),
+        Err(e) => ConnectState::Failed(e),
+    };
+    TlsConnect(Some(state))
+}
+
+
+/// The future returned by `tls_connect`.
+pub struct TlsConnect<S>(Option<ConnectState<S>>);
+
+enum ConnectState<S> {
+    Handshaking(Box<TlsStream<S>>),
+    Failed(io::Error),
+}
+
+impl<S: AsyncRead + AsyncWrite> Future for TlsConnect<S> {
+    type Item = TlsStream<S>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            Some(ConnectState::Handshaking(ref mut stream)) => {
+                if let Async::NotReady = stream.poll_handshake()? {
+                    return Ok(Async::NotReady);
+                }
+            }
+            Some(ConnectState::Failed(_)) => (),
+            None => panic!("TlsConnect polled after completion"),
+        }
+        match self.0.take() {
+            Some(ConnectState::Handshaking(stream)) => Ok(Async::Ready(*stream)),
+            Some(ConnectState::Failed(e)) => Err(e),
+            None => unreachable!(),
+        }
+    }
+}
+
+
+/// A stream encrypted with TLS.
+pub struct TlsStream<S> {
+    io: S,
+    session: ClientSession,
+    /// If the underlying stream has been closed.
+    eof: bool,
+}
+
+impl<S: Read + Write> TlsStream<S> {
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.io
+    }
+
+    fn poll_handshake(&mut self) -> Poll<(), io::Error> {
+        loop {
+            let result = if self.session.wants_write() {
+                self.session.write_tls(&mut self.io).map(|_| ())
+            } else if self.session.is_handshaking() {
+                match self.read_tls() {
+                    Ok(0) => Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed during TLS handshake",
+                    )),
+                    result => result.map(|_| ()),
+                }
+            } else {
+                return Ok(Async::Ready(()));
+            };
+            match result {
+                Ok(()) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads TLS records from the underlying stream and processes them.
+    fn read_tls(&mut self) -> io::Result<usize> {
+        let read = self.session.read_tls(&mut self.io)?;
+        if let Err(e) = self.session.process_new_packets() {
+            // Tells the server why the connection is closed, if possible.
+            let _ = self.session.write_tls(&mut self.io);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        Ok(read)
+    }
+
+    fn write_all_tls(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            self.session.write_tls(&mut self.io)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.session.read(buf) {
+                Ok(0) if !buf.is_empty() && !self.eof => (),
+                Ok(read) => return Ok(read),
+                // The server closed the connection with a close_notify alert.
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted => return Ok(0),
+                Err(e) => return Err(e),
+            }
+            if self.read_tls()? == 0 {
+                self.eof = true;
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.session.write(buf)?;
+        // Writes what the underlying stream accepts now, the rest is written by `flush`.
+        match self.write_all_tls() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(written),
+            Err(e) => Err(e),
+            Ok(()) => Ok(written),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.flush()?;
+        self.write_all_tls()?;
+        self.io.flush()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for TlsStream<S> {}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for TlsStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.session.send_close_notify();
+        match self.write_all_tls() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+            result => result?,
+        }
+        self.io.shutdown()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fingerprints() {
+        let hex = "90ffb0d296b9e2ef02fd8114c3716e27629a3f8872b0f66f8788097ebb9725e8";
+        let fingerprint = parse_fingerprint(hex).unwrap();
+        assert_eq!(32, fingerprint.len());
+        assert_eq!(&[0x90, 0xff, 0xb0][..], &fingerprint[..3]);
+
+        let with_colons = hex.as_bytes()
+            .chunks(2)
+            .map(|pair| ::std::str::from_utf8(pair).unwrap().to_uppercase())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(fingerprint, parse_fingerprint(&with_colons).unwrap());
+
+        assert!(parse_fingerprint(&hex[2..]).is_err());
+        assert!(parse_fingerprint(&hex.replace("9", "x")).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Caching of authentication tokens that are obtained asynchronously, shared by the JWT and
+//! session authentication of `HttpHandle`.
+
+use {Error, ErrorKind, Result};
+use futures::{future, Future, IntoFuture};
+use futures::future::Shared;
+use hyper::StatusCode;
+use hyper::header::Headers;
+use serde_json::{self, Value as JsonValue};
+use std::error;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+
+/// The future of obtaining a new token.
+pub type RefreshFuture =
+    Box<Future<Item = String, Error = Box<error::Error + Send + Sync>> + Send>;
+
+/// A future resolving to a valid token.
+pub(crate) type TokenFuture = Box<Future<Item = String, Error = Error> + Send>;
+
+/// Authentication of requests with a token, retrying requests rejected because of it.
+pub(crate) trait RequestAuth: Send + Sync {
+    /// Returns the current token, or obtains a new one.
+    fn token(&self) -> TokenFuture;
+
+    /// Adds `token` to a request, to its body or its headers. Returns the body to send.
+    fn authorize(&self, token: &str, body: Vec<u8>, headers: &mut Headers) -> Vec<u8>;
+
+    /// Returns whether `result`, the result of a request, means the server rejected the token.
+    fn is_rejection(&self, result: &Result<Vec<u8>>) -> bool;
+
+    /// Forgets `token` after the server rejected it.
+    fn invalidate(&self, token: &str);
+}
+
+/// Returns whether `result` is an HTTP 401 Unauthorized error, or a JSON-RPC error response with
+/// the error code `auth_error_code`.
+pub(crate) fn is_rejection(result: &Result<Vec<u8>>, auth_error_code: Option<i64>) -> bool {
+    match *result {
+        Err(ref error) => *error.kind() == ErrorKind::HttpError(StatusCode::Unauthorized),
+        Ok(ref response) => match auth_error_code {
+            Some(code) => {
+                let response: Option<JsonValue> = serde_json::from_slice(response).ok();
+                let error_code = response
+                    .as_ref()
+                    .and_then(|response| response.pointer("/error/code"))
+                    .and_then(JsonValue::as_i64);
+                error_code == Some(code)
+            }
+            None => false,
+        },
+    }
+}
+
+
+/// The current token, obtained with a refresh function. Clones share the token. Concurrent
+/// requests needing a new token share one refresh.
+#[derive(Clone)]
+pub(crate) struct TokenCache {
+    refresh: Arc<Fn() -> RefreshFuture + Send + Sync>,
+    /// Returns when a token expires, if it does.
+    expiry: fn(&str) -> Option<SystemTime>,
+    /// How long before its expiry a token is replaced.
+    pub refresh_margin: Duration,
+    state: Arc<Mutex<State>>,
+    /// The number of refreshes started, identifying the one in flight.
+    refreshes: Arc<AtomicUsize>,
+}
+
+enum State {
+    Empty,
+    Valid(Token),
+    /// A refresh is in flight, with the number identifying it.
+    Refreshing(usize, Shared<RefreshFuture>),
+}
+
+struct Token {
+    token: String,
+    expires_at: Option<SystemTime>,
+}
+
+impl TokenCache {
+    /// Creates a cache obtaining tokens by calling `refresh`, which expire as told by `expiry`.
+    pub fn new<F, R>(refresh: F, expiry: fn(&str) -> Option<SystemTime>) -> Self
+    where
+        F: Fn() -> R + Send + Sync + 'static,
+        R: IntoFuture<Item = String>,
+        R::Future: Send + 'static,
+        R::Error: Into<Box<error::Error + Send + Sync>> + 'static,
+    {
+        let refresh =
+            move || -> RefreshFuture { Box::new(refresh().into_future().map_err(Into::into)) };
+        TokenCache {
+            refresh: Arc::new(refresh),
+            expiry,
+            refresh_margin: Duration::from_secs(0),
+            state: Arc::new(Mutex::new(State::Empty)),
+            refreshes: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns the current token, or obtains a new one if there is none or it is about to
+    /// expire.
+    pub fn token(&self) -> TokenFuture {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Valid(ref token) if !self.expires_soon(token) => {
+                return Box::new(future::ok(token.token.clone()));
+            }
+            State::Refreshing(generation, ref refresh) => {
+                return self.await_refresh(generation, refresh.clone());
+            }
+            _ => (),
+        }
+        debug!("Obtaining a new authentication token");
+        let generation = self.refreshes.fetch_add(1, Ordering::SeqCst);
+        let refresh = (self.refresh)().shared();
+        *state = State::Refreshing(generation, refresh.clone());
+        self.await_refresh(generation, refresh)
+    }
+
+    /// Forgets `token` after the server rejected it, unless it has been replaced already.
+    pub fn invalidate(&self, token: &str) {
+        let mut state = self.state.lock().unwrap();
+        let rejected = match *state {
+            State::Valid(ref current) => current.token == token,
+            _ => false,
+        };
+        if rejected {
+            debug!("Server rejected the authentication token");
+            *state = State::Empty;
+        }
+    }
+
+    fn expires_soon(&self, token: &Token) -> bool {
+        token.expires_at.map_or(false, |expires_at| {
+            SystemTime::now() + self.refresh_margin >= expires_at
+        })
+    }
+
+    fn await_refresh(&self, generation: usize, refresh: Shared<RefreshFuture>) -> TokenFuture {
+        let cache = self.clone();
+        Box::new(refresh.then(move |result| {
+            let mut state = cache.state.lock().unwrap();
+            let current = match *state {
+                State::Refreshing(current, _) => current == generation,
+                _ => false,
+            };
+            match result {
+                Ok(token) => {
+                    if current {
+                        *state = State::Valid(Token {
+                            token: (*token).clone(),
+                            expires_at: (cache.expiry)(&token),
+                        });
+                    }
+                    Ok((*token).clone())
+                }
+                Err(error) => {
+                    if current {
+                        *state = State::Empty;
+                    }
+                    Err(Error::with_source(ErrorKind::AuthError, error.to_string()))
+                }
+            }
+        }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_rejections() {
+        let unauthorized = Err(ErrorKind::HttpError(StatusCode::Unauthorized).into());
+        assert!(is_rejection(&unauthorized, None));
+        let rejected = br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32001,"message":"Expired"}}"#;
+        assert!(is_rejection(&Ok(rejected.to_vec()), Some(-32001)));
+        assert!(!is_rejection(&Ok(rejected.to_vec()), None));
+        let result = br#"{"jsonrpc":"2.0","id":1,"result":-32001}"#;
+        assert!(!is_rejection(&Ok(result.to_vec()), Some(-32001)));
+    }
+
+    #[test]
+    fn failed_refresh_is_retried() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let cache = {
+            let attempts = attempts.clone();
+            TokenCache::new(
+                move || match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(Error::from(ErrorKind::Hyper)),
+                    _ => Ok("token".to_owned()),
+                },
+                |_| None,
+            )
+        };
+        assert_eq!(&ErrorKind::AuthError, cache.token().wait().unwrap_err().kind());
+        assert_eq!("token", cache.token().wait().unwrap());
+        assert_eq!("token", cache.token().wait().unwrap());
+        assert_eq!(2, attempts.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Support for sending HTTP requests over Unix domain sockets.
+//!
+//! Endpoints are written as `http+unix://<hex encoded socket path>/<request path>`. The socket
+//! path is hex encoded since it can't be represented in the authority part of a URI otherwise.
+//! Use [`unix_uri`](fn.unix_uri.html) to build such URIs.
+
+use client_creator::ClientCreator;
+use futures::future::{self, FutureResult};
+use hyper::{Body, Client, Uri};
+use hyper::client::Service;
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::str;
+use tokio_core::reactor::Handle;
+use tokio_uds::UnixStream;
+
+/// The URI scheme used for HTTP over Unix domain sockets.
+pub const UNIX_SCHEME: &str = "http+unix";
+
+
+/// Creates a URI that targets `path` on the HTTP server listening on the Unix domain socket at
+/// `socket_path`. The returned string can be given to
+/// [`HttpTransport::handle`](struct.HttpTransport.html#method.handle).
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate jsonrpc_client_http;
+/// # use jsonrpc_client_http::unix_uri;
+/// # fn main() {
+/// let uri = unix_uri("/var/run/daemon.sock", "/rpc");
+/// assert_eq!("http+unix://2f7661722f72756e2f6461656d6f6e2e736f636b/rpc", uri);
+/// # }
+/// ```
+pub fn unix_uri<P: AsRef<Path>>(socket_path: P, path: &str) -> String {
+    let host: String = socket_path
+        .as_ref()
+        .as_os_str()
+        .as_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let separator = if path.starts_with('/') { "" } else { "/" };
+    format!("{}://{}{}{}", UNIX_SCHEME, host, separator, path)
+}
+
+/// Extracts the socket path from a URI created by [`unix_uri`](fn.unix_uri.html).
+fn socket_path(uri: &Uri) -> io::Result<PathBuf> {
+    if uri.scheme() != Some(UNIX_SCHEME) {
+        return Err(invalid_uri("Scheme is not http+unix"));
+    }
+    let host = uri.host().ok_or_else(|| invalid_uri("Missing socket path"))?;
+    if host.len() % 2 != 0 {
+        return Err(invalid_uri("Socket path is not hex encoded"));
+    }
+    let bytes = host
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| invalid_uri("Socket path is not hex encoded"))?;
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+fn invalid_uri(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+
+/// Hyper connector that connects to the Unix domain socket encoded in the request URI.
+#[derive(Debug, Clone)]
+pub struct UnixConnector {
+    handle: Handle,
+}
+
+impl UnixConnector {
+    /// Creates a new connector that will register its sockets on the given Tokio `Handle`.
+    pub fn new(handle: &Handle) -> Self {
+        UnixConnector {
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl Service for UnixConnector {
+    type Request = Uri;
+    type Response = UnixStream;
+    type Error = io::Error;
+    type Future = FutureResult<UnixStream, io::Error>;
+
+    fn call(&self, uri: Uri) -> Self::Future {
+        trace!("Connecting to Unix socket for {}", uri);
+        future::result(socket_path(&uri).and_then(|path| UnixStream::connect(path, &self.handle)))
+    }
+}
+
+/// `Client` creator for Hyper `Client`s sending their requests over Unix domain sockets.
+#[derive(Debug, Default)]
+pub struct UnixClient;
+
+impl ClientCreator for UnixClient {
+    type Connect = UnixConnector;
+    type Error = io::Error;
+
+    fn create(&self, handle: &Handle) -> Result<Client<UnixConnector, Body>, io::Error> {
+        Ok(Client::configure()
+            .connector(UnixConnector::new(handle))
+            .build(handle))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn uri_roundtrip() {
+        let uri = Uri::from_str(&unix_uri("/tmp/some dir/rpc.sock", "rpc/v1")).unwrap();
+        assert_eq!("/rpc/v1", uri.path());
+        assert_eq!(
+            Path::new("/tmp/some dir/rpc.sock"),
+            socket_path(&uri).unwrap().as_path()
+        );
+    }
+
+    #[test]
+    fn wrong_scheme() {
+        let uri = Uri::from_str("http://2f746d70/").unwrap();
+        assert!(socket_path(&uri).is_err());
+    }
+}
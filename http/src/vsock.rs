@@ -0,0 +1,237 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Transports for JSON-RPC agents running inside virtual machines, reached over vsock without
+//! any network configuration in the guest.
+//!
+//! QEMU and cloud hypervisors expose guest vsock ports to the host as `AF_VSOCK` sockets,
+//! addressed by the context id (CID) of the guest and a port. Firecracker instead exposes them
+//! through a Unix domain socket on the host, where a `CONNECT <port>` line selects the guest
+//! port. Both use one JSON message per line by default, and many calls can be in flight at
+//! once.
+
+use futures::Future;
+use jsonrpc_client_core::duplex::DuplexTransport;
+use jsonrpc_client_core::framing::{self, Framing, LineCodec};
+use std::io::{self, Read, Write};
+use std::os::unix::net;
+use std::path::Path;
+use std::time::Duration;
+use tokio_core::reactor::Handle;
+use tokio_uds::UnixStream;
+
+/// How long to wait for Firecracker to answer the `CONNECT` line.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest answer to the `CONNECT` line accepted, like `OK 1073741824\n`.
+const MAX_HANDSHAKE_LENGTH: usize = 64;
+
+
+/// Connects to `port` in the virtual machine with context id `cid`, and returns a transport
+/// multiplexing calls over the connection, with one JSON message per line. The connection is
+/// driven by a task spawned on `handle`, which logs an error and fails all calls in flight if
+/// the connection fails.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate jsonrpc_client_http;
+/// # extern crate tokio_core;
+/// # use jsonrpc_client_http::vsock_transport;
+/// # fn main() {
+/// let core = tokio_core::reactor::Core::new().unwrap();
+/// // The QEMU guest agent of the VM started with `guest-cid=3`.
+/// let transport = vsock_transport(3, 9000, &core.handle()).unwrap();
+/// # }
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn vsock_transport(cid: u32, port: u32, handle: &Handle) -> io::Result<DuplexTransport> {
+    vsock_transport_framed(cid, port, LineCodec::new(), handle)
+}
+
+/// Connects to `port` in the virtual machine with context id `cid`, and returns a transport like
+/// the one of [`vsock_transport`](fn.vsock_transport.html), with messages framed with `framing`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn vsock_transport_framed<F>(
+    cid: u32,
+    port: u32,
+    framing: F,
+    handle: &Handle,
+) -> io::Result<DuplexTransport>
+where
+    F: Framing + 'static,
+{
+    use fd::{check, FdStream};
+    use libc;
+    use std::mem;
+    use tokio_core::reactor::PollEvented;
+
+    debug!("Connecting to vsock port {} of CID {}", port, cid);
+    let socket_type = libc::SOCK_STREAM | libc::SOCK_CLOEXEC;
+    let stream = FdStream::new(unsafe { libc::socket(libc::AF_VSOCK, socket_type, 0) })?;
+    unsafe {
+        let mut address: libc::sockaddr_vm = mem::zeroed();
+        address.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        address.svm_cid = cid;
+        address.svm_port = port;
+        check(libc::connect(
+            stream.fd(),
+            &address as *const libc::sockaddr_vm as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+        ))?;
+    }
+    stream.set_nonblocking()?;
+    let stream = PollEvented::new(stream, handle)?;
+    let (transport, connection) = framing::framed(stream, framing);
+    handle.spawn(connection.map_err(move |e| {
+        error!("Vsock connection to port {} of CID {} failed: {}", port, cid, e)
+    }));
+    Ok(transport)
+}
+
+/// Connects to `port` in a Firecracker microVM through the host side Unix domain socket of its
+/// vsock device at `uds_path`, and returns a transport multiplexing calls over the connection,
+/// with one JSON message per line. The connection is driven by a task spawned on `handle`, which
+/// logs an error and fails all calls in flight if the connection fails.
+///
+/// Fails with a `ConnectionRefused` error if Firecracker does not acknowledge the connection,
+/// for example because nothing listens on `port` in the guest.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate jsonrpc_client_http;
+/// # extern crate tokio_core;
+/// # use jsonrpc_client_http::firecracker_vsock_transport;
+/// # fn main() {
+/// let core = tokio_core::reactor::Core::new().unwrap();
+/// let transport = firecracker_vsock_transport("/tmp/v.sock", 52, &core.handle()).unwrap();
+/// # }
+/// ```
+pub fn firecracker_vsock_transport<P: AsRef<Path>>(
+    uds_path: P,
+    port: u32,
+    handle: &Handle,
+) -> io::Result<DuplexTransport> {
+    firecracker_vsock_transport_framed(uds_path, port, LineCodec::new(), handle)
+}
+
+/// Connects to `port` in a Firecracker microVM through the Unix domain socket at `uds_path`, and
+/// returns a transport like the one of
+/// [`firecracker_vsock_transport`](fn.firecracker_vsock_transport.html), with messages framed
+/// with `framing`.
+pub fn firecracker_vsock_transport_framed<P, F>(
+    uds_path: P,
+    port: u32,
+    framing: F,
+    handle: &Handle,
+) -> io::Result<DuplexTransport>
+where
+    P: AsRef<Path>,
+    F: Framing + 'static,
+{
+    let uds_path = uds_path.as_ref();
+    debug!("Connecting to vsock port {} through {}", port, uds_path.display());
+    let mut stream = net::UnixStream::connect(uds_path)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.write_all(format!("CONNECT {}\n", port).as_bytes())?;
+    let answer = read_handshake(&mut stream)?;
+    if !answer.starts_with("OK") {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("Firecracker refused vsock port {}: {}", port, answer.trim()),
+        ));
+    }
+    stream.set_read_timeout(None)?;
+    stream.set_nonblocking(true)?;
+    let stream = UnixStream::from_stream(stream, handle)?;
+    let (transport, connection) = framing::framed(stream, framing);
+    let path = uds_path.display().to_string();
+    handle.spawn(connection.map_err(move |e| {
+        error!("Vsock connection to port {} through {} failed: {}", port, path, e)
+    }));
+    Ok(transport)
+}
+
+/// Reads the answer to the `CONNECT` line one byte at a time, so that no data sent by the guest
+/// after the answer is consumed.
+fn read_handshake(stream: &mut net::UnixStream) -> io::Result<String> {
+    let mut answer = Vec::new();
+    let mut byte = [0; 1];
+    while answer.last() != Some(&b'\n') {
+        if answer.len() >= MAX_HANDSHAKE_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Too long vsock handshake"));
+        }
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "Connection closed during vsock handshake",
+            ));
+        }
+        answer.push(byte[0]);
+    }
+    String::from_utf8(answer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_client_core::RpcClient;
+    use std::env;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::thread;
+    use tokio_core::reactor::Core;
+
+    /// Listens like the host side socket of a Firecracker vsock device, answering the `CONNECT`
+    /// line with `answer`, then every request with its id as the result.
+    fn firecracker(name: &str, answer: &'static str) -> PathBuf {
+        let name = format!("jsonrpc-client-{}-{}", name, ::std::process::id());
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            assert_eq!("CONNECT 52", lines.next().unwrap().unwrap());
+            writer.write_all(answer.as_bytes()).unwrap();
+            for line in lines {
+                let request: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+                let id = &request["id"];
+                let response = json!({"jsonrpc": "2.0", "id": id, "result": id});
+                writeln!(writer, "{}", response).unwrap();
+            }
+        });
+        path
+    }
+
+    #[test]
+    fn calls_over_firecracker_vsock() {
+        let path = firecracker("vsock", "OK 1073741824\n");
+        let mut core = Core::new().unwrap();
+        let transport = firecracker_vsock_transport(&path, 52, &core.handle()).unwrap();
+        let mut client = RpcClient::new(transport);
+        let first = client.call::<_, u64>("ping", ());
+        let second = client.call::<_, u64>("ping", ());
+        assert_eq!((1, 2), core.run(first.join(second)).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refused_firecracker_vsock() {
+        let path = firecracker("vsock-refused", "FAILURE\n");
+        let core = Core::new().unwrap();
+        let error = firecracker_vsock_transport(&path, 52, &core.handle()).unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionRefused, error.kind());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate futures;
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+extern crate jsonrpc_http_server;
+extern crate tokio_core;
+
+use futures::future::{self, Either, Future};
+use jsonrpc_client_core::Transport;
+use jsonrpc_client_http::HttpTransport;
+use jsonrpc_http_server::hyper::{self, Body};
+use jsonrpc_http_server::hyper::server::{Http, Request, Response, Service};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+use tokio_core::reactor::{Core, Timeout};
+
+
+/// Never responds to the first request it gets, responds with "pong" to all others.
+struct HangOnceService(Arc<AtomicBool>);
+
+impl Service for HangOnceService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, _: Request) -> Self::Future {
+        if self.0.swap(true, Ordering::SeqCst) {
+            Box::new(future::ok(Response::new().with_body(Body::from("pong"))))
+        } else {
+            Box::new(future::empty())
+        }
+    }
+}
+
+#[test]
+fn dropped_request_is_aborted() {
+    let (address_tx, address_rx) = mpsc::channel();
+    ::std::thread::spawn(move || {
+        let hung = Arc::new(AtomicBool::new(false));
+        let address = "127.0.0.1:0".parse().unwrap();
+        let server = Http::new()
+            .bind(&address, move || Ok(HangOnceService(hung.clone())))
+            .unwrap();
+        address_tx.send(server.local_addr().unwrap()).unwrap();
+        server.run().unwrap();
+    });
+    let address = address_rx.recv().unwrap();
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let transport = HttpTransport::new()
+        .shared(&handle)
+        .unwrap()
+        .handle(&format!("http://{}", address))
+        .unwrap();
+
+    // Give up on the first request from the outside, dropping it.
+    let timeout = Timeout::new(Duration::from_millis(100), &handle).unwrap();
+    match core.run(transport.send(b"ping".to_vec()).select2(timeout)) {
+        Ok(Either::B(_)) => (),
+        _ => panic!("The hanging request did not time out"),
+    }
+
+    // Without the first request being aborted this one would be stuck behind it forever.
+    let timeout = Timeout::new(Duration::from_secs(2), &handle).unwrap();
+    match core.run(transport.send(b"ping".to_vec()).select2(timeout)) {
+        Ok(Either::A((response, _))) => assert_eq!(b"pong".to_vec(), response),
+        Ok(Either::B(_)) => panic!("Request after a dropped request did not complete"),
+        Err(_) => panic!("Request after a dropped request failed"),
+    }
+}
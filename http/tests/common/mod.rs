@@ -34,13 +34,16 @@ pub struct MockRpcServer;
 
 impl MockRpcServer {
     pub fn spawn() -> jsonrpc_http_server::Server {
-        let mut io = IoHandler::new();
-        io.extend_with(MockRpcServer.to_delegate());
-
-        ServerBuilder::new(io)
+        ServerBuilder::new(Self::io_handler())
             .start_http(&"127.0.0.1:0".parse().unwrap())
             .expect("failed to spawn server")
     }
+
+    pub fn io_handler() -> IoHandler {
+        let mut io = IoHandler::new();
+        io.extend_with(MockRpcServer.to_delegate());
+        io
+    }
 }
 
 impl MockRpcServerApi for MockRpcServer {
@@ -16,6 +16,7 @@ use jsonrpc_client_http::header::{ContentLength, ContentType, Host};
 use tokio_service::Service;
 
 use jsonrpc_client_core::Transport;
+use jsonrpc_client_core::metadata::{with_metadata, CallMetadata};
 use jsonrpc_client_http::{HttpHandle, HttpTransport};
 
 #[test]
@@ -84,7 +85,54 @@ fn set_content_length() {
     assert_eq!(*content_length, fake_content_length);
 }
 
+const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+#[test]
+fn header_hook_runs_last() {
+    let set = move |transport: &mut HttpHandle| {
+        transport.set_header(ContentType::xml());
+        transport.set_header_hook(|headers| {
+            assert_eq!(Some(&ContentType::xml()), headers.get::<ContentType>());
+            headers.set_raw("traceparent", TRACEPARENT);
+        });
+    };
+
+    let request = test_custom_headers(set);
+    let traceparent = request
+        .headers()
+        .get_raw("traceparent")
+        .expect("No traceparent");
+    assert_eq!(traceparent, TRACEPARENT);
+}
+
+#[test]
+fn call_metadata_headers() {
+    let set = move |transport: &mut HttpHandle| {
+        transport.set_header(ContentType::xml());
+        transport.set_trace_id_header("X-Request-Id");
+    };
+    let metadata = CallMetadata::new()
+        .header("Content-Type", "application/json-rpc")
+        .header("X-Tenant", "acme")
+        .header("X-Tenant", "globex")
+        .trace_id("4bf92f3577b34da6");
+
+    let request = test_call_headers(set, Some(metadata));
+    let headers = request.headers();
+    assert_eq!(headers.get_raw("Content-Type").unwrap(), "application/json-rpc");
+    let tenants: Vec<_> = headers.get_raw("X-Tenant").unwrap().iter().collect();
+    assert_eq!(vec![&b"acme"[..], &b"globex"[..]], tenants);
+    assert_eq!(headers.get_raw("X-Request-Id").unwrap(), "4bf92f3577b34da6");
+}
+
 fn test_custom_headers<S>(set_headers: S) -> Request
+where
+    S: FnOnce(&mut HttpHandle),
+{
+    test_call_headers(set_headers, None)
+}
+
+fn test_call_headers<S>(set_headers: S, metadata: Option<CallMetadata>) -> Request
 where
     S: FnOnce(&mut HttpHandle),
 {
@@ -96,7 +144,11 @@ where
 
     set_headers(&mut transport_handle);
 
-    transport_handle.send(Vec::new()).wait().unwrap();
+    let future = match metadata {
+        Some(metadata) => with_metadata(metadata, || transport_handle.send(Vec::new())),
+        None => transport_handle.send(Vec::new()),
+    };
+    future.wait().unwrap();
     server
         .requests
         .recv_timeout(Duration::from_secs(1))
@@ -0,0 +1,53 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate futures;
+#[macro_use]
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+
+extern crate tokio_core;
+
+extern crate jsonrpc_core;
+extern crate jsonrpc_http_server;
+#[macro_use]
+extern crate jsonrpc_macros;
+
+mod common;
+
+use jsonrpc_client_http::{transport_from_url, Endpoint};
+use tokio_core::reactor::Core;
+
+use common::{MockRpcClient, MockRpcServer};
+
+
+#[test]
+fn http_from_url() {
+    let server = MockRpcServer::spawn();
+    let url = format!("http://user:secret@{}/?timeout=5s", server.address());
+
+    let mut core = Core::new().unwrap();
+    let transport = core.run(transport_from_url(&url, &core.handle())).unwrap();
+    let client = MockRpcClient::new(transport);
+    let result = core.run(client.to_upper("from url")).unwrap();
+    assert_eq!("FROM URL", result);
+}
+
+#[test]
+fn http_from_env() {
+    let server = MockRpcServer::spawn();
+    let var = "JSONRPC_ENDPOINT_HTTP_FROM_ENV";
+    ::std::env::set_var(var, format!("http://{}", server.address()));
+
+    let mut core = Core::new().unwrap();
+    let endpoint = Endpoint::from_env_var(var).unwrap();
+    let transport = core.run(endpoint.connect(&core.handle())).unwrap();
+    let client = MockRpcClient::new(transport);
+    let result = core.run(client.to_upper("from env")).unwrap();
+    assert_eq!("FROM ENV", result);
+}
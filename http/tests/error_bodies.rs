@@ -0,0 +1,100 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate futures;
+extern crate hyper;
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+extern crate tokio_service;
+
+use std::thread;
+
+use futures::future::{Future, FutureResult, IntoFuture};
+use futures::sync::oneshot;
+use hyper::{Request, Response, StatusCode};
+use hyper::server::Http;
+use tokio_service::Service;
+
+use jsonrpc_client_core::{call_method, ClientConfig, ErrorKind};
+use jsonrpc_client_http::{Error, ErrorKind as HttpErrorKind, HttpHandle, HttpTransport};
+
+const ERROR_RESPONSE: &str =
+    r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"Database is down"}}"#;
+
+/// Responds to every request with 500 Internal Server Error and the given body.
+#[derive(Clone)]
+struct ServerError(&'static str);
+
+impl Service for ServerError {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn call(&self, _request: Request) -> Self::Future {
+        Ok(Response::new()
+            .with_status(StatusCode::InternalServerError)
+            .with_body(self.0))
+            .into_future()
+    }
+}
+
+/// Calls a method on a server responding with `body` and returns the error of the call.
+fn call_failing(body: &'static str, error_bodies: bool) -> jsonrpc_client_core::Error {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (port_tx, port_rx) = oneshot::channel();
+    thread::spawn(move || {
+        let address = "127.0.0.1:0".parse().unwrap();
+        let server = Http::new()
+            .bind(&address, move || Ok(ServerError(body)))
+            .unwrap();
+        port_tx.send(server.local_addr().unwrap().port()).unwrap();
+        server.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let port = port_rx.wait().unwrap();
+
+    let transport = HttpTransport::new()
+        .error_bodies(error_bodies)
+        .standalone()
+        .unwrap();
+    let handle: HttpHandle = transport
+        .handle(&format!("http://127.0.0.1:{}", port))
+        .unwrap();
+    let result: Result<bool, _> =
+        call_method(&handle, &ClientConfig::default(), "ping".to_owned(), ()).call();
+    drop(shutdown_tx);
+    result.unwrap_err()
+}
+
+#[test]
+fn json_rpc_error_in_body() {
+    let error = call_failing(ERROR_RESPONSE, true);
+    match *error.kind() {
+        ErrorKind::JsonRpcError(ref error) => assert_eq!("Database is down", error.message),
+        ref kind => panic!("Unexpected error kind {:?}", kind),
+    }
+}
+
+#[test]
+fn error_bodies_off() {
+    let error = call_failing(ERROR_RESPONSE, false);
+    assert_eq!(ErrorKind::TransportError, *error.kind());
+    assert_eq!(
+        Some(&HttpErrorKind::HttpError(StatusCode::InternalServerError)),
+        error.transport_error::<Error>().map(Error::kind)
+    );
+}
+
+#[test]
+fn other_body() {
+    let error = call_failing("<html>Internal Server Error</html>", true);
+    assert_eq!(
+        Some(&HttpErrorKind::HttpError(StatusCode::InternalServerError)),
+        error.transport_error::<Error>().map(Error::kind)
+    );
+}
@@ -0,0 +1,98 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate futures;
+extern crate hyper;
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+extern crate tokio_service;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use futures::future::{Future, FutureResult, IntoFuture};
+use futures::sync::oneshot;
+use hyper::{Request, Response, StatusCode};
+use hyper::header::{Authorization, Bearer};
+use hyper::server::Http;
+use tokio_service::Service;
+
+use jsonrpc_client_core::Transport;
+use jsonrpc_client_http::{Error, HttpTransport, JwtAuth};
+
+const RESPONSE: &str = r#"{"jsonrpc":"2.0","id":1,"result":"ok"}"#;
+
+/// Accepts only requests bearing the token "fresh", and records the tokens it was sent.
+#[derive(Clone)]
+struct AuthService {
+    tokens: Arc<Mutex<Vec<String>>>,
+}
+
+impl Service for AuthService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn call(&self, request: Request) -> Self::Future {
+        let token = request
+            .headers()
+            .get::<Authorization<Bearer>>()
+            .map(|authorization| authorization.0.token.clone())
+            .unwrap_or_default();
+        let response = if token == "fresh" {
+            Response::new().with_body(RESPONSE)
+        } else {
+            Response::new().with_status(StatusCode::Unauthorized)
+        };
+        self.tokens.lock().unwrap().push(token);
+        Ok(response).into_future()
+    }
+}
+
+#[test]
+fn rejected_token_is_refreshed() {
+    let service = AuthService {
+        tokens: Arc::new(Mutex::new(Vec::new())),
+    };
+    let tokens = service.tokens.clone();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (port_tx, port_rx) = oneshot::channel();
+    thread::spawn(move || {
+        let address = "127.0.0.1:0".parse().unwrap();
+        let server = Http::new()
+            .bind(&address, move || Ok(service.clone()))
+            .unwrap();
+        port_tx.send(server.local_addr().unwrap().port()).unwrap();
+        server.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let port = port_rx.wait().unwrap();
+
+    // Hands out a stale token first, and fresh ones after that.
+    let refreshes = Arc::new(Mutex::new(0));
+    let jwt_auth = {
+        let refreshes = refreshes.clone();
+        JwtAuth::new(move || {
+            let mut refreshes = refreshes.lock().unwrap();
+            *refreshes += 1;
+            let token = if *refreshes == 1 { "stale" } else { "fresh" };
+            Ok(token.to_owned()) as Result<_, Error>
+        })
+    };
+    let transport = HttpTransport::new().standalone().unwrap();
+    let mut handle = transport
+        .handle(&format!("http://127.0.0.1:{}", port))
+        .unwrap();
+    handle.set_jwt_auth(jwt_auth);
+
+    assert_eq!(RESPONSE.as_bytes(), &handle.send(Vec::new()).wait().unwrap()[..]);
+    assert_eq!(RESPONSE.as_bytes(), &handle.send(Vec::new()).wait().unwrap()[..]);
+    assert_eq!(2, *refreshes.lock().unwrap());
+    assert_eq!(vec!["stale", "fresh", "fresh"], *tokens.lock().unwrap());
+    drop(shutdown_tx);
+}
@@ -22,6 +22,8 @@ mod common;
 
 use futures::Future;
 use futures::future::Either;
+use jsonrpc_client_core::ClientConfig;
+use jsonrpc_client_core::streaming;
 use jsonrpc_client_http::HttpTransport;
 use std::time::Duration;
 use tokio_core::reactor::{Core, Timeout};
@@ -46,7 +48,7 @@ fn localhost_ping_pong() {
         .unwrap()
         .handle(&uri)
         .unwrap();
-    let mut client = MockRpcClient::new(transport);
+    let client = MockRpcClient::new(transport);
 
     // Just calling the method gives back a `RpcRequest`, which is a future
     // that can be used to execute the actual RPC call.
@@ -72,7 +74,7 @@ fn dropped_rpc_request_should_not_crash_transport() {
         .unwrap()
         .handle(&uri)
         .unwrap();
-    let mut client = MockRpcClient::new(transport);
+    let client = MockRpcClient::new(transport);
 
     let rpc = client.sleep(5).map_err(|e| e.to_string());
     let timeout = Timeout::new(Duration::from_millis(100), &core.handle()).unwrap();
@@ -88,3 +90,33 @@ fn dropped_rpc_request_should_not_crash_transport() {
         _ => panic!("Sleep did not return as it should"),
     }
 }
+
+#[test]
+fn response_metadata() {
+    let server = MockRpcServer::spawn();
+    let uri = format!("http://{}", server.address());
+
+    let transport = HttpTransport::new().standalone().unwrap().handle(&uri).unwrap();
+    let client = MockRpcClient::new(transport);
+
+    let (result, response) = client.to_upper("abc").with_response_metadata().wait().unwrap();
+    assert_eq!("ABC", result);
+    assert_eq!(Some(200), response.status);
+    assert_eq!(Some("application/json"), response.header("content-type"));
+}
+
+#[test]
+fn streaming_response() {
+    let server = MockRpcServer::spawn();
+    let uri = format!("http://{}", server.address());
+
+    let transport = HttpTransport::new().standalone().unwrap().handle(&uri).unwrap();
+    // Large enough for the response to arrive in many chunks.
+    let string = "abc".repeat(100_000);
+    let config = ClientConfig::default();
+    let method = "to_upper".to_owned();
+    let result: String = streaming::call_method(&transport, &config, method, [&string])
+        .wait()
+        .unwrap();
+    assert_eq!(string.to_uppercase(), result);
+}
@@ -0,0 +1,57 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate futures;
+#[macro_use]
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+
+extern crate tokio_core;
+
+extern crate jsonrpc_core;
+extern crate jsonrpc_http_server;
+#[macro_use]
+extern crate jsonrpc_macros;
+
+mod common;
+
+use jsonrpc_client_http::{HttpTransportBuilder, StaticResolver, TcpClient};
+use tokio_core::reactor::Core;
+
+use common::{MockRpcClient, MockRpcServer};
+
+
+#[test]
+fn http_to_static_host() {
+    let server = MockRpcServer::spawn();
+    let uri = format!("http://rpc.node.test:{}", server.address().port());
+
+    let mut core = Core::new().unwrap();
+    let resolver = StaticResolver::new().host("rpc.node.test", server.address().ip());
+    let transport = HttpTransportBuilder::with_client(TcpClient::new().resolver(resolver))
+        .shared(&core.handle())
+        .unwrap()
+        .handle(&uri)
+        .unwrap();
+    let client = MockRpcClient::new(transport);
+    let result = core.run(client.to_upper("resolved")).unwrap();
+    assert_eq!("RESOLVED", result);
+}
+
+#[test]
+fn unknown_host_fails() {
+    let mut core = Core::new().unwrap();
+    let client_creator = TcpClient::new().resolver(StaticResolver::new());
+    let transport = HttpTransportBuilder::with_client(client_creator)
+        .shared(&core.handle())
+        .unwrap()
+        .handle("http://rpc.node.test:8545")
+        .unwrap();
+    let client = MockRpcClient::new(transport);
+    assert!(core.run(client.to_upper("resolved")).is_err());
+}
@@ -0,0 +1,94 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate futures;
+#[macro_use]
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+
+extern crate jsonrpc_core;
+extern crate jsonrpc_http_server;
+#[macro_use]
+extern crate jsonrpc_macros;
+
+use futures::Future;
+use jsonrpc_client_http::HttpTransport;
+use jsonrpc_core::{BoxFuture, Error, IoHandler};
+use jsonrpc_http_server::ServerBuilder;
+use jsonrpc_macros::Trailing;
+
+// The API, declared once for both the server and the client.
+macro_rules! text_api {
+    ($macro:ident $($client:tt)*) => {
+        $macro! {
+            $($client)*
+
+            pub trait TextApi {
+                /// Repeats a string.
+                #[rpc(name = "text.repeat")]
+                fn repeat(&self, String, u64) -> Result<String, Error>;
+
+                #[rpc(name = "text.upper")]
+                fn upper(&self, String, Trailing<bool>) -> BoxFuture<String>;
+
+                #[rpc(name = "text.empty")]
+                fn empty(&self) -> Result<String, Error>;
+            }
+        }
+    };
+}
+
+text_api!(build_rpc_trait);
+text_api!(jsonrpc_client_from_rpc_trait pub struct TextClient (idempotent = [repeat, empty]););
+
+struct TextServer;
+
+impl TextApi for TextServer {
+    fn repeat(&self, s: String, times: u64) -> Result<String, Error> {
+        Ok(s.repeat(times as usize))
+    }
+
+    fn upper(&self, s: String, keep: Trailing<bool>) -> BoxFuture<String> {
+        let keep: Option<bool> = keep.into();
+        let result = if keep == Some(true) {
+            s
+        } else {
+            s.to_uppercase()
+        };
+        Box::new(futures::future::ok(result))
+    }
+
+    fn empty(&self) -> Result<String, Error> {
+        Ok(String::new())
+    }
+}
+
+
+#[test]
+fn client_from_rpc_trait() {
+    let mut io = IoHandler::new();
+    io.extend_with(TextServer.to_delegate());
+    let server = ServerBuilder::new(io)
+        .start_http(&"127.0.0.1:0".parse().unwrap())
+        .unwrap();
+    let uri = format!("http://{}", server.address());
+
+    let transport = HttpTransport::new().standalone().unwrap();
+    let client = TextClient::new(transport.handle(&uri).unwrap());
+    assert_eq!("abab", client.repeat("ab".to_owned(), 2).call().unwrap());
+    assert_eq!("AB", client.upper("ab".to_owned(), None).call().unwrap());
+    assert_eq!("ab", client.upper("ab".to_owned(), Some(true)).wait().unwrap());
+    assert_eq!("", client.empty().call().unwrap());
+
+    let methods = TextClient::<jsonrpc_client_http::HttpHandle>::METHODS;
+    let names: Vec<_> = methods.iter().map(|method| method.name).collect();
+    assert_eq!(vec!["text.repeat", "text.upper", "text.empty"], names);
+    assert_eq!(&["arg0", "arg1"], methods[1].params);
+    let idempotent: Vec<_> = methods.iter().map(|method| method.idempotent).collect();
+    assert_eq!(vec![true, false, true], idempotent);
+}
@@ -0,0 +1,91 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate futures;
+extern crate hyper;
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+extern crate tokio_service;
+
+use std::thread;
+
+use futures::future::{Future, FutureResult, IntoFuture};
+use futures::stream::Stream;
+use futures::sync::oneshot;
+use hyper::{Method, Request, Response};
+use hyper::server::Http;
+use tokio_service::Service;
+
+use jsonrpc_client_core::ClientConfig;
+use jsonrpc_client_core::pubsub;
+use jsonrpc_client_core::sse::SseTransport;
+use jsonrpc_client_http::HttpTransport;
+
+const SUBSCRIBED: &str = r#"{"jsonrpc":"2.0","id":1,"result":"0x9c"}"#;
+
+const EVENTS: &str = concat!(
+    ": connected\n\n",
+    "data: {\"jsonrpc\":\"2.0\",\"method\":\"eth_subscription\",\n",
+    "data: \"params\":{\"subscription\":\"0x9c\",\"result\":100}}\n\n",
+    "data: {\"jsonrpc\":\"2.0\",\"method\":\"eth_subscription\",",
+    "\"params\":{\"subscription\":\"0x9c\",\"result\":101}}\n\n",
+);
+
+/// Answers subscribe calls posted to it, and sends notifications as events to GET requests.
+#[derive(Clone)]
+struct SseGateway;
+
+impl Service for SseGateway {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn call(&self, request: Request) -> Self::Future {
+        let response = match *request.method() {
+            Method::Get => {
+                assert_eq!(
+                    &b"text/event-stream"[..],
+                    request.headers().get_raw("Accept").unwrap().one().unwrap()
+                );
+                Response::new().with_body(EVENTS)
+            }
+            _ => Response::new().with_body(SUBSCRIBED),
+        };
+        Ok(response).into_future()
+    }
+}
+
+#[test]
+fn notifications_from_event_stream() {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (port_tx, port_rx) = oneshot::channel();
+    thread::spawn(move || {
+        let address = "127.0.0.1:0".parse().unwrap();
+        let server = Http::new().bind(&address, || Ok(SseGateway)).unwrap();
+        port_tx.send(server.local_addr().unwrap().port()).unwrap();
+        server.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let port = port_rx.wait().unwrap();
+
+    let transport = HttpTransport::new().standalone().unwrap();
+    let uri = format!("http://127.0.0.1:{}", port);
+    let events = transport.handle(&format!("{}/events", uri)).unwrap().event_stream();
+    let (transport, connection) = SseTransport::new(transport.handle(&uri).unwrap(), events);
+
+    let config = ClientConfig::default();
+    let subscription =
+        pubsub::subscribe::<_, _, u64>(&transport, &config, "eth_subscribe".to_owned(), ())
+            .wait()
+            .unwrap();
+    // Reads the events until the server closes the stream, which ends the subscription.
+    connection.wait().unwrap();
+    let notifications: Vec<u64> = subscription.wait().map(Result::unwrap).collect();
+    assert_eq!(vec![100, 101], notifications);
+    drop(shutdown_tx);
+}
@@ -0,0 +1,119 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate futures;
+extern crate hyper;
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+extern crate tokio_service;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::future::{Future, FutureResult, IntoFuture};
+use futures::sync::oneshot;
+use hyper::{Request, Response, StatusCode};
+use hyper::header::RetryAfter;
+use hyper::server::Http;
+use tokio_service::Service;
+
+use jsonrpc_client_core::Transport;
+use jsonrpc_client_core::retry::{RetryPolicy, RetryTransport};
+use jsonrpc_client_http::{Error, ErrorKind, HttpHandle, HttpTransport};
+
+const RESPONSE: &str = r#"{"jsonrpc":"2.0","id":1,"result":true}"#;
+
+/// Throttles the first `throttled` requests with `status` and `Retry-After: 1`, if given.
+#[derive(Clone)]
+struct ThrottlingService {
+    status: StatusCode,
+    retry_after: bool,
+    throttled: usize,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Service for ThrottlingService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn call(&self, _request: Request) -> Self::Future {
+        if self.requests.fetch_add(1, Ordering::SeqCst) >= self.throttled {
+            return Ok(Response::new().with_body(RESPONSE)).into_future();
+        }
+        let mut response = Response::new().with_status(self.status);
+        if self.retry_after {
+            response
+                .headers_mut()
+                .set(RetryAfter::Delay(Duration::from_secs(1)));
+        }
+        Ok(response).into_future()
+    }
+}
+
+/// Spawns `service` and returns a handle to it and a channel shutting it down when dropped.
+fn spawn(service: ThrottlingService) -> (HttpHandle, oneshot::Sender<()>) {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (port_tx, port_rx) = oneshot::channel();
+    thread::spawn(move || {
+        let address = "127.0.0.1:0".parse().unwrap();
+        let server = Http::new()
+            .bind(&address, move || Ok(service.clone()))
+            .unwrap();
+        port_tx.send(server.local_addr().unwrap().port()).unwrap();
+        server.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let port = port_rx.wait().unwrap();
+    let transport = HttpTransport::new().standalone().unwrap();
+    let handle = transport
+        .handle(&format!("http://127.0.0.1:{}", port))
+        .unwrap();
+    (handle, shutdown_tx)
+}
+
+fn throttling(status: StatusCode, retry_after: bool, throttled: usize) -> ThrottlingService {
+    ThrottlingService {
+        status,
+        retry_after,
+        throttled,
+        requests: Arc::new(AtomicUsize::new(0)),
+    }
+}
+
+#[test]
+fn throttled_error() {
+    let (handle, _shutdown_tx) = spawn(throttling(StatusCode::TooManyRequests, false, 1));
+    let error = handle.send(Vec::new()).wait().unwrap_err();
+    assert_eq!(ErrorKind::Throttled(StatusCode::TooManyRequests, None), *error.kind());
+    assert_eq!(None, error.retry_after());
+
+    let (handle, _shutdown_tx) = spawn(throttling(StatusCode::ServiceUnavailable, true, 1));
+    let error = handle.send(Vec::new()).wait().unwrap_err();
+    assert_eq!(Some(Duration::from_secs(1)), error.retry_after());
+
+    let (handle, _shutdown_tx) = spawn(throttling(StatusCode::ServiceUnavailable, false, 1));
+    let error = handle.send(Vec::new()).wait().unwrap_err();
+    assert_eq!(ErrorKind::HttpError(StatusCode::ServiceUnavailable), *error.kind());
+}
+
+#[test]
+fn retry_waits_as_requested() {
+    let service = throttling(StatusCode::TooManyRequests, true, 1);
+    let requests = service.requests.clone();
+    let (handle, _shutdown_tx) = spawn(service);
+    let policy = RetryPolicy::fixed(Duration::from_millis(1)).retry_after(Error::retry_after);
+    let transport = RetryTransport::new(handle, policy);
+
+    let start = Instant::now();
+    assert_eq!(RESPONSE.as_bytes(), &transport.send(Vec::new()).wait().unwrap()[..]);
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert_eq!(2, requests.load(Ordering::SeqCst));
+}
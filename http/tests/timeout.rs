@@ -48,7 +48,7 @@ fn long_request_should_timeout() {
         .unwrap()
         .handle(&uri)
         .unwrap();
-    let mut client = MockRpcClient::new(transport);
+    let client = MockRpcClient::new(transport);
 
     let rpc_future = client.slow_to_upper("HARD string TAKES too LONG", 10_000);
     let result = rpc_future.wait();
@@ -67,7 +67,7 @@ fn short_request_should_succeed() {
         .unwrap()
         .handle(&uri)
         .unwrap();
-    let mut client = MockRpcClient::new(transport);
+    let client = MockRpcClient::new(transport);
 
     let rpc_future = client.to_upper("FAST sHoRt strIng");
     let result = rpc_future.wait().unwrap();
@@ -0,0 +1,120 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![cfg(feature = "rustls-tls")]
+
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+extern crate rustls;
+extern crate tokio_core;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::thread;
+
+use rustls::internal::pemfile;
+use rustls::{NoClientAuth, ServerConfig, ServerSession, StreamOwned};
+use tokio_core::reactor::Core;
+
+use jsonrpc_client_core::RpcClient;
+use jsonrpc_client_http::{tls_transport, transport_from_url, StaticResolver, TcpConnector,
+                          TlsConfig};
+
+/// The SHA-256 fingerprint of `data/localhost.crt`, a self-signed certificate.
+const FINGERPRINT: &str = "90:FF:B0:D2:96:B9:E2:EF:02:FD:81:14:C3:71:6E:27:\
+                           62:9A:3F:88:72:B0:F6:6F:87:88:09:7E:BB:97:25:E8";
+
+/// Starts a TLS server with the self-signed certificate, answering every request with a fixed
+/// response.
+fn spawn_server() -> SocketAddr {
+    let certs = pemfile::certs(&mut &include_bytes!("data/localhost.crt")[..]).unwrap();
+    let mut keys = pemfile::pkcs8_private_keys(&mut &include_bytes!("data/localhost.key")[..])
+        .unwrap();
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, keys.remove(0)).unwrap();
+    let config = Arc::new(config);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let session = ServerSession::new(&config);
+            let mut stream = BufReader::new(StreamOwned::new(session, stream.unwrap()));
+            let mut line = String::new();
+            if stream.read_line(&mut line).is_ok() && !line.is_empty() {
+                let response = "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":\"pong\"}\n";
+                stream.get_mut().write_all(response.as_bytes()).unwrap();
+                stream.get_mut().flush().unwrap();
+            }
+        }
+    });
+    addr
+}
+
+#[test]
+fn pinned_certificate() {
+    let addr = spawn_server();
+    let mut core = Core::new().unwrap();
+    let config = TlsConfig::new().pin_sha256(FINGERPRINT);
+    let transport = core.run(tls_transport(&addr, "localhost", &config, &core.handle()))
+        .unwrap();
+    let mut client = RpcClient::new(transport);
+    let result: String = core.run(client.call("ping", ())).unwrap();
+    assert_eq!("pong", result);
+}
+
+#[test]
+fn wrong_pin() {
+    let addr = spawn_server();
+    let mut core = Core::new().unwrap();
+    let config = TlsConfig::new().pin_sha256(FINGERPRINT.replace("90", "91"));
+    let error = core.run(tls_transport(&addr, "localhost", &config, &core.handle()))
+        .err()
+        .unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+}
+
+#[test]
+fn untrusted_self_signed_certificate() {
+    let addr = spawn_server();
+    let mut core = Core::new().unwrap();
+    let future = tls_transport(&addr, "localhost", &TlsConfig::new(), &core.handle());
+    assert!(core.run(future).is_err());
+}
+
+#[test]
+fn resolved_host_with_server_name() {
+    let addr = spawn_server();
+    let mut core = Core::new().unwrap();
+    let resolver = StaticResolver::new().host("electrum.test", addr.ip());
+    let connector = TcpConnector::new(&core.handle()).resolver(resolver);
+    let config = TlsConfig::new()
+        .pin_sha256(FINGERPRINT)
+        .server_name("localhost");
+    let transport = core.run(connector.tls_transport("electrum.test", addr.port(), &config))
+        .unwrap();
+    let mut client = RpcClient::new(transport);
+    let result: String = core.run(client.call("ping", ())).unwrap();
+    assert_eq!("pong", result);
+}
+
+#[test]
+fn pinned_certificate_from_url() {
+    let addr = spawn_server();
+    let mut core = Core::new().unwrap();
+    let url = format!(
+        "tls://127.0.0.1:{}?server_name=localhost&pin={}",
+        addr.port(),
+        FINGERPRINT.replace(":", "")
+    );
+    let transport = core.run(transport_from_url(&url, &core.handle())).unwrap();
+    let mut client = RpcClient::new(transport);
+    let result: String = core.run(client.call("ping", ())).unwrap();
+    assert_eq!("pong", result);
+}
@@ -0,0 +1,133 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![cfg(unix)]
+
+extern crate futures;
+#[macro_use]
+extern crate jsonrpc_client_core;
+extern crate jsonrpc_client_http;
+
+extern crate tokio_core;
+extern crate tokio_uds;
+
+extern crate jsonrpc_core;
+extern crate jsonrpc_http_server;
+#[macro_use]
+extern crate jsonrpc_macros;
+
+mod common;
+
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+
+use futures::{Future, Stream};
+use futures::future;
+use jsonrpc_client_http::{unix_uri, HttpTransport};
+use jsonrpc_core::IoHandler;
+use jsonrpc_http_server::hyper::{self, Body, Chunk};
+use jsonrpc_http_server::hyper::server::{Http, Request, Response, Service};
+use tokio_core::reactor::Core;
+use tokio_uds::UnixListener;
+
+// Use a simple RPC API for testing purposes.
+use common::{MockRpcClient, MockRpcServer};
+
+
+/// Hyper service answering JSON-RPC requests with the given `IoHandler`.
+struct IoService(Arc<IoHandler>);
+
+impl Service for IoService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn call(&self, request: Request) -> Self::Future {
+        let io = self.0.clone();
+        let response = request.body().concat2().map(move |body: Chunk| {
+            let request = String::from_utf8_lossy(&body).into_owned();
+            let response = io.handle_request_sync(&request).unwrap_or_default();
+            Response::new().with_body(Body::from(response))
+        });
+        Box::new(response)
+    }
+}
+
+/// Spawns a thread serving the `MockRpcServerApi` API over a Unix socket at the returned path.
+fn spawn_unix_server(name: &str) -> PathBuf {
+    let path = ::std::env::temp_dir().join(format!(
+        "jsonrpc-client-http-{}-{}.sock",
+        name,
+        ::std::process::id()
+    ));
+    let _ = ::std::fs::remove_file(&path);
+
+    let (ready_tx, ready_rx) = mpsc::channel();
+    let server_path = path.clone();
+    ::std::thread::spawn(move || {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let listener = UnixListener::bind(&server_path, &handle).unwrap();
+        ready_tx.send(()).unwrap();
+
+        let http = Http::<Chunk>::new();
+        let io = Arc::new(MockRpcServer::io_handler());
+        let server = listener.incoming().for_each(move |(stream, _)| {
+            let connection = http
+                .serve_connection(stream, IoService(io.clone()))
+                .map(|_| ())
+                .map_err(|e| println!("Server connection error: {}", e));
+            handle.spawn(connection);
+            future::ok(())
+        });
+        core.run(server).unwrap();
+    });
+    ready_rx.recv().unwrap();
+    path
+}
+
+
+#[test]
+fn unix_socket_ping_pong() {
+    let socket_path = spawn_unix_server("ping-pong");
+
+    let transport = HttpTransport::unix()
+        .standalone()
+        .unwrap()
+        .handle(&unix_uri(&socket_path, "/"))
+        .unwrap();
+    let client = MockRpcClient::new(transport);
+
+    assert_eq!("UNIX SOCKET", client.to_upper("Unix socket").call().unwrap());
+    assert_eq!("AGAIN", client.to_upper("again").call().unwrap());
+
+    let _ = ::std::fs::remove_file(&socket_path);
+}
+
+#[test]
+fn missing_socket_is_transport_error() {
+    let socket_path = ::std::env::temp_dir().join("jsonrpc-client-http-does-not-exist.sock");
+
+    let transport = HttpTransport::unix()
+        .standalone()
+        .unwrap()
+        .handle(&unix_uri(&socket_path, "/"))
+        .unwrap();
+    let client = MockRpcClient::new(transport);
+
+    let error = client.to_upper("nobody listens").call().unwrap_err();
+    match error.kind() {
+        &jsonrpc_client_core::ErrorKind::TransportError => (),
+        kind => panic!("Wrong error kind: {:?}", kind),
+    }
+    let transport_error = error
+        .transport_error::<jsonrpc_client_http::Error>()
+        .expect("Not a HTTP transport error");
+    assert_eq!(&jsonrpc_client_http::ErrorKind::Hyper, transport_error.kind());
+}
@@ -0,0 +1,60 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::error;
+use std::fmt;
+use std::result;
+
+
+/// A specialized `Result` type for the fetch transport.
+pub type Result<T> = result::Result<T, Error>;
+
+/// The error type of the fetch transport. Tells what went wrong through its
+/// [`kind`](#method.kind).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+}
+
+/// The different kinds of errors the fetch transport can fail with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// When the http status code of the response is not in the 2xx range.
+    HttpError(u16),
+    /// When a JavaScript API used by the transport threw an exception or rejected a promise.
+    JsError(String),
+    /// When the transport runs in a context without a `fetch` API.
+    NoFetchApi,
+}
+
+impl Error {
+    /// Returns the kind of this error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error { kind }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::HttpError(http_code) => write!(f, "Http error. Status code {}", http_code),
+            ErrorKind::JsError(ref msg) => write!(f, "JavaScript error: {}", msg),
+            ErrorKind::NoFetchApi => {
+                f.write_str("Neither a Window nor a WorkerGlobalScope is available")
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
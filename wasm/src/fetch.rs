@@ -0,0 +1,154 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use {Error, ErrorKind, Result};
+use futures::{future, Future, Poll};
+use futures03::compat::Compat;
+use js_sys::{Promise, Uint8Array};
+use jsonrpc_client_core::Transport;
+use std::str;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Headers, RequestInit, Response, Window, WorkerGlobalScope};
+
+
+/// The future returned by [`FetchTransport`](struct.FetchTransport.html) send operations.
+pub struct FetchFuture(Box<Future<Item = Vec<u8>, Error = Error>>);
+
+// The inner future holds JavaScript values, which are not `Send`. `wasm32-unknown-unknown`
+// without the atomics target feature has no threads, so the future can never be moved to another
+// thread.
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Send for FetchFuture {}
+
+// With atomics, futures can be moved to web workers, where the JavaScript values they hold are
+// not valid. Transports must return `Send` futures, so there is no sound transport to offer.
+#[cfg(target_feature = "atomics")]
+compile_error!("jsonrpc-client-wasm does not support the atomics target feature");
+
+impl Future for FetchFuture {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.0.poll()
+    }
+}
+
+
+/// A transport sending JSON-RPC requests as HTTP POST requests with the `fetch` API.
+///
+/// Works both in browser windows and web workers. Clones share the same id counter.
+#[derive(Debug, Clone)]
+pub struct FetchTransport {
+    url: String,
+    headers: Vec<(String, String)>,
+    id: Arc<AtomicUsize>,
+}
+
+impl FetchTransport {
+    /// Creates a new transport sending all requests to the given URL.
+    pub fn new(url: &str) -> Self {
+        FetchTransport {
+            url: url.to_owned(),
+            headers: Vec::new(),
+            id: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+
+    /// Configure a custom HTTP header for all requests sent through this transport.
+    ///
+    /// Replaces the `Content-Type` header set by this library if the names match. Browsers
+    /// silently drop headers that scripts are forbidden to set.
+    pub fn set_header(&mut self, name: &str, value: &str) -> &mut Self {
+        self.headers
+            .retain(|&(ref existing, _)| !existing.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Starts the fetch of a POST request with the given body.
+    fn start_fetch(&self, body: &[u8]) -> Result<Promise> {
+        let body = str::from_utf8(body)
+            .map_err(|_| ErrorKind::JsError("Request body is not valid UTF-8".to_owned()))?;
+        let headers = Headers::new().map_err(js_error)?;
+        headers
+            .set("Content-Type", "application/json")
+            .map_err(js_error)?;
+        for &(ref name, ref value) in &self.headers {
+            headers.set(name, value).map_err(js_error)?;
+        }
+
+        let init = RequestInit::new();
+        init.set_method("POST");
+        init.set_headers(&headers);
+        init.set_body(&JsValue::from_str(body));
+
+        trace!("Sending request to {}", self.url);
+        let global = js_sys::global();
+        if let Some(window) = global.dyn_ref::<Window>() {
+            Ok(window.fetch_with_str_and_init(&self.url, &init))
+        } else if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>() {
+            Ok(worker.fetch_with_str_and_init(&self.url, &init))
+        } else {
+            Err(ErrorKind::NoFetchApi.into())
+        }
+    }
+}
+
+impl Transport for FetchTransport {
+    type Future = FetchFuture;
+    type Error = Error;
+
+    fn get_next_id(&self) -> u64 {
+        self.id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        Some(self.url.clone())
+    }
+
+    fn send(&self, json_data: Vec<u8>) -> Self::Future {
+        let promise = match self.start_fetch(&json_data) {
+            Ok(promise) => promise,
+            Err(e) => return FetchFuture(Box::new(future::err(e))),
+        };
+        let future = js_future(promise)
+            .map_err(js_error)
+            .and_then(|response| {
+                let response: Response = response.dyn_into().map_err(js_error)?;
+                if !response.ok() {
+                    return Err(ErrorKind::HttpError(response.status()).into());
+                }
+                response.array_buffer().map_err(js_error)
+            })
+            .and_then(|promise| js_future(promise).map_err(js_error))
+            .map(|buffer| {
+                let array = Uint8Array::new(&buffer);
+                let mut body = vec![0; array.length() as usize];
+                array.copy_to(&mut body);
+                body
+            });
+        FetchFuture(Box::new(future))
+    }
+}
+
+/// Converts a JavaScript `Promise` into a futures 0.1 `Future`.
+fn js_future(promise: Promise) -> Compat<JsFuture> {
+    Compat::new(JsFuture::from(promise))
+}
+
+/// Converts a JavaScript exception or promise rejection into an `Error`.
+fn js_error(value: JsValue) -> Error {
+    let msg = value
+        .as_string()
+        .unwrap_or_else(|| format!("{:?}", value));
+    ErrorKind::JsError(msg).into()
+}
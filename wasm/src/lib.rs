@@ -0,0 +1,68 @@
+// Copyright 2017 Amagicom AB.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Browser transport implementation for the JSON-RPC 2.0 clients generated by
+//! [`jsonrpc-client-core`](../jsonrpc_client_core/index.html).
+//!
+//! When compiled for `wasm32-unknown-unknown` this crate provides
+//! [`FetchTransport`](struct.FetchTransport.html), a transport sending each request as a HTTP POST
+//! with the `fetch` API of the browser window or web worker it runs in. On other targets only the
+//! error types are available.
+//!
+//! The futures returned by the transport are resolved by the JavaScript event loop. They must be
+//! spawned, for example by converting them with the `compat` layer of futures 0.3 and handing them
+//! to `wasm_bindgen_futures::spawn_local`. They must never be blocked on with `RpcRequest::call` or
+//! `Future::wait`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! #[macro_use]
+//! extern crate jsonrpc_client_core;
+//! extern crate jsonrpc_client_wasm;
+//!
+//! use jsonrpc_client_wasm::{FetchFuture, FetchTransport};
+//!
+//! jsonrpc_client!(pub struct FizzBuzzClient {
+//!     /// Returns the fizz-buzz string for the given number.
+//!     pub fn fizz_buzz(&mut self, number: u64) -> RpcRequest<String>;
+//! });
+//!
+//! fn fizz_buzz(number: u64) -> jsonrpc_client_core::RpcRequest<String, FetchFuture> {
+//!     let mut transport = FetchTransport::new("https://api.fizzbuzzexample.org/rpc/");
+//!     transport.set_header("Authorization", "Bearer secret");
+//!     FizzBuzzClient::new(transport).fizz_buzz(number)
+//! }
+//! ```
+
+#![deny(missing_docs)]
+
+extern crate futures;
+extern crate jsonrpc_client_core;
+
+#[cfg(target_arch = "wasm32")]
+extern crate futures03;
+#[cfg(target_arch = "wasm32")]
+extern crate js_sys;
+#[cfg(target_arch = "wasm32")]
+#[macro_use]
+extern crate log;
+#[cfg(target_arch = "wasm32")]
+extern crate wasm_bindgen;
+#[cfg(target_arch = "wasm32")]
+extern crate wasm_bindgen_futures;
+#[cfg(target_arch = "wasm32")]
+extern crate web_sys;
+
+#[cfg(target_arch = "wasm32")]
+mod fetch;
+#[cfg(target_arch = "wasm32")]
+pub use fetch::*;
+
+mod error;
+pub use error::{Error, ErrorKind, Result};